@@ -0,0 +1,115 @@
+//! The GICv2 interrupt controller.
+//!
+//! All shared peripheral interrupts are routed to the boot CPU. The
+//! polarity of the interrupts can not be configured, they are all active
+//! high or rising edge.
+
+use {
+    super::board::{GICC_BASE, GICD_BASE},
+    crate::phys_to_virt,
+    core::sync::atomic::{AtomicU32, Ordering},
+    kernel_hal::IrqMode,
+};
+
+// registers of the distributor
+const GICD_CTLR: usize = 0x000;
+const GICD_TYPER: usize = 0x004;
+const GICD_ISENABLER: usize = 0x100;
+const GICD_ICENABLER: usize = 0x180;
+const GICD_IPRIORITYR: usize = 0x400;
+const GICD_ITARGETSR: usize = 0x800;
+const GICD_ICFGR: usize = 0xc00;
+
+// registers of the CPU interface
+const GICC_CTLR: usize = 0x000;
+const GICC_PMR: usize = 0x004;
+const GICC_IAR: usize = 0x00c;
+const GICC_EOIR: usize = 0x010;
+
+/// The interrupt ID read from `GICC_IAR` when none is pending.
+const SPURIOUS_ID: u32 = 1023;
+/// The first shared peripheral interrupt.
+pub const SPI_BASE: u32 = 32;
+
+static LINES: AtomicU32 = AtomicU32::new(0);
+
+fn reg(base: usize, offset: usize) -> *mut u32 {
+    phys_to_virt(base + offset) as *mut u32
+}
+
+fn gicd_read(offset: usize) -> u32 {
+    unsafe { reg(GICD_BASE, offset).read_volatile() }
+}
+
+fn gicd_write(offset: usize, value: u32) {
+    unsafe { reg(GICD_BASE, offset).write_volatile(value) }
+}
+
+fn gicc_write(offset: usize, value: u32) {
+    unsafe { reg(GICC_BASE, offset).write_volatile(value) }
+}
+
+/// Disable all shared peripheral interrupts and route them to the boot CPU,
+/// then enable the distributor and the CPU interface.
+pub fn init() {
+    gicd_write(GICD_CTLR, 0);
+    let lines = 32 * ((gicd_read(GICD_TYPER) & 0x1f) + 1);
+    LINES.store(lines, Ordering::Relaxed);
+    for id in (SPI_BASE..lines).step_by(32) {
+        gicd_write(GICD_ICENABLER + id as usize / 8, u32::MAX);
+    }
+    for id in (SPI_BASE..lines).step_by(4) {
+        gicd_write(GICD_IPRIORITYR + id as usize, 0xa0a0_a0a0);
+        gicd_write(GICD_ITARGETSR + id as usize, 0x0101_0101);
+    }
+    gicd_write(GICD_CTLR, 1);
+    init_cpu();
+}
+
+/// Enable the CPU interface of the current CPU.
+pub fn init_cpu() {
+    // take all priorities
+    gicc_write(GICC_PMR, 0xff);
+    gicc_write(GICC_CTLR, 1);
+}
+
+/// The number of interrupt IDs supported by the distributor.
+pub fn lines() -> u32 {
+    LINES.load(Ordering::Relaxed)
+}
+
+/// Set the interrupt `id` to be edge or level triggered by `mode`.
+pub fn configure(id: u32, mode: IrqMode) {
+    let offset = GICD_ICFGR + (id as usize / 16) * 4;
+    let bit = 1 << ((id % 16) * 2 + 1);
+    let value = gicd_read(offset);
+    let value = if mode.is_level() {
+        value & !bit
+    } else {
+        value | bit
+    };
+    gicd_write(offset, value);
+}
+
+/// Mask or unmask the interrupt `id`.
+pub fn set_masked(id: u32, masked: bool) {
+    let offset = if masked {
+        GICD_ICENABLER
+    } else {
+        GICD_ISENABLER
+    };
+    gicd_write(offset + (id as usize / 32) * 4, 1 << (id % 32));
+}
+
+/// Acknowledge the pending interrupt of the highest priority.
+pub fn ack() -> Option<u32> {
+    match unsafe { reg(GICC_BASE, GICC_IAR).read_volatile() } & 0x3ff {
+        SPURIOUS_ID => None,
+        id => Some(id),
+    }
+}
+
+/// Signal the end of the handling of the interrupt `id`.
+pub fn eoi(id: u32) {
+    gicc_write(GICC_EOIR, id);
+}
@@ -0,0 +1,232 @@
+//! The aarch64 platforms with a GICv2 and a PL011 UART: QEMU virt with a
+//! PL031 RTC and the secondary CPUs started by PSCI, and Raspberry Pi 4 with
+//! the `board-raspi4` feature.
+//!
+//! The kernel runs at EL1 in the upper half of the address space, mapped by
+//! `TTBR1_EL1`, and the page tables of the user programs are in `TTBR0_EL1`.
+
+use super::*;
+
+mod gic;
+mod paging;
+pub mod serial;
+mod smp;
+mod trap;
+
+pub use self::paging::{tlb_flush, PageTable};
+pub(crate) use self::smp::{cpu_id, secondary_cpus, start_cpu};
+pub use self::trap::{
+    irq_disable, irq_enable, irq_handle, irq_range, irq_register, irq_unregister,
+};
+
+/// The physical addresses and the interrupt IDs of the devices.
+#[cfg(not(feature = "board-raspi4"))]
+mod board {
+    pub const GICD_BASE: usize = 0x0800_0000;
+    pub const GICC_BASE: usize = 0x0801_0000;
+    pub const UART_BASE: usize = 0x0900_0000;
+    pub const UART_IRQ: u32 = 33;
+    pub const RTC_BASE: usize = 0x0901_0000;
+    pub const VIRTIO_MMIO_BASE: usize = 0x0a00_0000;
+    pub const VIRTIO_MMIO_STRIDE: usize = 0x200;
+    pub const VIRTIO_MMIO_COUNT: usize = 32;
+    pub const VIRTIO_MMIO_IRQ: u32 = 48;
+    /// The ECAM of the PCIe host bridge below 4GiB, for 16 buses.
+    pub const PCIE_ECAM_BASE: usize = 0x3f00_0000;
+    pub const PCIE_BUS_END: u8 = 15;
+    pub const PCIE_MMIO_BASE: usize = 0x1000_0000;
+    pub const PCIE_MMIO_END: usize = 0x3eff_0000;
+    /// The interrupt ID of the legacy INTA of the device 0.
+    pub const PCIE_IRQ: u32 = 35;
+}
+
+/// The physical addresses and the interrupt IDs of the devices.
+#[cfg(feature = "board-raspi4")]
+mod board {
+    pub const GICD_BASE: usize = 0xff84_1000;
+    pub const GICC_BASE: usize = 0xff84_2000;
+    pub const UART_BASE: usize = 0xfe20_1000;
+    pub const UART_IRQ: u32 = 153;
+    /// There is no RTC on the board.
+    pub const RTC_BASE: usize = 0;
+    pub const VIRTIO_MMIO_BASE: usize = 0;
+    pub const VIRTIO_MMIO_STRIDE: usize = 0;
+    pub const VIRTIO_MMIO_COUNT: usize = 0;
+    pub const VIRTIO_MMIO_IRQ: u32 = 0;
+    /// The PCIe host bridge is not compatible with the ECAM.
+    pub const PCIE_ECAM_BASE: usize = 0;
+    pub const PCIE_BUS_END: u8 = 0;
+    pub const PCIE_MMIO_BASE: usize = 0;
+    pub const PCIE_MMIO_END: usize = 0;
+    pub const PCIE_IRQ: u32 = 0;
+}
+
+/// The frequency of the timer interrupt.
+const TICKS_PER_SECOND: u64 = 100;
+
+/// PSCI function IDs of system power control.
+const PSCI_SYSTEM_OFF: u32 = 0x8400_0008;
+const PSCI_SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// Initialize the CPU and the devices of the platform.
+pub fn init() {
+    unsafe {
+        // set `VBAR_EL1` to the exception vectors routing to `trap_handler`
+        trapframe::init();
+    }
+    paging::init();
+    smp::init();
+    gic::init();
+    gic::set_masked(trap::TIMER_VECTOR, false);
+    set_next_tick();
+    serial::init();
+    unsafe { asm!("msr daifclr, #2") };
+}
+
+/// The slots of the virtio MMIO devices, and their IRQ vectors.
+pub(crate) fn virtio_mmio_slots() -> impl Iterator<Item = (PhysAddr, u32)> {
+    use self::board::*;
+    (0..VIRTIO_MMIO_COUNT).map(|i| {
+        let paddr = VIRTIO_MMIO_BASE + i * VIRTIO_MMIO_STRIDE;
+        (paddr, VIRTIO_MMIO_IRQ + i as u32)
+    })
+}
+
+/// The ECAM of the PCIe host bridge, and the buses it covers.
+pub(crate) fn pci_ecam() -> Option<(PhysAddr, core::ops::RangeInclusive<u8>)> {
+    match board::PCIE_ECAM_BASE {
+        0 => None,
+        base => Some((base, 0..=board::PCIE_BUS_END)),
+    }
+}
+
+/// The MMIO window of the host bridge, where the BARs are assigned.
+pub(crate) fn pci_mmio_window() -> Option<core::ops::Range<PhysAddr>> {
+    Some(board::PCIE_MMIO_BASE..board::PCIE_MMIO_END).filter(|window| !window.is_empty())
+}
+
+/// The legacy IRQs are the interrupt IDs from `PCIE_IRQ`, swizzled by the
+/// device number.
+pub(crate) fn pci_legacy_irq(addr: PciAddr, pin: u8, _line: u8) -> Option<u32> {
+    Some(board::PCIE_IRQ + (addr.device as u32 + pin as u32 - 1) % 4)
+}
+
+/// Run `f` with interrupts disabled.
+pub(crate) fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let daif: usize;
+    unsafe {
+        asm!("mrs {}, daif", out(reg) daif);
+        asm!("msr daifset, #2");
+    }
+    let ret = f();
+    unsafe { asm!("msr daif, {}", in(reg) daif) };
+    ret
+}
+
+/// Halt the CPU until the next interrupt.
+pub(crate) fn wait_for_interrupt() {
+    unsafe { asm!("msr daifclr, #2", "wfi") };
+}
+
+/// Program the EL1 physical timer for the next timer interrupt.
+fn set_next_tick() {
+    let interval = timer_ticks_per_second() / TICKS_PER_SECOND;
+    unsafe {
+        asm!("msr cntp_tval_el0, {}", in(reg) interval);
+        // enabled, not masked
+        asm!("msr cntp_ctl_el0, {}", in(reg) 1usize);
+    }
+}
+
+/// Read the physical counter, which is also read by the vDSO without
+/// entering the kernel.
+pub fn timer_ticks() -> u64 {
+    let ticks: u64;
+    unsafe { asm!("isb", "mrs {}, cntpct_el0", out(reg) ticks) };
+    ticks
+}
+
+/// Get the frequency of the counter, set by the firmware.
+pub fn timer_ticks_per_second() -> u64 {
+    let freq: u64;
+    unsafe { asm!("mrs {}, cntfrq_el0", out(reg) freq) };
+    freq
+}
+
+/// Get the size of the smallest data cache line, from `CTR_EL0`.
+pub fn cache_line_size() -> u32 {
+    let ctr: u64;
+    unsafe { asm!("mrs {}, ctr_el0", out(reg) ctr) };
+    4 << ((ctr >> 16) & 0xf)
+}
+
+/// Read the seconds since the UNIX epoch from the PL031 RTC.
+pub(crate) fn rtc_now() -> Option<Duration> {
+    if board::RTC_BASE == 0 {
+        return None;
+    }
+    // the data register
+    let seconds = unsafe { (phys_to_virt(board::RTC_BASE) as *const u32).read_volatile() };
+    Some(Duration::from_secs(seconds as u64))
+}
+
+/// Read a random number by RNDR, if the CPU implements FEAT_RNG.
+pub(crate) fn hw_random() -> Option<u64> {
+    let isar0: u64;
+    unsafe { asm!("mrs {}, id_aa64isar0_el1", out(reg) isar0) };
+    if isar0 >> 60 == 0 {
+        return None;
+    }
+    let (value, nzcv): (u64, u64);
+    unsafe {
+        // RNDR, which sets NZCV to 0b0100 on failure
+        asm!("mrs {}, s3_3_c2_c4_0", "mrs {}, nzcv", out(reg) value, out(reg) nzcv);
+    }
+    if nzcv & (1 << 30) == 0 {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// No CPU features are exposed to the user programs.
+pub fn cpu_features() -> vdso::Features {
+    vdso::Features::default()
+}
+
+/// Clean and invalidate the data cache of the frame to the point of
+/// coherency.
+pub fn frame_flush(target: PhysAddr) {
+    let line = cache_line_size() as usize;
+    let start = phys_to_virt(target);
+    for vaddr in (start..start + PAGE_SIZE).step_by(line) {
+        unsafe { asm!("dc civac, {}", in(reg) vaddr) };
+    }
+    unsafe { asm!("dsb sy") };
+}
+
+/// Run the user context until a trap, an interrupt or a syscall.
+pub fn context_run(context: &mut UserContext) {
+    context.run();
+}
+
+/// Reboot the system by PSCI.
+pub(crate) fn reboot() -> ! {
+    psci_system(PSCI_SYSTEM_RESET)
+}
+
+/// Shut down the system by PSCI.
+pub(crate) fn shutdown() -> ! {
+    psci_system(PSCI_SYSTEM_OFF)
+}
+
+fn psci_system(func_id: u32) -> ! {
+    kernel_hal::smc_call(&SmcParams {
+        func_id,
+        ..Default::default()
+    });
+    unreachable!("PSCI function {:#x} returned", func_id)
+}
@@ -0,0 +1,225 @@
+//! 4-level page tables of the user address space, with 4KiB granules.
+//!
+//! The tables are accessed by the linear mapping of the physical memory.
+//! `MAIR_EL1` is set by the bootloader, with the normal memory at index 0,
+//! and the uncached memory types are added at indexes 5-7.
+
+use {
+    crate::{phys_to_virt, PhysFrame},
+    bitflags::bitflags,
+    core::ops::Range,
+    kernel_hal::{
+        CachePolicy, HalError, MMUFlags, PageTableTrait, PhysAddr, Result, VirtAddr, PAGE_SIZE,
+    },
+};
+
+bitflags! {
+    struct PTF: u64 {
+        const VALID = 1 << 0;
+        /// A table at levels 0-2, or a page at level 3.
+        const TABLE_OR_PAGE = 1 << 1;
+        /// The index into `MAIR_EL1`.
+        const ATTR_INDEX = 0b111 << ATTR_INDEX_SHIFT;
+        /// Accessible at EL0.
+        const AP_EL0 = 1 << 6;
+        const AP_RO = 1 << 7;
+        const INNER_SHAREABLE = 0b11 << 8;
+        const ACCESSED = 1 << 10;
+        const NOT_GLOBAL = 1 << 11;
+        const PXN = 1 << 53;
+        const UXN = 1 << 54;
+    }
+}
+
+const ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// The shift of the index into `MAIR_EL1` in an entry.
+const ATTR_INDEX_SHIFT: u64 = 2;
+
+/// The memory types in `MAIR_EL1` of the uncached policies, at the index 4
+/// plus the policy: Device-nGnRnE, Device-nGnRE and Normal Non-cacheable.
+const MAIR_UNCACHED: [(CachePolicy, u64); 3] = [
+    (CachePolicy::Uncached, 0x00),
+    (CachePolicy::UncachedDevice, 0x04),
+    (CachePolicy::WriteCombining, 0x44),
+];
+
+/// Add the memory types of the uncached policies to `MAIR_EL1` of the
+/// current CPU. The secondary CPUs copy it from the boot CPU.
+pub fn init() {
+    let mut mair: u64;
+    unsafe { asm!("mrs {}, mair_el1", out(reg) mair) };
+    for &(policy, attr) in MAIR_UNCACHED.iter() {
+        let shift = 8 * attr_index(policy);
+        mair = (mair & !(0xff << shift)) | attr << shift;
+    }
+    unsafe { asm!("msr mair_el1, {}", "isb", in(reg) mair) };
+}
+
+/// The index into `MAIR_EL1` of the memory type of `policy`.
+fn attr_index(policy: CachePolicy) -> u64 {
+    match policy {
+        CachePolicy::Cached => 0,
+        policy => 4 + policy as u64,
+    }
+}
+
+type Entries = [u64; 512];
+
+/// Page Table
+#[repr(C)]
+pub struct PageTable {
+    table_phys: PhysAddr,
+}
+
+impl PageTable {
+    /// Get current page table
+    pub fn current() -> Self {
+        let ttbr0: u64;
+        unsafe { asm!("mrs {}, ttbr0_el1", out(reg) ttbr0) };
+        PageTable {
+            table_phys: (ttbr0 & ADDR_MASK) as usize,
+        }
+    }
+
+    /// Create a new empty `PageTable`, the kernel is mapped by `TTBR1_EL1`.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let root = alloc_table().expect("failed to allocate page table");
+        PageTable { table_phys: root }
+    }
+
+    /// Get the entry of the page of `vaddr`, creating the tables on the way
+    /// if `create`.
+    fn walk(&mut self, vaddr: VirtAddr, create: bool) -> Result<&'static mut u64> {
+        let mut table = table_of(self.table_phys);
+        for level in (1..4).rev() {
+            let entry = &mut table[(vaddr >> (12 + 9 * level)) & 0x1ff];
+            let flags = PTF::from_bits_truncate(*entry);
+            if !flags.contains(PTF::VALID) {
+                if !create {
+                    return Err(HalError);
+                }
+                let paddr = alloc_table().ok_or(HalError)?;
+                *entry = paddr as u64 | (PTF::VALID | PTF::TABLE_OR_PAGE).bits();
+            } else if !flags.contains(PTF::TABLE_OR_PAGE) {
+                // a block
+                return Err(HalError);
+            }
+            table = table_of((*entry & ADDR_MASK) as usize);
+        }
+        Ok(&mut table[(vaddr >> 12) & 0x1ff])
+    }
+
+    /// Flush the TLB of the page of `vaddr`, if the page table is active.
+    fn flush(&self, vaddr: VirtAddr) {
+        if Self::current().table_phys == self.table_phys {
+            unsafe {
+                asm!("dsb ishst", "tlbi vaae1is, {}", "dsb ish", "isb", in(reg) vaddr >> 12);
+            }
+        }
+    }
+}
+
+impl PageTableTrait for PageTable {
+    /// Map the page of `vaddr` to the frame of `paddr` with `flags`.
+    fn map(&mut self, vaddr: VirtAddr, paddr: PhysAddr, flags: MMUFlags) -> Result<()> {
+        debug_assert!(vaddr % PAGE_SIZE == 0 && paddr % PAGE_SIZE == 0);
+        let entry = self.walk(vaddr, true)?;
+        *entry = paddr as u64 | to_pt_flags(flags).bits();
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Unmap the page of `vaddr`.
+    fn unmap(&mut self, vaddr: VirtAddr) -> Result<()> {
+        *self.walk(vaddr, false)? = 0;
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Change the `flags` of the page of `vaddr`.
+    fn protect(&mut self, vaddr: VirtAddr, flags: MMUFlags) -> Result<()> {
+        let entry = self.walk(vaddr, false)?;
+        if *entry & PTF::VALID.bits() == 0 {
+            return Err(HalError);
+        }
+        *entry = (*entry & ADDR_MASK) | to_pt_flags(flags).bits();
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Query the physical address which the page of `vaddr` maps to.
+    fn query(&mut self, vaddr: VirtAddr) -> Result<PhysAddr> {
+        let entry = self.walk(vaddr, false)?;
+        if *entry & PTF::VALID.bits() == 0 {
+            return Err(HalError);
+        }
+        Ok((*entry & ADDR_MASK) as usize + vaddr % PAGE_SIZE)
+    }
+
+    /// Get the physical address of root page table.
+    fn table_phys(&self) -> PhysAddr {
+        self.table_phys
+    }
+}
+
+/// The number of pages above which the whole TLB is flushed instead.
+const FLUSH_ALL_PAGES: usize = 32;
+
+/// Invalidate the TLB entries of `vaddr` of the page table `vmtoken` on all
+/// CPUs, broadcast in the inner shareable domain.
+///
+/// There is no ASID, so the entries of `vaddr` of any page table are flushed.
+pub fn tlb_flush(vaddr: Range<VirtAddr>, _vmtoken: usize) {
+    unsafe {
+        asm!("dsb ishst");
+        if (vaddr.end - vaddr.start) / PAGE_SIZE > FLUSH_ALL_PAGES {
+            asm!("tlbi vmalle1is");
+        } else {
+            for vaddr in vaddr.step_by(PAGE_SIZE) {
+                asm!("tlbi vaae1is, {}", in(reg) vaddr >> 12);
+            }
+        }
+        asm!("dsb ish", "isb");
+    }
+}
+
+/// Allocate a zeroed frame for a page table, which is never freed.
+fn alloc_table() -> Option<PhysAddr> {
+    let frame = PhysFrame::alloc()?;
+    let paddr = frame.addr();
+    core::mem::forget(frame);
+    crate::pmem_zero(paddr, PAGE_SIZE);
+    Some(paddr)
+}
+
+fn table_of(paddr: PhysAddr) -> &'static mut Entries {
+    unsafe { &mut *(phys_to_virt(paddr) as *mut Entries) }
+}
+
+/// The pages accessible at EL0 are never executable at EL1, and the device
+/// memory is never executable, where the instructions may be fetched
+/// speculatively.
+fn to_pt_flags(flags: MMUFlags) -> PTF {
+    let policy = flags.cache_policy();
+    let mut pt_flags = PTF::VALID | PTF::TABLE_OR_PAGE | PTF::INNER_SHAREABLE | PTF::ACCESSED;
+    pt_flags |= PTF::from_bits_truncate(attr_index(policy) << ATTR_INDEX_SHIFT);
+    if !flags.contains(MMUFlags::WRITE) {
+        pt_flags |= PTF::AP_RO;
+    }
+    let execute = flags.is_executable()
+        && matches!(policy, CachePolicy::Cached | CachePolicy::WriteCombining);
+    if flags.contains(MMUFlags::USER) {
+        pt_flags |= PTF::AP_EL0 | PTF::NOT_GLOBAL | PTF::PXN;
+        if !execute {
+            pt_flags |= PTF::UXN;
+        }
+    } else {
+        pt_flags |= PTF::UXN;
+        if !execute {
+            pt_flags |= PTF::PXN;
+        }
+    }
+    pt_flags
+}
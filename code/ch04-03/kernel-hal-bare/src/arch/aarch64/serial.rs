@@ -0,0 +1,74 @@
+//! The PL011 UART, set up by the firmware.
+
+use {
+    super::{
+        board::{UART_BASE, UART_IRQ},
+        without_interrupts,
+    },
+    crate::phys_to_virt,
+    alloc::boxed::Box,
+    kernel_hal::IrqMode,
+};
+
+const UART_DR: usize = 0x00;
+const UART_FR: usize = 0x18;
+const UART_IMSC: usize = 0x38;
+const UART_ICR: usize = 0x44;
+
+/// Flag: the receive FIFO is empty.
+const FR_RXFE: u32 = 1 << 4;
+/// Flag: the transmit FIFO is full.
+const FR_TXFF: u32 = 1 << 5;
+/// The receive interrupt.
+const INT_RX: u32 = 1 << 4;
+/// The receive timeout interrupt.
+const INT_RT: u32 = 1 << 6;
+
+fn reg(offset: usize) -> *mut u32 {
+    phys_to_virt(UART_BASE + offset) as *mut u32
+}
+
+/// Receive input by the IRQ.
+pub fn init() {
+    unsafe {
+        reg(UART_ICR).write_volatile(0x7ff);
+        reg(UART_IMSC).write_volatile(INT_RX | INT_RT);
+    }
+    super::irq_register(UART_IRQ, IrqMode::LevelHigh, Box::new(handle_irq))
+        .expect("failed to register the UART IRQ");
+    super::irq_enable(UART_IRQ);
+}
+
+/// Move the received bytes to the input queue of the HAL.
+fn handle_irq() {
+    let mut buf = [0u8; 16];
+    let mut len = 0;
+    while unsafe { reg(UART_FR).read_volatile() } & FR_RXFE == 0 {
+        buf[len] = unsafe { reg(UART_DR).read_volatile() } as u8;
+        len += 1;
+        if len == buf.len() {
+            crate::serial_irq_put(&buf);
+            len = 0;
+        }
+    }
+    crate::serial_irq_put(&buf[..len]);
+}
+
+/// Write `s` to the UART, with `\n` converted to `\r\n`.
+pub fn write(s: &str) {
+    without_interrupts(|| {
+        for &c in s.as_bytes() {
+            if c == b'\n' {
+                putchar(b'\r');
+            }
+            putchar(c);
+        }
+    })
+}
+
+fn putchar(c: u8) {
+    unsafe {
+        while reg(UART_FR).read_volatile() & FR_TXFF != 0 {}
+        reg(UART_DR).write_volatile(c as u32);
+    }
+}
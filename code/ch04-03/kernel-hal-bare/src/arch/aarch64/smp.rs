@@ -0,0 +1,212 @@
+//! The bring-up of the secondary CPUs by PSCI, by `hvc` as on QEMU virt.
+//!
+//! A CPU starts at EL1 with the MMU off at the trampoline copied to a frame.
+//! It loads the system registers of the boot CPU, with a temporary `TTBR0_EL1`
+//! mapping the trampoline at its physical address, enables the MMU and jumps
+//! to `ap_main` on its stack.
+//!
+//! The CPUs of Raspberry Pi 4 are started by the spin tables instead, which
+//! is not supported.
+
+use {
+    super::{gic, set_next_tick, trap::TIMER_VECTOR},
+    crate::{frame, frame_flush, phys_to_virt, smp::MAX_CPUS},
+    alloc::vec::Vec,
+    core::sync::atomic::{AtomicU32, Ordering},
+    kernel_hal::{PhysAddr, VirtAddr, PAGE_SIZE},
+    spin::Once,
+};
+
+macro_rules! read_sysreg {
+    ($name:literal) => {{
+        let value: u64;
+        unsafe { asm!(concat!("mrs {}, ", $name), out(reg) value) };
+        value
+    }};
+}
+
+global_asm!(
+    ".pushsection .rodata.ap_trampoline, \"a\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    // the parameters in `x0`
+    "ap_trampoline_start:",
+    "    msr spsel, #1",
+    "    ldp x1, x2, [x0]",
+    "    msr mair_el1, x1",
+    "    msr tcr_el1, x2",
+    "    ldp x1, x2, [x0, #16]",
+    "    msr ttbr0_el1, x1",
+    "    msr ttbr1_el1, x2",
+    "    ldp x1, x2, [x0, #32]",
+    "    msr cpacr_el1, x2",
+    "    ldp x2, x3, [x0, #48]",
+    "    mov sp, x2",
+    "    ldr x2, [x0, #64]",
+    "    tlbi vmalle1",
+    "    dsb nsh",
+    "    isb",
+    "    msr sctlr_el1, x1",
+    "    isb",
+    "    mov x0, x2",
+    "    br x3",
+    "ap_trampoline_end:",
+    ".popsection",
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+}
+
+const PSCI_CPU_ON: usize = 0xc400_0003;
+const PSCI_AFFINITY_INFO: usize = 0xc400_0004;
+/// The CPU is off, from `AFFINITY_INFO`.
+const PSCI_OFF: usize = 1;
+
+/// The parameters of a starting CPU, after the trampoline.
+#[repr(C)]
+struct Params {
+    mair: u64,
+    tcr: u64,
+    ttbr0: u64,
+    ttbr1: u64,
+    sctlr: u64,
+    cpacr: u64,
+    stack: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+/// The frame of the trampoline and the parameters, and the temporary level-0
+/// table, never freed.
+struct Trampoline {
+    page: PhysAddr,
+    page_table: PhysAddr,
+}
+
+static TRAMPOLINE: Once<Option<Trampoline>> = Once::new();
+
+/// The offset of the parameters in the frame of the trampoline.
+const PARAMS_OFFSET: usize = 0x800;
+
+/// Disable the translation by `TTBR0_EL1` when the TLB misses.
+const TCR_EPD0: u64 = 1 << 7;
+
+const MPIDR_NONE: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// The affinity level 0 of the CPUs online, by their numbers.
+static MPIDRS: [AtomicU32; MAX_CPUS] = [MPIDR_NONE; MAX_CPUS];
+
+/// Record the boot CPU as the CPU 0.
+pub fn init() {
+    MPIDRS[0].store(mpidr_aff0(), Ordering::Relaxed);
+}
+
+/// Get the number of the current CPU.
+pub fn cpu_id() -> u32 {
+    let aff0 = mpidr_aff0();
+    let cpu = MPIDRS
+        .iter()
+        .position(|id| id.load(Ordering::Relaxed) == aff0);
+    cpu.unwrap_or(0) as u32
+}
+
+/// Get the affinity level 0 of the CPUs off in the first cluster.
+pub fn secondary_cpus() -> Vec<u32> {
+    if cfg!(feature = "board-raspi4") {
+        return Vec::new();
+    }
+    (0..MAX_CPUS as u32)
+        .filter(|&aff0| psci_call(PSCI_AFFINITY_INFO, aff0 as usize, 0, 0) == PSCI_OFF)
+        .collect()
+}
+
+/// Start the CPU of `aff0` as the CPU `cpu`, on the stack at `stack_top`.
+pub fn start_cpu(aff0: u32, cpu: u32, stack_top: VirtAddr) -> bool {
+    let trampoline = match TRAMPOLINE.call_once(Trampoline::new) {
+        Some(trampoline) => trampoline,
+        None => return false,
+    };
+    let params = Params {
+        mair: read_sysreg!("mair_el1"),
+        tcr: read_sysreg!("tcr_el1") & !TCR_EPD0,
+        ttbr0: trampoline.page_table as u64,
+        ttbr1: read_sysreg!("ttbr1_el1"),
+        sctlr: read_sysreg!("sctlr_el1"),
+        cpacr: read_sysreg!("cpacr_el1"),
+        stack: stack_top as u64,
+        entry: ap_main as usize as u64,
+        cpu: cpu as u64,
+    };
+    let paddr = trampoline.page + PARAMS_OFFSET;
+    unsafe { (phys_to_virt(paddr) as *mut Params).write_volatile(params) };
+    // read with the MMU off
+    frame_flush(trampoline.page);
+    psci_call(PSCI_CPU_ON, aff0 as usize, trampoline.page, paddr) == 0
+}
+
+impl Trampoline {
+    /// Copy the trampoline, and map the 1 GiB block of it by the temporary
+    /// tables.
+    fn new() -> Option<Self> {
+        let page = frame::alloc()?;
+        let page_table = frame::alloc()?;
+        let level1 = frame::alloc()?;
+        unsafe {
+            let start = &ap_trampoline_start as *const u8;
+            let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+            start.copy_to_nonoverlapping(phys_to_virt(page) as *mut u8, len);
+        }
+        crate::pmem_zero(page_table, PAGE_SIZE);
+        crate::pmem_zero(level1, PAGE_SIZE);
+        // valid, and a table
+        let table = 0b11;
+        // valid, accessed, inner shareable, and the normal memory in `MAIR_EL1`
+        let block = 1 | 1 << 10 | 0b11 << 8;
+        let write_entry = |table: PhysAddr, index: usize, entry: u64| unsafe {
+            (phys_to_virt(table) as *mut u64).add(index).write(entry);
+        };
+        write_entry(page_table, (page >> 39) & 0x1ff, level1 as u64 | table);
+        let block_addr = (page & !((1 << 30) - 1)) as u64;
+        write_entry(level1, (page >> 30) & 0x1ff, block_addr | block);
+        for &paddr in &[page, page_table, level1] {
+            frame_flush(paddr);
+        }
+        Some(Trampoline { page, page_table })
+    }
+}
+
+fn mpidr_aff0() -> u32 {
+    (read_sysreg!("mpidr_el1") & 0xff) as u32
+}
+
+fn psci_call(fid: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
+    let ret;
+    unsafe {
+        asm!(
+            "hvc #0",
+            inlateout("x0") fid => ret,
+            in("x1") arg0,
+            in("x2") arg1,
+            in("x3") arg2,
+        );
+    }
+    ret
+}
+
+/// The entry of a secondary CPU from the trampoline.
+extern "C" fn ap_main(cpu: u32) -> ! {
+    // no user space until a context runs
+    let ttbr0 = frame::zero_frame();
+    unsafe {
+        asm!("msr ttbr0_el1, {}", "tlbi vmalle1", "dsb nsh", "isb", in(reg) ttbr0);
+        trapframe::init();
+    }
+    gic::init_cpu();
+    gic::set_masked(TIMER_VECTOR, false);
+    set_next_tick();
+    MPIDRS[cpu as usize].store(mpidr_aff0(), Ordering::Relaxed);
+    unsafe { asm!("msr daifclr, #2") };
+    crate::smp::secondary_main(cpu)
+}
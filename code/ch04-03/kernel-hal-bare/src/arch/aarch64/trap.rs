@@ -0,0 +1,118 @@
+//! Handling the traps in the kernel, and the IRQs.
+//!
+//! The IRQ vectors are the interrupt IDs of the GIC. The IRQ exception
+//! taken by the CPU has the vector `IRQ_EXCEPTION`, where the pending
+//! interrupts are acknowledged and handled.
+
+use {
+    super::gic,
+    crate::{executor, fixup::fixup},
+    alloc::{collections::BTreeMap, sync::Arc},
+    core::ops::Range,
+    kernel_hal::{HalError, IrqHandler, IrqMode, Result},
+    lazy_static::lazy_static,
+    spin::Mutex,
+    trapframe::TrapFrame,
+};
+
+/// The kind of the exception in the low bits of `trap_num`: IRQ.
+pub const IRQ_EXCEPTION: u32 = 1;
+/// The EL1 physical timer, a private peripheral interrupt.
+pub const TIMER_VECTOR: u32 = 30;
+
+lazy_static! {
+    static ref IRQS: Mutex<BTreeMap<u32, Arc<dyn Fn() + Send + Sync>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Handle the traps in the kernel, called by `trapframe`.
+///
+/// The traps in the user mode return from `context_run` instead. The faults
+/// of the copies from or to the user memory resume at their fixups.
+#[no_mangle]
+pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    if tf.trap_num as u32 & 0xffff == IRQ_EXCEPTION {
+        irq_handle(IRQ_EXCEPTION);
+        return;
+    }
+    if let Some(fixup) = fixup(tf.elr) {
+        tf.elr = fixup;
+        return;
+    }
+    let (esr, far): (usize, usize);
+    unsafe {
+        asm!("mrs {}, esr_el1", out(reg) esr);
+        asm!("mrs {}, far_el1", out(reg) far);
+    }
+    panic!(
+        "unhandled exception in kernel: esr={:#x}, far={:#x}, {:#x?}",
+        esr, far, tf
+    );
+}
+
+/// Handle the interrupt `vector`, return whether it is handled.
+///
+/// It is also called with the interrupts taken in the user mode.
+pub fn irq_handle(vector: u32) -> bool {
+    if vector != IRQ_EXCEPTION {
+        return false;
+    }
+    while let Some(id) = gic::ack() {
+        if id == TIMER_VECTOR {
+            super::set_next_tick();
+            executor::timer_interrupt();
+        } else {
+            let handler = IRQS.lock().get(&id).cloned();
+            match handler {
+                Some(handler) => handler(),
+                None => warn!("unhandled IRQ: {}", id),
+            }
+        }
+        gic::eoi(id);
+    }
+    true
+}
+
+/// The IRQ vectors of the shared peripheral interrupts.
+pub fn irq_range() -> Range<u32> {
+    gic::SPI_BASE..gic::lines()
+}
+
+/// Register the handler of the IRQ, triggered in `mode`.
+pub fn irq_register(vector: u32, mode: IrqMode, handler: IrqHandler) -> Result<()> {
+    if !irq_range().contains(&vector) {
+        return Err(HalError);
+    }
+    super::without_interrupts(|| {
+        let mut irqs = IRQS.lock();
+        if irqs.contains_key(&vector) {
+            return Err(HalError);
+        }
+        irqs.insert(vector, Arc::from(handler));
+        gic::configure(vector, mode);
+        Ok(())
+    })
+}
+
+/// Unregister the handler of the IRQ, and mask it.
+pub fn irq_unregister(vector: u32) -> Result<()> {
+    super::without_interrupts(|| {
+        IRQS.lock().remove(&vector).ok_or(HalError)?;
+        gic::set_masked(vector, true);
+        Ok(())
+    })
+}
+
+/// Unmask the IRQ in the GIC.
+pub fn irq_enable(vector: u32) {
+    if irq_range().contains(&vector) {
+        gic::set_masked(vector, false);
+    }
+}
+
+/// Mask the IRQ in the GIC.
+pub fn irq_disable(vector: u32) {
+    if irq_range().contains(&vector) {
+        gic::set_masked(vector, true);
+    }
+}
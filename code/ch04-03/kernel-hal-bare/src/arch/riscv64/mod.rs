@@ -0,0 +1,155 @@
+//! The riscv64 platform of QEMU virt: the SBI console, timer and system
+//! reset, the PLIC, the secondary harts and Sv39 paging.
+
+use {
+    super::*,
+    riscv::{
+        asm,
+        register::{sie, sstatus, time},
+    },
+};
+
+mod paging;
+mod plic;
+mod sbi;
+pub mod serial;
+mod smp;
+mod trap;
+
+pub use self::paging::{tlb_flush, PageTable};
+pub(crate) use self::smp::{cpu_id, secondary_cpus, start_cpu};
+pub use self::trap::{
+    irq_disable, irq_enable, irq_handle, irq_range, irq_register, irq_unregister,
+};
+
+/// The frequency of the `time` CSR on QEMU virt.
+const TIMEBASE_FREQ: u64 = 10_000_000;
+
+/// The frequency of the timer interrupt.
+const TICKS_PER_SECOND: u64 = 100;
+
+/// Initialize the CPU and the devices of the platform.
+pub fn init() {
+    unsafe {
+        // set `stvec` to the trap entry routing to `trap_handler`
+        trapframe::init();
+        sie::set_stimer();
+        sie::set_sext();
+    }
+    smp::init();
+    plic::init();
+    set_next_tick();
+    unsafe { sstatus::set_sie() };
+}
+
+/// The slots of the virtio MMIO devices on QEMU virt, and their IRQ vectors.
+pub(crate) fn virtio_mmio_slots() -> impl Iterator<Item = (PhysAddr, u32)> {
+    (0..8).map(|i| (0x1000_1000 + i * 0x1000, trap::IRQ_BASE + 1 + i as u32))
+}
+
+/// The ECAM of the PCIe host bridge on QEMU virt, and the buses it covers.
+pub(crate) fn pci_ecam() -> Option<(PhysAddr, core::ops::RangeInclusive<u8>)> {
+    Some((0x3000_0000, 0..=255))
+}
+
+/// The MMIO window of the host bridge, where the BARs are assigned.
+pub(crate) fn pci_mmio_window() -> Option<core::ops::Range<PhysAddr>> {
+    Some(0x4000_0000..0x8000_0000)
+}
+
+/// The legacy IRQs are the PLIC sources from 32, swizzled by the device
+/// number.
+pub(crate) fn pci_legacy_irq(addr: PciAddr, pin: u8, _line: u8) -> Option<u32> {
+    Some(trap::IRQ_BASE + 32 + (addr.device as u32 + pin as u32 - 1) % 4)
+}
+
+/// Run `f` with interrupts disabled.
+pub(crate) fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let enabled = sstatus::read().sie();
+    if enabled {
+        unsafe { sstatus::clear_sie() };
+    }
+    let ret = f();
+    if enabled {
+        unsafe { sstatus::set_sie() };
+    }
+    ret
+}
+
+/// Halt the CPU until the next interrupt.
+pub(crate) fn wait_for_interrupt() {
+    unsafe {
+        sstatus::set_sie();
+        asm::wfi();
+    }
+}
+
+/// Program the SBI timer for the next timer interrupt.
+fn set_next_tick() {
+    sbi::set_timer(timer_ticks() + TIMEBASE_FREQ / TICKS_PER_SECOND);
+}
+
+/// Read the `time` CSR, which is also read by the vDSO without entering the
+/// kernel.
+pub fn timer_ticks() -> u64 {
+    time::read() as u64
+}
+
+/// Get the frequency of the `time` CSR.
+pub fn timer_ticks_per_second() -> u64 {
+    TIMEBASE_FREQ
+}
+
+/// The size of the cache line is not exposed by the ISA.
+pub fn cache_line_size() -> u32 {
+    64
+}
+
+/// The goldfish RTC on QEMU virt.
+const GOLDFISH_RTC_BASE: PhysAddr = 0x0010_1000;
+
+/// Read the nanoseconds since the UNIX epoch from the goldfish RTC.
+pub(crate) fn rtc_now() -> Option<Duration> {
+    let base = phys_to_virt(GOLDFISH_RTC_BASE);
+    // reading the low half latches the high half
+    let low = unsafe { (base as *const u32).read_volatile() } as u64;
+    let high = unsafe { ((base + 4) as *const u32).read_volatile() } as u64;
+    Some(Duration::from_nanos(high << 32 | low))
+}
+
+/// There is no RNG reachable without drivers on QEMU virt.
+pub(crate) fn hw_random() -> Option<u64> {
+    None
+}
+
+/// No CPU features are exposed to the user programs.
+pub fn cpu_features() -> vdso::Features {
+    vdso::Features::default()
+}
+
+/// The caches are coherent with the memory.
+pub fn frame_flush(_target: PhysAddr) {}
+
+/// Reboot the system by the SBI.
+pub(crate) fn reboot() -> ! {
+    sbi::system_reboot();
+    warn!("failed to reboot, shut down instead");
+    shutdown()
+}
+
+/// Shut down the system by the SBI.
+pub(crate) fn shutdown() -> ! {
+    sbi::system_shutdown();
+    loop {
+        asm::wfi();
+    }
+}
+
+/// Run the user context until a trap, an interrupt or a syscall.
+pub fn context_run(context: &mut UserContext) {
+    context.run();
+    kernel_hal::save_user_trap();
+}
@@ -0,0 +1,195 @@
+//! Sv39 page tables.
+//!
+//! The tables are accessed by the linear mapping of the physical memory.
+//! The kernel half of the address space is shared by all page tables.
+
+use {
+    super::sbi,
+    crate::{phys_to_virt, smp, PhysFrame},
+    bitflags::bitflags,
+    core::ops::Range,
+    kernel_hal::{HalError, MMUFlags, PageTableTrait, PhysAddr, Result, VirtAddr, PAGE_SIZE},
+    riscv::{asm, register::satp},
+};
+
+bitflags! {
+    struct PTF: u64 {
+        const VALID = 1 << 0;
+        const READABLE = 1 << 1;
+        const WRITABLE = 1 << 2;
+        const EXECUTABLE = 1 << 3;
+        const USER = 1 << 4;
+        const GLOBAL = 1 << 5;
+        const ACCESSED = 1 << 6;
+        const DIRTY = 1 << 7;
+    }
+}
+
+/// The leaf entries have any of the R/W/X bits.
+const LEAF: PTF = PTF::from_bits_truncate(0b1110);
+
+/// The physical page number starts at bit 10 of the entries.
+const PPN_SHIFT: usize = 10;
+const PPN_MASK: u64 = (1 << 44) - 1;
+
+/// The mode field of `satp` for Sv39.
+const SATP_SV39: usize = 8 << 60;
+
+type Entries = [u64; 512];
+
+/// Page Table
+#[repr(C)]
+pub struct PageTable {
+    table_phys: PhysAddr,
+}
+
+impl PageTable {
+    /// Get current page table
+    pub fn current() -> Self {
+        PageTable {
+            table_phys: satp::read().ppn() << 12,
+        }
+    }
+
+    /// Create a new `PageTable`, with the kernel half of the current one.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let root = alloc_table().expect("failed to allocate page table");
+        let table = table_of(root);
+        let current = table_of(Self::current().table_phys);
+        table[256..].copy_from_slice(&current[256..]);
+        PageTable { table_phys: root }
+    }
+
+    /// The value of `satp` to activate the page table.
+    pub fn satp(&self) -> usize {
+        SATP_SV39 | (self.table_phys >> 12)
+    }
+
+    /// Get the entry of the page of `vaddr`, creating the tables on the way
+    /// if `create`.
+    fn walk(&mut self, vaddr: VirtAddr, create: bool) -> Result<&'static mut u64> {
+        let mut table = table_of(self.table_phys);
+        for level in (1..3).rev() {
+            let entry = &mut table[(vaddr >> (12 + 9 * level)) & 0x1ff];
+            let flags = PTF::from_bits_truncate(*entry);
+            if !flags.contains(PTF::VALID) {
+                if !create {
+                    return Err(HalError);
+                }
+                let paddr = alloc_table().ok_or(HalError)?;
+                *entry = make_entry(paddr, PTF::VALID);
+            } else if flags.intersects(LEAF) {
+                // a huge page
+                return Err(HalError);
+            }
+            table = table_of(entry_addr(*entry));
+        }
+        Ok(&mut table[(vaddr >> 12) & 0x1ff])
+    }
+
+    /// Flush the TLB of the page of `vaddr`, if the page table is active.
+    fn flush(&self, vaddr: VirtAddr) {
+        if Self::current().table_phys == self.table_phys {
+            unsafe { asm::sfence_vma(0, vaddr) };
+        }
+    }
+}
+
+impl PageTableTrait for PageTable {
+    /// Map the page of `vaddr` to the frame of `paddr` with `flags`.
+    fn map(&mut self, vaddr: VirtAddr, paddr: PhysAddr, flags: MMUFlags) -> Result<()> {
+        debug_assert!(vaddr % PAGE_SIZE == 0 && paddr % PAGE_SIZE == 0);
+        let entry = self.walk(vaddr, true)?;
+        *entry = make_entry(paddr, to_pt_flags(flags));
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Unmap the page of `vaddr`.
+    fn unmap(&mut self, vaddr: VirtAddr) -> Result<()> {
+        *self.walk(vaddr, false)? = 0;
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Change the `flags` of the page of `vaddr`.
+    fn protect(&mut self, vaddr: VirtAddr, flags: MMUFlags) -> Result<()> {
+        let entry = self.walk(vaddr, false)?;
+        if *entry & PTF::VALID.bits() == 0 {
+            return Err(HalError);
+        }
+        *entry = make_entry(entry_addr(*entry), to_pt_flags(flags));
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Query the physical address which the page of `vaddr` maps to.
+    fn query(&mut self, vaddr: VirtAddr) -> Result<PhysAddr> {
+        let entry = self.walk(vaddr, false)?;
+        if *entry & PTF::VALID.bits() == 0 {
+            return Err(HalError);
+        }
+        Ok(entry_addr(*entry) + vaddr % PAGE_SIZE)
+    }
+
+    /// Get the physical address of root page table.
+    fn table_phys(&self) -> PhysAddr {
+        self.table_phys
+    }
+}
+
+/// Invalidate the TLB entries of `vaddr` of the page table `vmtoken` on all
+/// CPUs, by the remote fences of SBI if there are others.
+///
+/// There is no ASID, so the entries of `vaddr` of any page table are flushed.
+pub fn tlb_flush(vaddr: Range<VirtAddr>, _vmtoken: usize) {
+    if smp::cpu_count() > 1 {
+        sbi::remote_sfence_vma_all(vaddr.start, vaddr.end - vaddr.start);
+    } else {
+        for vaddr in vaddr.step_by(PAGE_SIZE) {
+            unsafe { asm::sfence_vma(0, vaddr) };
+        }
+    }
+}
+
+/// Allocate a zeroed frame for a page table, which is never freed.
+fn alloc_table() -> Option<PhysAddr> {
+    let frame = PhysFrame::alloc()?;
+    let paddr = frame.addr();
+    core::mem::forget(frame);
+    crate::pmem_zero(paddr, PAGE_SIZE);
+    Some(paddr)
+}
+
+fn table_of(paddr: PhysAddr) -> &'static mut Entries {
+    unsafe { &mut *(phys_to_virt(paddr) as *mut Entries) }
+}
+
+fn make_entry(paddr: PhysAddr, flags: PTF) -> u64 {
+    ((paddr as u64 >> 12) << PPN_SHIFT) | flags.bits()
+}
+
+fn entry_addr(entry: u64) -> PhysAddr {
+    (((entry >> PPN_SHIFT) & PPN_MASK) << 12) as PhysAddr
+}
+
+/// The A and D bits are always set, since they may not be updated by the
+/// hardware. The cache policy is ignored, as there are no memory types in
+/// the entries, but those of the physical memory attributes of the platform.
+fn to_pt_flags(flags: MMUFlags) -> PTF {
+    let mut pt_flags = PTF::VALID | PTF::ACCESSED | PTF::DIRTY;
+    if flags.contains(MMUFlags::READ) {
+        pt_flags |= PTF::READABLE;
+    }
+    if flags.contains(MMUFlags::WRITE) {
+        pt_flags |= PTF::WRITABLE;
+    }
+    if flags.is_executable() {
+        pt_flags |= PTF::EXECUTABLE;
+    }
+    if flags.contains(MMUFlags::USER) {
+        pt_flags |= PTF::USER;
+    }
+    pt_flags
+}
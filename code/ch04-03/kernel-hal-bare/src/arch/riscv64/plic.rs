@@ -0,0 +1,54 @@
+//! The Platform-Level Interrupt Controller of QEMU virt.
+//!
+//! Only the S-mode context of the boot hart is used.
+
+use crate::phys_to_virt;
+
+const PLIC_BASE: usize = 0x0c00_0000;
+/// The S-mode context of hart 0.
+const CONTEXT: usize = 1;
+
+/// The number of interrupt sources, where the source 0 is reserved.
+pub const SOURCES: u32 = 64;
+
+fn reg(offset: usize) -> *mut u32 {
+    phys_to_virt(PLIC_BASE + offset) as *mut u32
+}
+
+fn enable_reg(source: u32) -> *mut u32 {
+    reg(0x2000 + 0x80 * CONTEXT + (source as usize / 32) * 4)
+}
+
+/// Set the priority of all sources to 1, mask them, and take all
+/// interrupts with a nonzero priority.
+pub fn init() {
+    for source in 1..SOURCES {
+        unsafe { reg(source as usize * 4).write_volatile(1) };
+        set_masked(source, true);
+    }
+    // the threshold
+    unsafe { reg(0x20_0000 + 0x1000 * CONTEXT).write_volatile(0) };
+}
+
+/// Mask or unmask the `source`.
+pub fn set_masked(source: u32, masked: bool) {
+    let bit = 1 << (source % 32);
+    unsafe {
+        let value = enable_reg(source).read_volatile();
+        let value = if masked { value & !bit } else { value | bit };
+        enable_reg(source).write_volatile(value);
+    }
+}
+
+/// Claim the pending source of the highest priority.
+pub fn claim() -> Option<u32> {
+    match unsafe { reg(0x20_0004 + 0x1000 * CONTEXT).read_volatile() } {
+        0 => None,
+        source => Some(source),
+    }
+}
+
+/// Complete the handling of the claimed `source`.
+pub fn complete(source: u32) {
+    unsafe { reg(0x20_0004 + 0x1000 * CONTEXT).write_volatile(source) };
+}
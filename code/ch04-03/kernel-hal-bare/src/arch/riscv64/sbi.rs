@@ -0,0 +1,105 @@
+//! The legacy extensions of the Supervisor Binary Interface, the Hart State
+//! Management extension, the remote fences and the system reset.
+
+const SBI_SET_TIMER: usize = 0;
+const SBI_CONSOLE_PUTCHAR: usize = 1;
+const SBI_CONSOLE_GETCHAR: usize = 2;
+const SBI_SHUTDOWN: usize = 8;
+
+const EID_HSM: usize = 0x48534d;
+const HSM_HART_START: usize = 0;
+const HSM_HART_GET_STATUS: usize = 2;
+
+const EID_RFENCE: usize = 0x52464e43;
+const RFENCE_REMOTE_SFENCE_VMA: usize = 1;
+
+const EID_SRST: usize = 0x53525354;
+const SRST_SYSTEM_RESET: usize = 0;
+const RESET_TYPE_SHUTDOWN: usize = 0;
+const RESET_TYPE_COLD_REBOOT: usize = 1;
+
+/// The status of a hart not started yet.
+pub const HART_STOPPED: usize = 1;
+
+#[inline(always)]
+fn sbi_call(eid: usize, arg0: usize) -> usize {
+    let ret;
+    unsafe {
+        asm!("ecall", inlateout("a0") arg0 => ret, in("a7") eid);
+    }
+    ret
+}
+
+/// Call the function `fid` of the extension `eid`, return the error and the
+/// value.
+#[inline(always)]
+fn sbi_call_ext(eid: usize, fid: usize, args: [usize; 4]) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        asm!(
+            "ecall",
+            inlateout("a0") args[0] => error,
+            inlateout("a1") args[1] => value,
+            in("a2") args[2],
+            in("a3") args[3],
+            in("a6") fid,
+            in("a7") eid,
+        );
+    }
+    (error, value)
+}
+
+/// Raise the timer interrupt when the `time` CSR reaches `deadline`, and
+/// clear the pending one.
+pub fn set_timer(deadline: u64) {
+    sbi_call(SBI_SET_TIMER, deadline as usize);
+}
+
+/// Write a byte to the console.
+pub fn console_putchar(c: u8) {
+    sbi_call(SBI_CONSOLE_PUTCHAR, c as usize);
+}
+
+/// Read a byte from the console without blocking.
+pub fn console_getchar() -> Option<u8> {
+    match sbi_call(SBI_CONSOLE_GETCHAR, 0) as isize {
+        -1 => None,
+        c => Some(c as u8),
+    }
+}
+
+/// Start the `hart` at the physical `start_addr` in S-mode with the paging
+/// off, and its hart ID in `a0` and `opaque` in `a1`.
+pub fn hart_start(hart: usize, start_addr: usize, opaque: usize) -> bool {
+    sbi_call_ext(EID_HSM, HSM_HART_START, [hart, start_addr, opaque, 0]).0 == 0
+}
+
+/// Get the status of the `hart`, or `None` if there is no such hart.
+pub fn hart_get_status(hart: usize) -> Option<usize> {
+    match sbi_call_ext(EID_HSM, HSM_HART_GET_STATUS, [hart, 0, 0, 0]) {
+        (0, status) => Some(status),
+        _ => None,
+    }
+}
+
+/// Execute `sfence.vma` of `[start, start + size)` on all harts.
+pub fn remote_sfence_vma_all(start: usize, size: usize) {
+    // the mask is ignored with the base of -1
+    let args = [0, usize::MAX, start, size];
+    sbi_call_ext(EID_RFENCE, RFENCE_REMOTE_SFENCE_VMA, args);
+}
+
+/// Reboot the system, or return if the SBI does not support it.
+pub fn system_reboot() {
+    sbi_call_ext(
+        EID_SRST,
+        SRST_SYSTEM_RESET,
+        [RESET_TYPE_COLD_REBOOT, 0, 0, 0],
+    );
+}
+
+/// Shut down the system, by the system reset extension or the legacy one.
+pub fn system_shutdown() {
+    sbi_call_ext(EID_SRST, SRST_SYSTEM_RESET, [RESET_TYPE_SHUTDOWN, 0, 0, 0]);
+    sbi_call(SBI_SHUTDOWN, 0);
+}
@@ -0,0 +1,22 @@
+//! The console of the SBI.
+
+use super::sbi;
+
+/// Move the input of the console to the input queue of the HAL.
+///
+/// The legacy SBI console has no interrupt, so it is polled on each tick.
+pub fn poll() {
+    while let Some(c) = sbi::console_getchar() {
+        crate::serial_irq_put(&[c]);
+    }
+}
+
+/// Write `s` to the console, with `\n` converted to `\r\n`.
+pub fn write(s: &str) {
+    for &c in s.as_bytes() {
+        if c == b'\n' {
+            sbi::console_putchar(b'\r');
+        }
+        sbi::console_putchar(c);
+    }
+}
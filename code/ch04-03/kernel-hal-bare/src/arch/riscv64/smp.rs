@@ -0,0 +1,121 @@
+//! The bring-up of the secondary harts by the SBI HSM extension.
+//!
+//! A hart starts with the paging off at the trampoline copied to a frame.
+//! It sets `stvec` to `ap_entry` before enabling the paging, so it arrives
+//! there by the fault of the next fetch, or the jump if the trampoline is
+//! also mapped. The number of the CPU is kept in `tp`.
+
+use {
+    super::{sbi, set_next_tick},
+    crate::{frame, phys_to_virt, smp::MAX_CPUS},
+    alloc::vec::Vec,
+    kernel_hal::{PhysAddr, VirtAddr},
+    riscv::register::{satp, sie, sstatus},
+    spin::Once,
+};
+
+global_asm!(
+    ".pushsection .rodata.ap_trampoline, \"a\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    // the parameters in `a1`
+    "ap_trampoline_start:",
+    "    ld t0, 0(a1)",
+    "    ld sp, 8(a1)",
+    "    ld t1, 16(a1)",
+    "    ld tp, 24(a1)",
+    "    csrw stvec, t1",
+    "    csrw satp, t0",
+    "    sfence.vma",
+    "    jr t1",
+    "ap_trampoline_end:",
+    ".popsection",
+    // aligned as `stvec` requires
+    ".pushsection .text.ap_entry, \"ax\"",
+    ".balign 4",
+    ".global ap_entry",
+    "ap_entry:",
+    "    mv a0, tp",
+    "    tail ap_main",
+    ".popsection",
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    fn ap_entry();
+}
+
+/// The parameters of a starting hart, after the trampoline.
+#[repr(C)]
+struct Params {
+    satp: usize,
+    stack: usize,
+    entry: usize,
+    cpu: usize,
+}
+
+/// The frame of the trampoline and the parameters, never freed.
+static TRAMPOLINE: Once<Option<PhysAddr>> = Once::new();
+
+/// The offset of the parameters in the frame of the trampoline.
+const PARAMS_OFFSET: usize = 0x800;
+
+/// Set the number of the boot hart to 0.
+pub fn init() {
+    unsafe { asm!("mv tp, zero") };
+}
+
+/// Get the number of the current CPU.
+pub fn cpu_id() -> u32 {
+    let cpu: usize;
+    unsafe { asm!("mv {}, tp", out(reg) cpu) };
+    cpu as u32
+}
+
+/// Get the IDs of the harts not started yet.
+pub fn secondary_cpus() -> Vec<u32> {
+    (0..MAX_CPUS)
+        .filter(|&hart| sbi::hart_get_status(hart) == Some(sbi::HART_STOPPED))
+        .map(|hart| hart as u32)
+        .collect()
+}
+
+/// Start the `hart` as the CPU `cpu`, on the stack at `stack_top`.
+pub fn start_cpu(hart: u32, cpu: u32, stack_top: VirtAddr) -> bool {
+    let page = match *TRAMPOLINE.call_once(copy_trampoline) {
+        Some(page) => page,
+        None => return false,
+    };
+    let params = Params {
+        satp: satp::read().bits(),
+        stack: stack_top,
+        entry: ap_entry as usize,
+        cpu: cpu as usize,
+    };
+    let ptr = phys_to_virt(page + PARAMS_OFFSET) as *mut Params;
+    unsafe { ptr.write_volatile(params) };
+    sbi::hart_start(hart as usize, page, page + PARAMS_OFFSET)
+}
+
+fn copy_trampoline() -> Option<PhysAddr> {
+    let page = frame::alloc()?;
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+        start.copy_to_nonoverlapping(phys_to_virt(page) as *mut u8, len);
+    }
+    Some(page)
+}
+
+/// The entry of a secondary hart from `ap_entry`.
+#[no_mangle]
+extern "C" fn ap_main(cpu: u32) -> ! {
+    unsafe {
+        trapframe::init();
+        sie::set_stimer();
+    }
+    set_next_tick();
+    unsafe { sstatus::set_sie() };
+    crate::smp::secondary_main(cpu)
+}
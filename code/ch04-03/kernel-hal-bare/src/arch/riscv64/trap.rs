@@ -0,0 +1,124 @@
+//! Handling the traps in the kernel, and the IRQs.
+//!
+//! The vectors of the interrupts are their codes in `scause`, and the PLIC
+//! sources are given the vectors from `IRQ_BASE`.
+
+use {
+    super::plic,
+    crate::{executor, fixup::fixup},
+    alloc::{collections::BTreeMap, sync::Arc},
+    core::ops::Range,
+    kernel_hal::{HalError, IrqHandler, IrqMode, Result},
+    lazy_static::lazy_static,
+    riscv::register::{
+        scause::{self, Trap},
+        stval,
+    },
+    spin::Mutex,
+    trapframe::TrapFrame,
+};
+
+/// The supervisor timer interrupt.
+pub const TIMER_VECTOR: u32 = 5;
+/// The supervisor external interrupt, raised by the PLIC.
+pub const EXTERNAL_VECTOR: u32 = 9;
+/// The vector of the PLIC source 0.
+pub const IRQ_BASE: u32 = 0x20;
+
+lazy_static! {
+    static ref IRQS: Mutex<BTreeMap<u32, Arc<dyn Fn() + Send + Sync>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Handle the traps in the kernel, called by `trapframe`.
+///
+/// The traps in the user mode return from `context_run` instead. The faults
+/// of the copies from or to the user memory resume at their fixups.
+#[no_mangle]
+pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    match scause::read().cause() {
+        Trap::Interrupt(_) => {
+            irq_handle(scause::read().code() as u32);
+        }
+        Trap::Exception(_) if fixup(tf.sepc).is_some() => {
+            tf.sepc = fixup(tf.sepc).unwrap();
+        }
+        Trap::Exception(e) => panic!(
+            "unhandled exception in kernel: {:?}, stval={:#x}, {:#x?}",
+            e,
+            stval::read(),
+            tf
+        ),
+    }
+}
+
+/// Handle the interrupt `vector`, return whether it is handled.
+///
+/// It is also called with the interrupts taken in the user mode.
+pub fn irq_handle(vector: u32) -> bool {
+    match vector {
+        TIMER_VECTOR => {
+            super::set_next_tick();
+            super::serial::poll();
+            executor::timer_interrupt();
+            true
+        }
+        EXTERNAL_VECTOR => {
+            while let Some(source) = plic::claim() {
+                let handler = IRQS.lock().get(&(IRQ_BASE + source)).cloned();
+                match handler {
+                    Some(handler) => handler(),
+                    None => warn!("unhandled IRQ: {:#x}", IRQ_BASE + source),
+                }
+                plic::complete(source);
+            }
+            true
+        }
+        _ => false,
+    }
+}
+
+/// The IRQ vectors of the PLIC sources.
+pub fn irq_range() -> Range<u32> {
+    IRQ_BASE + 1..IRQ_BASE + plic::SOURCES
+}
+
+/// Register the handler of the IRQ.
+///
+/// The trigger `mode` is fixed by the devices behind the PLIC.
+pub fn irq_register(vector: u32, _mode: IrqMode, handler: IrqHandler) -> Result<()> {
+    if !irq_range().contains(&vector) {
+        return Err(HalError);
+    }
+    super::without_interrupts(|| {
+        let mut irqs = IRQS.lock();
+        if irqs.contains_key(&vector) {
+            return Err(HalError);
+        }
+        irqs.insert(vector, Arc::from(handler));
+        Ok(())
+    })
+}
+
+/// Unregister the handler of the IRQ, and mask it.
+pub fn irq_unregister(vector: u32) -> Result<()> {
+    super::without_interrupts(|| {
+        IRQS.lock().remove(&vector).ok_or(HalError)?;
+        plic::set_masked(vector - IRQ_BASE, true);
+        Ok(())
+    })
+}
+
+/// Unmask the IRQ in the PLIC.
+pub fn irq_enable(vector: u32) {
+    if irq_range().contains(&vector) {
+        plic::set_masked(vector - IRQ_BASE, false);
+    }
+}
+
+/// Mask the IRQ in the PLIC.
+pub fn irq_disable(vector: u32) {
+    if irq_range().contains(&vector) {
+        plic::set_masked(vector - IRQ_BASE, true);
+    }
+}
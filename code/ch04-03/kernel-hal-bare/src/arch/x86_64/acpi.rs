@@ -0,0 +1,294 @@
+//! The ACPI tables: the CPUs, the IOAPIC and the ISA IRQ overrides in the
+//! MADT, the HPET, the ECAM of PCI in the MCFG, and the reset register and
+//! the i8042 controller in the FADT.
+//!
+//! Without the tables, the platform is assumed to have one CPU, and the
+//! APICs at their default addresses.
+
+use {
+    crate::phys_to_virt,
+    alloc::vec::Vec,
+    core::ops::RangeInclusive,
+    kernel_hal::{AcpiTable, IrqMode, PhysAddr},
+    spin::Once,
+};
+
+/// The RSDP is searched in the BIOS area if not given by the bootloader.
+const BIOS_AREA: core::ops::Range<PhysAddr> = 0xe_0000..0x10_0000;
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+/// The size of the header of all tables.
+const SDT_HEADER_SIZE: usize = 36;
+/// The offset of the revision in the header.
+const SDT_REVISION: usize = 8;
+
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_ADDRESS: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+/// Flags of a local APIC: enabled or able to be online.
+const LOCAL_APIC_USABLE: u32 = 0b11;
+
+/// The offsets of the fields in the FADT.
+const FADT_IAPC_BOOT_ARCH: usize = 109;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REG: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+/// FADT flags: the reset register is supported.
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+/// IA-PC boot architecture flags: the i8042 controller is present.
+const IAPC_BOOT_ARCH_8042: u16 = 1 << 1;
+/// The address spaces of a generic address structure.
+const GAS_SYSTEM_MEMORY: u8 = 0;
+const GAS_SYSTEM_IO: u8 = 1;
+
+const DEFAULT_LAPIC_BASE: PhysAddr = 0xfee0_0000;
+const DEFAULT_IOAPIC_BASE: PhysAddr = 0xfec0_0000;
+
+/// The information found in the ACPI tables.
+#[derive(Debug)]
+pub struct AcpiInfo {
+    /// The APIC IDs of the CPUs, in the order of the MADT.
+    pub cpus: Vec<u32>,
+    pub lapic_base: PhysAddr,
+    /// The address of the first IOAPIC, the only one used.
+    pub ioapic_base: PhysAddr,
+    /// The ISA IRQs routed to other IOAPIC inputs.
+    pub overrides: Vec<IrqOverride>,
+    pub hpet_base: Option<PhysAddr>,
+    /// The ECAM of the PCI segment 0, and the buses it covers.
+    pub pci_ecam: Option<(PhysAddr, RangeInclusive<u8>)>,
+    /// The register written to reset the system.
+    pub reset_reg: Option<ResetRegister>,
+    /// Whether the i8042 controller of the keyboard is present, assumed if
+    /// not told by the FADT.
+    pub has_8042: bool,
+    pub tables: Vec<AcpiTable>,
+}
+
+/// The reset register in the FADT, in the memory or I/O space.
+#[derive(Debug, Clone, Copy)]
+pub enum ResetRegister {
+    Memory { paddr: PhysAddr, value: u8 },
+    Io { port: u16, value: u8 },
+}
+
+/// An ISA IRQ routed to the IOAPIC input `gsi`.
+#[derive(Debug, Clone, Copy)]
+pub struct IrqOverride {
+    pub irq: u32,
+    pub gsi: u32,
+    /// The trigger mode if not the ISA default, edge-triggered active high.
+    pub mode: Option<IrqMode>,
+}
+
+static ACPI: Once<AcpiInfo> = Once::new();
+
+/// Parse the ACPI tables from the RSDP at `rsdp`, or the one in the BIOS
+/// area if `None`.
+pub fn init(rsdp: Option<PhysAddr>) {
+    let info = ACPI.call_once(|| {
+        let mut info = AcpiInfo {
+            cpus: Vec::new(),
+            lapic_base: DEFAULT_LAPIC_BASE,
+            ioapic_base: DEFAULT_IOAPIC_BASE,
+            overrides: Vec::new(),
+            hpet_base: None,
+            pci_ecam: None,
+            reset_reg: None,
+            has_8042: true,
+            tables: Vec::new(),
+        };
+        match rsdp.or_else(find_rsdp) {
+            Some(rsdp) => info.parse(rsdp),
+            None => warn!("ACPI: RSDP not found"),
+        }
+        if info.cpus.is_empty() {
+            info.cpus.push(0);
+        }
+        info
+    });
+    info!(
+        "ACPI: {} tables, {} CPUs, IOAPIC at {:#x}, HPET at {:x?}",
+        info.tables.len(),
+        info.cpus.len(),
+        info.ioapic_base,
+        info.hpet_base
+    );
+}
+
+/// Get the information found in the ACPI tables.
+pub fn info() -> &'static AcpiInfo {
+    ACPI.get().expect("ACPI is not initialized")
+}
+
+/// Get the IOAPIC input of the ISA `irq`, and its trigger mode.
+pub fn isa_irq(irq: u32) -> (u32, IrqMode) {
+    match info().overrides.iter().find(|o| o.irq == irq) {
+        Some(o) => (o.gsi, o.mode.unwrap_or(IrqMode::EdgeHigh)),
+        None => (irq, IrqMode::EdgeHigh),
+    }
+}
+
+fn read<T: Copy>(paddr: PhysAddr) -> T {
+    unsafe { (phys_to_virt(paddr) as *const T).read_unaligned() }
+}
+
+/// Whether the bytes of `len` at `paddr` sum to 0.
+fn checksum_ok(paddr: PhysAddr, len: usize) -> bool {
+    (0..len).fold(0u8, |sum, i| sum.wrapping_add(read::<u8>(paddr + i))) == 0
+}
+
+fn find_rsdp() -> Option<PhysAddr> {
+    // aligned to 16 bytes
+    BIOS_AREA
+        .step_by(16)
+        .find(|&paddr| read::<[u8; 8]>(paddr) == *RSDP_SIGNATURE && checksum_ok(paddr, 20))
+}
+
+impl AcpiInfo {
+    /// Find the tables by the RSDT or the XSDT, and parse the known ones.
+    fn parse(&mut self, rsdp: PhysAddr) {
+        if read::<[u8; 8]>(rsdp) != *RSDP_SIGNATURE || !checksum_ok(rsdp, 20) {
+            warn!("ACPI: bad RSDP at {:#x}", rsdp);
+            return;
+        }
+        let revision = read::<u8>(rsdp + 15);
+        // the XSDT of 64-bit pointers since ACPI 2.0
+        let (sdt, entry_size) = match revision {
+            0 => (read::<u32>(rsdp + 16) as PhysAddr, 4),
+            _ => (read::<u64>(rsdp + 24) as PhysAddr, 8),
+        };
+        let sdt_len = match self.add_table(sdt) {
+            Some(table) => table.size,
+            None => return,
+        };
+        let count = sdt_len.saturating_sub(SDT_HEADER_SIZE) / entry_size;
+        for i in 0..count {
+            let entry = sdt + SDT_HEADER_SIZE + i * entry_size;
+            let paddr = match entry_size {
+                4 => read::<u32>(entry) as PhysAddr,
+                _ => read::<u64>(entry) as PhysAddr,
+            };
+            match self.add_table(paddr) {
+                Some(table) if &table.signature == b"APIC" => self.parse_madt(table),
+                Some(table) if &table.signature == b"HPET" => {
+                    // the address in the generic address structure
+                    self.hpet_base = Some(read::<u64>(table.paddr + 44) as PhysAddr);
+                }
+                Some(table) if &table.signature == b"MCFG" => self.parse_mcfg(table),
+                Some(table) if &table.signature == b"FACP" => self.parse_fadt(table),
+                _ => {}
+            }
+        }
+    }
+
+    /// Add the table at `paddr` if it is valid.
+    fn add_table(&mut self, paddr: PhysAddr) -> Option<AcpiTable> {
+        let table = AcpiTable {
+            signature: read(paddr),
+            paddr,
+            size: read::<u32>(paddr + 4) as usize,
+        };
+        if table.size < SDT_HEADER_SIZE || !checksum_ok(paddr, table.size) {
+            warn!("ACPI: bad table at {:#x}", paddr);
+            return None;
+        }
+        self.tables.push(table);
+        Some(table)
+    }
+
+    fn parse_mcfg(&mut self, mcfg: AcpiTable) {
+        // the entries of 16 bytes after 8 reserved bytes
+        let mut offset = SDT_HEADER_SIZE + 8;
+        while offset + 16 <= mcfg.size {
+            let entry = mcfg.paddr + offset;
+            if read::<u16>(entry + 8) == 0 {
+                let buses = read::<u8>(entry + 10)..=read::<u8>(entry + 11);
+                self.pci_ecam = Some((read::<u64>(entry) as PhysAddr, buses));
+                return;
+            }
+            offset += 16;
+        }
+    }
+
+    fn parse_fadt(&mut self, fadt: AcpiTable) {
+        // the boot architecture flags and the reset register since ACPI 2.0
+        if read::<u8>(fadt.paddr + SDT_REVISION) < 2 {
+            return;
+        }
+        if fadt.size >= FADT_IAPC_BOOT_ARCH + 2 {
+            let flags = read::<u16>(fadt.paddr + FADT_IAPC_BOOT_ARCH);
+            self.has_8042 = flags & IAPC_BOOT_ARCH_8042 != 0;
+        }
+        if fadt.size <= FADT_RESET_VALUE
+            || read::<u32>(fadt.paddr + FADT_FLAGS) & FADT_RESET_REG_SUP == 0
+        {
+            return;
+        }
+        let reg = fadt.paddr + FADT_RESET_REG;
+        let value = read::<u8>(fadt.paddr + FADT_RESET_VALUE);
+        let address = read::<u64>(reg + 4);
+        self.reset_reg = match read::<u8>(reg) {
+            GAS_SYSTEM_MEMORY => Some(ResetRegister::Memory {
+                paddr: address as PhysAddr,
+                value,
+            }),
+            GAS_SYSTEM_IO => Some(ResetRegister::Io {
+                port: address as u16,
+                value,
+            }),
+            space => {
+                warn!("ACPI: reset register in address space {}", space);
+                None
+            }
+        };
+    }
+
+    fn parse_madt(&mut self, madt: AcpiTable) {
+        self.lapic_base = read::<u32>(madt.paddr + SDT_HEADER_SIZE) as PhysAddr;
+        let mut has_ioapic = false;
+        let mut offset = SDT_HEADER_SIZE + 8;
+        while offset + 2 <= madt.size {
+            let entry = madt.paddr + offset;
+            let (type_, len) = (read::<u8>(entry), read::<u8>(entry + 1) as usize);
+            if len < 2 {
+                break;
+            }
+            match type_ {
+                MADT_LOCAL_APIC if read::<u32>(entry + 4) & LOCAL_APIC_USABLE != 0 => {
+                    self.cpus.push(read::<u8>(entry + 3) as u32);
+                }
+                MADT_LOCAL_X2APIC if read::<u32>(entry + 8) & LOCAL_APIC_USABLE != 0 => {
+                    self.cpus.push(read::<u32>(entry + 4));
+                }
+                MADT_IO_APIC if !has_ioapic => {
+                    self.ioapic_base = read::<u32>(entry + 4) as PhysAddr;
+                    has_ioapic = true;
+                }
+                MADT_INTERRUPT_OVERRIDE => {
+                    let flags = read::<u16>(entry + 8);
+                    // the polarity and the trigger mode, 0 for the default
+                    let active_low = flags & 0b11 == 0b11;
+                    let level = (flags >> 2) & 0b11 == 0b11;
+                    let mode = match (level, active_low) {
+                        (false, false) => None,
+                        (false, true) => Some(IrqMode::EdgeLow),
+                        (true, false) => Some(IrqMode::LevelHigh),
+                        (true, true) => Some(IrqMode::LevelLow),
+                    };
+                    self.overrides.push(IrqOverride {
+                        irq: read::<u8>(entry + 3) as u32,
+                        gsi: read::<u32>(entry + 4),
+                        mode,
+                    });
+                }
+                MADT_LOCAL_APIC_ADDRESS => {
+                    self.lapic_base = read::<u64>(entry + 4) as PhysAddr;
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! The local APIC and the IOAPIC, at the addresses in the ACPI tables.
+//!
+//! The legacy PICs are disabled. The local APIC timer interrupts
+//! periodically, and the ISA IRQs are routed by the IOAPIC to the vectors
+//! from `IRQ_BASE`.
+
+use {
+    super::acpi,
+    crate::phys_to_virt,
+    core::{
+        arch::x86_64::_rdtsc,
+        ptr::{read_volatile, write_volatile},
+        sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    },
+    kernel_hal::{IrqMode, PhysAddr},
+    x86_64::instructions::port::Port,
+};
+
+// registers of the local APIC
+const LAPIC_ID: usize = 0x20;
+const LAPIC_EOI: usize = 0xb0;
+const LAPIC_SVR: usize = 0xf0;
+const LAPIC_ICR_LOW: usize = 0x300;
+const LAPIC_ICR_HIGH: usize = 0x310;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LAPIC_TIMER_INIT: usize = 0x380;
+const LAPIC_TIMER_CURRENT: usize = 0x390;
+const LAPIC_TIMER_DIVIDE: usize = 0x3e0;
+
+/// The bit to mask an LVT or a redirection entry.
+const MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+/// Divide the bus clock by 1.
+const TIMER_DIVIDE_BY_1: u32 = 0b1011;
+/// The IPIs to start a CPU, with the level asserted.
+const ICR_INIT: u32 = 0x4500;
+const ICR_STARTUP: u32 = 0x4600;
+/// The IPI is not accepted yet.
+const ICR_PENDING: u32 = 1 << 12;
+
+/// The vector of the timer interrupt.
+pub const TIMER_VECTOR: u32 = 0x20;
+/// The vector of the IPI of the TLB shootdown, after the MSI vectors.
+pub const TLB_SHOOTDOWN_VECTOR: u32 = 0xf0;
+/// The vector of the spurious interrupt, which needs no EOI.
+pub const SPURIOUS_VECTOR: u32 = 0xff;
+/// The vector of the IOAPIC input 0.
+pub const IRQ_BASE: u32 = 0x30;
+
+/// The frequency of the timer interrupt.
+const TIMER_INTERRUPTS_PER_SECOND: u64 = 100;
+
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0);
+/// The initial count of the timer, measured by the boot CPU.
+static TIMER_COUNT: AtomicU32 = AtomicU32::new(0);
+static IOAPIC_INPUTS: AtomicU32 = AtomicU32::new(0);
+
+pub fn init() {
+    disable_pic();
+    let tsc_frequency = calibrate_tsc();
+    TSC_FREQUENCY.store(tsc_frequency, Ordering::Relaxed);
+    info!("TSC frequency: {} Hz", tsc_frequency);
+
+    // enable the local APIC
+    lapic_write(LAPIC_SVR, 0x100 | SPURIOUS_VECTOR);
+    // count the APIC timer in a period measured by the TSC
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_1);
+    lapic_write(LAPIC_LVT_TIMER, MASKED);
+    lapic_write(LAPIC_TIMER_INIT, u32::MAX);
+    let start = unsafe { _rdtsc() };
+    while unsafe { _rdtsc() } - start < tsc_frequency / TIMER_INTERRUPTS_PER_SECOND {
+        core::hint::spin_loop();
+    }
+    let count = u32::MAX - lapic_read(LAPIC_TIMER_CURRENT);
+    TIMER_COUNT.store(count, Ordering::Relaxed);
+    lapic_write(LAPIC_LVT_TIMER, TIMER_VECTOR | TIMER_PERIODIC);
+    lapic_write(LAPIC_TIMER_INIT, count);
+
+    // all inputs of the IOAPIC are masked until enabled
+    let inputs = ((ioapic_read(1) >> 16) & 0xff) + 1;
+    IOAPIC_INPUTS.store(inputs, Ordering::Relaxed);
+    for input in 0..inputs {
+        ioapic_write_entry(input, MASKED | (IRQ_BASE + input));
+    }
+}
+
+/// Enable the local APIC of a secondary CPU, with the timer counted as the
+/// boot CPU's.
+pub fn init_secondary() {
+    lapic_write(LAPIC_SVR, 0x100 | SPURIOUS_VECTOR);
+    lapic_write(LAPIC_TIMER_DIVIDE, TIMER_DIVIDE_BY_1);
+    lapic_write(LAPIC_LVT_TIMER, TIMER_VECTOR | TIMER_PERIODIC);
+    lapic_write(LAPIC_TIMER_INIT, TIMER_COUNT.load(Ordering::Relaxed));
+}
+
+/// Get the ID of the local APIC of the current CPU.
+pub fn lapic_id() -> u32 {
+    lapic_read(LAPIC_ID) >> 24
+}
+
+/// Start the CPU of `apic_id` by the INIT-SIPI-SIPI sequence, running the
+/// real-mode code at the physical `page`.
+pub fn start_cpu(apic_id: u32, page: u8) {
+    send_ipi(apic_id, ICR_INIT);
+    delay_micros(10_000);
+    for _ in 0..2 {
+        send_ipi(apic_id, ICR_STARTUP | page as u32);
+        delay_micros(200);
+    }
+}
+
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::Relaxed)
+}
+
+/// Get the number of inputs of the IOAPIC.
+pub fn ioapic_inputs() -> u32 {
+    IOAPIC_INPUTS.load(Ordering::Relaxed)
+}
+
+/// Get the address and the data of the message signaled interrupt of
+/// `vector`, delivered to the current CPU.
+pub fn msi_message(vector: u32) -> (u64, u32) {
+    (0xfee0_0000 | (lapic_id() as u64) << 12, vector)
+}
+
+/// Signal the end of the interrupt being handled.
+pub fn eoi() {
+    lapic_write(LAPIC_EOI, 0);
+}
+
+/// Route the IOAPIC `input` to its vector in `mode`, masked.
+pub fn ioapic_configure(input: u32, mode: IrqMode) {
+    let mut entry = MASKED | (IRQ_BASE + input);
+    if mode.is_level() {
+        entry |= 1 << 15;
+    }
+    if matches!(mode, IrqMode::EdgeLow | IrqMode::LevelLow) {
+        entry |= 1 << 13;
+    }
+    ioapic_write_entry(input, entry);
+}
+
+/// Mask or unmask the IOAPIC `input`.
+pub fn ioapic_set_masked(input: u32, masked: bool) {
+    let mut entry = ioapic_read(0x10 + input * 2);
+    if masked {
+        entry |= MASKED;
+    } else {
+        entry &= !MASKED;
+    }
+    ioapic_write(0x10 + input * 2, entry);
+}
+
+/// Write the low half of a redirection entry, delivered to the boot CPU.
+fn ioapic_write_entry(input: u32, entry: u32) {
+    ioapic_write(0x10 + input * 2 + 1, 0);
+    ioapic_write(0x10 + input * 2, entry);
+}
+
+fn lapic_read(reg: usize) -> u32 {
+    let base = phys_to_virt(acpi::info().lapic_base);
+    unsafe { read_volatile((base + reg) as *const u32) }
+}
+
+fn lapic_write(reg: usize, value: u32) {
+    let base = phys_to_virt(acpi::info().lapic_base);
+    unsafe { write_volatile((base + reg) as *mut u32, value) }
+}
+
+/// Send the IPI of `vector` to the CPU of `apic_id`.
+pub fn send_fixed_ipi(apic_id: u32, vector: u32) {
+    send_ipi(apic_id, vector);
+}
+
+/// Send the IPI `command` to the CPU of `apic_id`, and wait until it is
+/// accepted.
+fn send_ipi(apic_id: u32, command: u32) {
+    lapic_write(LAPIC_ICR_HIGH, apic_id << 24);
+    lapic_write(LAPIC_ICR_LOW, command);
+    while lapic_read(LAPIC_ICR_LOW) & ICR_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Spin for `micros` microseconds, measured by the TSC.
+fn delay_micros(micros: u64) {
+    let ticks = tsc_frequency() * micros / 1_000_000;
+    let start = unsafe { _rdtsc() };
+    while unsafe { _rdtsc() } - start < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+fn ioapic_read(reg: u32) -> u32 {
+    let base = phys_to_virt(acpi::info().ioapic_base);
+    unsafe {
+        write_volatile(base as *mut u32, reg);
+        read_volatile((base + 0x10) as *const u32)
+    }
+}
+
+fn ioapic_write(reg: u32, value: u32) {
+    let base = phys_to_virt(acpi::info().ioapic_base);
+    unsafe {
+        write_volatile(base as *mut u32, reg);
+        write_volatile((base + 0x10) as *mut u32, value);
+    }
+}
+
+/// Mask all IRQs of the legacy PICs.
+fn disable_pic() {
+    unsafe {
+        Port::<u8>::new(0x21).write(0xff);
+        Port::<u8>::new(0xa1).write(0xff);
+    }
+}
+
+/// The time to measure the frequency of the TSC.
+const CALIBRATE_MILLIS: u64 = 10;
+
+/// Measure the frequency of the TSC with the HPET if there is one, or the
+/// channel 2 of the PIT.
+fn calibrate_tsc() -> u64 {
+    match acpi::info().hpet_base {
+        Some(base) => calibrate_tsc_hpet(base),
+        None => calibrate_tsc_pit(),
+    }
+}
+
+/// Measure the frequency of the TSC with the main counter of the HPET.
+fn calibrate_tsc_hpet(base: PhysAddr) -> u64 {
+    const HPET_CAPABILITIES: usize = 0x00;
+    const HPET_CONFIG: usize = 0x10;
+    const HPET_COUNTER: usize = 0xf0;
+    let base = phys_to_virt(base);
+    let read = |reg: usize| unsafe { read_volatile((base + reg) as *const u64) };
+    // the period of the counter in femtoseconds
+    let period = read(HPET_CAPABILITIES) >> 32;
+    unsafe {
+        let config = read(HPET_CONFIG);
+        write_volatile((base + HPET_CONFIG) as *mut u64, config | 1);
+    }
+    let count = CALIBRATE_MILLIS * 1_000_000_000_000 / period;
+    let (start_counter, start) = (read(HPET_COUNTER), unsafe { _rdtsc() });
+    while read(HPET_COUNTER).wrapping_sub(start_counter) < count {
+        core::hint::spin_loop();
+    }
+    (unsafe { _rdtsc() } - start) * 1000 / CALIBRATE_MILLIS
+}
+
+/// Measure the frequency of the TSC with the channel 2 of the PIT.
+fn calibrate_tsc_pit() -> u64 {
+    const PIT_FREQUENCY: u64 = 1_193_182;
+    let count = PIT_FREQUENCY * CALIBRATE_MILLIS / 1000;
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut data = Port::<u8>::new(0x42);
+    unsafe {
+        // disable the speaker, and stop the counting by the gate
+        let value = gate.read() & !0x03;
+        gate.write(value);
+        // channel 2, interrupt on terminal count
+        command.write(0b1011_0000);
+        data.write(count as u8);
+        data.write((count >> 8) as u8);
+        // start the counting
+        gate.write(value | 0x01);
+        let start = _rdtsc();
+        // the output of channel 2 is set at the terminal count
+        while gate.read() & 0x20 == 0 {
+            core::hint::spin_loop();
+        }
+        (_rdtsc() - start) * 1000 / CALIBRATE_MILLIS
+    }
+}
@@ -0,0 +1,101 @@
+//! The I/O permission bitmap in the TSS of each CPU.
+//!
+//! The TSS set up by `trapframe` has no room for the bitmap, so it is copied
+//! to a larger one, which replaces it in the GDT. The bitmap of the process
+//! to run is loaded into it before switching to the user context.
+
+use {
+    crate::smp::MAX_CPUS,
+    alloc::alloc::{alloc_zeroed, Layout},
+    core::{
+        convert::TryInto,
+        mem::size_of,
+        sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+    },
+    x86_64::instructions::interrupts,
+};
+
+/// The number of bytes of the bitmap, one bit for each of the 65536 ports.
+const IOPB_SIZE: usize = 0x10000 / 8;
+
+/// The size of the TSS without the bitmap.
+const TSS_SIZE: usize = 104;
+
+/// The 64-bit TSS, followed by the I/O permission bitmap.
+#[repr(C, packed)]
+struct Tss {
+    /// The TSS copied from the one of `trapframe`, with the stacks of the
+    /// traps from the user mode.
+    tss: [u8; TSS_SIZE],
+    /// A set bit denies the access to the port.
+    iopb: [u8; IOPB_SIZE],
+    /// The byte after the bitmap must be all ones.
+    iopb_end: u8,
+}
+
+const TSS_NONE: AtomicPtr<Tss> = AtomicPtr::new(core::ptr::null_mut());
+const GRANTED_NONE: AtomicBool = AtomicBool::new(false);
+
+/// The TSS of the CPUs, by their numbers.
+static TSS: [AtomicPtr<Tss>; MAX_CPUS] = [TSS_NONE; MAX_CPUS];
+
+/// Whether any port is granted in the bitmap of the CPUs.
+static GRANTED: [AtomicBool; MAX_CPUS] = [GRANTED_NONE; MAX_CPUS];
+
+/// Replace the TSS of the CPU `cpu` loaded by `trapframe` with one denying
+/// all ports by its bitmap.
+pub fn init(cpu: u32) {
+    let tss = unsafe { &mut *(alloc_zeroed(Layout::new::<Tss>()) as *mut Tss) };
+    tss.iopb = [0xff; IOPB_SIZE];
+    tss.iopb_end = 0xff;
+    unsafe {
+        let mut gdtr = [0u8; 10];
+        let selector: u16;
+        asm!("sgdt [{}]", in(reg) gdtr.as_mut_ptr());
+        asm!("str {:x}", out(reg) selector);
+        let gdt = u64::from_le_bytes(gdtr[2..].try_into().unwrap()) as *mut u64;
+        let entry = gdt.add(selector as usize >> 3);
+        let (low, high) = (entry.read(), entry.add(1).read());
+        let old = (low >> 16 & 0xff_ffff) | (low >> 56 << 24) | (high << 32);
+        tss.tss = (old as *const [u8; TSS_SIZE]).read();
+        // the I/O map base address is the last field
+        tss.tss[TSS_SIZE - 2..].copy_from_slice(&(TSS_SIZE as u16).to_le_bytes());
+        // an available 64-bit TSS, to be marked busy by `ltr`
+        let base = tss as *const Tss as u64;
+        let limit = (size_of::<Tss>() - 1) as u64;
+        let low = (limit & 0xffff)
+            | (base & 0xff_ffff) << 16
+            | 0x89 << 40
+            | (limit >> 16 & 0xf) << 48
+            | (base >> 24 & 0xff) << 56;
+        entry.write(low);
+        entry.add(1).write(base >> 32);
+        asm!("ltr {:x}", in(reg) selector);
+    }
+    TSS[cpu as usize].store(tss, Ordering::Release);
+}
+
+/// Load the I/O permission `bitmap` of a process into the TSS of the
+/// current CPU, where a set bit grants the access to the port.
+pub fn load_bitmap(bitmap: &[u64]) {
+    interrupts::without_interrupts(|| {
+        let cpu = super::cpu_id() as usize;
+        let tss = TSS[cpu].load(Ordering::Acquire);
+        if tss.is_null() {
+            return;
+        }
+        let tss = unsafe { &mut *tss };
+        // most processes have no port, and the bitmap is left as it is
+        if bitmap.is_empty() {
+            if GRANTED[cpu].swap(false, Ordering::Relaxed) {
+                tss.iopb = [0xff; IOPB_SIZE];
+            }
+            return;
+        }
+        for (i, bytes) in tss.iopb.chunks_exact_mut(8).enumerate() {
+            let granted = bitmap.get(i).copied().unwrap_or(0);
+            bytes.copy_from_slice(&(!granted).to_le_bytes());
+        }
+        GRANTED[cpu].store(true, Ordering::Relaxed);
+    })
+}
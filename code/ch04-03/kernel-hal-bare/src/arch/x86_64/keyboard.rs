@@ -0,0 +1,257 @@
+//! The PS/2 keyboard on the i8042 controller.
+//!
+//! The controller translates the scancodes to the set 1, which are
+//! translated to the keycodes, then to the input of a terminal.
+
+use {super::apic::IRQ_BASE, alloc::boxed::Box, spin::Mutex, x86_64::instructions::port::Port};
+
+const DATA_PORT: u16 = 0x60;
+const COMMAND_PORT: u16 = 0x64;
+/// The ISA IRQ of the first PS/2 port.
+const KEYBOARD_IRQ: u32 = 1;
+
+/// Status: the output buffer is full.
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+/// Status: the input buffer is full.
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+/// The times to poll the status before giving up, about 100ms.
+const TIMEOUT_POLLS: usize = 100_000;
+/// The size of the output buffer, discarded before the setup.
+const OUTPUT_BUFFER_SIZE: usize = 16;
+
+const CMD_READ_CONFIG: u8 = 0x20;
+const CMD_WRITE_CONFIG: u8 = 0x60;
+const CMD_DISABLE_PORT2: u8 = 0xa7;
+const CMD_DISABLE_PORT1: u8 = 0xad;
+const CMD_ENABLE_PORT1: u8 = 0xae;
+/// Pulse the reset line of the CPU.
+const CMD_PULSE_RESET: u8 = 0xfe;
+
+/// Config: the interrupt of the first port.
+const CONFIG_PORT1_IRQ: u8 = 1 << 0;
+/// Config: the interrupt of the second port.
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+/// Config: translate the scancodes to the set 1.
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+/// The prefix of the extended scancodes.
+const SCANCODE_EXTENDED: u8 = 0xe0;
+/// The bit of the scancodes of the released keys.
+const SCANCODE_RELEASED: u8 = 0x80;
+
+/// A key on the keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keycode {
+    /// A key of a character, without and with shift.
+    Char(u8, u8),
+    Shift,
+    Ctrl,
+    CapsLock,
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Delete,
+}
+
+/// The state of the keyboard between the interrupts.
+struct KeyboardState {
+    extended: bool,
+    shift: bool,
+    ctrl: bool,
+    caps_lock: bool,
+}
+
+static STATE: Mutex<KeyboardState> = Mutex::new(KeyboardState {
+    extended: false,
+    shift: false,
+    ctrl: false,
+    caps_lock: false,
+});
+
+fn data() -> Port<u8> {
+    Port::new(DATA_PORT)
+}
+
+fn status() -> u8 {
+    unsafe { Port::<u8>::new(COMMAND_PORT).read() }
+}
+
+/// Wait until the `flag` of the status is `set`, `None` if timeout.
+fn wait_status(flag: u8, set: bool) -> Option<()> {
+    for _ in 0..TIMEOUT_POLLS {
+        if (status() & flag != 0) == set {
+            return Some(());
+        }
+        core::hint::spin_loop();
+    }
+    None
+}
+
+fn command(cmd: u8) -> Option<()> {
+    wait_status(STATUS_INPUT_FULL, false)?;
+    unsafe { Port::<u8>::new(COMMAND_PORT).write(cmd) };
+    Some(())
+}
+
+fn read_data() -> Option<u8> {
+    wait_status(STATUS_OUTPUT_FULL, true)?;
+    Some(unsafe { data().read() })
+}
+
+fn write_data(value: u8) -> Option<()> {
+    wait_status(STATUS_INPUT_FULL, false)?;
+    unsafe { data().write(value) };
+    Some(())
+}
+
+/// Enable the first port with the translation, and receive input by the IRQ.
+///
+/// Skipped if the FADT tells no controller, or the controller does not
+/// respond.
+pub fn init() {
+    if !super::acpi::info().has_8042 {
+        info!("keyboard: no i8042 controller");
+        return;
+    }
+    if setup().is_none() {
+        warn!("keyboard: i8042 controller not responding");
+        return;
+    }
+    let (input, mode) = super::acpi::isa_irq(KEYBOARD_IRQ);
+    let vector = IRQ_BASE + input;
+    super::irq_register(vector, mode, Box::new(handle_irq))
+        .expect("failed to register the IRQ of keyboard");
+    super::irq_enable(vector);
+}
+
+fn setup() -> Option<()> {
+    command(CMD_DISABLE_PORT1)?;
+    command(CMD_DISABLE_PORT2)?;
+    // discard the pending input
+    for _ in 0..OUTPUT_BUFFER_SIZE {
+        if status() & STATUS_OUTPUT_FULL == 0 {
+            break;
+        }
+        unsafe { data().read() };
+    }
+    command(CMD_READ_CONFIG)?;
+    let mut config = read_data()?;
+    config |= CONFIG_PORT1_IRQ | CONFIG_TRANSLATION;
+    config &= !CONFIG_PORT2_IRQ;
+    command(CMD_WRITE_CONFIG)?;
+    write_data(config)?;
+    command(CMD_ENABLE_PORT1)
+}
+
+/// Reset the system by the reset line of the controller, if present.
+pub fn pulse_reset() {
+    if super::acpi::info().has_8042 {
+        let _ = command(CMD_PULSE_RESET);
+    }
+}
+
+/// Put the input of the pressed keys to the input queue of the HAL.
+fn handle_irq() {
+    let mut state = STATE.lock();
+    while status() & STATUS_OUTPUT_FULL != 0 {
+        let scancode = unsafe { data().read() };
+        let mut buf = [0u8; 4];
+        let len = state.input(scancode, &mut buf);
+        crate::serial_irq_put(&buf[..len]);
+    }
+}
+
+impl KeyboardState {
+    /// Handle a scancode, write the input to `buf` and return its length.
+    fn input(&mut self, scancode: u8, buf: &mut [u8; 4]) -> usize {
+        if scancode == SCANCODE_EXTENDED {
+            self.extended = true;
+            return 0;
+        }
+        let extended = core::mem::take(&mut self.extended);
+        let pressed = scancode & SCANCODE_RELEASED == 0;
+        let keycode = match keycode(scancode & !SCANCODE_RELEASED, extended) {
+            Some(keycode) => keycode,
+            None => return 0,
+        };
+        match keycode {
+            Keycode::Shift => self.shift = pressed,
+            Keycode::Ctrl => self.ctrl = pressed,
+            Keycode::CapsLock if pressed => self.caps_lock = !self.caps_lock,
+            _ => {}
+        }
+        if !pressed {
+            return 0;
+        }
+        let input: &[u8] = match keycode {
+            Keycode::Char(normal, shifted) => {
+                let mut c = if self.shift { shifted } else { normal };
+                if self.caps_lock && c.is_ascii_alphabetic() {
+                    c ^= 0x20;
+                }
+                if self.ctrl && c.is_ascii_alphabetic() {
+                    // Ctrl-A to Ctrl-Z
+                    c = c.to_ascii_uppercase() - b'@';
+                }
+                buf[0] = c;
+                return 1;
+            }
+            Keycode::Up => b"\x1b[A",
+            Keycode::Down => b"\x1b[B",
+            Keycode::Right => b"\x1b[C",
+            Keycode::Left => b"\x1b[D",
+            Keycode::Home => b"\x1b[H",
+            Keycode::End => b"\x1b[F",
+            Keycode::Delete => b"\x1b[3~",
+            _ => b"",
+        };
+        buf[..input.len()].copy_from_slice(input);
+        input.len()
+    }
+}
+
+/// Translate the scancode of the set 1 to the keycode, `None` if unknown.
+fn keycode(scancode: u8, extended: bool) -> Option<Keycode> {
+    use Keycode::*;
+    if extended {
+        return match scancode {
+            0x1c => Some(Char(b'\r', b'\r')),
+            0x1d => Some(Ctrl),
+            0x35 => Some(Char(b'/', b'/')),
+            0x47 => Some(Home),
+            0x48 => Some(Up),
+            0x4b => Some(Left),
+            0x4d => Some(Right),
+            0x4f => Some(End),
+            0x50 => Some(Down),
+            0x53 => Some(Delete),
+            _ => None,
+        };
+    }
+    const ROW1: &[u8; 13] = b"1234567890-=\x7f";
+    const ROW1_SHIFTED: &[u8; 13] = b"!@#$%^&*()_+\x7f";
+    const ROW2: &[u8; 14] = b"\tqwertyuiop[]\r";
+    const ROW2_SHIFTED: &[u8; 14] = b"\tQWERTYUIOP{}\r";
+    const ROW3: &[u8; 12] = b"asdfghjkl;'`";
+    const ROW3_SHIFTED: &[u8; 12] = b"ASDFGHJKL:\"~";
+    const ROW4: &[u8; 11] = b"\\zxcvbnm,./";
+    const ROW4_SHIFTED: &[u8; 11] = b"|ZXCVBNM<>?";
+    let code = scancode as usize;
+    let keycode = match scancode {
+        0x01 => Char(0x1b, 0x1b),
+        0x02..=0x0e => Char(ROW1[code - 0x02], ROW1_SHIFTED[code - 0x02]),
+        0x0f..=0x1c => Char(ROW2[code - 0x0f], ROW2_SHIFTED[code - 0x0f]),
+        0x1d => Ctrl,
+        0x1e..=0x29 => Char(ROW3[code - 0x1e], ROW3_SHIFTED[code - 0x1e]),
+        0x2a | 0x36 => Shift,
+        0x2b..=0x35 => Char(ROW4[code - 0x2b], ROW4_SHIFTED[code - 0x2b]),
+        0x37 => Char(b'*', b'*'),
+        0x39 => Char(b' ', b' '),
+        0x3a => CapsLock,
+        _ => return None,
+    };
+    Some(keycode)
+}
@@ -0,0 +1,146 @@
+//! The x86_64 platform: the ACPI tables, the 16550 serial console, the PS/2
+//! keyboard, the CMOS RTC, the local APIC timer, the IOAPIC, the PCI config
+//! space, the secondary CPUs, 4-level paging, reboot and shutdown.
+
+use {
+    super::*,
+    core::arch::x86_64::{CpuidResult, __cpuid},
+    x86_64::{
+        instructions::interrupts,
+        registers::model_specific::{Efer, EferFlags},
+    },
+};
+
+mod acpi;
+mod apic;
+mod ioport;
+mod keyboard;
+mod paging;
+mod pci;
+mod power;
+mod rtc;
+pub mod serial;
+mod smp;
+mod trap;
+
+pub(crate) use self::apic::msi_message;
+pub(crate) use self::ioport::load_bitmap as ioport_load_bitmap;
+pub use self::paging::{tlb_flush, PageTable};
+pub(crate) use self::pci::{
+    pci_config_read, pci_config_write, pci_ecam, pci_legacy_irq, pci_mmio_window,
+};
+pub(crate) use self::power::{reboot, shutdown};
+pub(crate) use self::rtc::rtc_now;
+pub(crate) use self::smp::{cpu_id, secondary_cpus, start_cpu};
+pub use self::trap::{
+    irq_disable, irq_enable, irq_handle, irq_range, irq_register, irq_unregister,
+};
+pub(crate) use self::trap::{msi_alloc, msi_free};
+
+/// Initialize the CPU and the devices of the platform.
+pub fn init() {
+    unsafe {
+        // set up the GDT with the user segments and the TSS, the IDT routing
+        // all vectors to `trap_handler`, and the MSRs of `syscall`
+        trapframe::init();
+        Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+    }
+    ioport::init(0);
+    paging::init();
+    acpi::init(crate::config().acpi_rsdp);
+    apic::init();
+    smp::init();
+    serial::init();
+    keyboard::init();
+    interrupts::enable();
+}
+
+/// The slots of the virtio MMIO devices, none on the PC, where the virtio
+/// devices are on PCI.
+pub(crate) fn virtio_mmio_slots() -> impl Iterator<Item = (PhysAddr, u32)> {
+    core::iter::empty()
+}
+
+/// Get the ACPI tables found at boot.
+pub fn acpi_tables() -> Vec<AcpiTable> {
+    acpi::info().tables.clone()
+}
+
+/// Run `f` with interrupts disabled.
+pub(crate) fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    interrupts::without_interrupts(f)
+}
+
+/// Halt the CPU until the next interrupt.
+pub(crate) fn wait_for_interrupt() {
+    interrupts::enable_and_hlt();
+}
+
+/// Wait until `cond` holds, checked after each interrupt if the interrupts
+/// are enabled, or by polling otherwise.
+pub(crate) fn wait_until(mut cond: impl FnMut() -> bool) {
+    if !interrupts::are_enabled() {
+        while !cond() {
+            core::hint::spin_loop();
+        }
+        return;
+    }
+    loop {
+        interrupts::disable();
+        if cond() {
+            interrupts::enable();
+            return;
+        }
+        // not to miss the interrupt after the check, `sti` takes effect
+        // after `hlt`
+        interrupts::enable_and_hlt();
+    }
+}
+
+/// Read the TSC, which is also read by the vDSO without entering the kernel.
+pub fn timer_ticks() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Get the frequency of the TSC, calibrated with the PIT at boot.
+pub fn timer_ticks_per_second() -> u64 {
+    apic::tsc_frequency()
+}
+
+/// Get the size of the cache line flushed by `clflush`.
+pub fn cache_line_size() -> u32 {
+    let CpuidResult { ebx, .. } = unsafe { __cpuid(1) };
+    match (ebx >> 8) & 0xff {
+        0 => 64,
+        n => n * 8,
+    }
+}
+
+/// Read a random number by RDRAND, if the CPU supports it.
+pub(crate) fn hw_random() -> Option<u64> {
+    let CpuidResult { ecx, .. } = unsafe { __cpuid(1) };
+    if ecx & (1 << 30) == 0 {
+        return None;
+    }
+    let mut value = 0;
+    // RDRAND may fail transiently when the entropy is exhausted
+    (0..10)
+        .find(|_| unsafe { core::arch::x86_64::_rdrand64_step(&mut value) } == 1)
+        .map(|_| value)
+}
+
+/// No CPU features or debug registers are exposed to the user programs.
+pub fn cpu_features() -> vdso::Features {
+    vdso::Features::default()
+}
+
+/// The caches are coherent with the memory.
+pub fn frame_flush(_target: PhysAddr) {}
+
+/// Run the user context until a trap, an interrupt or a syscall.
+pub fn context_run(context: &mut UserContext) {
+    context.run();
+}
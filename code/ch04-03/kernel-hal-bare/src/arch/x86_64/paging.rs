@@ -0,0 +1,190 @@
+//! 4-level page tables.
+//!
+//! The tables are accessed by the linear mapping of the physical memory.
+//! The kernel half of the address space is shared by all page tables.
+//! The cache policies of the pages are selected by the PAT, where the entry 1
+//! is the write-combining instead of the write-through.
+
+use {
+    super::smp,
+    crate::{phys_to_virt, PhysFrame},
+    core::ops::Range,
+    kernel_hal::{
+        CachePolicy, HalError, MMUFlags, PageTableTrait, PhysAddr, Result, VirtAddr, PAGE_SIZE,
+    },
+    x86_64::{
+        instructions::tlb,
+        registers::{control::Cr3, model_specific::Msr},
+        structures::paging::{PageTable as X86PageTable, PageTableEntry, PageTableFlags as PTF},
+    },
+};
+
+/// The `IA32_PAT` MSR.
+const IA32_PAT: u32 = 0x277;
+
+/// The PAT entries by the PAT, PCD and PWT bits of a page: WB, WC, UC- and UC,
+/// twice.
+const PAT_ENTRIES: u64 = 0x0007_0106_0007_0106;
+
+/// Program the PAT of the current CPU.
+pub fn init() {
+    unsafe { Msr::new(IA32_PAT).write(PAT_ENTRIES) };
+}
+
+/// Page Table
+#[repr(C)]
+pub struct PageTable {
+    table_phys: PhysAddr,
+}
+
+impl PageTable {
+    /// Get current page table
+    pub fn current() -> Self {
+        let (frame, _) = Cr3::read();
+        PageTable {
+            table_phys: frame.start_address().as_u64() as usize,
+        }
+    }
+
+    /// Create a new `PageTable`, with the kernel half of the current one.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        let root = alloc_table().expect("failed to allocate page table");
+        let table = table_of(root);
+        let current = table_of(Self::current().table_phys);
+        for i in 256..512 {
+            table[i] = current[i].clone();
+        }
+        PageTable { table_phys: root }
+    }
+
+    /// Get the entry of the page of `vaddr`, creating the tables on the way
+    /// if `create`.
+    fn walk(&mut self, vaddr: VirtAddr, create: bool) -> Result<&'static mut PageTableEntry> {
+        let mut table = table_of(self.table_phys);
+        for level in (1..4).rev() {
+            let entry = &mut table[(vaddr >> (12 + 9 * level)) & 0x1ff];
+            if entry.is_unused() {
+                if !create {
+                    return Err(HalError);
+                }
+                let paddr = alloc_table().ok_or(HalError)?;
+                let flags = PTF::PRESENT | PTF::WRITABLE | PTF::USER_ACCESSIBLE;
+                entry.set_addr(x86_64::PhysAddr::new(paddr as u64), flags);
+            } else if entry.flags().contains(PTF::HUGE_PAGE) {
+                return Err(HalError);
+            }
+            table = table_of(entry.addr().as_u64() as usize);
+        }
+        Ok(&mut table[(vaddr >> 12) & 0x1ff])
+    }
+
+    /// Flush the TLB of the page of `vaddr`, if the page table is active.
+    fn flush(&self, vaddr: VirtAddr) {
+        if Self::current().table_phys == self.table_phys {
+            tlb::flush(x86_64::VirtAddr::new(vaddr as u64));
+        }
+    }
+}
+
+impl PageTableTrait for PageTable {
+    /// Map the page of `vaddr` to the frame of `paddr` with `flags`.
+    fn map(&mut self, vaddr: VirtAddr, paddr: PhysAddr, flags: MMUFlags) -> Result<()> {
+        debug_assert!(vaddr % PAGE_SIZE == 0 && paddr % PAGE_SIZE == 0);
+        let entry = self.walk(vaddr, true)?;
+        entry.set_addr(x86_64::PhysAddr::new(paddr as u64), to_pt_flags(flags));
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Unmap the page of `vaddr`.
+    fn unmap(&mut self, vaddr: VirtAddr) -> Result<()> {
+        self.walk(vaddr, false)?.set_unused();
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Change the `flags` of the page of `vaddr`.
+    fn protect(&mut self, vaddr: VirtAddr, flags: MMUFlags) -> Result<()> {
+        let entry = self.walk(vaddr, false)?;
+        if entry.is_unused() {
+            return Err(HalError);
+        }
+        entry.set_flags(to_pt_flags(flags));
+        self.flush(vaddr);
+        Ok(())
+    }
+
+    /// Query the physical address which the page of `vaddr` maps to.
+    fn query(&mut self, vaddr: VirtAddr) -> Result<PhysAddr> {
+        let entry = self.walk(vaddr, false)?;
+        if entry.is_unused() {
+            return Err(HalError);
+        }
+        Ok(entry.addr().as_u64() as usize + vaddr % PAGE_SIZE)
+    }
+
+    /// Get the physical address of root page table.
+    fn table_phys(&self) -> PhysAddr {
+        self.table_phys
+    }
+}
+
+/// The number of pages above which the whole TLB is flushed instead.
+const FLUSH_ALL_PAGES: usize = 32;
+
+/// Invalidate the TLB entries of `vaddr` of the page table `vmtoken` on the
+/// current CPU, if the page table is active.
+pub fn flush_local(vaddr: Range<VirtAddr>, vmtoken: usize) {
+    if PageTable::current().table_phys != vmtoken {
+        return;
+    }
+    if (vaddr.end - vaddr.start) / PAGE_SIZE > FLUSH_ALL_PAGES {
+        tlb::flush_all();
+    } else {
+        for vaddr in vaddr.step_by(PAGE_SIZE) {
+            tlb::flush(x86_64::VirtAddr::new(vaddr as u64));
+        }
+    }
+}
+
+/// Invalidate the TLB entries of `vaddr` of the page table `vmtoken` on all
+/// CPUs, by the IPIs to the others.
+pub fn tlb_flush(vaddr: Range<VirtAddr>, vmtoken: usize) {
+    flush_local(vaddr.clone(), vmtoken);
+    smp::shootdown(vaddr, vmtoken);
+}
+
+/// Allocate a zeroed frame for a page table, which is never freed.
+fn alloc_table() -> Option<PhysAddr> {
+    let frame = PhysFrame::alloc()?;
+    let paddr = frame.addr();
+    core::mem::forget(frame);
+    crate::pmem_zero(paddr, PAGE_SIZE);
+    Some(paddr)
+}
+
+fn table_of(paddr: PhysAddr) -> &'static mut X86PageTable {
+    unsafe { &mut *(phys_to_virt(paddr) as *mut X86PageTable) }
+}
+
+fn to_pt_flags(flags: MMUFlags) -> PTF {
+    let mut pt_flags = PTF::PRESENT;
+    if flags.contains(MMUFlags::WRITE) {
+        pt_flags |= PTF::WRITABLE;
+    }
+    if flags.contains(MMUFlags::USER) {
+        pt_flags |= PTF::USER_ACCESSIBLE;
+    }
+    if !flags.is_executable() {
+        pt_flags |= PTF::NO_EXECUTE;
+    }
+    match flags.cache_policy() {
+        CachePolicy::Cached => {}
+        CachePolicy::WriteCombining => pt_flags |= PTF::WRITE_THROUGH,
+        CachePolicy::Uncached | CachePolicy::UncachedDevice => {
+            pt_flags |= PTF::NO_CACHE | PTF::WRITE_THROUGH;
+        }
+    }
+    pt_flags
+}
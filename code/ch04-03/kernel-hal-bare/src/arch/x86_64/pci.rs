@@ -0,0 +1,70 @@
+//! PCI on the PC: the ECAM found in the ACPI tables, or else the I/O ports
+//! of the configuration mechanism #1, and the legacy IRQs routed by the
+//! firmware.
+
+use {
+    super::{
+        acpi,
+        apic::{ioapic_inputs, IRQ_BASE},
+    },
+    core::ops::RangeInclusive,
+    kernel_hal::{PciAddr, PhysAddr},
+    spin::Mutex,
+    x86_64::instructions::port::Port,
+};
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+/// The address is enabled to access the config space.
+const ADDRESS_ENABLE: u32 = 1 << 31;
+
+/// The lock of the address and the data port, written in pairs.
+static PORTS: Mutex<()> = Mutex::new(());
+
+/// The ECAM and the buses it covers, if any.
+pub fn pci_ecam() -> Option<(PhysAddr, RangeInclusive<u8>)> {
+    acpi::info().pci_ecam.clone()
+}
+
+/// There is no window to assign the BARs, which are assigned by the
+/// firmware.
+pub fn pci_mmio_window() -> Option<core::ops::Range<PhysAddr>> {
+    None
+}
+
+fn address(addr: PciAddr, offset: usize) -> u32 {
+    ADDRESS_ENABLE
+        | (addr.bus as u32) << 16
+        | (addr.device as u32) << 11
+        | (addr.function as u32) << 8
+        | (offset as u32 & 0xfc)
+}
+
+/// Read the aligned 32 bits at `offset` of the config space of the function
+/// by the I/O ports, in the first 256 bytes.
+pub fn pci_config_read(addr: PciAddr, offset: usize) -> u32 {
+    let _lock = PORTS.lock();
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address(addr, offset));
+        Port::<u32>::new(CONFIG_DATA).read()
+    }
+}
+
+/// Write the aligned 32 bits at `offset` of the config space of the function
+/// by the I/O ports, in the first 256 bytes.
+pub fn pci_config_write(addr: PciAddr, offset: usize, value: u32) {
+    let _lock = PORTS.lock();
+    unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address(addr, offset));
+        Port::<u32>::new(CONFIG_DATA).write(value);
+    }
+}
+
+/// Get the IRQ vector of the interrupt line assigned by the firmware, which
+/// is the IOAPIC input in the APIC mode.
+pub fn pci_legacy_irq(_addr: PciAddr, _pin: u8, line: u8) -> Option<u32> {
+    match line as u32 {
+        input if input < ioapic_inputs() => Some(IRQ_BASE + input),
+        _ => None,
+    }
+}
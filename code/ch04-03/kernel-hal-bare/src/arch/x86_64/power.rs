@@ -0,0 +1,60 @@
+//! Reboot by the ACPI reset register or the keyboard controller, and shut
+//! down on QEMU, Bochs and VirtualBox.
+//!
+//! Shutting down by ACPI in general needs the `\_S5` object in the DSDT,
+//! which is AML and not parsed, so only the virtual machines are supported.
+
+use {
+    super::acpi::{self, ResetRegister},
+    crate::phys_to_virt,
+    x86_64::{
+        instructions::{interrupts, port::Port, tables::lidt},
+        structures::DescriptorTablePointer,
+        VirtAddr,
+    },
+};
+
+/// The PM1a control ports of QEMU (PIIX4 and ICH9), Bochs and VirtualBox,
+/// and the values to enter the sleep state S5.
+const VM_POWER_OFF: &[(u16, u16)] = &[(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+/// Reboot the system, by the ACPI reset register, then the keyboard
+/// controller, then a triple fault.
+pub fn reboot() -> ! {
+    interrupts::disable();
+    match acpi::info().reset_reg {
+        Some(ResetRegister::Memory { paddr, value }) => unsafe {
+            (phys_to_virt(paddr) as *mut u8).write_volatile(value);
+        },
+        Some(ResetRegister::Io { port, value }) => unsafe {
+            Port::<u8>::new(port).write(value);
+        },
+        None => {}
+    }
+    super::keyboard::pulse_reset();
+    // no IDT to handle the exception
+    unsafe {
+        lidt(&DescriptorTablePointer {
+            limit: 0,
+            base: VirtAddr::new(0),
+        });
+        asm!("int3");
+    }
+    halt()
+}
+
+/// Shut down the system on the virtual machines, or halt the CPU otherwise.
+pub fn shutdown() -> ! {
+    interrupts::disable();
+    for &(port, value) in VM_POWER_OFF {
+        unsafe { Port::<u16>::new(port).write(value) };
+    }
+    warn!("failed to shut down, halt the CPU");
+    halt()
+}
+
+fn halt() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
@@ -0,0 +1,119 @@
+//! The RTC in the CMOS.
+
+use {super::without_interrupts, core::time::Duration, x86_64::instructions::port::Port};
+
+const CMOS_ADDR: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+/// The century, at the offset given by the FADT on most PCs.
+const REG_CENTURY: u8 = 0x32;
+
+/// Status A: the registers are being updated.
+const STATUS_A_UPDATING: u8 = 1 << 7;
+/// Status B: the hours are in the 24-hour format.
+const STATUS_B_24_HOUR: u8 = 1 << 1;
+/// Status B: the values are binary instead of BCD.
+const STATUS_B_BINARY: u8 = 1 << 2;
+/// The bit of PM in the 12-hour format.
+const HOUR_PM: u8 = 1 << 7;
+
+fn read(reg: u8) -> u8 {
+    unsafe {
+        // keep the NMI disabled
+        Port::new(CMOS_ADDR).write(reg | 0x80);
+        Port::new(CMOS_DATA).read()
+    }
+}
+
+/// The times to poll the registers before giving up, far more than an
+/// update takes on a working RTC.
+const MAX_RETRIES: usize = 100_000;
+
+/// Read the registers of the date and time, when they are not being updated.
+///
+/// Return `None` if they are always being updated, or there is no RTC.
+fn read_all() -> Option<[u8; 7]> {
+    (0..MAX_RETRIES).find(|_| read(REG_STATUS_A) & STATUS_A_UPDATING == 0)?;
+    let regs = [
+        REG_SECONDS,
+        REG_MINUTES,
+        REG_HOURS,
+        REG_DAY,
+        REG_MONTH,
+        REG_YEAR,
+        REG_CENTURY,
+    ];
+    let mut values = [0; 7];
+    for (value, &reg) in values.iter_mut().zip(regs.iter()) {
+        *value = read(reg);
+    }
+    Some(values)
+}
+
+/// Read the time since the UNIX epoch from the RTC, which is in UTC.
+pub fn rtc_now() -> Option<Duration> {
+    let (values, status_b) = without_interrupts(|| {
+        // read again until the same values are read, in case of an update,
+        // which happens once a second, so the values differing twice in a
+        // row mean the RTC is broken
+        let mut values = read_all()?;
+        for _ in 0..2 {
+            let again = read_all()?;
+            if again == values {
+                return Some((values, read(REG_STATUS_B)));
+            }
+            values = again;
+        }
+        None
+    })?;
+    let bcd = |value: u8| match status_b & STATUS_B_BINARY {
+        0 => (value >> 4) * 10 + (value & 0xf),
+        _ => value,
+    };
+    let [seconds, minutes, hours, day, month, year, century] = values;
+    let mut hour = bcd(hours & !HOUR_PM) as u64;
+    if status_b & STATUS_B_24_HOUR == 0 {
+        hour %= 12;
+        if hours & HOUR_PM != 0 {
+            hour += 12;
+        }
+    }
+    let century = match bcd(century) {
+        19..=99 => bcd(century) as i64,
+        // no century register
+        _ => 20,
+    };
+    let year = century * 100 + bcd(year) as i64;
+    let (month, day) = (bcd(month) as u32, bcd(day) as u32);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+    let seconds =
+        days as u64 * 86400 + hour * 3600 + bcd(minutes) as u64 * 60 + bcd(seconds) as u64;
+    Some(Duration::from_secs(seconds))
+}
+
+/// The number of days from 1970-01-01 to the date in the Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // the years start from March, so the leap day is the last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    // 719468 days from 0000-03-01 to 1970-01-01
+    era * 146_097 + day_of_era - 719_468
+}
@@ -0,0 +1,77 @@
+//! The 16550 UART at COM1.
+
+use {
+    super::{apic::IRQ_BASE, without_interrupts},
+    alloc::boxed::Box,
+    x86_64::instructions::port::Port,
+};
+
+const COM1: u16 = 0x3f8;
+/// The ISA IRQ of COM1.
+const COM1_IRQ: u32 = 4;
+
+/// Line status: data ready.
+const LSR_DATA_READY: u8 = 1 << 0;
+/// Line status: transmitter holding register empty.
+const LSR_THR_EMPTY: u8 = 1 << 5;
+
+fn port(offset: u16) -> Port<u8> {
+    Port::new(COM1 + offset)
+}
+
+/// Set the UART to 115200 8N1 with FIFOs, and receive input by the IRQ.
+pub fn init() {
+    unsafe {
+        port(1).write(0x00); // disable interrupts
+        port(3).write(0x80); // set the divisor
+        port(0).write(0x01); // 115200 baud
+        port(1).write(0x00);
+        port(3).write(0x03); // 8 bits, no parity, one stop bit
+        port(2).write(0xc7); // enable and clear the FIFOs
+        port(4).write(0x0b); // DTR, RTS, and OUT2 to route the IRQ
+        port(1).write(0x01); // interrupt when data are available
+    }
+    let (input, mode) = super::acpi::isa_irq(COM1_IRQ);
+    let vector = IRQ_BASE + input;
+    super::irq_register(vector, mode, Box::new(handle_irq))
+        .expect("failed to register the IRQ of serial");
+    super::irq_enable(vector);
+}
+
+fn line_status() -> u8 {
+    unsafe { port(5).read() }
+}
+
+/// Put the received bytes to the input queue of the HAL.
+fn handle_irq() {
+    let mut buf = [0u8; 16];
+    let mut len = 0;
+    while line_status() & LSR_DATA_READY != 0 {
+        buf[len] = unsafe { port(0).read() };
+        len += 1;
+        if len == buf.len() {
+            crate::serial_irq_put(&buf);
+            len = 0;
+        }
+    }
+    crate::serial_irq_put(&buf[..len]);
+}
+
+/// Output a string, with `\n` translated to `\r\n`.
+pub fn write(s: &str) {
+    without_interrupts(|| {
+        for &c in s.as_bytes() {
+            if c == b'\n' {
+                putc(b'\r');
+            }
+            putc(c);
+        }
+    })
+}
+
+fn putc(c: u8) {
+    while line_status() & LSR_THR_EMPTY == 0 {
+        core::hint::spin_loop();
+    }
+    unsafe { port(0).write(c) };
+}
@@ -0,0 +1,282 @@
+//! The bring-up of the secondary CPUs by the INIT-SIPI-SIPI sequence.
+//!
+//! A CPU starts in the real mode at the trampoline copied below 1 MiB. It
+//! enters the long mode directly with the control registers of the boot
+//! CPU and a temporary page table, which maps the trampoline at its physical
+//! address besides the kernel space, then jumps to `ap_main` on its stack.
+
+use {
+    super::{acpi, apic, ioport, paging},
+    crate::{frame, phys_to_virt, smp::MAX_CPUS},
+    alloc::vec::Vec,
+    core::{
+        ops::Range,
+        sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+    },
+    kernel_hal::{PhysAddr, VirtAddr, PAGE_SIZE},
+    spin::{Mutex, Once},
+    x86_64::{
+        instructions::interrupts,
+        registers::{
+            control::{Cr0, Cr3, Cr3Flags, Cr4, Cr4Flags},
+            model_specific::{Efer, EferFlags},
+        },
+        structures::paging::PhysFrame,
+    },
+};
+
+global_asm!(
+    ".pushsection .rodata.ap_trampoline, \"a\"",
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_params",
+    ".global ap_trampoline_end",
+    ".code16",
+    "ap_trampoline_start:",
+    ".Lstart:",
+    "    cli",
+    "    cld",
+    "    mov %cs, %ax",
+    "    mov %ax, %ds",
+    // fix up the pointers with the linear address of the trampoline
+    "    xor %ebx, %ebx",
+    "    mov %ax, %bx",
+    "    shl $4, %ebx",
+    "    lea (.Lgdt - .Lstart)(%ebx), %eax",
+    "    mov %eax, (.Lgdt_ptr - .Lstart + 2)",
+    "    lea (.Llong_mode - .Lstart)(%ebx), %eax",
+    "    mov %eax, (.Lfar_ptr - .Lstart)",
+    "    lgdtl (.Lgdt_ptr - .Lstart)",
+    // CR4, CR3 and EFER, then CR0 with the paging enabled
+    "    mov (.Lparams - .Lstart + 16), %eax",
+    "    mov %eax, %cr4",
+    "    mov (.Lparams - .Lstart + 8), %eax",
+    "    mov %eax, %cr3",
+    "    mov $0xc0000080, %ecx",
+    "    mov (.Lparams - .Lstart + 24), %eax",
+    "    xor %edx, %edx",
+    "    wrmsr",
+    "    mov (.Lparams - .Lstart), %eax",
+    "    mov %eax, %cr0",
+    "    ljmpl *(.Lfar_ptr - .Lstart)",
+    ".code64",
+    ".Llong_mode:",
+    "    xor %eax, %eax",
+    "    mov %ax, %ds",
+    "    mov %ax, %es",
+    "    mov %ax, %ss",
+    "    mov %ax, %fs",
+    "    mov %ax, %gs",
+    "    mov .Lparams + 32(%rip), %rsp",
+    "    mov .Lparams + 48(%rip), %rdi",
+    "    mov .Lparams + 40(%rip), %rax",
+    // the null return address
+    "    push $0",
+    "    jmp *%rax",
+    ".balign 8",
+    ".Lgdt:",
+    "    .quad 0",
+    // the 64-bit code segment
+    "    .quad 0x00af9a000000ffff",
+    ".Lgdt_ptr:",
+    "    .short .Lgdt_ptr - .Lgdt - 1",
+    "    .long 0",
+    ".Lfar_ptr:",
+    "    .long 0",
+    "    .short 8",
+    ".balign 8",
+    "ap_trampoline_params:",
+    ".Lparams:",
+    "    .zero 56",
+    "ap_trampoline_end:",
+    ".popsection",
+    options(att_syntax)
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_params: u8;
+    static ap_trampoline_end: u8;
+}
+
+/// The parameters at the end of the trampoline.
+#[repr(C)]
+struct Params {
+    cr0: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    stack: u64,
+    entry: u64,
+    cpu: u64,
+}
+
+/// The trampoline copied below 1 MiB, and the registers of the boot CPU.
+struct Trampoline {
+    page: PhysAddr,
+    /// The temporary page table, below 4 GiB to be loaded in the real mode.
+    page_table: PhysAddr,
+    cr3: (PhysFrame, Cr3Flags),
+    cr4: u64,
+}
+
+static TRAMPOLINE: Once<Option<Trampoline>> = Once::new();
+
+const APIC_ID_NONE: AtomicU32 = AtomicU32::new(u32::MAX);
+
+/// The local APIC IDs of the CPUs online, by their numbers.
+static APIC_IDS: [AtomicU32; MAX_CPUS] = [APIC_ID_NONE; MAX_CPUS];
+
+/// Record the boot CPU as the CPU 0.
+pub fn init() {
+    APIC_IDS[0].store(apic::lapic_id(), Ordering::Relaxed);
+}
+
+/// Get the number of the current CPU.
+pub fn cpu_id() -> u32 {
+    let apic_id = apic::lapic_id();
+    let cpu = APIC_IDS
+        .iter()
+        .position(|id| id.load(Ordering::Relaxed) == apic_id);
+    cpu.unwrap_or(0) as u32
+}
+
+/// Get the local APIC IDs of the CPUs in the ACPI tables, except the boot
+/// CPU.
+pub fn secondary_cpus() -> Vec<u32> {
+    let boot = apic::lapic_id();
+    let cpus = acpi::info().cpus.iter().copied();
+    cpus.filter(|&id| id != boot).collect()
+}
+
+/// Start the CPU of `apic_id` as the CPU `cpu`, on the stack at
+/// `stack_top`.
+pub fn start_cpu(apic_id: u32, cpu: u32, stack_top: VirtAddr) -> bool {
+    // only the 8-bit IDs are addressed by the xAPIC
+    if apic_id > 0xff {
+        return false;
+    }
+    let trampoline = match TRAMPOLINE.call_once(Trampoline::new) {
+        Some(trampoline) => trampoline,
+        None => return false,
+    };
+    // the PCIDE and the LMA bits can only be set in the long mode
+    let params = Params {
+        cr0: Cr0::read_raw(),
+        cr3: trampoline.page_table as u64,
+        cr4: trampoline.cr4 & !Cr4Flags::PCID.bits(),
+        efer: Efer::read_raw() & !EferFlags::LONG_MODE_ACTIVE.bits(),
+        stack: stack_top as u64,
+        entry: ap_main as usize as u64,
+        cpu: cpu as u64,
+    };
+    unsafe {
+        let offset = &ap_trampoline_params as *const u8 as usize
+            - &ap_trampoline_start as *const u8 as usize;
+        let ptr = phys_to_virt(trampoline.page + offset) as *mut Params;
+        ptr.write_volatile(params);
+    }
+    apic::start_cpu(apic_id, (trampoline.page / PAGE_SIZE) as u8);
+    true
+}
+
+impl Trampoline {
+    /// Copy the trampoline, and set up the temporary page table.
+    ///
+    /// They are kept for the CPUs started later, and never freed.
+    fn new() -> Option<Self> {
+        let page = frame::alloc_below(0x10_0000)?;
+        let page_table = frame::alloc_below(0x1_0000_0000)?;
+        let pdpt = frame::alloc()?;
+        let pd = frame::alloc()?;
+        let cr3 = Cr3::read();
+        unsafe {
+            let start = &ap_trampoline_start as *const u8;
+            let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+            start.copy_to_nonoverlapping(phys_to_virt(page) as *mut u8, len);
+        }
+        // the kernel space of the boot CPU, and the first 2 MiB identity
+        // mapped by a huge page
+        let kernel_table = cr3.0.start_address().as_u64() as PhysAddr;
+        crate::frame_copy(kernel_table, page_table);
+        crate::pmem_zero(pdpt, PAGE_SIZE);
+        crate::pmem_zero(pd, PAGE_SIZE);
+        let present_writable = 0x3;
+        let huge_page = 0x80;
+        let write_entry = |table: PhysAddr, entry: u64| unsafe {
+            (phys_to_virt(table) as *mut u64).write(entry);
+        };
+        write_entry(page_table, pdpt as u64 | present_writable);
+        write_entry(pdpt, pd as u64 | present_writable);
+        write_entry(pd, huge_page | present_writable);
+        Some(Trampoline {
+            page,
+            page_table,
+            cr3,
+            cr4: Cr4::read_raw(),
+        })
+    }
+}
+
+/// Held by the CPU sending the IPIs of the TLB shootdown, one at a time.
+static SHOOTDOWN_LOCK: Mutex<()> = Mutex::new(());
+
+/// The range and the page table of the TLB shootdown in progress, and the
+/// number of CPUs not done yet.
+static SHOOTDOWN_START: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_END: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_VMTOKEN: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_PENDING: AtomicU32 = AtomicU32::new(0);
+
+/// Invalidate the TLB entries of `vaddr` of the page table `vmtoken` on the
+/// other CPUs online, and wait until they are done.
+///
+/// The CPUs waiting for the lock still take the IPIs, as the interrupts are
+/// enabled in the kernel.
+pub fn shootdown(vaddr: Range<VirtAddr>, vmtoken: usize) {
+    let this = apic::lapic_id();
+    let others = || {
+        APIC_IDS
+            .iter()
+            .map(|id| id.load(Ordering::Relaxed))
+            .filter(move |&id| id != u32::MAX && id != this)
+    };
+    if others().next().is_none() {
+        return;
+    }
+    let _guard = SHOOTDOWN_LOCK.lock();
+    SHOOTDOWN_START.store(vaddr.start, Ordering::Relaxed);
+    SHOOTDOWN_END.store(vaddr.end, Ordering::Relaxed);
+    SHOOTDOWN_VMTOKEN.store(vmtoken, Ordering::Relaxed);
+    SHOOTDOWN_PENDING.store(others().count() as u32, Ordering::Release);
+    for apic_id in others() {
+        apic::send_fixed_ipi(apic_id, apic::TLB_SHOOTDOWN_VECTOR);
+    }
+    while SHOOTDOWN_PENDING.load(Ordering::Acquire) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Handle the IPI of the TLB shootdown.
+pub fn handle_shootdown() {
+    let start = SHOOTDOWN_START.load(Ordering::Relaxed);
+    let end = SHOOTDOWN_END.load(Ordering::Relaxed);
+    paging::flush_local(start..end, SHOOTDOWN_VMTOKEN.load(Ordering::Relaxed));
+    SHOOTDOWN_PENDING.fetch_sub(1, Ordering::Release);
+}
+
+/// The entry of a secondary CPU from the trampoline.
+extern "C" fn ap_main(cpu: u32) -> ! {
+    let trampoline = TRAMPOLINE.get().unwrap().as_ref().unwrap();
+    unsafe {
+        let (frame, flags) = trampoline.cr3;
+        Cr3::write(frame, flags);
+        Cr4::write_raw(trampoline.cr4);
+        trapframe::init();
+    }
+    ioport::init(cpu);
+    paging::init();
+    apic::init_secondary();
+    APIC_IDS[cpu as usize].store(apic::lapic_id(), Ordering::Relaxed);
+    interrupts::enable();
+    crate::smp::secondary_main(cpu)
+}
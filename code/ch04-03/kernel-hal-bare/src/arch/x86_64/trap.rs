@@ -0,0 +1,172 @@
+//! Handling the traps in the kernel, and the IRQs.
+
+use {
+    super::{
+        apic::{self, IRQ_BASE, SPURIOUS_VECTOR, TIMER_VECTOR, TLB_SHOOTDOWN_VECTOR},
+        smp,
+    },
+    crate::{executor, fixup::fixup},
+    alloc::{collections::BTreeMap, sync::Arc},
+    core::ops::Range,
+    kernel_hal::{HalError, IrqHandler, IrqMode, Result},
+    lazy_static::lazy_static,
+    spin::Mutex,
+    trapframe::TrapFrame,
+    x86_64::registers::control::Cr2,
+};
+
+const GENERAL_PROTECTION_FAULT: usize = 13;
+const PAGE_FAULT: usize = 14;
+
+/// The vectors of the message signaled interrupts, after the IOAPIC inputs.
+const MSI_VECTORS: Range<u32> = 0x80..0xf0;
+
+/// The bitmap of the allocated vectors in `MSI_VECTORS`.
+static MSI_ALLOCATED: Mutex<u128> = Mutex::new(0);
+
+lazy_static! {
+    static ref IRQS: Mutex<BTreeMap<u32, Arc<dyn Fn() + Send + Sync>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+/// Handle the traps in the kernel, called by `trapframe`.
+///
+/// The traps in the user mode return from `context_run` instead. The faults
+/// of the copies from or to the user memory resume at their fixups.
+#[no_mangle]
+pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
+    match tf.trap_num {
+        PAGE_FAULT | GENERAL_PROTECTION_FAULT if fixup(tf.rip).is_some() => {
+            tf.rip = fixup(tf.rip).unwrap();
+        }
+        PAGE_FAULT => panic!(
+            "page fault in kernel: addr={:#x}, error_code={:#x}, {:#x?}",
+            Cr2::read(),
+            tf.error_code,
+            tf
+        ),
+        vector @ 0x20..=0xff => {
+            irq_handle(vector as u32);
+        }
+        _ => panic!("unhandled trap in kernel: {:#x?}", tf),
+    }
+}
+
+/// Handle the interrupt `vector`, return whether it is handled.
+///
+/// It is also called with the interrupts taken in the user mode.
+pub fn irq_handle(vector: u32) -> bool {
+    let handled = match vector {
+        SPURIOUS_VECTOR => return true,
+        TIMER_VECTOR => {
+            executor::timer_interrupt();
+            true
+        }
+        TLB_SHOOTDOWN_VECTOR => {
+            smp::handle_shootdown();
+            true
+        }
+        _ => {
+            let handler = IRQS.lock().get(&vector).cloned();
+            match handler {
+                Some(handler) => {
+                    handler();
+                    true
+                }
+                None => {
+                    warn!("unhandled IRQ: {:#x}", vector);
+                    false
+                }
+            }
+        }
+    };
+    apic::eoi();
+    handled
+}
+
+/// The IRQ vectors of the IOAPIC inputs.
+pub fn irq_range() -> Range<u32> {
+    IRQ_BASE..IRQ_BASE + apic::ioapic_inputs()
+}
+
+/// Register the handler of the IRQ, routed from the IOAPIC in `mode`, or an
+/// allocated vector of the message signaled interrupts.
+pub fn irq_register(vector: u32, mode: IrqMode, handler: IrqHandler) -> Result<()> {
+    let is_msi = MSI_VECTORS.contains(&vector);
+    if !irq_range().contains(&vector) && !is_msi {
+        return Err(HalError);
+    }
+    super::without_interrupts(|| {
+        if is_msi && *MSI_ALLOCATED.lock() & msi_bit(vector) == 0 {
+            return Err(HalError);
+        }
+        let mut irqs = IRQS.lock();
+        if irqs.contains_key(&vector) {
+            return Err(HalError);
+        }
+        irqs.insert(vector, Arc::from(handler));
+        if !is_msi {
+            apic::ioapic_configure(vector - IRQ_BASE, mode);
+        }
+        Ok(())
+    })
+}
+
+/// Unregister the handler of the IRQ, and mask it.
+pub fn irq_unregister(vector: u32) -> Result<()> {
+    super::without_interrupts(|| {
+        IRQS.lock().remove(&vector).ok_or(HalError)?;
+        if irq_range().contains(&vector) {
+            apic::ioapic_set_masked(vector - IRQ_BASE, true);
+        }
+        Ok(())
+    })
+}
+
+/// Unmask the IRQ in the IOAPIC. The message signaled interrupts are
+/// masked by the devices.
+pub fn irq_enable(vector: u32) {
+    if irq_range().contains(&vector) {
+        apic::ioapic_set_masked(vector - IRQ_BASE, false);
+    }
+}
+
+/// Mask the IRQ in the IOAPIC.
+pub fn irq_disable(vector: u32) {
+    if irq_range().contains(&vector) {
+        apic::ioapic_set_masked(vector - IRQ_BASE, true);
+    }
+}
+
+/// Allocate `count` contiguous vectors of the message signaled interrupts,
+/// aligned to `count`, a power of two up to 32. Return the first vector.
+pub fn msi_alloc(count: u32) -> Result<u32> {
+    if !count.is_power_of_two() || count > 32 {
+        return Err(HalError);
+    }
+    let mask = (1u128 << count) - 1;
+    super::without_interrupts(|| {
+        let mut allocated = MSI_ALLOCATED.lock();
+        let vector = MSI_VECTORS
+            .step_by(count as usize)
+            .filter(|&vector| vector + count <= MSI_VECTORS.end)
+            .find(|&vector| *allocated & mask << (vector - MSI_VECTORS.start) == 0)
+            .ok_or(HalError)?;
+        *allocated |= mask << (vector - MSI_VECTORS.start);
+        Ok(vector)
+    })
+}
+
+/// Free the `count` vectors from `vector`, allocated by [`msi_alloc`].
+pub fn msi_free(vector: u32, count: u32) {
+    super::without_interrupts(|| {
+        let mut allocated = MSI_ALLOCATED.lock();
+        for vector in vector..vector + count {
+            *allocated &= !msi_bit(vector);
+        }
+    })
+}
+
+fn msi_bit(vector: u32) -> u128 {
+    1 << (vector - MSI_VECTORS.start)
+}
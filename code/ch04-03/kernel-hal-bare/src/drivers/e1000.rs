@@ -0,0 +1,410 @@
+//! The Intel 8254x/8257x (e1000/e1000e) network devices, emulated by QEMU,
+//! VirtualBox and VMware.
+
+use {
+    super::{pci, NetDriver},
+    crate::{frame, phys_to_virt},
+    alloc::{boxed::Box, sync::Arc, vec::Vec},
+    core::ptr::{read_volatile, write_volatile},
+    kernel_hal::{
+        HalError, IrqMode, NetDeviceInfo, NetRxCallback, PciBar, PciDeviceInfo, PhysAddr, Result,
+        VirtAddr,
+    },
+    spin::Mutex,
+};
+
+/// The supported devices: the 82540EM of QEMU and VirtualBox, the 82545EM
+/// of VMware, and the 82574L of QEMU as e1000e.
+const DEVICE_IDS: &[u16] = &[0x100e, 0x100f, DEVICE_82574L];
+const DEVICE_82574L: u16 = 0x10d3;
+
+// registers
+const REG_CTRL: usize = 0x0000;
+const REG_STATUS: usize = 0x0008;
+const REG_EERD: usize = 0x0014;
+const REG_ICR: usize = 0x00c0;
+const REG_IMS: usize = 0x00d0;
+const REG_IMC: usize = 0x00d8;
+const REG_RCTL: usize = 0x0100;
+const REG_TCTL: usize = 0x0400;
+const REG_TIPG: usize = 0x0410;
+const REG_RDBAL: usize = 0x2800;
+const REG_RDBAH: usize = 0x2804;
+const REG_RDLEN: usize = 0x2808;
+const REG_RDH: usize = 0x2810;
+const REG_RDT: usize = 0x2818;
+const REG_TDBAL: usize = 0x3800;
+const REG_TDBAH: usize = 0x3804;
+const REG_TDLEN: usize = 0x3808;
+const REG_TDH: usize = 0x3810;
+const REG_TDT: usize = 0x3818;
+/// The multicast table of 128 entries.
+const REG_MTA: usize = 0x5200;
+const REG_RAL0: usize = 0x5400;
+const REG_RAH0: usize = 0x5404;
+
+const CTRL_ASDE: u32 = 1 << 5;
+const CTRL_SLU: u32 = 1 << 6;
+const CTRL_RST: u32 = 1 << 26;
+const STATUS_LU: u32 = 1 << 1;
+const EERD_START: u32 = 1 << 0;
+/// The address in the receive address registers is valid.
+const RAH_AV: u32 = 1 << 31;
+
+// interrupt causes
+const INT_LSC: u32 = 1 << 2;
+const INT_RXDMT0: u32 = 1 << 4;
+const INT_RXO: u32 = 1 << 6;
+const INT_RXT0: u32 = 1 << 7;
+
+/// RCTL: enable, accept broadcast, strip the CRC, with buffers of 2048 bytes.
+const RCTL_EN: u32 = 1 << 1;
+const RCTL_BAM: u32 = 1 << 15;
+const RCTL_SECRC: u32 = 1 << 26;
+/// TCTL: enable, pad short packets, the collision threshold and distance.
+const TCTL_EN: u32 = 1 << 1;
+const TCTL_PSP: u32 = 1 << 3;
+const TCTL_CT: u32 = 0x0f << 4;
+const TCTL_COLD: u32 = 0x40 << 12;
+/// The inter packet gap recommended for the copper devices.
+const TIPG_DEFAULT: u32 = 10 | 8 << 10 | 6 << 20;
+
+/// A descriptor is done by the device.
+const DESC_DD: u8 = 1 << 0;
+/// TX command: end of packet, insert the CRC, report the status.
+const TX_CMD_EOP: u8 = 1 << 0;
+const TX_CMD_IFCS: u8 = 1 << 1;
+const TX_CMD_RS: u8 = 1 << 3;
+
+/// The times to poll the registers or the descriptors before giving up.
+const TIMEOUT_POLLS: usize = 1_000_000;
+
+/// The layout of the EEPROM read register, different on the 82574L.
+struct EerdLayout {
+    done: u32,
+    addr_shift: u32,
+}
+
+const EERD_8254X: EerdLayout = EerdLayout {
+    done: 1 << 4,
+    addr_shift: 8,
+};
+const EERD_82574L: EerdLayout = EerdLayout {
+    done: 1 << 1,
+    addr_shift: 2,
+};
+
+const RX_RING_SIZE: usize = 32;
+const TX_RING_SIZE: usize = 8;
+const MTU: usize = 1500;
+/// The size of the buffers, one in each frame.
+const BUFFER_SIZE: usize = 2048;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RxDesc {
+    addr: u64,
+    len: u16,
+    checksum: u16,
+    status: u8,
+    errors: u8,
+    special: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct TxDesc {
+    addr: u64,
+    len: u16,
+    cso: u8,
+    cmd: u8,
+    status: u8,
+    css: u8,
+    special: u16,
+}
+
+/// An e1000 network device, with the legacy descriptors.
+///
+/// The received frames are passed to the callback in the interrupt, and
+/// each frame is sent by waiting for the descriptor to be done by polling,
+/// failing if the device does not finish it in time.
+pub struct E1000 {
+    base: VirtAddr,
+    mac: [u8; 6],
+    eerd: &'static EerdLayout,
+    rx: Mutex<RxInner>,
+    tx: Mutex<TxInner>,
+    callback: Mutex<Option<NetRxCallback>>,
+}
+
+struct RxInner {
+    ring: PhysAddr,
+    buffers: Vec<PhysAddr>,
+    /// The next descriptor to be done by the device.
+    next: usize,
+}
+
+struct TxInner {
+    ring: PhysAddr,
+    buffers: Vec<PhysAddr>,
+    /// The next descriptor to put a frame.
+    tail: usize,
+}
+
+impl E1000 {
+    /// Whether the device ID of Intel is supported.
+    pub fn supports(device_id: u16) -> bool {
+        DEVICE_IDS.contains(&device_id)
+    }
+
+    /// Reset the device and receive by the legacy interrupt, return `None`
+    /// if it fails.
+    pub fn new(info: &PciDeviceInfo) -> Option<Arc<Self>> {
+        let base = match info.bars[0]? {
+            PciBar::Mmio { addr, .. } => phys_to_virt(addr),
+            PciBar::Pio { .. } => return None,
+        };
+        let vector = info.legacy_irq?;
+        pci::enable_bus_master(info.addr);
+
+        let rx = RxInner {
+            ring: frame::alloc()?,
+            buffers: (0..RX_RING_SIZE).filter_map(|_| frame::alloc()).collect(),
+            next: 0,
+        };
+        let tx = TxInner {
+            ring: frame::alloc()?,
+            buffers: (0..TX_RING_SIZE).filter_map(|_| frame::alloc()).collect(),
+            tail: 0,
+        };
+        if rx.buffers.len() != RX_RING_SIZE || tx.buffers.len() != TX_RING_SIZE {
+            return None;
+        }
+        for (i, &buffer) in rx.buffers.iter().enumerate() {
+            rx.write_desc(
+                i,
+                RxDesc {
+                    addr: buffer as u64,
+                    len: 0,
+                    checksum: 0,
+                    status: 0,
+                    errors: 0,
+                    special: 0,
+                },
+            );
+        }
+        crate::pmem_zero(tx.ring, TX_RING_SIZE * core::mem::size_of::<TxDesc>());
+
+        let mut e1000 = E1000 {
+            base,
+            mac: [0; 6],
+            eerd: match info.device_id {
+                DEVICE_82574L => &EERD_82574L,
+                _ => &EERD_8254X,
+            },
+            rx: Mutex::new(rx),
+            tx: Mutex::new(tx),
+            callback: Mutex::new(None),
+        };
+        e1000.reset()?;
+        let e1000 = Arc::new(e1000);
+        let handler = e1000.clone();
+        crate::irq_register(
+            vector,
+            IrqMode::LevelLow,
+            Box::new(move || handler.handle_irq()),
+        )
+        .ok()?;
+        crate::irq_enable(vector);
+        e1000.write(REG_IMS, INT_RXT0 | INT_RXO | INT_RXDMT0 | INT_LSC);
+        info!("e1000: mac {:x?}, vector {:#x}", e1000.mac, vector);
+        Some(e1000)
+    }
+
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg) as *mut u32, value) }
+    }
+
+    /// Reset the device, read the MAC address and set up the rings.
+    fn reset(&mut self) -> Option<()> {
+        self.write(REG_IMC, !0);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_RST);
+        if !(0..TIMEOUT_POLLS).any(|_| self.read(REG_CTRL) & CTRL_RST == 0) {
+            return None;
+        }
+        self.write(REG_IMC, !0);
+        self.read(REG_ICR);
+        self.write(REG_CTRL, self.read(REG_CTRL) | CTRL_SLU | CTRL_ASDE);
+
+        // the address loaded from the EEPROM at reset, or read by EERD
+        if self.read(REG_RAH0) & RAH_AV == 0 {
+            for i in 0..3 {
+                let word = self.read_eeprom(i)?;
+                self.mac[i * 2..i * 2 + 2].copy_from_slice(&word.to_le_bytes());
+            }
+            let mac = self.mac;
+            self.write(
+                REG_RAL0,
+                u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+            );
+            self.write(
+                REG_RAH0,
+                u16::from_le_bytes([mac[4], mac[5]]) as u32 | RAH_AV,
+            );
+        }
+        let low = self.read(REG_RAL0).to_le_bytes();
+        let high = self.read(REG_RAH0).to_le_bytes();
+        self.mac[..4].copy_from_slice(&low);
+        self.mac[4..].copy_from_slice(&high[..2]);
+        for i in 0..128 {
+            self.write(REG_MTA + i * 4, 0);
+        }
+
+        let rx_ring = self.rx.get_mut().ring as u64;
+        self.write(REG_RDBAL, rx_ring as u32);
+        self.write(REG_RDBAH, (rx_ring >> 32) as u32);
+        self.write(
+            REG_RDLEN,
+            (RX_RING_SIZE * core::mem::size_of::<RxDesc>()) as u32,
+        );
+        self.write(REG_RDH, 0);
+        // all but one descriptors are given to the device
+        self.write(REG_RDT, RX_RING_SIZE as u32 - 1);
+        self.write(REG_RCTL, RCTL_EN | RCTL_BAM | RCTL_SECRC);
+
+        let tx_ring = self.tx.get_mut().ring as u64;
+        self.write(REG_TDBAL, tx_ring as u32);
+        self.write(REG_TDBAH, (tx_ring >> 32) as u32);
+        self.write(
+            REG_TDLEN,
+            (TX_RING_SIZE * core::mem::size_of::<TxDesc>()) as u32,
+        );
+        self.write(REG_TDH, 0);
+        self.write(REG_TDT, 0);
+        self.write(REG_TCTL, TCTL_EN | TCTL_PSP | TCTL_CT | TCTL_COLD);
+        self.write(REG_TIPG, TIPG_DEFAULT);
+        Some(())
+    }
+
+    /// Read the word at `addr` of the EEPROM, by EERD in the layout of the
+    /// device.
+    fn read_eeprom(&self, addr: usize) -> Option<u16> {
+        self.write(REG_EERD, EERD_START | (addr as u32) << self.eerd.addr_shift);
+        (0..TIMEOUT_POLLS)
+            .map(|_| self.read(REG_EERD))
+            .find(|value| value & self.eerd.done != 0)
+            .map(|value| (value >> 16) as u16)
+    }
+
+    /// Pass the received frames to the callback, and give the descriptors
+    /// back.
+    fn handle_irq(&self) {
+        // the interrupt may be shared, and is acknowledged by reading
+        if self.read(REG_ICR) == 0 {
+            return;
+        }
+        let mut rx = self.rx.lock();
+        let mut frame = [0u8; BUFFER_SIZE];
+        loop {
+            let i = rx.next;
+            let mut desc = rx.read_desc(i);
+            if desc.status & DESC_DD == 0 {
+                break;
+            }
+            let len = (desc.len as usize).min(BUFFER_SIZE);
+            crate::pmem_read(rx.buffers[i], &mut frame[..len]);
+            if let Some(callback) = self.callback.lock().as_ref() {
+                callback(&frame[..len]);
+            }
+            desc.status = 0;
+            rx.write_desc(i, desc);
+            self.write(REG_RDT, i as u32);
+            rx.next = (i + 1) % RX_RING_SIZE;
+        }
+    }
+}
+
+impl RxInner {
+    fn desc(&self, i: usize) -> *mut RxDesc {
+        (phys_to_virt(self.ring) as *mut RxDesc).wrapping_add(i)
+    }
+
+    fn read_desc(&self, i: usize) -> RxDesc {
+        unsafe { read_volatile(self.desc(i)) }
+    }
+
+    fn write_desc(&self, i: usize, desc: RxDesc) {
+        unsafe { write_volatile(self.desc(i), desc) }
+    }
+}
+
+impl NetDriver for E1000 {
+    fn info(&self) -> NetDeviceInfo {
+        NetDeviceInfo {
+            mac: self.mac,
+            mtu: MTU,
+        }
+    }
+
+    fn link_up(&self) -> bool {
+        self.read(REG_STATUS) & STATUS_LU != 0
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        if frame.len() > BUFFER_SIZE {
+            return Err(HalError);
+        }
+        let mut tx = self.tx.lock();
+        let i = tx.tail;
+        let buffer = tx.buffers[i];
+        crate::pmem_write(buffer, frame);
+        let desc = (phys_to_virt(tx.ring) as *mut TxDesc).wrapping_add(i);
+        unsafe {
+            write_volatile(
+                desc,
+                TxDesc {
+                    addr: buffer as u64,
+                    len: frame.len() as u16,
+                    cso: 0,
+                    cmd: TX_CMD_EOP | TX_CMD_IFCS | TX_CMD_RS,
+                    status: 0,
+                    css: 0,
+                    special: 0,
+                },
+            );
+        }
+        tx.tail = (i + 1) % TX_RING_SIZE;
+        self.write(REG_TDT, tx.tail as u32);
+        if !(0..TIMEOUT_POLLS).any(|_| unsafe { read_volatile(desc) }.status & DESC_DD != 0) {
+            warn!("e1000: send timeout");
+            return Err(HalError);
+        }
+        Ok(())
+    }
+
+    fn set_rx_callback(&self, callback: Option<NetRxCallback>) {
+        *self.callback.lock() = callback;
+    }
+}
+
+impl Drop for RxInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.ring);
+        for &buffer in self.buffers.iter() {
+            frame::dealloc(buffer);
+        }
+    }
+}
+
+impl Drop for TxInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.ring);
+        for &buffer in self.buffers.iter() {
+            frame::dealloc(buffer);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! The device drivers in the kernel, and the devices found by probing.
+
+use {
+    super::arch,
+    alloc::{sync::Arc, vec::Vec},
+    kernel_hal::{BlockDeviceInfo, FramebufferInfo, NetDeviceInfo, NetRxCallback, Result},
+    lazy_static::lazy_static,
+    spin::RwLock,
+};
+
+mod e1000;
+// the interrupts of NVMe are by MSI-X, only on the PC
+#[cfg(target_arch = "x86_64")]
+mod nvme;
+pub mod pci;
+pub mod virtio;
+
+/// A block device driver.
+pub trait BlockDriver: Send + Sync {
+    /// Get the block size and the number of blocks.
+    fn info(&self) -> BlockDeviceInfo;
+
+    /// Read the block `block` to `buf` of the block size.
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<()>;
+
+    /// Write `buf` of the block size to the block `block`.
+    fn write_block(&self, block: u64, buf: &[u8]) -> Result<()>;
+}
+
+/// A network device driver.
+pub trait NetDriver: Send + Sync {
+    /// Get the MAC address and the MTU.
+    fn info(&self) -> NetDeviceInfo;
+
+    /// Whether the link is up.
+    fn link_up(&self) -> bool;
+
+    /// Send an ethernet frame.
+    fn send(&self, frame: &[u8]) -> Result<()>;
+
+    /// Set the handler of the received frames, called in the interrupt.
+    fn set_rx_callback(&self, callback: Option<NetRxCallback>);
+}
+
+/// A console driver, the input of which is put to the input queue of the HAL.
+pub trait ConsoleDriver: Send + Sync {
+    /// Write `s` to the console, with `\n` converted to `\r\n`.
+    fn write(&self, s: &[u8]);
+}
+
+/// A display driver.
+pub trait DisplayDriver: Send + Sync {
+    /// Get the framebuffer in the current mode.
+    fn info(&self) -> FramebufferInfo;
+
+    /// Show the update of the rectangle in the framebuffer.
+    fn flush(&self, x: u32, y: u32, width: u32, height: u32);
+}
+
+lazy_static! {
+    /// The block devices, in the order they are found.
+    pub static ref BLOCK_DEVICES: RwLock<Vec<Arc<dyn BlockDriver>>> = RwLock::new(Vec::new());
+    /// The network devices, in the order they are found.
+    pub static ref NET_DEVICES: RwLock<Vec<Arc<dyn NetDriver>>> = RwLock::new(Vec::new());
+    /// The console replacing the UART, selected by `console=virtio`.
+    pub static ref CONSOLE: RwLock<Option<Arc<dyn ConsoleDriver>>> = RwLock::new(None);
+    /// The display, the first one found.
+    pub static ref DISPLAY: RwLock<Option<Arc<dyn DisplayDriver>>> = RwLock::new(None);
+}
+
+/// Probe the devices of the platform, and add them to the lists.
+pub fn init() {
+    for (paddr, vector) in arch::virtio_mmio_slots() {
+        virtio::probe_mmio(paddr, vector);
+    }
+    pci::init();
+    info!(
+        "drivers: {} block devices, {} network devices, display {}",
+        BLOCK_DEVICES.read().len(),
+        NET_DEVICES.read().len(),
+        match *DISPLAY.read() {
+            Some(_) => "found",
+            None => "not found",
+        }
+    );
+}
@@ -0,0 +1,391 @@
+//! The NVMe controllers, with the admin queue pair and one I/O queue pair.
+
+use {
+    super::{pci, BlockDriver},
+    crate::{arch, frame, phys_to_virt},
+    alloc::boxed::Box,
+    core::{
+        mem::size_of,
+        ptr::{read_volatile, write_volatile},
+        time::Duration,
+    },
+    kernel_hal::{
+        BlockDeviceInfo, HalError, IrqMode, PciBar, PciDeviceInfo, PhysAddr, Result, VirtAddr,
+        PAGE_SIZE,
+    },
+    spin::Mutex,
+};
+
+// registers
+const REG_CAP: usize = 0x00;
+const REG_CC: usize = 0x14;
+const REG_CSTS: usize = 0x1c;
+const REG_AQA: usize = 0x24;
+const REG_ASQ: usize = 0x28;
+const REG_ACQ: usize = 0x30;
+/// The doorbells of the queues, from the tail of the admin submission queue.
+const REG_DOORBELL: usize = 0x1000;
+
+const CC_EN: u32 = 1 << 0;
+/// The sizes of the entries of the I/O submission and completion queues,
+/// log2 of 64 and 16 bytes.
+const CC_IOSQES: u32 = 6 << 16;
+const CC_IOCQES: u32 = 4 << 20;
+const CSTS_RDY: u32 = 1 << 0;
+/// The controller has a fatal error.
+const CSTS_CFS: u32 = 1 << 1;
+
+const ADMIN_CREATE_IO_SQ: u8 = 0x01;
+const ADMIN_CREATE_IO_CQ: u8 = 0x05;
+const ADMIN_IDENTIFY: u8 = 0x06;
+const IO_WRITE: u8 = 0x01;
+const IO_READ: u8 = 0x02;
+/// Identify: the data structure of a namespace.
+const IDENTIFY_NAMESPACE: u32 = 0;
+/// Create queue: physically contiguous.
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+/// Create completion queue: interrupts enabled.
+const QUEUE_INTERRUPTS: u32 = 1 << 1;
+
+/// The preferred number of entries of each queue.
+const QUEUE_SIZE: u16 = 16;
+const ADMIN_QUEUE_ID: u16 = 0;
+const IO_QUEUE_ID: u16 = 1;
+/// The namespace of the blocks.
+const NAMESPACE_ID: u32 = 1;
+
+/// An entry of a submission queue.
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Command {
+    opcode: u8,
+    flags: u8,
+    cid: u16,
+    nsid: u32,
+    reserved: u64,
+    mptr: u64,
+    prp1: u64,
+    prp2: u64,
+    cdw10: u32,
+    cdw11: u32,
+    cdw12: u32,
+    cdw13: u32,
+    cdw14: u32,
+    cdw15: u32,
+}
+
+/// An entry of a completion queue.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Completion {
+    result: u32,
+    reserved: u32,
+    sq_head: u16,
+    sq_id: u16,
+    cid: u16,
+    /// The phase tag in bit 0, and the status from bit 1.
+    status: u16,
+}
+
+/// The registers of a controller.
+struct Regs {
+    base: VirtAddr,
+    /// The distance between the doorbells.
+    doorbell_stride: usize,
+}
+
+/// A submission queue and its completion queue, each in a frame.
+struct Queue {
+    id: u16,
+    size: u16,
+    sq: PhysAddr,
+    cq: PhysAddr,
+    sq_tail: u16,
+    cq_head: u16,
+    /// The phase tag of the new entries in the completion queue, flipped at
+    /// each wrap.
+    phase: u16,
+    next_cid: u16,
+}
+
+/// An NVMe controller, exposing the namespace 1.
+///
+/// The commands are submitted one at a time, and the CPU halts until the
+/// MSI-X interrupt of the completion. The data are copied through a DMA
+/// frame.
+///
+/// The controller is disabled when dropped, before the frames are freed.
+pub struct Nvme {
+    regs: Regs,
+    /// The time to wait for the controller to be enabled or disabled.
+    timeout: Duration,
+    /// The vector of MSI-X, once registered.
+    vector: Option<u32>,
+    block_size: usize,
+    block_count: u64,
+    inner: Mutex<NvmeInner>,
+}
+
+struct NvmeInner {
+    admin: Queue,
+    io: Queue,
+    /// The frame of the data of a command.
+    dma: PhysAddr,
+}
+
+impl Nvme {
+    /// Reset the controller and create the I/O queue pair, return `None` if
+    /// it fails.
+    pub fn new(info: &PciDeviceInfo) -> Option<Self> {
+        let base = match info.bars[0]? {
+            PciBar::Mmio { addr, .. } => phys_to_virt(addr),
+            PciBar::Pio { .. } => return None,
+        };
+        pci::enable_bus_master(info.addr);
+        let cap = unsafe { read_volatile((base + REG_CAP) as *const u64) };
+        let size = QUEUE_SIZE.min((cap & 0xffff) as u16 + 1);
+        // in the unit of 500 milliseconds, at least one
+        let timeout = Duration::from_millis(((cap >> 24) & 0xff).max(1) * 500);
+        let mut nvme = Nvme {
+            regs: Regs {
+                base,
+                doorbell_stride: 4 << ((cap >> 32) & 0xf) as usize,
+            },
+            timeout,
+            vector: None,
+            block_size: 0,
+            block_count: 0,
+            inner: Mutex::new(NvmeInner {
+                admin: Queue::new(ADMIN_QUEUE_ID, size)?,
+                io: Queue::new(IO_QUEUE_ID, size)?,
+                dma: frame::alloc()?,
+            }),
+        };
+        nvme.regs.write(REG_CC, 0);
+        nvme.regs.wait_ready(false, timeout)?;
+
+        let regs = &nvme.regs;
+        let inner = nvme.inner.get_mut();
+        let admin_size = (size as u32 - 1) << 16 | (size as u32 - 1);
+        regs.write(REG_AQA, admin_size);
+        regs.write_u64(REG_ASQ, inner.admin.sq as u64);
+        regs.write_u64(REG_ACQ, inner.admin.cq as u64);
+        // the completions are checked by the waiting CPU after the interrupt
+        let vector = arch::msi_alloc(1).ok()?;
+        if crate::irq_register(vector, IrqMode::EdgeHigh, Box::new(|| {})).is_err() {
+            arch::msi_free(vector, 1);
+            return None;
+        }
+        nvme.vector = Some(vector);
+        pci::msix_enable(info, vector)?;
+        regs.write(REG_CC, CC_EN | CC_IOSQES | CC_IOCQES);
+        regs.wait_ready(true, timeout)?;
+
+        let identify = Command {
+            opcode: ADMIN_IDENTIFY,
+            nsid: NAMESPACE_ID,
+            prp1: inner.dma as u64,
+            cdw10: IDENTIFY_NAMESPACE,
+            ..Default::default()
+        };
+        inner.admin.submit(regs, identify).ok()?;
+        let mut namespace = [0u8; 192];
+        crate::pmem_read(inner.dma, &mut namespace);
+        // the size in blocks, and the format of the blocks in use
+        let mut block_count = [0u8; 8];
+        block_count.copy_from_slice(&namespace[..8]);
+        let format = (namespace[26] & 0xf) as usize;
+        let block_size = 1usize << namespace[128 + format * 4 + 2];
+        if block_size > PAGE_SIZE {
+            return None;
+        }
+
+        let io_size = (inner.io.size as u32 - 1) << 16 | IO_QUEUE_ID as u32;
+        let create_cq = Command {
+            opcode: ADMIN_CREATE_IO_CQ,
+            prp1: inner.io.cq as u64,
+            cdw10: io_size,
+            // on the MSI-X entry 0
+            cdw11: QUEUE_INTERRUPTS | QUEUE_CONTIGUOUS,
+            ..Default::default()
+        };
+        let create_sq = Command {
+            opcode: ADMIN_CREATE_IO_SQ,
+            prp1: inner.io.sq as u64,
+            cdw10: io_size,
+            cdw11: (IO_QUEUE_ID as u32) << 16 | QUEUE_CONTIGUOUS,
+            ..Default::default()
+        };
+        inner.admin.submit(regs, create_cq).ok()?;
+        inner.admin.submit(regs, create_sq).ok()?;
+        nvme.block_size = block_size;
+        nvme.block_count = u64::from_le_bytes(block_count);
+        info!(
+            "nvme: {} blocks of {} bytes, vector {:#x}",
+            nvme.block_count, block_size, vector
+        );
+        Some(nvme)
+    }
+
+    /// Submit an I/O command of `opcode` on `block`, with the data in the
+    /// DMA frame.
+    fn request(&self, inner: &mut NvmeInner, opcode: u8, block: u64) -> Result<()> {
+        if block >= self.block_count {
+            return Err(HalError);
+        }
+        let command = Command {
+            opcode,
+            nsid: NAMESPACE_ID,
+            prp1: inner.dma as u64,
+            cdw10: block as u32,
+            cdw11: (block >> 32) as u32,
+            // the number of blocks minus one
+            cdw12: 0,
+            ..Default::default()
+        };
+        inner.io.submit(&self.regs, command).map(|_| ())
+    }
+}
+
+impl Regs {
+    fn read(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+
+    fn write(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg) as *mut u32, value) }
+    }
+
+    fn write_u64(&self, reg: usize, value: u64) {
+        unsafe { write_volatile((self.base + reg) as *mut u64, value) }
+    }
+
+    /// Wait until the ready status is `ready`, return `None` if timed out
+    /// or the controller fails.
+    fn wait_ready(&self, ready: bool, timeout: Duration) -> Option<()> {
+        let deadline = crate::timer_now() + timeout;
+        loop {
+            let status = self.read(REG_CSTS);
+            if status & CSTS_CFS != 0 {
+                return None;
+            }
+            if (status & CSTS_RDY != 0) == ready {
+                return Some(());
+            }
+            if crate::timer_now() > deadline {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Ring the doorbell of the submission queue `id`, or its completion
+    /// queue.
+    fn ring(&self, id: u16, completion: bool, value: u16) {
+        let index = id as usize * 2 + completion as usize;
+        self.write(REG_DOORBELL + index * self.doorbell_stride, value as u32);
+    }
+}
+
+impl Queue {
+    fn new(id: u16, size: u16) -> Option<Self> {
+        let sq = frame::alloc()?;
+        let cq = match frame::alloc() {
+            Some(cq) => cq,
+            None => {
+                frame::dealloc(sq);
+                return None;
+            }
+        };
+        // the phase tags of the entries not written are 0
+        crate::pmem_zero(cq, PAGE_SIZE);
+        Some(Queue {
+            id,
+            size,
+            sq,
+            cq,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: 1,
+            next_cid: 0,
+        })
+    }
+
+    fn completion(&self) -> *const Completion {
+        (phys_to_virt(self.cq) as *const Completion).wrapping_add(self.cq_head as usize)
+    }
+
+    /// Submit the command and wait for the completion, return the result.
+    fn submit(&mut self, regs: &Regs, mut command: Command) -> Result<u32> {
+        command.cid = self.next_cid;
+        self.next_cid = self.next_cid.wrapping_add(1);
+        let entry = self.sq + self.sq_tail as usize * size_of::<Command>();
+        unsafe { write_volatile(phys_to_virt(entry) as *mut Command, command) };
+        self.sq_tail = (self.sq_tail + 1) % self.size;
+        regs.ring(self.id, false, self.sq_tail);
+
+        let entry = self.completion();
+        let phase = self.phase;
+        arch::wait_until(|| unsafe { read_volatile(entry) }.status & 1 == phase);
+        let completion = unsafe { read_volatile(entry) };
+        self.cq_head += 1;
+        if self.cq_head == self.size {
+            self.cq_head = 0;
+            self.phase ^= 1;
+        }
+        regs.ring(self.id, true, self.cq_head);
+        match completion.status >> 1 {
+            0 => Ok(completion.result),
+            _ => Err(HalError),
+        }
+    }
+}
+
+impl BlockDriver for Nvme {
+    fn info(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            block_size: self.block_size,
+            block_count: self.block_count,
+        }
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<()> {
+        let mut inner = self.inner.lock();
+        self.request(&mut inner, IO_READ, block)?;
+        crate::pmem_read(inner.dma, &mut buf[..self.block_size]);
+        Ok(())
+    }
+
+    fn write_block(&self, block: u64, buf: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock();
+        crate::pmem_write(inner.dma, &buf[..self.block_size]);
+        self.request(&mut inner, IO_WRITE, block)
+    }
+}
+
+impl Drop for Nvme {
+    fn drop(&mut self) {
+        // stop the controller accessing the queues and the DMA frame
+        self.regs.write(REG_CC, 0);
+        if self.regs.wait_ready(false, self.timeout).is_none() {
+            warn!("nvme: failed to disable the controller");
+        }
+        if let Some(vector) = self.vector {
+            let _ = crate::irq_unregister(vector);
+            arch::msi_free(vector, 1);
+        }
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        frame::dealloc(self.sq);
+        frame::dealloc(self.cq);
+    }
+}
+
+impl Drop for NvmeInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.dma);
+    }
+}
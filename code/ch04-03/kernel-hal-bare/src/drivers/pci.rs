@@ -0,0 +1,481 @@
+//! The PCI bus, scanned by the config space, and the drivers of the PCI
+//! devices.
+//!
+//! The config space is accessed by the ECAM of the platform, or the legacy
+//! I/O ports on the PC. Where there is no firmware assigning the BARs, they
+//! are assigned in the MMIO window of the host bridge.
+
+use {
+    super::{e1000::E1000, virtio, NET_DEVICES},
+    crate::{
+        arch::{pci_ecam, pci_legacy_irq, pci_mmio_window},
+        phys_to_virt,
+    },
+    alloc::vec::Vec,
+    core::{
+        ops::Range,
+        ptr::{read_volatile, write_volatile},
+    },
+    kernel_hal::{HalError, PciAddr, PciBar, PciDeviceInfo, PhysAddr, Result},
+    spin::Once,
+};
+#[cfg(target_arch = "x86_64")]
+use {
+    super::{nvme::Nvme, BLOCK_DEVICES},
+    crate::arch::{msi_alloc as arch_msi_alloc, msi_free as arch_msi_free, msi_message},
+    alloc::{collections::BTreeMap, sync::Arc},
+    lazy_static::lazy_static,
+    spin::Mutex,
+};
+
+// offsets in the config space
+const CONFIG_ID: usize = 0x00;
+const CONFIG_COMMAND: usize = 0x04;
+const CONFIG_CLASS: usize = 0x08;
+const CONFIG_HEADER_TYPE: usize = 0x0c;
+const CONFIG_BAR0: usize = 0x10;
+const CONFIG_CAPABILITIES: usize = 0x34;
+const CONFIG_INTERRUPT: usize = 0x3c;
+/// The size of the config space accessible by the HAL functions, without
+/// the extended space of PCIe.
+const CONFIG_SIZE: usize = 256;
+
+/// Command: respond to the I/O port accesses.
+const COMMAND_IO: u32 = 1 << 0;
+/// Command: respond to the memory accesses.
+const COMMAND_MEMORY: u32 = 1 << 1;
+/// Command: access the memory as the bus master, for DMA.
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+/// Command: disable the legacy interrupt.
+#[cfg(target_arch = "x86_64")]
+const COMMAND_INTERRUPT_DISABLE: u32 = 1 << 10;
+/// Status: the capability list is present.
+const STATUS_CAPABILITIES: u32 = 1 << 20;
+
+/// The header type of the general devices, with the BARs.
+const HEADER_GENERAL: u32 = 0;
+/// The header type bit of the multi-function devices.
+const HEADER_MULTI_FUNCTION: u32 = 0x80;
+const CAPABILITY_MSI: u32 = 0x05;
+#[cfg(target_arch = "x86_64")]
+const CAPABILITY_MSIX: u32 = 0x11;
+/// MSI message control: enable, the number of enabled vectors in log2, and
+/// the 64-bit address.
+#[cfg(target_arch = "x86_64")]
+const MSI_ENABLE: u32 = 1 << 0;
+#[cfg(target_arch = "x86_64")]
+const MSI_ENABLED_SHIFT: u32 = 4;
+#[cfg(target_arch = "x86_64")]
+const MSI_64BIT: u32 = 1 << 7;
+/// MSI-X message control: enable, and mask all entries of the function.
+#[cfg(target_arch = "x86_64")]
+const MSIX_ENABLE: u32 = 1 << 15;
+#[cfg(target_arch = "x86_64")]
+const MSIX_FUNCTION_MASK: u32 = 1 << 14;
+/// MSI-X vector control: the entry is masked.
+#[cfg(target_arch = "x86_64")]
+const MSIX_ENTRY_MASKED: u32 = 1;
+
+const VENDOR_INTEL: u16 = 0x8086;
+const VENDOR_VIRTIO: u16 = 0x1af4;
+/// The class of the NVMe controllers.
+#[cfg(target_arch = "x86_64")]
+const CLASS_NVME: (u8, u8, u8) = (0x01, 0x08, 0x02);
+
+/// The functions found at boot.
+static FUNCTIONS: Once<Vec<PciDeviceInfo>> = Once::new();
+
+#[cfg(target_arch = "x86_64")]
+lazy_static! {
+    /// The first vector and the number of the MSI vectors of the functions.
+    static ref MSI_VECTORS: Mutex<BTreeMap<PciAddr, (u32, u32)>> = Mutex::new(BTreeMap::new());
+}
+
+/// Scan the buses, and initialize the drivers of the functions found.
+pub fn init() {
+    let functions = FUNCTIONS.call_once(enumerate);
+    info!("PCI: {} functions", functions.len());
+    for info in functions.iter() {
+        probe(info);
+    }
+}
+
+/// Get the functions found at boot.
+pub fn functions() -> Vec<PciDeviceInfo> {
+    FUNCTIONS.get().cloned().unwrap_or_default()
+}
+
+/// Read the aligned 32 bits at `offset` of the config space, all ones if
+/// there is no such function.
+pub fn read32(addr: PciAddr, offset: usize) -> u32 {
+    match ecam_address(addr, offset) {
+        Some(vaddr) => unsafe { read_volatile(vaddr as *const u32) },
+        #[cfg(target_arch = "x86_64")]
+        None => crate::arch::pci_config_read(addr, offset),
+        #[cfg(not(target_arch = "x86_64"))]
+        None => !0,
+    }
+}
+
+/// Write the aligned 32 bits at `offset` of the config space.
+pub fn write32(addr: PciAddr, offset: usize, value: u32) {
+    match ecam_address(addr, offset) {
+        Some(vaddr) => unsafe { write_volatile(vaddr as *mut u32, value) },
+        #[cfg(target_arch = "x86_64")]
+        None => crate::arch::pci_config_write(addr, offset, value),
+        #[cfg(not(target_arch = "x86_64"))]
+        None => {}
+    }
+}
+
+/// The virtual address of `offset` in the config space by the ECAM, if the
+/// bus is covered.
+fn ecam_address(addr: PciAddr, offset: usize) -> Option<usize> {
+    let (base, buses) = pci_ecam()?;
+    if !buses.contains(&addr.bus) {
+        return None;
+    }
+    let function =
+        (addr.bus as usize) << 20 | (addr.device as usize) << 15 | (addr.function as usize) << 12;
+    Some(phys_to_virt(base + function + (offset & 0xffc)))
+}
+
+/// Read `width` bytes at `offset` of the config space of a function found.
+pub fn config_read(addr: PciAddr, offset: usize, width: usize) -> Result<u32> {
+    check_access(addr, offset, width)?;
+    let value = read32(addr, offset & !3) >> ((offset & 3) * 8);
+    Ok(match width {
+        4 => value,
+        _ => value & ((1 << (width * 8)) - 1),
+    })
+}
+
+/// Write `width` bytes at `offset` of the config space of a function found.
+pub fn config_write(addr: PciAddr, offset: usize, width: usize, value: u32) -> Result<()> {
+    check_access(addr, offset, width)?;
+    let value = match width {
+        4 => value,
+        _ => {
+            let shift = (offset & 3) * 8;
+            let mask = ((1u32 << (width * 8)) - 1) << shift;
+            let old = read32(addr, offset & !3);
+            (old & !mask) | (value << shift & mask)
+        }
+    };
+    write32(addr, offset & !3, value);
+    Ok(())
+}
+
+fn check_access(addr: PciAddr, offset: usize, width: usize) -> Result<()> {
+    let found = FUNCTIONS
+        .get()
+        .map_or(false, |functions| functions.iter().any(|f| f.addr == addr));
+    let aligned = matches!(width, 1 | 2 | 4) && offset % width == 0;
+    match found && aligned && offset + width <= CONFIG_SIZE {
+        true => Ok(()),
+        false => Err(HalError),
+    }
+}
+
+/// Scan all buses, return the functions found.
+fn enumerate() -> Vec<PciDeviceInfo> {
+    let mut window = pci_mmio_window();
+    let mut functions = Vec::new();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let addr = PciAddr {
+                bus,
+                device,
+                function: 0,
+            };
+            if read32(addr, CONFIG_ID) & 0xffff == 0xffff {
+                continue;
+            }
+            let header = read32(addr, CONFIG_HEADER_TYPE) >> 16;
+            let count = match header & HEADER_MULTI_FUNCTION {
+                0 => 1,
+                _ => 8,
+            };
+            for function in 0..count {
+                let addr = PciAddr {
+                    bus,
+                    device,
+                    function,
+                };
+                if let Some(info) = read_info(addr, &mut window) {
+                    functions.push(info);
+                }
+            }
+        }
+    }
+    functions
+}
+
+/// Read the information of the function, `None` if it is absent.
+///
+/// The BARs are assigned in `window` if it is given.
+fn read_info(addr: PciAddr, window: &mut Option<Range<PhysAddr>>) -> Option<PciDeviceInfo> {
+    let id = read32(addr, CONFIG_ID);
+    if id & 0xffff == 0xffff {
+        return None;
+    }
+    let class = read32(addr, CONFIG_CLASS);
+    let header = (read32(addr, CONFIG_HEADER_TYPE) >> 16) & !HEADER_MULTI_FUNCTION;
+    let interrupt = read32(addr, CONFIG_INTERRUPT);
+    // the line is meaningful only if the function has an interrupt pin
+    let legacy_irq = match (interrupt >> 8) as u8 {
+        0 => None,
+        pin => pci_legacy_irq(addr, pin, interrupt as u8),
+    };
+    Some(PciDeviceInfo {
+        addr,
+        vendor_id: id as u16,
+        device_id: (id >> 16) as u16,
+        base_class: (class >> 24) as u8,
+        sub_class: (class >> 16) as u8,
+        program_interface: (class >> 8) as u8,
+        revision_id: class as u8,
+        bars: match header {
+            HEADER_GENERAL => read_bars(addr, window),
+            _ => [None; 6],
+        },
+        legacy_irq,
+        msi_count: msi_count(addr),
+    })
+}
+
+/// Read the addresses of the BARs, and their sizes by writing all ones.
+///
+/// The memory BARs are assigned in `window` if it is given, where the I/O
+/// port BARs are not accessible.
+fn read_bars(addr: PciAddr, window: &mut Option<Range<PhysAddr>>) -> [Option<PciBar>; 6] {
+    let mut bars = [None; 6];
+    // stop decoding while the BARs are changed
+    let mut command = read32(addr, CONFIG_COMMAND) & 0xffff;
+    write32(
+        addr,
+        CONFIG_COMMAND,
+        command & !(COMMAND_IO | COMMAND_MEMORY),
+    );
+    let mut i = 0;
+    while i < 6 {
+        let offset = CONFIG_BAR0 + i * 4;
+        let (value, mask) = size_bar(addr, offset);
+        if value & 1 != 0 {
+            let size = (!(mask & !0b11)).wrapping_add(1) & 0xffff;
+            if value & !0b11 != 0 && size != 0 && window.is_none() {
+                bars[i] = Some(PciBar::Pio {
+                    addr: (value & !0b11) as u16,
+                    size: size as usize,
+                });
+            }
+            i += 1;
+            continue;
+        }
+        let is_64bit = (value >> 1) & 0b11 == 0b10 && i < 5;
+        let mut paddr = (value & !0xf) as u64;
+        let mut mask = (mask & !0xf) as u64 | 0xffff_ffff_0000_0000;
+        if is_64bit {
+            let (high, high_mask) = size_bar(addr, offset + 4);
+            paddr |= (high as u64) << 32;
+            mask = (mask & 0xffff_ffff) | (high_mask as u64) << 32;
+        }
+        let size = (!mask).wrapping_add(1);
+        if let (Some(window), true) = (window.as_mut(), size != 0) {
+            // naturally aligned in the window
+            let start = (window.start as u64 + size - 1) & !(size - 1);
+            paddr = match start + size <= window.end as u64 {
+                true => start,
+                false => 0,
+            };
+            if paddr != 0 {
+                window.start = (start + size) as usize;
+                write32(addr, offset, paddr as u32 | (value & 0xf));
+                if is_64bit {
+                    write32(addr, offset + 4, (paddr >> 32) as u32);
+                }
+                command |= COMMAND_MEMORY;
+            }
+        }
+        if paddr != 0 && size != 0 {
+            bars[i] = Some(PciBar::Mmio {
+                addr: paddr as usize,
+                size: size as usize,
+            });
+        }
+        i += if is_64bit { 2 } else { 1 };
+    }
+    write32(addr, CONFIG_COMMAND, command);
+    bars
+}
+
+/// Return the value of the BAR at `offset`, and the mask of the size.
+fn size_bar(addr: PciAddr, offset: usize) -> (u32, u32) {
+    let value = read32(addr, offset);
+    write32(addr, offset, !0);
+    let mask = read32(addr, offset);
+    write32(addr, offset, value);
+    (value, mask)
+}
+
+/// Get the maximum number of the MSI vectors, by the MSI capability.
+fn msi_count(addr: PciAddr) -> u32 {
+    match find_capabilities(addr, CAPABILITY_MSI).first() {
+        // the multiple message capable field, log2 of the count
+        Some(&offset) => 1 << ((read32(addr, offset) >> 17) & 0b111),
+        None => 0,
+    }
+}
+
+/// Find the capabilities of `id`, return their offsets in the config space.
+pub fn find_capabilities(addr: PciAddr, id: u32) -> Vec<usize> {
+    let mut found = Vec::new();
+    if read32(addr, CONFIG_COMMAND) & STATUS_CAPABILITIES == 0 {
+        return found;
+    }
+    let mut offset = read32(addr, CONFIG_CAPABILITIES) as usize & 0xfc;
+    // bounded in case of a loop in the list
+    for _ in 0..48 {
+        if offset == 0 {
+            break;
+        }
+        let capability = read32(addr, offset);
+        if capability & 0xff == id {
+            found.push(offset);
+        }
+        offset = (capability >> 8) as usize & 0xfc;
+    }
+    found
+}
+
+/// Enable the memory decoding and the DMA of the function.
+pub fn enable_bus_master(addr: PciAddr) {
+    let command = read32(addr, CONFIG_COMMAND) & 0xffff;
+    write32(
+        addr,
+        CONFIG_COMMAND,
+        command | COMMAND_MEMORY | COMMAND_BUS_MASTER,
+    );
+}
+
+/// Allocate and enable `count` MSI vectors of the function, return the
+/// first vector. The legacy interrupt is disabled.
+#[cfg(target_arch = "x86_64")]
+pub fn msi_alloc(addr: PciAddr, count: u32) -> Result<u32> {
+    let offset = *find_capabilities(addr, CAPABILITY_MSI)
+        .first()
+        .ok_or(HalError)?;
+    let mut vectors = MSI_VECTORS.lock();
+    if vectors.contains_key(&addr) || count > msi_count(addr) {
+        return Err(HalError);
+    }
+    let vector = arch_msi_alloc(count)?;
+    let (address, data) = msi_message(vector);
+    let control = read32(addr, offset) >> 16;
+    let data_offset = match control & MSI_64BIT {
+        0 => offset + 8,
+        _ => {
+            write32(addr, offset + 8, (address >> 32) as u32);
+            offset + 12
+        }
+    };
+    write32(addr, offset + 4, address as u32);
+    let old = read32(addr, data_offset) & 0xffff_0000;
+    write32(addr, data_offset, old | data);
+    let enabled = MSI_ENABLE | count.trailing_zeros() << MSI_ENABLED_SHIFT;
+    let control = (control & !(0b111 << MSI_ENABLED_SHIFT)) | enabled;
+    write32(
+        addr,
+        offset,
+        (read32(addr, offset) & 0xffff) | control << 16,
+    );
+    disable_legacy_irq(addr);
+    vectors.insert(addr, (vector, count));
+    Ok(vector)
+}
+
+/// MSI is not supported.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn msi_alloc(_addr: PciAddr, _count: u32) -> Result<u32> {
+    Err(HalError)
+}
+
+/// Disable and free the MSI vectors of the function.
+#[cfg(target_arch = "x86_64")]
+pub fn msi_free(addr: PciAddr) {
+    let (vector, count) = match MSI_VECTORS.lock().remove(&addr) {
+        Some(vectors) => vectors,
+        None => return,
+    };
+    if let Some(&offset) = find_capabilities(addr, CAPABILITY_MSI).first() {
+        let control = read32(addr, offset);
+        write32(addr, offset, control & !(MSI_ENABLE << 16));
+    }
+    arch_msi_free(vector, count);
+}
+
+/// MSI is not supported.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn msi_free(_addr: PciAddr) {}
+
+#[cfg(target_arch = "x86_64")]
+fn disable_legacy_irq(addr: PciAddr) {
+    let command = read32(addr, CONFIG_COMMAND) & 0xffff;
+    write32(addr, CONFIG_COMMAND, command | COMMAND_INTERRUPT_DISABLE);
+}
+
+/// Enable the MSI-X of the function, with the entry 0 of the table
+/// delivering `vector`. Other entries are masked.
+///
+/// The legacy interrupt is disabled.
+#[cfg(target_arch = "x86_64")]
+pub fn msix_enable(info: &PciDeviceInfo, vector: u32) -> Option<()> {
+    let offset = *find_capabilities(info.addr, CAPABILITY_MSIX).first()?;
+    let control = read32(info.addr, offset) >> 16;
+    let table = read32(info.addr, offset + 4);
+    let bar = match info.bars.get(table as usize & 0b111)? {
+        Some(PciBar::Mmio { addr, .. }) => *addr,
+        _ => return None,
+    };
+    let table = phys_to_virt(bar + (table & !0b111) as usize) as *mut u32;
+    let (address, data) = msi_message(vector);
+    // the table size field is the number of entries minus one
+    for i in 0..=(control & 0x7ff) as usize {
+        let entry = table.wrapping_add(i * 4);
+        unsafe {
+            match i {
+                0 => {
+                    write_volatile(entry, address as u32);
+                    write_volatile(entry.wrapping_add(1), (address >> 32) as u32);
+                    write_volatile(entry.wrapping_add(2), data);
+                    write_volatile(entry.wrapping_add(3), 0);
+                }
+                _ => write_volatile(entry.wrapping_add(3), MSIX_ENTRY_MASKED),
+            }
+        }
+    }
+    disable_legacy_irq(info.addr);
+    let control = (control | MSIX_ENABLE) & !MSIX_FUNCTION_MASK;
+    let header = read32(info.addr, offset) & 0xffff;
+    write32(info.addr, offset, header | control << 16);
+    Some(())
+}
+
+/// Initialize the driver of the function, if any.
+fn probe(info: &PciDeviceInfo) {
+    match (info.vendor_id, info.device_id) {
+        (VENDOR_VIRTIO, _) => virtio::probe_pci(info),
+        (VENDOR_INTEL, id) if E1000::supports(id) => match E1000::new(info) {
+            Some(e1000) => NET_DEVICES.write().push(e1000),
+            None => warn!("e1000 at {:x?}: failed to initialize", info.addr),
+        },
+        #[cfg(target_arch = "x86_64")]
+        _ if (info.base_class, info.sub_class, info.program_interface) == CLASS_NVME => {
+            match Nvme::new(info) {
+                Some(nvme) => BLOCK_DEVICES.write().push(Arc::new(nvme)),
+                None => warn!("nvme at {:x?}: failed to initialize", info.addr),
+            }
+        }
+        _ => {}
+    }
+}
@@ -0,0 +1,142 @@
+//! The virtio block device.
+
+use {
+    super::{Transport, VirtQueue},
+    crate::{drivers::BlockDriver, frame},
+    core::mem::size_of,
+    kernel_hal::{BlockDeviceInfo, HalError, PhysAddr, Result},
+    spin::Mutex,
+};
+
+/// The size of the sectors, in which the capacity and the offsets are.
+const SECTOR_SIZE: usize = 512;
+
+/// The preferred number of descriptors.
+const QUEUE_SIZE: u16 = 16;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const STATUS_OK: u8 = 0;
+
+/// The offsets in the DMA frame of a request, which holds a sector.
+const HEADER_OFFSET: usize = 0;
+const STATUS_OFFSET: usize = 16;
+const DATA_OFFSET: usize = SECTOR_SIZE;
+
+#[repr(C)]
+struct RequestHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// A virtio block device.
+///
+/// The requests are served one at a time, waiting for the completion by
+/// polling. The data are copied through a DMA frame.
+pub struct VirtioBlk<T: Transport> {
+    transport: T,
+    capacity: u64,
+    inner: Mutex<BlkInner>,
+}
+
+struct BlkInner {
+    queue: VirtQueue,
+    /// The frame of the request header, the status and the data.
+    dma: PhysAddr,
+}
+
+impl<T: Transport> VirtioBlk<T> {
+    /// Initialize the device, return `None` if it fails.
+    pub fn new(transport: T) -> Option<Self> {
+        transport.begin_init(0)?;
+        // three descriptors for each request
+        let size = transport.queue_size(0, QUEUE_SIZE);
+        if size < 4 {
+            return None;
+        }
+        let queue = VirtQueue::new(size)?;
+        transport.setup_queue(0, &queue);
+        transport.finish_init();
+        let capacity = transport.config_read_u64(0);
+        info!(
+            "virtio-blk: {} sectors, vector {:#x}",
+            capacity,
+            transport.vector()
+        );
+        Some(VirtioBlk {
+            transport,
+            capacity,
+            inner: Mutex::new(BlkInner {
+                queue,
+                dma: frame::alloc()?,
+            }),
+        })
+    }
+
+    /// Send a request of `type_` on `sector`, with the data in the DMA frame.
+    fn request(&self, inner: &mut BlkInner, type_: u32, sector: u64) -> Result<()> {
+        if sector >= self.capacity {
+            return Err(HalError);
+        }
+        let header = RequestHeader {
+            type_,
+            reserved: 0,
+            sector,
+        };
+        let header = unsafe {
+            core::slice::from_raw_parts(
+                &header as *const _ as *const u8,
+                size_of::<RequestHeader>(),
+            )
+        };
+        crate::pmem_write(inner.dma + HEADER_OFFSET, header);
+        crate::pmem_write(inner.dma + STATUS_OFFSET, &[0xff]);
+        let dma = inner.dma;
+        let buffers = [
+            (dma + HEADER_OFFSET, size_of::<RequestHeader>(), false),
+            (dma + DATA_OFFSET, SECTOR_SIZE, type_ == REQ_IN),
+            (dma + STATUS_OFFSET, 1, true),
+        ];
+        inner.queue.add(&buffers).ok_or(HalError)?;
+        self.transport.notify(0);
+        while inner.queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+        self.transport.ack_interrupt();
+        let mut status = [0u8];
+        crate::pmem_read(dma + STATUS_OFFSET, &mut status);
+        match status[0] {
+            STATUS_OK => Ok(()),
+            _ => Err(HalError),
+        }
+    }
+}
+
+impl<T: Transport> BlockDriver for VirtioBlk<T> {
+    fn info(&self) -> BlockDeviceInfo {
+        BlockDeviceInfo {
+            block_size: SECTOR_SIZE,
+            block_count: self.capacity,
+        }
+    }
+
+    fn read_block(&self, block: u64, buf: &mut [u8]) -> Result<()> {
+        let mut inner = self.inner.lock();
+        self.request(&mut inner, REQ_IN, block)?;
+        crate::pmem_read(inner.dma + DATA_OFFSET, &mut buf[..SECTOR_SIZE]);
+        Ok(())
+    }
+
+    fn write_block(&self, block: u64, buf: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock();
+        crate::pmem_write(inner.dma + DATA_OFFSET, &buf[..SECTOR_SIZE]);
+        self.request(&mut inner, REQ_OUT, block)
+    }
+}
+
+impl Drop for BlkInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.dma);
+    }
+}
@@ -0,0 +1,156 @@
+//! The virtio console device.
+
+use {
+    super::{Transport, VirtQueue},
+    crate::{drivers::ConsoleDriver, frame},
+    alloc::{boxed::Box, sync::Arc, vec, vec::Vec},
+    kernel_hal::PhysAddr,
+    spin::Mutex,
+};
+
+const QUEUE_RX: u16 = 0;
+const QUEUE_TX: u16 = 1;
+const QUEUE_SIZE: u16 = 4;
+
+/// The size of the buffers of the input, all in one frame.
+const RX_BUFFER_SIZE: usize = 64;
+
+/// A virtio console device, on the first port.
+///
+/// The input is put to the input queue of the HAL in the interrupt, and the
+/// output is written by waiting for the completion by polling.
+pub struct VirtioConsole<T: Transport> {
+    transport: T,
+    rx: Mutex<RxInner>,
+    tx: Mutex<TxInner>,
+}
+
+struct RxInner {
+    queue: VirtQueue,
+    /// The buffers given to the device, indexed by the descriptor.
+    buffers: Vec<PhysAddr>,
+    /// The frame divided into the buffers.
+    dma: PhysAddr,
+}
+
+struct TxInner {
+    queue: VirtQueue,
+    buffer: PhysAddr,
+}
+
+impl<T: Transport> VirtioConsole<T> {
+    /// Initialize the device and receive by the interrupt, return `None`
+    /// if it fails.
+    pub fn new(transport: T) -> Option<Arc<Self>> {
+        transport.begin_init(0)?;
+        let rx_size = transport.queue_size(QUEUE_RX, QUEUE_SIZE);
+        if rx_size == 0 || transport.queue_size(QUEUE_TX, 1) == 0 {
+            return None;
+        }
+        let mut rx_queue = VirtQueue::new(rx_size)?;
+        let tx_queue = VirtQueue::new(1)?;
+        let dma = frame::alloc()?;
+        let mut buffers = vec![0; rx_queue.size() as usize];
+        for i in 0..rx_queue.size() as usize {
+            let buffer = dma + i * RX_BUFFER_SIZE;
+            let head = rx_queue.add(&[(buffer, RX_BUFFER_SIZE, true)])?;
+            buffers[head as usize] = buffer;
+        }
+        transport.setup_queue(QUEUE_RX, &rx_queue);
+        transport.setup_queue(QUEUE_TX, &tx_queue);
+        let console = Arc::new(VirtioConsole {
+            rx: Mutex::new(RxInner {
+                queue: rx_queue,
+                buffers,
+                dma,
+            }),
+            tx: Mutex::new(TxInner {
+                queue: tx_queue,
+                buffer: frame::alloc()?,
+            }),
+            transport,
+        });
+        let vector = console.transport.vector();
+        let handler = console.clone();
+        crate::irq_register(
+            vector,
+            console.transport.irq_mode(),
+            Box::new(move || handler.handle_irq()),
+        )
+        .ok()?;
+        crate::irq_enable(vector);
+        console.transport.finish_init();
+        console.transport.notify(QUEUE_RX);
+        info!("virtio-console: vector {:#x}", vector);
+        Some(console)
+    }
+
+    /// Put the input to the input queue of the HAL, and give the buffers
+    /// back.
+    fn handle_irq(&self) {
+        self.transport.ack_interrupt();
+        let mut rx = self.rx.lock();
+        let mut input = [0u8; RX_BUFFER_SIZE];
+        let mut received = false;
+        while let Some((head, len)) = rx.queue.pop_used() {
+            let buffer = rx.buffers[head as usize];
+            let len = (len as usize).min(RX_BUFFER_SIZE);
+            crate::pmem_read(buffer, &mut input[..len]);
+            crate::serial_irq_put(&input[..len]);
+            let head = rx.queue.add(&[(buffer, RX_BUFFER_SIZE, true)]).unwrap();
+            rx.buffers[head as usize] = buffer;
+            received = true;
+        }
+        if received {
+            self.transport.notify(QUEUE_RX);
+        }
+    }
+
+    /// Send the first `len` bytes of the TX buffer.
+    fn send(&self, tx: &mut TxInner, len: usize) {
+        if tx.queue.add(&[(tx.buffer, len, false)]).is_none() {
+            return;
+        }
+        self.transport.notify(QUEUE_TX);
+        while tx.queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+impl<T: Transport> ConsoleDriver for VirtioConsole<T> {
+    fn write(&self, s: &[u8]) {
+        let mut tx = self.tx.lock();
+        let mut buf = [0u8; 256];
+        let mut len = 0;
+        for &c in s {
+            if len + 2 > buf.len() {
+                crate::pmem_write(tx.buffer, &buf[..len]);
+                self.send(&mut tx, len);
+                len = 0;
+            }
+            if c == b'\n' {
+                buf[len] = b'\r';
+                len += 1;
+            }
+            buf[len] = c;
+            len += 1;
+        }
+        if len != 0 {
+            crate::pmem_write(tx.buffer, &buf[..len]);
+            self.send(&mut tx, len);
+        }
+    }
+}
+
+impl Drop for RxInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.dma);
+    }
+}
+
+impl Drop for TxInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.buffer);
+    }
+}
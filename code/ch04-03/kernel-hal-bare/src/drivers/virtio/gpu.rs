@@ -0,0 +1,320 @@
+//! The virtio GPU device, with a 2D framebuffer.
+
+use {
+    super::{Transport, VirtQueue},
+    crate::{drivers::DisplayDriver, frame},
+    core::mem::size_of,
+    kernel_hal::{FramebufferInfo, HalError, PhysAddr, Result, PAGE_SIZE},
+    spin::Mutex,
+};
+
+const QUEUE_CONTROL: u16 = 0;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// The pixel format of the blue, green, red and an unused byte.
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+const MAX_SCANOUTS: usize = 16;
+/// The ID of the resource of the framebuffer.
+const RESOURCE_ID: u32 = 1;
+const BYTES_PER_PIXEL: usize = 4;
+/// The mode if the host does not tell one.
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+
+/// The offsets in the DMA frame of a command.
+const REQUEST_OFFSET: usize = 0;
+const RESPONSE_OFFSET: usize = PAGE_SIZE / 2;
+
+#[repr(C)]
+#[derive(Default)]
+struct ControlHeader {
+    type_: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    padding: u32,
+}
+
+impl ControlHeader {
+    fn new(type_: u32) -> Self {
+        ControlHeader {
+            type_,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct DisplayOne {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct RespDisplayInfo {
+    header: ControlHeader,
+    pmodes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+struct ResourceCreate2D {
+    header: ControlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+struct ResourceAttachBacking {
+    header: ControlHeader,
+    resource_id: u32,
+    nr_entries: u32,
+    // one entry
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct SetScanout {
+    header: ControlHeader,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+struct TransferToHost2D {
+    header: ControlHeader,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct ResourceFlush {
+    header: ControlHeader,
+    rect: Rect,
+    resource_id: u32,
+    padding: u32,
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) }
+}
+
+/// A virtio GPU device.
+///
+/// The mode of the first scanout is set at initialization, showing a
+/// framebuffer in contiguous frames as the backing of a 2D resource. The
+/// updates are transferred to the host when flushed. The commands are sent
+/// one at a time, waiting for the completion by polling.
+pub struct VirtioGpu<T: Transport> {
+    transport: T,
+    info: FramebufferInfo,
+    inner: Mutex<GpuInner>,
+}
+
+struct GpuInner {
+    queue: VirtQueue,
+    /// The frame of the request and the response.
+    dma: PhysAddr,
+}
+
+impl<T: Transport> VirtioGpu<T> {
+    /// Initialize the device and set the mode, return `None` if it fails.
+    pub fn new(transport: T) -> Option<Self> {
+        transport.begin_init(0)?;
+        // two descriptors for each command
+        let size = transport.queue_size(QUEUE_CONTROL, 2);
+        if size < 2 {
+            return None;
+        }
+        let queue = VirtQueue::new(size)?;
+        transport.setup_queue(QUEUE_CONTROL, &queue);
+        transport.finish_init();
+        let mut inner = GpuInner {
+            queue,
+            dma: frame::alloc()?,
+        };
+
+        let mut display_info = RespDisplayInfo::default();
+        let request = ControlHeader::new(CMD_GET_DISPLAY_INFO);
+        let resp = Self::command(
+            &transport,
+            &mut inner,
+            as_bytes(&request),
+            &mut display_info,
+        );
+        let (width, height) = match display_info.pmodes[0] {
+            mode if matches!(resp, Ok(RESP_OK_DISPLAY_INFO)) && mode.enabled != 0 => {
+                (mode.rect.width, mode.rect.height)
+            }
+            _ => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+        };
+        let size = width as usize * height as usize * BYTES_PER_PIXEL;
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let paddr = frame::alloc_contiguous(pages, 0)?;
+        crate::pmem_zero(paddr, pages * PAGE_SIZE);
+        let info = FramebufferInfo {
+            paddr,
+            size,
+            width,
+            height,
+            stride: width,
+        };
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+
+        let create = ResourceCreate2D {
+            header: ControlHeader::new(CMD_RESOURCE_CREATE_2D),
+            resource_id: RESOURCE_ID,
+            format: FORMAT_B8G8R8X8_UNORM,
+            width,
+            height,
+        };
+        let attach = ResourceAttachBacking {
+            header: ControlHeader::new(CMD_RESOURCE_ATTACH_BACKING),
+            resource_id: RESOURCE_ID,
+            nr_entries: 1,
+            addr: paddr as u64,
+            length: size as u32,
+            padding: 0,
+        };
+        let scanout = SetScanout {
+            header: ControlHeader::new(CMD_SET_SCANOUT),
+            rect,
+            scanout_id: 0,
+            resource_id: RESOURCE_ID,
+        };
+        let requests: [&[u8]; 3] = [as_bytes(&create), as_bytes(&attach), as_bytes(&scanout)];
+        for request in requests.iter() {
+            let mut header = ControlHeader::default();
+            let resp = Self::command(&transport, &mut inner, request, &mut header);
+            if !matches!(resp, Ok(RESP_OK_NODATA)) {
+                return None;
+            }
+        }
+        info!(
+            "virtio-gpu: {}x{} framebuffer at {:#x}, vector {:#x}",
+            width,
+            height,
+            paddr,
+            transport.vector()
+        );
+        let gpu = VirtioGpu {
+            transport,
+            info,
+            inner: Mutex::new(inner),
+        };
+        gpu.flush(0, 0, width, height);
+        Some(gpu)
+    }
+
+    /// Send the command `request`, and receive the response to `response`.
+    ///
+    /// Return the type of the response.
+    fn command<T>(
+        transport: &T,
+        inner: &mut GpuInner,
+        request: &[u8],
+        response: &mut T,
+    ) -> Result<u32> {
+        let dma = inner.dma;
+        let response = as_bytes_mut(response);
+        crate::pmem_write(dma + REQUEST_OFFSET, request);
+        let buffers = [
+            (dma + REQUEST_OFFSET, request.len(), false),
+            (dma + RESPONSE_OFFSET, response.len(), true),
+        ];
+        inner.queue.add(&buffers).ok_or(HalError)?;
+        transport.notify(QUEUE_CONTROL);
+        while inner.queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+        transport.ack_interrupt();
+        crate::pmem_read(dma + RESPONSE_OFFSET, response);
+        let header = unsafe { &*(response.as_ptr() as *const ControlHeader) };
+        Ok(header.type_)
+    }
+}
+
+impl<T: Transport> DisplayDriver for VirtioGpu<T> {
+    fn info(&self) -> FramebufferInfo {
+        self.info
+    }
+
+    fn flush(&self, x: u32, y: u32, width: u32, height: u32) {
+        // clip the rectangle to the framebuffer
+        let x = x.min(self.info.width);
+        let y = y.min(self.info.height);
+        let rect = Rect {
+            x,
+            y,
+            width: width.min(self.info.width - x),
+            height: height.min(self.info.height - y),
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+        let transfer = TransferToHost2D {
+            header: ControlHeader::new(CMD_TRANSFER_TO_HOST_2D),
+            rect,
+            offset: (y as u64 * self.info.stride as u64 + x as u64) * BYTES_PER_PIXEL as u64,
+            resource_id: RESOURCE_ID,
+            padding: 0,
+        };
+        let flush = ResourceFlush {
+            header: ControlHeader::new(CMD_RESOURCE_FLUSH),
+            rect,
+            resource_id: RESOURCE_ID,
+            padding: 0,
+        };
+        let mut inner = self.inner.lock();
+        for request in [as_bytes(&transfer), as_bytes(&flush)].iter() {
+            let mut header = ControlHeader::default();
+            let resp = Self::command(&self.transport, &mut inner, request, &mut header);
+            if !matches!(resp, Ok(RESP_OK_NODATA)) {
+                warn!("virtio-gpu: failed to flush: {:x?}", resp);
+                return;
+            }
+        }
+    }
+}
+
+impl Drop for GpuInner {
+    fn drop(&mut self) {
+        frame::dealloc(self.dma);
+    }
+}
@@ -0,0 +1,157 @@
+//! The MMIO transport, both the legacy (version 1) and the modern
+//! (version 2) register layouts.
+
+use {
+    super::{
+        Transport, VirtQueue, FEATURE_VERSION_1, STATUS_ACKNOWLEDGE, STATUS_DRIVER,
+        STATUS_DRIVER_OK, STATUS_FEATURES_OK,
+    },
+    crate::phys_to_virt,
+    kernel_hal::{IrqMode, PhysAddr, VirtAddr, PAGE_SIZE},
+};
+
+/// The magic value "virt".
+const MAGIC: u32 = 0x7472_6976;
+
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028;
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c;
+const REG_QUEUE_PFN: usize = 0x040;
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC: usize = 0x080;
+const REG_QUEUE_DRIVER: usize = 0x090;
+const REG_QUEUE_DEVICE: usize = 0x0a0;
+const REG_CONFIG: usize = 0x100;
+
+/// The registers of a virtio device on the MMIO transport.
+pub struct VirtioMmio {
+    base: VirtAddr,
+    version: u32,
+    /// The IRQ vector of the device.
+    vector: u32,
+}
+
+impl VirtioMmio {
+    /// Get the device at `paddr`, and its device ID.
+    ///
+    /// Return `None` if there is no device, or the slot is empty.
+    pub fn probe(paddr: PhysAddr, vector: u32) -> Option<(Self, u32)> {
+        let mmio = VirtioMmio {
+            base: phys_to_virt(paddr),
+            version: 0,
+            vector,
+        };
+        if mmio.read(REG_MAGIC) != MAGIC {
+            return None;
+        }
+        let version = mmio.read(REG_VERSION);
+        let device_id = mmio.read(REG_DEVICE_ID);
+        if device_id == 0 || !(1..=2).contains(&version) {
+            return None;
+        }
+        Some((VirtioMmio { version, ..mmio }, device_id))
+    }
+
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+}
+
+impl Transport for VirtioMmio {
+    fn vector(&self) -> u32 {
+        self.vector
+    }
+
+    fn irq_mode(&self) -> IrqMode {
+        IrqMode::EdgeHigh
+    }
+
+    fn config_read_u32(&self, offset: usize) -> u32 {
+        self.read(REG_CONFIG + offset)
+    }
+
+    fn begin_init(&self, supported: u64) -> Option<u64> {
+        self.write(REG_STATUS, 0);
+        self.write(REG_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        self.write(REG_DEVICE_FEATURES_SEL, 0);
+        let mut offered = self.read(REG_DEVICE_FEATURES) as u64;
+        self.write(REG_DEVICE_FEATURES_SEL, 1);
+        offered |= (self.read(REG_DEVICE_FEATURES) as u64) << 32;
+
+        let mut features = offered & supported;
+        if self.version == 2 {
+            features |= FEATURE_VERSION_1;
+        }
+        self.write(REG_DRIVER_FEATURES_SEL, 0);
+        self.write(REG_DRIVER_FEATURES, features as u32);
+        self.write(REG_DRIVER_FEATURES_SEL, 1);
+        self.write(REG_DRIVER_FEATURES, (features >> 32) as u32);
+        if self.version == 1 {
+            self.write(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+            return Some(features);
+        }
+        let status = STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK;
+        self.write(REG_STATUS, status);
+        if self.read(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+            return None;
+        }
+        Some(features)
+    }
+
+    fn finish_init(&self) {
+        let status = self.read(REG_STATUS);
+        self.write(REG_STATUS, status | STATUS_DRIVER_OK);
+    }
+
+    fn queue_max_size(&self, index: u16) -> u16 {
+        self.write(REG_QUEUE_SEL, index as u32);
+        self.read(REG_QUEUE_NUM_MAX).min(u16::MAX as u32) as u16
+    }
+
+    fn setup_queue(&self, index: u16, queue: &VirtQueue) {
+        self.write(REG_QUEUE_SEL, index as u32);
+        self.write(REG_QUEUE_NUM, queue.size() as u32);
+        if self.version == 1 {
+            self.write(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(REG_QUEUE_PFN, (queue.desc_paddr() / PAGE_SIZE) as u32);
+            return;
+        }
+        let regs = [
+            (REG_QUEUE_DESC, queue.desc_paddr()),
+            (REG_QUEUE_DRIVER, queue.avail_paddr()),
+            (REG_QUEUE_DEVICE, queue.used_paddr()),
+        ];
+        for &(reg, paddr) in regs.iter() {
+            self.write(reg, paddr as u32);
+            self.write(reg + 4, (paddr as u64 >> 32) as u32);
+        }
+        self.write(REG_QUEUE_READY, 1);
+    }
+
+    fn notify(&self, index: u16) {
+        self.write(REG_QUEUE_NOTIFY, index as u32);
+    }
+
+    fn ack_interrupt(&self) -> u32 {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        self.write(REG_INTERRUPT_ACK, status);
+        status
+    }
+}
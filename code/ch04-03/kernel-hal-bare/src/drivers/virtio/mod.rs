@@ -0,0 +1,139 @@
+//! The virtio devices, on the MMIO transport or the PCI transport.
+
+use {
+    super::{BLOCK_DEVICES, CONSOLE, DISPLAY, NET_DEVICES},
+    alloc::sync::Arc,
+    core::fmt::Display,
+    kernel_hal::{IrqMode, PciDeviceInfo, PhysAddr},
+};
+
+mod blk;
+mod console;
+mod gpu;
+mod mmio;
+mod net;
+mod pci;
+mod queue;
+
+pub use self::blk::VirtioBlk;
+pub use self::console::VirtioConsole;
+pub use self::gpu::VirtioGpu;
+pub use self::mmio::VirtioMmio;
+pub use self::net::VirtioNet;
+pub use self::pci::VirtioPci;
+pub use self::queue::VirtQueue;
+
+/// The device ID of the network devices.
+const DEVICE_NET: u32 = 1;
+/// The device ID of the block devices.
+const DEVICE_BLOCK: u32 = 2;
+/// The device ID of the consoles.
+const DEVICE_CONSOLE: u32 = 3;
+/// The device ID of the GPUs.
+const DEVICE_GPU: u32 = 16;
+
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+
+/// The feature of the modern devices, required on version 2 of the MMIO
+/// transport and on the PCI transport.
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// The transport of a virtio device, by which the driver negotiates the
+/// features, sets up the queues and is notified.
+pub trait Transport: Send + Sync + 'static {
+    /// The IRQ vector of the device.
+    fn vector(&self) -> u32;
+
+    /// The trigger mode of the IRQ.
+    fn irq_mode(&self) -> IrqMode;
+
+    /// Read the 32-bit field at `offset` of the device config space.
+    fn config_read_u32(&self, offset: usize) -> u32;
+
+    /// Read the 64-bit field at `offset` of the device config space.
+    fn config_read_u64(&self, offset: usize) -> u64 {
+        let low = self.config_read_u32(offset) as u64;
+        let high = self.config_read_u32(offset + 4) as u64;
+        high << 32 | low
+    }
+
+    /// Reset the device, and accept the features in `supported` the device
+    /// offers. Return the accepted features, or `None` if they are refused.
+    fn begin_init(&self, supported: u64) -> Option<u64>;
+
+    /// Tell the device the driver is ready.
+    fn finish_init(&self);
+
+    /// The maximum size of the queue `index`, 0 if it is not available.
+    fn queue_max_size(&self, index: u16) -> u16;
+
+    /// The size of the queue `index` to allocate, the largest power of two
+    /// not above `preferred` and the maximum. Return 0 if it is not available.
+    fn queue_size(&self, index: u16, preferred: u16) -> u16 {
+        let max = self.queue_max_size(index).min(preferred);
+        match max {
+            0 => 0,
+            _ => 1 << (15 - max.leading_zeros()),
+        }
+    }
+
+    /// Give the memory of `queue` to the device as the queue `index`.
+    fn setup_queue(&self, index: u16, queue: &VirtQueue);
+
+    /// Tell the device there are new buffers in the queue `index`.
+    fn notify(&self, index: u16);
+
+    /// Acknowledge the interrupt, return its causes.
+    fn ack_interrupt(&self) -> u32;
+}
+
+/// Probe the virtio device at `paddr`, and add it to the device lists if it
+/// is supported.
+pub fn probe_mmio(paddr: PhysAddr, vector: u32) {
+    if let Some((mmio, device_id)) = VirtioMmio::probe(paddr, vector) {
+        probe(mmio, device_id, format_args!("{:#x}", paddr));
+    }
+}
+
+/// Probe the virtio function on the PCI bus, and add it to the device lists
+/// if it is supported.
+pub fn probe_pci(info: &PciDeviceInfo) {
+    if let Some((pci, device_id)) = VirtioPci::probe(info) {
+        probe(pci, device_id, format_args!("{:x?}", info.addr));
+    }
+}
+
+/// Initialize the driver of the device on `transport`, the location of
+/// which is shown in the messages.
+fn probe<T: Transport>(transport: T, device_id: u32, location: impl Display) {
+    match device_id {
+        DEVICE_BLOCK => match VirtioBlk::new(transport) {
+            Some(blk) => BLOCK_DEVICES.write().push(Arc::new(blk)),
+            None => warn!("virtio-blk at {}: failed to initialize", location),
+        },
+        DEVICE_NET => match VirtioNet::new(transport) {
+            Some(net) => NET_DEVICES.write().push(net),
+            None => warn!("virtio-net at {}: failed to initialize", location),
+        },
+        // the UART is kept unless the command line selects the virtio console
+        DEVICE_CONSOLE if crate::cmdline_option("console") == Some("virtio") => {
+            if CONSOLE.read().is_some() {
+                return;
+            }
+            match VirtioConsole::new(transport) {
+                Some(console) => *CONSOLE.write() = Some(console),
+                None => warn!("virtio-console at {}: failed to initialize", location),
+            }
+        }
+        DEVICE_GPU => match VirtioGpu::new(transport) {
+            Some(gpu) => {
+                DISPLAY.write().get_or_insert(Arc::new(gpu));
+            }
+            None => warn!("virtio-gpu at {}: failed to initialize", location),
+        },
+        _ => debug!("virtio at {}: unsupported device {}", location, device_id),
+    }
+}
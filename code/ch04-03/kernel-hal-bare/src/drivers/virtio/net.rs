@@ -0,0 +1,170 @@
+//! The virtio network device.
+
+use {
+    super::{Transport, VirtQueue, FEATURE_VERSION_1},
+    crate::{drivers::NetDriver, frame},
+    alloc::{boxed::Box, sync::Arc, vec, vec::Vec},
+    kernel_hal::{HalError, NetDeviceInfo, NetRxCallback, PhysAddr, Result},
+    spin::Mutex,
+};
+
+/// The device has a MAC address in the config space.
+const FEATURE_MAC: u64 = 1 << 5;
+/// The device has the link status in the config space.
+const FEATURE_STATUS: u64 = 1 << 16;
+
+/// The link status: up.
+const STATUS_LINK_UP: u32 = 1;
+
+const QUEUE_RX: u16 = 0;
+const QUEUE_TX: u16 = 1;
+const QUEUE_SIZE: u16 = 16;
+
+const MTU: usize = 1500;
+/// The size of the buffers, with the header and the frame.
+const BUFFER_SIZE: usize = 2048;
+
+/// A virtio network device.
+///
+/// The received frames are passed to the callback in the interrupt, and
+/// each frame is sent by waiting for the completion by polling. The frames
+/// are copied through the DMA frames.
+pub struct VirtioNet<T: Transport> {
+    transport: T,
+    mac: [u8; 6],
+    has_status: bool,
+    /// The size of the header before each frame.
+    header_len: usize,
+    rx: Mutex<RxInner>,
+    tx: Mutex<TxInner>,
+    callback: Mutex<Option<NetRxCallback>>,
+}
+
+struct RxInner {
+    queue: VirtQueue,
+    /// The buffers given to the device, indexed by the descriptor.
+    buffers: Vec<PhysAddr>,
+}
+
+struct TxInner {
+    queue: VirtQueue,
+    buffer: PhysAddr,
+}
+
+impl<T: Transport> VirtioNet<T> {
+    /// Initialize the device and receive by the interrupt, return `None`
+    /// if it fails.
+    pub fn new(transport: T) -> Option<Arc<Self>> {
+        let features = transport.begin_init(FEATURE_MAC | FEATURE_STATUS)?;
+        let header_len = if features & FEATURE_VERSION_1 != 0 {
+            12
+        } else {
+            10
+        };
+        let mut mac = [0u8; 6];
+        if features & FEATURE_MAC != 0 {
+            let low = transport.config_read_u32(0).to_le_bytes();
+            let high = transport.config_read_u32(4).to_le_bytes();
+            mac[..4].copy_from_slice(&low);
+            mac[4..].copy_from_slice(&high[..2]);
+        }
+        let rx_size = transport.queue_size(QUEUE_RX, QUEUE_SIZE);
+        if rx_size == 0 || transport.queue_size(QUEUE_TX, 1) == 0 {
+            return None;
+        }
+        let mut rx_queue = VirtQueue::new(rx_size)?;
+        let tx_queue = VirtQueue::new(1)?;
+        let mut buffers = vec![0; rx_queue.size() as usize];
+        for _ in 0..rx_queue.size() {
+            let buffer = frame::alloc()?;
+            let head = rx_queue.add(&[(buffer, BUFFER_SIZE, true)])?;
+            buffers[head as usize] = buffer;
+        }
+        transport.setup_queue(QUEUE_RX, &rx_queue);
+        transport.setup_queue(QUEUE_TX, &tx_queue);
+        let net = Arc::new(VirtioNet {
+            has_status: features & FEATURE_STATUS != 0,
+            header_len,
+            mac,
+            rx: Mutex::new(RxInner {
+                queue: rx_queue,
+                buffers,
+            }),
+            tx: Mutex::new(TxInner {
+                queue: tx_queue,
+                buffer: frame::alloc()?,
+            }),
+            callback: Mutex::new(None),
+            transport,
+        });
+        let vector = net.transport.vector();
+        let handler = net.clone();
+        crate::irq_register(
+            vector,
+            net.transport.irq_mode(),
+            Box::new(move || handler.handle_irq()),
+        )
+        .ok()?;
+        crate::irq_enable(vector);
+        net.transport.finish_init();
+        net.transport.notify(QUEUE_RX);
+        info!("virtio-net: mac {:x?}, vector {:#x}", net.mac, vector);
+        Some(net)
+    }
+
+    /// Pass the received frames to the callback, and give the buffers back.
+    fn handle_irq(&self) {
+        self.transport.ack_interrupt();
+        let mut rx = self.rx.lock();
+        let mut frame = [0u8; BUFFER_SIZE];
+        let mut received = false;
+        while let Some((head, len)) = rx.queue.pop_used() {
+            let buffer = rx.buffers[head as usize];
+            let len = (len as usize).saturating_sub(self.header_len);
+            crate::pmem_read(buffer + self.header_len, &mut frame[..len]);
+            if let Some(callback) = self.callback.lock().as_ref() {
+                callback(&frame[..len]);
+            }
+            let head = rx.queue.add(&[(buffer, BUFFER_SIZE, true)]).unwrap();
+            rx.buffers[head as usize] = buffer;
+            received = true;
+        }
+        if received {
+            self.transport.notify(QUEUE_RX);
+        }
+    }
+}
+
+impl<T: Transport> NetDriver for VirtioNet<T> {
+    fn info(&self) -> NetDeviceInfo {
+        NetDeviceInfo {
+            mac: self.mac,
+            mtu: MTU,
+        }
+    }
+
+    fn link_up(&self) -> bool {
+        !self.has_status || (self.transport.config_read_u32(4) >> 16) & STATUS_LINK_UP != 0
+    }
+
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        if frame.len() > BUFFER_SIZE - self.header_len {
+            return Err(HalError);
+        }
+        let mut tx = self.tx.lock();
+        let buffer = tx.buffer;
+        crate::pmem_zero(buffer, self.header_len);
+        crate::pmem_write(buffer + self.header_len, frame);
+        let len = self.header_len + frame.len();
+        tx.queue.add(&[(buffer, len, false)]).ok_or(HalError)?;
+        self.transport.notify(QUEUE_TX);
+        while tx.queue.pop_used().is_none() {
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+
+    fn set_rx_callback(&self, callback: Option<NetRxCallback>) {
+        *self.callback.lock() = callback;
+    }
+}
@@ -0,0 +1,222 @@
+//! The PCI transport of the modern devices, with the structures found by
+//! the vendor capabilities, and the legacy interrupt.
+//!
+//! The transitional devices are driven by the modern interface as well.
+
+use {
+    super::{
+        Transport, VirtQueue, FEATURE_VERSION_1, STATUS_ACKNOWLEDGE, STATUS_DRIVER,
+        STATUS_DRIVER_OK, STATUS_FEATURES_OK,
+    },
+    crate::{drivers::pci, phys_to_virt},
+    alloc::vec::Vec,
+    core::ptr::{read_volatile, write_volatile},
+    kernel_hal::{IrqMode, PciBar, PciDeviceInfo, VirtAddr},
+};
+
+/// The capability of the vendor, describing a structure of the device.
+const CAPABILITY_VENDOR: u32 = 0x09;
+
+// types of the structures
+const CFG_COMMON: u32 = 1;
+const CFG_NOTIFY: u32 = 2;
+const CFG_ISR: u32 = 3;
+const CFG_DEVICE: u32 = 4;
+
+// offsets in the capability
+const CAP_BAR: usize = 4;
+const CAP_OFFSET: usize = 8;
+const CAP_NOTIFY_MULTIPLIER: usize = 16;
+
+// offsets in the common structure
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0c;
+const COMMON_NUM_QUEUES: usize = 0x12;
+const COMMON_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_ENABLE: usize = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+/// The device IDs of the modern devices, from the device type 0.
+const DEVICE_ID_MODERN: u16 = 0x1040;
+const DEVICE_ID_MODERN_END: u16 = 0x107f;
+
+/// The structures of a virtio device on the PCI transport.
+pub struct VirtioPci {
+    common: VirtAddr,
+    isr: VirtAddr,
+    device: VirtAddr,
+    /// The notification addresses of the queues.
+    notify: Vec<VirtAddr>,
+    /// The IRQ vector of the legacy interrupt.
+    vector: u32,
+}
+
+impl VirtioPci {
+    /// Get the structures of the function, and its device ID.
+    ///
+    /// Return `None` if it is not a virtio device of the modern interface,
+    /// or its legacy interrupt is not routed.
+    pub fn probe(info: &PciDeviceInfo) -> Option<(Self, u32)> {
+        let device_id = match info.device_id {
+            id if (DEVICE_ID_MODERN..=DEVICE_ID_MODERN_END).contains(&id) => {
+                (id - DEVICE_ID_MODERN) as u32
+            }
+            // the transitional devices
+            0x1000 => 1,
+            0x1001 => 2,
+            0x1003 => 3,
+            _ => return None,
+        };
+        let vector = info.legacy_irq?;
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut multiplier = 0;
+        for cap in pci::find_capabilities(info.addr, CAPABILITY_VENDOR) {
+            let cfg_type = pci::read32(info.addr, cap) >> 24;
+            let bar = pci::read32(info.addr, cap + CAP_BAR) as u8 as usize;
+            let offset = pci::read32(info.addr, cap + CAP_OFFSET) as usize;
+            let vaddr = match info.bars.get(bar) {
+                Some(Some(PciBar::Mmio { addr, .. })) => phys_to_virt(addr + offset),
+                _ => continue,
+            };
+            // the first structure of each type is used
+            let slot = match cfg_type {
+                CFG_COMMON => &mut common,
+                CFG_NOTIFY if notify.is_none() => {
+                    multiplier = pci::read32(info.addr, cap + CAP_NOTIFY_MULTIPLIER) as usize;
+                    &mut notify
+                }
+                CFG_ISR => &mut isr,
+                CFG_DEVICE => &mut device,
+                _ => continue,
+            };
+            slot.get_or_insert(vaddr);
+        }
+        let mut pci = VirtioPci {
+            common: common?,
+            isr: isr?,
+            device: device?,
+            notify: Vec::new(),
+            vector,
+        };
+        let notify = notify?;
+        let num_queues = pci.read_u16(COMMON_NUM_QUEUES);
+        pci.notify = (0..num_queues)
+            .map(|index| {
+                pci.write_u16(COMMON_QUEUE_SELECT, index);
+                notify + pci.read_u16(COMMON_QUEUE_NOTIFY_OFF) as usize * multiplier
+            })
+            .collect();
+        pci::enable_bus_master(info.addr);
+        Some((pci, device_id))
+    }
+
+    fn read_u8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.common + offset) as *const u8) }
+    }
+
+    fn write_u8(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.common + offset) as *mut u8, value) }
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile((self.common + offset) as *const u16) }
+    }
+
+    fn write_u16(&self, offset: usize, value: u16) {
+        unsafe { write_volatile((self.common + offset) as *mut u16, value) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.common + offset) as *const u32) }
+    }
+
+    fn write_u32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.common + offset) as *mut u32, value) }
+    }
+
+    fn write_u64(&self, offset: usize, value: u64) {
+        self.write_u32(offset, value as u32);
+        self.write_u32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+impl Transport for VirtioPci {
+    fn vector(&self) -> u32 {
+        self.vector
+    }
+
+    fn irq_mode(&self) -> IrqMode {
+        IrqMode::LevelLow
+    }
+
+    fn config_read_u32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.device + offset) as *const u32) }
+    }
+
+    fn begin_init(&self, supported: u64) -> Option<u64> {
+        // the reset is done when the status is read as 0
+        self.write_u8(COMMON_STATUS, 0);
+        while self.read_u8(COMMON_STATUS) != 0 {
+            core::hint::spin_loop();
+        }
+        let status = (STATUS_ACKNOWLEDGE | STATUS_DRIVER) as u8;
+        self.write_u8(COMMON_STATUS, status);
+        self.write_u32(COMMON_DEVICE_FEATURE_SELECT, 0);
+        let mut offered = self.read_u32(COMMON_DEVICE_FEATURE) as u64;
+        self.write_u32(COMMON_DEVICE_FEATURE_SELECT, 1);
+        offered |= (self.read_u32(COMMON_DEVICE_FEATURE) as u64) << 32;
+        if offered & FEATURE_VERSION_1 == 0 {
+            return None;
+        }
+
+        let features = offered & supported | FEATURE_VERSION_1;
+        self.write_u32(COMMON_DRIVER_FEATURE_SELECT, 0);
+        self.write_u32(COMMON_DRIVER_FEATURE, features as u32);
+        self.write_u32(COMMON_DRIVER_FEATURE_SELECT, 1);
+        self.write_u32(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+        let status = status | STATUS_FEATURES_OK as u8;
+        self.write_u8(COMMON_STATUS, status);
+        if self.read_u8(COMMON_STATUS) & STATUS_FEATURES_OK as u8 == 0 {
+            return None;
+        }
+        Some(features)
+    }
+
+    fn finish_init(&self) {
+        let status = self.read_u8(COMMON_STATUS);
+        self.write_u8(COMMON_STATUS, status | STATUS_DRIVER_OK as u8);
+    }
+
+    fn queue_max_size(&self, index: u16) -> u16 {
+        if index as usize >= self.notify.len() {
+            return 0;
+        }
+        self.write_u16(COMMON_QUEUE_SELECT, index);
+        self.read_u16(COMMON_QUEUE_SIZE)
+    }
+
+    fn setup_queue(&self, index: u16, queue: &VirtQueue) {
+        self.write_u16(COMMON_QUEUE_SELECT, index);
+        self.write_u16(COMMON_QUEUE_SIZE, queue.size());
+        self.write_u64(COMMON_QUEUE_DESC, queue.desc_paddr() as u64);
+        self.write_u64(COMMON_QUEUE_DRIVER, queue.avail_paddr() as u64);
+        self.write_u64(COMMON_QUEUE_DEVICE, queue.used_paddr() as u64);
+        self.write_u16(COMMON_QUEUE_ENABLE, 1);
+    }
+
+    fn notify(&self, index: u16) {
+        unsafe { write_volatile(self.notify[index as usize] as *mut u16, index) }
+    }
+
+    fn ack_interrupt(&self) -> u32 {
+        // the ISR status is cleared by reading
+        unsafe { read_volatile(self.isr as *const u8) as u32 }
+    }
+}
@@ -0,0 +1,180 @@
+//! The split virtqueue, in the legacy layout also accepted by the modern
+//! devices.
+
+use {
+    crate::{frame, phys_to_virt},
+    core::{
+        mem::size_of,
+        sync::atomic::{fence, Ordering},
+    },
+    kernel_hal::{PhysAddr, PAGE_SIZE},
+};
+
+/// The descriptor continues via the `next` field.
+const DESC_F_NEXT: u16 = 1;
+/// The buffer is written by the device.
+const DESC_F_WRITE: u16 = 2;
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+/// A virtqueue in contiguous frames: the descriptor table, the available
+/// ring, and the used ring at the next page.
+pub struct VirtQueue {
+    paddr: PhysAddr,
+    pages: usize,
+    size: u16,
+    /// The head of the free descriptors, chained by `next`.
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+impl VirtQueue {
+    /// Allocate a queue of `size` descriptors, which is a power of two.
+    pub fn new(size: u16) -> Option<Self> {
+        assert!(size.is_power_of_two());
+        let used_offset = align_up(size as usize * (size_of::<Descriptor>() + 2) + 6);
+        let pages = (used_offset + align_up(size as usize * size_of::<UsedElem>() + 6)) / PAGE_SIZE;
+        let paddr = frame::alloc_contiguous(pages, 0)?;
+        crate::pmem_zero(paddr, pages * PAGE_SIZE);
+        let queue = VirtQueue {
+            paddr,
+            pages,
+            size,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            unsafe { (*queue.desc(i)).next = i + 1 };
+        }
+        Some(queue)
+    }
+
+    /// The number of descriptors.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_paddr(&self) -> PhysAddr {
+        self.paddr
+    }
+
+    pub fn avail_paddr(&self) -> PhysAddr {
+        self.paddr + self.size as usize * size_of::<Descriptor>()
+    }
+
+    pub fn used_paddr(&self) -> PhysAddr {
+        self.paddr + align_up(self.avail_paddr() - self.paddr + self.size as usize * 2 + 6)
+    }
+
+    fn desc(&self, index: u16) -> *mut Descriptor {
+        (phys_to_virt(self.paddr) as *mut Descriptor).wrapping_add(index as usize)
+    }
+
+    /// The `idx` field of the available ring, and the `ring` entry `slot`.
+    fn avail(&self, slot: Option<u16>) -> *mut u16 {
+        let ring = phys_to_virt(self.avail_paddr()) as *mut u16;
+        match slot {
+            Some(slot) => ring.wrapping_add(2 + slot as usize),
+            None => ring.wrapping_add(1),
+        }
+    }
+
+    fn used_idx(&self) -> *const u16 {
+        (phys_to_virt(self.used_paddr()) as *const u16).wrapping_add(1)
+    }
+
+    fn used_elem(&self, slot: u16) -> *const UsedElem {
+        (phys_to_virt(self.used_paddr() + 4) as *const UsedElem).wrapping_add(slot as usize)
+    }
+
+    /// Add a chain of the buffers `(paddr, len, writable)` to the available
+    /// ring, where the `writable` ones are written by the device and follow
+    /// the others. Return the head descriptor, or `None` if the queue is full.
+    pub fn add(&mut self, buffers: &[(PhysAddr, usize, bool)]) -> Option<u16> {
+        if buffers.is_empty() || buffers.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free_head;
+        let mut index = head;
+        for (i, &(paddr, len, writable)) in buffers.iter().enumerate() {
+            let desc = self.desc(index);
+            let mut flags = if writable { DESC_F_WRITE } else { 0 };
+            if i + 1 < buffers.len() {
+                flags |= DESC_F_NEXT;
+            }
+            unsafe {
+                (*desc).addr = paddr as u64;
+                (*desc).len = len as u32;
+                (*desc).flags = flags;
+                index = (*desc).next;
+            }
+        }
+        self.free_head = index;
+        self.num_free -= buffers.len() as u16;
+
+        let slot = self.avail_idx & (self.size - 1);
+        unsafe { self.avail(Some(slot)).write_volatile(head) };
+        // the entry is visible before the index
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { self.avail(None).write_volatile(self.avail_idx) };
+        fence(Ordering::SeqCst);
+        Some(head)
+    }
+
+    /// Take a chain used by the device, return its head descriptor and the
+    /// number of bytes written.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        fence(Ordering::SeqCst);
+        if unsafe { self.used_idx().read_volatile() } == self.last_used_idx {
+            return None;
+        }
+        let slot = self.last_used_idx & (self.size - 1);
+        let elem = unsafe { self.used_elem(slot).read_volatile() };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        // put the chain back to the free list
+        let head = elem.id as u16;
+        let mut index = head;
+        loop {
+            self.num_free += 1;
+            let desc = unsafe { &mut *self.desc(index) };
+            if desc.flags & DESC_F_NEXT == 0 {
+                desc.next = self.free_head;
+                break;
+            }
+            index = desc.next;
+        }
+        self.free_head = head;
+        Some((head, elem.len))
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        for i in 0..self.pages {
+            frame::dealloc(self.paddr + i * PAGE_SIZE);
+        }
+    }
+}
+
+fn align_up(len: usize) -> usize {
+    (len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
@@ -0,0 +1,207 @@
+//! A FIFO executor of the tasks on each CPU.
+//!
+//! Each CPU has its own run queue, where the tasks woken on it are pushed.
+//! An idle CPU steals the tasks from the back of the others' queues, or
+//! waits for its next interrupt. The tasks are woken by the interrupt
+//! handlers, so the run queues are only locked with interrupts disabled.
+//!
+//! The timer and console interrupts only mark the timers or the console
+//! input to be checked, and their callbacks are called by the executor, out
+//! of the interrupt context.
+//!
+//! A task woken on a CPU out of its affinity is pushed to the first online
+//! CPU in it instead, and is never stolen by the CPUs out of it.
+
+use {
+    super::{arch, smp},
+    alloc::{boxed::Box, collections::VecDeque, sync::Arc, task::Wake},
+    core::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+        task::{Context, Poll, Waker},
+    },
+    kernel_hal::CpuAffinity,
+    lazy_static::lazy_static,
+    spin::Mutex,
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    /// Whether the task is in the run queue.
+    queued: AtomicBool,
+    tid: AtomicU64,
+    pid: AtomicU64,
+    affinity: AtomicU64,
+}
+
+impl Task {
+    /// Whether the task may run on `cpu`.
+    fn allows(&self, cpu: usize) -> bool {
+        self.affinity.load(Ordering::Relaxed) & (1 << cpu) != 0
+    }
+
+    /// The CPU to run the task woken on `cpu`, itself if it is in the
+    /// affinity.
+    fn target_cpu(&self, cpu: usize) -> usize {
+        if self.allows(cpu) {
+            return cpu;
+        }
+        (0..smp::cpu_count() as usize)
+            .find(|&i| self.allows(i))
+            .unwrap_or(cpu)
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            arch::without_interrupts(|| {
+                let cpu = self.target_cpu(arch::cpu_id() as usize);
+                CPUS[cpu].run_queue.lock().push_back(self)
+            });
+        }
+    }
+}
+
+#[derive(Default)]
+struct Cpu {
+    run_queue: Mutex<VecDeque<Arc<Task>>>,
+    /// The task being polled.
+    current: Mutex<Option<Arc<Task>>>,
+}
+
+lazy_static! {
+    static ref CPUS: Vec<Cpu> = (0..smp::MAX_CPUS).map(|_| Cpu::default()).collect();
+}
+
+/// Whether the timer interrupt is taken since the timers are last checked.
+static TIMER_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Whether some input of the console is received since the waiters of the
+/// console are last called back.
+static SERIAL_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Mark the timers to be checked by the executor, in the timer interrupt.
+pub fn timer_interrupt() {
+    TIMER_PENDING.store(true, Ordering::Release);
+}
+
+/// Mark the waiters of the console to be called back by the executor, in the
+/// console interrupt.
+pub fn serial_interrupt() {
+    SERIAL_PENDING.store(true, Ordering::Release);
+}
+
+/// Call the callbacks of the timers and the console marked by the
+/// interrupts.
+fn handle_pending() {
+    if TIMER_PENDING.swap(false, Ordering::AcqRel) {
+        kernel_hal::timer_tick();
+    }
+    if SERIAL_PENDING.swap(false, Ordering::AcqRel) {
+        // the queue is also locked in the console interrupt
+        let callbacks = arch::without_interrupts(kernel_hal::serial_take_callbacks);
+        for callback in callbacks {
+            callback();
+        }
+    }
+}
+
+/// Whether some callbacks are marked by the interrupts.
+fn has_pending() -> bool {
+    TIMER_PENDING.load(Ordering::Acquire) || SERIAL_PENDING.load(Ordering::Acquire)
+}
+
+fn this_cpu() -> &'static Cpu {
+    &CPUS[arch::cpu_id() as usize]
+}
+
+/// Add a new task to the run queue of the current CPU, or the first one in
+/// its `affinity`.
+pub fn spawn(future: BoxFuture, affinity: CpuAffinity) {
+    let task = Arc::new(Task {
+        future: Mutex::new(Some(future)),
+        queued: AtomicBool::new(false),
+        tid: AtomicU64::new(0),
+        pid: AtomicU64::new(0),
+        affinity: AtomicU64::new(affinity),
+    });
+    task.wake();
+}
+
+/// Run the tasks on the current CPU forever, and wait for interrupts when
+/// there is no task to run.
+pub fn run() -> ! {
+    let cpu = arch::cpu_id() as usize;
+    loop {
+        handle_pending();
+        let task = arch::without_interrupts(|| pop(cpu));
+        let task = match task {
+            Some(task) => task,
+            None => {
+                if !has_pending() {
+                    arch::wait_for_interrupt();
+                }
+                continue;
+            }
+        };
+        task.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.clone());
+        let mut cx = Context::from_waker(&waker);
+        *CPUS[cpu].current.lock() = Some(task.clone());
+        // a task woken while being polled may be taken by another CPU, which
+        // waits here until the poll is done
+        let mut future = task.future.lock();
+        if let Some(fut) = future.as_mut() {
+            if let Poll::Ready(()) = fut.as_mut().poll(&mut cx) {
+                *future = None;
+            }
+        }
+        drop(future);
+        *CPUS[cpu].current.lock() = None;
+    }
+}
+
+/// Take the task at the front of the run queue of `cpu`, or steal the last
+/// one allowed on `cpu` in another CPU's.
+fn pop(cpu: usize) -> Option<Arc<Task>> {
+    if let Some(task) = CPUS[cpu].run_queue.lock().pop_front() {
+        return Some(task);
+    }
+    let count = smp::cpu_count() as usize;
+    (1..count).find_map(|i| {
+        let mut queue = CPUS[(cpu + i) % count].run_queue.lock();
+        let index = queue.iter().rposition(|task| task.allows(cpu))?;
+        queue.remove(index)
+    })
+}
+
+/// Set the affinity of the current task, which takes effect the next time it
+/// is woken.
+pub fn set_current_affinity(affinity: CpuAffinity) {
+    if let Some(task) = this_cpu().current.lock().as_ref() {
+        task.affinity.store(affinity, Ordering::Relaxed);
+    }
+}
+
+/// Set the tid and pid of the current task.
+pub fn set_current_tid(tid: u64, pid: u64) {
+    if let Some(task) = this_cpu().current.lock().as_ref() {
+        task.tid.store(tid, Ordering::Relaxed);
+        task.pid.store(pid, Ordering::Relaxed);
+    }
+}
+
+/// Get the tid and pid of the current task, or 0 outside the tasks.
+pub fn current_tid() -> (u64, u64) {
+    match this_cpu().current.lock().as_ref() {
+        Some(task) => (
+            task.tid.load(Ordering::Relaxed),
+            task.pid.load(Ordering::Relaxed),
+        ),
+        None => (0, 0),
+    }
+}
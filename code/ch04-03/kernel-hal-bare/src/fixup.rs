@@ -0,0 +1,128 @@
+//! Recovery from faults when copying between user and kernel.
+//!
+//! The instructions accessing the user memory are listed in the section
+//! `ex_table` with the addresses to resume at if they fault, like the
+//! exception table of Linux. The trap handler moves the faulting instruction
+//! pointer to the fixup, which makes the copy return an error.
+
+use kernel_hal::{HalError, Result};
+
+#[cfg(target_arch = "x86_64")]
+global_asm!(
+    ".text",
+    ".global zcore_user_copy",
+    "zcore_user_copy:",
+    "    mov %rdx, %rcx",
+    "zcore_user_copy_insn:",
+    "    rep movsb",
+    "    xor %eax, %eax",
+    "    ret",
+    "zcore_user_copy_fixup:",
+    "    mov $1, %eax",
+    "    ret",
+    ".pushsection ex_table, \"a\"",
+    ".balign 8",
+    ".quad zcore_user_copy_insn, zcore_user_copy_fixup",
+    ".popsection",
+    options(att_syntax)
+);
+
+// the user memory is only accessible with `SUM` set in `sstatus`
+#[cfg(target_arch = "riscv64")]
+global_asm!(
+    ".text",
+    ".global zcore_user_copy",
+    "zcore_user_copy:",
+    "    li t1, 1 << 18",
+    "    csrs sstatus, t1",
+    "    beqz a2, zcore_user_copy_done",
+    "zcore_user_copy_load:",
+    "    lb t0, 0(a1)",
+    "zcore_user_copy_store:",
+    "    sb t0, 0(a0)",
+    "    addi a0, a0, 1",
+    "    addi a1, a1, 1",
+    "    addi a2, a2, -1",
+    "    bnez a2, zcore_user_copy_load",
+    "zcore_user_copy_done:",
+    "    csrc sstatus, t1",
+    "    li a0, 0",
+    "    ret",
+    "zcore_user_copy_fixup:",
+    "    li t1, 1 << 18",
+    "    csrc sstatus, t1",
+    "    li a0, 1",
+    "    ret",
+    ".pushsection ex_table, \"a\"",
+    ".balign 8",
+    ".dword zcore_user_copy_load, zcore_user_copy_fixup",
+    ".dword zcore_user_copy_store, zcore_user_copy_fixup",
+    ".popsection",
+);
+
+#[cfg(target_arch = "aarch64")]
+global_asm!(
+    ".text",
+    ".global zcore_user_copy",
+    "zcore_user_copy:",
+    "    cbz x2, zcore_user_copy_done",
+    "zcore_user_copy_load:",
+    "    ldrb w3, [x1], #1",
+    "zcore_user_copy_store:",
+    "    strb w3, [x0], #1",
+    "    subs x2, x2, #1",
+    "    b.ne zcore_user_copy_load",
+    "zcore_user_copy_done:",
+    "    mov x0, #0",
+    "    ret",
+    "zcore_user_copy_fixup:",
+    "    mov x0, #1",
+    "    ret",
+    ".pushsection ex_table, \"a\"",
+    ".balign 8",
+    ".quad zcore_user_copy_load, zcore_user_copy_fixup",
+    ".quad zcore_user_copy_store, zcore_user_copy_fixup",
+    ".popsection",
+);
+
+/// An entry of the exception table.
+#[repr(C)]
+struct ExceptionEntry {
+    /// The address of the instruction which may fault.
+    insn: usize,
+    /// The address to resume at if it faults.
+    fixup: usize,
+}
+
+extern "C" {
+    fn zcore_user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    // defined by the linker around the section
+    static __start_ex_table: ExceptionEntry;
+    static __stop_ex_table: ExceptionEntry;
+}
+
+/// Copy `len` bytes from `src` to `dst`, return `Err` if the copy faults.
+///
+/// # Safety
+///
+/// The kernel side of the copy must be valid for `len` bytes.
+pub unsafe fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+    match zcore_user_copy(dst, src, len) {
+        0 => Ok(()),
+        _ => Err(HalError),
+    }
+}
+
+/// Get the address to resume at after a fault in the kernel at `pc`, or
+/// `None` if the fault is not recoverable.
+pub fn fixup(pc: usize) -> Option<usize> {
+    let table = unsafe {
+        let start = &__start_ex_table as *const ExceptionEntry;
+        let end = &__stop_ex_table as *const ExceptionEntry;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+    table
+        .iter()
+        .find(|entry| entry.insn == pc)
+        .map(|entry| entry.fixup)
+}
@@ -0,0 +1,156 @@
+//! The physical frame allocator.
+//!
+//! Frames are taken from the RAM regions in order, and the freed ones are
+//! recycled. Contiguous runs are only taken from the untouched part of the
+//! regions.
+
+use {
+    super::arch,
+    alloc::vec::Vec,
+    core::ops::Range,
+    kernel_hal::{FrameStats, MemoryRegion, MemoryRegionKind, PhysAddr, PAGE_SIZE},
+    lazy_static::lazy_static,
+    spin::{Mutex, Once},
+};
+
+#[derive(Default)]
+struct FrameAllocator {
+    /// The untouched parts of the RAM regions.
+    regions: Vec<Range<PhysAddr>>,
+    /// The frames freed.
+    recycled: Vec<PhysAddr>,
+    total: usize,
+    allocated: usize,
+}
+
+lazy_static! {
+    static ref FRAMES: Mutex<FrameAllocator> = Mutex::new(FrameAllocator::default());
+}
+
+/// The frame always filled with zero.
+static ZERO_FRAME: Once<PhysAddr> = Once::new();
+
+/// Add the RAM regions in `memory_map` to the allocator.
+pub fn init(memory_map: &[MemoryRegion]) {
+    let mut frames = FRAMES.lock();
+    for region in memory_map {
+        if region.kind != MemoryRegionKind::Ram {
+            continue;
+        }
+        // the frame at 0 is never used, so that 0 is not a valid frame
+        let start = align_up(region.addr.max(PAGE_SIZE), PAGE_SIZE);
+        let end = (region.addr + region.size) & !(PAGE_SIZE - 1);
+        if start < end {
+            frames.total += (end - start) / PAGE_SIZE;
+            frames.regions.push(start..end);
+        }
+    }
+    drop(frames);
+    ZERO_FRAME.call_once(|| {
+        let paddr = alloc().expect("failed to allocate the zero frame");
+        super::pmem_zero(paddr, PAGE_SIZE);
+        paddr
+    });
+    info!("frame allocator: {} frames", stats().total);
+}
+
+pub fn alloc() -> Option<PhysAddr> {
+    arch::without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        let paddr = match frames.recycled.pop() {
+            Some(paddr) => paddr,
+            None => frames.take(1, 0)?,
+        };
+        frames.allocated += 1;
+        Some(paddr)
+    })
+}
+
+/// Allocate `size` contiguous frames aligned to `2^align_log2` frames.
+pub fn alloc_contiguous(size: usize, align_log2: usize) -> Option<PhysAddr> {
+    arch::without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        let paddr = frames.take(size, align_log2)?;
+        frames.allocated += size;
+        Some(paddr)
+    })
+}
+
+/// Allocate a frame below `end`, for the code and the data addressed in the
+/// real mode.
+#[cfg(target_arch = "x86_64")]
+pub fn alloc_below(end: PhysAddr) -> Option<PhysAddr> {
+    arch::without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        let paddr = match frames.recycled.iter().position(|&paddr| paddr < end) {
+            Some(i) => frames.recycled.swap_remove(i),
+            None => {
+                let index = frames
+                    .regions
+                    .iter()
+                    .position(|region| region.start + PAGE_SIZE <= end)?;
+                frames.take_from(index, PAGE_SIZE)
+            }
+        };
+        frames.allocated += 1;
+        Some(paddr)
+    })
+}
+
+pub fn dealloc(paddr: PhysAddr) {
+    arch::without_interrupts(|| {
+        let mut frames = FRAMES.lock();
+        frames.recycled.push(paddr);
+        frames.allocated -= 1;
+    })
+}
+
+pub fn zero_frame() -> PhysAddr {
+    *ZERO_FRAME
+        .get()
+        .expect("frame allocator is not initialized")
+}
+
+pub fn stats() -> FrameStats {
+    let frames = FRAMES.lock();
+    FrameStats {
+        total: frames.total,
+        free: frames.total - frames.allocated,
+    }
+}
+
+impl FrameAllocator {
+    /// Take `size` frames from the first region with room for them. The
+    /// frames skipped for the alignment are recycled.
+    fn take(&mut self, size: usize, align_log2: usize) -> Option<PhysAddr> {
+        let align = PAGE_SIZE << align_log2;
+        let len = size * PAGE_SIZE;
+        let (index, start) = self.regions.iter().enumerate().find_map(|(i, region)| {
+            let start = align_up(region.start, align);
+            if start + len <= region.end {
+                Some((i, start))
+            } else {
+                None
+            }
+        })?;
+        let skipped = self.regions[index].start..start;
+        self.recycled.extend(skipped.step_by(PAGE_SIZE));
+        self.regions[index].start = start;
+        Some(self.take_from(index, len))
+    }
+
+    /// Take `len` bytes from the start of the region `index`.
+    fn take_from(&mut self, index: usize, len: usize) -> PhysAddr {
+        let region = &mut self.regions[index];
+        let start = region.start;
+        region.start += len;
+        if region.start == region.end {
+            self.regions.remove(index);
+        }
+        start
+    }
+}
+
+fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
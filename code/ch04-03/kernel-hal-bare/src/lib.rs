@@ -0,0 +1,782 @@
+//! Kernel HAL implementation for bare metal environment.
+//!
+//! The kernel should provide the global allocator, call [`init`] with the
+//! information from the bootloader, then run the tasks by [`run`].
+
+#![no_std]
+#![feature(asm)]
+#![feature(global_asm)]
+#![deny(warnings)]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use {
+    alloc::{boxed::Box, string::String, vec, vec::Vec},
+    core::{
+        convert::TryInto,
+        fmt::{Debug, Formatter},
+        future::Future,
+        ops::Range,
+        pin::Pin,
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+    spin::Once,
+};
+
+pub use kernel_hal::{defs::*, *};
+
+#[cfg(target_arch = "x86_64")]
+#[path = "arch/x86_64/mod.rs"]
+mod arch;
+#[cfg(target_arch = "riscv64")]
+#[path = "arch/riscv64/mod.rs"]
+mod arch;
+#[cfg(target_arch = "aarch64")]
+#[path = "arch/aarch64/mod.rs"]
+mod arch;
+mod drivers;
+mod executor;
+mod fixup;
+mod frame;
+mod smp;
+
+// named explicitly to shadow the functions of `kernel_hal`
+pub use self::arch::{
+    cache_line_size, context_run, cpu_features, frame_flush, irq_disable, irq_enable, irq_handle,
+    irq_range, irq_register, irq_unregister, serial, timer_ticks, timer_ticks_per_second,
+    tlb_flush, PageTable,
+};
+#[cfg(target_arch = "x86_64")]
+pub use self::arch::acpi_tables;
+pub use self::executor::run;
+
+/// Configuration of the HAL, given by the bootloader.
+#[derive(Debug)]
+pub struct Config {
+    /// The offset of the linear mapping of the physical memory in the kernel
+    /// address space. It should also cover the MMIO regions of the platform.
+    pub phys_offset: usize,
+    /// The physical memory map. The RAM regions not used by the kernel image
+    /// and the bootloader are managed by the frame allocator.
+    pub memory_map: Vec<MemoryRegion>,
+    /// The physical address of the ACPI RSDP given by UEFI. It is searched
+    /// in the BIOS area if `None`. Only used on x86_64.
+    pub acpi_rsdp: Option<PhysAddr>,
+    /// The kernel command line, the options separated by spaces.
+    ///
+    /// The HAL recognizes `console=virtio`, to use the virtio console
+    /// instead of the UART once it is found.
+    pub cmdline: String,
+    /// The physical address of a region of `CRASHLOG_REGION_SIZE` bytes,
+    /// reserved by the bootloader to keep the crashlog across warm reboots.
+    /// It must not be in a RAM region of the memory map. Nothing is persisted
+    /// if `None`.
+    pub crashlog_region: Option<PhysAddr>,
+}
+
+static CONFIG: Once<Config> = Once::new();
+
+/// Initialize the HAL on the boot CPU, and start the secondary CPUs.
+pub fn init(config: Config) {
+    kernel_hal::set_hal(&BareHal);
+    let config = CONFIG.call_once(|| config);
+    frame::init(&config.memory_map);
+    arch::init();
+    smp::init();
+    drivers::init();
+    info!("HAL initialized");
+}
+
+/// The HAL of the bare metal environment.
+pub struct BareHal;
+
+impl Hal for BareHal {
+    fn thread_spawn(
+        &self,
+        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+        vmtoken: usize,
+        affinity: CpuAffinity,
+    ) {
+        Thread::spawn(future, vmtoken, affinity);
+    }
+
+    fn thread_set_affinity(&self, affinity: CpuAffinity) {
+        Thread::set_affinity(affinity)
+    }
+
+    fn thread_set_tid(&self, tid: u64, pid: u64) {
+        Thread::set_tid(tid, pid)
+    }
+
+    fn thread_get_tid(&self) -> (u64, u64) {
+        Thread::get_tid()
+    }
+
+    fn timer_now(&self) -> Duration {
+        timer_now()
+    }
+
+    fn rtc_now(&self) -> Option<Duration> {
+        rtc_now()
+    }
+
+    fn timer_ticks(&self) -> u64 {
+        timer_ticks()
+    }
+
+    fn timer_ticks_per_second(&self) -> u64 {
+        timer_ticks_per_second()
+    }
+
+    fn timer_set(
+        &self,
+        deadline: Duration,
+        callback: Box<dyn FnOnce(Duration) + Send + Sync>,
+    ) -> u64 {
+        timer_set(deadline, callback)
+    }
+
+    fn timer_cancel(&self, id: u64) {
+        timer_cancel(id)
+    }
+
+    fn irq_register(&self, vector: u32, mode: IrqMode, handler: IrqHandler) -> Result<()> {
+        irq_register(vector, mode, handler)
+    }
+
+    fn irq_unregister(&self, vector: u32) -> Result<()> {
+        irq_unregister(vector)
+    }
+
+    fn irq_enable(&self, vector: u32) {
+        irq_enable(vector)
+    }
+
+    fn irq_disable(&self, vector: u32) {
+        irq_disable(vector)
+    }
+
+    fn irq_handle(&self, vector: u32) -> bool {
+        irq_handle(vector)
+    }
+
+    fn irq_range(&self) -> Range<u32> {
+        irq_range()
+    }
+
+    fn memory_map(&self) -> Vec<MemoryRegion> {
+        memory_map()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn acpi_tables(&self) -> Vec<AcpiTable> {
+        acpi_tables()
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn ioport_set_access(&self, _port: u16, _len: u32, _enable: bool) -> Result<()> {
+        // the bitmap of the process is loaded before it runs
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn ioport_load_bitmap(&self, bitmap: &[u64]) {
+        arch::ioport_load_bitmap(bitmap)
+    }
+
+    fn cpu_count(&self) -> u32 {
+        cpu_count()
+    }
+
+    fn cpu_id(&self) -> u32 {
+        cpu_id()
+    }
+
+    fn cache_line_size(&self) -> u32 {
+        cache_line_size()
+    }
+
+    fn cpu_features(&self) -> vdso::Features {
+        cpu_features()
+    }
+
+    fn pci_enumerate(&self) -> Vec<PciDeviceInfo> {
+        pci_enumerate()
+    }
+
+    fn pci_config_read(&self, addr: PciAddr, offset: usize, width: usize) -> Result<u32> {
+        pci_config_read(addr, offset, width)
+    }
+
+    fn pci_config_write(
+        &self,
+        addr: PciAddr,
+        offset: usize,
+        width: usize,
+        value: u32,
+    ) -> Result<()> {
+        pci_config_write(addr, offset, width, value)
+    }
+
+    fn pci_msi_alloc(&self, addr: PciAddr, count: u32) -> Result<u32> {
+        pci_msi_alloc(addr, count)
+    }
+
+    fn pci_msi_free(&self, addr: PciAddr) {
+        pci_msi_free(addr)
+    }
+
+    fn block_devices(&self) -> Vec<BlockDeviceInfo> {
+        block_devices()
+    }
+
+    fn block_read(&self, index: usize, block: u64, buf: &mut [u8]) -> Result<()> {
+        block_read(index, block, buf)
+    }
+
+    fn block_write(&self, index: usize, block: u64, buf: &[u8]) -> Result<()> {
+        block_write(index, block, buf)
+    }
+
+    fn net_devices(&self) -> Vec<NetDeviceInfo> {
+        net_devices()
+    }
+
+    fn net_link_up(&self, index: usize) -> bool {
+        net_link_up(index)
+    }
+
+    fn net_send(&self, index: usize, frame: &[u8]) -> Result<()> {
+        net_send(index, frame)
+    }
+
+    fn net_set_rx_callback(&self, index: usize, callback: Option<NetRxCallback>) -> Result<()> {
+        net_set_rx_callback(index, callback)
+    }
+
+    fn framebuffer_info(&self) -> Option<FramebufferInfo> {
+        framebuffer_info()
+    }
+
+    fn framebuffer_flush(&self, x: u32, y: u32, width: u32, height: u32) {
+        framebuffer_flush(x, y, width, height)
+    }
+
+    fn rand_bytes(&self, buf: &mut [u8]) {
+        rand_bytes(buf)
+    }
+
+    fn reboot(&self) -> ! {
+        arch::reboot()
+    }
+
+    fn shutdown(&self) -> ! {
+        arch::shutdown()
+    }
+
+    unsafe fn user_copy(&self, dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+        fixup::user_copy(dst, src, len)
+    }
+
+    fn crashlog_save(&self, data: &[u8]) {
+        crashlog_save(data)
+    }
+
+    fn crashlog_load(&self) -> Vec<u8> {
+        crashlog_load()
+    }
+
+    fn frame_alloc(&self) -> Option<PhysAddr> {
+        let frame = PhysFrame::alloc()?;
+        let paddr = frame.paddr;
+        core::mem::forget(frame);
+        Some(paddr)
+    }
+
+    fn frame_alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<PhysAddr> {
+        PhysFrame::alloc_contiguous_base(size, align_log2)
+    }
+
+    fn frame_dealloc(&self, paddr: PhysAddr) {
+        drop(PhysFrame { paddr })
+    }
+
+    fn zero_frame_paddr(&self) -> PhysAddr {
+        PhysFrame::zero_frame_addr()
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        frame_stats()
+    }
+
+    fn pmem_read(&self, paddr: PhysAddr, buf: &mut [u8]) {
+        pmem_read(paddr, buf)
+    }
+
+    fn pmem_write(&self, paddr: PhysAddr, buf: &[u8]) {
+        pmem_write(paddr, buf)
+    }
+
+    fn pmem_zero(&self, paddr: PhysAddr, len: usize) {
+        pmem_zero(paddr, len)
+    }
+
+    fn pmem_fetch_add_u64(&self, paddr: PhysAddr, value: u64) -> u64 {
+        pmem_fetch_add_u64(paddr, value)
+    }
+
+    fn frame_copy(&self, src: PhysAddr, target: PhysAddr) {
+        frame_copy(src, target)
+    }
+
+    fn frame_copy_n(&self, frames: &[(PhysAddr, PhysAddr)]) {
+        frame_copy_n(frames)
+    }
+
+    fn frame_flush(&self, target: PhysAddr) {
+        frame_flush(target)
+    }
+
+    fn pt_new(&self) -> Box<dyn PageTableTrait> {
+        Box::new(PageTable::new())
+    }
+
+    fn pt_current(&self) -> Box<dyn PageTableTrait> {
+        Box::new(PageTable::current())
+    }
+
+    fn tlb_flush(&self, vaddr: Range<VirtAddr>, vmtoken: usize) {
+        tlb_flush(vaddr, vmtoken)
+    }
+
+    fn context_run(&self, context: &mut UserContext) -> Result<()> {
+        context_run(context);
+        Ok(())
+    }
+
+    fn serial_read(&self, buf: &mut [u8]) -> usize {
+        serial_read(buf)
+    }
+
+    fn serial_set_callback(&self, callback: Box<dyn FnOnce() + Send + Sync>) {
+        serial_set_callback(callback)
+    }
+
+    fn serial_write(&self, s: &str) {
+        serial_write(s)
+    }
+}
+
+fn config() -> &'static Config {
+    CONFIG.get().expect("HAL is not initialized")
+}
+
+/// Get the value of the option `key=value` in the command line.
+fn cmdline_option(key: &str) -> Option<&'static str> {
+    config().cmdline.split_whitespace().find_map(|option| {
+        let mut kv = option.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some(k), Some(value)) if k == key => Some(value),
+            _ => None,
+        }
+    })
+}
+
+/// Convert the physical address to the virtual address in the linear mapping.
+pub fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    config().phys_offset + paddr
+}
+
+#[repr(C)]
+pub struct Thread {
+    thread: usize,
+}
+
+impl Thread {
+    /// Spawn a new thread, which is a task of the executor only run on the
+    /// CPUs of `affinity`.
+    pub fn spawn(
+        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+        _vmtoken: usize,
+        affinity: CpuAffinity,
+    ) -> Self {
+        executor::spawn(future, affinity);
+        Thread { thread: 0 }
+    }
+
+    /// Change the CPUs the current task runs on.
+    pub fn set_affinity(affinity: CpuAffinity) {
+        executor::set_current_affinity(affinity);
+    }
+
+    /// Set tid and pid of the current task.
+    pub fn set_tid(tid: u64, pid: u64) {
+        executor::set_current_tid(tid, pid);
+    }
+
+    /// Get tid and pid of the current task.
+    pub fn get_tid() -> (u64, u64) {
+        executor::current_tid()
+    }
+}
+
+/// Get the monotonic time, converted from the ticks of the timebase.
+pub fn timer_now() -> Duration {
+    let nanos = timer_ticks() as u128 * 1_000_000_000 / timer_ticks_per_second() as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Set a new timer.
+///
+/// After `deadline`, the `callback` will be called by the executor on the
+/// next timer interrupt.
+pub fn timer_set(deadline: Duration, callback: Box<dyn FnOnce(Duration) + Send + Sync>) -> u64 {
+    timer_add(deadline, callback)
+}
+
+/// Cancel the timer `id`.
+pub fn timer_cancel(id: u64) {
+    timer_remove(id);
+}
+
+/// Get the physical memory map given by the bootloader.
+pub fn memory_map() -> Vec<MemoryRegion> {
+    config().memory_map.clone()
+}
+
+/// Get the number of CPUs online.
+pub fn cpu_count() -> u32 {
+    smp::cpu_count()
+}
+
+/// Get the number of the current CPU, 0 for the boot CPU.
+pub fn cpu_id() -> u32 {
+    arch::cpu_id()
+}
+
+#[repr(C)]
+pub struct PhysFrame {
+    paddr: PhysAddr,
+}
+
+impl Debug for PhysFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "PhysFrame({:#x})", self.paddr)
+    }
+}
+
+impl PhysFrame {
+    pub fn alloc() -> Option<Self> {
+        let ret = frame::alloc().map(|paddr| PhysFrame { paddr });
+        trace!("frame alloc: {:?}", ret);
+        ret
+    }
+
+    pub fn alloc_contiguous_base(size: usize, align_log2: usize) -> Option<PhysAddr> {
+        let ret = frame::alloc_contiguous(size, align_log2);
+        trace!("frame alloc contiguous: {:x?}, size={}", ret, size);
+        ret
+    }
+
+    pub fn zero_frame_addr() -> PhysAddr {
+        frame::zero_frame()
+    }
+}
+
+impl Drop for PhysFrame {
+    fn drop(&mut self) {
+        trace!("frame dealloc: {:?}", self);
+        frame::dealloc(self.paddr);
+    }
+}
+
+/// Get statistics of the physical frame allocator.
+pub fn frame_stats() -> FrameStats {
+    frame::stats()
+}
+
+/// Read physical memory from `paddr` to `buf`.
+pub fn pmem_read(paddr: PhysAddr, buf: &mut [u8]) {
+    trace!("pmem read: paddr={:#x}, len={:#x}", paddr, buf.len());
+    unsafe {
+        (phys_to_virt(paddr) as *const u8).copy_to_nonoverlapping(buf.as_mut_ptr(), buf.len());
+    }
+}
+
+/// Write physical memory to `paddr` from `buf`.
+pub fn pmem_write(paddr: PhysAddr, buf: &[u8]) {
+    trace!("pmem write: paddr={:#x}, len={:#x}", paddr, buf.len());
+    unsafe {
+        buf.as_ptr()
+            .copy_to_nonoverlapping(phys_to_virt(paddr) as _, buf.len());
+    }
+}
+
+/// Zero physical memory at `[paddr, paddr + len)`
+pub fn pmem_zero(paddr: PhysAddr, len: usize) {
+    trace!("pmem_zero: addr={:#x}, len={:#x}", paddr, len);
+    unsafe {
+        core::ptr::write_bytes(phys_to_virt(paddr) as *mut u8, 0, len);
+    }
+}
+
+/// Atomically add `value` to the `u64` at `paddr`, return the previous value.
+pub fn pmem_fetch_add_u64(paddr: PhysAddr, value: u64) -> u64 {
+    assert!(paddr % 8 == 0);
+    let atomic = unsafe { &*(phys_to_virt(paddr) as *const AtomicU64) };
+    atomic.fetch_add(value, Ordering::Relaxed)
+}
+
+/// Copy content of `src` frame to `target` frame
+pub fn frame_copy(src: PhysAddr, target: PhysAddr) {
+    trace!("frame_copy: {:#x} <- {:#x}", target, src);
+    unsafe {
+        let buf = phys_to_virt(src) as *const u8;
+        buf.copy_to_nonoverlapping(phys_to_virt(target) as _, PAGE_SIZE);
+    }
+}
+
+/// Copy the content of each `(src, target)` pair of frames, by a copy of
+/// each run contiguous in both.
+pub fn frame_copy_n(frames: &[(PhysAddr, PhysAddr)]) {
+    trace!("frame_copy_n: {} frames", frames.len());
+    for (src, target, len) in kernel_hal::copy_runs(frames) {
+        unsafe {
+            let buf = phys_to_virt(src) as *const u8;
+            buf.copy_to_nonoverlapping(phys_to_virt(target) as _, len);
+        }
+    }
+}
+
+/// The size of the region reserved for the crashlog, with the header of the
+/// magic and the length before the content.
+pub const CRASHLOG_REGION_SIZE: usize = CRASHLOG_HEADER_SIZE + CRASHLOG_CAPACITY;
+
+/// The size of the header of the crashlog region.
+const CRASHLOG_HEADER_SIZE: usize = 16;
+
+/// The magic of a valid crashlog in the region.
+const CRASHLOG_MAGIC: u64 = 0x676f_6c68_7361_7263;
+
+/// Get the crashlog region given by the bootloader, if it is not taken by the
+/// frame allocator.
+fn crashlog_region() -> Option<PhysAddr> {
+    let config = CONFIG.get()?;
+    let paddr = config.crashlog_region?;
+    let end = paddr + CRASHLOG_REGION_SIZE;
+    let in_ram = config.memory_map.iter().any(|region| {
+        region.kind == MemoryRegionKind::Ram
+            && paddr < region.addr + region.size
+            && region.addr < end
+    });
+    if in_ram {
+        warn!("crashlog region {:#x} overlaps RAM, ignored", paddr);
+        return None;
+    }
+    Some(paddr)
+}
+
+/// Save the crashlog to the region reserved by the bootloader, truncated to
+/// `CRASHLOG_CAPACITY` bytes. It is called on panic, when no other CPU
+/// touches the region.
+pub fn crashlog_save(data: &[u8]) {
+    let paddr = match crashlog_region() {
+        Some(paddr) => paddr,
+        None => return,
+    };
+    let len = data.len().min(CRASHLOG_CAPACITY);
+    pmem_write(paddr + CRASHLOG_HEADER_SIZE, &data[..len]);
+    pmem_write(paddr + 8, &(len as u64).to_le_bytes());
+    pmem_write(paddr, &CRASHLOG_MAGIC.to_le_bytes());
+}
+
+/// Take the crashlog saved in the last boot, empty if nothing is saved, or
+/// the region is lost on a cold boot.
+pub fn crashlog_load() -> Vec<u8> {
+    let paddr = match crashlog_region() {
+        Some(paddr) => paddr,
+        None => return Vec::new(),
+    };
+    let mut header = [0u8; CRASHLOG_HEADER_SIZE];
+    pmem_read(paddr, &mut header);
+    let magic = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let len = u64::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+    if magic != CRASHLOG_MAGIC || len > CRASHLOG_CAPACITY {
+        return Vec::new();
+    }
+    pmem_zero(paddr, 8);
+    let mut data = vec![0; len];
+    pmem_read(paddr + CRASHLOG_HEADER_SIZE, &mut data);
+    data
+}
+
+/// Read the real-time clock of the platform.
+pub fn rtc_now() -> Option<Duration> {
+    arch::rtc_now()
+}
+
+/// Fill `buf` with random bytes from the hardware RNG of the CPU, or the
+/// jitter of the timebase if there is none.
+pub fn rand_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let value = arch::hw_random().unwrap_or_else(jitter_random);
+        chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+    }
+}
+
+/// Collect the low bits of the timebase around a short spin many times,
+/// mixed by SplitMix64.
+///
+/// It is only a weak source of entropy, for the seed of the kernel CSPRNG.
+fn jitter_random() -> u64 {
+    let mut state = 0u64;
+    for _ in 0..64 {
+        let start = timer_ticks();
+        for _ in 0..(start & 0xff) {
+            core::hint::spin_loop();
+        }
+        state = state.rotate_left(7) ^ timer_ticks().wrapping_sub(start);
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        state ^= state >> 31;
+    }
+    state
+}
+
+/// Get the PCI functions found at boot.
+pub fn pci_enumerate() -> Vec<PciDeviceInfo> {
+    drivers::pci::functions()
+}
+
+/// Read `width` bytes at `offset` of the config space of the PCI function.
+pub fn pci_config_read(addr: PciAddr, offset: usize, width: usize) -> Result<u32> {
+    drivers::pci::config_read(addr, offset, width)
+}
+
+/// Write `width` bytes at `offset` of the config space of the PCI function.
+pub fn pci_config_write(addr: PciAddr, offset: usize, width: usize, value: u32) -> Result<()> {
+    drivers::pci::config_write(addr, offset, width, value)
+}
+
+/// Allocate and enable `count` MSI vectors of the PCI function, return the
+/// first vector. Only supported on x86_64.
+pub fn pci_msi_alloc(addr: PciAddr, count: u32) -> Result<u32> {
+    drivers::pci::msi_alloc(addr, count)
+}
+
+/// Disable and free the MSI vectors of the PCI function.
+pub fn pci_msi_free(addr: PciAddr) {
+    drivers::pci::msi_free(addr)
+}
+
+/// Get the information of the block devices found by probing.
+pub fn block_devices() -> Vec<BlockDeviceInfo> {
+    let devices = drivers::BLOCK_DEVICES.read();
+    devices.iter().map(|device| device.info()).collect()
+}
+
+/// Read the blocks of the `index`-th block device from `block` to `buf`.
+pub fn block_read(index: usize, block: u64, buf: &mut [u8]) -> Result<()> {
+    let device = drivers::BLOCK_DEVICES.read().get(index).cloned();
+    let device = device.ok_or(HalError)?;
+    let block_size = device.info().block_size;
+    if buf.len() % block_size != 0 {
+        return Err(HalError);
+    }
+    for (i, chunk) in buf.chunks_mut(block_size).enumerate() {
+        device.read_block(block + i as u64, chunk)?;
+    }
+    Ok(())
+}
+
+/// Write `buf` to the blocks of the `index`-th block device from `block`.
+pub fn block_write(index: usize, block: u64, buf: &[u8]) -> Result<()> {
+    let device = drivers::BLOCK_DEVICES.read().get(index).cloned();
+    let device = device.ok_or(HalError)?;
+    let block_size = device.info().block_size;
+    if buf.len() % block_size != 0 {
+        return Err(HalError);
+    }
+    for (i, chunk) in buf.chunks(block_size).enumerate() {
+        device.write_block(block + i as u64, chunk)?;
+    }
+    Ok(())
+}
+
+/// Get the information of the network devices found by probing.
+pub fn net_devices() -> Vec<NetDeviceInfo> {
+    let devices = drivers::NET_DEVICES.read();
+    devices.iter().map(|device| device.info()).collect()
+}
+
+/// Whether the link of the `index`-th network device is up.
+pub fn net_link_up(index: usize) -> bool {
+    let device = drivers::NET_DEVICES.read().get(index).cloned();
+    device.map_or(false, |device| device.link_up())
+}
+
+/// Send an ethernet `frame` by the `index`-th network device.
+pub fn net_send(index: usize, frame: &[u8]) -> Result<()> {
+    let device = drivers::NET_DEVICES.read().get(index).cloned();
+    device.ok_or(HalError)?.send(frame)
+}
+
+/// Set the handler of the frames received by the `index`-th network device.
+///
+/// The `callback` will be called in the interrupt of the device.
+pub fn net_set_rx_callback(index: usize, callback: Option<NetRxCallback>) -> Result<()> {
+    let device = drivers::NET_DEVICES.read().get(index).cloned();
+    let device = device.ok_or(HalError)?;
+    // the callback is also locked in the interrupt
+    arch::without_interrupts(|| device.set_rx_callback(callback));
+    Ok(())
+}
+
+/// Get the framebuffer of the display found by probing.
+pub fn framebuffer_info() -> Option<FramebufferInfo> {
+    let display = drivers::DISPLAY.read();
+    display.as_ref().map(|display| display.info())
+}
+
+/// Show the update of the rectangle in the framebuffer on the display.
+pub fn framebuffer_flush(x: u32, y: u32, width: u32, height: u32) {
+    let display = drivers::DISPLAY.read().clone();
+    if let Some(display) = display {
+        display.flush(x, y, width, height);
+    }
+}
+
+/// Read the received input of the console without blocking.
+pub fn serial_read(buf: &mut [u8]) -> usize {
+    // the queue is also locked by `serial_push` in the console interrupt
+    arch::without_interrupts(|| serial_take(buf))
+}
+
+/// Put the input of the console received in its interrupt, and let the
+/// executor call back the waiters.
+pub(crate) fn serial_irq_put(data: &[u8]) {
+    serial_push(data);
+    executor::serial_interrupt();
+}
+
+/// Call `callback` once some input of the console is available.
+///
+/// The `callback` will be called by the executor after the interrupt of the
+/// console.
+pub fn serial_set_callback(callback: Box<dyn FnOnce() + Send + Sync>) {
+    arch::without_interrupts(|| serial_add_callback(callback));
+}
+
+/// Output a string to console.
+pub fn serial_write(s: &str) {
+    let console = drivers::CONSOLE.read().clone();
+    match console {
+        // the TX queue may also be used in the interrupt
+        Some(console) => arch::without_interrupts(|| console.write(s.as_bytes())),
+        None => arch::serial::write(s),
+    }
+}
@@ -0,0 +1,76 @@
+//! The bring-up of the secondary CPUs.
+//!
+//! The CPUs are numbered from 0, the boot CPU, in the order they are
+//! started. Each of them runs the executor on its own stack once it is
+//! online.
+
+use {
+    super::{arch, executor, frame},
+    core::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    },
+    kernel_hal::PAGE_SIZE,
+};
+
+/// The maximum number of CPUs, including the boot CPU.
+pub const MAX_CPUS: usize = 32;
+
+/// The size of the stack of a secondary CPU, in frames.
+const STACK_FRAMES: usize = 16;
+
+/// The time to wait for a secondary CPU to be online.
+const START_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The number of CPUs online.
+static ONLINE: AtomicU32 = AtomicU32::new(1);
+
+/// Start the secondary CPUs one by one.
+pub fn init() {
+    for hw_id in arch::secondary_cpus() {
+        let cpu = ONLINE.load(Ordering::Acquire);
+        if cpu as usize >= MAX_CPUS {
+            warn!("SMP: more than {} CPUs, the others are ignored", MAX_CPUS);
+            break;
+        }
+        let stack = match frame::alloc_contiguous(STACK_FRAMES, 0) {
+            Some(stack) => stack,
+            None => {
+                warn!("SMP: no memory for the stack of CPU {}", cpu);
+                break;
+            }
+        };
+        let stack_top = crate::phys_to_virt(stack) + STACK_FRAMES * PAGE_SIZE;
+        if !arch::start_cpu(hw_id, cpu, stack_top) {
+            warn!("SMP: failed to start CPU {:#x}", hw_id);
+            for i in 0..STACK_FRAMES {
+                frame::dealloc(stack + i * PAGE_SIZE);
+            }
+            continue;
+        }
+        let deadline = crate::timer_now() + START_TIMEOUT;
+        while ONLINE.load(Ordering::Acquire) == cpu && crate::timer_now() < deadline {
+            core::hint::spin_loop();
+        }
+        // a late CPU may still take the number and the stack, so no more CPUs
+        // are started after it
+        if ONLINE.load(Ordering::Acquire) == cpu {
+            warn!("SMP: CPU {:#x} is not online in time", hw_id);
+            break;
+        }
+    }
+    info!("SMP: {} CPUs online", cpu_count());
+}
+
+/// Get the number of CPUs online.
+pub fn cpu_count() -> u32 {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// The entry of the secondary CPU `cpu` after its architectural
+/// initialization, on its own stack.
+pub fn secondary_main(cpu: u32) -> ! {
+    ONLINE.fetch_add(1, Ordering::AcqRel);
+    info!("SMP: CPU {} is online", cpu);
+    executor::run()
+}
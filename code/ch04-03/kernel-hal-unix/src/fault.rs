@@ -0,0 +1,166 @@
+//! Recovery from faults when copying between user and kernel.
+//!
+//! The copy is done by a single `rep movsb`. If it faults, the signal handler
+//! moves the instruction pointer to a fixup which returns an error, like the
+//! exception table of a real kernel. Other faults are passed to the handler
+//! installed before.
+//!
+//! The handlers run on an alternate signal stack of each thread, since the
+//! signals may arrive when the stack is the one of the user program.
+
+use {
+    super::*,
+    core::{cell::Cell, mem::MaybeUninit},
+    std::sync::Once,
+};
+
+#[cfg(target_os = "linux")]
+global_asm!(
+    "
+    .text
+    .global zcore_user_copy
+zcore_user_copy:
+    mov rcx, rdx
+    .global zcore_user_copy_insn
+zcore_user_copy_insn:
+    rep movsb
+    xor eax, eax
+    ret
+    .global zcore_user_copy_fixup
+zcore_user_copy_fixup:
+    mov eax, 1
+    ret
+"
+);
+
+// the symbols of C have a leading underscore on macOS
+#[cfg(target_os = "macos")]
+global_asm!(
+    "
+    .text
+    .global _zcore_user_copy
+_zcore_user_copy:
+    mov rcx, rdx
+    .global _zcore_user_copy_insn
+_zcore_user_copy_insn:
+    rep movsb
+    xor eax, eax
+    ret
+    .global _zcore_user_copy_fixup
+_zcore_user_copy_fixup:
+    mov eax, 1
+    ret
+"
+);
+
+extern "C" {
+    fn zcore_user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    fn zcore_user_copy_insn();
+    fn zcore_user_copy_fixup();
+}
+
+/// Copy `len` bytes from `src` to `dst`, return `Err` if the copy faults.
+///
+/// # Safety
+///
+/// The kernel side of the copy must be valid for `len` bytes.
+pub unsafe fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+    match zcore_user_copy(dst, src, len) {
+        0 => Ok(()),
+        _ => Err(HalError),
+    }
+}
+
+/// The handlers of SIGSEGV and SIGBUS installed before.
+static mut OLD_ACTIONS: [MaybeUninit<libc::sigaction>; 2] = [MaybeUninit::uninit(); 2];
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGSEGV, libc::SIGBUS];
+
+/// The size of the alternate signal stack.
+const SIGNAL_STACK_SIZE: usize = 0x10000;
+
+/// Install the signal handlers, only once.
+pub fn init() {
+    init_signal_stack();
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        let mut action: libc::sigaction = core::mem::zeroed();
+        action.sa_sigaction = signal_handler as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        for (i, &signal) in SIGNALS.iter().enumerate() {
+            let ret = libc::sigaction(signal, &action, OLD_ACTIONS[i].as_mut_ptr());
+            assert_eq!(ret, 0, "failed to install signal handler");
+        }
+    });
+}
+
+/// Set up the alternate signal stack of the current thread, only once.
+///
+/// The stack is leaked when the thread exits.
+pub fn init_signal_stack() {
+    thread_local! {
+        static INITIALIZED: Cell<bool> = Cell::new(false);
+    }
+    INITIALIZED.with(|initialized| {
+        if initialized.replace(true) {
+            return;
+        }
+        let stack = Box::leak(vec![0u8; SIGNAL_STACK_SIZE].into_boxed_slice());
+        let stack = libc::stack_t {
+            ss_sp: stack.as_mut_ptr() as _,
+            ss_flags: 0,
+            ss_size: SIGNAL_STACK_SIZE,
+        };
+        let ret = unsafe { libc::sigaltstack(&stack, core::ptr::null_mut()) };
+        assert_eq!(
+            ret,
+            0,
+            "failed to set signal stack: {:?}",
+            Error::last_os_error()
+        );
+    });
+}
+
+/// Get the instruction pointer saved in the signal context.
+#[cfg(target_os = "linux")]
+unsafe fn context_ip(context: &mut libc::ucontext_t) -> &mut usize {
+    &mut *(&mut context.uc_mcontext.gregs[libc::REG_RIP as usize] as *mut i64 as *mut usize)
+}
+
+/// Get the instruction pointer saved in the signal context.
+#[cfg(target_os = "macos")]
+unsafe fn context_ip(context: &mut libc::ucontext_t) -> &mut usize {
+    &mut *(&mut (*context.uc_mcontext).__ss.__rip as *mut u64 as *mut usize)
+}
+
+extern "C" fn signal_handler(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    unsafe {
+        let context = &mut *(context as *mut libc::ucontext_t);
+        let rip = context_ip(context);
+        if *rip == zcore_user_copy_insn as usize {
+            *rip = zcore_user_copy_fixup as usize;
+            return;
+        }
+        let i = SIGNALS.iter().position(|&s| s == signal).unwrap();
+        let old = &*OLD_ACTIONS[i].as_ptr();
+        match old.sa_sigaction {
+            libc::SIG_DFL | libc::SIG_IGN => {
+                // restore it, and the fault happens again
+                libc::sigaction(signal, old, core::ptr::null_mut());
+            }
+            handler if old.sa_flags & libc::SA_SIGINFO != 0 => {
+                let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) =
+                    core::mem::transmute(handler);
+                handler(signal, info, context as *mut _ as *mut _);
+            }
+            handler => {
+                let handler: extern "C" fn(libc::c_int) = core::mem::transmute(handler);
+                handler(signal);
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The bitmap allocator of the frames in the pmem file.
+
+use alloc::vec::Vec;
+
+/// A bitmap of the frames, where a set bit means allocated.
+pub struct FrameAllocator {
+    bits: Vec<u64>,
+    total: usize,
+    free: usize,
+    /// Where to start searching for a single frame, after the last one
+    /// allocated to keep the locality.
+    next: usize,
+}
+
+impl FrameAllocator {
+    /// Create an allocator of `total` free frames.
+    pub fn new(total: usize) -> Self {
+        let mut bits = vec![0u64; (total + 63) / 64];
+        // the bits beyond the frames are always allocated
+        if total % 64 != 0 {
+            *bits.last_mut().unwrap() = !0 << (total % 64);
+        }
+        FrameAllocator {
+            bits,
+            total,
+            free: total,
+            next: 0,
+        }
+    }
+
+    /// The number of free frames.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    fn is_free(&self, index: usize) -> bool {
+        self.bits[index / 64] & (1 << (index % 64)) == 0
+    }
+
+    fn set(&mut self, range: core::ops::Range<usize>, allocated: bool) {
+        for index in range {
+            debug_assert_eq!(self.is_free(index), allocated);
+            if allocated {
+                self.bits[index / 64] |= 1 << (index % 64);
+            } else {
+                self.bits[index / 64] &= !(1 << (index % 64));
+            }
+        }
+    }
+
+    /// Allocate a frame, return its index.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let words = self.bits.len();
+        let index = (0..words)
+            .map(|i| (self.next / 64 + i) % words)
+            .find(|&i| self.bits[i] != !0)
+            .map(|i| i * 64 + (!self.bits[i]).trailing_zeros() as usize)?;
+        self.set(index..index + 1, true);
+        self.free -= 1;
+        self.next = index + 1;
+        Some(index)
+    }
+
+    /// Allocate `count` contiguous frames starting at a multiple of
+    /// `1 << align_log2`, return the index of the first one.
+    pub fn alloc_contiguous(&mut self, count: usize, align_log2: usize) -> Option<usize> {
+        if count == 0 || count > self.free {
+            return None;
+        }
+        let align = 1 << align_log2;
+        let mut start = 0;
+        while start + count <= self.total {
+            match (start..start + count).rev().find(|&i| !self.is_free(i)) {
+                // skip past the allocated frame
+                Some(used) => start = (used + align) & !(align - 1),
+                None => {
+                    self.set(start..start + count, true);
+                    self.free -= count;
+                    return Some(start);
+                }
+            }
+        }
+        None
+    }
+
+    /// Free the frame of `index`.
+    pub fn dealloc(&mut self, index: usize) {
+        self.set(index..index + 1, false);
+        self.free += 1;
+    }
+
+    /// Mark the frame of `index` as allocated forever.
+    pub fn reserve(&mut self, index: usize) {
+        self.set(index..index + 1, true);
+        self.free -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contiguous() {
+        let mut allocator = FrameAllocator::new(100);
+        allocator.reserve(0);
+        assert_eq!(allocator.alloc(), Some(1));
+        // aligned after the allocated frames
+        assert_eq!(allocator.alloc_contiguous(4, 2), Some(4));
+        assert_eq!(allocator.alloc_contiguous(3, 0), Some(8));
+        // the single frames fill the holes
+        assert_eq!(allocator.alloc(), Some(2));
+        assert_eq!(allocator.alloc(), Some(3));
+        allocator.dealloc(5);
+        assert_eq!(allocator.alloc_contiguous(1, 0), Some(5));
+        assert_eq!(allocator.alloc_contiguous(64, 5), Some(32));
+        assert_eq!(allocator.free(), 100 - 11 - 64);
+        // the free frames are in [11, 32) and [96, 100)
+        assert_eq!(allocator.alloc_contiguous(22, 0), None);
+        assert_eq!(allocator.alloc_contiguous(21, 0), Some(11));
+    }
+}
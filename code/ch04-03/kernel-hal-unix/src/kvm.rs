@@ -0,0 +1,555 @@
+//! Hardware virtualization backed by the Linux KVM.
+//!
+//! Guest physical pages are mapped to the frames of the physical memory file,
+//! each page in its own memory slot. A vCPU starts in 32-bit protected mode
+//! with flat segments and paging disabled.
+
+use {
+    super::*,
+    core::sync::atomic::AtomicUsize,
+    std::os::unix::io::{FromRawFd, RawFd},
+};
+
+const KVM_API_VERSION: i32 = 12;
+
+const KVM_GET_API_VERSION: u64 = 0xae00;
+const KVM_CREATE_VM: u64 = 0xae01;
+const KVM_GET_VCPU_MMAP_SIZE: u64 = 0xae04;
+const KVM_CREATE_VCPU: u64 = 0xae41;
+const KVM_SET_USER_MEMORY_REGION: u64 = 0x4020_ae46;
+const KVM_SET_TSS_ADDR: u64 = 0xae47;
+const KVM_RUN: u64 = 0xae80;
+const KVM_GET_REGS: u64 = 0x8090_ae81;
+const KVM_SET_REGS: u64 = 0x4090_ae82;
+const KVM_GET_SREGS: u64 = 0x8138_ae83;
+const KVM_SET_SREGS: u64 = 0x4138_ae84;
+const KVM_INTERRUPT: u64 = 0x4004_ae86;
+
+const KVM_EXIT_IO: u32 = 2;
+const KVM_EXIT_HLT: u32 = 5;
+const KVM_EXIT_MMIO: u32 = 6;
+const KVM_EXIT_IRQ_WINDOW_OPEN: u32 = 7;
+const KVM_EXIT_INTR: u32 = 10;
+
+const KVM_EXIT_IO_IN: u8 = 0;
+const KVM_MEM_READONLY: u32 = 1 << 1;
+
+/// The address of the 3 pages of TSS required by Intel VMX.
+const TSS_ADDR: usize = 0xfffb_d000;
+
+#[repr(C)]
+struct KvmUserspaceMemoryRegion {
+    slot: u32,
+    flags: u32,
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmRegs {
+    rax: u64,
+    rbx: u64,
+    rcx: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rsp: u64,
+    rbp: u64,
+    r8: u64,
+    r9: u64,
+    r10: u64,
+    r11: u64,
+    r12: u64,
+    r13: u64,
+    r14: u64,
+    r15: u64,
+    rip: u64,
+    rflags: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSegment {
+    base: u64,
+    limit: u32,
+    selector: u16,
+    type_: u8,
+    present: u8,
+    dpl: u8,
+    db: u8,
+    s: u8,
+    l: u8,
+    g: u8,
+    avl: u8,
+    unusable: u8,
+    padding: u8,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmDtable {
+    base: u64,
+    limit: u16,
+    padding: [u16; 3],
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct KvmSregs {
+    cs: KvmSegment,
+    ds: KvmSegment,
+    es: KvmSegment,
+    fs: KvmSegment,
+    gs: KvmSegment,
+    ss: KvmSegment,
+    tr: KvmSegment,
+    ldt: KvmSegment,
+    gdt: KvmDtable,
+    idt: KvmDtable,
+    cr0: u64,
+    cr2: u64,
+    cr3: u64,
+    cr4: u64,
+    cr8: u64,
+    efer: u64,
+    apic_base: u64,
+    interrupt_bitmap: [u64; 4],
+}
+
+/// The head of the shared `struct kvm_run`.
+#[repr(C)]
+struct KvmRun {
+    request_interrupt_window: u8,
+    immediate_exit: u8,
+    padding1: [u8; 6],
+    exit_reason: u32,
+    ready_for_interrupt_injection: u8,
+    if_flag: u8,
+    flags: u16,
+    cr8: u64,
+    apic_base: u64,
+    /// The union of exit information.
+    exit: [u8; 256],
+}
+
+#[repr(C)]
+struct KvmRunIo {
+    direction: u8,
+    size: u8,
+    port: u16,
+    count: u32,
+    data_offset: u64,
+}
+
+#[repr(C)]
+struct KvmRunMmio {
+    phys_addr: u64,
+    data: [u8; 8],
+    len: u32,
+    is_write: u8,
+}
+
+lazy_static! {
+    /// The KVM device, `None` if it is not available.
+    static ref KVM: Option<File> = open_kvm();
+    static ref GUESTS: Mutex<BTreeMap<usize, Arc<KvmGuest>>> = Mutex::new(BTreeMap::new());
+    static ref VCPUS: Mutex<BTreeMap<usize, Arc<KvmVcpu>>> = Mutex::new(BTreeMap::new());
+}
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+struct KvmGuest {
+    vm: File,
+    inner: Mutex<KvmGuestInner>,
+}
+
+#[derive(Default)]
+struct KvmGuestInner {
+    /// The memory slot of each mapped page.
+    slots: BTreeMap<GuestPhysAddr, u32>,
+    free_slots: Vec<u32>,
+    next_slot: u32,
+    next_vcpu: usize,
+}
+
+struct KvmVcpu {
+    fd: File,
+    run: usize,
+    run_size: usize,
+    interrupts: Mutex<VecDeque<u32>>,
+}
+
+fn open_kvm() -> Option<File> {
+    let kvm = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/kvm")
+        .ok()?;
+    if ioctl(&kvm, KVM_GET_API_VERSION, 0).ok()? != KVM_API_VERSION {
+        warn!("unsupported KVM API version");
+        return None;
+    }
+    Some(kvm)
+}
+
+fn ioctl(file: &File, request: u64, arg: usize) -> Result<i32> {
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if ret < 0 {
+        warn!(
+            "KVM ioctl {:#x} failed: {:?}",
+            request,
+            Error::last_os_error()
+        );
+        return Err(HalError);
+    }
+    Ok(ret)
+}
+
+fn file_from_fd(fd: i32) -> File {
+    unsafe { File::from_raw_fd(fd as RawFd) }
+}
+
+fn get_guest(guest: usize) -> Result<Arc<KvmGuest>> {
+    GUESTS.lock().unwrap().get(&guest).cloned().ok_or(HalError)
+}
+
+fn get_vcpu(vcpu: usize) -> Result<Arc<KvmVcpu>> {
+    VCPUS.lock().unwrap().get(&vcpu).cloned().ok_or(HalError)
+}
+
+pub fn guest_create() -> Result<usize> {
+    let kvm = KVM.as_ref().ok_or(HalError)?;
+    let vm = file_from_fd(ioctl(kvm, KVM_CREATE_VM, 0)?);
+    ioctl(&vm, KVM_SET_TSS_ADDR, TSS_ADDR)?;
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let guest = KvmGuest {
+        vm,
+        inner: Mutex::new(KvmGuestInner::default()),
+    };
+    GUESTS.lock().unwrap().insert(id, Arc::new(guest));
+    Ok(id)
+}
+
+pub fn guest_destroy(guest: usize) {
+    GUESTS.lock().unwrap().remove(&guest);
+}
+
+impl KvmGuest {
+    fn set_memory_region(
+        &self,
+        slot: u32,
+        flags: u32,
+        gpaddr: usize,
+        size: usize,
+        vaddr: usize,
+    ) -> Result<()> {
+        let region = KvmUserspaceMemoryRegion {
+            slot,
+            flags,
+            guest_phys_addr: gpaddr as u64,
+            memory_size: size as u64,
+            userspace_addr: vaddr as u64,
+        };
+        ioctl(
+            &self.vm,
+            KVM_SET_USER_MEMORY_REGION,
+            &region as *const _ as usize,
+        )?;
+        Ok(())
+    }
+
+    fn unmap(&self, inner: &mut KvmGuestInner, gpaddr: GuestPhysAddr) -> Result<()> {
+        let slot = inner.slots.remove(&gpaddr).ok_or(HalError)?;
+        self.set_memory_region(slot, 0, gpaddr, 0, 0)?;
+        inner.free_slots.push(slot);
+        Ok(())
+    }
+}
+
+pub fn guest_map(
+    guest: usize,
+    gpaddr: GuestPhysAddr,
+    paddr: PhysAddr,
+    flags: MMUFlags,
+) -> Result<()> {
+    let guest = get_guest(guest)?;
+    let mut inner = guest.inner.lock().unwrap();
+    // the userspace address of a slot can not be changed
+    if inner.slots.contains_key(&gpaddr) {
+        guest.unmap(&mut inner, gpaddr)?;
+    }
+    let slot = match inner.free_slots.pop() {
+        Some(slot) => slot,
+        None => {
+            inner.next_slot += 1;
+            inner.next_slot - 1
+        }
+    };
+    let kvm_flags = if flags.contains(MMUFlags::WRITE) {
+        0
+    } else {
+        KVM_MEM_READONLY
+    };
+    ensure_pmem(paddr + PAGE_SIZE);
+    if let Err(e) = guest.set_memory_region(slot, kvm_flags, gpaddr, PAGE_SIZE, phys_to_virt(paddr))
+    {
+        inner.free_slots.push(slot);
+        return Err(e);
+    }
+    inner.slots.insert(gpaddr, slot);
+    Ok(())
+}
+
+pub fn guest_unmap(guest: usize, gpaddr: GuestPhysAddr) -> Result<()> {
+    let guest = get_guest(guest)?;
+    let mut inner = guest.inner.lock().unwrap();
+    guest.unmap(&mut inner, gpaddr)
+}
+
+pub fn vcpu_create(guest: usize, entry: GuestPhysAddr) -> Result<usize> {
+    let kvm = KVM.as_ref().ok_or(HalError)?;
+    let guest = get_guest(guest)?;
+    let index = {
+        let mut inner = guest.inner.lock().unwrap();
+        inner.next_vcpu += 1;
+        inner.next_vcpu - 1
+    };
+    let fd = file_from_fd(ioctl(&guest.vm, KVM_CREATE_VCPU, index)?);
+    let run_size = ioctl(kvm, KVM_GET_VCPU_MMAP_SIZE, 0)? as usize;
+    let run = unsafe {
+        libc::mmap(
+            core::ptr::null_mut(),
+            run_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd.as_raw_fd(),
+            0,
+        )
+    };
+    if run == libc::MAP_FAILED {
+        return Err(HalError);
+    }
+    let vcpu = KvmVcpu {
+        fd,
+        run: run as usize,
+        run_size,
+        interrupts: Mutex::new(VecDeque::new()),
+    };
+    vcpu.reset(entry)?;
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    VCPUS.lock().unwrap().insert(id, Arc::new(vcpu));
+    Ok(id)
+}
+
+pub fn vcpu_destroy(vcpu: usize) {
+    VCPUS.lock().unwrap().remove(&vcpu);
+}
+
+impl KvmVcpu {
+    /// Enter 32-bit protected mode at `entry`.
+    fn reset(&self, entry: GuestPhysAddr) -> Result<()> {
+        let mut sregs = KvmSregs::default();
+        ioctl(&self.fd, KVM_GET_SREGS, &mut sregs as *mut _ as usize)?;
+        let segment = |selector, type_| KvmSegment {
+            base: 0,
+            limit: 0xffff_ffff,
+            selector,
+            type_,
+            present: 1,
+            db: 1,
+            s: 1,
+            g: 1,
+            ..Default::default()
+        };
+        sregs.cs = segment(0x8, 0xb);
+        sregs.ds = segment(0x10, 0x3);
+        sregs.es = segment(0x10, 0x3);
+        sregs.fs = segment(0x10, 0x3);
+        sregs.gs = segment(0x10, 0x3);
+        sregs.ss = segment(0x10, 0x3);
+        sregs.cr0 |= 1;
+        ioctl(&self.fd, KVM_SET_SREGS, &sregs as *const _ as usize)?;
+        let regs = KvmRegs {
+            rip: entry as u64,
+            rflags: 2,
+            ..Default::default()
+        };
+        ioctl(&self.fd, KVM_SET_REGS, &regs as *const _ as usize)?;
+        Ok(())
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn run(&self) -> &mut KvmRun {
+        unsafe { &mut *(self.run as *mut KvmRun) }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn run_io(&self) -> &mut KvmRunIo {
+        unsafe { &mut *(self.run().exit.as_mut_ptr() as *mut KvmRunIo) }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn run_mmio(&self) -> &mut KvmRunMmio {
+        unsafe { &mut *(self.run().exit.as_mut_ptr() as *mut KvmRunMmio) }
+    }
+
+    /// Get the data buffer of the last I/O exit.
+    #[allow(clippy::mut_from_ref)]
+    fn io_data(&self) -> &mut [u8] {
+        let io = self.run_io();
+        let ptr = (self.run + io.data_offset as usize) as *mut u8;
+        unsafe { core::slice::from_raw_parts_mut(ptr, io.size as usize) }
+    }
+
+    fn get_regs(&self) -> Result<KvmRegs> {
+        let mut regs = KvmRegs::default();
+        ioctl(&self.fd, KVM_GET_REGS, &mut regs as *mut _ as usize)?;
+        Ok(regs)
+    }
+
+    /// Inject a pending interrupt if possible, or request to exit when the
+    /// vCPU is able to take it.
+    fn inject_interrupt(&self) -> Result<()> {
+        let mut interrupts = self.interrupts.lock().unwrap();
+        let run = self.run();
+        if run.ready_for_interrupt_injection != 0 && run.if_flag != 0 {
+            if let Some(vector) = interrupts.pop_front() {
+                ioctl(&self.fd, KVM_INTERRUPT, &vector as *const _ as usize)?;
+            }
+        }
+        run.request_interrupt_window = !interrupts.is_empty() as u8;
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<VcpuExit> {
+        loop {
+            self.inject_interrupt()?;
+            let ret = unsafe { libc::ioctl(self.fd.as_raw_fd(), KVM_RUN as _, 0) };
+            if ret < 0 {
+                let error = Error::last_os_error();
+                if error.raw_os_error() == Some(libc::EINTR) {
+                    continue;
+                }
+                warn!("KVM_RUN failed: {:?}", error);
+                return Err(HalError);
+            }
+            match self.run().exit_reason {
+                KVM_EXIT_IO => {
+                    let io = self.run_io();
+                    let input = io.direction == KVM_EXIT_IO_IN;
+                    let mut data = [0u8; 4];
+                    if !input {
+                        let len = self.io_data().len().min(4);
+                        data[..len].copy_from_slice(&self.io_data()[..len]);
+                    }
+                    return Ok(VcpuExit::Io {
+                        port: io.port,
+                        access_size: io.size,
+                        input,
+                        data: u32::from_le_bytes(data),
+                    });
+                }
+                KVM_EXIT_MMIO => {
+                    let mmio = self.run_mmio();
+                    return Ok(VcpuExit::Mmio {
+                        addr: mmio.phys_addr as usize,
+                        access_size: mmio.len as u8,
+                        write: mmio.is_write != 0,
+                        data: u64::from_le_bytes(mmio.data),
+                    });
+                }
+                KVM_EXIT_HLT => return Ok(VcpuExit::Halt),
+                KVM_EXIT_IRQ_WINDOW_OPEN | KVM_EXIT_INTR => continue,
+                reason => {
+                    warn!("unhandled KVM exit: reason={}", reason);
+                    return Err(HalError);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for KvmVcpu {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.run as _, self.run_size);
+        }
+    }
+}
+
+pub fn vcpu_resume(vcpu: usize) -> Result<VcpuExit> {
+    get_vcpu(vcpu)?.resume()
+}
+
+pub fn vcpu_read_state(vcpu: usize) -> Result<VcpuState> {
+    let regs = get_vcpu(vcpu)?.get_regs()?;
+    Ok(VcpuState {
+        rax: regs.rax,
+        rcx: regs.rcx,
+        rdx: regs.rdx,
+        rbx: regs.rbx,
+        rsp: regs.rsp,
+        rbp: regs.rbp,
+        rsi: regs.rsi,
+        rdi: regs.rdi,
+        r8: regs.r8,
+        r9: regs.r9,
+        r10: regs.r10,
+        r11: regs.r11,
+        r12: regs.r12,
+        r13: regs.r13,
+        r14: regs.r14,
+        r15: regs.r15,
+        rflags: regs.rflags,
+    })
+}
+
+pub fn vcpu_write_state(vcpu: usize, state: &VcpuState) -> Result<()> {
+    let vcpu = get_vcpu(vcpu)?;
+    // keep the instruction pointer
+    let rip = vcpu.get_regs()?.rip;
+    let regs = KvmRegs {
+        rax: state.rax,
+        rbx: state.rbx,
+        rcx: state.rcx,
+        rdx: state.rdx,
+        rsi: state.rsi,
+        rdi: state.rdi,
+        rsp: state.rsp,
+        rbp: state.rbp,
+        r8: state.r8,
+        r9: state.r9,
+        r10: state.r10,
+        r11: state.r11,
+        r12: state.r12,
+        r13: state.r13,
+        r14: state.r14,
+        r15: state.r15,
+        rip,
+        rflags: state.rflags,
+    };
+    ioctl(&vcpu.fd, KVM_SET_REGS, &regs as *const _ as usize)?;
+    Ok(())
+}
+
+pub fn vcpu_write_io(vcpu: usize, data: &[u8]) -> Result<()> {
+    let vcpu = get_vcpu(vcpu)?;
+    let buf = match vcpu.run().exit_reason {
+        KVM_EXIT_IO if vcpu.run_io().direction == KVM_EXIT_IO_IN => vcpu.io_data(),
+        KVM_EXIT_MMIO if vcpu.run_mmio().is_write == 0 => {
+            let len = vcpu.run_mmio().len as usize;
+            &mut vcpu.run_mmio().data[..len]
+        }
+        _ => return Err(HalError),
+    };
+    let len = buf.len().min(data.len());
+    buf[..len].copy_from_slice(&data[..len]);
+    Ok(())
+}
+
+pub fn vcpu_interrupt(vcpu: usize, vector: u32) -> Result<()> {
+    get_vcpu(vcpu)?.interrupts.lock().unwrap().push_back(vector);
+    Ok(())
+}
@@ -1,5 +1,5 @@
 #![feature(asm)]
-#![feature(linkage)]
+#![feature(global_asm)]
 #![deny(warnings)]
 
 extern crate alloc;
@@ -8,71 +8,919 @@ extern crate log;
 
 use {
     alloc::boxed::Box,
-    alloc::collections::VecDeque,
+    alloc::collections::BTreeMap,
+    alloc::sync::Arc,
     async_std::task_local,
+    core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
     core::time::Duration,
-    core::{cell::Cell, future::Future, pin::Pin},
+    core::{
+        cell::Cell,
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll, Waker},
+    },
     git_version::git_version,
     lazy_static::*,
     std::fmt::{Debug, Formatter},
     std::fs::{File, OpenOptions},
     std::io::Error,
     std::os::unix::io::AsRawFd,
-    std::sync::Mutex,
-    std::time::SystemTime,
+    std::sync::{Condvar, Mutex},
+    std::time::Instant,
     tempfile::tempdir,
 };
 
+use self::frame::FrameAllocator;
 use kernel_hal::vdso::*;
 pub use kernel_hal::{defs::*, *};
 pub use trapframe::syscall_fn_entry as syscall_entry;
 
+#[cfg(all(any(target_os = "linux", target_os = "macos"), target_arch = "x86_64"))]
+mod fault;
+mod frame;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod kvm;
+#[cfg(feature = "mock")]
+pub mod mock;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod preempt;
+
 #[repr(C)]
 pub struct Thread {
     thread: usize,
 }
 
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
 impl Thread {
-    #[export_name = "hal_thread_spawn"]
-    pub fn spawn(
-        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
-        _vmtoken: usize,
-    ) -> Self {
-        async_std::task::spawn(future);
+    /// Spawn a new thread, polled with the address space of `vmtoken`
+    /// active, or none if it is 0.
+    ///
+    /// It is a task of `async-std`, or runs on a host thread of its own
+    /// pinned to the host CPUs of `affinity` if it excludes some of them.
+    pub fn spawn(future: BoxFuture, vmtoken: usize, affinity: CpuAffinity) -> Self {
+        let future: BoxFuture = if vmtoken == 0 {
+            future
+        } else {
+            Box::pin(AddressSpaceFuture { future, vmtoken })
+        };
+        if host_cpu_set(affinity).is_some() {
+            spawn_pinned(future, affinity, (0, 0));
+        } else {
+            async_std::task::spawn(ThreadFuture {
+                future: Some(future),
+                pinned: false,
+            });
+        }
         Thread { thread: 0 }
     }
 
-    #[export_name = "hal_thread_set_tid"]
+    /// Change the CPUs the current thread runs on, once it is pending.
+    pub fn set_affinity(affinity: CpuAffinity) {
+        NEW_AFFINITY.with(|x| x.set(Some(affinity)));
+    }
+
     pub fn set_tid(tid: u64, pid: u64) {
         TID.with(|x| x.set(tid));
         PID.with(|x| x.set(pid));
     }
 
-    #[export_name = "hal_thread_get_tid"]
     pub fn get_tid() -> (u64, u64) {
         (TID.with(|x| x.get()), PID.with(|x| x.get()))
     }
 }
 
+/// A thread of the HAL, which moves to a pinned host thread once its
+/// affinity excludes some host CPUs.
+struct ThreadFuture {
+    future: Option<BoxFuture>,
+    /// Whether it runs on a host thread of its own.
+    pinned: bool,
+}
+
+impl Future for ThreadFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let future = self.future.as_mut().expect("polled after it moves");
+        let poll = future.as_mut().poll(cx);
+        let affinity = match NEW_AFFINITY.with(Cell::take) {
+            Some(affinity) if poll.is_pending() => affinity,
+            _ => return poll,
+        };
+        if self.pinned {
+            pin_host_thread(affinity);
+        } else if host_cpu_set(affinity).is_some() {
+            // it is polled again on the new host thread to register the waker
+            let future = self.future.take().unwrap();
+            spawn_pinned(future, affinity, Thread::get_tid());
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+/// Spawn a host thread running `future` as the task of `tid` and `pid`,
+/// pinned to the host CPUs of `affinity`.
+fn spawn_pinned(future: BoxFuture, affinity: CpuAffinity, (tid, pid): (u64, u64)) {
+    std::thread::Builder::new()
+        .name("pinned".into())
+        .spawn(move || {
+            pin_host_thread(affinity);
+            async_std::task::block_on(async move {
+                Thread::set_tid(tid, pid);
+                let pinned = ThreadFuture {
+                    future: Some(future),
+                    pinned: true,
+                };
+                pinned.await
+            })
+        })
+        .expect("failed to spawn a pinned thread");
+}
+
+/// The host CPUs in `affinity`, `None` if it excludes none of the online
+/// ones, or includes none of them.
+fn host_cpu_set(affinity: CpuAffinity) -> Option<u64> {
+    let online = match cpu_count() {
+        count if count >= 64 => u64::MAX,
+        count => (1 << count) - 1,
+    };
+    Some(affinity & online).filter(|&set| set != 0 && set != online)
+}
+
+/// Pin the current host thread to the host CPUs of `affinity`, or all of them
+/// if it excludes none.
+#[cfg(target_os = "linux")]
+fn pin_host_thread(affinity: CpuAffinity) {
+    let set = host_cpu_set(affinity).unwrap_or(u64::MAX);
+    unsafe {
+        let mut cpu_set: libc::cpu_set_t = core::mem::zeroed();
+        for cpu in (0..cpu_count().min(64) as usize).filter(|cpu| set & 1 << cpu != 0) {
+            libc::CPU_SET(cpu, &mut cpu_set);
+        }
+        let ret = libc::sched_setaffinity(0, core::mem::size_of_val(&cpu_set), &cpu_set);
+        if ret != 0 {
+            warn!("failed to set the affinity: {}", Error::last_os_error());
+        }
+    }
+}
+
+/// The host threads can not be pinned on macOS.
+#[cfg(not(target_os = "linux"))]
+fn pin_host_thread(_affinity: CpuAffinity) {}
+
+/// A future polled with the address space of `vmtoken` active.
+struct AddressSpaceFuture {
+    future: BoxFuture,
+    vmtoken: usize,
+}
+
+impl Future for AddressSpaceFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let vmtoken = self.vmtoken;
+        poll_address_space(vmtoken, cx, |cx| self.future.as_mut().poll(cx))
+    }
+}
+
 task_local! {
     static TID: Cell<u64> = Cell::new(0);
     static PID: Cell<u64> = Cell::new(0);
+    /// The affinity set by the current task, to be applied once it is
+    /// pending.
+    static NEW_AFFINITY: Cell<Option<CpuAffinity>> = Cell::new(None);
 }
 
-/// Get current time.
-#[export_name = "hal_timer_now"]
+/// Get the monotonic time, converted from the ticks of the timebase.
 pub fn timer_now() -> Duration {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .unwrap()
+    let nanos = timer_ticks() as u128 * 1_000_000_000 / timer_ticks_per_second() as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Read the system clock.
+pub fn rtc_now() -> Option<Duration> {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+}
+
+/// Read the TSC, which is also read by the vDSO without entering the kernel.
+#[cfg(target_arch = "x86_64")]
+pub fn timer_ticks() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Nanoseconds elapsed since the first call.
+#[cfg(not(target_arch = "x86_64"))]
+pub fn timer_ticks() -> u64 {
+    lazy_static! {
+        static ref START: Instant = Instant::now();
+    }
+    START.elapsed().as_nanos() as u64
+}
+
+/// Get the frequency of the TSC, calibrated against the host clock once.
+#[cfg(target_arch = "x86_64")]
+pub fn timer_ticks_per_second() -> u64 {
+    lazy_static! {
+        static ref TSC_FREQUENCY: u64 = {
+            let (instant, tsc) = (Instant::now(), timer_ticks());
+            std::thread::sleep(Duration::from_millis(10));
+            let ticks = timer_ticks() - tsc;
+            let khz = ticks as u128 * 1_000_000 / instant.elapsed().as_nanos();
+            khz as u64 * 1000
+        };
+    }
+    *TSC_FREQUENCY
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+pub fn timer_ticks_per_second() -> u64 {
+    1_000_000_000
+}
+
+/// Set a new timer.
+///
+/// After `deadline`, the `callback` will be called on the timer thread.
+pub fn timer_set(deadline: Duration, callback: Box<dyn FnOnce(Duration) + Send + Sync>) -> u64 {
+    host::timer_set(deadline, callback)
+}
+
+/// Cancel the timer `id`.
+pub fn timer_cancel(id: u64) {
+    host::timer_cancel(id)
+}
+
+/// A registered IRQ handler.
+struct IrqEntry {
+    handler: Arc<dyn Fn() + Send + Sync>,
+    enabled: bool,
+}
+
+lazy_static! {
+    static ref IRQS: Mutex<BTreeMap<u32, IrqEntry>> = Mutex::new(BTreeMap::new());
+}
+
+/// The vector of the timer, reported when the user code is preempted.
+const TIMER_VECTOR: u32 = 0x20;
+
+/// The IRQ vectors available to devices, after the exception vectors and the
+/// timer.
+pub fn irq_range() -> core::ops::Range<u32> {
+    TIMER_VECTOR + 1..0x200
+}
+
+/// Register the handler of an IRQ.
+///
+/// There is no hardware IRQ on unix, they are raised by `irq_handle`.
+pub fn irq_register(vector: u32, _mode: IrqMode, handler: IrqHandler) -> Result<()> {
+    let mut irqs = IRQS.lock().unwrap();
+    if irqs.contains_key(&vector) {
+        return Err(HalError);
+    }
+    let entry = IrqEntry {
+        handler: Arc::from(handler),
+        enabled: false,
+    };
+    irqs.insert(vector, entry);
+    Ok(())
+}
+
+/// Unregister the handler of an IRQ.
+pub fn irq_unregister(vector: u32) -> Result<()> {
+    IRQS.lock().unwrap().remove(&vector).ok_or(HalError)?;
+    Ok(())
+}
+
+/// Enable an IRQ.
+pub fn irq_enable(vector: u32) {
+    if let Some(entry) = IRQS.lock().unwrap().get_mut(&vector) {
+        entry.enabled = true;
+    }
+}
+
+/// Disable an IRQ.
+pub fn irq_disable(vector: u32) {
+    if let Some(entry) = IRQS.lock().unwrap().get_mut(&vector) {
+        entry.enabled = false;
+    }
+}
+
+/// Raise an IRQ, return whether it is handled.
+///
+/// The handler is not called if the IRQ is not registered or disabled.
+pub fn irq_handle(vector: u32) -> bool {
+    if vector == TIMER_VECTOR {
+        // the timers are fired by the timer thread
+        return true;
+    }
+    let handler = match IRQS.lock().unwrap().get(&vector) {
+        Some(entry) if entry.enabled => entry.handler.clone(),
+        _ => return false,
+    };
+    handler();
+    true
+}
+
+/// Grant or revoke the access to I/O ports.
+///
+/// There is no I/O port on unix, do nothing.
+pub fn ioport_set_access(_port: u16, _len: u32, _enable: bool) -> Result<()> {
+    Ok(())
+}
+
+/// A simulated PCI function.
+struct PciFunction {
+    info: PciDeviceInfo,
+    config: [u8; PCI_CONFIG_SIZE],
+    msi_base: Option<u32>,
+}
+
+const PCI_CONFIG_SIZE: usize = 256;
+
+/// The first vector allocated to MSI.
+const MSI_VECTOR_BASE: u32 = 0x100;
+
+lazy_static! {
+    static ref PCI_FUNCTIONS: Mutex<BTreeMap<PciAddr, PciFunction>> = Mutex::new(BTreeMap::new());
+}
+
+/// Add a simulated PCI function to the bus.
+///
+/// There is no PCI bus on unix, the functions are simulated with their
+/// config space initialized from `info`.
+pub fn pci_add_device(info: PciDeviceInfo) {
+    let mut config = [0u8; PCI_CONFIG_SIZE];
+    config[0..2].copy_from_slice(&info.vendor_id.to_le_bytes());
+    config[2..4].copy_from_slice(&info.device_id.to_le_bytes());
+    config[8] = info.revision_id;
+    config[9] = info.program_interface;
+    config[0xa] = info.sub_class;
+    config[0xb] = info.base_class;
+    config[0x3c] = info.legacy_irq.unwrap_or(0xff) as u8;
+    let function = PciFunction {
+        info,
+        config,
+        msi_base: None,
+    };
+    PCI_FUNCTIONS.lock().unwrap().insert(info.addr, function);
+}
+
+pub fn pci_enumerate() -> Vec<PciDeviceInfo> {
+    let functions = PCI_FUNCTIONS.lock().unwrap();
+    functions.values().map(|f| f.info).collect()
+}
+
+pub fn pci_config_read(addr: PciAddr, offset: usize, width: usize) -> Result<u32> {
+    let functions = PCI_FUNCTIONS.lock().unwrap();
+    let function = functions.get(&addr).ok_or(HalError)?;
+    let bytes = function
+        .config
+        .get(offset..offset + width)
+        .ok_or(HalError)?;
+    let mut value = [0u8; 4];
+    value[..width].copy_from_slice(bytes);
+    Ok(u32::from_le_bytes(value))
+}
+
+pub fn pci_config_write(addr: PciAddr, offset: usize, width: usize, value: u32) -> Result<()> {
+    let mut functions = PCI_FUNCTIONS.lock().unwrap();
+    let function = functions.get_mut(&addr).ok_or(HalError)?;
+    let bytes = function
+        .config
+        .get_mut(offset..offset + width)
+        .ok_or(HalError)?;
+    bytes.copy_from_slice(&value.to_le_bytes()[..width]);
+    Ok(())
+}
+
+pub fn pci_msi_alloc(addr: PciAddr, count: u32) -> Result<u32> {
+    static NEXT_VECTOR: AtomicU32 = AtomicU32::new(MSI_VECTOR_BASE);
+    let mut functions = PCI_FUNCTIONS.lock().unwrap();
+    let function = functions.get_mut(&addr).ok_or(HalError)?;
+    if count > function.info.msi_count || function.msi_base.is_some() {
+        return Err(HalError);
+    }
+    let base = NEXT_VECTOR.fetch_add(count, Ordering::SeqCst);
+    function.msi_base = Some(base);
+    Ok(base)
+}
+
+pub fn pci_msi_free(addr: PciAddr) {
+    if let Some(function) = PCI_FUNCTIONS.lock().unwrap().get_mut(&addr) {
+        function.msi_base = None;
+    }
+}
+
+/// The size of the blocks of the file-backed block devices.
+const BLOCK_SIZE: usize = 512;
+
+lazy_static! {
+    static ref BLOCK_FILES: Mutex<Vec<File>> = Mutex::new(Vec::new());
+}
+
+/// Add the file at `path` as a block device.
+///
+/// The blocks past the end of the file are not accessible, and the file is
+/// written directly.
+pub fn block_add_file(path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    BLOCK_FILES.lock().unwrap().push(file);
+    Ok(())
+}
+
+pub fn block_devices() -> Vec<BlockDeviceInfo> {
+    let files = BLOCK_FILES.lock().unwrap();
+    files
+        .iter()
+        .map(|file| BlockDeviceInfo {
+            block_size: BLOCK_SIZE,
+            block_count: file.metadata().map_or(0, |m| m.len()) / BLOCK_SIZE as u64,
+        })
+        .collect()
+}
+
+pub fn block_read(index: usize, block: u64, buf: &mut [u8]) -> Result<()> {
+    use std::os::unix::fs::FileExt;
+    if buf.len() % BLOCK_SIZE != 0 {
+        return Err(HalError);
+    }
+    let files = BLOCK_FILES.lock().unwrap();
+    let file = files.get(index).ok_or(HalError)?;
+    file.read_exact_at(buf, block * BLOCK_SIZE as u64)
+        .map_err(|_| HalError)
+}
+
+pub fn block_write(index: usize, block: u64, buf: &[u8]) -> Result<()> {
+    use std::os::unix::fs::FileExt;
+    if buf.len() % BLOCK_SIZE != 0 {
+        return Err(HalError);
+    }
+    let files = BLOCK_FILES.lock().unwrap();
+    let file = files.get(index).ok_or(HalError)?;
+    let end = block * BLOCK_SIZE as u64 + buf.len() as u64;
+    if end > file.metadata().map_err(|_| HalError)?.len() {
+        return Err(HalError);
+    }
+    file.write_all_at(buf, block * BLOCK_SIZE as u64)
+        .map_err(|_| HalError)
+}
+
+/// A simulated network device, which receives the frames sent by itself.
+struct LoopbackNet {
+    mac: [u8; 6],
+    callback: Option<Arc<dyn Fn(&[u8]) + Send + Sync>>,
+}
+
+lazy_static! {
+    static ref NET_DEVICES: Mutex<Vec<LoopbackNet>> = Mutex::new(Vec::new());
+}
+
+/// Add a simulated network device with `mac`, looping the frames back.
+pub fn net_add_loopback(mac: [u8; 6]) {
+    let device = LoopbackNet {
+        mac,
+        callback: None,
+    };
+    NET_DEVICES.lock().unwrap().push(device);
+}
+
+pub fn net_devices() -> Vec<NetDeviceInfo> {
+    let devices = NET_DEVICES.lock().unwrap();
+    devices
+        .iter()
+        .map(|device| NetDeviceInfo {
+            mac: device.mac,
+            mtu: 1500,
+        })
+        .collect()
+}
+
+pub fn net_link_up(index: usize) -> bool {
+    index < NET_DEVICES.lock().unwrap().len()
+}
+
+/// Send an ethernet `frame`, which is received on the same device.
+pub fn net_send(index: usize, frame: &[u8]) -> Result<()> {
+    let devices = NET_DEVICES.lock().unwrap();
+    let callback = devices.get(index).ok_or(HalError)?.callback.clone();
+    drop(devices);
+    if let Some(callback) = callback {
+        callback(frame);
+    }
+    Ok(())
 }
 
+pub fn net_set_rx_callback(index: usize, callback: Option<NetRxCallback>) -> Result<()> {
+    let mut devices = NET_DEVICES.lock().unwrap();
+    let device = devices.get_mut(index).ok_or(HalError)?;
+    device.callback = callback.map(Arc::from);
+    Ok(())
+}
+
+/// PSCI functions emulated by the SMC.
+const PSCI_VERSION: u32 = 0x8400_0000;
+const PSCI_FEATURES: u32 = 0x8400_000a;
+
+/// The result of an unknown SMC function.
+const SMC_UNKNOWN: u64 = u64::MAX;
+
+/// Emulate the SMC, as a secure monitor only supporting the PSCI version
+/// and feature queries.
+pub fn smc_call(params: &SmcParams) -> SmcResult {
+    let arg0 = match params.func_id {
+        // PSCI 1.0
+        PSCI_VERSION => 0x1_0000,
+        PSCI_FEATURES if matches!(params.arg1 as u32, PSCI_VERSION | PSCI_FEATURES) => 0,
+        _ => SMC_UNKNOWN,
+    };
+    SmcResult {
+        arg0,
+        ..Default::default()
+    }
+}
+
+/// The file to persist the crashlog across runs.
+fn crashlog_path() -> std::path::PathBuf {
+    std::env::temp_dir().join("zcore-crashlog")
+}
+
+/// Save the crashlog to a file, to be recovered in the next run.
+pub fn crashlog_save(data: &[u8]) {
+    if let Err(e) = std::fs::write(crashlog_path(), data) {
+        warn!("failed to save crashlog: {}", e);
+    }
+}
+
+/// Take the crashlog saved in the last run.
+pub fn crashlog_load() -> Vec<u8> {
+    let path = crashlog_path();
+    let data = std::fs::read(&path).unwrap_or_default();
+    let _ = std::fs::remove_file(path);
+    data
+}
+
+/// Reboot the system, which exits the process on unix.
+pub fn reboot() -> ! {
+    std::process::exit(0)
+}
+
+/// Shut down the system, which exits the process on unix.
+pub fn shutdown() -> ! {
+    std::process::exit(0)
+}
+
+/// Fill `buf` with random bytes from the host.
+pub fn rand_bytes(buf: &mut [u8]) {
+    getrandom::getrandom(buf).expect("failed to get random bytes");
+}
+
+/// The heap of the host, counted for `heap_stats`.
+#[global_allocator]
+static HEAP: CountingHeap<std::alloc::System> = CountingHeap::new(std::alloc::System);
+
 /// Initialize the HAL.
 ///
 /// This function must be called at the beginning.
 pub fn init() {
-    #[cfg(target_os = "macos")]
-    unimplemented!()
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), target_arch = "x86_64"))]
+    fault::init();
+    kernel_hal::set_hal(&UnixHal);
+}
+
+/// The HAL of the LibOS on unix.
+pub struct UnixHal;
+
+impl Hal for UnixHal {
+    fn thread_spawn(&self, future: BoxFuture, vmtoken: usize, affinity: CpuAffinity) {
+        Thread::spawn(future, vmtoken, affinity);
+    }
+
+    fn thread_set_affinity(&self, affinity: CpuAffinity) {
+        Thread::set_affinity(affinity)
+    }
+
+    fn thread_set_tid(&self, tid: u64, pid: u64) {
+        Thread::set_tid(tid, pid)
+    }
+
+    fn thread_get_tid(&self) -> (u64, u64) {
+        Thread::get_tid()
+    }
+
+    fn timer_now(&self) -> Duration {
+        timer_now()
+    }
+
+    fn rtc_now(&self) -> Option<Duration> {
+        rtc_now()
+    }
+
+    fn timer_ticks(&self) -> u64 {
+        timer_ticks()
+    }
+
+    fn timer_ticks_per_second(&self) -> u64 {
+        timer_ticks_per_second()
+    }
+
+    fn timer_set(
+        &self,
+        deadline: Duration,
+        callback: Box<dyn FnOnce(Duration) + Send + Sync>,
+    ) -> u64 {
+        timer_set(deadline, callback)
+    }
+
+    fn timer_cancel(&self, id: u64) {
+        timer_cancel(id)
+    }
+
+    fn irq_register(&self, vector: u32, mode: IrqMode, handler: IrqHandler) -> Result<()> {
+        irq_register(vector, mode, handler)
+    }
+
+    fn irq_unregister(&self, vector: u32) -> Result<()> {
+        irq_unregister(vector)
+    }
+
+    fn irq_enable(&self, vector: u32) {
+        irq_enable(vector)
+    }
+
+    fn irq_disable(&self, vector: u32) {
+        irq_disable(vector)
+    }
+
+    fn irq_handle(&self, vector: u32) -> bool {
+        irq_handle(vector)
+    }
+
+    fn irq_range(&self) -> core::ops::Range<u32> {
+        irq_range()
+    }
+
+    fn ioport_set_access(&self, port: u16, len: u32, enable: bool) -> Result<()> {
+        ioport_set_access(port, len, enable)
+    }
+
+    fn memory_map(&self) -> Vec<MemoryRegion> {
+        memory_map()
+    }
+
+    fn physmem_size(&self) -> u64 {
+        physmem_size()
+    }
+
+    fn version_string(&self) -> &'static str {
+        version_string()
+    }
+
+    fn cpu_count(&self) -> u32 {
+        cpu_count()
+    }
+
+    fn cpu_id(&self) -> u32 {
+        cpu_id()
+    }
+
+    fn libos_syscall_entry(&self) -> Option<usize> {
+        libos_syscall_entry()
+    }
+
+    fn cache_line_size(&self) -> u32 {
+        cache_line_size()
+    }
+
+    fn cpu_features(&self) -> Features {
+        cpu_features()
+    }
+
+    fn pci_enumerate(&self) -> Vec<PciDeviceInfo> {
+        pci_enumerate()
+    }
+
+    fn pci_config_read(&self, addr: PciAddr, offset: usize, width: usize) -> Result<u32> {
+        pci_config_read(addr, offset, width)
+    }
+
+    fn pci_config_write(
+        &self,
+        addr: PciAddr,
+        offset: usize,
+        width: usize,
+        value: u32,
+    ) -> Result<()> {
+        pci_config_write(addr, offset, width, value)
+    }
+
+    fn pci_msi_alloc(&self, addr: PciAddr, count: u32) -> Result<u32> {
+        pci_msi_alloc(addr, count)
+    }
+
+    fn pci_msi_free(&self, addr: PciAddr) {
+        pci_msi_free(addr)
+    }
+
+    fn block_devices(&self) -> Vec<BlockDeviceInfo> {
+        block_devices()
+    }
+
+    fn block_read(&self, index: usize, block: u64, buf: &mut [u8]) -> Result<()> {
+        block_read(index, block, buf)
+    }
+
+    fn block_write(&self, index: usize, block: u64, buf: &[u8]) -> Result<()> {
+        block_write(index, block, buf)
+    }
+
+    fn net_devices(&self) -> Vec<NetDeviceInfo> {
+        net_devices()
+    }
+
+    fn net_link_up(&self, index: usize) -> bool {
+        net_link_up(index)
+    }
+
+    fn net_send(&self, index: usize, frame: &[u8]) -> Result<()> {
+        net_send(index, frame)
+    }
+
+    fn net_set_rx_callback(&self, index: usize, callback: Option<NetRxCallback>) -> Result<()> {
+        net_set_rx_callback(index, callback)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn guest_create(&self) -> Result<usize> {
+        kvm::guest_create()
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn guest_destroy(&self, guest: usize) {
+        kvm::guest_destroy(guest)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn guest_map(
+        &self,
+        guest: usize,
+        gpaddr: GuestPhysAddr,
+        paddr: PhysAddr,
+        flags: MMUFlags,
+    ) -> Result<()> {
+        kvm::guest_map(guest, gpaddr, paddr, flags)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn guest_unmap(&self, guest: usize, gpaddr: GuestPhysAddr) -> Result<()> {
+        kvm::guest_unmap(guest, gpaddr)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_create(&self, guest: usize, entry: GuestPhysAddr) -> Result<usize> {
+        kvm::vcpu_create(guest, entry)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_destroy(&self, vcpu: usize) {
+        kvm::vcpu_destroy(vcpu)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_resume(&self, vcpu: usize) -> Result<VcpuExit> {
+        kvm::vcpu_resume(vcpu)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_read_state(&self, vcpu: usize) -> Result<VcpuState> {
+        kvm::vcpu_read_state(vcpu)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_write_state(&self, vcpu: usize, state: &VcpuState) -> Result<()> {
+        kvm::vcpu_write_state(vcpu, state)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_write_io(&self, vcpu: usize, data: &[u8]) -> Result<()> {
+        kvm::vcpu_write_io(vcpu, data)
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn vcpu_interrupt(&self, vcpu: usize, vector: u32) -> Result<()> {
+        kvm::vcpu_interrupt(vcpu, vector)
+    }
+
+    fn smc_call(&self, params: &SmcParams) -> SmcResult {
+        smc_call(params)
+    }
+
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), target_arch = "x86_64"))]
+    unsafe fn user_copy(&self, dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+        fault::user_copy(dst, src, len)
+    }
+
+    fn crashlog_save(&self, data: &[u8]) {
+        crashlog_save(data)
+    }
+
+    fn crashlog_load(&self) -> Vec<u8> {
+        crashlog_load()
+    }
+
+    fn reboot(&self) -> ! {
+        reboot()
+    }
+
+    fn shutdown(&self) -> ! {
+        shutdown()
+    }
+
+    fn rand_bytes(&self, buf: &mut [u8]) {
+        rand_bytes(buf)
+    }
+
+    fn frame_alloc(&self) -> Option<PhysAddr> {
+        let frame = PhysFrame::alloc()?;
+        let paddr = frame.paddr;
+        core::mem::forget(frame);
+        Some(paddr)
+    }
+
+    fn frame_alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<PhysAddr> {
+        PhysFrame::alloc_contiguous_base(size, align_log2)
+    }
+
+    fn frame_dealloc(&self, paddr: PhysAddr) {
+        drop(PhysFrame { paddr })
+    }
+
+    fn zero_frame_paddr(&self) -> PhysAddr {
+        PhysFrame::zero_frame_addr()
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        frame_stats()
+    }
+
+    fn pmem_read(&self, paddr: PhysAddr, buf: &mut [u8]) {
+        pmem_read(paddr, buf)
+    }
+
+    fn pmem_write(&self, paddr: PhysAddr, buf: &[u8]) {
+        pmem_write(paddr, buf)
+    }
+
+    fn pmem_zero(&self, paddr: PhysAddr, len: usize) {
+        pmem_zero(paddr, len)
+    }
+
+    fn pmem_fetch_add_u64(&self, paddr: PhysAddr, value: u64) -> u64 {
+        pmem_fetch_add_u64(paddr, value)
+    }
+
+    fn frame_copy(&self, src: PhysAddr, target: PhysAddr) {
+        frame_copy(src, target)
+    }
+
+    fn frame_copy_n(&self, frames: &[(PhysAddr, PhysAddr)]) {
+        frame_copy_n(frames)
+    }
+
+    fn frame_flush(&self, target: PhysAddr) {
+        frame_flush(target)
+    }
+
+    fn pt_new(&self) -> Box<dyn PageTableTrait> {
+        Box::new(PageTable::new())
+    }
+
+    fn pt_current(&self) -> Box<dyn PageTableTrait> {
+        Box::new(PageTable::current())
+    }
+
+    fn tlb_flush(&self, vaddr: core::ops::Range<VirtAddr>, vmtoken: usize) {
+        tlb_flush(vaddr, vmtoken)
+    }
+
+    fn context_run(&self, context: &mut UserContext) -> Result<()> {
+        unsafe { context_run(context) };
+        Ok(())
+    }
+
+    fn serial_read(&self, buf: &mut [u8]) -> usize {
+        serial_read(buf)
+    }
+
+    fn serial_set_callback(&self, callback: Box<dyn FnOnce() + Send + Sync>) {
+        serial_set_callback(callback)
+    }
+
+    fn serial_write(&self, s: &str) {
+        serial_write(s)
+    }
 }
 
 #[repr(C)]
@@ -86,16 +934,112 @@ impl Debug for PhysFrame {
     }
 }
 
-const PMEM_SIZE: usize = 0x4000_0000; // 1GiB
+/// The size of the physical memory if `ZCORE_PMEM_SIZE` is not set.
+const DEFAULT_PMEM_SIZE: usize = 0x4000_0000; // 1GiB
+/// The pmem file grows by this size at least.
+const PMEM_GROW_SIZE: usize = 0x100_0000; // 16MiB
+
+lazy_static! {
+    /// The size of the physical memory, from `ZCORE_PMEM_SIZE` in bytes with
+    /// an optional suffix `K`, `M` or `G`, rounded down to the pages.
+    static ref PMEM_SIZE: usize = {
+        let size = match std::env::var("ZCORE_PMEM_SIZE") {
+            Ok(var) => parse_size(&var).expect("invalid ZCORE_PMEM_SIZE"),
+            Err(_) => DEFAULT_PMEM_SIZE,
+        };
+        let size = size & !(PAGE_SIZE - 1);
+        // the zero frame and one more at least
+        assert!(size >= 2 * PAGE_SIZE, "ZCORE_PMEM_SIZE is too small");
+        size
+    };
+}
+
+/// Parse a size like `512M`.
+fn parse_size(s: &str) -> Option<usize> {
+    let s = s.trim();
+    let (digits, shift) = match s.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&s[..s.len() - 1], 10),
+        b'M' => (&s[..s.len() - 1], 20),
+        b'G' => (&s[..s.len() - 1], 30),
+        _ => (s, 0),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(1 << shift)
+}
 const PAGE_SIZE: usize = 0x1000;
 fn page_aligned(x: VirtAddr) -> bool {
     x % PAGE_SIZE == 0
 }
 
+/// The simulated MMIO region, the conventional PCI hole below 4GiB.
+const MMIO_BASE: PhysAddr = 0xc000_0000;
+const MMIO_SIZE: usize = 0x4000_0000;
+
+/// Get the physical memory map, the RAM backed by the pmem file and the
+/// simulated MMIO region.
+pub fn memory_map() -> Vec<MemoryRegion> {
+    vec![
+        MemoryRegion {
+            kind: MemoryRegionKind::Ram,
+            addr: 0,
+            size: *PMEM_SIZE,
+        },
+        MemoryRegion {
+            kind: MemoryRegionKind::Mmio,
+            addr: MMIO_BASE,
+            size: MMIO_SIZE,
+        },
+    ]
+}
+
+/// The syscalls are function calls to the entry of `trapframe`.
+pub fn libos_syscall_entry() -> Option<usize> {
+    Some(syscall_entry as usize)
+}
+
+/// Get the number of online CPUs of the host.
+pub fn cpu_count() -> u32 {
+    let count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    count.max(1) as u32
+}
+
+/// Get the host CPU running the current thread, only known on Linux.
+pub fn cpu_id() -> u32 {
+    #[cfg(target_os = "linux")]
+    let cpu = unsafe { libc::sched_getcpu() };
+    #[cfg(not(target_os = "linux"))]
+    let cpu = 0;
+    cpu.max(0) as u32
+}
+
+/// Get the size of the L1 data cache line of the host.
+pub fn cache_line_size() -> u32 {
+    #[cfg(target_os = "linux")]
+    let size = unsafe { libc::sysconf(libc::_SC_LEVEL1_DCACHE_LINESIZE) };
+    #[cfg(not(target_os = "linux"))]
+    let size = 0;
+    if size > 0 {
+        size as u32
+    } else {
+        64
+    }
+}
+
+/// No CPU features or debug registers are exposed to the user programs.
+pub fn cpu_features() -> Features {
+    Features::default()
+}
+
 lazy_static! {
     static ref FRAME_FILE: File = create_pmem_file();
+    /// Held when growing the pmem file.
+    static ref PMEM_GROW_LOCK: Mutex<()> = Mutex::new(());
 }
 
+/// The length of the pmem file, grown on demand up to `PMEM_SIZE`.
+static PMEM_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Create the pmem file of the first `PMEM_GROW_SIZE` bytes, and mmap the
+/// whole physical memory, beyond the end of the file.
 fn create_pmem_file() -> File {
     let dir = tempdir().expect("failed to create pmem dir");
     let path = dir.path().join("pmem");
@@ -111,14 +1055,35 @@ fn create_pmem_file() -> File {
         .create(true)
         .open(&path)
         .expect("failed to create pmem file");
-    file.set_len(PMEM_SIZE as u64)
-        .expect("failed to resize file");
-    trace!("create pmem file: path={:?}, size={:#x}", path, PMEM_SIZE);
+    let len = PMEM_GROW_SIZE.min(*PMEM_SIZE);
+    file.set_len(len as u64).expect("failed to resize file");
+    PMEM_LEN.store(len, Ordering::Release);
+    trace!("create pmem file: path={:?}, size={:#x}", path, *PMEM_SIZE);
     let prot = libc::PROT_READ | libc::PROT_WRITE;
-    mmap(file.as_raw_fd(), 0, PMEM_SIZE, phys_to_virt(0), prot);
+    mmap(file.as_raw_fd(), 0, *PMEM_SIZE, phys_to_virt(0), prot);
     file
 }
 
+/// Ensure the physical memory below `end` is mmapped, and backed by the pmem
+/// file, which grows by `PMEM_GROW_SIZE` at least.
+fn ensure_pmem(end: PhysAddr) {
+    let file = &*FRAME_FILE;
+    let end = end.min(*PMEM_SIZE);
+    if end <= PMEM_LEN.load(Ordering::Acquire) {
+        return;
+    }
+    let _guard = PMEM_GROW_LOCK.lock().unwrap();
+    let len = PMEM_LEN.load(Ordering::Acquire);
+    if end <= len {
+        return;
+    }
+    let new_len = (len + PMEM_GROW_SIZE).max(end).min(*PMEM_SIZE);
+    trace!("grow pmem file: {:#x} -> {:#x}", len, new_len);
+    file.set_len(new_len as u64)
+        .expect("failed to grow the pmem file");
+    PMEM_LEN.store(new_len, Ordering::Release);
+}
+
 /// Mmap frame file `fd` to `vaddr`.
 fn mmap(fd: libc::c_int, offset: usize, len: usize, vaddr: VirtAddr, prot: libc::c_int) {
     // workaround on macOS to write text section.
@@ -145,32 +1110,70 @@ fn mmap(fd: libc::c_int, offset: usize, len: usize, vaddr: VirtAddr, prot: libc:
 }
 
 lazy_static! {
-    static ref AVAILABLE_FRAMES: Mutex<VecDeque<usize>> =
-        Mutex::new((PAGE_SIZE..PMEM_SIZE).step_by(PAGE_SIZE).collect());
+    static ref FRAME_ALLOCATOR: Mutex<FrameAllocator> = {
+        let mut allocator = FrameAllocator::new(*PMEM_SIZE / PAGE_SIZE);
+        // the first frame is reserved as the zero frame
+        allocator.reserve(0);
+        Mutex::new(allocator)
+    };
 }
 
 impl PhysFrame {
-    #[export_name = "hal_frame_alloc"]
     pub fn alloc() -> Option<Self> {
-        let ret = AVAILABLE_FRAMES
+        let ret = FRAME_ALLOCATOR
             .lock()
             .unwrap()
-            .pop_front()
-            .map(|paddr| PhysFrame { paddr });
+            .alloc()
+            .map(|index| PhysFrame {
+                paddr: index * PAGE_SIZE,
+            });
+        if let Some(frame) = &ret {
+            ensure_pmem(frame.paddr + PAGE_SIZE);
+        }
         trace!("frame alloc: {:?}", ret);
         ret
     }
-    #[export_name = "hal_zero_frame_paddr"]
+
+    /// Allocate `size` contiguous frames, aligned to `1 << align_log2` frames.
+    pub fn alloc_contiguous_base(size: usize, align_log2: usize) -> Option<PhysAddr> {
+        let ret = FRAME_ALLOCATOR
+            .lock()
+            .unwrap()
+            .alloc_contiguous(size, align_log2)
+            .map(|index| index * PAGE_SIZE);
+        if let Some(base) = ret {
+            ensure_pmem(base + size * PAGE_SIZE);
+        }
+        trace!(
+            "frame alloc contiguous: size={}, align_log2={}, base={:x?}",
+            size,
+            align_log2,
+            ret
+        );
+        ret
+    }
+
     pub fn zero_frame_addr() -> PhysAddr {
         0
     }
 }
 
 impl Drop for PhysFrame {
-    #[export_name = "hal_frame_dealloc"]
     fn drop(&mut self) {
         trace!("frame dealloc: {:?}", self);
-        AVAILABLE_FRAMES.lock().unwrap().push_back(self.paddr);
+        FRAME_ALLOCATOR
+            .lock()
+            .unwrap()
+            .dealloc(self.paddr / PAGE_SIZE);
+    }
+}
+
+/// Get statistics of the physical frame allocator.
+pub fn frame_stats() -> FrameStats {
+    FrameStats {
+        // the first frame is reserved as the zero frame
+        total: *PMEM_SIZE / PAGE_SIZE - 1,
+        free: FRAME_ALLOCATOR.lock().unwrap().free(),
     }
 }
 
@@ -181,28 +1184,21 @@ fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
     PMEM_BASE + paddr
 }
 
-/// Ensure physical memory are mmapped and accessible.
-fn ensure_mmap_pmem() {
-    FRAME_FILE.as_raw_fd();
-}
-
 /// Read physical memory from `paddr` to `buf`.
-#[export_name = "hal_pmem_read"]
 pub fn pmem_read(paddr: PhysAddr, buf: &mut [u8]) {
     trace!("pmem read: paddr={:#x}, len={:#x}", paddr, buf.len());
-    assert!(paddr + buf.len() <= PMEM_SIZE);
-    ensure_mmap_pmem();
+    assert!(paddr + buf.len() <= *PMEM_SIZE);
+    ensure_pmem(paddr + buf.len());
     unsafe {
         (phys_to_virt(paddr) as *const u8).copy_to_nonoverlapping(buf.as_mut_ptr(), buf.len());
     }
 }
 
 /// Write physical memory to `paddr` from `buf`.
-#[export_name = "hal_pmem_write"]
 pub fn pmem_write(paddr: PhysAddr, buf: &[u8]) {
     trace!("pmem write: paddr={:#x}, len={:#x}", paddr, buf.len());
-    assert!(paddr + buf.len() <= PMEM_SIZE);
-    ensure_mmap_pmem();
+    assert!(paddr + buf.len() <= *PMEM_SIZE);
+    ensure_pmem(paddr + buf.len());
     unsafe {
         buf.as_ptr()
             .copy_to_nonoverlapping(phys_to_virt(paddr) as _, buf.len());
@@ -210,35 +1206,195 @@ pub fn pmem_write(paddr: PhysAddr, buf: &[u8]) {
 }
 
 /// Zero physical memory at `[paddr, paddr + len)`
-#[export_name = "hal_pmem_zero"]
 pub fn pmem_zero(paddr: PhysAddr, len: usize) {
     trace!("pmem_zero: addr={:#x}, len={:#x}", paddr, len);
-    assert!(paddr + len <= PMEM_SIZE);
-    ensure_mmap_pmem();
+    assert!(paddr + len <= *PMEM_SIZE);
+    ensure_pmem(paddr + len);
     unsafe {
         core::ptr::write_bytes(phys_to_virt(paddr) as *mut u8, 0, len);
     }
 }
 
+/// Atomically add `value` to the `u64` at `paddr`, return the previous value.
+pub fn pmem_fetch_add_u64(paddr: PhysAddr, value: u64) -> u64 {
+    assert!(paddr % 8 == 0 && paddr + 8 <= *PMEM_SIZE);
+    ensure_pmem(paddr + 8);
+    let atomic = unsafe { &*(phys_to_virt(paddr) as *const AtomicU64) };
+    atomic.fetch_add(value, Ordering::Relaxed)
+}
+
 /// Copy content of `src` frame to `target` frame
-#[export_name = "hal_frame_copy"]
 pub fn frame_copy(src: PhysAddr, target: PhysAddr) {
     trace!("frame_copy: {:#x} <- {:#x}", target, src);
-    assert!(src + PAGE_SIZE <= PMEM_SIZE && target + PAGE_SIZE <= PMEM_SIZE);
-    ensure_mmap_pmem();
+    assert!(src + PAGE_SIZE <= *PMEM_SIZE && target + PAGE_SIZE <= *PMEM_SIZE);
+    ensure_pmem(src.max(target) + PAGE_SIZE);
     unsafe {
         let buf = phys_to_virt(src) as *const u8;
         buf.copy_to_nonoverlapping(phys_to_virt(target) as _, PAGE_SIZE);
     }
 }
 
+/// Copy the content of each `(src, target)` pair of frames, by a copy of
+/// each run contiguous in both.
+pub fn frame_copy_n(frames: &[(PhysAddr, PhysAddr)]) {
+    trace!("frame_copy_n: {} frames", frames.len());
+    for (src, target, len) in kernel_hal::copy_runs(frames) {
+        assert!(src + len <= *PMEM_SIZE && target + len <= *PMEM_SIZE);
+        ensure_pmem(src.max(target) + len);
+        unsafe {
+            let buf = phys_to_virt(src) as *const u8;
+            buf.copy_to_nonoverlapping(phys_to_virt(target) as _, len);
+        }
+    }
+}
+
 /// Flush the physical frame.
-#[export_name = "hal_frame_flush"]
 pub fn frame_flush(_target: PhysAddr) {
     // do nothing
 }
 
+/// The address spaces of the page tables.
+///
+/// All user programs share the address space of the host, so only the pages
+/// of the active address space are mapped there, and the accesses to the
+/// other ones fault. The threads of the active address space run in
+/// parallel, and it is only switched when none of them is running.
+struct AddressSpaces {
+    /// The `vmtoken` of the active address space, 0 if none.
+    active: usize,
+    /// The number of threads running with the active address space.
+    runners: usize,
+    /// The address space to switch to next, requested by the first thread
+    /// waiting for it.
+    next: Option<usize>,
+    /// The tasks waiting to enter their address spaces, woken when the last
+    /// runner leaves.
+    waiters: Vec<Waker>,
+    /// The pages mapped by each page table, with the frames and the flags.
+    spaces: BTreeMap<usize, BTreeMap<VirtAddr, (PhysAddr, MMUFlags)>>,
+}
+
+impl AddressSpaces {
+    /// Enter the address space of `vmtoken` if no thread of another one is
+    /// running and no switch to another one is requested, or request a
+    /// switch to it and return `false`.
+    fn enter(&mut self, vmtoken: usize) -> bool {
+        let ready = self.runners == 0 || self.active == vmtoken;
+        match self.next {
+            None if ready => {}
+            Some(next) if next == vmtoken && ready => self.next = None,
+            None => {
+                self.next = Some(vmtoken);
+                return false;
+            }
+            Some(_) => return false,
+        }
+        self.activate(vmtoken);
+        self.runners += 1;
+        true
+    }
+
+    /// Unmap the pages of the active address space from the host, and map
+    /// the pages of `vmtoken`.
+    fn activate(&mut self, vmtoken: usize) {
+        if self.active == vmtoken {
+            return;
+        }
+        trace!("switch address space: {} -> {}", self.active, vmtoken);
+        if let Some(pages) = self.spaces.get(&self.active) {
+            for &vaddr in pages.keys() {
+                munmap(vaddr, 1);
+            }
+        }
+        if let Some(pages) = self.spaces.get(&vmtoken) {
+            for (&vaddr, &(paddr, flags)) in pages.iter() {
+                mmap(
+                    FRAME_FILE.as_raw_fd(),
+                    paddr,
+                    PAGE_SIZE,
+                    vaddr,
+                    flags.to_mmap_prot(),
+                );
+            }
+        }
+        self.active = vmtoken;
+    }
+}
+
+lazy_static! {
+    static ref ADDRESS_SPACES: Mutex<AddressSpaces> = Mutex::new(AddressSpaces {
+        active: 0,
+        runners: 0,
+        next: None,
+        waiters: Vec::new(),
+        spaces: BTreeMap::new(),
+    });
+    /// Notified when the last thread running with an address space leaves.
+    static ref RUN_CONDVAR: Condvar = Condvar::new();
+}
+
+static NEXT_VMTOKEN: AtomicUsize = AtomicUsize::new(1);
+
+/// Poll `f` with the address space of the page table `vmtoken` active.
+///
+/// The threads of the user programs are polled by it, so the threads of the
+/// same address space run in parallel, and the others are pending until the
+/// address space is switched to theirs, without blocking the host thread.
+/// Once a switch is requested, no more threads of the active address space
+/// are started, so that none of them starves. It should not be called
+/// recursively.
+pub fn poll_address_space<T>(
+    vmtoken: usize,
+    cx: &mut Context<'_>,
+    f: impl FnOnce(&mut Context<'_>) -> Poll<T>,
+) -> Poll<T> {
+    let mut spaces = ADDRESS_SPACES.lock().unwrap();
+    if !spaces.enter(vmtoken) {
+        spaces.waiters.push(cx.waker().clone());
+        return Poll::Pending;
+    }
+    drop(spaces);
+    let _guard = RunGuard;
+    f(cx)
+}
+
+/// Run `f` with the address space of the page table `vmtoken` active,
+/// blocking the current host thread until it is.
+///
+/// It must not be called on the executor, where [`poll_address_space`] is
+/// used instead.
+pub fn with_address_space<T>(vmtoken: usize, f: impl FnOnce() -> T) -> T {
+    let mut spaces = ADDRESS_SPACES.lock().unwrap();
+    while !spaces.enter(vmtoken) {
+        spaces = RUN_CONDVAR.wait(spaces).unwrap();
+    }
+    drop(spaces);
+    let _guard = RunGuard;
+    f()
+}
+
+/// Leaves the active address space on drop, even if the thread panics.
+struct RunGuard;
+
+impl Drop for RunGuard {
+    fn drop(&mut self) {
+        let mut spaces = ADDRESS_SPACES.lock().unwrap_or_else(|e| e.into_inner());
+        spaces.runners -= 1;
+        if spaces.runners == 0 {
+            RUN_CONDVAR.notify_all();
+            // woken after the lock is released, as they may be polled at once
+            let waiters = core::mem::take(&mut spaces.waiters);
+            drop(spaces);
+            for waker in waiters {
+                waker.wake();
+            }
+        }
+    }
+}
+
 /// Page Table
+///
+/// The `table_phys` is the `vmtoken` of its address space, not an address.
 #[repr(C)]
 pub struct PageTable {
     table_phys: PhysAddr,
@@ -247,65 +1403,118 @@ pub struct PageTable {
 impl PageTable {
     /// Create a new `PageTable`.
     #[allow(clippy::new_without_default)]
-    #[export_name = "hal_pt_new"]
     pub fn new() -> Self {
-        PageTable { table_phys: 0 }
+        PageTable {
+            table_phys: NEXT_VMTOKEN.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Get the page table of the active address space, of `vmtoken` 0 if
+    /// none is.
+    pub fn current() -> Self {
+        PageTable {
+            table_phys: ADDRESS_SPACES.lock().unwrap().active,
+        }
     }
 }
 
 impl PageTableTrait for PageTable {
     /// Map the page of `vaddr` to the frame of `paddr` with `flags`.
-    #[export_name = "hal_pt_map"]
     fn map(&mut self, vaddr: VirtAddr, paddr: PhysAddr, flags: MMUFlags) -> Result<()> {
         debug_assert!(page_aligned(vaddr));
         debug_assert!(page_aligned(paddr));
-        let prot = flags.to_mmap_prot();
-        mmap(FRAME_FILE.as_raw_fd(), paddr, PAGE_SIZE, vaddr, prot);
+        let mut spaces = ADDRESS_SPACES.lock().unwrap();
+        if spaces.active == self.table_phys {
+            mmap(
+                FRAME_FILE.as_raw_fd(),
+                paddr,
+                PAGE_SIZE,
+                vaddr,
+                flags.to_mmap_prot(),
+            );
+        }
+        spaces
+            .spaces
+            .entry(self.table_phys)
+            .or_default()
+            .insert(vaddr, (paddr, flags));
         Ok(())
     }
 
     /// Unmap the page of `vaddr`.
-    #[export_name = "hal_pt_unmap"]
     fn unmap(&mut self, vaddr: VirtAddr) -> Result<()> {
         self.unmap_cont(vaddr, 1)
     }
 
     /// Change the `flags` of the page of `vaddr`.
-    #[export_name = "hal_pt_protect"]
     fn protect(&mut self, vaddr: VirtAddr, flags: MMUFlags) -> Result<()> {
         debug_assert!(page_aligned(vaddr));
-        let prot = flags.to_mmap_prot();
-        let ret = unsafe { libc::mprotect(vaddr as _, PAGE_SIZE, prot) };
-        assert_eq!(ret, 0, "failed to mprotect: {:?}", Error::last_os_error());
+        let mut spaces = ADDRESS_SPACES.lock().unwrap();
+        let active = spaces.active == self.table_phys;
+        let page = spaces
+            .spaces
+            .get_mut(&self.table_phys)
+            .and_then(|pages| pages.get_mut(&vaddr))
+            .ok_or(HalError)?;
+        page.1 = flags;
+        if active {
+            let ret = unsafe { libc::mprotect(vaddr as _, PAGE_SIZE, flags.to_mmap_prot()) };
+            assert_eq!(ret, 0, "failed to mprotect: {:?}", Error::last_os_error());
+        }
         Ok(())
     }
 
     /// Query the physical address which the page of `vaddr` maps to.
-    #[export_name = "hal_pt_query"]
     fn query(&mut self, vaddr: VirtAddr) -> Result<PhysAddr> {
         debug_assert!(page_aligned(vaddr));
-        unimplemented!()
+        let spaces = ADDRESS_SPACES.lock().unwrap();
+        spaces
+            .spaces
+            .get(&self.table_phys)
+            .and_then(|pages| pages.get(&vaddr))
+            .map(|&(paddr, _)| paddr)
+            .ok_or(HalError)
     }
 
     /// Get the physical address of root page table.
-    #[export_name = "hal_pt_table_phys"]
     fn table_phys(&self) -> PhysAddr {
         self.table_phys
     }
 
-    #[export_name = "hal_pt_unmap_cont"]
     fn unmap_cont(&mut self, vaddr: VirtAddr, pages: usize) -> Result<()> {
         if pages == 0 {
             return Ok(());
         }
         debug_assert!(page_aligned(vaddr));
-        let ret = unsafe { libc::munmap(vaddr as _, PAGE_SIZE * pages) };
-        assert_eq!(ret, 0, "failed to munmap: {:?}", Error::last_os_error());
+        let mut spaces = ADDRESS_SPACES.lock().unwrap();
+        if spaces.active == self.table_phys {
+            munmap(vaddr, pages);
+        }
+        if let Some(mapped) = spaces.spaces.get_mut(&self.table_phys) {
+            for i in 0..pages {
+                mapped.remove(&(vaddr + i * PAGE_SIZE));
+            }
+            if mapped.is_empty() {
+                spaces.spaces.remove(&self.table_phys);
+            }
+        }
         Ok(())
     }
 }
 
+/// The pages are unmapped and protected on the host at once by `munmap` and
+/// `mprotect`, there is nothing to flush.
+pub fn tlb_flush(_vaddr: core::ops::Range<VirtAddr>, _vmtoken: usize) {}
+
+/// Unmap `pages` pages at `vaddr` from the host.
+fn munmap(vaddr: VirtAddr, pages: usize) {
+    let ret = unsafe { libc::munmap(vaddr as _, PAGE_SIZE * pages) };
+    assert_eq!(ret, 0, "failed to munmap: {:?}", Error::last_os_error());
+}
+
 trait FlagsExt {
+    /// The protection of the page on the host. The cache policy is ignored,
+    /// as the frames are in the memory of the host process.
     fn to_mmap_prot(&self) -> libc::c_int;
 }
 
@@ -318,46 +1527,84 @@ impl FlagsExt for MMUFlags {
         if self.contains(MMUFlags::WRITE) {
             flags |= libc::PROT_WRITE;
         }
-        if self.contains(MMUFlags::EXECUTE) {
+        if self.is_executable() {
             flags |= libc::PROT_EXEC;
         }
         flags
     }
 }
 
-#[export_name = "hal_context_run"]
+/// Run the user context until it makes a syscall, or until the end of its
+/// time slice on Linux.
 unsafe fn context_run(context: &mut UserContext) {
+    // the signals must not be delivered on the stack of the user program
+    #[cfg(all(any(target_os = "linux", target_os = "macos"), target_arch = "x86_64"))]
+    fault::init_signal_stack();
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    preempt::run(context);
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
     context.run_fncall();
 }
 
-#[export_name = "hal_vdso_constants"]
-pub fn vdso_constants() -> VdsoConstants {
-    let tsc_frequency = 3000u16;
-    let mut constants = VdsoConstants {
-        max_num_cpus: 1,
-        features: Features {
-            cpu: 0,
-            hw_breakpoint_count: 0,
-            hw_watchpoint_count: 0,
-        },
-        dcache_line_size: 0,
-        icache_line_size: 0,
-        ticks_per_second: tsc_frequency as u64 * 1_000_000,
-        ticks_to_mono_numerator: 1000,
-        ticks_to_mono_denominator: tsc_frequency as u32,
-        physmem: PMEM_SIZE as u64,
-        version_string_len: 0,
-        version_string: Default::default(),
-    };
-    constants.set_version_string(git_version!(
+/// The size of the physical memory, the pmem file when fully grown.
+pub fn physmem_size() -> u64 {
+    *PMEM_SIZE as u64
+}
+
+/// The git revision of the source tree.
+pub fn version_string() -> &'static str {
+    git_version!(
         prefix = "git-",
         args = ["--always", "--abbrev=40", "--dirty=-dirty"]
-    ));
-    constants
+    )
+}
+
+/// Read the available input of stdin without blocking.
+pub fn serial_read(buf: &mut [u8]) -> usize {
+    STDIN_RAW.call_once(set_stdin_raw);
+    host::serial_read(buf)
+}
+
+/// Call `callback` once some input of stdin is available.
+///
+/// The `callback` will be called on the stdin thread.
+pub fn serial_set_callback(callback: Box<dyn FnOnce() + Send + Sync>) {
+    STDIN_RAW.call_once(set_stdin_raw);
+    host::serial_set_callback(callback)
+}
+
+static STDIN_RAW: std::sync::Once = std::sync::Once::new();
+
+/// The terminal attributes of stdin before it is set to raw mode.
+static mut STDIN_TERMIOS: Option<libc::termios> = None;
+
+/// Pass the keystrokes of the terminal to the kernel as they are typed,
+/// without the line editing and echo of the host, and restore at exit.
+fn set_stdin_raw() {
+    extern "C" fn restore() {
+        if let Some(termios) = unsafe { STDIN_TERMIOS } {
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) };
+        }
+    }
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return;
+        }
+        let mut termios = core::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return;
+        }
+        STDIN_TERMIOS = Some(termios);
+        libc::atexit(restore);
+        // the signal keys still work on the host
+        termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+        termios.c_cc[libc::VMIN] = 1;
+        termios.c_cc[libc::VTIME] = 0;
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+    }
 }
 
 /// Output a char to console.
-#[export_name = "hal_serial_write"]
 pub fn serial_write(s: &str) {
     eprint!("{}", s);
 }
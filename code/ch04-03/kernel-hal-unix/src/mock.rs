@@ -0,0 +1,402 @@
+//! A deterministic HAL for the tests, on the memory and the page tables of
+//! the unix HAL.
+//!
+//! The time is virtual. It starts from 0, and only moves forward by
+//! `advance`, or to the next deadline of the timers when all threads are
+//! blocked in `block_on` and `run`. The threads spawned are queued, and
+//! polled one at a time on the calling thread by `step`, so they interleave
+//! in the same order on each run. The frames allocated and freed are counted.
+//!
+//! There are no devices, interrupts or guests. The HAL is registered for the
+//! calling thread only, with its own time, threads and timers, so the tests
+//! using it run in parallel with each other and with those on the unix HAL.
+
+use {
+    super::*,
+    std::cell::RefCell,
+    std::collections::VecDeque,
+    std::sync::atomic::AtomicBool,
+    std::task::{Wake, Waker},
+};
+
+/// The deterministic HAL, registered by `init`.
+pub struct MockHal;
+
+/// The threads woken, to be polled by `step`.
+type ReadyQueue = Arc<Mutex<VecDeque<Arc<Task>>>>;
+
+type TimerCallback = Box<dyn FnOnce(Duration) + Send + Sync>;
+
+/// A thread spawned, queued when it is woken.
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    vmtoken: usize,
+    tid: Mutex<(u64, u64)>,
+    queued: AtomicBool,
+    /// The queue of the host thread it is spawned on.
+    ready: ReadyQueue,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.ready.clone().lock().unwrap().push_back(self);
+        }
+    }
+}
+
+/// The frames allocated and freed since `init`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameCounts {
+    pub allocated: usize,
+    pub freed: usize,
+}
+
+std::thread_local! {
+    static NOW: Cell<Duration> = Cell::new(Duration::default());
+    static READY: RefCell<ReadyQueue> = RefCell::new(ReadyQueue::default());
+    /// The timers ordered by their deadlines, and then the order they are
+    /// added.
+    static TIMERS: RefCell<BTreeMap<(Duration, u64), TimerCallback>> =
+        RefCell::new(BTreeMap::new());
+    static NEXT_TIMER_ID: Cell<u64> = Cell::new(0);
+    static FRAME_COUNTS: Cell<FrameCounts> = Cell::new(FrameCounts::default());
+    /// The state of the SplitMix64 generator of `rand_bytes`.
+    static RAND_STATE: Cell<u64> = Cell::new(0);
+    /// The thread ID and process ID of the thread being polled.
+    static TID: Cell<(u64, u64)> = Cell::new((0, 0));
+}
+
+/// The registration of the `MockHal` on a host thread, until it is dropped.
+#[must_use]
+pub struct MockGuard {
+    _not_send: core::marker::PhantomData<*const ()>,
+}
+
+impl Drop for MockGuard {
+    fn drop(&mut self) {
+        kernel_hal::host::set_thread_hal(None);
+    }
+}
+
+/// Register the `MockHal` on the calling thread, and reset the time, the
+/// queue of threads, the timers, the frame counts and the random numbers.
+///
+/// The HAL is unregistered when the guard returned is dropped.
+pub fn init() -> MockGuard {
+    kernel_hal::host::set_thread_hal(Some(&MockHal));
+    NOW.with(|now| now.set(Duration::default()));
+    // the threads left by the last test are never woken
+    READY.with(|ready| *ready.borrow_mut() = ReadyQueue::default());
+    // the IDs are not reset, not to cancel a new timer by the ID of one
+    // cleared here
+    TIMERS.with(|timers| timers.borrow_mut().clear());
+    FRAME_COUNTS.with(|counts| counts.set(FrameCounts::default()));
+    RAND_STATE.with(|state| state.set(0));
+    MockGuard {
+        _not_send: core::marker::PhantomData,
+    }
+}
+
+/// Poll the first thread in the queue once, on the current thread.
+///
+/// Return `false` if no thread is ready.
+pub fn step() -> bool {
+    let task = match READY.with(|ready| ready.borrow().lock().unwrap().pop_front()) {
+        Some(task) => task,
+        None => return false,
+    };
+    task.queued.store(false, Ordering::Release);
+    let mut future = task.future.lock().unwrap();
+    if let Some(inner) = future.as_mut() {
+        let waker = Waker::from(task.clone());
+        let mut cx = Context::from_waker(&waker);
+        let saved = TID.with(|tid| tid.replace(*task.tid.lock().unwrap()));
+        let poll = if task.vmtoken == 0 {
+            inner.as_mut().poll(&mut cx)
+        } else {
+            poll_address_space(task.vmtoken, &mut cx, |cx| inner.as_mut().poll(cx))
+        };
+        *task.tid.lock().unwrap() = TID.with(|tid| tid.replace(saved));
+        if poll.is_ready() {
+            *future = None;
+        }
+    }
+    true
+}
+
+/// Poll the threads until none of them is ready.
+pub fn run_until_idle() {
+    while step() {}
+}
+
+/// Run the threads until idle, and move the virtual time to the next
+/// deadline whenever they are, until there is no timer left.
+pub fn run() {
+    loop {
+        run_until_idle();
+        match next_deadline() {
+            Some(deadline) => fire(deadline),
+            None => return,
+        }
+    }
+}
+
+/// Run `future` as a thread by `run`, until it completes.
+///
+/// # Panics
+///
+/// If it never completes, when all threads are blocked with no timer left.
+pub fn block_on<T: Send + 'static>(future: impl Future<Output = T> + Send + 'static) -> T {
+    let output = Arc::new(Mutex::new(None));
+    MockHal.thread_spawn(
+        Box::pin({
+            let output = output.clone();
+            async move {
+                let ret = future.await;
+                *output.lock().unwrap() = Some(ret);
+            }
+        }),
+        0,
+        CPU_AFFINITY_ALL,
+    );
+    loop {
+        run_until_idle();
+        if let Some(ret) = output.lock().unwrap().take() {
+            return ret;
+        }
+        let deadline =
+            next_deadline().expect("deadlock: all threads are blocked with no timer left");
+        fire(deadline);
+    }
+}
+
+/// Move the virtual time forward by `duration`.
+///
+/// The timers expired fire in the order of their deadlines, each at its
+/// deadline, and the threads woken by them run until idle before the next.
+pub fn advance(duration: Duration) {
+    let end = NOW.with(|now| now.get()) + duration;
+    run_until_idle();
+    while let Some(deadline) = next_deadline().filter(|&d| d <= end) {
+        fire(deadline);
+        run_until_idle();
+    }
+    set_now(end);
+}
+
+/// Get the frames allocated and freed since `init`.
+pub fn frame_counts() -> FrameCounts {
+    FRAME_COUNTS.with(|counts| counts.get())
+}
+
+/// Get the earliest deadline of the timers.
+fn next_deadline() -> Option<Duration> {
+    TIMERS.with(|timers| timers.borrow().keys().next().map(|&(deadline, _)| deadline))
+}
+
+/// Move the virtual time to `deadline` if it is later, and fire the timers
+/// expired.
+fn fire(deadline: Duration) {
+    set_now(deadline);
+    let now = NOW.with(|now| now.get());
+    loop {
+        // not borrowed while calling, which may add new timers
+        let callback = TIMERS.with(|timers| {
+            let mut timers = timers.borrow_mut();
+            match timers.keys().next() {
+                Some(&key) if key.0 <= now => timers.remove(&key),
+                _ => None,
+            }
+        });
+        match callback {
+            Some(callback) => callback(now),
+            None => return,
+        }
+    }
+}
+
+fn set_now(now: Duration) {
+    NOW.with(|time| time.set(time.get().max(now)));
+}
+
+impl Hal for MockHal {
+    fn thread_spawn(&self, future: BoxFuture, vmtoken: usize, _affinity: CpuAffinity) {
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(future)),
+            vmtoken,
+            tid: Mutex::new(TID.with(|tid| tid.get())),
+            queued: AtomicBool::new(false),
+            ready: READY.with(|ready| ready.borrow().clone()),
+        });
+        task.wake();
+    }
+
+    fn thread_set_tid(&self, tid: u64, pid: u64) {
+        TID.with(|cell| cell.set((tid, pid)));
+    }
+
+    fn thread_get_tid(&self) -> (u64, u64) {
+        TID.with(|tid| tid.get())
+    }
+
+    fn timer_now(&self) -> Duration {
+        NOW.with(|now| now.get())
+    }
+
+    fn rtc_now(&self) -> Option<Duration> {
+        Some(self.timer_now())
+    }
+
+    fn timer_ticks(&self) -> u64 {
+        self.timer_now().as_nanos() as u64
+    }
+
+    fn timer_ticks_per_second(&self) -> u64 {
+        1_000_000_000
+    }
+
+    fn timer_set(&self, deadline: Duration, callback: TimerCallback) -> u64 {
+        let id = NEXT_TIMER_ID.with(|id| id.replace(id.get() + 1));
+        TIMERS.with(|timers| timers.borrow_mut().insert((deadline, id), callback));
+        id
+    }
+
+    fn timer_cancel(&self, id: u64) {
+        TIMERS.with(|timers| timers.borrow_mut().retain(|&(_, i), _| i != id));
+    }
+
+    fn memory_map(&self) -> Vec<MemoryRegion> {
+        UnixHal.memory_map()
+    }
+
+    fn physmem_size(&self) -> u64 {
+        UnixHal.physmem_size()
+    }
+
+    fn cpu_count(&self) -> u32 {
+        1
+    }
+
+    fn libos_syscall_entry(&self) -> Option<usize> {
+        UnixHal.libos_syscall_entry()
+    }
+
+    fn cache_line_size(&self) -> u32 {
+        UnixHal.cache_line_size()
+    }
+
+    fn cpu_features(&self) -> Features {
+        UnixHal.cpu_features()
+    }
+
+    /// Fail the test, as the system can't reboot.
+    fn reboot(&self) -> ! {
+        panic!("reboot on the mock HAL")
+    }
+
+    /// Fail the test, as the system can't shut down.
+    fn shutdown(&self) -> ! {
+        panic!("shutdown on the mock HAL")
+    }
+
+    unsafe fn user_copy(&self, dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+        UnixHal.user_copy(dst, src, len)
+    }
+
+    /// The same bytes on each run since `init`, by SplitMix64.
+    fn rand_bytes(&self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let state = RAND_STATE.with(|state| {
+                state.set(state.get().wrapping_add(0x9e37_79b9_7f4a_7c15));
+                state.get()
+            });
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^= z >> 31;
+            chunk.copy_from_slice(&z.to_ne_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn frame_alloc(&self) -> Option<PhysAddr> {
+        let paddr = UnixHal.frame_alloc()?;
+        count_frames(|counts| counts.allocated += 1);
+        Some(paddr)
+    }
+
+    fn frame_alloc_contiguous(&self, size: usize, align_log2: usize) -> Option<PhysAddr> {
+        let paddr = UnixHal.frame_alloc_contiguous(size, align_log2)?;
+        count_frames(|counts| counts.allocated += size);
+        Some(paddr)
+    }
+
+    fn frame_dealloc(&self, paddr: PhysAddr) {
+        UnixHal.frame_dealloc(paddr);
+        count_frames(|counts| counts.freed += 1);
+    }
+
+    fn zero_frame_paddr(&self) -> PhysAddr {
+        UnixHal.zero_frame_paddr()
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        UnixHal.frame_stats()
+    }
+
+    fn pmem_read(&self, paddr: PhysAddr, buf: &mut [u8]) {
+        UnixHal.pmem_read(paddr, buf)
+    }
+
+    fn pmem_write(&self, paddr: PhysAddr, buf: &[u8]) {
+        UnixHal.pmem_write(paddr, buf)
+    }
+
+    fn pmem_zero(&self, paddr: PhysAddr, len: usize) {
+        UnixHal.pmem_zero(paddr, len)
+    }
+
+    fn pmem_fetch_add_u64(&self, paddr: PhysAddr, value: u64) -> u64 {
+        UnixHal.pmem_fetch_add_u64(paddr, value)
+    }
+
+    fn frame_copy(&self, src: PhysAddr, target: PhysAddr) {
+        UnixHal.frame_copy(src, target)
+    }
+
+    fn frame_copy_n(&self, frames: &[(PhysAddr, PhysAddr)]) {
+        UnixHal.frame_copy_n(frames)
+    }
+
+    fn frame_flush(&self, target: PhysAddr) {
+        UnixHal.frame_flush(target)
+    }
+
+    fn pt_new(&self) -> Box<dyn PageTableTrait> {
+        UnixHal.pt_new()
+    }
+
+    fn pt_current(&self) -> Box<dyn PageTableTrait> {
+        UnixHal.pt_current()
+    }
+
+    fn tlb_flush(&self, vaddr: core::ops::Range<VirtAddr>, vmtoken: usize) {
+        UnixHal.tlb_flush(vaddr, vmtoken)
+    }
+
+    fn context_run(&self, context: &mut UserContext) -> Result<()> {
+        UnixHal.context_run(context)
+    }
+
+    fn serial_write(&self, s: &str) {
+        UnixHal.serial_write(s)
+    }
+}
+
+fn count_frames(f: impl FnOnce(&mut FrameCounts)) {
+    FRAME_COUNTS.with(|counts| {
+        let mut value = counts.get();
+        f(&mut value);
+        counts.set(value);
+    });
+}
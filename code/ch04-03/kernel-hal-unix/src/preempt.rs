@@ -0,0 +1,135 @@
+//! Preemption of the user code by the timer signals.
+//!
+//! The host threads in `context_run` are sent `SIGURG` once their time slice
+//! expires. If the signal interrupts the user code, the handler makes it call
+//! `syscall_fn_entry` as if it made a syscall, with `zcore_preempt_resume` as
+//! the return address, so `context_run` returns to the executor and reports
+//! the timer IRQ. When the context runs again, `zcore_preempt_resume` restores
+//! `r11` clobbered by the entry, and returns to the code interrupted.
+//!
+//! The signals interrupting the kernel are ignored, the next one tries again.
+//! The stack below the red zone holds the return address and `r11` until the
+//! context runs again, so the registers read meanwhile are the ones of
+//! `zcore_preempt_resume`.
+
+use {super::*, std::sync::Once};
+
+global_asm!(
+    "
+    .text
+    .global zcore_preempt_resume
+zcore_preempt_resume:
+    pop r11
+    ret 128
+"
+);
+
+extern "C" {
+    fn zcore_preempt_resume();
+}
+
+/// The time a user context runs before it is preempted.
+const TIME_SLICE: Duration = Duration::from_millis(10);
+
+const SIGNAL: libc::c_int = libc::SIGURG;
+
+/// The red zone below the stack pointer of the System V ABI.
+const RED_ZONE_SIZE: usize = 128;
+
+/// The return address, `r11` and the instruction pointer interrupted, below
+/// the red zone.
+const FRAME_SIZE: usize = RED_ZONE_SIZE + 3 * 8;
+
+lazy_static! {
+    /// The host threads running a user context, and when they entered it.
+    static ref RUNNING: Mutex<BTreeMap<libc::pthread_t, Duration>> = Mutex::new(BTreeMap::new());
+}
+
+/// Install the signal handler and spawn the host thread of the ticks, only
+/// once.
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe {
+        let mut action: libc::sigaction = core::mem::zeroed();
+        action.sa_sigaction = signal_handler as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK | libc::SA_RESTART;
+        let ret = libc::sigaction(SIGNAL, &action, core::ptr::null_mut());
+        assert_eq!(ret, 0, "failed to install signal handler");
+        std::thread::Builder::new()
+            .name("preempt".into())
+            .spawn(tick_thread)
+            .expect("failed to spawn the preempt thread");
+    });
+}
+
+/// Run the user `context` until it makes a syscall or it is preempted.
+pub fn run(context: &mut UserContext) {
+    init();
+    let thread = unsafe { libc::pthread_self() };
+    RUNNING.lock().unwrap().insert(thread, timer_now());
+    unsafe { context.run_fncall() };
+    // no signal after it is removed
+    RUNNING.lock().unwrap().remove(&thread);
+    if context.general.rip == zcore_preempt_resume as usize {
+        context.trap_num = TIMER_VECTOR as usize;
+    }
+}
+
+/// Signal the threads which run their user contexts for a time slice.
+fn tick_thread() {
+    loop {
+        std::thread::sleep(TIME_SLICE);
+        let now = timer_now();
+        for (&thread, &start) in RUNNING.lock().unwrap().iter() {
+            if now - start >= TIME_SLICE {
+                unsafe { libc::pthread_kill(thread, SIGNAL) };
+            }
+        }
+    }
+}
+
+/// Whether `rip` is in the user code of the active address space, and
+/// the frame can be pushed below `rsp`.
+///
+/// The check is skipped if the address spaces are being changed.
+fn is_user(rip: usize, rsp: usize) -> bool {
+    let spaces = match ADDRESS_SPACES.try_lock() {
+        Ok(spaces) => spaces,
+        Err(_) => return false,
+    };
+    let pages = match spaces.spaces.get(&spaces.active) {
+        Some(pages) if spaces.active != 0 => pages,
+        _ => return false,
+    };
+    let has = |vaddr: usize, flags: MMUFlags| {
+        let page = vaddr & !(PAGE_SIZE - 1);
+        matches!(pages.get(&page), Some((_, f)) if f.contains(flags))
+    };
+    rsp > FRAME_SIZE
+        && has(rip, MMUFlags::EXECUTE)
+        && has(rsp - FRAME_SIZE, MMUFlags::WRITE)
+        && has(rsp - 1, MMUFlags::WRITE)
+}
+
+extern "C" fn signal_handler(
+    _signal: libc::c_int,
+    _info: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    unsafe {
+        let context = &mut *(context as *mut libc::ucontext_t);
+        let regs = &mut context.uc_mcontext.gregs;
+        let rip = regs[libc::REG_RIP as usize] as usize;
+        let rsp = regs[libc::REG_RSP as usize] as usize;
+        if !is_user(rip, rsp) {
+            return;
+        }
+        // as if `call syscall_fn_entry` returning to `zcore_preempt_resume`
+        let frame = (rsp - FRAME_SIZE) as *mut usize;
+        frame.write(zcore_preempt_resume as usize);
+        frame.add(1).write(regs[libc::REG_R11 as usize] as usize);
+        frame.add(2).write(rip);
+        regs[libc::REG_RSP as usize] = frame as i64;
+        regs[libc::REG_RIP as usize] = syscall_entry as usize as i64;
+    }
+}
@@ -0,0 +1,570 @@
+//! Kernel HAL implementation on Windows.
+//!
+//! The physical memory is a section backed by the paging file, mapped once
+//! for the kernel. The views of a section are aligned to 64KiB on Windows,
+//! so the page tables only record the pages and do not map them into the
+//! host, and the user programs can not run: `context_run` returns an error
+//! instead. Running them needs a backend mapping the pages of each address
+//! space into a host process or a fiber of its own, which is not done yet.
+//! It is enough for the kernel objects and their tests.
+//!
+//! The timers and the console are served by the host threads shared with
+//! the other host HALs, in `kernel_hal::host`.
+
+#![cfg(windows)]
+#![deny(warnings)]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use {
+    alloc::collections::{BTreeMap, VecDeque},
+    async_std::task_local,
+    core::{cell::Cell, future::Future, pin::Pin, time::Duration},
+    lazy_static::lazy_static,
+    std::sync::Mutex,
+    winapi::um::{
+        handleapi::INVALID_HANDLE_VALUE,
+        memoryapi::{CreateFileMappingW, MapViewOfFile, FILE_MAP_ALL_ACCESS},
+        profileapi::{QueryPerformanceCounter, QueryPerformanceFrequency},
+        sysinfoapi::GetSystemInfo,
+        winnt::PAGE_READWRITE,
+    },
+};
+
+pub use kernel_hal::{defs::*, *};
+
+#[repr(C)]
+pub struct Thread {
+    thread: usize,
+}
+
+impl Thread {
+    /// Spawn a new thread, which is a task of `async-std` on any CPU.
+    pub fn spawn(
+        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+        _vmtoken: usize,
+        _affinity: CpuAffinity,
+    ) -> Self {
+        async_std::task::spawn(future);
+        Thread { thread: 0 }
+    }
+
+    pub fn set_tid(tid: u64, pid: u64) {
+        TID.with(|x| x.set(tid));
+        PID.with(|x| x.set(pid));
+    }
+
+    pub fn get_tid() -> (u64, u64) {
+        (TID.with(|x| x.get()), PID.with(|x| x.get()))
+    }
+}
+
+task_local! {
+    static TID: Cell<u64> = Cell::new(0);
+    static PID: Cell<u64> = Cell::new(0);
+}
+
+/// Get the monotonic time, converted from the ticks of the timebase.
+pub fn timer_now() -> Duration {
+    let nanos = timer_ticks() as u128 * 1_000_000_000 / timer_ticks_per_second() as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+/// Read the system clock.
+pub fn rtc_now() -> Option<Duration> {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+}
+
+/// Read the performance counter.
+pub fn timer_ticks() -> u64 {
+    unsafe {
+        let mut counter = core::mem::zeroed();
+        QueryPerformanceCounter(&mut counter);
+        *counter.QuadPart() as u64
+    }
+}
+
+/// Get the frequency of the performance counter, fixed at boot.
+pub fn timer_ticks_per_second() -> u64 {
+    lazy_static! {
+        static ref FREQUENCY: u64 = unsafe {
+            let mut frequency = core::mem::zeroed();
+            QueryPerformanceFrequency(&mut frequency);
+            *frequency.QuadPart() as u64
+        };
+    }
+    *FREQUENCY
+}
+
+/// Set a new timer.
+///
+/// After `deadline`, the `callback` will be called on the timer thread.
+pub fn timer_set(deadline: Duration, callback: Box<dyn FnOnce(Duration) + Send + Sync>) -> u64 {
+    host::timer_set(deadline, callback)
+}
+
+/// Cancel the timer `id`.
+pub fn timer_cancel(id: u64) {
+    host::timer_cancel(id)
+}
+
+/// Fill `buf` with random bytes from the host.
+pub fn rand_bytes(buf: &mut [u8]) {
+    getrandom::getrandom(buf).expect("failed to get random bytes");
+}
+
+/// The physical memory is all RAM.
+pub fn memory_map() -> Vec<MemoryRegion> {
+    vec![MemoryRegion {
+        kind: MemoryRegionKind::Ram,
+        addr: 0,
+        size: PMEM_SIZE,
+    }]
+}
+
+/// Get the number of the processors of the host.
+pub fn cpu_count() -> u32 {
+    let mut info = unsafe { core::mem::zeroed() };
+    unsafe { GetSystemInfo(&mut info) };
+    info.dwNumberOfProcessors.max(1)
+}
+
+/// The size of the cache line of the x86 and ARM processors of Windows.
+pub fn cache_line_size() -> u32 {
+    64
+}
+
+/// No CPU features or debug registers are exposed to the user programs.
+pub fn cpu_features() -> vdso::Features {
+    vdso::Features::default()
+}
+
+const PMEM_SIZE: usize = 0x4000_0000; // 1GiB
+
+/// The address of the view of the physical memory.
+struct PmemBase(usize);
+
+lazy_static! {
+    static ref PMEM_BASE: PmemBase = create_pmem_section();
+}
+
+fn create_pmem_section() -> PmemBase {
+    let base = unsafe {
+        let section = CreateFileMappingW(
+            INVALID_HANDLE_VALUE,
+            core::ptr::null_mut(),
+            PAGE_READWRITE,
+            (PMEM_SIZE as u64 >> 32) as u32,
+            PMEM_SIZE as u32,
+            core::ptr::null(),
+        );
+        assert!(!section.is_null(), "failed to create pmem section");
+        MapViewOfFile(section, FILE_MAP_ALL_ACCESS, 0, 0, PMEM_SIZE)
+    };
+    assert!(!base.is_null(), "failed to map pmem section");
+    trace!(
+        "create pmem section: base={:?}, size={:#x}",
+        base,
+        PMEM_SIZE
+    );
+    PmemBase(base as usize)
+}
+
+fn phys_to_virt(paddr: PhysAddr) -> VirtAddr {
+    PMEM_BASE.0 + paddr
+}
+
+#[repr(C)]
+pub struct PhysFrame {
+    paddr: PhysAddr,
+}
+
+lazy_static! {
+    static ref AVAILABLE_FRAMES: Mutex<VecDeque<usize>> =
+        Mutex::new((PAGE_SIZE..PMEM_SIZE).step_by(PAGE_SIZE).collect());
+}
+
+impl PhysFrame {
+    pub fn alloc() -> Option<Self> {
+        let ret = AVAILABLE_FRAMES
+            .lock()
+            .unwrap()
+            .pop_front()
+            .map(|paddr| PhysFrame { paddr });
+        trace!("frame alloc: {:?}", ret.as_ref().map(|frame| frame.paddr));
+        ret
+    }
+
+    pub fn zero_frame_addr() -> PhysAddr {
+        0
+    }
+}
+
+impl Drop for PhysFrame {
+    fn drop(&mut self) {
+        trace!("frame dealloc: {:#x}", self.paddr);
+        AVAILABLE_FRAMES.lock().unwrap().push_back(self.paddr);
+    }
+}
+
+/// Get statistics of the physical frame allocator.
+pub fn frame_stats() -> FrameStats {
+    FrameStats {
+        // the first frame is reserved as the zero frame
+        total: PMEM_SIZE / PAGE_SIZE - 1,
+        free: AVAILABLE_FRAMES.lock().unwrap().len(),
+    }
+}
+
+/// Read physical memory from `paddr` to `buf`.
+pub fn pmem_read(paddr: PhysAddr, buf: &mut [u8]) {
+    trace!("pmem read: paddr={:#x}, len={:#x}", paddr, buf.len());
+    assert!(paddr + buf.len() <= PMEM_SIZE);
+    unsafe {
+        (phys_to_virt(paddr) as *const u8).copy_to_nonoverlapping(buf.as_mut_ptr(), buf.len());
+    }
+}
+
+/// Write physical memory to `paddr` from `buf`.
+pub fn pmem_write(paddr: PhysAddr, buf: &[u8]) {
+    trace!("pmem write: paddr={:#x}, len={:#x}", paddr, buf.len());
+    assert!(paddr + buf.len() <= PMEM_SIZE);
+    unsafe {
+        buf.as_ptr()
+            .copy_to_nonoverlapping(phys_to_virt(paddr) as _, buf.len());
+    }
+}
+
+/// Zero physical memory at `[paddr, paddr + len)`
+pub fn pmem_zero(paddr: PhysAddr, len: usize) {
+    trace!("pmem_zero: addr={:#x}, len={:#x}", paddr, len);
+    assert!(paddr + len <= PMEM_SIZE);
+    unsafe {
+        core::ptr::write_bytes(phys_to_virt(paddr) as *mut u8, 0, len);
+    }
+}
+
+/// Atomically add `value` to the `u64` at `paddr`, return the previous value.
+pub fn pmem_fetch_add_u64(paddr: PhysAddr, value: u64) -> u64 {
+    assert!(paddr % 8 == 0 && paddr + 8 <= PMEM_SIZE);
+    let atomic = unsafe { &*(phys_to_virt(paddr) as *const core::sync::atomic::AtomicU64) };
+    atomic.fetch_add(value, core::sync::atomic::Ordering::Relaxed)
+}
+
+/// Copy content of `src` frame to `target` frame
+pub fn frame_copy(src: PhysAddr, target: PhysAddr) {
+    trace!("frame_copy: {:#x} <- {:#x}", target, src);
+    assert!(src + PAGE_SIZE <= PMEM_SIZE && target + PAGE_SIZE <= PMEM_SIZE);
+    unsafe {
+        let buf = phys_to_virt(src) as *const u8;
+        buf.copy_to_nonoverlapping(phys_to_virt(target) as _, PAGE_SIZE);
+    }
+}
+
+/// Copy the content of each `(src, target)` pair of frames, by a copy of
+/// each run contiguous in both.
+pub fn frame_copy_n(frames: &[(PhysAddr, PhysAddr)]) {
+    trace!("frame_copy_n: {} frames", frames.len());
+    for (src, target, len) in kernel_hal::copy_runs(frames) {
+        assert!(src + len <= PMEM_SIZE && target + len <= PMEM_SIZE);
+        unsafe {
+            let buf = phys_to_virt(src) as *const u8;
+            buf.copy_to_nonoverlapping(phys_to_virt(target) as _, len);
+        }
+    }
+}
+
+/// Flush the physical frame.
+pub fn frame_flush(_target: PhysAddr) {
+    // do nothing
+}
+
+lazy_static! {
+    /// The pages recorded by each page table, with the frames and the flags.
+    static ref ADDRESS_SPACES: Mutex<BTreeMap<usize, BTreeMap<VirtAddr, (PhysAddr, MMUFlags)>>> =
+        Mutex::new(BTreeMap::new());
+}
+
+static NEXT_VMTOKEN: core::sync::atomic::AtomicUsize = core::sync::atomic::AtomicUsize::new(1);
+
+/// Page Table
+///
+/// The pages are only recorded, see the crate documentation.
+#[repr(C)]
+pub struct PageTable {
+    table_phys: PhysAddr,
+}
+
+impl PageTable {
+    /// Create a new `PageTable`.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        use core::sync::atomic::Ordering;
+        PageTable {
+            table_phys: NEXT_VMTOKEN.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Get the page table of the kernel, of `vmtoken` 0, as no user context
+    /// runs on Windows.
+    pub fn current() -> Self {
+        PageTable { table_phys: 0 }
+    }
+}
+
+impl PageTableTrait for PageTable {
+    /// Map the page of `vaddr` to the frame of `paddr` with `flags`.
+    fn map(&mut self, vaddr: VirtAddr, paddr: PhysAddr, flags: MMUFlags) -> Result<()> {
+        ADDRESS_SPACES
+            .lock()
+            .unwrap()
+            .entry(self.table_phys)
+            .or_default()
+            .insert(vaddr, (paddr, flags));
+        Ok(())
+    }
+
+    /// Unmap the page of `vaddr`.
+    fn unmap(&mut self, vaddr: VirtAddr) -> Result<()> {
+        self.unmap_cont(vaddr, 1)
+    }
+
+    /// Change the `flags` of the page of `vaddr`.
+    fn protect(&mut self, vaddr: VirtAddr, flags: MMUFlags) -> Result<()> {
+        let mut spaces = ADDRESS_SPACES.lock().unwrap();
+        let page = spaces
+            .get_mut(&self.table_phys)
+            .and_then(|pages| pages.get_mut(&vaddr))
+            .ok_or(HalError)?;
+        page.1 = flags;
+        Ok(())
+    }
+
+    /// Query the physical address which the page of `vaddr` maps to.
+    fn query(&mut self, vaddr: VirtAddr) -> Result<PhysAddr> {
+        let spaces = ADDRESS_SPACES.lock().unwrap();
+        spaces
+            .get(&self.table_phys)
+            .and_then(|pages| pages.get(&vaddr))
+            .map(|&(paddr, _)| paddr)
+            .ok_or(HalError)
+    }
+
+    /// Get the physical address of root page table.
+    fn table_phys(&self) -> PhysAddr {
+        self.table_phys
+    }
+
+    fn unmap_cont(&mut self, vaddr: VirtAddr, pages: usize) -> Result<()> {
+        let mut spaces = ADDRESS_SPACES.lock().unwrap();
+        if let Some(mapped) = spaces.get_mut(&self.table_phys) {
+            for i in 0..pages {
+                mapped.remove(&(vaddr + i * PAGE_SIZE));
+            }
+            if mapped.is_empty() {
+                spaces.remove(&self.table_phys);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The user programs can not run, see the crate documentation.
+///
+/// Always return `Err`, leaving the `context` unchanged, so that the loaders
+/// kill the process instead of the kernel.
+pub fn context_run(_context: &mut UserContext) -> Result<()> {
+    error!("user programs are not supported on Windows");
+    Err(HalError)
+}
+
+/// The size of the pmem section.
+pub fn physmem_size() -> u64 {
+    PMEM_SIZE as u64
+}
+
+/// Read the input of the console without blocking.
+pub fn serial_read(buf: &mut [u8]) -> usize {
+    host::serial_read(buf)
+}
+
+/// Call `callback` once some input of the console is available.
+///
+/// The `callback` will be called on the stdin thread.
+pub fn serial_set_callback(callback: Box<dyn FnOnce() + Send + Sync>) {
+    host::serial_set_callback(callback)
+}
+
+/// Output a char to console.
+pub fn serial_write(s: &str) {
+    eprint!("{}", s);
+}
+
+/// The heap of the host, counted for `heap_stats`.
+#[global_allocator]
+static HEAP: CountingHeap<std::alloc::System> = CountingHeap::new(std::alloc::System);
+
+/// Initialize the HAL.
+///
+/// This function must be called at the beginning.
+pub fn init() {
+    lazy_static::initialize(&PMEM_BASE);
+    kernel_hal::set_hal(&WindowsHal);
+}
+
+/// The HAL on Windows.
+pub struct WindowsHal;
+
+impl Hal for WindowsHal {
+    fn thread_spawn(
+        &self,
+        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+        vmtoken: usize,
+        affinity: CpuAffinity,
+    ) {
+        Thread::spawn(future, vmtoken, affinity);
+    }
+
+    fn thread_set_tid(&self, tid: u64, pid: u64) {
+        Thread::set_tid(tid, pid)
+    }
+
+    fn thread_get_tid(&self) -> (u64, u64) {
+        Thread::get_tid()
+    }
+
+    fn timer_now(&self) -> Duration {
+        timer_now()
+    }
+
+    fn rtc_now(&self) -> Option<Duration> {
+        rtc_now()
+    }
+
+    fn timer_ticks(&self) -> u64 {
+        timer_ticks()
+    }
+
+    fn timer_ticks_per_second(&self) -> u64 {
+        timer_ticks_per_second()
+    }
+
+    fn timer_set(
+        &self,
+        deadline: Duration,
+        callback: Box<dyn FnOnce(Duration) + Send + Sync>,
+    ) -> u64 {
+        timer_set(deadline, callback)
+    }
+
+    fn timer_cancel(&self, id: u64) {
+        timer_cancel(id)
+    }
+
+    fn memory_map(&self) -> Vec<MemoryRegion> {
+        memory_map()
+    }
+
+    fn physmem_size(&self) -> u64 {
+        physmem_size()
+    }
+
+    fn cpu_count(&self) -> u32 {
+        cpu_count()
+    }
+
+    fn cache_line_size(&self) -> u32 {
+        cache_line_size()
+    }
+
+    fn cpu_features(&self) -> vdso::Features {
+        cpu_features()
+    }
+
+    fn rand_bytes(&self, buf: &mut [u8]) {
+        rand_bytes(buf)
+    }
+
+    fn reboot(&self) -> ! {
+        std::process::exit(0)
+    }
+
+    fn shutdown(&self) -> ! {
+        std::process::exit(0)
+    }
+
+    fn frame_alloc(&self) -> Option<PhysAddr> {
+        let frame = PhysFrame::alloc()?;
+        let paddr = frame.paddr;
+        core::mem::forget(frame);
+        Some(paddr)
+    }
+
+    fn frame_dealloc(&self, paddr: PhysAddr) {
+        drop(PhysFrame { paddr })
+    }
+
+    fn zero_frame_paddr(&self) -> PhysAddr {
+        PhysFrame::zero_frame_addr()
+    }
+
+    fn frame_stats(&self) -> FrameStats {
+        frame_stats()
+    }
+
+    fn pmem_read(&self, paddr: PhysAddr, buf: &mut [u8]) {
+        pmem_read(paddr, buf)
+    }
+
+    fn pmem_write(&self, paddr: PhysAddr, buf: &[u8]) {
+        pmem_write(paddr, buf)
+    }
+
+    fn pmem_zero(&self, paddr: PhysAddr, len: usize) {
+        pmem_zero(paddr, len)
+    }
+
+    fn pmem_fetch_add_u64(&self, paddr: PhysAddr, value: u64) -> u64 {
+        pmem_fetch_add_u64(paddr, value)
+    }
+
+    fn frame_copy(&self, src: PhysAddr, target: PhysAddr) {
+        frame_copy(src, target)
+    }
+
+    fn frame_copy_n(&self, frames: &[(PhysAddr, PhysAddr)]) {
+        frame_copy_n(frames)
+    }
+
+    fn frame_flush(&self, target: PhysAddr) {
+        frame_flush(target)
+    }
+
+    fn pt_new(&self) -> Box<dyn PageTableTrait> {
+        Box::new(PageTable::new())
+    }
+
+    fn pt_current(&self) -> Box<dyn PageTableTrait> {
+        Box::new(PageTable::current())
+    }
+
+    fn context_run(&self, context: &mut UserContext) -> Result<()> {
+        context_run(context)
+    }
+
+    fn serial_read(&self, buf: &mut [u8]) -> usize {
+        serial_read(buf)
+    }
+
+    fn serial_set_callback(&self, callback: Box<dyn FnOnce() + Send + Sync>) {
+        serial_set_callback(callback)
+    }
+
+    fn serial_write(&self, s: &str) {
+        serial_write(s)
+    }
+}
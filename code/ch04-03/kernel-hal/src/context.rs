@@ -0,0 +1,330 @@
+//! The user context of `trapframe`, and the accessors of its registers by
+//! their roles, for the architectures supported.
+
+pub use trapframe::{GeneralRegs, UserContext};
+
+#[cfg(target_arch = "x86_64")]
+use crate::user::UserInPtr;
+#[cfg(target_arch = "riscv64")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The reason `context_run` returns.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UserTrap {
+    /// A syscall by the ABI of the architecture.
+    Syscall,
+    /// An interrupt, to be handled by `irq_handle` with the vector.
+    Irq(u32),
+    /// Another exception, with its number and error code of the architecture.
+    Exception { num: usize, code: usize },
+}
+
+/// The accessors of the registers in the user context.
+pub trait UserContextExt {
+    /// Set the registers to start at `entry` on `stack` with two arguments,
+    /// the interrupts enabled.
+    fn setup(&mut self, entry: usize, stack: usize, arg1: usize, arg2: usize) {
+        self.set_ip(entry);
+        self.set_sp(stack);
+        self.set_args(arg1, arg2);
+        self.enable_interrupts();
+    }
+
+    /// Get the instruction pointer.
+    fn ip(&self) -> usize;
+
+    /// Set the instruction pointer.
+    fn set_ip(&mut self, ip: usize);
+
+    /// Get the stack pointer.
+    fn sp(&self) -> usize;
+
+    /// Set the stack pointer.
+    fn set_sp(&mut self, sp: usize);
+
+    /// Set the first two arguments of the function call.
+    fn set_args(&mut self, arg1: usize, arg2: usize);
+
+    /// Enable the interrupts in the user mode.
+    fn enable_interrupts(&mut self);
+
+    /// Get the reason the last `context_run` returned.
+    fn trap(&self) -> UserTrap;
+
+    /// Get the number of the syscall.
+    fn syscall_num(&self) -> u32;
+
+    /// Get the arguments of the syscall.
+    fn syscall_args(&self) -> [usize; 8];
+
+    /// Set the return value of the syscall, and continue after the syscall
+    /// instruction.
+    fn set_syscall_ret(&mut self, ret: usize);
+}
+
+#[cfg(target_arch = "x86_64")]
+impl UserContextExt for UserContext {
+    fn ip(&self) -> usize {
+        self.general.rip
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.general.rip = ip;
+    }
+
+    fn sp(&self) -> usize {
+        self.general.rsp
+    }
+
+    fn set_sp(&mut self, sp: usize) {
+        self.general.rsp = sp;
+    }
+
+    fn set_args(&mut self, arg1: usize, arg2: usize) {
+        self.general.rdi = arg1;
+        self.general.rsi = arg2;
+    }
+
+    fn enable_interrupts(&mut self) {
+        // IF, and IOPL 0 so that the I/O ports are checked against the
+        // bitmap of the process
+        self.general.rflags = (self.general.rflags & !0x3000) | 0x202;
+    }
+
+    fn trap(&self) -> UserTrap {
+        match self.trap_num {
+            0x100 => UserTrap::Syscall,
+            vector @ 0x20..=0xff => UserTrap::Irq(vector as u32),
+            num => UserTrap::Exception {
+                num,
+                code: self.error_code,
+            },
+        }
+    }
+
+    fn syscall_num(&self) -> u32 {
+        self.general.rax as u32
+    }
+
+    /// The Zircon syscall ABI: `rdi, rsi, rdx, r10, r8, r9, r12, r13`.
+    ///
+    /// With the syscall entry of the LibOS, the function call ABI instead,
+    /// the last two arguments on the user stack, 0 if it can't be read.
+    fn syscall_args(&self) -> [usize; 8] {
+        let regs = &self.general;
+        if crate::libos_syscall_entry().is_none() {
+            return [
+                regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9, regs.r12, regs.r13,
+            ];
+        }
+        let [a6, a7] = UserInPtr::<[usize; 2]>::from(regs.rsp)
+            .read()
+            .unwrap_or_default();
+        [
+            regs.rdi, regs.rsi, regs.rdx, regs.rcx, regs.r8, regs.r9, a6, a7,
+        ]
+    }
+
+    fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.rax = ret;
+    }
+}
+
+#[cfg(target_arch = "riscv64")]
+impl UserContextExt for UserContext {
+    fn ip(&self) -> usize {
+        self.sepc
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.sepc = ip;
+    }
+
+    fn sp(&self) -> usize {
+        self.general.sp
+    }
+
+    fn set_sp(&mut self, sp: usize) {
+        self.general.sp = sp;
+    }
+
+    fn set_args(&mut self, arg1: usize, arg2: usize) {
+        self.general.a0 = arg1;
+        self.general.a1 = arg2;
+    }
+
+    fn enable_interrupts(&mut self) {
+        // SPIE, and SPP cleared to return to the user mode
+        self.sstatus = (self.sstatus | 1 << 5) & !(1 << 8);
+    }
+
+    /// The cause is the one captured by [`save_user_trap`] on this CPU, so
+    /// it is called before switching to another context on it.
+    fn trap(&self) -> UserTrap {
+        const INTERRUPT: usize = 1 << 63;
+        const ENV_CALL_FROM_U: usize = 8;
+        let (scause, stval) = &USER_TRAPS[crate::cpu_id() as usize];
+        let (scause, stval) = (
+            scause.load(Ordering::Relaxed),
+            stval.load(Ordering::Relaxed),
+        );
+        match scause {
+            _ if scause & INTERRUPT != 0 => UserTrap::Irq((scause & !INTERRUPT) as u32),
+            ENV_CALL_FROM_U => UserTrap::Syscall,
+            num => UserTrap::Exception { num, code: stval },
+        }
+    }
+
+    /// The number in `t0` as on Fuchsia.
+    fn syscall_num(&self) -> u32 {
+        self.general.t0 as u32
+    }
+
+    fn syscall_args(&self) -> [usize; 8] {
+        let regs = &self.general;
+        [
+            regs.a0, regs.a1, regs.a2, regs.a3, regs.a4, regs.a5, regs.a6, regs.a7,
+        ]
+    }
+
+    /// `sepc` is at the `ecall`, of 4 bytes.
+    fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.a0 = ret;
+        self.sepc += 4;
+    }
+}
+
+/// The `scause` and `stval` of the last trap from the user mode on each CPU,
+/// not in `UserContext` of RISC-V.
+#[cfg(target_arch = "riscv64")]
+static USER_TRAPS: [(AtomicUsize, AtomicUsize); 256] = [NO_TRAP; 256];
+
+#[cfg(target_arch = "riscv64")]
+const NO_TRAP: (AtomicUsize, AtomicUsize) = (AtomicUsize::new(0), AtomicUsize::new(0));
+
+/// Capture `scause` and `stval` of the trap from the user mode, called by
+/// `context_run` as it returns, before they are overwritten by the traps in
+/// the kernel.
+#[cfg(target_arch = "riscv64")]
+pub fn save_user_trap() {
+    let (scause, stval): (usize, usize);
+    unsafe {
+        asm!("csrr {}, scause", out(reg) scause);
+        asm!("csrr {}, stval", out(reg) stval);
+    }
+    let (saved_scause, saved_stval) = &USER_TRAPS[crate::cpu_id() as usize];
+    saved_scause.store(scause, Ordering::Relaxed);
+    saved_stval.store(stval, Ordering::Relaxed);
+}
+
+#[cfg(target_arch = "aarch64")]
+impl UserContextExt for UserContext {
+    fn ip(&self) -> usize {
+        self.elr
+    }
+
+    fn set_ip(&mut self, ip: usize) {
+        self.elr = ip;
+    }
+
+    fn sp(&self) -> usize {
+        self.sp
+    }
+
+    fn set_sp(&mut self, sp: usize) {
+        self.sp = sp;
+    }
+
+    fn set_args(&mut self, arg1: usize, arg2: usize) {
+        self.general.x0 = arg1;
+        self.general.x1 = arg2;
+    }
+
+    fn enable_interrupts(&mut self) {
+        // EL0t, and no exception masked
+        self.spsr = 0;
+    }
+
+    fn trap(&self) -> UserTrap {
+        const SYNC_EXCEPTION: usize = 0;
+        const IRQ_EXCEPTION: usize = 1;
+        const EC_SVC64: usize = 0x15;
+        match self.trap_num & 0xffff {
+            IRQ_EXCEPTION => UserTrap::Irq(IRQ_EXCEPTION as u32),
+            SYNC_EXCEPTION if self.error_code >> 26 == EC_SVC64 => UserTrap::Syscall,
+            num => UserTrap::Exception {
+                num,
+                code: self.error_code,
+            },
+        }
+    }
+
+    /// The number in `x16` as on Fuchsia.
+    fn syscall_num(&self) -> u32 {
+        self.general.x16 as u32
+    }
+
+    fn syscall_args(&self) -> [usize; 8] {
+        let regs = &self.general;
+        [
+            regs.x0, regs.x1, regs.x2, regs.x3, regs.x4, regs.x5, regs.x6, regs.x7,
+        ]
+    }
+
+    /// `elr` is already after the `svc`.
+    fn set_syscall_ret(&mut self, ret: usize) {
+        self.general.x0 = ret;
+    }
+}
+
+/// The floating point and vector registers, in the layout of the `fxsave`
+/// area.
+///
+/// They are not in `UserContext`, so the owner of the context saves them
+/// after `context_run` returns, and restores them before it runs again.
+#[cfg(target_arch = "x86_64")]
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy)]
+pub struct FpState(pub [u8; 512]);
+
+#[cfg(target_arch = "x86_64")]
+impl FpState {
+    /// The offset of the x87 control word.
+    const FCW_OFFSET: usize = 0;
+    /// The offset of `MXCSR`.
+    const MXCSR_OFFSET: usize = 24;
+    /// The bits of `MXCSR` which can be set.
+    const MXCSR_MASK: u32 = 0xffbf;
+
+    /// Save the registers of the current CPU.
+    pub fn save(&mut self) {
+        unsafe { core::arch::x86_64::_fxsave64(self.0.as_mut_ptr()) }
+    }
+
+    /// Load the registers into the current CPU.
+    pub fn restore(&self) {
+        unsafe { core::arch::x86_64::_fxrstor64(self.0.as_ptr()) }
+    }
+
+    /// Clear the reserved bits of `MXCSR`, which fault on `restore`, in the
+    /// state from the user.
+    pub fn sanitize(&mut self) {
+        let range = Self::MXCSR_OFFSET..Self::MXCSR_OFFSET + 4;
+        let mut mxcsr = [0u8; 4];
+        mxcsr.copy_from_slice(&self.0[range.clone()]);
+        let mxcsr = u32::from_le_bytes(mxcsr) & Self::MXCSR_MASK;
+        self.0[range].copy_from_slice(&mxcsr.to_le_bytes());
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Default for FpState {
+    /// The state after `fninit`, with all the exceptions masked.
+    fn default() -> Self {
+        let mut state = FpState([0; 512]);
+        state.0[Self::FCW_OFFSET..Self::FCW_OFFSET + 2].copy_from_slice(&0x37fu16.to_le_bytes());
+        state.0[Self::MXCSR_OFFSET..Self::MXCSR_OFFSET + 4]
+            .copy_from_slice(&0x1f80u32.to_le_bytes());
+        state
+    }
+}
@@ -0,0 +1,190 @@
+use crate::timer_now;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::future::Future;
+use core::mem;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+/// Sleep until the specified `deadline`.
+///
+/// A deadline not less than `i64::MAX` nanoseconds means forever.
+pub fn sleep_until(deadline: Duration) -> SleepFuture {
+    SleepFuture {
+        deadline,
+        waker: None,
+        timer: None,
+    }
+}
+
+/// The future returned by [`sleep_until`].
+///
+/// The timer is set on the first poll, and wakes the waker of the last poll.
+/// It is canceled when the future is dropped.
+#[must_use = "sleep_until does nothing unless polled/`await`-ed"]
+pub struct SleepFuture {
+    deadline: Duration,
+    waker: Option<Arc<Mutex<Waker>>>,
+    /// The ID of the timer set.
+    timer: Option<u64>,
+}
+
+impl Future for SleepFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if timer_now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if self.deadline.as_nanos() >= i64::max_value() as u128 {
+            return Poll::Pending;
+        }
+        match &self.waker {
+            Some(waker) => {
+                let mut waker = waker.lock();
+                if !waker.will_wake(cx.waker()) {
+                    *waker = cx.waker().clone();
+                }
+            }
+            None => {
+                let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                let weak = Arc::downgrade(&waker);
+                let timer = crate::timer_set(
+                    self.deadline,
+                    Box::new(move |_| {
+                        if let Some(waker) = weak.upgrade() {
+                            waker.lock().wake_by_ref();
+                        }
+                    }),
+                );
+                self.waker = Some(waker);
+                self.timer = Some(timer);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for SleepFuture {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer {
+            crate::timer_cancel(timer);
+        }
+    }
+}
+
+/// Wait until some input of the serial console is available.
+pub fn serial_wait() -> SerialFuture {
+    SerialFuture { waiter: None }
+}
+
+/// The wakers of the pending [`SerialFuture`]s, sharing one callback of the
+/// serial console.
+#[derive(Default)]
+struct SerialWaiters {
+    wakers: BTreeMap<u64, Waker>,
+    next_id: u64,
+    /// Whether the callback is set and not called yet.
+    armed: bool,
+}
+
+lazy_static! {
+    static ref SERIAL_WAITERS: Mutex<SerialWaiters> = Mutex::new(SerialWaiters::default());
+}
+
+/// The number of times the callback of the serial console is called.
+static SERIAL_EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Wake all pending [`SerialFuture`]s, on some input of the serial console.
+fn serial_wake_all() {
+    let wakers = {
+        let mut waiters = SERIAL_WAITERS.lock();
+        SERIAL_EPOCH.fetch_add(1, Ordering::AcqRel);
+        waiters.armed = false;
+        mem::take(&mut waiters.wakers)
+    };
+    for waker in wakers.into_values() {
+        waker.wake();
+    }
+}
+
+/// The future returned by [`serial_wait`].
+///
+/// It is ready once the callback of the serial console is called after its
+/// first poll. The input is not taken, and may be read by others before it
+/// is ready. The pending futures share one callback, and their wakers are
+/// removed when they are dropped.
+#[must_use = "serial_wait does nothing unless polled/`await`-ed"]
+pub struct SerialFuture {
+    /// The id of the waker, and the epoch of the first poll.
+    waiter: Option<(u64, u64)>,
+}
+
+impl Future for SerialFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut waiters = SERIAL_WAITERS.lock();
+        let id = match self.waiter {
+            Some((id, epoch)) => {
+                if SERIAL_EPOCH.load(Ordering::Acquire) != epoch {
+                    waiters.wakers.remove(&id);
+                    self.waiter = None;
+                    return Poll::Ready(());
+                }
+                id
+            }
+            None => {
+                let id = waiters.next_id;
+                waiters.next_id += 1;
+                self.waiter = Some((id, SERIAL_EPOCH.load(Ordering::Acquire)));
+                id
+            }
+        };
+        waiters.wakers.insert(id, cx.waker().clone());
+        if !waiters.armed {
+            waiters.armed = true;
+            // the callback may be called at once, which takes the lock
+            drop(waiters);
+            crate::serial_set_callback(Box::new(serial_wake_all));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for SerialFuture {
+    fn drop(&mut self) {
+        if let Some((id, _)) = self.waiter {
+            SERIAL_WAITERS.lock().wakers.remove(&id);
+        }
+    }
+}
+
+/// Yield the CPU to other tasks once.
+pub fn yield_now() -> YieldFuture {
+    YieldFuture { yielded: false }
+}
+
+/// The future returned by [`yield_now`].
+#[must_use = "yield_now does nothing unless polled/`await`-ed"]
+pub struct YieldFuture {
+    yielded: bool,
+}
+
+impl Future for YieldFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
@@ -0,0 +1,510 @@
+//! The interface implemented by a HAL, and the registration of the HAL used
+//! by the kernel.
+//!
+//! The functions of the crate call the methods of the HAL registered by
+//! `set_hal`. The methods without a default must be implemented by every HAL,
+//! the others fall back to a generic one, or report the feature unsupported
+//! by an error or an empty result.
+
+use super::*;
+use crate::vdso::Features;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::ops::Range;
+use core::pin::Pin;
+use core::time::Duration;
+use spin::RwLock;
+
+/// The hardware abstraction layer used by the kernel.
+pub trait Hal: Send + Sync {
+    /// Spawn a new thread running `future` in the address space `vmtoken`, on
+    /// the CPUs of `affinity`.
+    fn thread_spawn(
+        &self,
+        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+        vmtoken: usize,
+        affinity: CpuAffinity,
+    );
+
+    /// Change the CPUs the current thread runs on, ignored by default.
+    fn thread_set_affinity(&self, _affinity: CpuAffinity) {}
+
+    /// Set tid and pid of the current task.
+    fn thread_set_tid(&self, tid: u64, pid: u64);
+
+    /// Get tid and pid of the current task.
+    fn thread_get_tid(&self) -> (u64, u64);
+
+    /// Get the monotonic time.
+    fn timer_now(&self) -> Duration;
+
+    /// Read the real-time clock, `None` if there is no real-time clock.
+    fn rtc_now(&self) -> Option<Duration>;
+
+    /// Get the current value of the high-resolution timebase.
+    fn timer_ticks(&self) -> u64;
+
+    /// Get the frequency of the timebase in ticks per second.
+    fn timer_ticks_per_second(&self) -> u64;
+
+    /// Set a new timer. After `deadline`, the `callback` will be called.
+    /// Return the ID of the timer.
+    fn timer_set(
+        &self,
+        deadline: Duration,
+        callback: Box<dyn FnOnce(Duration) + Send + Sync>,
+    ) -> u64;
+
+    /// Cancel the timer `id`, so that its callback is dropped without being
+    /// called. Nothing happens if it is fired already.
+    fn timer_cancel(&self, id: u64);
+
+    /// Register the `handler` of IRQ `vector` in `mode`.
+    ///
+    /// The HALs without interrupts of devices do not support it.
+    fn irq_register(&self, _vector: u32, _mode: IrqMode, _handler: IrqHandler) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Unregister the handler of IRQ `vector`.
+    fn irq_unregister(&self, _vector: u32) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Enable (unmask) IRQ `vector`, ignored by default.
+    fn irq_enable(&self, _vector: u32) {}
+
+    /// Disable (mask) IRQ `vector`, ignored by default.
+    fn irq_disable(&self, _vector: u32) {}
+
+    /// Handle the interrupt `vector` taken by the CPU, return whether it is
+    /// handled, none by default.
+    fn irq_handle(&self, _vector: u32) -> bool {
+        false
+    }
+
+    /// Get the range of IRQ vectors available to devices, empty by default.
+    fn irq_range(&self) -> Range<u32> {
+        0..0
+    }
+
+    /// Grant or revoke the access to I/O ports of the current process.
+    ///
+    /// The HALs without a TSS I/O permission bitmap do not support it.
+    fn ioport_set_access(&self, _port: u16, _len: u32, _enable: bool) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Load the I/O permission bitmap of the process to run on the current
+    /// CPU, where a set bit grants the access to the port. An empty bitmap
+    /// grants nothing. Nothing to do by default.
+    fn ioport_load_bitmap(&self, _bitmap: &[u64]) {}
+
+    /// Get the physical memory map of the platform.
+    fn memory_map(&self) -> Vec<MemoryRegion>;
+
+    /// Get the total size of RAM in bytes, the sum of the RAM regions in the
+    /// memory map by default.
+    fn physmem_size(&self) -> u64 {
+        self.memory_map()
+            .iter()
+            .filter(|region| region.kind == MemoryRegionKind::Ram)
+            .map(|region| region.size as u64)
+            .sum()
+    }
+
+    /// Get the version string of the kernel.
+    fn version_string(&self) -> &'static str {
+        concat!("zcore-", env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Get the ACPI tables found at boot, empty if there is no ACPI.
+    fn acpi_tables(&self) -> Vec<AcpiTable> {
+        Vec::new()
+    }
+
+    /// Get the number of CPUs that might be online.
+    fn cpu_count(&self) -> u32;
+
+    /// Get the number of the current CPU.
+    fn cpu_id(&self) -> u32 {
+        0
+    }
+
+    /// Get the entry of the syscalls made by function calls in the LibOS.
+    fn libos_syscall_entry(&self) -> Option<usize> {
+        None
+    }
+
+    /// Get the number of bytes in a cache line.
+    fn cache_line_size(&self) -> u32;
+
+    /// Get the CPU features and the number of debug registers.
+    fn cpu_features(&self) -> Features;
+
+    /// Enumerate the PCI buses, return all functions found, none by default.
+    fn pci_enumerate(&self) -> Vec<PciDeviceInfo> {
+        Vec::new()
+    }
+
+    /// Read `width` bytes at `offset` of the config space of the PCI function.
+    fn pci_config_read(&self, _addr: PciAddr, _offset: usize, _width: usize) -> Result<u32> {
+        Err(HalError)
+    }
+
+    /// Write `width` bytes at `offset` of the config space of the PCI function.
+    fn pci_config_write(
+        &self,
+        _addr: PciAddr,
+        _offset: usize,
+        _width: usize,
+        _value: u32,
+    ) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Allocate and enable `count` MSI vectors of the PCI function.
+    fn pci_msi_alloc(&self, _addr: PciAddr, _count: u32) -> Result<u32> {
+        Err(HalError)
+    }
+
+    /// Disable and free the MSI vectors of the PCI function, none allocated
+    /// by default.
+    fn pci_msi_free(&self, _addr: PciAddr) {}
+
+    /// Get the information of the block devices found by the HAL.
+    fn block_devices(&self) -> Vec<BlockDeviceInfo> {
+        Vec::new()
+    }
+
+    /// Read the blocks of the `index`-th block device from `block` to `buf`.
+    fn block_read(&self, _index: usize, _block: u64, _buf: &mut [u8]) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Write `buf` to the blocks of the `index`-th block device from `block`.
+    fn block_write(&self, _index: usize, _block: u64, _buf: &[u8]) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Get the information of the network devices found by the HAL.
+    fn net_devices(&self) -> Vec<NetDeviceInfo> {
+        Vec::new()
+    }
+
+    /// Whether the link of the `index`-th network device is up.
+    fn net_link_up(&self, _index: usize) -> bool {
+        false
+    }
+
+    /// Send an ethernet `frame` by the `index`-th network device.
+    fn net_send(&self, _index: usize, _frame: &[u8]) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Set the handler of the frames received by the `index`-th network
+    /// device.
+    fn net_set_rx_callback(&self, _index: usize, _callback: Option<NetRxCallback>) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Get the framebuffer of the display, `None` if there is no display.
+    fn framebuffer_info(&self) -> Option<FramebufferInfo> {
+        None
+    }
+
+    /// Show the update of the rectangle in the framebuffer on the display.
+    fn framebuffer_flush(&self, _x: u32, _y: u32, _width: u32, _height: u32) {}
+
+    /// Create a hardware virtual machine, return its ID.
+    fn guest_create(&self) -> Result<usize> {
+        Err(HalError)
+    }
+
+    /// Destroy the virtual machine.
+    fn guest_destroy(&self, _guest: usize) {}
+
+    /// Map the page of guest physical address `gpaddr` to the frame of `paddr`.
+    fn guest_map(
+        &self,
+        _guest: usize,
+        _gpaddr: GuestPhysAddr,
+        _paddr: PhysAddr,
+        _flags: MMUFlags,
+    ) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Unmap the page of guest physical address `gpaddr`.
+    fn guest_unmap(&self, _guest: usize, _gpaddr: GuestPhysAddr) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Create a vCPU of the virtual machine starting at `entry`, return its ID.
+    fn vcpu_create(&self, _guest: usize, _entry: GuestPhysAddr) -> Result<usize> {
+        Err(HalError)
+    }
+
+    /// Destroy the vCPU.
+    fn vcpu_destroy(&self, _vcpu: usize) {}
+
+    /// Run the vCPU until an exit to be handled by the kernel.
+    fn vcpu_resume(&self, _vcpu: usize) -> Result<VcpuExit> {
+        Err(HalError)
+    }
+
+    /// Read the general registers of the vCPU.
+    fn vcpu_read_state(&self, _vcpu: usize) -> Result<VcpuState> {
+        Err(HalError)
+    }
+
+    /// Write the general registers of the vCPU.
+    fn vcpu_write_state(&self, _vcpu: usize, _state: &VcpuState) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Complete the input of the last I/O or MMIO exit with `data`.
+    fn vcpu_write_io(&self, _vcpu: usize, _data: &[u8]) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Queue an interrupt of `vector` to be injected into the vCPU.
+    fn vcpu_interrupt(&self, _vcpu: usize, _vector: u32) -> Result<()> {
+        Err(HalError)
+    }
+
+    /// Issue a secure monitor call following the SMC calling convention.
+    ///
+    /// By the `smc` instruction on aarch64, and unknown to the others.
+    fn smc_call(&self, params: &SmcParams) -> SmcResult {
+        smc_call_default(params)
+    }
+
+    /// Copy `len` bytes from `src` to `dst`, one of which is a user address.
+    ///
+    /// The default one does not recover from faults.
+    ///
+    /// # Safety
+    ///
+    /// The kernel side of the copy must be valid for `len` bytes.
+    unsafe fn user_copy(&self, dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+        dst.copy_from_nonoverlapping(src, len);
+        Ok(())
+    }
+
+    /// Save the crashlog to be recovered in the next boot. Nothing is
+    /// persisted by default.
+    fn crashlog_save(&self, _data: &[u8]) {}
+
+    /// Take the crashlog saved in the last boot.
+    fn crashlog_load(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Reboot the system.
+    fn reboot(&self) -> !;
+
+    /// Shut down the system.
+    fn shutdown(&self) -> !;
+
+    /// Fill `buf` with random bytes from the hardware RNG.
+    fn rand_bytes(&self, buf: &mut [u8]);
+
+    /// Allocate a physical frame.
+    fn frame_alloc(&self) -> Option<PhysAddr>;
+
+    /// Allocate `size` contiguous frames aligned to `1 << align_log2` pages,
+    /// return the first one. None can be allocated by default.
+    fn frame_alloc_contiguous(&self, _size: usize, _align_log2: usize) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Deallocate the physical frame of `paddr`.
+    fn frame_dealloc(&self, paddr: PhysAddr);
+
+    /// Get the physical address of the frame filled with zeros.
+    fn zero_frame_paddr(&self) -> PhysAddr;
+
+    /// Get statistics of the physical frame allocator.
+    fn frame_stats(&self) -> FrameStats;
+
+    /// Read physical memory from `paddr` to `buf`.
+    fn pmem_read(&self, paddr: PhysAddr, buf: &mut [u8]);
+
+    /// Write physical memory to `paddr` from `buf`.
+    fn pmem_write(&self, paddr: PhysAddr, buf: &[u8]);
+
+    /// Zero physical memory at `[paddr, paddr + len)`.
+    fn pmem_zero(&self, paddr: PhysAddr, len: usize);
+
+    /// Atomically add `value` to the `u64` at `paddr`, aligned to 8 bytes,
+    /// return the previous value.
+    fn pmem_fetch_add_u64(&self, paddr: PhysAddr, value: u64) -> u64;
+
+    /// Copy content of `src` frame to `target` frame.
+    fn frame_copy(&self, src: PhysAddr, target: PhysAddr);
+
+    /// Copy the content of each `(src, target)` pair of frames, by a
+    /// `frame_copy` per pair by default.
+    fn frame_copy_n(&self, frames: &[(PhysAddr, PhysAddr)]) {
+        for &(src, target) in frames {
+            self.frame_copy(src, target);
+        }
+    }
+
+    /// Zero each `(paddr, len)` range of physical memory, by a `pmem_zero`
+    /// per run of adjacent ranges by default.
+    fn pmem_zero_n(&self, ranges: &[(PhysAddr, usize)]) {
+        for (paddr, len) in zero_runs(ranges) {
+            self.pmem_zero(paddr, len);
+        }
+    }
+
+    /// Flush the physical frame.
+    fn frame_flush(&self, target: PhysAddr);
+
+    /// Create a new page table.
+    fn pt_new(&self) -> Box<dyn PageTableTrait>;
+
+    /// Get the current page table.
+    fn pt_current(&self) -> Box<dyn PageTableTrait>;
+
+    /// Invalidate the TLB entries of the pages in `vaddr` of the page table
+    /// `vmtoken` on all CPUs.
+    fn tlb_flush(&self, _vaddr: Range<VirtAddr>, _vmtoken: usize) {}
+
+    /// Run the user `context` until it traps to the kernel.
+    ///
+    /// Return `Err` if the user programs can not run on this HAL.
+    fn context_run(&self, context: &mut UserContext) -> Result<()>;
+
+    /// Read the input of the console without blocking.
+    fn serial_read(&self, buf: &mut [u8]) -> usize {
+        serial_take(buf)
+    }
+
+    /// Call `callback` once some input of the console is available.
+    fn serial_set_callback(&self, callback: Box<dyn FnOnce() + Send + Sync>) {
+        serial_add_callback(callback);
+    }
+
+    /// Output a string to console.
+    fn serial_write(&self, s: &str);
+}
+
+/// The HAL registered by `set_hal`.
+static HAL: RwLock<Option<&'static dyn Hal>> = RwLock::new(None);
+
+/// Register the HAL used by the kernel, replacing the previous one.
+pub fn set_hal(hal: &'static dyn Hal) {
+    *HAL.write() = Some(hal);
+}
+
+/// Get the HAL registered for the current thread by `host::set_thread_hal`,
+/// or the one registered by `set_hal`.
+///
+/// # Panics
+///
+/// If no HAL is registered.
+pub fn hal() -> &'static dyn Hal {
+    if let Some(hal) = thread_hal() {
+        return hal;
+    }
+    match *HAL.read() {
+        Some(hal) => hal,
+        None => panic!("no HAL is registered"),
+    }
+}
+
+#[cfg(feature = "std")]
+fn thread_hal() -> Option<&'static dyn Hal> {
+    crate::host::thread_hal()
+}
+
+#[cfg(not(feature = "std"))]
+fn thread_hal() -> Option<&'static dyn Hal> {
+    None
+}
+
+#[cfg(target_arch = "aarch64")]
+fn smc_call_default(params: &SmcParams) -> SmcResult {
+    let (arg0, arg1, arg2, arg3, arg6): (u64, u64, u64, u64, u64);
+    let client = ((params.secure_os_id as u64) << 16) | params.client_id as u64;
+    unsafe {
+        asm!(
+            "smc #0",
+            inlateout("x0") params.func_id as u64 => arg0,
+            inlateout("x1") params.arg1 => arg1,
+            inlateout("x2") params.arg2 => arg2,
+            inlateout("x3") params.arg3 => arg3,
+            inlateout("x4") params.arg4 => _,
+            inlateout("x5") params.arg5 => _,
+            inlateout("x6") params.arg6 => arg6,
+            inlateout("x7") client => _,
+            out("x8") _, out("x9") _, out("x10") _, out("x11") _,
+            out("x12") _, out("x13") _, out("x14") _, out("x15") _,
+            out("x16") _, out("x17") _,
+        );
+    }
+    SmcResult {
+        arg0,
+        arg1,
+        arg2,
+        arg3,
+        arg6,
+    }
+}
+
+/// The result of an unknown SMC function.
+#[cfg(not(target_arch = "aarch64"))]
+const SMC_UNKNOWN: u64 = u64::MAX;
+
+#[cfg(not(target_arch = "aarch64"))]
+fn smc_call_default(_params: &SmcParams) -> SmcResult {
+    SmcResult {
+        arg0: SMC_UNKNOWN,
+        ..Default::default()
+    }
+}
+
+/// Split the `(src, target)` pairs of frames into the runs contiguous in
+/// both, as `(src, target, len)`, for the HALs to copy each by a `memcpy`.
+pub fn copy_runs(
+    frames: &[(PhysAddr, PhysAddr)],
+) -> impl Iterator<Item = (PhysAddr, PhysAddr, usize)> + '_ {
+    let mut rest = frames;
+    core::iter::from_fn(move || {
+        let &(src, target) = rest.first()?;
+        let mut n = 1;
+        while let Some(&(next_src, next_target)) = rest.get(n) {
+            if next_src != src + n * PAGE_SIZE || next_target != target + n * PAGE_SIZE {
+                break;
+            }
+            n += 1;
+        }
+        rest = &rest[n..];
+        Some((src, target, n * PAGE_SIZE))
+    })
+}
+
+/// Merge the adjacent `(paddr, len)` ranges.
+fn zero_runs(ranges: &[(PhysAddr, usize)]) -> impl Iterator<Item = (PhysAddr, usize)> + '_ {
+    let mut rest = ranges;
+    core::iter::from_fn(move || {
+        let &(paddr, mut len) = rest.first()?;
+        let mut n = 1;
+        while let Some(&(next, next_len)) = rest.get(n) {
+            if next != paddr + len {
+                break;
+            }
+            len += next_len;
+            n += 1;
+        }
+        rest = &rest[n..];
+        Some((paddr, len))
+    })
+}
+
+/// The maximum size of the crashlog kept across reboots.
+pub const CRASHLOG_CAPACITY: usize = 0x1000;
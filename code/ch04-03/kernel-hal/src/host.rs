@@ -0,0 +1,123 @@
+//! The host threads shared by the HAL implementations on the host OSes.
+//!
+//! The timers are fired on a timer thread sleeping until the next deadline,
+//! and the input of the console is read from stdin on a stdin thread.
+//!
+//! A host thread may use its own HAL instead of the one of the process, so
+//! the tests on different HALs run in parallel.
+
+use {
+    crate::{
+        serial_add_callback, serial_put, serial_take, timer_add, timer_next_deadline, timer_now,
+        timer_remove, timer_tick, Hal,
+    },
+    alloc::boxed::Box,
+    core::{cell::Cell, time::Duration},
+    lazy_static::lazy_static,
+    std::{
+        io::{ErrorKind, Read},
+        sync::{Condvar, Mutex, Once},
+    },
+};
+
+std::thread_local! {
+    /// The HAL of the current host thread.
+    static THREAD_HAL: Cell<Option<&'static dyn Hal>> = Cell::new(None);
+}
+
+/// Register the HAL used on the current host thread in place of the one
+/// registered by `set_hal`, or unregister it if `None`.
+pub fn set_thread_hal(hal: Option<&'static dyn Hal>) {
+    THREAD_HAL.with(|cell| cell.set(hal));
+}
+
+/// Get the HAL registered on the current host thread.
+pub fn thread_hal() -> Option<&'static dyn Hal> {
+    THREAD_HAL.with(|cell| cell.get())
+}
+
+static TIMER_THREAD: Once = Once::new();
+
+lazy_static! {
+    static ref TIMER_LOCK: Mutex<()> = Mutex::new(());
+    static ref TIMER_CONDVAR: Condvar = Condvar::new();
+}
+
+/// Set a new timer.
+///
+/// After `deadline`, the `callback` will be called on the timer thread.
+pub fn timer_set(deadline: Duration, callback: Box<dyn FnOnce(Duration) + Send + Sync>) -> u64 {
+    // hold the lock to not miss the wakeup of the timer thread
+    let _guard = TIMER_LOCK.lock().unwrap();
+    TIMER_THREAD.call_once(|| {
+        std::thread::Builder::new()
+            .name("timer".into())
+            .spawn(timer_thread)
+            .expect("failed to spawn the timer thread");
+    });
+    let id = timer_add(deadline, callback);
+    TIMER_CONDVAR.notify_one();
+    id
+}
+
+/// Cancel the timer `id`.
+///
+/// The timer thread is not woken, and finds nothing to fire at the deadline.
+pub fn timer_cancel(id: u64) {
+    timer_remove(id);
+}
+
+/// The host thread sleeping until the next deadline to call `timer_tick`.
+fn timer_thread() {
+    let mut guard = TIMER_LOCK.lock().unwrap();
+    loop {
+        let now = timer_now();
+        guard = match timer_next_deadline() {
+            Some(deadline) if deadline <= now => {
+                drop(guard);
+                timer_tick();
+                TIMER_LOCK.lock().unwrap()
+            }
+            Some(deadline) => TIMER_CONDVAR.wait_timeout(guard, deadline - now).unwrap().0,
+            None => TIMER_CONDVAR.wait(guard).unwrap(),
+        };
+    }
+}
+
+static STDIN_THREAD: Once = Once::new();
+
+/// Read the available input of stdin without blocking.
+pub fn serial_read(buf: &mut [u8]) -> usize {
+    STDIN_THREAD.call_once(spawn_stdin_thread);
+    serial_take(buf)
+}
+
+/// Call `callback` once some input of stdin is available.
+///
+/// The `callback` will be called on the stdin thread.
+pub fn serial_set_callback(callback: Box<dyn FnOnce() + Send + Sync>) {
+    STDIN_THREAD.call_once(spawn_stdin_thread);
+    serial_add_callback(callback);
+}
+
+fn spawn_stdin_thread() {
+    std::thread::Builder::new()
+        .name("stdin".into())
+        .spawn(stdin_thread)
+        .expect("failed to spawn the stdin thread");
+}
+
+/// The host thread reading stdin to the input queue of the HAL, until the
+/// end of the input.
+fn stdin_thread() {
+    let mut buf = [0u8; 256];
+    let mut stdin = std::io::stdin();
+    loop {
+        match stdin.read(&mut buf) {
+            Ok(0) => return,
+            Ok(len) => serial_put(&buf[..len]),
+            Err(err) if err.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return,
+        }
+    }
+}
@@ -0,0 +1,730 @@
+//! The functions of the HAL used by the kernel, forwarded to the HAL
+//! registered.
+
+use super::*;
+use crate::vdso::Features;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::future::Future;
+use core::ops::Range;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+#[derive(Debug)]
+pub struct HalError;
+/// The result type returned by HAL functions.
+pub type Result<T> = core::result::Result<T, HalError>;
+
+#[repr(C)]
+pub struct Thread {
+    id: usize,
+}
+
+impl Thread {
+    /// Spawn a new thread, which only runs on the CPUs of `affinity`.
+    pub fn spawn(
+        future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+        vmtoken: usize,
+        affinity: CpuAffinity,
+    ) -> Self {
+        hal().thread_spawn(future, vmtoken, affinity);
+        Thread { id: 0 }
+    }
+
+    /// Change the CPUs the current thread runs on, from the next time it is
+    /// scheduled.
+    pub fn set_affinity(affinity: CpuAffinity) {
+        hal().thread_set_affinity(affinity)
+    }
+
+    /// Set tid and pid of current task.
+    pub fn set_tid(tid: u64, pid: u64) {
+        hal().thread_set_tid(tid, pid)
+    }
+
+    /// Get tid and pid of current task.
+    pub fn get_tid() -> (u64, u64) {
+        hal().thread_get_tid()
+    }
+}
+
+pub fn timer_now() -> Duration {
+    hal().timer_now()
+}
+
+/// Read the real-time clock, the time since the UNIX epoch.
+///
+/// Return `None` if there is no real-time clock.
+pub fn rtc_now() -> Option<Duration> {
+    hal().rtc_now()
+}
+
+/// Get the current value of the high-resolution timebase.
+///
+/// The monotonic time returned by `timer_now` is derived from it.
+pub fn timer_ticks() -> u64 {
+    hal().timer_ticks()
+}
+
+/// Get the frequency of the timebase in ticks per second.
+pub fn timer_ticks_per_second() -> u64 {
+    hal().timer_ticks_per_second()
+}
+
+/// Set a new timer. After `deadline`, the `callback` will be called.
+///
+/// The timer is usually added to the queue fired by `timer_tick`, and the
+/// `callback` is never called in the interrupt context. Return the ID of the
+/// timer to cancel it.
+pub fn timer_set(deadline: Duration, callback: Box<dyn FnOnce(Duration) + Send + Sync>) -> u64 {
+    hal().timer_set(deadline, callback)
+}
+
+/// Cancel the timer `id` set by [`timer_set`], if it is not fired yet.
+pub fn timer_cancel(id: u64) {
+    hal().timer_cancel(id)
+}
+
+/// The handler of an IRQ.
+pub type IrqHandler = Box<dyn Fn() + Send + Sync>;
+
+/// Register the `handler` of IRQ `vector` in `mode`.
+///
+/// The IRQ is disabled until `irq_enable` is called.
+pub fn irq_register(vector: u32, mode: IrqMode, handler: IrqHandler) -> Result<()> {
+    hal().irq_register(vector, mode, handler)
+}
+
+/// Unregister the handler of IRQ `vector`.
+pub fn irq_unregister(vector: u32) -> Result<()> {
+    hal().irq_unregister(vector)
+}
+
+/// Enable (unmask) IRQ `vector`.
+pub fn irq_enable(vector: u32) {
+    hal().irq_enable(vector)
+}
+
+/// Disable (mask) IRQ `vector`.
+pub fn irq_disable(vector: u32) {
+    hal().irq_disable(vector)
+}
+
+/// Grant or revoke the access to I/O ports `[port, port + len)` of the
+/// current process.
+pub fn ioport_set_access(port: u16, len: u32, enable: bool) -> Result<()> {
+    hal().ioport_set_access(port, len, enable)
+}
+
+/// Load the I/O permission bitmap of the process to run on the current CPU,
+/// before switching to its user context.
+pub fn ioport_load_bitmap(bitmap: &[u64]) {
+    hal().ioport_load_bitmap(bitmap)
+}
+
+/// Get the physical memory map of the platform.
+pub fn memory_map() -> Vec<MemoryRegion> {
+    hal().memory_map()
+}
+
+/// Get the total size of RAM in bytes.
+///
+/// It sums up the RAM regions in the memory map by default.
+pub fn physmem_size() -> u64 {
+    hal().physmem_size()
+}
+
+/// Get the version string of the kernel, returned by
+/// `zx_system_get_version_string`.
+pub fn version_string() -> &'static str {
+    hal().version_string()
+}
+
+/// Handle the interrupt `vector` taken by the CPU, return whether it is handled.
+pub fn irq_handle(vector: u32) -> bool {
+    hal().irq_handle(vector)
+}
+
+/// Get the ACPI tables found at boot, empty if there is no ACPI.
+pub fn acpi_tables() -> Vec<AcpiTable> {
+    hal().acpi_tables()
+}
+
+/// Get the range of IRQ vectors available to devices.
+pub fn irq_range() -> Range<u32> {
+    hal().irq_range()
+}
+
+/// Get the number of CPUs that might be online.
+pub fn cpu_count() -> u32 {
+    hal().cpu_count()
+}
+
+/// Get the entry of the syscalls made by function calls in the LibOS, to be
+/// filled in the vDSO, or `None` if they are made by the instruction of the
+/// architecture.
+pub fn libos_syscall_entry() -> Option<usize> {
+    hal().libos_syscall_entry()
+}
+
+/// Get the number of the current CPU, from 0 to `cpu_count() - 1`.
+pub fn cpu_id() -> u32 {
+    hal().cpu_id()
+}
+
+/// Get the number of bytes in a cache line.
+pub fn cache_line_size() -> u32 {
+    hal().cache_line_size()
+}
+
+/// Get the CPU features and the number of debug registers.
+pub fn cpu_features() -> Features {
+    hal().cpu_features()
+}
+
+/// Enumerate the PCI buses, return all functions found.
+pub fn pci_enumerate() -> Vec<PciDeviceInfo> {
+    hal().pci_enumerate()
+}
+
+/// Read `width` bytes at `offset` of the config space of the PCI function.
+pub fn pci_config_read(addr: PciAddr, offset: usize, width: usize) -> Result<u32> {
+    hal().pci_config_read(addr, offset, width)
+}
+
+/// Write `width` bytes at `offset` of the config space of the PCI function.
+pub fn pci_config_write(addr: PciAddr, offset: usize, width: usize, value: u32) -> Result<()> {
+    hal().pci_config_write(addr, offset, width, value)
+}
+
+/// Allocate and enable `count` MSI vectors of the PCI function, return the
+/// first vector.
+pub fn pci_msi_alloc(addr: PciAddr, count: u32) -> Result<u32> {
+    hal().pci_msi_alloc(addr, count)
+}
+
+/// Disable and free the MSI vectors of the PCI function.
+pub fn pci_msi_free(addr: PciAddr) {
+    hal().pci_msi_free(addr)
+}
+
+/// Get the information of the block devices found by the HAL.
+pub fn block_devices() -> Vec<BlockDeviceInfo> {
+    hal().block_devices()
+}
+
+/// Read the blocks of the `index`-th block device from `block` to `buf`.
+///
+/// The length of `buf` should be a multiple of the block size.
+pub fn block_read(index: usize, block: u64, buf: &mut [u8]) -> Result<()> {
+    hal().block_read(index, block, buf)
+}
+
+/// Write `buf` to the blocks of the `index`-th block device from `block`.
+///
+/// The length of `buf` should be a multiple of the block size.
+pub fn block_write(index: usize, block: u64, buf: &[u8]) -> Result<()> {
+    hal().block_write(index, block, buf)
+}
+
+/// Get the information of the network devices found by the HAL.
+pub fn net_devices() -> Vec<NetDeviceInfo> {
+    hal().net_devices()
+}
+
+/// Whether the link of the `index`-th network device is up.
+pub fn net_link_up(index: usize) -> bool {
+    hal().net_link_up(index)
+}
+
+/// Send an ethernet `frame` by the `index`-th network device.
+pub fn net_send(index: usize, frame: &[u8]) -> Result<()> {
+    hal().net_send(index, frame)
+}
+
+/// The handler of the frames received by a network device.
+pub type NetRxCallback = Box<dyn Fn(&[u8]) + Send + Sync>;
+
+/// Set the handler of the frames received by the `index`-th network device,
+/// replacing the previous one. The frames are dropped without a handler.
+///
+/// The `callback` may be called in the interrupt.
+pub fn net_set_rx_callback(index: usize, callback: Option<NetRxCallback>) -> Result<()> {
+    hal().net_set_rx_callback(index, callback)
+}
+
+/// Get the framebuffer of the display, `None` if there is no display.
+pub fn framebuffer_info() -> Option<FramebufferInfo> {
+    hal().framebuffer_info()
+}
+
+/// Show the update of the rectangle in the framebuffer on the display.
+///
+/// Nothing to do if the framebuffer is scanned out directly.
+pub fn framebuffer_flush(x: u32, y: u32, width: u32, height: u32) {
+    hal().framebuffer_flush(x, y, width, height)
+}
+
+/// Create a hardware virtual machine, return its ID.
+///
+/// Fail if the hardware virtualization is not supported.
+pub fn guest_create() -> Result<usize> {
+    hal().guest_create()
+}
+
+/// Destroy the virtual machine.
+pub fn guest_destroy(guest: usize) {
+    hal().guest_destroy(guest)
+}
+
+/// Map the page of guest physical address `gpaddr` to the frame of `paddr`.
+pub fn guest_map(
+    guest: usize,
+    gpaddr: GuestPhysAddr,
+    paddr: PhysAddr,
+    flags: MMUFlags,
+) -> Result<()> {
+    hal().guest_map(guest, gpaddr, paddr, flags)
+}
+
+/// Unmap the page of guest physical address `gpaddr`.
+pub fn guest_unmap(guest: usize, gpaddr: GuestPhysAddr) -> Result<()> {
+    hal().guest_unmap(guest, gpaddr)
+}
+
+/// Create a vCPU of the virtual machine starting at `entry`, return its ID.
+pub fn vcpu_create(guest: usize, entry: GuestPhysAddr) -> Result<usize> {
+    hal().vcpu_create(guest, entry)
+}
+
+/// Destroy the vCPU.
+pub fn vcpu_destroy(vcpu: usize) {
+    hal().vcpu_destroy(vcpu)
+}
+
+/// Run the vCPU until an exit to be handled by the kernel.
+///
+/// Fail if the vCPU can not continue, e.g. on a triple fault.
+pub fn vcpu_resume(vcpu: usize) -> Result<VcpuExit> {
+    hal().vcpu_resume(vcpu)
+}
+
+/// Read the general registers of the vCPU.
+pub fn vcpu_read_state(vcpu: usize) -> Result<VcpuState> {
+    hal().vcpu_read_state(vcpu)
+}
+
+/// Write the general registers of the vCPU.
+pub fn vcpu_write_state(vcpu: usize, state: &VcpuState) -> Result<()> {
+    hal().vcpu_write_state(vcpu, state)
+}
+
+/// Complete the input of the last I/O or MMIO exit with `data`.
+pub fn vcpu_write_io(vcpu: usize, data: &[u8]) -> Result<()> {
+    hal().vcpu_write_io(vcpu, data)
+}
+
+/// Queue an interrupt of `vector`, to be injected into the vCPU when it is
+/// able to take interrupts.
+pub fn vcpu_interrupt(vcpu: usize, vector: u32) -> Result<()> {
+    hal().vcpu_interrupt(vcpu, vector)
+}
+
+/// Issue a secure monitor call following the SMC calling convention.
+pub fn smc_call(params: &SmcParams) -> SmcResult {
+    hal().smc_call(params)
+}
+
+/// Copy `len` bytes from `src` to `dst`, one of which is a user address.
+///
+/// Return `Err` instead of crashing if the copy faults. The default one does
+/// not recover from faults.
+///
+/// # Safety
+///
+/// The kernel side of the copy must be valid for `len` bytes.
+pub unsafe fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> Result<()> {
+    hal().user_copy(dst, src, len)
+}
+
+/// Save the crashlog to be recovered in the next boot.
+///
+/// It is truncated to `CRASHLOG_CAPACITY` bytes, and nothing is persisted if
+/// the platform has no place to keep it.
+pub fn crashlog_save(data: &[u8]) {
+    hal().crashlog_save(data)
+}
+
+/// Take the crashlog saved in the last boot.
+///
+/// It is empty if nothing is saved, or the crashlog is lost, such as on a
+/// cold boot.
+pub fn crashlog_load() -> Vec<u8> {
+    hal().crashlog_load()
+}
+
+/// Reboot the system.
+pub fn reboot() -> ! {
+    hal().reboot()
+}
+
+/// Shut down the system.
+pub fn shutdown() -> ! {
+    hal().shutdown()
+}
+
+/// Fill `buf` with random bytes from the hardware RNG.
+pub fn rand_bytes(buf: &mut [u8]) {
+    hal().rand_bytes(buf)
+}
+
+#[repr(C)]
+pub struct PhysFrame {
+    paddr: PhysAddr,
+}
+
+impl PhysFrame {
+    pub fn alloc() -> Option<Self> {
+        hal().frame_alloc().map(|paddr| PhysFrame { paddr })
+    }
+
+    pub fn alloc_contiguous_base(size: usize, align_log2: usize) -> Option<PhysAddr> {
+        hal().frame_alloc_contiguous(size, align_log2)
+    }
+
+    pub fn alloc_contiguous(size: usize, align_log2: usize) -> Vec<Self> {
+        PhysFrame::alloc_contiguous_base(size, align_log2).map_or(Vec::new(), |base| {
+            (0..size)
+                .map(|i| PhysFrame {
+                    paddr: base + i * PAGE_SIZE,
+                })
+                .collect()
+        })
+    }
+
+    pub fn alloc_zeroed() -> Option<Self> {
+        Self::alloc().map(|f| {
+            pmem_zero(f.addr(), PAGE_SIZE);
+            f
+        })
+    }
+
+    pub fn alloc_contiguous_zeroed(size: usize, align_log2: usize) -> Vec<Self> {
+        PhysFrame::alloc_contiguous_base(size, align_log2).map_or(Vec::new(), |base| {
+            pmem_zero(base, size * PAGE_SIZE);
+            (0..size)
+                .map(|i| PhysFrame {
+                    paddr: base + i * PAGE_SIZE,
+                })
+                .collect()
+        })
+    }
+
+    pub fn addr(&self) -> PhysAddr {
+        self.paddr
+    }
+
+    pub fn zero_frame_addr() -> PhysAddr {
+        hal().zero_frame_paddr()
+    }
+}
+
+impl Drop for PhysFrame {
+    fn drop(&mut self) {
+        hal().frame_dealloc(self.paddr)
+    }
+}
+
+/// Statistics of the physical frame allocator.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameStats {
+    /// Number of frames managed by the allocator.
+    pub total: usize,
+    /// Number of frames which are not allocated yet.
+    pub free: usize,
+}
+
+/// Get statistics of the physical frame allocator.
+pub fn frame_stats() -> FrameStats {
+    hal().frame_stats()
+}
+
+/// Statistics of the kernel heap.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HeapStats {
+    /// Number of bytes the heap has grown to.
+    pub total: usize,
+    /// Number of bytes of the heap not allocated.
+    pub free: usize,
+}
+
+/// The bytes allocated by `CountingHeap`, and the most of them ever.
+static HEAP_USED: AtomicUsize = AtomicUsize::new(0);
+static HEAP_PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// A global allocator counting the bytes allocated by `inner`, reported by
+/// `heap_stats`.
+///
+/// The allocators below do not tell how large their heaps are, so the heap
+/// is taken to be as large as the most bytes ever allocated.
+pub struct CountingHeap<A> {
+    inner: A,
+}
+
+impl<A> CountingHeap<A> {
+    /// Count the allocations of `inner`.
+    pub const fn new(inner: A) -> Self {
+        CountingHeap { inner }
+    }
+
+    fn count_alloc(size: usize) {
+        let used = HEAP_USED.fetch_add(size, Ordering::Relaxed) + size;
+        HEAP_PEAK.fetch_max(used, Ordering::Relaxed);
+    }
+
+    fn count_dealloc(size: usize) {
+        HEAP_USED.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingHeap<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            Self::count_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        Self::count_dealloc(layout.size());
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            Self::count_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            Self::count_dealloc(layout.size());
+            Self::count_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+/// Get statistics of the kernel heap, all zero unless the global allocator
+/// is a `CountingHeap`.
+pub fn heap_stats() -> HeapStats {
+    let used = HEAP_USED.load(Ordering::Relaxed);
+    let total = HEAP_PEAK.load(Ordering::Relaxed).max(used);
+    HeapStats {
+        total,
+        free: total - used,
+    }
+}
+
+/// Read physical memory from `paddr` to `buf`.
+pub fn pmem_read(paddr: PhysAddr, buf: &mut [u8]) {
+    hal().pmem_read(paddr, buf)
+}
+
+/// Write physical memory to `paddr` from `buf`.
+pub fn pmem_write(paddr: PhysAddr, buf: &[u8]) {
+    hal().pmem_write(paddr, buf)
+}
+
+/// Zero physical memory at `[paddr, paddr + len)`
+pub fn pmem_zero(paddr: PhysAddr, len: usize) {
+    hal().pmem_zero(paddr, len)
+}
+
+/// Atomically add `value` to the `u64` at `paddr`, return the previous value.
+pub fn pmem_fetch_add_u64(paddr: PhysAddr, value: u64) -> u64 {
+    hal().pmem_fetch_add_u64(paddr, value)
+}
+
+/// Copy content of `src` frame to `target` frame.
+pub fn frame_copy(src: PhysAddr, target: PhysAddr) {
+    hal().frame_copy(src, target)
+}
+
+/// Copy the content of each `(src, target)` pair of frames.
+pub fn frame_copy_n(frames: &[(PhysAddr, PhysAddr)]) {
+    hal().frame_copy_n(frames)
+}
+
+/// Zero each `(paddr, len)` range of physical memory.
+pub fn pmem_zero_n(ranges: &[(PhysAddr, usize)]) {
+    hal().pmem_zero_n(ranges)
+}
+
+/// Flush the physical frame.
+pub fn frame_flush(target: PhysAddr) {
+    hal().frame_flush(target)
+}
+
+pub trait PageTableTrait: Sync + Send {
+    /// Map the page of `vaddr` to the frame of `paddr` with `flags`.
+    fn map(&mut self, _vaddr: VirtAddr, _paddr: PhysAddr, _flags: MMUFlags) -> Result<()>;
+
+    /// Unmap the page of `vaddr`.
+    fn unmap(&mut self, _vaddr: VirtAddr) -> Result<()>;
+
+    /// Change the `flags` of the page of `vaddr`.
+    fn protect(&mut self, _vaddr: VirtAddr, _flags: MMUFlags) -> Result<()>;
+
+    /// Query the physical address which the page of `vaddr` maps to.
+    fn query(&mut self, _vaddr: VirtAddr) -> Result<PhysAddr>;
+
+    /// Get the physical address of root page table.
+    fn table_phys(&self) -> PhysAddr;
+
+    #[cfg(target_arch = "riscv64")]
+    /// Activate this page table
+    fn activate(&self);
+
+    fn map_many(
+        &mut self,
+        mut vaddr: VirtAddr,
+        paddrs: &[PhysAddr],
+        flags: MMUFlags,
+    ) -> Result<()> {
+        for &paddr in paddrs {
+            self.map(vaddr, paddr, flags)?;
+            vaddr += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    fn map_cont(
+        &mut self,
+        mut vaddr: VirtAddr,
+        paddr: PhysAddr,
+        pages: usize,
+        flags: MMUFlags,
+    ) -> Result<()> {
+        for i in 0..pages {
+            let paddr = paddr + i * PAGE_SIZE;
+            self.map(vaddr, paddr, flags)?;
+            vaddr += PAGE_SIZE;
+        }
+        Ok(())
+    }
+
+    fn unmap_cont(&mut self, vaddr: VirtAddr, pages: usize) -> Result<()> {
+        for i in 0..pages {
+            self.unmap(vaddr + i * PAGE_SIZE)?;
+        }
+        Ok(())
+    }
+}
+
+/// Page Table, created by the HAL registered.
+pub struct PageTable {
+    inner: Box<dyn PageTableTrait>,
+}
+
+impl PageTable {
+    /// Get current page table
+    pub fn current() -> Self {
+        PageTable {
+            inner: hal().pt_current(),
+        }
+    }
+
+    /// Create a new `PageTable`.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        PageTable {
+            inner: hal().pt_new(),
+        }
+    }
+}
+
+impl PageTableTrait for PageTable {
+    fn map(&mut self, vaddr: VirtAddr, paddr: PhysAddr, flags: MMUFlags) -> Result<()> {
+        self.inner.map(vaddr, paddr, flags)
+    }
+
+    fn unmap(&mut self, vaddr: VirtAddr) -> Result<()> {
+        self.inner.unmap(vaddr)
+    }
+
+    fn protect(&mut self, vaddr: VirtAddr, flags: MMUFlags) -> Result<()> {
+        self.inner.protect(vaddr, flags)
+    }
+
+    fn query(&mut self, vaddr: VirtAddr) -> Result<PhysAddr> {
+        self.inner.query(vaddr)
+    }
+
+    fn table_phys(&self) -> PhysAddr {
+        self.inner.table_phys()
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    fn activate(&self) {
+        self.inner.activate()
+    }
+
+    fn map_many(&mut self, vaddr: VirtAddr, paddrs: &[PhysAddr], flags: MMUFlags) -> Result<()> {
+        self.inner.map_many(vaddr, paddrs, flags)
+    }
+
+    fn map_cont(
+        &mut self,
+        vaddr: VirtAddr,
+        paddr: PhysAddr,
+        pages: usize,
+        flags: MMUFlags,
+    ) -> Result<()> {
+        self.inner.map_cont(vaddr, paddr, pages, flags)
+    }
+
+    fn unmap_cont(&mut self, vaddr: VirtAddr, pages: usize) -> Result<()> {
+        self.inner.unmap_cont(vaddr, pages)
+    }
+}
+
+/// Invalidate the TLB entries of the pages in `vaddr` of the page table
+/// `vmtoken` on all CPUs, after they are unmapped or protected.
+pub fn tlb_flush(vaddr: Range<VirtAddr>, vmtoken: usize) {
+    hal().tlb_flush(vaddr, vmtoken)
+}
+
+/// Run the user `context` until it traps to the kernel.
+///
+/// Return `Err` if the user programs can not run on this HAL.
+pub fn context_run(context: &mut UserContext) -> Result<()> {
+    hal().context_run(context)
+}
+
+/// Read the input of the console without blocking.
+///
+/// The input is taken from the queue filled by `serial_put` or
+/// `serial_push` by default.
+pub fn serial_read(buf: &mut [u8]) -> usize {
+    hal().serial_read(buf)
+}
+
+/// Call `callback` once some input of the console is available, never in
+/// the interrupt context.
+pub fn serial_set_callback(callback: Box<dyn FnOnce() + Send + Sync>) {
+    hal().serial_set_callback(callback)
+}
+
+/// Output a string to console.
+pub fn serial_write(s: &str) {
+    hal().serial_write(s)
+}
@@ -1,27 +1,61 @@
 //! Hardware Abstraction Layer
 
 #![no_std]
-#![feature(linkage)]
+#![cfg_attr(any(target_arch = "aarch64", target_arch = "riscv64"), feature(asm))]
 #![deny(warnings)]
 
 extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 pub mod defs {
     use bitflags::bitflags;
     use numeric_enum_macro::numeric_enum;
 
     bitflags! {
+        /// The flags of a mapping.
+        ///
+        /// The low 2 bits are a field of the `CachePolicy` of the mapping,
+        /// read by `cache_policy` and set by `with_cache_policy`. The mapping
+        /// is executable only with `EXECUTE` and without `NO_EXECUTE`.
         pub struct MMUFlags: usize {
-            #[allow(clippy::identity_op)]
-            const CACHE_1   = 1 << 0;
-            const CACHE_2   = 1 << 1;
-            const READ      = 1 << 2;
-            const WRITE     = 1 << 3;
-            const EXECUTE   = 1 << 4;
-            const USER      = 1 << 5;
+            const CACHE_POLICY  = 0b11;
+            const READ          = 1 << 2;
+            const WRITE         = 1 << 3;
+            const EXECUTE       = 1 << 4;
+            const USER          = 1 << 5;
+            const NO_EXECUTE    = 1 << 6;
             const RXW = Self::READ.bits | Self::WRITE.bits | Self::EXECUTE.bits;
         }
     }
+
+    impl MMUFlags {
+        /// Get the cache policy of the mapping.
+        pub fn cache_policy(self) -> CachePolicy {
+            match (self & MMUFlags::CACHE_POLICY).bits {
+                0 => CachePolicy::Cached,
+                1 => CachePolicy::Uncached,
+                2 => CachePolicy::UncachedDevice,
+                _ => CachePolicy::WriteCombining,
+            }
+        }
+
+        /// Replace the cache policy of the mapping by `policy`.
+        pub fn with_cache_policy(self, policy: CachePolicy) -> Self {
+            (self - MMUFlags::CACHE_POLICY) | MMUFlags::from_bits_truncate(policy as u32 as usize)
+        }
+
+        /// Whether the memory of the mapping is cached.
+        pub fn is_cached(self) -> bool {
+            self.cache_policy() == CachePolicy::Cached
+        }
+
+        /// Whether the instructions may be fetched from the mapping.
+        pub fn is_executable(self) -> bool {
+            self.contains(MMUFlags::EXECUTE) && !self.contains(MMUFlags::NO_EXECUTE)
+        }
+    }
+
     numeric_enum! {
         #[repr(u32)]
         #[derive(Debug, PartialEq, Clone, Copy)]
@@ -41,16 +75,216 @@ pub mod defs {
 
     pub const CACHE_POLICY_MASK: u32 = 3;
 
+    /// The trigger mode and polarity of an IRQ.
+    #[derive(Debug, PartialEq, Clone, Copy)]
+    pub enum IrqMode {
+        EdgeLow,
+        EdgeHigh,
+        EdgeBoth,
+        LevelLow,
+        LevelHigh,
+    }
+
+    impl IrqMode {
+        /// Whether the IRQ is level triggered.
+        pub fn is_level(self) -> bool {
+            matches!(self, IrqMode::LevelLow | IrqMode::LevelHigh)
+        }
+    }
+
+    /// The address of a PCI function.
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+    pub struct PciAddr {
+        pub bus: u8,
+        pub device: u8,
+        pub function: u8,
+    }
+
+    /// A base address register of a PCI function.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum PciBar {
+        /// Memory mapped I/O range.
+        Mmio { addr: PhysAddr, size: usize },
+        /// I/O port range.
+        Pio { addr: u16, size: usize },
+    }
+
+    /// A PCI function found by bus enumeration.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct PciDeviceInfo {
+        pub addr: PciAddr,
+        pub vendor_id: u16,
+        pub device_id: u16,
+        pub base_class: u8,
+        pub sub_class: u8,
+        pub program_interface: u8,
+        pub revision_id: u8,
+        pub bars: [Option<PciBar>; 6],
+        /// The IRQ vector the legacy interrupt is routed to.
+        pub legacy_irq: Option<u32>,
+        /// The maximum number of MSI vectors, 0 if MSI is not supported.
+        pub msi_count: u32,
+    }
+
+    /// A block device found by the HAL.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct BlockDeviceInfo {
+        /// The size of each block in bytes.
+        pub block_size: usize,
+        pub block_count: u64,
+    }
+
+    /// A network device found by the HAL.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct NetDeviceInfo {
+        /// The MAC address.
+        pub mac: [u8; 6],
+        /// The maximum size of the payload of the frames.
+        pub mtu: usize,
+    }
+
+    /// An ACPI table in the physical memory.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct AcpiTable {
+        pub signature: [u8; 4],
+        pub paddr: PhysAddr,
+        /// The size of the table with its header.
+        pub size: usize,
+    }
+
+    /// The framebuffer of a display, in 32-bit pixels of the blue, green,
+    /// red and an unused byte in memory order.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct FramebufferInfo {
+        pub paddr: PhysAddr,
+        /// The size of the framebuffer in bytes.
+        pub size: usize,
+        pub width: u32,
+        pub height: u32,
+        /// The number of pixels in each row.
+        pub stride: u32,
+    }
+
+    /// The kind of a region in the physical memory map.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum MemoryRegionKind {
+        Ram,
+        Mmio,
+        Reserved,
+    }
+
+    /// A region in the physical memory map.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct MemoryRegion {
+        pub kind: MemoryRegionKind,
+        pub addr: PhysAddr,
+        pub size: usize,
+    }
+
+    /// The general registers of a vCPU, in the layout of `zx_vcpu_state_t`.
+    #[repr(C)]
+    #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+    pub struct VcpuState {
+        pub rax: u64,
+        pub rcx: u64,
+        pub rdx: u64,
+        pub rbx: u64,
+        pub rsp: u64,
+        pub rbp: u64,
+        pub rsi: u64,
+        pub rdi: u64,
+        pub r8: u64,
+        pub r9: u64,
+        pub r10: u64,
+        pub r11: u64,
+        pub r12: u64,
+        pub r13: u64,
+        pub r14: u64,
+        pub r15: u64,
+        pub rflags: u64,
+    }
+
+    /// The reason a vCPU exits to the kernel.
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum VcpuExit {
+        /// Port I/O, `data` is valid for output.
+        Io {
+            port: u16,
+            access_size: u8,
+            input: bool,
+            data: u32,
+        },
+        /// Access to a guest physical address not backed by memory,
+        /// `data` is valid for write.
+        Mmio {
+            addr: GuestPhysAddr,
+            access_size: u8,
+            write: bool,
+            data: u64,
+        },
+        /// The vCPU is halted until an interrupt.
+        Halt,
+    }
+
+    /// The parameters of an SMC, in the layout of `zx_smc_parameters_t`.
+    #[repr(C)]
+    #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+    pub struct SmcParams {
+        pub func_id: u32,
+        pub padding1: [u8; 4],
+        pub arg1: u64,
+        pub arg2: u64,
+        pub arg3: u64,
+        pub arg4: u64,
+        pub arg5: u64,
+        pub arg6: u64,
+        pub client_id: u16,
+        pub secure_os_id: u16,
+        pub padding2: [u8; 4],
+    }
+
+    /// The result of an SMC, in the layout of `zx_smc_result_t`.
+    #[repr(C)]
+    #[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+    pub struct SmcResult {
+        pub arg0: u64,
+        pub arg1: u64,
+        pub arg2: u64,
+        pub arg3: u64,
+        /// The client ID of the secure monitor.
+        pub arg6: u64,
+    }
+
+    /// The CPUs a thread may run on, bit `i` for CPU `i`.
+    ///
+    /// The thread runs on any CPU if none of them is online.
+    pub type CpuAffinity = u64;
+
+    /// Any CPU.
+    pub const CPU_AFFINITY_ALL: CpuAffinity = u64::MAX;
+
     pub type PhysAddr = usize;
     pub type VirtAddr = usize;
     pub type DevVAddr = usize;
+    pub type GuestPhysAddr = usize;
     pub const PAGE_SIZE: usize = 0x1000;
 }
 
-mod dummy;
+mod context;
+mod future;
+mod hal;
+#[cfg(feature = "std")]
+pub mod host;
+mod interface;
+mod serial;
+mod timer;
 pub mod user;
 pub mod vdso;
 
+pub use self::context::*;
 pub use self::defs::*;
-pub use self::dummy::*;
-pub use trapframe::{GeneralRegs, UserContext};
+pub use self::future::*;
+pub use self::hal::*;
+pub use self::interface::*;
+pub use self::serial::*;
+pub use self::timer::*;
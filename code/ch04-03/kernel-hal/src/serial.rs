@@ -0,0 +1,85 @@
+//! The serial input queue shared by the HAL implementations.
+//!
+//! The input is put by `serial_put` from the host stdin threads, or by
+//! `serial_push` in the UART interrupts of the bare metal backends, and
+//! taken by `serial_read`. The waiters are called back when some input is
+//! available, never in the interrupt context.
+
+use {
+    alloc::{boxed::Box, collections::VecDeque, vec::Vec},
+    core::mem,
+    lazy_static::lazy_static,
+    spin::Mutex,
+};
+
+type Callback = Box<dyn FnOnce() + Send + Sync>;
+
+#[derive(Default)]
+struct SerialQueue {
+    input: VecDeque<u8>,
+    callbacks: Vec<Callback>,
+}
+
+lazy_static! {
+    static ref SERIAL: Mutex<SerialQueue> = Mutex::new(SerialQueue::default());
+}
+
+/// Put the received `data` to the queue, and call back the waiters.
+///
+/// It is called by the host stdin threads, out of the interrupt context.
+pub fn serial_put(data: &[u8]) {
+    serial_push(data);
+    // the lock is released before calling, which may add new callbacks
+    for callback in serial_take_callbacks() {
+        callback();
+    }
+}
+
+/// Put the received `data` to the queue, without calling back the waiters.
+///
+/// The bare metal backends call it in the UART interrupt, and call the
+/// callbacks taken by `serial_take_callbacks` later out of the interrupt.
+pub fn serial_push(data: &[u8]) {
+    if !data.is_empty() {
+        SERIAL.lock().input.extend(data);
+    }
+}
+
+/// Take the callbacks of the waiters if some input is in the queue, to be
+/// called without the lock of the queue.
+///
+/// The bare metal backends should call it with the UART interrupt disabled.
+pub fn serial_take_callbacks() -> Vec<Callback> {
+    let mut serial = SERIAL.lock();
+    if serial.input.is_empty() {
+        return Vec::new();
+    }
+    mem::take(&mut serial.callbacks)
+}
+
+/// Take the input in the queue to `buf` without blocking.
+///
+/// It is the default of `serial_read`. The bare metal backends should call
+/// it with the UART interrupt disabled.
+pub fn serial_take(buf: &mut [u8]) -> usize {
+    let mut serial = SERIAL.lock();
+    let len = buf.len().min(serial.input.len());
+    for (dst, src) in buf.iter_mut().zip(serial.input.drain(..len)) {
+        *dst = src;
+    }
+    len
+}
+
+/// Call `callback` once some input is in the queue, or now if there is.
+///
+/// It is the default of `serial_set_callback`, with the same requirement as
+/// [`serial_take`].
+pub fn serial_add_callback(callback: Callback) {
+    let mut serial = SERIAL.lock();
+    if serial.input.is_empty() {
+        serial.callbacks.push(callback);
+    } else {
+        drop(serial);
+        callback();
+    }
+}
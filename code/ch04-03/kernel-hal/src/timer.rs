@@ -0,0 +1,93 @@
+//! The timer queue shared by the HAL implementations.
+//!
+//! The timers are added by `timer_set`, removed by `timer_cancel`, and fired
+//! by `timer_tick` called from the host timer threads or the executors of
+//! the backends.
+//!
+//! `timer_tick` must not be called in the interrupt context, as the
+//! callbacks wake tasks and take locks that are not safe there, and the
+//! queue is locked with the interrupts enabled. The timer interrupts of the
+//! bare metal backends only mark the timers to be checked by the executor.
+
+use {
+    crate::timer_now,
+    alloc::{boxed::Box, collections::BTreeMap},
+    core::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    },
+    lazy_static::lazy_static,
+    spin::Mutex,
+};
+
+type Callback = Box<dyn FnOnce(Duration) + Send + Sync>;
+
+#[derive(Default)]
+struct TimerQueue {
+    /// The timers ordered by their deadlines, and then their IDs, the order
+    /// they are added.
+    timers: BTreeMap<(Duration, u64), Callback>,
+    /// The deadlines of the timers by their IDs.
+    deadlines: BTreeMap<u64, Duration>,
+}
+
+lazy_static! {
+    static ref TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::default());
+}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Add a timer calling `callback` after `deadline` to the queue, return its
+/// ID to cancel it.
+///
+/// It is the default of `timer_set`.
+pub fn timer_add(deadline: Duration, callback: Callback) -> u64 {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut queue = TIMERS.lock();
+    queue.timers.insert((deadline, id), callback);
+    queue.deadlines.insert(id, deadline);
+    id
+}
+
+/// Remove the timer `id` from the queue, if it is not fired yet.
+///
+/// It is the default of `timer_cancel`.
+pub fn timer_remove(id: u64) {
+    // the callback is dropped after the lock is released
+    let _callback = {
+        let mut queue = TIMERS.lock();
+        match queue.deadlines.remove(&id) {
+            Some(deadline) => queue.timers.remove(&(deadline, id)),
+            None => None,
+        }
+    };
+}
+
+/// Get the earliest deadline of the timers in the queue.
+pub fn timer_next_deadline() -> Option<Duration> {
+    TIMERS
+        .lock()
+        .timers
+        .keys()
+        .next()
+        .map(|&(deadline, _)| deadline)
+}
+
+/// Call the callbacks of the expired timers, out of the interrupt context.
+pub fn timer_tick() {
+    let now = timer_now();
+    loop {
+        // the lock is released before calling, which may add new timers
+        let callback = {
+            let mut queue = TIMERS.lock();
+            match queue.timers.keys().next() {
+                Some(&key) if key.0 <= now => {
+                    queue.deadlines.remove(&key.1);
+                    queue.timers.remove(&key).unwrap()
+                }
+                _ => break,
+            }
+        };
+        callback(now);
+    }
+}
@@ -2,6 +2,8 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter};
 use core::marker::PhantomData;
+use core::mem::{size_of, ManuallyDrop, MaybeUninit};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 #[repr(C)]
 pub struct UserPtr<T, P: Policy> {
@@ -40,6 +42,23 @@ pub enum Error {
     InvalidVectorAddress,
 }
 
+/// The end of the user address space, covering the root VMARs created.
+static USER_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Extend the user address space to cover `[0, end)`.
+///
+/// Called with the end of each root VMAR, so that the user pointers are
+/// checked against the address space actually given to the processes.
+pub fn extend_user_space(end: usize) {
+    USER_END.fetch_max(end, Ordering::Relaxed);
+}
+
+/// Copy `len` elements between user and kernel, recovering from faults.
+unsafe fn copy<T>(dst: *mut T, src: *const T, len: usize) -> Result<()> {
+    crate::user_copy(dst as *mut u8, src as *const u8, len * size_of::<T>())
+        .map_err(|_| Error::InvalidPointer)
+}
+
 impl<T, P: Policy> Debug for UserPtr<T, P> {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "{:?}", self.ptr)
@@ -91,17 +110,39 @@ impl<T, P: Policy> UserPtr<T, P> {
         }
         Ok(())
     }
+
+    /// Check that `len` elements from the pointer are aligned and inside
+    /// the user address space.
+    pub fn check_range(&self, len: usize) -> Result<()> {
+        self.check()?;
+        let size = len
+            .checked_mul(size_of::<T>())
+            .ok_or(Error::InvalidLength)?;
+        match (self.ptr as usize).checked_add(size) {
+            Some(end) if end <= USER_END.load(Ordering::Relaxed) => Ok(()),
+            _ => Err(Error::InvalidPointer),
+        }
+    }
 }
 
 impl<T, P: Read> UserPtr<T, P> {
+    /// Borrow the value in user space, after probing that it is readable.
     pub fn as_ref(&self) -> Result<&'static T> {
-        Ok(unsafe { &*self.ptr })
+        self.check_range(1)?;
+        let mut value = MaybeUninit::<T>::uninit();
+        unsafe {
+            copy(value.as_mut_ptr(), self.ptr, 1)?;
+            Ok(&*self.ptr)
+        }
     }
 
     pub fn read(&self) -> Result<T> {
-        // TODO: check ptr and return err
-        self.check()?;
-        Ok(unsafe { self.ptr.read() })
+        self.check_range(1)?;
+        let mut value = MaybeUninit::<T>::uninit();
+        unsafe {
+            copy(value.as_mut_ptr(), self.ptr, 1)?;
+            Ok(value.assume_init())
+        }
     }
 
     pub fn read_if_not_null(&self) -> Result<Option<T>> {
@@ -116,39 +157,45 @@ impl<T, P: Read> UserPtr<T, P> {
         if len == 0 {
             return Ok(Vec::default());
         }
-        self.check()?;
+        self.check_range(len)?;
         let mut ret = Vec::<T>::with_capacity(len);
         unsafe {
+            copy(ret.as_mut_ptr(), self.ptr, len)?;
             ret.set_len(len);
-            ret.as_mut_ptr().copy_from_nonoverlapping(self.ptr, len);
         }
         Ok(ret)
     }
+
+    pub fn read_array_into(&self, buf: &mut [T]) -> Result<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        self.check_range(buf.len())?;
+        unsafe { copy(buf.as_mut_ptr(), self.ptr, buf.len()) }
+    }
 }
 
 impl<P: Read> UserPtr<u8, P> {
     pub fn read_string(&self, len: usize) -> Result<String> {
-        self.check()?;
-        let src = unsafe { core::slice::from_raw_parts(self.ptr, len) };
-        let s = core::str::from_utf8(src).map_err(|_| Error::InvalidUtf8)?;
-        Ok(String::from(s))
+        let bytes = self.read_array(len)?;
+        String::from_utf8(bytes).map_err(|_| Error::InvalidUtf8)
     }
 
     pub fn read_cstring(&self) -> Result<String> {
-        self.check()?;
-        let len = unsafe { (0usize..).find(|&i| *self.ptr.add(i) == 0).unwrap() };
+        let mut len = 0;
+        while self.add(len).read()? != 0 {
+            len += 1;
+        }
         self.read_string(len)
     }
 }
 
 impl<P: Read> UserPtr<UserPtr<u8, P>, P> {
     pub fn read_cstring_array(&self) -> Result<Vec<String>> {
-        self.check()?;
-        let len = unsafe {
-            (0usize..)
-                .find(|&i| self.ptr.add(i).read().is_null())
-                .unwrap()
-        };
+        let mut len = 0;
+        while !self.add(len).read()?.is_null() {
+            len += 1;
+        }
         self.read_array(len)?
             .into_iter()
             .map(|ptr| ptr.read_cstring())
@@ -158,11 +205,9 @@ impl<P: Read> UserPtr<UserPtr<u8, P>, P> {
 
 impl<T, P: Write> UserPtr<T, P> {
     pub fn write(&mut self, value: T) -> Result<()> {
-        self.check()?;
-        unsafe {
-            self.ptr.write(value);
-        }
-        Ok(())
+        self.check_range(1)?;
+        let value = ManuallyDrop::new(value);
+        unsafe { copy(self.ptr, &*value, 1) }
     }
 
     pub fn write_if_not_null(&mut self, value: T) -> Result<()> {
@@ -176,12 +221,8 @@ impl<T, P: Write> UserPtr<T, P> {
         if values.is_empty() {
             return Ok(());
         }
-        self.check()?;
-        unsafe {
-            self.ptr
-                .copy_from_nonoverlapping(values.as_ptr(), values.len());
-        }
-        Ok(())
+        self.check_range(values.len())?;
+        unsafe { copy(self.ptr, values.as_ptr(), values.len()) }
     }
 }
 
@@ -189,9 +230,6 @@ impl<P: Write> UserPtr<u8, P> {
     pub fn write_cstring(&mut self, s: &str) -> Result<()> {
         let bytes = s.as_bytes();
         self.write_array(bytes)?;
-        unsafe {
-            self.ptr.add(bytes.len()).write(0);
-        }
-        Ok(())
+        self.add(bytes.len()).write(0)
     }
 }
@@ -1,4 +1,13 @@
 use core::fmt::{Debug, Error, Formatter};
+use core::mem::{align_of, size_of};
+
+/// The size of [`VdsoConstants`] expected by the vDSO.
+pub const VDSO_CONSTANTS_SIZE: usize = 0x78;
+
+// The layout must match `struct vdso_constants` of the vDSO.
+const _: [(); VDSO_CONSTANTS_SIZE] = [(); size_of::<VdsoConstants>()];
+const _: [(); 8] = [(); align_of::<VdsoConstants>()];
+const _: [(); 12] = [(); size_of::<Features>()];
 
 /// This struct contains constants that are initialized by the kernel
 /// once at boot time.  From the vDSO code's perspective, they are
@@ -35,7 +44,7 @@ pub struct VdsoConstants {
 ///
 /// For specific feature bits, see zircon/features.h.
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Features {
     pub cpu: u32,
     /// Total amount of debug registers available in the system.
@@ -44,11 +53,64 @@ pub struct Features {
 }
 
 impl VdsoConstants {
+    /// Build the constants by querying the HAL.
+    pub fn from_hal() -> Self {
+        // ClockMono(ticks) = ticks * 10^9 / ticks_per_second, reduced to fit in u32
+        let ticks_per_second = crate::timer_ticks_per_second();
+        let gcd = gcd(1_000_000_000, ticks_per_second);
+        let mut constants = VdsoConstants {
+            max_num_cpus: crate::cpu_count(),
+            features: crate::cpu_features(),
+            dcache_line_size: crate::cache_line_size(),
+            icache_line_size: crate::cache_line_size(),
+            ticks_per_second,
+            ticks_to_mono_numerator: (1_000_000_000 / gcd) as u32,
+            ticks_to_mono_denominator: (ticks_per_second / gcd) as u32,
+            physmem: crate::physmem_size(),
+            version_string_len: 0,
+            version_string: Default::default(),
+        };
+        constants.set_version_string(crate::version_string());
+        constants
+    }
+
+    /// Serialize the constants field by field, in the layout of the vDSO.
+    pub fn to_bytes(&self) -> [u8; VDSO_CONSTANTS_SIZE] {
+        let mut buf = [0u8; VDSO_CONSTANTS_SIZE];
+        let mut offset = 0;
+        let mut put = |bytes: &[u8]| {
+            buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+            offset += bytes.len();
+        };
+        put(&self.max_num_cpus.to_ne_bytes());
+        put(&self.features.cpu.to_ne_bytes());
+        put(&self.features.hw_breakpoint_count.to_ne_bytes());
+        put(&self.features.hw_watchpoint_count.to_ne_bytes());
+        put(&self.dcache_line_size.to_ne_bytes());
+        put(&self.icache_line_size.to_ne_bytes());
+        put(&self.ticks_per_second.to_ne_bytes());
+        put(&self.ticks_to_mono_numerator.to_ne_bytes());
+        put(&self.ticks_to_mono_denominator.to_ne_bytes());
+        put(&self.physmem.to_ne_bytes());
+        put(&self.version_string_len.to_ne_bytes());
+        put(&self.version_string.0);
+        debug_assert_eq!(offset, VDSO_CONSTANTS_SIZE);
+        buf
+    }
+
     /// Set version string.
     pub fn set_version_string(&mut self, s: &str) {
         let len = s.len().min(64);
         self.version_string_len = len as u64;
-        self.version_string.0[..len].copy_from_slice(s.as_bytes());
+        self.version_string.0[..len].copy_from_slice(&s.as_bytes()[..len]);
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
     }
 }
 
@@ -0,0 +1,175 @@
+//! Linux programs loader and runner
+
+#![no_std]
+#![deny(warnings, unused_must_use)]
+
+extern crate alloc;
+#[macro_use]
+extern crate log;
+
+use {
+    alloc::{boxed::Box, string::String, sync::Arc, vec::Vec},
+    core::{future::Future, pin::Pin},
+    linux_object::{
+        error::LxResult,
+        fs::{Vfs, TTY},
+        loader::LinuxElfLoader,
+        process::ProcessExt,
+        signal::Signal,
+        thread::{CurrentThreadExt, ThreadExt},
+    },
+    linux_syscall::Syscall,
+    zircon_object::{object::*, task::*},
+};
+
+/// The number of pages of the main thread stack.
+const STACK_PAGES: usize = 8;
+
+/// Run the statically linked Linux program `data` with `args` and `envs`,
+/// in a new process of the root job on the file tree `fs`.
+pub fn run(
+    args: Vec<String>,
+    envs: Vec<String>,
+    data: &[u8],
+    fs: Arc<Vfs>,
+) -> LxResult<Arc<Process>> {
+    let job = Job::root();
+    let name = args.first().map(String::as_str).unwrap_or("linux");
+    let proc = Process::create_linux(&job, name, fs)?;
+    TTY.set_foreground(&job, proc.id());
+    let thread = Thread::create_linux(&proc, "main")?;
+    let loader = LinuxElfLoader {
+        syscall_entry: kernel_hal_unix::syscall_entry as usize,
+        stack_pages: STACK_PAGES,
+    };
+    let (entry, sp) = loader.load(&proc.vmar(), data, args, envs)?;
+    proc.start(&thread, entry, sp, None, 0, thread_fn)?;
+    Ok(proc)
+}
+
+async fn new_thread(thread: CurrentThread) {
+    kernel_hal::Thread::set_tid(thread.id(), thread.proc().id());
+
+    loop {
+        let mut cx = thread.wait_for_run().await;
+        if thread.state() == ThreadState::Dying {
+            break;
+        }
+        trace!("go to user: {:#x?}", cx);
+        // the floating point registers are not in the context, and the
+        // other threads running meanwhile change them
+        thread.linux().restore_fp_state();
+        if kernel_hal::context_run(&mut cx).is_err() {
+            error!("user programs can not run on this HAL");
+            thread.end_running(cx);
+            thread.proc().kill_by_signal(Signal::SIGKILL);
+            continue;
+        }
+        thread.linux().save_fp_state();
+        trace!("back from user: {:#x?}", cx);
+        let trap_num = cx.trap_num;
+        thread.end_running(cx);
+        match trap_num {
+            0x100 => handle_syscall(&thread).await,
+            // the timer IRQ of the HAL preempting the program
+            vector @ 0x20..=0xff => {
+                kernel_hal::irq_handle(vector as u32);
+                // the end of the time slice, let the others run
+                kernel_hal::yield_now().await;
+            }
+            n => {
+                let signal = exception_signal(n);
+                warn!("exception {:#x}, send {:?}", n, signal);
+                thread.force_signal(signal);
+            }
+        }
+        // signals are delivered on the return from syscalls, exceptions and
+        // the preemption, so a program spinning still receives them
+        thread.handle_signal();
+    }
+}
+
+/// Get the signal of the exception `trap_num` of x86_64.
+fn exception_signal(trap_num: usize) -> Signal {
+    match trap_num {
+        // divide error, x87 and SIMD floating-point exceptions
+        0x0 | 0x10 | 0x13 => Signal::SIGFPE,
+        // debug and breakpoint
+        0x1 | 0x3 => Signal::SIGTRAP,
+        // invalid opcode
+        0x6 => Signal::SIGILL,
+        // alignment check
+        0x11 => Signal::SIGBUS,
+        // page fault, general protection and the others
+        _ => Signal::SIGSEGV,
+    }
+}
+
+fn thread_fn(thread: CurrentThread) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+    Box::pin(new_thread(thread))
+}
+
+async fn handle_syscall(thread: &CurrentThread) {
+    // the syscall ABI of Linux, kept by the entry of the libc
+    let (num, args) = thread.with_context(|cx| {
+        let regs = cx.general;
+        let args = [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9];
+        (regs.rax as u32, args)
+    });
+    let mut syscall = Syscall {
+        thread,
+        syscall_entry: kernel_hal_unix::syscall_entry as usize,
+        thread_fn,
+    };
+    let ret = syscall.syscall(num, args).await as usize;
+    thread.with_context(|cx| {
+        cx.general.rax = ret;
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, core::time::Duration, linux_object::fs::RamInode};
+
+    /// A position-independent program of one segment, spinning by `jmp $`.
+    fn spin_elf() -> Vec<u8> {
+        const CODE: [u8; 2] = [0xeb, 0xfe];
+        // the code after the ELF header and the program header
+        let entry = 64 + 56;
+        let size = (entry + CODE.len()) as u64;
+        let mut elf = Vec::new();
+        elf.extend_from_slice(b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0");
+        // ET_DYN, x86_64, version 1
+        elf.extend_from_slice(&3u16.to_le_bytes());
+        elf.extend_from_slice(&0x3eu16.to_le_bytes());
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&(entry as u64).to_le_bytes());
+        // the program headers right after, and no sections
+        elf.extend_from_slice(&64u64.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes());
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        for field in [64u16, 56, 1, 64, 0, 0].iter() {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+        // PT_LOAD of the whole file, readable and executable
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&5u32.to_le_bytes());
+        for field in [0, 0, 0, size, size, 0x1000].iter() {
+            elf.extend_from_slice(&field.to_le_bytes());
+        }
+        elf.extend_from_slice(&CODE);
+        elf
+    }
+
+    #[async_std::test]
+    async fn preempted() {
+        kernel_hal_unix::init();
+        let fs = Vfs::new(RamInode::new_root());
+        let proc = run(vec!["spin".into()], Vec::new(), &spin_elf(), fs).unwrap();
+        // still running after several time slices
+        async_std::task::sleep(Duration::from_millis(100)).await;
+        assert_eq!(proc.status(), Status::Running);
+        proc.kill();
+        assert_eq!(proc.wait_for_end().await, TASK_RETCODE_SYSCALL_KILL);
+    }
+}
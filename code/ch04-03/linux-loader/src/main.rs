@@ -0,0 +1,72 @@
+#![deny(warnings, unused_must_use)]
+
+extern crate log;
+
+use linux_loader::*;
+use linux_object::fs::{initramfs, DevFs, FileType, RamInode, Vfs};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt()]
+struct Opt {
+    /// The statically linked program to run.
+    #[structopt(parse(from_os_str))]
+    path: PathBuf,
+    /// The arguments of the program.
+    args: Vec<String>,
+    /// The `cpio` archive extracted as the root filesystem.
+    #[structopt(long, parse(from_os_str))]
+    initrd: Option<PathBuf>,
+}
+
+#[async_std::main]
+async fn main() {
+    kernel_hal_unix::init();
+    init_logger();
+    let opt = Opt::from_args();
+    let data = std::fs::read(&opt.path).expect("failed to read file");
+    let mut args = vec![opt.path.to_string_lossy().into_owned()];
+    args.extend(opt.args);
+    let envs = vec!["PATH=/usr/sbin:/usr/bin:/sbin:/bin".into()];
+    let fs = Vfs::new(RamInode::new_root());
+    if let Some(initrd) = &opt.initrd {
+        let image = std::fs::read(initrd).expect("failed to read initrd");
+        initramfs::load_cpio(&fs.root(), &image).expect("failed to load initrd");
+    }
+    if fs.lookup("/dev").is_err() {
+        fs.root()
+            .create("dev", FileType::Dir, 0o755)
+            .expect("failed to create /dev");
+    }
+    fs.mount("/dev", DevFs::new())
+        .expect("failed to mount /dev");
+    let proc = run(args, envs, &data, fs).expect("failed to run program");
+    drop(data);
+    let code = proc.wait_for_end().await;
+    std::process::exit(code as i32);
+}
+
+fn init_logger() {
+    env_logger::builder()
+        .format(|buf, record| {
+            use env_logger::fmt::Color;
+            use log::Level;
+            use std::io::Write;
+
+            let (tid, pid) = kernel_hal::Thread::get_tid();
+            let mut style = buf.style();
+            match record.level() {
+                Level::Trace => style.set_color(Color::Black).set_intense(true),
+                Level::Debug => style.set_color(Color::White),
+                Level::Info => style.set_color(Color::Green),
+                Level::Warn => style.set_color(Color::Yellow),
+                Level::Error => style.set_color(Color::Red).set_bold(true),
+            };
+            let now = kernel_hal_unix::timer_now();
+            let level = style.value(record.level());
+            let args = record.args();
+            writeln!(buf, "[{:?} {:>5} {}:{}] {}", now, level, pid, tid, args)
+        })
+        .init();
+}
@@ -0,0 +1,92 @@
+//! Linux error codes
+use zircon_object::ZxError;
+
+/// Linux result type.
+pub type LxResult<T = ()> = Result<T, LxError>;
+
+/// Linux error codes, returned negated by syscalls.
+#[allow(missing_docs)]
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LxError {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENXIO = 6,
+    E2BIG = 7,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    ENOTBLK = 15,
+    EBUSY = 16,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENODEV = 19,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    ENFILE = 23,
+    EMFILE = 24,
+    ENOTTY = 25,
+    ETXTBSY = 26,
+    EFBIG = 27,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    EMLINK = 31,
+    EPIPE = 32,
+    EDOM = 33,
+    ERANGE = 34,
+    EDEADLK = 35,
+    ENAMETOOLONG = 36,
+    ENOLCK = 37,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+    EOVERFLOW = 75,
+    ENOTSOCK = 88,
+    ENOTSUP = 95,
+    ENOBUFS = 105,
+    ETIMEDOUT = 110,
+}
+
+impl From<ZxError> for LxError {
+    fn from(e: ZxError) -> Self {
+        match e {
+            ZxError::INVALID_ARGS | ZxError::WRONG_TYPE | ZxError::BAD_STATE => LxError::EINVAL,
+            ZxError::NOT_SUPPORTED => LxError::ENOSYS,
+            ZxError::ALREADY_EXISTS => LxError::EEXIST,
+            ZxError::SHOULD_WAIT => LxError::EAGAIN,
+            ZxError::PEER_CLOSED => LxError::EPIPE,
+            ZxError::BAD_HANDLE => LxError::EBADF,
+            ZxError::NOT_FOUND => LxError::ENOENT,
+            ZxError::NO_MEMORY | ZxError::NO_RESOURCES => LxError::ENOMEM,
+            ZxError::ACCESS_DENIED => LxError::EACCES,
+            ZxError::TIMED_OUT => LxError::ETIMEDOUT,
+            ZxError::INTERNAL_INTR_RETRY => LxError::EINTR,
+            ZxError::OUT_OF_RANGE => LxError::EFAULT,
+            // never success, whatever the error is
+            _ => LxError::EIO,
+        }
+    }
+}
+
+use kernel_hal::user::Error;
+
+impl From<Error> for LxError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidUtf8 => LxError::EINVAL,
+            Error::InvalidPointer => LxError::EFAULT,
+            Error::BufferTooSmall => LxError::ENOBUFS,
+            Error::InvalidLength => LxError::EINVAL,
+            Error::InvalidVectorAddress => LxError::EINVAL,
+        }
+    }
+}
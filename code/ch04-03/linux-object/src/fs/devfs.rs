@@ -0,0 +1,183 @@
+use {
+    super::{
+        tty::TTY,
+        vfs::{alloc_ino, FileType, INode, Metadata},
+    },
+    crate::error::{LxError, LxResult},
+    alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec},
+    zircon_object::util::random,
+};
+
+/// The filesystem of devices, usually mounted on `/dev`.
+///
+/// It has the character devices `null`, `zero`, `random`, `urandom` and
+/// `tty`, and no other files can be created.
+pub struct DevFs {
+    ino: usize,
+    devices: BTreeMap<&'static str, Arc<dyn INode>>,
+}
+
+impl DevFs {
+    /// Create the root directory of the devices.
+    pub fn new() -> Arc<Self> {
+        let mut devices = BTreeMap::<&'static str, Arc<dyn INode>>::new();
+        devices.insert("null", Device::new(DeviceKind::Null, 1, 3));
+        devices.insert("zero", Device::new(DeviceKind::Zero, 1, 5));
+        devices.insert("random", Device::new(DeviceKind::Random, 1, 8));
+        devices.insert("urandom", Device::new(DeviceKind::Random, 1, 9));
+        devices.insert("tty", TTY.clone());
+        Arc::new(DevFs {
+            ino: alloc_ino(),
+            devices,
+        })
+    }
+}
+
+impl INode for DevFs {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: self.ino,
+            type_: FileType::Dir,
+            mode: 0o755,
+            nlinks: 1,
+            size: 0,
+            rdev: 0,
+        }
+    }
+
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::EISDIR)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EISDIR)
+    }
+
+    fn resize(&self, _len: usize) -> LxResult {
+        Err(LxError::EISDIR)
+    }
+
+    fn find(&self, name: &str) -> LxResult<Arc<dyn INode>> {
+        self.devices.get(name).cloned().ok_or(LxError::ENOENT)
+    }
+
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> LxResult<Arc<dyn INode>> {
+        Err(LxError::EPERM)
+    }
+
+    fn unlink(&self, _name: &str) -> LxResult {
+        Err(LxError::EPERM)
+    }
+
+    fn list(&self) -> LxResult<Vec<(String, Arc<dyn INode>)>> {
+        let entries = self.devices.iter();
+        Ok(entries
+            .map(|(&name, device)| (String::from(name), device.clone()))
+            .collect())
+    }
+}
+
+/// The kinds of the memory devices.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum DeviceKind {
+    /// Read nothing, and discard the writes.
+    Null,
+    /// Read zeros, and discard the writes.
+    Zero,
+    /// Read random bytes from the kernel CSPRNG, and discard the writes.
+    Random,
+}
+
+/// A memory device.
+struct Device {
+    ino: usize,
+    kind: DeviceKind,
+    rdev: usize,
+}
+
+impl Device {
+    fn new(kind: DeviceKind, major: usize, minor: usize) -> Arc<Self> {
+        Arc::new(Device {
+            ino: alloc_ino(),
+            kind,
+            rdev: major << 8 | minor,
+        })
+    }
+}
+
+impl INode for Device {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: self.ino,
+            type_: FileType::CharDevice,
+            mode: 0o666,
+            nlinks: 1,
+            size: 0,
+            rdev: self.rdev,
+        }
+    }
+
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> LxResult<usize> {
+        match self.kind {
+            DeviceKind::Null => return Ok(0),
+            DeviceKind::Zero => buf.fill(0),
+            DeviceKind::Random => random::draw(buf),
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> LxResult<usize> {
+        Ok(buf.len())
+    }
+
+    fn resize(&self, _len: usize) -> LxResult {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{RamInode, Vfs};
+
+    #[test]
+    fn devices() {
+        kernel_hal_unix::init();
+        let fs = Vfs::new(RamInode::new_root());
+        fs.root().create("dev", FileType::Dir, 0o755).unwrap();
+        fs.mount("/dev", DevFs::new()).unwrap();
+        let names: Vec<String> = fs
+            .lookup("/dev")
+            .unwrap()
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.0)
+            .collect();
+        assert_eq!(names, ["null", "random", "tty", "urandom", "zero"]);
+
+        let mut buf = [1u8; 64];
+        let null = fs.lookup("/dev/null").unwrap();
+        assert_eq!(null.read_at(0, &mut buf), Ok(0));
+        assert_eq!(null.write_at(0, b"lost"), Ok(4));
+        let meta = null.metadata();
+        assert_eq!((meta.type_, meta.rdev), (FileType::CharDevice, 0x103));
+        assert_eq!(fs.lookup("/dev/zero").unwrap().read_at(0, &mut buf), Ok(64));
+        assert_eq!(buf, [0; 64]);
+        let urandom = fs.lookup("/dev/urandom").unwrap();
+        assert_eq!(urandom.read_at(0, &mut buf), Ok(64));
+        assert_ne!(buf, [0; 64]);
+        assert_eq!(
+            fs.lookup("/dev/tty").unwrap().metadata().type_,
+            FileType::CharDevice
+        );
+
+        let dev = fs.lookup("/dev").unwrap();
+        assert_eq!(
+            dev.create("x", FileType::File, 0o644).err(),
+            Some(LxError::EPERM)
+        );
+        assert_eq!(dev.unlink("null"), Err(LxError::EPERM));
+        assert_eq!(dev.find("none").err(), Some(LxError::ENOENT));
+    }
+}
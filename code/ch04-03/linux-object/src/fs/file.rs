@@ -0,0 +1,154 @@
+use {
+    super::{
+        vfs::{INode, Metadata},
+        FileLike, OpenFlags, PollStatus,
+    },
+    crate::error::{LxError, LxResult},
+    alloc::{boxed::Box, string::String, sync::Arc},
+    futures::future::{self, BoxFuture},
+    spin::Mutex,
+};
+
+/// The position to seek from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SeekFrom {
+    /// From the start of the file.
+    Start(usize),
+    /// From the end of the file.
+    End(isize),
+    /// From the current offset.
+    Current(isize),
+}
+
+/// A file opened from the filesystem.
+pub struct File {
+    inode: Arc<dyn INode>,
+    /// The absolute path opened.
+    path: String,
+    flags: OpenFlags,
+    /// The current offset.
+    offset: Mutex<usize>,
+}
+
+impl File {
+    /// Create a file opened at the absolute `path` with `flags`, at offset 0.
+    pub fn new(inode: Arc<dyn INode>, path: String, flags: OpenFlags) -> Self {
+        File {
+            inode,
+            path,
+            flags,
+            offset: Mutex::new(0),
+        }
+    }
+
+    /// Get the inode of the file.
+    pub fn inode(&self) -> &Arc<dyn INode> {
+        &self.inode
+    }
+
+    /// Get the absolute path opened.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Move the current offset to `pos`, return the new offset.
+    pub fn seek(&self, pos: SeekFrom) -> LxResult<usize> {
+        let mut offset = self.offset.lock();
+        let new_offset = match pos {
+            SeekFrom::Start(off) => Some(off),
+            SeekFrom::End(off) => add_offset(self.inode.metadata().size, off),
+            SeekFrom::Current(off) => add_offset(*offset, off),
+        };
+        *offset = new_offset.ok_or(LxError::EINVAL)?;
+        Ok(*offset)
+    }
+
+    fn check_readable(&self) -> LxResult {
+        if self.flags.readable() {
+            Ok(())
+        } else {
+            Err(LxError::EBADF)
+        }
+    }
+
+    fn check_writable(&self) -> LxResult {
+        if self.flags.writable() {
+            Ok(())
+        } else {
+            Err(LxError::EBADF)
+        }
+    }
+}
+
+/// Add the signed `off` to `base`, return `None` if it becomes negative.
+fn add_offset(base: usize, off: isize) -> Option<usize> {
+    if off < 0 {
+        base.checked_sub(off.unsigned_abs())
+    } else {
+        base.checked_add(off as usize)
+    }
+}
+
+impl FileLike for File {
+    fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        self.check_readable()?;
+        let mut offset = self.offset.lock();
+        let len = self.inode.read_at(*offset, buf)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    /// Write to the current offset, or the end of the file if it is opened
+    /// with `APPEND`.
+    fn write(&self, buf: &[u8]) -> LxResult<usize> {
+        self.check_writable()?;
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::APPEND) {
+            *offset = self.inode.metadata().size;
+        }
+        let len = self.inode.write_at(*offset, buf)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> LxResult<usize> {
+        self.check_readable()?;
+        self.inode.read_at(offset as usize, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> LxResult<usize> {
+        self.check_writable()?;
+        self.inode.write_at(offset as usize, buf)
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.inode.metadata()
+    }
+
+    fn poll(&self) -> PollStatus {
+        self.inode.poll()
+    }
+
+    fn async_poll(&self) -> BoxFuture<'_, PollStatus> {
+        self.inode.async_poll()
+    }
+
+    /// Read as `read`, waiting for the data unless the file is opened with
+    /// `NONBLOCK`.
+    fn async_read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, LxResult<usize>> {
+        if self.flags.contains(OpenFlags::NONBLOCK) {
+            return Box::pin(future::ready(self.read(buf)));
+        }
+        Box::pin(async move {
+            self.check_readable()?;
+            let offset = *self.offset.lock();
+            let len = self.inode.async_read_at(offset, buf).await?;
+            *self.offset.lock() = offset + len;
+            Ok(len)
+        })
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> LxResult<usize> {
+        self.inode.ioctl(request, arg)
+    }
+}
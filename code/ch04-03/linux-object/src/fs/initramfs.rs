@@ -0,0 +1,156 @@
+//! Initial filesystem from a `cpio` archive
+use {
+    super::vfs::{FileType, INode},
+    crate::error::{LxError, LxResult},
+    alloc::sync::Arc,
+    core::str,
+};
+
+/// The magic of the "new ASCII" format.
+const MAGIC: &[u8] = b"070701";
+
+/// The length of the header, followed by the name.
+const HEADER_LEN: usize = 110;
+
+/// The name of the last entry.
+const TRAILER: &str = "TRAILER!!!";
+
+/// The mask of the file type bits in the mode.
+const MODE_TYPE_MASK: u32 = 0o170000;
+
+/// Extract the `cpio` archive `data` of the "new ASCII" format into the
+/// directory `root`.
+///
+/// The directories in the paths are created if they are not in the archive.
+/// Only regular files and directories are extracted, other entries are
+/// skipped.
+pub fn load_cpio(root: &Arc<dyn INode>, data: &[u8]) -> LxResult {
+    let mut offset = 0;
+    loop {
+        let header = data
+            .get(offset..offset + HEADER_LEN)
+            .ok_or(LxError::EINVAL)?;
+        if &header[..6] != MAGIC {
+            return Err(LxError::EINVAL);
+        }
+        // the fields are 8 hex digits each, after the magic
+        let field = |i: usize| -> LxResult<usize> {
+            let digits = &header[6 + i * 8..6 + (i + 1) * 8];
+            let digits = str::from_utf8(digits).map_err(|_| LxError::EINVAL)?;
+            usize::from_str_radix(digits, 16).map_err(|_| LxError::EINVAL)
+        };
+        let mode = field(1)? as u32;
+        let file_size = field(6)?;
+        let name_size = field(11)?;
+        let name_start = offset + HEADER_LEN;
+        let name = data
+            .get(name_start..name_start + name_size)
+            .ok_or(LxError::EINVAL)?;
+        // the name ends with NUL
+        let name =
+            str::from_utf8(&name[..name_size.saturating_sub(1)]).map_err(|_| LxError::EINVAL)?;
+        let data_start = align4(name_start + name_size);
+        let content = data
+            .get(data_start..data_start + file_size)
+            .ok_or(LxError::EINVAL)?;
+        offset = align4(data_start + file_size);
+        if name == TRAILER {
+            return Ok(());
+        }
+        let path = name.trim_start_matches("./").trim_start_matches('/');
+        if path.is_empty() || path == "." {
+            continue;
+        }
+        let perm = mode & 0o7777;
+        match mode & MODE_TYPE_MASK {
+            0o040000 => {
+                create_path(root, path, FileType::Dir, perm)?;
+            }
+            0o100000 => {
+                let file = create_path(root, path, FileType::File, perm)?;
+                file.resize(0)?;
+                file.write_at(0, content)?;
+            }
+            _ => warn!("cpio: skip {:?} of mode {:#o}", path, mode),
+        }
+    }
+}
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+/// Create the file `path` relative to `root` with its directories, or get it
+/// if it exists.
+fn create_path(
+    root: &Arc<dyn INode>,
+    path: &str,
+    type_: FileType,
+    mode: u32,
+) -> LxResult<Arc<dyn INode>> {
+    let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+    let mut inode = root.clone();
+    while let Some(name) = names.next() {
+        let (type_, mode) = match names.peek() {
+            Some(_) => (FileType::Dir, 0o755),
+            None => (type_, mode),
+        };
+        inode = match inode.find(name) {
+            Ok(child) => child,
+            Err(LxError::ENOENT) => inode.create(name, type_, mode)?,
+            Err(err) => return Err(err),
+        };
+    }
+    Ok(inode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{RamInode, Vfs};
+    use alloc::{format, vec::Vec};
+
+    fn push_entry(archive: &mut Vec<u8>, name: &str, mode: u32, content: &[u8]) {
+        archive.extend(b"070701");
+        let fields = [0, mode as usize, 0, 0, 1, 0, content.len(), 0, 0, 0, 0];
+        for field in fields.iter() {
+            archive.extend(format!("{:08x}", field).bytes());
+        }
+        archive.extend(format!("{:08x}{:08x}", name.len() + 1, 0).bytes());
+        archive.extend(name.bytes());
+        archive.push(0);
+        archive.resize(align4(archive.len()), 0);
+        archive.extend(content);
+        archive.resize(align4(archive.len()), 0);
+    }
+
+    #[test]
+    fn load() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, ".", 0o040755, b"");
+        push_entry(&mut archive, "bin", 0o040755, b"");
+        push_entry(&mut archive, "bin/hello", 0o100755, b"hello");
+        push_entry(&mut archive, "./etc/motd", 0o100644, b"hi\n");
+        push_entry(&mut archive, "lib/link", 0o120777, b"target");
+        push_entry(&mut archive, TRAILER, 0, b"");
+
+        let fs = Vfs::new(RamInode::new_root());
+        load_cpio(&fs.root(), &archive).unwrap();
+        let hello = fs.lookup("/bin/hello").unwrap();
+        let meta = hello.metadata();
+        assert_eq!(
+            (meta.type_, meta.mode, meta.size),
+            (FileType::File, 0o755, 5)
+        );
+        let mut buf = [0u8; 8];
+        assert_eq!(fs.lookup("/etc/motd").unwrap().read_at(0, &mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"hi\n");
+        assert_eq!(fs.lookup("/etc").unwrap().metadata().type_, FileType::Dir);
+        // the symbolic link is skipped
+        assert_eq!(fs.lookup("/lib").err(), Some(LxError::ENOENT));
+
+        // truncated or malformed archives
+        assert_eq!(load_cpio(&fs.root(), &archive[..100]), Err(LxError::EINVAL));
+        assert_eq!(load_cpio(&fs.root(), b"not a cpio"), Err(LxError::EINVAL));
+    }
+}
@@ -0,0 +1,207 @@
+//! Linux files
+use {
+    crate::error::{LxError, LxResult},
+    alloc::{boxed::Box, string::String, vec::Vec},
+    bitflags::bitflags,
+    downcast_rs::{impl_downcast, DowncastSync},
+    futures::future::{self, BoxFuture},
+};
+
+pub use self::devfs::DevFs;
+pub use self::file::{File, SeekFrom};
+pub use self::pipe::Pipe;
+pub use self::ramfs::RamInode;
+pub use self::stdio::{Stdin, Stdout};
+pub use self::tty::{Tty, TTY};
+pub use self::vfs::{FileType, INode, Metadata, Vfs};
+
+mod devfs;
+mod file;
+pub mod initramfs;
+mod pipe;
+mod ramfs;
+mod stdio;
+mod tty;
+mod vfs;
+
+/// A file descriptor, the index of a file in the file descriptor table.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct FileDesc(i32);
+
+impl FileDesc {
+    /// The standard input.
+    pub const STDIN: Self = FileDesc(0);
+    /// The standard output.
+    pub const STDOUT: Self = FileDesc(1);
+    /// The standard error.
+    pub const STDERR: Self = FileDesc(2);
+    /// The current working directory, as the directory of relative paths.
+    pub const CWD: Self = FileDesc(-100);
+}
+
+impl From<usize> for FileDesc {
+    fn from(x: usize) -> Self {
+        FileDesc(x as i32)
+    }
+}
+
+impl From<i32> for FileDesc {
+    fn from(x: i32) -> Self {
+        FileDesc(x)
+    }
+}
+
+impl From<FileDesc> for usize {
+    fn from(f: FileDesc) -> Self {
+        f.0 as _
+    }
+}
+
+impl From<FileDesc> for i32 {
+    fn from(f: FileDesc) -> Self {
+        f.0
+    }
+}
+
+bitflags! {
+    /// The flags of opened files.
+    pub struct OpenFlags: usize {
+        /// Open for reading only.
+        const RDONLY = 0;
+        /// Open for writing only.
+        const WRONLY = 0o1;
+        /// Open for reading and writing.
+        const RDWR = 0o2;
+        /// Create the file if it does not exist.
+        const CREAT = 0o100;
+        /// Fail if the file exists, with `CREAT`.
+        const EXCL = 0o200;
+        /// Do not become the controlling terminal.
+        const NOCTTY = 0o400;
+        /// Truncate the file to 0 bytes.
+        const TRUNC = 0o1000;
+        /// Write to the end of the file.
+        const APPEND = 0o2000;
+        /// Do not block on I/O.
+        const NONBLOCK = 0o4000;
+        /// Fail if the file is not a directory.
+        const DIRECTORY = 0o200000;
+        /// Close the file descriptor on `execve`.
+        const CLOEXEC = 0o2000000;
+    }
+}
+
+impl OpenFlags {
+    /// Whether the file is opened for reading.
+    pub fn readable(self) -> bool {
+        self.bits & 0o3 != OpenFlags::WRONLY.bits
+    }
+
+    /// Whether the file is opened for writing.
+    pub fn writable(self) -> bool {
+        self.bits & 0o3 != OpenFlags::RDONLY.bits
+    }
+}
+
+/// The readiness of a file for I/O.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PollStatus {
+    /// Reading will not block.
+    pub read: bool,
+    /// Writing will not block.
+    pub write: bool,
+    /// An error occurred, such as the read end of a pipe is closed.
+    pub error: bool,
+    /// The peer hung up, such as the write end of a pipe is closed.
+    pub hangup: bool,
+}
+
+/// An opened file, referred by file descriptors.
+pub trait FileLike: DowncastSync {
+    /// Read from the current offset of the file.
+    fn read(&self, buf: &mut [u8]) -> LxResult<usize>;
+
+    /// Write to the current offset of the file.
+    fn write(&self, buf: &[u8]) -> LxResult<usize>;
+
+    /// Read from the `offset` of the file, without changing the current
+    /// offset.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    /// Write to the `offset` of the file, without changing the current
+    /// offset.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::ESPIPE)
+    }
+
+    /// Get the metadata of the file.
+    fn metadata(&self) -> Metadata;
+
+    /// Get the readiness of the file.
+    ///
+    /// The file is always ready for reading and writing by default.
+    fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: true,
+            write: true,
+            ..PollStatus::default()
+        }
+    }
+
+    /// Wait until the file is ready for reading or writing, or an error
+    /// occurs, return the readiness.
+    fn async_poll(&self) -> BoxFuture<'_, PollStatus> {
+        Box::pin(future::ready(self.poll()))
+    }
+
+    /// Read from the current offset of the file, waiting for the data if the
+    /// file is blocking.
+    fn async_read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, LxResult<usize>> {
+        Box::pin(future::ready(self.read(buf)))
+    }
+
+    /// Write to the current offset of the file, waiting for the space if the
+    /// file is blocking.
+    fn async_write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, LxResult<usize>> {
+        Box::pin(future::ready(self.write(buf)))
+    }
+
+    /// Control the device of the file, `arg` is usually a pointer in the user
+    /// memory.
+    ///
+    /// Fail with `ENOTTY` if it is not a device.
+    fn ioctl(&self, _request: usize, _arg: usize) -> LxResult<usize> {
+        Err(LxError::ENOTTY)
+    }
+}
+
+impl_downcast!(sync FileLike);
+
+/// Join `path` to the absolute path `base`, and normalize the result.
+///
+/// An absolute `path` replaces `base`. The components `.` and `..` are
+/// resolved lexically, where `..` of the root is the root itself.
+pub fn join_path(base: &str, path: &str) -> String {
+    let mut components = Vec::new();
+    let base = if path.starts_with('/') { "" } else { base };
+    for name in base.split('/').chain(path.split('/')) {
+        match name {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            _ => components.push(name),
+        }
+    }
+    let mut result = String::new();
+    for name in components {
+        result += "/";
+        result += name;
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    result
+}
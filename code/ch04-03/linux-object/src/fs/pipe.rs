@@ -0,0 +1,212 @@
+use {
+    super::{FileLike, FileType, Metadata, OpenFlags, PollStatus},
+    crate::error::{LxError, LxResult},
+    alloc::{boxed::Box, sync::Arc},
+    futures::future::BoxFuture,
+    zircon_object::{ipc::Socket, object::*},
+};
+
+/// Which end of a pipe.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum PipeEnd {
+    Read,
+    Write,
+}
+
+/// An end of a pipe, over an endpoint of a socket.
+///
+/// The data written to the write end is read from the read end. The read end
+/// reads EOF once all write ends are closed, and writing fails with `EPIPE`
+/// once all read ends are closed.
+pub struct Pipe {
+    socket: Arc<Socket>,
+    end: PipeEnd,
+    nonblock: bool,
+}
+
+impl Pipe {
+    /// Create a pipe, return the read end and the write end.
+    ///
+    /// Only `NONBLOCK` of `flags` takes effect on the ends.
+    pub fn create(flags: OpenFlags) -> (Self, Self) {
+        let (end0, end1) = Socket::create();
+        let nonblock = flags.contains(OpenFlags::NONBLOCK);
+        let read = Pipe {
+            socket: end0,
+            end: PipeEnd::Read,
+            nonblock,
+        };
+        let write = Pipe {
+            socket: end1,
+            end: PipeEnd::Write,
+            nonblock,
+        };
+        (read, write)
+    }
+
+    /// Wait until any of `signal` is asserted on the socket endpoint.
+    async fn wait(&self, signal: Signal) {
+        let object: Arc<dyn KernelObject> = self.socket.clone();
+        object.wait_signal(signal).await;
+    }
+
+    /// The signals asserted when the readiness of this end may change.
+    fn ready_signal(&self) -> Signal {
+        match self.end {
+            PipeEnd::Read => Signal::READABLE | Signal::PEER_CLOSED,
+            PipeEnd::Write => Signal::WRITABLE | Signal::PEER_CLOSED,
+        }
+    }
+}
+
+impl FileLike for Pipe {
+    /// Read the data available, fail with `EAGAIN` if none, or read EOF if
+    /// the write end is closed.
+    fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        if self.end != PipeEnd::Read {
+            return Err(LxError::EBADF);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.socket.read(false, buf) {
+            Ok(len) => Ok(len),
+            Err(ZxError::SHOULD_WAIT) => Err(LxError::EAGAIN),
+            Err(ZxError::PEER_CLOSED) => Ok(0),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Write as much as the buffer of the pipe holds, fail with `EAGAIN` if
+    /// it is full, or `EPIPE` if the read end is closed.
+    fn write(&self, buf: &[u8]) -> LxResult<usize> {
+        if self.end != PipeEnd::Write {
+            return Err(LxError::EBADF);
+        }
+        match self.socket.write(buf) {
+            Ok(len) => Ok(len),
+            Err(ZxError::SHOULD_WAIT) => Err(LxError::EAGAIN),
+            Err(ZxError::PEER_CLOSED) => Err(LxError::EPIPE),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: self.socket.id() as usize,
+            type_: FileType::NamedPipe,
+            mode: 0o600,
+            nlinks: 1,
+            size: 0,
+            rdev: 0,
+        }
+    }
+
+    fn poll(&self) -> PollStatus {
+        let signal = self.socket.signal();
+        let closed = signal.contains(Signal::PEER_CLOSED);
+        match self.end {
+            PipeEnd::Read => PollStatus {
+                read: signal.contains(Signal::READABLE) || closed,
+                hangup: closed,
+                ..PollStatus::default()
+            },
+            PipeEnd::Write => PollStatus {
+                write: signal.contains(Signal::WRITABLE),
+                error: closed,
+                ..PollStatus::default()
+            },
+        }
+    }
+
+    fn async_poll(&self) -> BoxFuture<'_, PollStatus> {
+        Box::pin(async move {
+            self.wait(self.ready_signal()).await;
+            self.poll()
+        })
+    }
+
+    /// Wait for the data unless the pipe is non-blocking.
+    fn async_read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, LxResult<usize>> {
+        Box::pin(async move {
+            loop {
+                match self.read(buf) {
+                    Err(LxError::EAGAIN) if !self.nonblock => self.wait(self.ready_signal()).await,
+                    ret => return ret,
+                }
+            }
+        })
+    }
+
+    /// Wait until all data is written unless the pipe is non-blocking.
+    fn async_write<'a>(&'a self, buf: &'a [u8]) -> BoxFuture<'a, LxResult<usize>> {
+        Box::pin(async move {
+            let mut written = 0;
+            loop {
+                match self.write(&buf[written..]) {
+                    Ok(len) => {
+                        written += len;
+                        if written == buf.len() || self.nonblock {
+                            return Ok(written);
+                        }
+                    }
+                    Err(LxError::EAGAIN) if !self.nonblock => {}
+                    Err(_) if written != 0 => return Ok(written),
+                    Err(err) => return Err(err),
+                }
+                self.wait(self.ready_signal()).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    #[test]
+    fn read_write() {
+        kernel_hal_unix::init();
+        let (read, write) = Pipe::create(OpenFlags::empty());
+        let mut buf = [0u8; 4];
+        assert_eq!(read.read(&mut buf), Err(LxError::EAGAIN));
+        assert_eq!(read.write(b"x"), Err(LxError::EBADF));
+        assert_eq!(write.read(&mut buf), Err(LxError::EBADF));
+        assert!(!read.poll().read);
+        assert!(write.poll().write);
+
+        // a blocking read waits for the data
+        let mut future = read.async_read(&mut buf);
+        assert!(future.as_mut().now_or_never().is_none());
+        assert_eq!(write.write(b"abcdef"), Ok(6));
+        assert_eq!(future.now_or_never(), Some(Ok(4)));
+        assert_eq!(&buf, b"abcd");
+        assert!(read.poll().read);
+
+        // EOF after the write end is closed
+        drop(write);
+        assert_eq!(read.read(&mut buf), Ok(2));
+        assert_eq!(read.read(&mut buf), Ok(0));
+        assert_eq!(read.async_read(&mut buf).now_or_never(), Some(Ok(0)));
+        assert!(read.poll().hangup);
+    }
+
+    #[test]
+    fn broken_and_nonblock() {
+        kernel_hal_unix::init();
+        let (read, write) = Pipe::create(OpenFlags::NONBLOCK);
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            read.async_read(&mut buf).now_or_never(),
+            Some(Err(LxError::EAGAIN))
+        );
+        drop(read);
+        assert_eq!(write.write(b"x"), Err(LxError::EPIPE));
+        assert_eq!(
+            write.async_write(b"x").now_or_never(),
+            Some(Err(LxError::EPIPE))
+        );
+        assert!(write.poll().error);
+    }
+}
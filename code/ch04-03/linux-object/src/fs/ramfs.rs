@@ -0,0 +1,142 @@
+use {
+    super::vfs::{alloc_ino, FileType, INode, Metadata},
+    crate::error::{LxError, LxResult},
+    alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec},
+    spin::Mutex,
+};
+
+/// The largest size of a file, so a write at a huge offset can not take
+/// all the memory.
+pub const MAX_FILE_SIZE: usize = 0x1000_0000;
+
+/// A file or a directory of an in-memory filesystem.
+pub struct RamInode {
+    ino: usize,
+    type_: FileType,
+    mode: u32,
+    inner: Mutex<RamInodeInner>,
+}
+
+#[derive(Default)]
+struct RamInodeInner {
+    /// The content of a file.
+    data: Vec<u8>,
+    /// The entries of a directory.
+    children: BTreeMap<String, Arc<RamInode>>,
+}
+
+impl RamInode {
+    /// Create the root directory of a new in-memory filesystem.
+    pub fn new_root() -> Arc<Self> {
+        Self::new(FileType::Dir, 0o755)
+    }
+
+    fn new(type_: FileType, mode: u32) -> Arc<Self> {
+        Arc::new(RamInode {
+            ino: alloc_ino(),
+            type_,
+            mode,
+            inner: Mutex::new(RamInodeInner::default()),
+        })
+    }
+
+    fn check_file(&self) -> LxResult {
+        match self.type_ {
+            FileType::Dir => Err(LxError::EISDIR),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_dir(&self) -> LxResult {
+        match self.type_ {
+            FileType::Dir => Ok(()),
+            _ => Err(LxError::ENOTDIR),
+        }
+    }
+}
+
+impl INode for RamInode {
+    fn metadata(&self) -> Metadata {
+        let inner = self.inner.lock();
+        Metadata {
+            ino: self.ino,
+            type_: self.type_,
+            mode: self.mode,
+            nlinks: 1,
+            size: inner.data.len(),
+            rdev: 0,
+        }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> LxResult<usize> {
+        self.check_file()?;
+        let inner = self.inner.lock();
+        if offset >= inner.data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(inner.data.len() - offset);
+        buf[..len].copy_from_slice(&inner.data[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> LxResult<usize> {
+        self.check_file()?;
+        let end = offset.checked_add(buf.len()).ok_or(LxError::EINVAL)?;
+        if end > MAX_FILE_SIZE {
+            return Err(LxError::EFBIG);
+        }
+        let mut inner = self.inner.lock();
+        if end > inner.data.len() {
+            inner.data.resize(end, 0);
+        }
+        inner.data[offset..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn resize(&self, len: usize) -> LxResult {
+        self.check_file()?;
+        if len > MAX_FILE_SIZE {
+            return Err(LxError::EFBIG);
+        }
+        self.inner.lock().data.resize(len, 0);
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> LxResult<Arc<dyn INode>> {
+        self.check_dir()?;
+        let inner = self.inner.lock();
+        let child = inner.children.get(name).ok_or(LxError::ENOENT)?;
+        Ok(child.clone())
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> LxResult<Arc<dyn INode>> {
+        self.check_dir()?;
+        let mut inner = self.inner.lock();
+        if inner.children.contains_key(name) {
+            return Err(LxError::EEXIST);
+        }
+        let child = RamInode::new(type_, mode);
+        inner.children.insert(String::from(name), child.clone());
+        Ok(child)
+    }
+
+    fn unlink(&self, name: &str) -> LxResult {
+        self.check_dir()?;
+        let mut inner = self.inner.lock();
+        let child = inner.children.get(name).ok_or(LxError::ENOENT)?;
+        if !child.inner.lock().children.is_empty() {
+            return Err(LxError::ENOTEMPTY);
+        }
+        inner.children.remove(name);
+        Ok(())
+    }
+
+    fn list(&self) -> LxResult<Vec<(String, Arc<dyn INode>)>> {
+        self.check_dir()?;
+        let inner = self.inner.lock();
+        let entries = inner.children.iter();
+        Ok(entries
+            .map(|(name, child)| (name.clone(), child.clone() as Arc<dyn INode>))
+            .collect())
+    }
+}
@@ -0,0 +1,62 @@
+use {
+    super::{tty::TTY, FileLike, INode, Metadata, PollStatus},
+    crate::error::{LxError, LxResult},
+    futures::future::BoxFuture,
+};
+
+/// The standard input, read from the terminal on the console.
+#[derive(Debug, Default)]
+pub struct Stdin;
+
+impl FileLike for Stdin {
+    /// Read the input available in the terminal, fail with `EAGAIN` if none.
+    fn read(&self, buf: &mut [u8]) -> LxResult<usize> {
+        TTY.read_at(0, buf)
+    }
+
+    fn write(&self, _buf: &[u8]) -> LxResult<usize> {
+        Err(LxError::EBADF)
+    }
+
+    fn metadata(&self) -> Metadata {
+        TTY.metadata()
+    }
+
+    fn poll(&self) -> PollStatus {
+        TTY.poll()
+    }
+
+    fn async_poll(&self) -> BoxFuture<'_, PollStatus> {
+        TTY.async_poll()
+    }
+
+    fn async_read<'a>(&'a self, buf: &'a mut [u8]) -> BoxFuture<'a, LxResult<usize>> {
+        TTY.async_read_at(0, buf)
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> LxResult<usize> {
+        TTY.ioctl(request, arg)
+    }
+}
+
+/// The standard output and error, written to the terminal on the console.
+#[derive(Debug, Default)]
+pub struct Stdout;
+
+impl FileLike for Stdout {
+    fn read(&self, _buf: &mut [u8]) -> LxResult<usize> {
+        Err(LxError::EBADF)
+    }
+
+    fn write(&self, buf: &[u8]) -> LxResult<usize> {
+        TTY.write_at(0, buf)
+    }
+
+    fn metadata(&self) -> Metadata {
+        TTY.metadata()
+    }
+
+    fn ioctl(&self, request: usize, arg: usize) -> LxResult<usize> {
+        TTY.ioctl(request, arg)
+    }
+}
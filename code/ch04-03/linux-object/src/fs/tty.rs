@@ -0,0 +1,469 @@
+use {
+    super::{
+        vfs::{alloc_ino, FileType, INode, Metadata},
+        PollStatus,
+    },
+    crate::{
+        error::{LxError, LxResult},
+        process::{process_group, ProcessExt},
+        signal::Signal,
+    },
+    alloc::{
+        boxed::Box,
+        collections::VecDeque,
+        string::String,
+        sync::{Arc, Weak},
+        vec::Vec,
+    },
+    futures::future::BoxFuture,
+    kernel_hal::user::{UserInPtr, UserOutPtr},
+    lazy_static::lazy_static,
+    spin::Mutex,
+    zircon_object::{object::KoID, task::Job},
+};
+
+lazy_static! {
+    /// The terminal on the serial console.
+    pub static ref TTY: Arc<Tty> = Tty::new();
+}
+
+/// The device number of `/dev/tty`.
+const TTY_RDEV: usize = 5 << 8;
+
+/// Get the terminal attributes, into `struct termios`.
+const TCGETS: usize = 0x5401;
+/// Set the terminal attributes, from `struct termios`.
+const TCSETS: usize = 0x5402;
+/// Set the terminal attributes after the output is written.
+const TCSETSW: usize = 0x5403;
+/// Set the terminal attributes after the output is written, and discard the
+/// pending input.
+const TCSETSF: usize = 0x5404;
+/// Get the foreground process group, into `pid_t`.
+const TIOCGPGRP: usize = 0x540f;
+/// Set the foreground process group, from `pid_t`.
+const TIOCSPGRP: usize = 0x5410;
+/// Get the window size, into `struct winsize`.
+const TIOCGWINSZ: usize = 0x5413;
+/// Set the window size, from `struct winsize`.
+const TIOCSWINSZ: usize = 0x5414;
+
+/// Translate CR to NL on input, in `iflag`.
+const ICRNL: u32 = 0o400;
+/// Post-process the output, in `oflag`.
+const OPOST: u32 = 0o1;
+/// Translate NL to CR-NL on output, in `oflag`.
+const ONLCR: u32 = 0o4;
+/// 38400 baud, 8 bits a character and enable the receiver, in `cflag`.
+const B38400_CS8_CREAD: u32 = 0o277;
+/// Generate signals on the interrupt characters, in `lflag`.
+const ISIG: u32 = 0o1;
+/// Edit the input by lines, in `lflag`.
+const ICANON: u32 = 0o2;
+/// Echo the input, in `lflag`.
+const ECHO: u32 = 0o10;
+/// Echo the erase character as erasing the last character, in `lflag`.
+const ECHOE: u32 = 0o20;
+
+/// The index of the interrupt character in `cc`.
+const VINTR: usize = 0;
+/// The index of the quit character in `cc`.
+const VQUIT: usize = 1;
+/// The index of the erase character in `cc`.
+const VERASE: usize = 2;
+/// The index of the kill line character in `cc`.
+const VKILL: usize = 3;
+/// The index of the end of file character in `cc`.
+const VEOF: usize = 4;
+
+/// The terminal attributes, in the layout of `struct termios` of `ioctl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Termios {
+    /// The input modes.
+    pub iflag: u32,
+    /// The output modes.
+    pub oflag: u32,
+    /// The control modes.
+    pub cflag: u32,
+    /// The local modes.
+    pub lflag: u32,
+    /// The line discipline.
+    pub line: u8,
+    /// The special characters.
+    pub cc: [u8; 19],
+}
+
+impl Default for Termios {
+    /// The attributes of Linux, without `ECHO`.
+    ///
+    /// The console is assumed to echo the input locally, as a host terminal
+    /// does.
+    fn default() -> Self {
+        Termios {
+            iflag: ICRNL,
+            oflag: OPOST | ONLCR,
+            cflag: B38400_CS8_CREAD,
+            lflag: ISIG | ICANON | ECHOE,
+            line: 0,
+            // ^C ^\ DEL ^U ^D, and read at least 1 character without timeout
+            cc: [
+                3, 0x1c, 0x7f, 0x15, 4, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0xf, 0x17, 0x16, 0, 0,
+                0,
+            ],
+        }
+    }
+}
+
+/// The window size, in the layout of `struct winsize`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct WinSize {
+    /// The number of rows.
+    pub row: u16,
+    /// The number of columns.
+    pub col: u16,
+    /// The width in pixels, unused.
+    pub xpixel: u16,
+    /// The height in pixels, unused.
+    pub ypixel: u16,
+}
+
+impl Default for WinSize {
+    fn default() -> Self {
+        WinSize {
+            row: 24,
+            col: 80,
+            xpixel: 0,
+            ypixel: 0,
+        }
+    }
+}
+
+/// A terminal on the serial console, with a line discipline.
+///
+/// In the canonical mode, the input is read by lines, edited by the erase
+/// and kill characters, and the end of file character ends a line without
+/// the newline. Otherwise the input is read as it is received.
+///
+/// With `ISIG`, the interrupt and quit characters discard the pending input,
+/// and send `SIGINT` and `SIGQUIT` to the foreground process group.
+pub struct Tty {
+    ino: usize,
+    inner: Mutex<TtyInner>,
+}
+
+#[derive(Default)]
+struct TtyInner {
+    termios: Termios,
+    winsize: WinSize,
+    /// The line being edited, in the canonical mode.
+    line: Vec<u8>,
+    /// The input ready to read, by lines in the canonical mode. An empty
+    /// line is the end of file.
+    input: VecDeque<Vec<u8>>,
+    /// The job of the session, and the foreground process group in it.
+    foreground: (Weak<Job>, KoID),
+}
+
+impl Tty {
+    fn new() -> Arc<Self> {
+        Arc::new(Tty {
+            ino: alloc_ino(),
+            inner: Mutex::new(TtyInner::default()),
+        })
+    }
+
+    /// Get the terminal attributes.
+    pub fn termios(&self) -> Termios {
+        self.inner.lock().termios
+    }
+
+    /// Set the foreground process group to `pgid`, of the processes in `job`.
+    pub fn set_foreground(&self, job: &Arc<Job>, pgid: KoID) {
+        self.inner.lock().foreground = (Arc::downgrade(job), pgid);
+    }
+
+    /// Receive the input available in the serial console.
+    fn receive_serial(&self) {
+        let mut buf = [0u8; 256];
+        loop {
+            let len = kernel_hal::serial_read(&mut buf);
+            if len == 0 {
+                return;
+            }
+            self.receive(&buf[..len]);
+        }
+    }
+
+    /// Process the received input `data` by the line discipline.
+    fn receive(&self, data: &[u8]) {
+        let mut inner = self.inner.lock();
+        let termios = inner.termios;
+        let mut echo = Vec::new();
+        let mut signals = Vec::new();
+        for &c in data {
+            let c = match c {
+                b'\r' if termios.iflag & ICRNL != 0 => b'\n',
+                c => c,
+            };
+            let signal = match c {
+                _ if termios.lflag & ISIG == 0 => None,
+                c if c == termios.cc[VINTR] => Some(Signal::SIGINT),
+                c if c == termios.cc[VQUIT] => Some(Signal::SIGQUIT),
+                _ => None,
+            };
+            if let Some(signal) = signal {
+                inner.line.clear();
+                inner.input.clear();
+                signals.push(signal);
+            } else if termios.lflag & ICANON == 0 {
+                inner.input.push_back(alloc::vec![c]);
+                echo.push(c);
+            } else if c == termios.cc[VERASE] {
+                if inner.line.pop().is_some() && termios.lflag & ECHOE != 0 {
+                    echo.extend(b"\x08 \x08");
+                }
+            } else if c == termios.cc[VKILL] {
+                inner.line.clear();
+                echo.push(b'\n');
+            } else if c == termios.cc[VEOF] {
+                let line = core::mem::take(&mut inner.line);
+                inner.input.push_back(line);
+            } else {
+                inner.line.push(c);
+                echo.push(c);
+                if c == b'\n' {
+                    let line = core::mem::take(&mut inner.line);
+                    inner.input.push_back(line);
+                }
+            }
+        }
+        let (job, pgid) = inner.foreground.clone();
+        drop(inner);
+        if termios.lflag & ECHO != 0 && !echo.is_empty() {
+            kernel_hal::serial_write(&String::from_utf8_lossy(&echo));
+        }
+        if let Some(job) = job.upgrade() {
+            for signal in signals {
+                for proc in process_group(&job, pgid) {
+                    proc.send_signal(signal);
+                }
+            }
+        }
+    }
+
+    /// Read the input ready into `buf`, fail with `EAGAIN` if none.
+    ///
+    /// Read up to a line in the canonical mode, return 0 at the end of file.
+    fn read_input(&self, buf: &mut [u8]) -> LxResult<usize> {
+        let mut inner = self.inner.lock();
+        let canonical = inner.termios.lflag & ICANON != 0;
+        if inner.input.is_empty() {
+            return Err(LxError::EAGAIN);
+        }
+        let mut len = 0;
+        while len < buf.len() {
+            let chunk = match inner.input.front_mut() {
+                Some(chunk) => chunk,
+                None => break,
+            };
+            let n = chunk.len().min(buf.len() - len);
+            buf[len..len + n].copy_from_slice(&chunk[..n]);
+            chunk.drain(..n);
+            len += n;
+            if chunk.is_empty() {
+                inner.input.pop_front();
+                if canonical {
+                    break;
+                }
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl INode for Tty {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            ino: self.ino,
+            type_: FileType::CharDevice,
+            mode: 0o666,
+            nlinks: 1,
+            size: 0,
+            rdev: TTY_RDEV,
+        }
+    }
+
+    /// Read the input of the console, fail with `EAGAIN` if none.
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> LxResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.receive_serial();
+        self.read_input(buf)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> LxResult<usize> {
+        kernel_hal::serial_write(&String::from_utf8_lossy(buf));
+        Ok(buf.len())
+    }
+
+    fn resize(&self, _len: usize) -> LxResult {
+        Ok(())
+    }
+
+    fn poll(&self) -> PollStatus {
+        self.receive_serial();
+        PollStatus {
+            read: !self.inner.lock().input.is_empty(),
+            write: true,
+            ..PollStatus::default()
+        }
+    }
+
+    /// Wait for the input of the serial console.
+    fn async_poll(&self) -> BoxFuture<'_, PollStatus> {
+        Box::pin(async move {
+            loop {
+                let status = self.poll();
+                if status.read {
+                    return status;
+                }
+                kernel_hal::serial_wait().await;
+            }
+        })
+    }
+
+    /// Read the input of the console, waiting for it if none.
+    fn async_read_at<'a>(
+        &'a self,
+        offset: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, LxResult<usize>> {
+        Box::pin(async move {
+            loop {
+                match self.read_at(offset, buf) {
+                    Err(LxError::EAGAIN) => {}
+                    ret => return ret,
+                }
+                kernel_hal::serial_wait().await;
+            }
+        })
+    }
+
+    /// Get or set the terminal attributes and the window size.
+    ///
+    /// The user memory is only accessed out of the lock, which may fault.
+    fn ioctl(&self, request: usize, arg: usize) -> LxResult<usize> {
+        match request {
+            TCGETS => {
+                let termios = self.inner.lock().termios;
+                UserOutPtr::<Termios>::from(arg).write(termios)?;
+            }
+            TCSETS | TCSETSW | TCSETSF => {
+                let termios = UserInPtr::<Termios>::from(arg).read()?;
+                let mut inner = self.inner.lock();
+                inner.termios = termios;
+                if request == TCSETSF {
+                    inner.line.clear();
+                    inner.input.clear();
+                }
+            }
+            TIOCGPGRP => {
+                let pgid = self.inner.lock().foreground.1 as i32;
+                UserOutPtr::<i32>::from(arg).write(pgid)?;
+            }
+            TIOCSPGRP => {
+                let pgid = UserInPtr::<i32>::from(arg).read()? as KoID;
+                self.inner.lock().foreground.1 = pgid;
+            }
+            TIOCGWINSZ => {
+                let winsize = self.inner.lock().winsize;
+                UserOutPtr::<WinSize>::from(arg).write(winsize)?;
+            }
+            TIOCSWINSZ => {
+                let winsize = UserInPtr::<WinSize>::from(arg).read()?;
+                self.inner.lock().winsize = winsize;
+            }
+            _ => return Err(LxError::ENOTTY),
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fs::{RamInode, Vfs},
+        thread::ThreadExt,
+    };
+    use zircon_object::task::{Process, Thread};
+
+    #[test]
+    fn line_discipline() {
+        kernel_hal_unix::init();
+        let tty = Tty::new();
+        let mut buf = [0u8; 16];
+        assert_eq!(tty.read_input(&mut buf), Err(LxError::EAGAIN));
+
+        // lines are edited, and read one at a time
+        tty.receive(b"ab\x7fc\r\x15xyz");
+        assert_eq!(tty.read_input(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"ac\n");
+        assert_eq!(tty.read_input(&mut buf), Err(LxError::EAGAIN));
+        tty.receive(b"w\nnext\n");
+        assert_eq!(tty.read_input(&mut buf[..1]), Ok(1));
+        assert_eq!(tty.read_input(&mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"yzw\n");
+        assert_eq!(tty.read_input(&mut buf), Ok(5));
+        // the end of file character ends a line without the newline
+        tty.receive(b"end\x04\x04");
+        assert_eq!(tty.read_input(&mut buf), Ok(3));
+        assert_eq!(tty.read_input(&mut buf), Ok(0));
+
+        // the raw mode
+        tty.inner.lock().termios.lflag &= !ICANON;
+        tty.receive(b"a\x7f\r");
+        assert_eq!(tty.read_input(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"a\x7f\n");
+    }
+
+    #[test]
+    fn signal_foreground() {
+        kernel_hal_unix::init();
+        let job = Job::root();
+        let fs = Vfs::new(RamInode::new_root());
+        let proc = Process::create_linux(&job, "proc", fs).unwrap();
+        let child = proc.fork_linux().unwrap();
+        let other = proc.fork_linux().unwrap();
+        other.linux().set_pgid(other.id());
+        // the signals terminate the processes with threads not blocking them
+        let _threads: Vec<_> = [&proc, &child, &other]
+            .iter()
+            .map(|proc| Thread::create_linux(proc, "main").unwrap())
+            .collect();
+        let tty = Tty::new();
+        tty.set_foreground(&job, proc.id());
+        let mut buf = [0u8; 16];
+
+        // the pending input is discarded, and the group is signaled
+        tty.receive(b"ab\ncd\x03ef\n");
+        assert_eq!(tty.read_input(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"ef\n");
+        assert_eq!(proc.linux().term_signal(), Some(Signal::SIGINT));
+        assert_eq!(child.linux().term_signal(), Some(Signal::SIGINT));
+        assert_eq!(other.linux().term_signal(), None);
+
+        // without `ISIG`, the characters are read as they are
+        tty.inner.lock().termios.lflag &= !ISIG;
+        tty.set_foreground(&job, other.id());
+        tty.receive(b"\x1c\n");
+        assert_eq!(tty.read_input(&mut buf), Ok(2));
+        assert_eq!(other.linux().term_signal(), None);
+        tty.inner.lock().termios.lflag |= ISIG;
+        tty.receive(b"\x1c");
+        assert_eq!(other.linux().term_signal(), Some(Signal::SIGQUIT));
+    }
+}
@@ -0,0 +1,313 @@
+use {
+    super::{join_path, PollStatus},
+    crate::error::{LxError, LxResult},
+    alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec},
+    core::sync::atomic::{AtomicUsize, Ordering},
+    downcast_rs::{impl_downcast, DowncastSync},
+    futures::future::{self, BoxFuture},
+    spin::Mutex,
+};
+
+/// The type of a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link.
+    SymLink,
+    /// A character device.
+    CharDevice,
+    /// A block device.
+    BlockDevice,
+    /// A named pipe.
+    NamedPipe,
+    /// A socket.
+    Socket,
+}
+
+impl FileType {
+    /// The file type bits in `st_mode`.
+    pub fn mode_bits(self) -> u32 {
+        match self {
+            FileType::File => 0o100000,
+            FileType::Dir => 0o040000,
+            FileType::SymLink => 0o120000,
+            FileType::CharDevice => 0o020000,
+            FileType::BlockDevice => 0o060000,
+            FileType::NamedPipe => 0o010000,
+            FileType::Socket => 0o140000,
+        }
+    }
+
+    /// The file type in `d_type` of directory entries.
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            FileType::File => 8,
+            FileType::Dir => 4,
+            FileType::SymLink => 10,
+            FileType::CharDevice => 2,
+            FileType::BlockDevice => 6,
+            FileType::NamedPipe => 1,
+            FileType::Socket => 12,
+        }
+    }
+}
+
+/// The metadata of a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Metadata {
+    /// The inode number.
+    pub ino: usize,
+    /// The type of the file.
+    pub type_: FileType,
+    /// The permission bits.
+    pub mode: u32,
+    /// The number of hard links.
+    pub nlinks: usize,
+    /// The size in bytes.
+    pub size: usize,
+    /// The device ID, if it is a device.
+    pub rdev: usize,
+}
+
+/// A node of a filesystem, which is a file or a directory.
+///
+/// The operations on directories fail with `ENOTDIR` by default.
+pub trait INode: DowncastSync {
+    /// Get the metadata.
+    fn metadata(&self) -> Metadata;
+
+    /// Read from the `offset` of the file.
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> LxResult<usize>;
+
+    /// Write to the `offset` of the file, which is extended if needed.
+    fn write_at(&self, offset: usize, buf: &[u8]) -> LxResult<usize>;
+
+    /// Resize the file to `len` bytes.
+    fn resize(&self, len: usize) -> LxResult;
+
+    /// Get the readiness of the file.
+    ///
+    /// The file is always ready for reading and writing by default.
+    fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: true,
+            write: true,
+            ..PollStatus::default()
+        }
+    }
+
+    /// Wait until the file is ready for reading or writing, return the
+    /// readiness.
+    fn async_poll(&self) -> BoxFuture<'_, PollStatus> {
+        Box::pin(future::ready(self.poll()))
+    }
+
+    /// Read from the `offset` of the file, waiting for the data if there is
+    /// none, such as on a terminal.
+    fn async_read_at<'a>(
+        &'a self,
+        offset: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, LxResult<usize>> {
+        Box::pin(future::ready(self.read_at(offset, buf)))
+    }
+
+    /// Control the device of the file, `arg` is usually a pointer in the user
+    /// memory.
+    ///
+    /// Fail with `ENOTTY` if it is not a device.
+    fn ioctl(&self, _request: usize, _arg: usize) -> LxResult<usize> {
+        Err(LxError::ENOTTY)
+    }
+
+    /// Find the entry `name` in the directory.
+    fn find(&self, _name: &str) -> LxResult<Arc<dyn INode>> {
+        Err(LxError::ENOTDIR)
+    }
+
+    /// Create the entry `name` of `type_` with the permission bits `mode` in
+    /// the directory.
+    fn create(&self, _name: &str, _type_: FileType, _mode: u32) -> LxResult<Arc<dyn INode>> {
+        Err(LxError::ENOTDIR)
+    }
+
+    /// Remove the entry `name` from the directory, which must not be a
+    /// non-empty directory.
+    fn unlink(&self, _name: &str) -> LxResult {
+        Err(LxError::ENOTDIR)
+    }
+
+    /// List the entries of the directory.
+    fn list(&self) -> LxResult<Vec<(String, Arc<dyn INode>)>> {
+        Err(LxError::ENOTDIR)
+    }
+}
+
+impl_downcast!(sync INode);
+
+/// Allocate a new inode number, unique in all filesystems.
+pub(super) fn alloc_ino() -> usize {
+    static NEXT_INO: AtomicUsize = AtomicUsize::new(1);
+    NEXT_INO.fetch_add(1, Ordering::SeqCst)
+}
+
+/// The tree of files, made of filesystems mounted on directories.
+pub struct Vfs {
+    /// The roots of filesystems, by the absolute paths they are mounted on.
+    mounts: Mutex<BTreeMap<String, Arc<dyn INode>>>,
+}
+
+impl Vfs {
+    /// Create a tree with the filesystem of `root` mounted on `/`.
+    pub fn new(root: Arc<dyn INode>) -> Arc<Self> {
+        let mut mounts = BTreeMap::new();
+        mounts.insert(String::from("/"), root);
+        Arc::new(Vfs {
+            mounts: Mutex::new(mounts),
+        })
+    }
+
+    /// Get the root directory.
+    pub fn root(&self) -> Arc<dyn INode> {
+        self.mounts.lock()["/"].clone()
+    }
+
+    /// Mount the filesystem of `root` on the directory `path`, hiding its
+    /// entries.
+    pub fn mount(&self, path: &str, root: Arc<dyn INode>) -> LxResult {
+        let path = join_path("/", path);
+        if self.lookup(&path)?.metadata().type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        self.mounts.lock().insert(path, root);
+        Ok(())
+    }
+
+    /// Find the file of the absolute `path`.
+    pub fn lookup(&self, path: &str) -> LxResult<Arc<dyn INode>> {
+        let path = join_path("/", path);
+        let mounts = self.mounts.lock();
+        let mut inode = mounts["/"].clone();
+        let mut prefix = String::new();
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.find(name)?;
+            prefix += "/";
+            prefix += name;
+            if let Some(root) = mounts.get(&prefix) {
+                inode = root.clone();
+            }
+        }
+        Ok(inode)
+    }
+
+    /// Find the parent directory of the absolute `path`, return it with the
+    /// last name in the path.
+    ///
+    /// The root has no parent, fail with `EBUSY`.
+    pub fn lookup_parent(&self, path: &str) -> LxResult<(Arc<dyn INode>, String)> {
+        let path = join_path("/", path);
+        let split = path.rfind('/').unwrap();
+        let name = &path[split + 1..];
+        if name.is_empty() {
+            return Err(LxError::EBUSY);
+        }
+        let dir = self.lookup(&path[..split])?;
+        if dir.metadata().type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        Ok((dir, String::from(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{File, FileLike, OpenFlags, RamInode, SeekFrom};
+
+    #[test]
+    fn lookup_and_mount() {
+        kernel_hal_unix::init();
+        let fs = Vfs::new(RamInode::new_root());
+        let root = fs.root();
+        let usr = root.create("usr", FileType::Dir, 0o755).unwrap();
+        usr.create("lib", FileType::Dir, 0o755).unwrap();
+        assert_eq!(
+            root.create("usr", FileType::Dir, 0o755).err(),
+            Some(LxError::EEXIST)
+        );
+        let file = root.create("file", FileType::File, 0o644).unwrap();
+
+        assert_eq!(
+            fs.lookup("/usr/./lib/..").unwrap().metadata(),
+            usr.metadata()
+        );
+        assert_eq!(fs.lookup("/").unwrap().metadata(), root.metadata());
+        assert_eq!(fs.lookup("/none").err(), Some(LxError::ENOENT));
+        assert_eq!(fs.lookup("/file/x").err(), Some(LxError::ENOTDIR));
+        let (dir, name) = fs.lookup_parent("/usr/lib/x").unwrap();
+        assert_eq!((dir.metadata().type_, name.as_str()), (FileType::Dir, "x"));
+        assert_eq!(fs.lookup_parent("/").err(), Some(LxError::EBUSY));
+        assert_eq!(fs.lookup_parent("/file/x").err(), Some(LxError::ENOTDIR));
+
+        // the mounted filesystem hides the directory
+        let tmp = RamInode::new_root();
+        tmp.create("a", FileType::File, 0o644).unwrap();
+        fs.mount("/usr", tmp).unwrap();
+        assert!(fs.lookup("/usr/a").is_ok());
+        assert_eq!(fs.lookup("/usr/lib").err(), Some(LxError::ENOENT));
+        assert_eq!(
+            fs.mount("/file", RamInode::new_root()),
+            Err(LxError::ENOTDIR)
+        );
+
+        assert_eq!(root.unlink("usr"), Err(LxError::ENOTEMPTY));
+        root.unlink("file").unwrap();
+        assert_eq!(root.unlink("file"), Err(LxError::ENOENT));
+        assert_eq!(file.find("x").err(), Some(LxError::ENOTDIR));
+        let names: Vec<String> = root.list().unwrap().into_iter().map(|e| e.0).collect();
+        assert_eq!(names, ["usr"]);
+    }
+
+    #[test]
+    fn file() {
+        kernel_hal_unix::init();
+        let root = RamInode::new_root();
+        let inode = root.create("file", FileType::File, 0o644).unwrap();
+        let file = File::new(inode.clone(), String::from("/file"), OpenFlags::RDWR);
+        assert_eq!(file.write(b"hello"), Ok(5));
+        assert_eq!(file.seek(SeekFrom::Current(-3)), Ok(2));
+        let mut buf = [0u8; 8];
+        assert_eq!(file.read(&mut buf), Ok(3));
+        assert_eq!(&buf[..3], b"llo");
+        assert_eq!(file.read(&mut buf), Ok(0));
+        assert_eq!(file.seek(SeekFrom::End(2)), Ok(7));
+        assert_eq!(file.write(b"!"), Ok(1));
+        assert_eq!(file.read_at(4, &mut buf), Ok(4));
+        assert_eq!(&buf[..4], b"o\0\0!");
+        assert_eq!(file.seek(SeekFrom::Current(-9)), Err(LxError::EINVAL));
+
+        // the access mode is checked, and appending writes at the end
+        let file = File::new(inode.clone(), String::from("/file"), OpenFlags::RDONLY);
+        assert_eq!(file.write(b"x"), Err(LxError::EBADF));
+        let flags = OpenFlags::WRONLY | OpenFlags::APPEND;
+        let file = File::new(inode.clone(), String::from("/file"), flags);
+        assert_eq!(file.read(&mut buf), Err(LxError::EBADF));
+        assert_eq!(file.write(b"?"), Ok(1));
+        assert_eq!(inode.metadata().size, 9);
+
+        let dir = File::new(root, String::from("/"), OpenFlags::RDONLY);
+        assert_eq!(dir.read(&mut buf), Err(LxError::EISDIR));
+
+        // the size of a file is bounded
+        assert_eq!(inode.write_at(usize::MAX, b"x"), Err(LxError::EINVAL));
+        assert_eq!(
+            inode.write_at(crate::fs::ramfs::MAX_FILE_SIZE, b"x"),
+            Err(LxError::EFBIG)
+        );
+        assert_eq!(inode.resize(usize::MAX), Err(LxError::EFBIG));
+        assert_eq!(inode.metadata().size, 9);
+    }
+}
@@ -0,0 +1,24 @@
+//! Linux kernel objects
+//!
+//! The Linux compatibility layer is built on the kernel objects of Zircon:
+//! a Linux process is a Zircon process extended with Linux states, such as
+//! the file descriptor table and the current working directory.
+
+#![no_std]
+#![deny(warnings, unsafe_code, missing_docs)]
+
+extern crate alloc;
+
+#[cfg(test)]
+#[macro_use]
+extern crate std;
+
+#[macro_use]
+extern crate log;
+
+pub mod error;
+pub mod fs;
+pub mod loader;
+pub mod process;
+pub mod signal;
+pub mod thread;
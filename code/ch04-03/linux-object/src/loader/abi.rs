@@ -0,0 +1,171 @@
+//! The initial stack of a Linux program, as specified by the System V ABI.
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+
+/// The end of the auxiliary vector.
+pub const AT_NULL: u8 = 0;
+/// The address of the program headers.
+pub const AT_PHDR: u8 = 3;
+/// The size of a program header entry.
+pub const AT_PHENT: u8 = 4;
+/// The number of program headers.
+pub const AT_PHNUM: u8 = 5;
+/// The page size.
+pub const AT_PAGESZ: u8 = 6;
+/// The base address of the interpreter, 0 if none.
+pub const AT_BASE: u8 = 7;
+/// The entry point of the program.
+pub const AT_ENTRY: u8 = 9;
+/// The frequency of `times()`.
+pub const AT_CLKTCK: u8 = 17;
+/// The address of 16 random bytes.
+pub const AT_RANDOM: u8 = 25;
+/// The path of the program.
+pub const AT_EXECFN: u8 = 31;
+
+/// The information passed to a Linux program on its initial stack.
+pub struct ProcInitInfo {
+    /// The arguments.
+    pub args: Vec<String>,
+    /// The environment variables.
+    pub envs: Vec<String>,
+    /// The auxiliary vector, except `AT_RANDOM` and `AT_EXECFN` which point
+    /// into the stack.
+    pub auxv: BTreeMap<u8, usize>,
+    /// The random bytes of `AT_RANDOM`.
+    pub random: [u8; 16],
+}
+
+impl ProcInitInfo {
+    /// Build the initial stack ending at `stack_top`.
+    ///
+    /// Return the content of the stack, whose start is the initial stack
+    /// pointer, 16-byte aligned, pointing to `argc`.
+    pub fn push_at(&self, stack_top: usize) -> Vec<u8> {
+        let mut stack = Stack::new(stack_top);
+        // the strings and random bytes on the top
+        let execfn = match self.args.first() {
+            Some(path) => stack.push_str(path),
+            None => 0,
+        };
+        let envs: Vec<usize> = self.envs.iter().map(|s| stack.push_str(s)).collect();
+        let args: Vec<usize> = self.args.iter().map(|s| stack.push_str(s)).collect();
+        stack.push_bytes(&self.random);
+        let random = stack.sp;
+        stack.align(16);
+
+        let mut auxv = self.auxv.clone();
+        auxv.insert(AT_RANDOM, random);
+        if execfn != 0 {
+            auxv.insert(AT_EXECFN, execfn);
+        }
+        // argc, argv, envp and auxv, each of a word
+        let words = 1 + args.len() + 1 + envs.len() + 1 + (auxv.len() + 1) * 2;
+        if words % 2 == 1 {
+            stack.push_word(0);
+        }
+        stack.push_word(0);
+        stack.push_word(AT_NULL as usize);
+        for (&kind, &value) in auxv.iter().rev() {
+            stack.push_word(value);
+            stack.push_word(kind as usize);
+        }
+        stack.push_word(0);
+        for &env in envs.iter().rev() {
+            stack.push_word(env);
+        }
+        stack.push_word(0);
+        for &arg in args.iter().rev() {
+            stack.push_word(arg);
+        }
+        stack.push_word(args.len());
+        stack.data
+    }
+}
+
+/// A stack growing down from the top, built before being written to the
+/// user memory.
+struct Stack {
+    sp: usize,
+    data: Vec<u8>,
+}
+
+impl Stack {
+    fn new(top: usize) -> Self {
+        Stack {
+            sp: top,
+            data: Vec::new(),
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.sp -= bytes.len();
+        self.data.splice(0..0, bytes.iter().cloned());
+    }
+
+    fn push_word(&mut self, value: usize) {
+        self.push_bytes(&value.to_ne_bytes());
+    }
+
+    /// Push a NUL-terminated string, return its address.
+    fn push_str(&mut self, s: &str) -> usize {
+        self.push_bytes(&[0]);
+        self.push_bytes(s.as_bytes());
+        self.sp
+    }
+
+    fn align(&mut self, align: usize) {
+        let padding = self.sp % align;
+        self.push_bytes(&[0; 16][..padding]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use core::convert::TryInto;
+
+    #[test]
+    fn init_stack() {
+        let mut auxv = BTreeMap::new();
+        auxv.insert(AT_PAGESZ, 0x1000);
+        let info = ProcInitInfo {
+            args: vec!["/bin/busybox".to_string(), "sh".to_string()],
+            envs: vec!["PATH=/bin".to_string()],
+            auxv,
+            random: [7; 16],
+        };
+        let top = 0x10_0000;
+        let data = info.push_at(top);
+        let sp = top - data.len();
+        assert_eq!(sp % 16, 0);
+        let word = |addr: usize| {
+            let offset = addr - sp;
+            usize::from_ne_bytes(data[offset..offset + 8].try_into().unwrap())
+        };
+        let cstr = |addr: usize| {
+            let offset = addr - sp;
+            let len = data[offset..].iter().position(|&b| b == 0).unwrap();
+            core::str::from_utf8(&data[offset..offset + len]).unwrap()
+        };
+        assert_eq!(word(sp), 2);
+        assert_eq!(cstr(word(sp + 8)), "/bin/busybox");
+        assert_eq!(cstr(word(sp + 16)), "sh");
+        assert_eq!(word(sp + 24), 0);
+        assert_eq!(cstr(word(sp + 32)), "PATH=/bin");
+        assert_eq!(word(sp + 40), 0);
+        // auxv in the order of types
+        let mut auxv = Vec::new();
+        let mut addr = sp + 48;
+        while word(addr) != AT_NULL as usize {
+            auxv.push((word(addr) as u8, word(addr + 8)));
+            addr += 16;
+        }
+        assert_eq!(auxv.len(), 3);
+        assert_eq!(auxv[0], (AT_PAGESZ, 0x1000));
+        assert_eq!(auxv[1].0, AT_RANDOM);
+        assert_eq!(data[auxv[1].1 - sp..auxv[1].1 - sp + 16], [7; 16]);
+        assert_eq!(auxv[2].0, AT_EXECFN);
+        assert_eq!(cstr(auxv[2].1), "/bin/busybox");
+    }
+}
@@ -0,0 +1,127 @@
+//! Linux ELF loading
+use {
+    crate::error::{LxError, LxResult},
+    alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec},
+    kernel_hal::MMUFlags,
+    xmas_elf::{header::Type, program, ElfFile},
+    zircon_object::{
+        util::{elf_loader::*, random},
+        vm::*,
+    },
+};
+
+pub mod abi;
+
+/// The symbol of the syscall entry, filled by the loader in the libos mode.
+const SYSCALL_ENTRY_SYMBOL: &str = "rcore_syscall_entry";
+
+/// The frequency of `times()`.
+const CLOCKS_PER_SEC: usize = 100;
+
+/// The loader of statically linked Linux programs.
+pub struct LinuxElfLoader {
+    /// The address of the syscall entry, filled into the program if it
+    /// calls syscalls through the entry, as the libc patched for the libos
+    /// mode does.
+    pub syscall_entry: usize,
+    /// The number of pages of the stack.
+    pub stack_pages: usize,
+}
+
+impl LinuxElfLoader {
+    /// Load the ELF `data` with `args` and `envs` into `vmar`.
+    ///
+    /// Return the entry point and the initial stack pointer.
+    ///
+    /// Position-independent programs are placed anywhere in `vmar`, while
+    /// others are placed at their linked addresses, which must be inside
+    /// `vmar`. Programs requiring an interpreter are not supported.
+    pub fn load(
+        &self,
+        vmar: &Arc<VmAddressRegion>,
+        data: &[u8],
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) -> LxResult<(usize, usize)> {
+        let data = self.patch_syscall_entry(data)?;
+        let elf = ElfFile::new(&data).map_err(|_| LxError::ENOEXEC)?;
+        if elf.get_interpreter().is_ok() {
+            warn!("elf: dynamically linked programs are not supported");
+            return Err(LxError::ENOEXEC);
+        }
+        let size = elf.load_segment_size();
+        let flags = VmarFlags::CAN_MAP_RXW;
+        // the programs not position-independent are placed at their linked
+        // addresses, that is, with the base 0
+        let (image_vmar, base, start) = match elf.header.pt2.type_().as_type() {
+            Type::SharedObject => {
+                let image_vmar = vmar.allocate(None, size, flags, PAGE_SIZE)?;
+                let base = image_vmar.addr();
+                (image_vmar, base, 0)
+            }
+            Type::Executable => {
+                let start = elf.load_segment_start();
+                let offset = start.checked_sub(vmar.addr()).ok_or(LxError::ENOEXEC)?;
+                let image_vmar = vmar
+                    .allocate_at(offset, size - start, flags | VmarFlags::SPECIFIC, PAGE_SIZE)
+                    .map_err(|_| LxError::ENOEXEC)?;
+                (image_vmar, 0, start)
+            }
+            _ => return Err(LxError::ENOEXEC),
+        };
+        image_vmar.load_from_elf_at(&elf, start)?;
+        let entry = base + elf.header.pt2.entry_point() as usize;
+
+        let stack_vmo = VmObject::new_paged(self.stack_pages);
+        let flags = MMUFlags::READ | MMUFlags::WRITE | MMUFlags::USER;
+        let stack_bottom = vmar.map(None, stack_vmo.clone(), 0, stack_vmo.len(), flags)?;
+        let stack_top = stack_bottom + stack_vmo.len();
+
+        let mut auxv = BTreeMap::new();
+        if let Some(phdr) = elf.get_phdr_vaddr() {
+            auxv.insert(abi::AT_PHDR, base + phdr as usize);
+        }
+        auxv.insert(abi::AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
+        auxv.insert(abi::AT_PHNUM, elf.header.pt2.ph_count() as usize);
+        auxv.insert(abi::AT_PAGESZ, PAGE_SIZE);
+        auxv.insert(abi::AT_BASE, 0);
+        auxv.insert(abi::AT_ENTRY, entry);
+        auxv.insert(abi::AT_CLKTCK, CLOCKS_PER_SEC);
+        let mut info = abi::ProcInitInfo {
+            args,
+            envs,
+            auxv,
+            random: [0; 16],
+        };
+        random::draw(&mut info.random);
+        let init_stack = info.push_at(stack_top);
+        if init_stack.len() > stack_vmo.len() {
+            return Err(LxError::E2BIG);
+        }
+        stack_vmo.write(stack_vmo.len() - init_stack.len(), &init_stack)?;
+        Ok((entry, stack_top - init_stack.len()))
+    }
+
+    /// Copy the ELF `data`, with the syscall entry filled if the program
+    /// has the symbol of it.
+    fn patch_syscall_entry(&self, data: &[u8]) -> LxResult<Vec<u8>> {
+        let mut data = data.to_vec();
+        let elf = ElfFile::new(&data).map_err(|_| LxError::ENOEXEC)?;
+        let vaddr = match elf.get_symbol_address(SYSCALL_ENTRY_SYMBOL) {
+            Some(vaddr) => vaddr,
+            None => return Ok(data),
+        };
+        let entry = self.syscall_entry.to_ne_bytes();
+        let offset = elf
+            .program_iter()
+            .filter(|ph| ph.get_type() == Ok(program::Type::Load))
+            .find(|ph| {
+                ph.virtual_addr() <= vaddr
+                    && vaddr + entry.len() as u64 <= ph.virtual_addr() + ph.file_size()
+            })
+            .map(|ph| (ph.offset() + vaddr - ph.virtual_addr()) as usize)
+            .ok_or(LxError::ENOEXEC)?;
+        data[offset..offset + entry.len()].copy_from_slice(&entry);
+        Ok(data)
+    }
+}
@@ -0,0 +1,756 @@
+//! Linux process
+use {
+    crate::{
+        error::{LxError, LxResult},
+        fs::{join_path, File, FileDesc, FileLike, FileType, INode, Stdin, Stdout, Vfs},
+        signal::{SigSet, Signal, SignalAction, SignalActionFlags, SIGNAL_COUNT},
+        thread::ThreadExt,
+    },
+    alloc::{
+        boxed::Box,
+        collections::BTreeMap,
+        string::String,
+        sync::{Arc, Weak},
+        vec::Vec,
+    },
+    core::cmp::Ordering,
+    kernel_hal::MMUFlags,
+    spin::Mutex,
+    zircon_object::{
+        object::{KernelObject, KoID, Signal as ZxSignal},
+        signal::Event,
+        task::{Job, Process, Status, Thread},
+        vm::*,
+        ZxError, ZxResult,
+    },
+};
+
+/// Linux extensions of Zircon processes.
+pub trait ProcessExt {
+    /// Create a new Linux process in the `job`, on the file tree `fs`.
+    ///
+    /// It has no parent, and adopts the orphans of its descendants as the
+    /// init process.
+    fn create_linux(job: &Arc<Job>, name: &str, fs: Arc<Vfs>) -> ZxResult<Arc<Self>>;
+
+    /// Create a child process of this one, with a copy of the address space,
+    /// the file descriptor table and the current working directory, on the
+    /// same file tree.
+    ///
+    /// The opened files are shared between the processes. The child is kept
+    /// as a zombie after it terminates, until it is reaped by the parent.
+    fn fork_linux(self: &Arc<Self>) -> ZxResult<Arc<Self>>;
+
+    /// Get the Linux states of the process.
+    ///
+    /// Panic if it is not a Linux process.
+    fn linux(&self) -> &LinuxProcess;
+
+    /// Exit the process with the Linux exit `code`.
+    ///
+    /// Only the low 8 bits of the code are kept, as in Linux.
+    fn exit_linux(&self, code: i32);
+
+    /// Get the Linux exit code if the process has exited.
+    fn exit_code(&self) -> Option<i32>;
+
+    /// Get the wait status of the exited process, as `wait4` reports.
+    ///
+    /// It is the signal number if the process is killed by a signal,
+    /// otherwise the exit code shifted left by 8 bits.
+    fn wait_status(&self) -> i32;
+
+    /// Send `signal` to the process.
+    ///
+    /// The signal is discarded if it is ignored. If its default action is to
+    /// terminate and a thread does not block it, the process is terminated
+    /// at once. Otherwise it is pending until a thread not blocking it
+    /// handles it, and one of such threads blocking in a syscall is
+    /// interrupted.
+    fn send_signal(&self, signal: Signal);
+
+    /// Terminate the process by `signal`, with the exit code `128 + signal`
+    /// as a shell reports.
+    fn kill_by_signal(&self, signal: Signal);
+}
+
+impl ProcessExt for Process {
+    fn create_linux(job: &Arc<Job>, name: &str, fs: Arc<Vfs>) -> ZxResult<Arc<Self>> {
+        let proc = Process::create_with_ext(job, name, LinuxProcess::new(fs))?;
+        proc.linux().inner.lock().pgid = proc.id();
+        watch_termination(&proc);
+        Ok(proc)
+    }
+
+    fn fork_linux(self: &Arc<Self>) -> ZxResult<Arc<Self>> {
+        let mut inner = self.linux().inner.lock();
+        let linux = LinuxProcess {
+            fs: self.linux().fs.clone(),
+            child_exit: Event::new(),
+            inner: Mutex::new(LinuxProcessInner {
+                parent: Arc::downgrade(self),
+                pgid: inner.pgid,
+                cwd: inner.cwd.clone(),
+                files: inner.files.clone(),
+                heap: None,
+                children: BTreeMap::new(),
+                signal_actions: inner.signal_actions,
+                pending_signals: SigSet::default(),
+                term_signal: None,
+            }),
+        };
+        let proc = self.fork_with_ext(linux)?;
+        if let Some(heap) = &inner.heap {
+            let vmar = proc
+                .vmar()
+                .find_child(heap.vmar.addr())
+                .ok_or(ZxError::BAD_STATE)?;
+            proc.linux().inner.lock().heap = Some(Heap {
+                vmar,
+                brk: heap.brk,
+            });
+        }
+        inner.children.insert(proc.id(), proc.clone());
+        watch_termination(&proc);
+        Ok(proc)
+    }
+
+    fn linux(&self) -> &LinuxProcess {
+        self.ext().downcast_ref::<LinuxProcess>().unwrap()
+    }
+
+    fn exit_linux(&self, code: i32) {
+        self.exit((code & 0xff) as i64);
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        match self.status() {
+            Status::Exited(code) => Some(code as i32),
+            _ => None,
+        }
+    }
+
+    fn wait_status(&self) -> i32 {
+        match self.linux().term_signal() {
+            Some(signal) => signal.num() as i32,
+            None => (self.exit_code().unwrap_or(0) & 0xff) << 8,
+        }
+    }
+
+    fn send_signal(&self, signal: Signal) {
+        let mut inner = self.linux().inner.lock();
+        let action = inner.signal_actions[signal.num() - 1];
+        if action.ignores(signal) {
+            return;
+        }
+        let receivers: Vec<Arc<Thread>> = self
+            .thread_ids()
+            .into_iter()
+            .filter_map(|id| self.get_child(id).ok()?.downcast_arc::<Thread>().ok())
+            .filter(|thread| !thread.linux().signal_mask().contains(signal))
+            .collect();
+        if action.terminates(signal) && !receivers.is_empty() {
+            drop(inner);
+            self.kill_by_signal(signal);
+            return;
+        }
+        inner.pending_signals.insert(signal);
+        drop(inner);
+        for thread in receivers {
+            if thread.interrupt() {
+                break;
+            }
+        }
+    }
+
+    fn kill_by_signal(&self, signal: Signal) {
+        if self.exit_code().is_some() {
+            return;
+        }
+        info!("process {} is killed by {:?}", self.id(), signal);
+        self.linux().inner.lock().term_signal = Some(signal);
+        self.exit(128 + signal.num() as i64);
+    }
+}
+
+/// Notify the parent when `proc` terminates, and give its children to the
+/// init process.
+///
+/// The callback runs with the signals of `proc` locked, so it does not touch
+/// the signals of `proc` itself.
+fn watch_termination(proc: &Arc<Process>) {
+    let weak = Arc::downgrade(proc);
+    proc.add_signal_callback(Box::new(move |signal| {
+        if !signal.contains(ZxSignal::PROCESS_TERMINATED) {
+            return false;
+        }
+        if let Some(proc) = weak.upgrade() {
+            on_terminated(&proc);
+        }
+        true
+    }));
+}
+
+/// Re-parent the children of the terminated `proc` to the init process, and
+/// wake up its parent waiting for children with `SIGCHLD` sent.
+fn on_terminated(proc: &Arc<Process>) {
+    let linux = proc.linux();
+    let children = core::mem::take(&mut linux.inner.lock().children);
+    let parent = linux.parent();
+    // the orphans of the init process are left without a parent
+    if let Some(init) = linux.init_process() {
+        if !children.is_empty() {
+            let init_linux = init.linux();
+            for (id, child) in children {
+                child.linux().inner.lock().parent = Arc::downgrade(&init);
+                init_linux.inner.lock().children.insert(id, child);
+            }
+            // some of them may be zombies already
+            init_linux.child_exit.signal_set(ZxSignal::SIGNALED);
+        }
+    }
+    if let Some(parent) = parent {
+        parent.linux().child_exit.signal_set(ZxSignal::SIGNALED);
+        parent.send_signal(Signal::SIGCHLD);
+    }
+}
+
+/// The children waited for.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum WaitTarget {
+    /// Any child.
+    Any,
+    /// The child of the ID.
+    Process(KoID),
+    /// The children in the process group of the ID.
+    Group(KoID),
+}
+
+/// Get the Linux processes of the process group `pgid` in `job`.
+pub fn process_group(job: &Job, pgid: KoID) -> Vec<Arc<Process>> {
+    job.process_ids()
+        .into_iter()
+        .filter_map(|id| job.get_child(id).ok()?.downcast_arc::<Process>().ok())
+        .filter(|proc| proc.ext().is::<LinuxProcess>() && proc.linux().pgid() == pgid)
+        .collect()
+}
+
+/// The maximum number of opened files of a process.
+pub const FILE_LIMIT: usize = 1024;
+
+/// The size of the address space reserved for the heap grown by `brk`.
+pub const HEAP_SIZE: usize = 0x1000_0000;
+
+/// The Linux states of a process.
+pub struct LinuxProcess {
+    /// The file tree.
+    fs: Arc<Vfs>,
+    /// The event signaled when a child terminates or is adopted.
+    child_exit: Arc<Event>,
+    inner: Mutex<LinuxProcessInner>,
+}
+
+struct LinuxProcessInner {
+    /// The parent process, which forked this one or adopted it as an orphan.
+    parent: Weak<Process>,
+    /// The ID of the process group, inherited from the parent.
+    pgid: KoID,
+    /// The current working directory, as an absolute path.
+    cwd: String,
+    /// The file descriptor table.
+    files: BTreeMap<FileDesc, Arc<dyn FileLike>>,
+    /// The heap, allocated on the first use of `brk`.
+    heap: Option<Heap>,
+    /// The child processes, kept until they are reaped.
+    children: BTreeMap<KoID, Arc<Process>>,
+    /// The actions on the signals, indexed by the signal number minus 1.
+    signal_actions: [SignalAction; SIGNAL_COUNT],
+    /// The signals sent to the process, not handled yet.
+    pending_signals: SigSet,
+    /// The signal terminating the process, if any.
+    term_signal: Option<Signal>,
+}
+
+/// The heap of a process, from the start of its VMAR to the program break.
+struct Heap {
+    vmar: Arc<VmAddressRegion>,
+    brk: usize,
+}
+
+impl LinuxProcess {
+    /// Create the Linux states on the file tree `fs`, with the standard
+    /// input, output and error opened on the console, in the root directory.
+    pub fn new(fs: Arc<Vfs>) -> Self {
+        let mut files = BTreeMap::<FileDesc, Arc<dyn FileLike>>::new();
+        files.insert(FileDesc::STDIN, Arc::new(Stdin));
+        files.insert(FileDesc::STDOUT, Arc::new(Stdout));
+        files.insert(FileDesc::STDERR, Arc::new(Stdout));
+        LinuxProcess {
+            fs,
+            child_exit: Event::new(),
+            inner: Mutex::new(LinuxProcessInner {
+                parent: Weak::new(),
+                pgid: 0,
+                cwd: String::from("/"),
+                files,
+                heap: None,
+                children: BTreeMap::new(),
+                signal_actions: [SignalAction::default(); SIGNAL_COUNT],
+                pending_signals: SigSet::default(),
+                term_signal: None,
+            }),
+        }
+    }
+
+    /// Get the file tree.
+    pub fn fs(&self) -> &Arc<Vfs> {
+        &self.fs
+    }
+
+    /// Get the parent process, if it is alive.
+    pub fn parent(&self) -> Option<Arc<Process>> {
+        self.inner.lock().parent.upgrade()
+    }
+
+    /// Get the ID of the process group.
+    pub fn pgid(&self) -> KoID {
+        self.inner.lock().pgid
+    }
+
+    /// Move the process to the process group `pgid`.
+    pub fn set_pgid(&self, pgid: KoID) {
+        self.inner.lock().pgid = pgid;
+    }
+
+    /// Get the init process, the farthest ancestor of the process.
+    ///
+    /// The init process itself gets `None`.
+    fn init_process(&self) -> Option<Arc<Process>> {
+        let mut init = self.parent()?;
+        while let Some(parent) = init.linux().parent() {
+            init = parent;
+        }
+        Some(init)
+    }
+
+    /// Get the child processes, including the zombies.
+    pub fn children(&self) -> Vec<Arc<Process>> {
+        self.inner.lock().children.values().cloned().collect()
+    }
+
+    /// Get the event signaled when a child terminates.
+    ///
+    /// It is never cleared by the process, the waiter clears it before
+    /// checking the children.
+    pub fn child_exit_event(&self) -> Arc<Event> {
+        self.child_exit.clone()
+    }
+
+    /// Reap a terminated child of `target`, return its ID and the wait
+    /// status.
+    ///
+    /// Return `None` if no such child terminated yet, fail with `ECHILD` if
+    /// there is no such child.
+    pub fn reap_child(&self, target: WaitTarget) -> LxResult<Option<(KoID, i32)>> {
+        // the signals of the children are not read under the lock, which
+        // is taken by their termination callbacks
+        let children: Vec<Arc<Process>> = self.inner.lock().children.values().cloned().collect();
+        let children: Vec<Arc<Process>> = children
+            .into_iter()
+            .filter(|child| match target {
+                WaitTarget::Any => true,
+                WaitTarget::Process(pid) => child.id() == pid,
+                WaitTarget::Group(pgid) => child.linux().pgid() == pgid,
+            })
+            .collect();
+        if children.is_empty() {
+            return Err(LxError::ECHILD);
+        }
+        for child in children {
+            if !child.signal().contains(ZxSignal::PROCESS_TERMINATED) {
+                continue;
+            }
+            // another thread may reap it first
+            if self.inner.lock().children.remove(&child.id()).is_some() {
+                return Ok(Some((child.id(), child.wait_status())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the action on `signal`.
+    pub fn signal_action(&self, signal: Signal) -> SignalAction {
+        self.inner.lock().signal_actions[signal.num() - 1]
+    }
+
+    /// Set the action on `signal`, fail with `EINVAL` if it cannot be
+    /// caught or ignored.
+    ///
+    /// The pending `signal` is discarded if it becomes ignored.
+    pub fn set_signal_action(&self, signal: Signal, action: SignalAction) -> LxResult {
+        if !signal.catchable() {
+            return Err(LxError::EINVAL);
+        }
+        let mut inner = self.inner.lock();
+        inner.signal_actions[signal.num() - 1] = action;
+        if action.ignores(signal) {
+            inner.pending_signals.remove(signal);
+        }
+        Ok(())
+    }
+
+    /// Get the signal terminating the process, if it is terminated by one.
+    pub fn term_signal(&self) -> Option<Signal> {
+        self.inner.lock().term_signal
+    }
+
+    /// Take a signal pending on the thread `thread` or on the process, which
+    /// is not blocked by the thread, with the action on it.
+    ///
+    /// The action is reset to the default if it has `RESETHAND`.
+    pub(crate) fn dequeue_signal(&self, thread: &Thread) -> Option<(Signal, SignalAction)> {
+        let mut inner = self.inner.lock();
+        let linux = thread.linux();
+        let mask = linux.signal_mask();
+        let signal = match linux.dequeue_signal(mask) {
+            Some(signal) => signal,
+            None => {
+                let signal = inner.pending_signals.first_unmasked(mask)?;
+                inner.pending_signals.remove(signal);
+                signal
+            }
+        };
+        let action = &mut inner.signal_actions[signal.num() - 1];
+        let ret = *action;
+        if action.flags.contains(SignalActionFlags::RESETHAND) {
+            *action = SignalAction::default();
+        }
+        Some((signal, ret))
+    }
+
+    /// Whether a signal not blocked by `mask` is pending on the process.
+    pub(crate) fn has_signal(&self, mask: SigSet) -> bool {
+        self.inner
+            .lock()
+            .pending_signals
+            .first_unmasked(mask)
+            .is_some()
+    }
+
+    /// Add a file to the lowest free file descriptor.
+    pub fn add_file(&self, file: Arc<dyn FileLike>) -> LxResult<FileDesc> {
+        let mut inner = self.inner.lock();
+        let fd = (0..FILE_LIMIT)
+            .map(FileDesc::from)
+            .find(|fd| !inner.files.contains_key(fd))
+            .ok_or(LxError::EMFILE)?;
+        inner.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    /// Add a file to the file descriptor `fd`, closing the file previously
+    /// referred by it.
+    pub fn insert_file(&self, fd: FileDesc, file: Arc<dyn FileLike>) -> LxResult {
+        if !(0..FILE_LIMIT as i32).contains(&i32::from(fd)) {
+            return Err(LxError::EBADF);
+        }
+        self.inner.lock().files.insert(fd, file);
+        Ok(())
+    }
+
+    /// Get the file referred by `fd`.
+    pub fn get_file(&self, fd: FileDesc) -> LxResult<Arc<dyn FileLike>> {
+        let inner = self.inner.lock();
+        inner.files.get(&fd).cloned().ok_or(LxError::EBADF)
+    }
+
+    /// Close the file descriptor `fd`.
+    pub fn close_file(&self, fd: FileDesc) -> LxResult {
+        let mut inner = self.inner.lock();
+        inner.files.remove(&fd).map(|_| ()).ok_or(LxError::EBADF)
+    }
+
+    /// Get the current working directory.
+    pub fn cwd(&self) -> String {
+        self.inner.lock().cwd.clone()
+    }
+
+    /// Change the current working directory to the directory `path`,
+    /// absolute or relative to the current one.
+    pub fn change_directory(&self, path: &str) -> LxResult {
+        let path = self.absolute_path(FileDesc::CWD, path)?;
+        if self.fs.lookup(&path)?.metadata().type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        self.inner.lock().cwd = path;
+        Ok(())
+    }
+
+    /// Get the normalized absolute path of `path`, which is relative to the
+    /// directory opened as `dirfd` if it is not absolute.
+    ///
+    /// `dirfd` is the current working directory if it is `FileDesc::CWD`.
+    pub fn absolute_path(&self, dirfd: FileDesc, path: &str) -> LxResult<String> {
+        if path.is_empty() {
+            return Err(LxError::ENOENT);
+        }
+        if path.starts_with('/') {
+            return Ok(join_path("/", path));
+        }
+        if dirfd == FileDesc::CWD {
+            return Ok(join_path(&self.inner.lock().cwd, path));
+        }
+        let file = self.get_file(dirfd)?;
+        let dir = file.downcast_ref::<File>().ok_or(LxError::ENOTDIR)?;
+        if dir.inode().metadata().type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        Ok(join_path(dir.path(), path))
+    }
+
+    /// Find the file of `path` relative to `dirfd`, as `absolute_path`.
+    pub fn lookup_inode(&self, dirfd: FileDesc, path: &str) -> LxResult<Arc<dyn INode>> {
+        let path = self.absolute_path(dirfd, path)?;
+        self.fs.lookup(&path)
+    }
+
+    /// Set the program break to `addr`, return the new break.
+    ///
+    /// The heap is allocated in `vmar` on the first call, with the break at
+    /// its start. The pages up to the break are mapped, and unmapped when the
+    /// break moves down. The break is not changed if `addr` is out of the
+    /// heap or the memory is exhausted, and the current one is returned.
+    pub fn brk(&self, vmar: &Arc<VmAddressRegion>, addr: usize) -> usize {
+        let mut inner = self.inner.lock();
+        if inner.heap.is_none() {
+            let flags =
+                VmarFlags::CAN_MAP_READ | VmarFlags::CAN_MAP_WRITE | VmarFlags::CAN_MAP_SPECIFIC;
+            match vmar.allocate(None, HEAP_SIZE, flags, PAGE_SIZE) {
+                Ok(vmar) => {
+                    let brk = vmar.addr();
+                    inner.heap = Some(Heap { vmar, brk });
+                }
+                Err(err) => {
+                    warn!("brk: failed to allocate the heap: {:?}", err);
+                    return 0;
+                }
+            }
+        }
+        let heap = inner.heap.as_mut().unwrap();
+        let start = heap.vmar.addr();
+        if addr < start || addr > start + HEAP_SIZE {
+            return heap.brk;
+        }
+        let old_end = roundup_pages(heap.brk);
+        let new_end = roundup_pages(addr);
+        match new_end.cmp(&old_end) {
+            Ordering::Greater => {
+                let len = new_end - old_end;
+                let vmo = VmObject::new_paged(pages(len));
+                let flags = MMUFlags::READ | MMUFlags::WRITE | MMUFlags::USER;
+                if let Err(err) = heap.vmar.map_at(old_end - start, vmo, 0, len, flags) {
+                    warn!("brk: failed to map the heap: {:?}", err);
+                    return heap.brk;
+                }
+            }
+            Ordering::Less => heap
+                .vmar
+                .unmap(new_end, old_end - new_end)
+                .expect("failed to unmap the heap"),
+            Ordering::Equal => {}
+        }
+        heap.brk = addr;
+        addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::RamInode;
+
+    fn create_proc() -> Arc<Process> {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let fs = Vfs::new(RamInode::new_root());
+        Process::create_linux(&root_job, "proc", fs).unwrap()
+    }
+
+    #[test]
+    fn files() {
+        let proc = create_proc();
+        let linux = proc.linux();
+
+        // the standard input, output and error are opened
+        let stdout = linux.get_file(FileDesc::STDOUT).unwrap();
+        assert!(stdout.downcast_ref::<Stdout>().is_some());
+        assert_eq!(linux.add_file(Arc::new(Stdin)), Ok(3.into()));
+        linux.close_file(0.into()).unwrap();
+        assert_eq!(linux.close_file(0.into()), Err(LxError::EBADF));
+        assert_eq!(linux.get_file(0.into()).err(), Some(LxError::EBADF));
+        assert_eq!(linux.add_file(Arc::new(Stdin)), Ok(0.into()));
+
+        linux.insert_file(10.into(), Arc::new(Stdout)).unwrap();
+        assert!(linux.get_file(10.into()).is_ok());
+        assert_eq!(
+            linux.insert_file(FILE_LIMIT.into(), Arc::new(Stdout)),
+            Err(LxError::EBADF)
+        );
+        assert_eq!(
+            linux.insert_file((-1).into(), Arc::new(Stdout)),
+            Err(LxError::EBADF)
+        );
+    }
+
+    #[test]
+    fn cwd_and_exit() {
+        let proc = create_proc();
+        let linux = proc.linux();
+        let usr = linux
+            .fs()
+            .root()
+            .create("usr", FileType::Dir, 0o755)
+            .unwrap();
+        usr.create("lib", FileType::Dir, 0o755).unwrap();
+        let bin = linux
+            .fs()
+            .root()
+            .create("bin", FileType::Dir, 0o755)
+            .unwrap();
+        bin.create("sh", FileType::File, 0o755).unwrap();
+
+        assert_eq!(linux.cwd(), "/");
+        linux.change_directory("usr/./lib/").unwrap();
+        assert_eq!(linux.cwd(), "/usr/lib");
+        linux.change_directory("../../..").unwrap();
+        assert_eq!(linux.cwd(), "/");
+        linux.change_directory("/bin").unwrap();
+        assert_eq!(linux.cwd(), "/bin");
+        assert_eq!(linux.change_directory(""), Err(LxError::ENOENT));
+        assert_eq!(linux.change_directory("/none"), Err(LxError::ENOENT));
+        assert_eq!(linux.change_directory("sh"), Err(LxError::ENOTDIR));
+        assert_eq!(linux.cwd(), "/bin");
+
+        // paths relative to opened directories
+        let dir = File::new(usr, String::from("/usr"), crate::fs::OpenFlags::RDONLY);
+        let dirfd = linux.add_file(Arc::new(dir)).unwrap();
+        assert_eq!(linux.absolute_path(dirfd, "lib/../x").unwrap(), "/usr/x");
+        assert_eq!(linux.absolute_path(FileDesc::CWD, "sh").unwrap(), "/bin/sh");
+        assert_eq!(linux.absolute_path(dirfd, "/etc").unwrap(), "/etc");
+        assert_eq!(
+            linux.lookup_inode(dirfd, "lib").unwrap().metadata().type_,
+            FileType::Dir
+        );
+        assert_eq!(
+            linux.absolute_path(FileDesc::STDOUT, "x"),
+            Err(LxError::ENOTDIR)
+        );
+        assert_eq!(linux.absolute_path(100.into(), "x"), Err(LxError::EBADF));
+
+        assert_eq!(proc.exit_code(), None);
+        proc.exit_linux(0x1ff);
+        assert_eq!(proc.exit_code(), Some(0xff));
+    }
+
+    #[test]
+    fn brk() {
+        let proc = create_proc();
+        let linux = proc.linux();
+        let vmar = proc.vmar();
+
+        let start = linux.brk(&vmar, 0);
+        assert_ne!(start, 0);
+        assert_eq!(start % PAGE_SIZE, 0);
+        assert_eq!(linux.brk(&vmar, start + 0x1800), start + 0x1800);
+        assert_eq!(linux.brk(&vmar, start + 0x3000), start + 0x3000);
+        // out of the heap
+        assert_eq!(linux.brk(&vmar, start + HEAP_SIZE + 1), start + 0x3000);
+        assert_eq!(linux.brk(&vmar, start - 1), start + 0x3000);
+        // shrink, then grow again
+        assert_eq!(linux.brk(&vmar, start + 0x1000), start + 0x1000);
+        assert_eq!(linux.brk(&vmar, start + 0x4000), start + 0x4000);
+        assert_eq!(linux.brk(&vmar, 0), start + 0x4000);
+    }
+
+    #[test]
+    fn fork() {
+        let proc = create_proc();
+        let linux = proc.linux();
+        let vmar = proc.vmar();
+        linux
+            .fs()
+            .root()
+            .create("bin", FileType::Dir, 0o755)
+            .unwrap();
+        linux.change_directory("/bin").unwrap();
+        linux.insert_file(5.into(), Arc::new(Stdout)).unwrap();
+        let start = linux.brk(&vmar, 0);
+        linux.brk(&vmar, start + 0x1000);
+
+        let child = proc.fork_linux().unwrap();
+        let child_linux = child.linux();
+        assert!(Arc::ptr_eq(&child_linux.parent().unwrap(), &proc));
+        assert!(linux.parent().is_none());
+        assert!(Arc::ptr_eq(&linux.children()[0], &child));
+        assert_eq!(child_linux.cwd(), "/bin");
+        assert_eq!(child_linux.pgid(), proc.id());
+        assert!(Arc::ptr_eq(child_linux.fs(), linux.fs()));
+        assert_eq!(
+            Arc::as_ptr(&child_linux.get_file(5.into()).unwrap()) as *const u8,
+            Arc::as_ptr(&linux.get_file(5.into()).unwrap()) as *const u8
+        );
+        // the heap is copied, and grows separately
+        let child_vmar = child.vmar();
+        assert_eq!(child_linux.brk(&child_vmar, 0), start + 0x1000);
+        assert_eq!(child_linux.brk(&child_vmar, start + 0x2000), start + 0x2000);
+        assert_eq!(linux.brk(&vmar, 0), start + 0x1000);
+
+        // the tables are separate
+        child_linux.close_file(5.into()).unwrap();
+        assert!(linux.get_file(5.into()).is_ok());
+    }
+
+    #[test]
+    fn reap_and_adopt() {
+        let init = create_proc();
+        let parent = init.fork_linux().unwrap();
+        let child = parent.fork_linux().unwrap();
+        let linux = parent.linux();
+
+        assert_eq!(linux.reap_child(WaitTarget::Any), Ok(None));
+        let group = WaitTarget::Group(child.linux().pgid());
+        assert_eq!(linux.reap_child(group), Ok(None));
+        let other = WaitTarget::Group(child.linux().pgid() + 1);
+        assert_eq!(linux.reap_child(other), Err(LxError::ECHILD));
+        assert_eq!(
+            linux.reap_child(WaitTarget::Process(init.id())),
+            Err(LxError::ECHILD)
+        );
+        child.exit_linux(3);
+        assert!(linux
+            .child_exit_event()
+            .signal()
+            .contains(ZxSignal::SIGNALED));
+        assert_eq!(
+            linux.reap_child(WaitTarget::Process(child.id())),
+            Ok(Some((child.id(), 3 << 8)))
+        );
+        assert_eq!(linux.reap_child(WaitTarget::Any), Err(LxError::ECHILD));
+
+        // the orphans are adopted by the init process
+        let orphan = parent.fork_linux().unwrap();
+        parent.kill_by_signal(Signal::SIGKILL);
+        assert!(Arc::ptr_eq(&orphan.linux().parent().unwrap(), &init));
+        let init_linux = init.linux();
+        assert_eq!(
+            init_linux.reap_child(WaitTarget::Process(parent.id())),
+            Ok(Some((parent.id(), 9)))
+        );
+        assert_eq!(init_linux.reap_child(WaitTarget::Any), Ok(None));
+        orphan.exit_linux(0);
+        assert_eq!(
+            init_linux.reap_child(WaitTarget::Any),
+            Ok(Some((orphan.id(), 0)))
+        );
+    }
+}
@@ -0,0 +1,499 @@
+//! Linux signals
+//!
+//! A handler is run by rewriting the saved registers of the thread before it
+//! returns to the user, with a frame pushed to the user stack in the layout
+//! of `struct rt_sigframe` of x86_64, above which the floating point
+//! registers are saved in the layout of `fxsave`. The handler returns to the
+//! restorer, which calls `rt_sigreturn` to restore the registers from the
+//! frame.
+use {
+    crate::error::{LxError, LxResult},
+    bitflags::bitflags,
+    core::{fmt, mem::size_of},
+    kernel_hal::{
+        user::{UserInPtr, UserOutPtr},
+        FpState, GeneralRegs,
+    },
+};
+
+/// The number of signals, including the real-time ones.
+pub const SIGNAL_COUNT: usize = 64;
+
+/// A signal number, from 1 to `SIGNAL_COUNT`.
+#[derive(Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Signal(u8);
+
+#[allow(missing_docs)]
+impl Signal {
+    pub const SIGHUP: Signal = Signal(1);
+    pub const SIGINT: Signal = Signal(2);
+    pub const SIGQUIT: Signal = Signal(3);
+    pub const SIGILL: Signal = Signal(4);
+    pub const SIGTRAP: Signal = Signal(5);
+    pub const SIGABRT: Signal = Signal(6);
+    pub const SIGBUS: Signal = Signal(7);
+    pub const SIGFPE: Signal = Signal(8);
+    pub const SIGKILL: Signal = Signal(9);
+    pub const SIGUSR1: Signal = Signal(10);
+    pub const SIGSEGV: Signal = Signal(11);
+    pub const SIGUSR2: Signal = Signal(12);
+    pub const SIGPIPE: Signal = Signal(13);
+    pub const SIGALRM: Signal = Signal(14);
+    pub const SIGTERM: Signal = Signal(15);
+    pub const SIGSTKFLT: Signal = Signal(16);
+    pub const SIGCHLD: Signal = Signal(17);
+    pub const SIGCONT: Signal = Signal(18);
+    pub const SIGSTOP: Signal = Signal(19);
+    pub const SIGTSTP: Signal = Signal(20);
+    pub const SIGTTIN: Signal = Signal(21);
+    pub const SIGTTOU: Signal = Signal(22);
+    pub const SIGURG: Signal = Signal(23);
+    pub const SIGXCPU: Signal = Signal(24);
+    pub const SIGXFSZ: Signal = Signal(25);
+    pub const SIGVTALRM: Signal = Signal(26);
+    pub const SIGPROF: Signal = Signal(27);
+    pub const SIGWINCH: Signal = Signal(28);
+    pub const SIGIO: Signal = Signal(29);
+    pub const SIGPWR: Signal = Signal(30);
+    pub const SIGSYS: Signal = Signal(31);
+}
+
+impl Signal {
+    /// Get the signal of number `num`, fail with `EINVAL` if it is out of
+    /// range.
+    pub fn new(num: usize) -> LxResult<Self> {
+        match num {
+            1..=SIGNAL_COUNT => Ok(Signal(num as u8)),
+            _ => Err(LxError::EINVAL),
+        }
+    }
+
+    /// Get the signal number.
+    pub fn num(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Whether the signal can be caught, ignored or blocked.
+    ///
+    /// `SIGKILL` and `SIGSTOP` cannot.
+    pub fn catchable(self) -> bool {
+        self != Signal::SIGKILL && self != Signal::SIGSTOP
+    }
+
+    /// Whether the default action of the signal is to ignore it.
+    ///
+    /// The stop signals are ignored too, since there is no job control.
+    pub fn ignored_by_default(self) -> bool {
+        matches!(
+            self,
+            Signal::SIGCHLD
+                | Signal::SIGCONT
+                | Signal::SIGURG
+                | Signal::SIGWINCH
+                | Signal::SIGSTOP
+                | Signal::SIGTSTP
+                | Signal::SIGTTIN
+                | Signal::SIGTTOU
+        )
+    }
+}
+
+impl fmt::Debug for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Signal({})", self.0)
+    }
+}
+
+/// A set of signals, bit `n - 1` for the signal `n`, in the layout of the
+/// kernel `sigset_t`.
+#[repr(transparent)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    /// Whether `signal` is in the set.
+    pub fn contains(self, signal: Signal) -> bool {
+        self.0 & 1 << (signal.num() - 1) != 0
+    }
+
+    /// Add `signal` to the set.
+    pub fn insert(&mut self, signal: Signal) {
+        self.0 |= 1 << (signal.num() - 1);
+    }
+
+    /// Remove `signal` from the set.
+    pub fn remove(&mut self, signal: Signal) {
+        self.0 &= !(1 << (signal.num() - 1));
+    }
+
+    /// Remove `SIGKILL` and `SIGSTOP`, which cannot be blocked.
+    pub fn uncatchable_removed(mut self) -> Self {
+        self.remove(Signal::SIGKILL);
+        self.remove(Signal::SIGSTOP);
+        self
+    }
+
+    /// Get the lowest signal in the set, but not in `mask`.
+    pub fn first_unmasked(self, mask: SigSet) -> Option<Signal> {
+        match self.0 & !mask.0 {
+            0 => None,
+            bits => Some(Signal(bits.trailing_zeros() as u8 + 1)),
+        }
+    }
+}
+
+/// Use the default action.
+pub const SIG_DFL: usize = 0;
+
+/// Ignore the signal.
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    /// The flags of signal actions.
+    #[derive(Default)]
+    pub struct SignalActionFlags: usize {
+        /// Pass the signal information and the user context to the handler.
+        const SIGINFO = 0x4;
+        /// Run the handler on the alternate stack, not supported.
+        const ONSTACK = 0x0800_0000;
+        /// Restart the interrupted syscalls, not supported.
+        const RESTART = 0x1000_0000;
+        /// Do not block the signal in the handler.
+        const NODEFER = 0x4000_0000;
+        /// Reset the action to the default before running the handler.
+        const RESETHAND = 0x8000_0000;
+        /// The restorer is set.
+        const RESTORER = 0x0400_0000;
+    }
+}
+
+/// The action on a signal, in the layout of the kernel `struct sigaction`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct SignalAction {
+    /// The handler, or `SIG_DFL` or `SIG_IGN`.
+    pub handler: usize,
+    /// The flags.
+    pub flags: SignalActionFlags,
+    /// The function the handler returns to, which calls `rt_sigreturn`.
+    pub restorer: usize,
+    /// The signals blocked while running the handler.
+    pub mask: SigSet,
+}
+
+impl SignalAction {
+    /// Whether `signal` is ignored by the action.
+    pub fn ignores(&self, signal: Signal) -> bool {
+        match self.handler {
+            SIG_IGN => signal.catchable(),
+            SIG_DFL => signal.ignored_by_default(),
+            _ => false,
+        }
+    }
+
+    /// Whether `signal` terminates the process by the action.
+    pub fn terminates(&self, signal: Signal) -> bool {
+        !signal.catchable() || self.handler == SIG_DFL && !signal.ignored_by_default()
+    }
+}
+
+/// The information of a signal, in the layout of `siginfo_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _pad: i32,
+    /// The fields depending on the signal, always 0.
+    fields: [u64; 14],
+}
+
+/// The alternate signal stack, in the layout of `stack_t`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct SignalStack {
+    sp: usize,
+    flags: i32,
+    size: usize,
+}
+
+/// The saved registers, in the layout of `struct sigcontext` of x86_64.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct MachineContext {
+    r8: usize,
+    r9: usize,
+    r10: usize,
+    r11: usize,
+    r12: usize,
+    r13: usize,
+    r14: usize,
+    r15: usize,
+    rdi: usize,
+    rsi: usize,
+    rbp: usize,
+    rbx: usize,
+    rdx: usize,
+    rax: usize,
+    rcx: usize,
+    rsp: usize,
+    rip: usize,
+    rflags: usize,
+    /// The segment selectors, the error code, the trap number, the old mask
+    /// and `cr2`, always 0.
+    _unused: [usize; 5],
+    /// The address of the floating point registers saved.
+    fpstate: usize,
+    _reserved: [usize; 8],
+}
+
+/// The user context, in the layout of the kernel `struct ucontext`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+struct SignalUserContext {
+    flags: usize,
+    link: usize,
+    stack: SignalStack,
+    context: MachineContext,
+    mask: SigSet,
+}
+
+/// The frame pushed to the user stack to run a handler, in the layout of
+/// `struct rt_sigframe`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SignalFrame {
+    /// The return address of the handler.
+    restorer: usize,
+    context: SignalUserContext,
+    info: SigInfo,
+}
+
+/// The size of the red zone below the user stack pointer, kept by the
+/// frame.
+const RED_ZONE_SIZE: usize = 128;
+
+/// The flags restored by `rt_sigreturn`: CF, PF, AF, ZF, SF, TF, DF, OF, RF
+/// and AC. The others, like IF and IOPL, are kept.
+const RFLAGS_USER_MASK: usize = 0x50dd5;
+
+/// The alignment of the floating point registers saved.
+const FP_STATE_ALIGN: usize = 64;
+
+/// The signal was sent by `kill`, in `si_code`.
+const SI_USER: i32 = 0;
+
+/// Push a frame to run the `handler` of `signal` on the user stack, and
+/// change `regs` to run it.
+///
+/// The registers, the floating point registers `fp_state` and the signal
+/// `mask` are saved in the frame, to be restored by [`restore_frame`].
+///
+/// As on x86_64 Linux, the handler must have a restorer (`SA_RESTORER`),
+/// which it returns to. Without it, or when the stack has no room for the
+/// frame, `EFAULT` is returned, and the thread should get `SIGSEGV`.
+pub fn setup_frame(
+    regs: &mut GeneralRegs,
+    fp_state: &FpState,
+    signal: Signal,
+    action: &SignalAction,
+    mask: SigSet,
+) -> LxResult {
+    if !action.flags.contains(SignalActionFlags::RESTORER) {
+        return Err(LxError::EFAULT);
+    }
+    let fp_addr = regs
+        .rsp
+        .checked_sub(RED_ZONE_SIZE + size_of::<FpState>())
+        .ok_or(LxError::EFAULT)?
+        & !(FP_STATE_ALIGN - 1);
+    UserOutPtr::<FpState>::from(fp_addr).write(*fp_state)?;
+    let frame = SignalFrame {
+        restorer: action.restorer,
+        context: SignalUserContext {
+            context: MachineContext {
+                r8: regs.r8,
+                r9: regs.r9,
+                r10: regs.r10,
+                r11: regs.r11,
+                r12: regs.r12,
+                r13: regs.r13,
+                r14: regs.r14,
+                r15: regs.r15,
+                rdi: regs.rdi,
+                rsi: regs.rsi,
+                rbp: regs.rbp,
+                rbx: regs.rbx,
+                rdx: regs.rdx,
+                rax: regs.rax,
+                rcx: regs.rcx,
+                rsp: regs.rsp,
+                rip: regs.rip,
+                rflags: regs.rflags,
+                fpstate: fp_addr,
+                ..MachineContext::default()
+            },
+            mask,
+            ..SignalUserContext::default()
+        },
+        info: SigInfo {
+            signo: signal.num() as i32,
+            errno: 0,
+            code: SI_USER,
+            _pad: 0,
+            fields: [0; 14],
+        },
+    };
+    // the restorer is at the place of the return address, so the stack is
+    // aligned as on a function call
+    let sp = fp_addr
+        .checked_sub(size_of::<SignalFrame>() + 8)
+        .ok_or(LxError::EFAULT)?
+        & !0xf;
+    let sp = sp + 8;
+    UserOutPtr::<SignalFrame>::from(sp).write(frame)?;
+    regs.rsp = sp;
+    regs.rip = action.handler;
+    regs.rdi = signal.num();
+    regs.rsi = sp + offset_of_info();
+    regs.rdx = sp + size_of::<usize>();
+    regs.rax = 0;
+    Ok(())
+}
+
+/// The offset of the signal information in the frame.
+fn offset_of_info() -> usize {
+    size_of::<usize>() + size_of::<SignalUserContext>()
+}
+
+/// Restore `regs` from the frame pushed by [`setup_frame`], when the handler
+/// returns to the restorer, return the signal mask and the floating point
+/// registers saved.
+///
+/// The return address is popped, so the user context of the frame is at the
+/// stack pointer. Only the flags the user can change are restored, and the
+/// floating point registers are reset if they are not saved.
+pub fn restore_frame(regs: &mut GeneralRegs) -> LxResult<(SigSet, FpState)> {
+    let uc = UserInPtr::<SignalUserContext>::from(regs.rsp).read()?;
+    let cx = uc.context;
+    let fp_state = match cx.fpstate {
+        0 => FpState::default(),
+        addr => {
+            let mut fp_state = UserInPtr::<FpState>::from(addr).read()?;
+            fp_state.sanitize();
+            fp_state
+        }
+    };
+    regs.r8 = cx.r8;
+    regs.r9 = cx.r9;
+    regs.r10 = cx.r10;
+    regs.r11 = cx.r11;
+    regs.r12 = cx.r12;
+    regs.r13 = cx.r13;
+    regs.r14 = cx.r14;
+    regs.r15 = cx.r15;
+    regs.rdi = cx.rdi;
+    regs.rsi = cx.rsi;
+    regs.rbp = cx.rbp;
+    regs.rbx = cx.rbx;
+    regs.rdx = cx.rdx;
+    regs.rax = cx.rax;
+    regs.rcx = cx.rcx;
+    regs.rsp = cx.rsp;
+    regs.rip = cx.rip;
+    regs.rflags = (regs.rflags & !RFLAGS_USER_MASK) | (cx.rflags & RFLAGS_USER_MASK);
+    Ok((uc.mask.uncatchable_removed(), fp_state))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sigset() {
+        kernel_hal_unix::init();
+        let mut set = SigSet::default();
+        set.insert(Signal::SIGINT);
+        set.insert(Signal::new(64).unwrap());
+        assert!(set.contains(Signal::SIGINT));
+        assert!(!set.contains(Signal::SIGHUP));
+        assert_eq!(set.0, 1 << 1 | 1 << 63);
+        assert_eq!(set.first_unmasked(SigSet::default()), Some(Signal::SIGINT));
+        assert_eq!(set.first_unmasked(SigSet(0b10)).unwrap().num(), 64);
+        assert_eq!(set.first_unmasked(SigSet(!0)), None);
+        set.remove(Signal::SIGINT);
+        assert_eq!(set.0, 1 << 63);
+        assert_eq!(SigSet(!0).uncatchable_removed().0, !(1 << 8 | 1 << 18));
+        assert_eq!(Signal::new(0), Err(LxError::EINVAL));
+        assert_eq!(Signal::new(65), Err(LxError::EINVAL));
+    }
+
+    #[test]
+    fn frame() {
+        kernel_hal_unix::init();
+        assert_eq!(size_of::<SignalUserContext>(), 304);
+        assert_eq!(size_of::<SigInfo>(), 128);
+
+        let mut stack = vec![0usize; 0x100];
+        let top = stack.as_mut_ptr() as usize + 0x800;
+        // the stack on the heap plays the user stack
+        kernel_hal::user::extend_user_space(top);
+        let mut regs = GeneralRegs {
+            rax: 1,
+            rsp: top,
+            rip: 0x1000,
+            r15: 15,
+            rflags: 0x246,
+            ..GeneralRegs::default()
+        };
+        let saved = regs;
+        let action = SignalAction {
+            handler: 0x2000,
+            flags: SignalActionFlags::RESTORER,
+            restorer: 0x3000,
+            ..SignalAction::default()
+        };
+        let mut fp_state = FpState::default();
+        fp_state.0[160] = 1;
+        setup_frame(&mut regs, &fp_state, Signal::SIGUSR1, &action, SigSet(0b11)).unwrap();
+        assert_eq!((regs.rip, regs.rdi, regs.rax), (0x2000, 10, 0));
+        assert_eq!((regs.rsp + 8) % 16, 0);
+        let fp_end = regs.rsp + size_of::<SignalFrame>() + size_of::<FpState>();
+        assert!(fp_end <= top - RED_ZONE_SIZE);
+        let restorer = UserInPtr::<usize>::from(regs.rsp).read().unwrap();
+        assert_eq!(restorer, 0x3000);
+        let signo = UserInPtr::<i32>::from(regs.rsi).read().unwrap();
+        assert_eq!(signo, 10);
+        assert_eq!(regs.rdx, regs.rsp + 8);
+
+        // the handler returns to the restorer, which calls `rt_sigreturn`
+        regs.rsp += 8;
+        regs.r15 = 0;
+        regs.rflags = 0x202;
+        // IOPL and the reserved bits of MXCSR in the frame are not restored
+        let uc = regs.rsp as *mut SignalUserContext;
+        unsafe {
+            (*uc).context.rflags |= 0x3000;
+            let fp = (*uc).context.fpstate as *mut FpState;
+            assert_eq!((*fp).0[160], 1);
+            (*fp).0[24] = 0xff;
+        }
+        let (mask, fp_state) = restore_frame(&mut regs).unwrap();
+        assert_eq!(mask, SigSet(0b11));
+        assert_eq!((fp_state.0[160], fp_state.0[24]), (1, 0xbf));
+        assert_eq!(regs, saved);
+
+        // no restorer to return to, or no room for the frame
+        let no_restorer = SignalAction {
+            flags: SignalActionFlags::empty(),
+            ..action
+        };
+        let ret = setup_frame(&mut regs, &fp_state, Signal::SIGUSR1, &no_restorer, mask);
+        assert_eq!(ret, Err(LxError::EFAULT));
+        regs.rsp = 0x100;
+        let ret = setup_frame(&mut regs, &fp_state, Signal::SIGUSR1, &action, mask);
+        assert_eq!(ret, Err(LxError::EFAULT));
+        assert_eq!(regs.rip, saved.rip);
+    }
+}
@@ -0,0 +1,241 @@
+//! Linux thread
+use {
+    alloc::sync::Arc,
+    core::sync::atomic::AtomicI32,
+    kernel_hal::{
+        user::{UserInPtr, UserOutPtr},
+        FpState,
+    },
+    spin::Mutex,
+    zircon_object::{
+        task::{CurrentThread, Process, Thread},
+        ZxResult,
+    },
+};
+
+use crate::{
+    process::ProcessExt,
+    signal::{setup_frame, SigSet, Signal, SignalActionFlags, SIG_DFL, SIG_IGN},
+};
+
+/// Linux extensions of Zircon threads.
+pub trait ThreadExt {
+    /// Create a new Linux thread in the process `proc`.
+    fn create_linux(proc: &Arc<Process>, name: &str) -> ZxResult<Arc<Self>>;
+
+    /// Get the Linux states of the thread.
+    ///
+    /// Panic if it is not a Linux thread.
+    fn linux(&self) -> &LinuxThread;
+
+    /// Send `signal` to the thread.
+    ///
+    /// The signal is discarded if it is ignored. If its default action is to
+    /// terminate and the thread does not block it, the process is terminated
+    /// at once. Otherwise it is pending until the thread handles it, and the
+    /// thread is interrupted if it is blocking in a syscall.
+    fn send_signal(&self, signal: Signal);
+
+    /// Send `signal` caused by an exception of the thread, like `SIGSEGV`.
+    ///
+    /// Unlike `send_signal`, the process is terminated if the signal is
+    /// ignored or blocked, since the faulting instruction would run again.
+    fn force_signal(&self, signal: Signal);
+}
+
+/// Linux extensions of the current thread.
+pub trait CurrentThreadExt {
+    /// Exit the thread with the Linux exit `code`.
+    ///
+    /// The child thread ID is cleared and the futex on it is woken up, for
+    /// the threads joining this one. The process exits with the code if it
+    /// is the last thread.
+    fn exit_linux(&self, code: i32);
+
+    /// Handle a signal pending on the thread or on the process, which is not
+    /// blocked by the thread.
+    ///
+    /// The default action terminates the process or ignores the signal. A
+    /// handler is run by changing the user context of the thread, with the
+    /// signals in the mask of the action blocked in addition, which are
+    /// unblocked by `rt_sigreturn`. It is called before the thread returns
+    /// to the user from a syscall, the only time a signal is delivered.
+    fn handle_signal(&self);
+}
+
+impl ThreadExt for Thread {
+    fn create_linux(proc: &Arc<Process>, name: &str) -> ZxResult<Arc<Self>> {
+        Thread::create_with_ext(proc, name, LinuxThread::new())
+    }
+
+    fn linux(&self) -> &LinuxThread {
+        self.ext().downcast_ref::<LinuxThread>().unwrap()
+    }
+
+    fn send_signal(&self, signal: Signal) {
+        let proc = self.proc();
+        let action = proc.linux().signal_action(signal);
+        if action.ignores(signal) {
+            return;
+        }
+        let linux = self.linux();
+        let blocked = linux.signal_mask().contains(signal);
+        if action.terminates(signal) && !blocked {
+            proc.kill_by_signal(signal);
+            return;
+        }
+        linux.pending_signals.lock().insert(signal);
+        if !blocked {
+            self.interrupt();
+        }
+    }
+
+    fn force_signal(&self, signal: Signal) {
+        let proc = self.proc();
+        let action = proc.linux().signal_action(signal);
+        if action.ignores(signal) || self.linux().signal_mask().contains(signal) {
+            proc.kill_by_signal(signal);
+            return;
+        }
+        self.send_signal(signal);
+    }
+}
+
+impl CurrentThreadExt for CurrentThread {
+    fn exit_linux(&self, code: i32) {
+        let clear_child_tid = self.linux().clear_child_tid.lock();
+        if !clear_child_tid.is_null() {
+            // the fault is ignored, and the waiters are woken only if the ID
+            // is cleared
+            let mut tid_ptr = UserOutPtr::<i32>::from(clear_child_tid.as_ptr() as usize);
+            match tid_ptr.write(0).and_then(|()| clear_child_tid.as_ref()) {
+                Ok(tid) => {
+                    self.proc().get_futex(tid).wake(1);
+                }
+                Err(err) => warn!("failed to clear the child thread ID: {:?}", err),
+            }
+        }
+        drop(clear_child_tid);
+        let proc = self.proc();
+        if proc.thread_ids().len() == 1 {
+            proc.exit_linux(code);
+        } else {
+            self.exit();
+        }
+    }
+
+    fn handle_signal(&self) {
+        let proc = self.proc();
+        while let Some((signal, action)) = proc.linux().dequeue_signal(self) {
+            match action.handler {
+                SIG_IGN => continue,
+                SIG_DFL if action.ignores(signal) => continue,
+                SIG_DFL => {
+                    proc.kill_by_signal(signal);
+                    return;
+                }
+                _ => {}
+            }
+            info!("handle {:?} by {:#x}", signal, action.handler);
+            let linux = self.linux();
+            let mask = linux.signal_mask();
+            let fp_state = linux.fp_state();
+            if let Err(err) = self
+                .with_context(|cx| setup_frame(&mut cx.general, &fp_state, signal, &action, mask))
+            {
+                warn!("failed to push the signal frame: {:?}", err);
+                proc.kill_by_signal(Signal::SIGSEGV);
+                return;
+            }
+            let mut new_mask = SigSet(mask.0 | action.mask.0);
+            if !action.flags.contains(SignalActionFlags::NODEFER) {
+                new_mask.insert(signal);
+            }
+            linux.set_signal_mask(new_mask);
+            return;
+        }
+    }
+}
+
+/// The Linux states of a thread.
+pub struct LinuxThread {
+    /// The address to clear the thread ID on exit.
+    clear_child_tid: Mutex<UserInPtr<AtomicI32>>,
+    /// The signals blocked.
+    signal_mask: Mutex<SigSet>,
+    /// The signals sent to the thread, not handled yet.
+    pending_signals: Mutex<SigSet>,
+    /// The floating point registers of the user, saved when the thread
+    /// returns from the user.
+    fp_state: Mutex<FpState>,
+}
+
+impl LinuxThread {
+    /// Create the Linux states.
+    pub fn new() -> Self {
+        LinuxThread {
+            clear_child_tid: Mutex::new(0.into()),
+            signal_mask: Mutex::new(SigSet::default()),
+            pending_signals: Mutex::new(SigSet::default()),
+            fp_state: Mutex::new(FpState::default()),
+        }
+    }
+
+    /// Set the address to clear the thread ID on exit.
+    pub fn set_clear_child_tid(&self, tidptr: UserInPtr<AtomicI32>) {
+        *self.clear_child_tid.lock() = tidptr;
+    }
+
+    /// Get the floating point registers saved.
+    pub fn fp_state(&self) -> FpState {
+        *self.fp_state.lock()
+    }
+
+    /// Set the floating point registers to be restored.
+    pub fn set_fp_state(&self, state: FpState) {
+        *self.fp_state.lock() = state;
+    }
+
+    /// Save the floating point registers of the current CPU, after the
+    /// thread returns from the user.
+    pub fn save_fp_state(&self) {
+        self.fp_state.lock().save();
+    }
+
+    /// Load the floating point registers saved into the current CPU, before
+    /// the thread runs in the user.
+    pub fn restore_fp_state(&self) {
+        self.fp_state.lock().restore();
+    }
+
+    /// Get the signals blocked.
+    pub fn signal_mask(&self) -> SigSet {
+        *self.signal_mask.lock()
+    }
+
+    /// Set the signals blocked, except `SIGKILL` and `SIGSTOP`.
+    pub fn set_signal_mask(&self, mask: SigSet) {
+        *self.signal_mask.lock() = mask.uncatchable_removed();
+    }
+
+    /// Whether a signal not blocked is pending on the thread or on the
+    /// process `proc`.
+    pub fn has_signal(&self, proc: &Process) -> bool {
+        let mask = self.signal_mask();
+        self.pending_signals.lock().first_unmasked(mask).is_some() || proc.linux().has_signal(mask)
+    }
+
+    /// Take a signal pending on the thread, not blocked by `mask`.
+    pub(crate) fn dequeue_signal(&self, mask: SigSet) -> Option<Signal> {
+        let mut pending = self.pending_signals.lock();
+        let signal = pending.first_unmasked(mask)?;
+        pending.remove(signal);
+        Some(signal)
+    }
+}
+
+impl Default for LinuxThread {
+    fn default() -> Self {
+        Self::new()
+    }
+}
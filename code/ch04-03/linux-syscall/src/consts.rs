@@ -0,0 +1,83 @@
+//! Linux syscall numbers of x86_64.
+use numeric_enum_macro::numeric_enum;
+
+numeric_enum! {
+#[repr(u32)]
+#[derive(Debug, Eq, PartialEq)]
+#[allow(non_camel_case_types)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum SyscallType {
+    READ = 0,
+    WRITE = 1,
+    OPEN = 2,
+    CLOSE = 3,
+    STAT = 4,
+    FSTAT = 5,
+    LSTAT = 6,
+    POLL = 7,
+    LSEEK = 8,
+    MMAP = 9,
+    MPROTECT = 10,
+    MUNMAP = 11,
+    BRK = 12,
+    RT_SIGACTION = 13,
+    RT_SIGPROCMASK = 14,
+    RT_SIGRETURN = 15,
+    IOCTL = 16,
+    PREAD64 = 17,
+    PWRITE64 = 18,
+    READV = 19,
+    WRITEV = 20,
+    ACCESS = 21,
+    PIPE = 22,
+    SCHED_YIELD = 24,
+    MADVISE = 28,
+    DUP = 32,
+    DUP2 = 33,
+    NANOSLEEP = 35,
+    GETPID = 39,
+    CLONE = 56,
+    FORK = 57,
+    VFORK = 58,
+    EXECVE = 59,
+    EXIT = 60,
+    WAIT4 = 61,
+    KILL = 62,
+    UNAME = 63,
+    FCNTL = 72,
+    GETCWD = 79,
+    CHDIR = 80,
+    MKDIR = 83,
+    RMDIR = 84,
+    UNLINK = 87,
+    READLINK = 89,
+    UMASK = 95,
+    GETUID = 102,
+    GETGID = 104,
+    GETEUID = 107,
+    GETEGID = 108,
+    SETPGID = 109,
+    GETPPID = 110,
+    GETPGRP = 111,
+    SIGALTSTACK = 131,
+    ARCH_PRCTL = 158,
+    GETTID = 186,
+    TKILL = 200,
+    FUTEX = 202,
+    GETDENTS64 = 217,
+    SET_TID_ADDRESS = 218,
+    CLOCK_GETTIME = 228,
+    EXIT_GROUP = 231,
+    TGKILL = 234,
+    OPENAT = 257,
+    MKDIRAT = 258,
+    NEWFSTATAT = 262,
+    UNLINKAT = 263,
+    READLINKAT = 267,
+    FACCESSAT = 269,
+    PPOLL = 271,
+    DUP3 = 292,
+    PIPE2 = 293,
+    PRLIMIT64 = 302,
+}
+}
@@ -0,0 +1,113 @@
+use {
+    super::*,
+    alloc::{string::String, vec::Vec},
+    linux_object::fs::{join_path, File, FileType, SeekFrom},
+};
+
+/// Remove a directory instead of a file, in `unlinkat`.
+const AT_REMOVEDIR: usize = 0x200;
+
+/// The length of `struct linux_dirent64` before the name.
+const DIRENT64_HEADER_LEN: usize = 19;
+
+impl Syscall<'_> {
+    /// Create the directory `path` as `mkdirat` relative to the current
+    /// working directory.
+    pub fn sys_mkdir(&self, path: UserInPtr<u8>, mode: u32) -> SysResult {
+        self.sys_mkdirat(FileDesc::CWD, path, mode)
+    }
+
+    /// Create the directory `path` relative to the directory `dirfd`, with
+    /// the permission bits `mode`.
+    pub fn sys_mkdirat(&self, dirfd: FileDesc, path: UserInPtr<u8>, mode: u32) -> SysResult {
+        let path = path.read_cstring()?;
+        info!(
+            "mkdirat: dirfd={:?}, path={:?}, mode={:#o}",
+            dirfd, path, mode
+        );
+        let proc = self.linux_process();
+        let path = proc.absolute_path(dirfd, &path)?;
+        if proc.fs().lookup(&path).is_ok() {
+            return Err(LxError::EEXIST);
+        }
+        let (dir, name) = proc.fs().lookup_parent(&path)?;
+        dir.create(&name, FileType::Dir, mode & 0o7777)?;
+        Ok(0)
+    }
+
+    /// Remove the directory `path`, which must be empty.
+    pub fn sys_rmdir(&self, path: UserInPtr<u8>) -> SysResult {
+        self.sys_unlinkat(FileDesc::CWD, path, AT_REMOVEDIR)
+    }
+
+    /// Remove the file `path` as `unlinkat` relative to the current working
+    /// directory.
+    pub fn sys_unlink(&self, path: UserInPtr<u8>) -> SysResult {
+        self.sys_unlinkat(FileDesc::CWD, path, 0)
+    }
+
+    /// Remove the file `path` relative to the directory `dirfd`, or the empty
+    /// directory with `AT_REMOVEDIR`.
+    pub fn sys_unlinkat(&self, dirfd: FileDesc, path: UserInPtr<u8>, flags: usize) -> SysResult {
+        let path = path.read_cstring()?;
+        info!(
+            "unlinkat: dirfd={:?}, path={:?}, flags={:#x}",
+            dirfd, path, flags
+        );
+        let proc = self.linux_process();
+        let path = proc.absolute_path(dirfd, &path)?;
+        let is_dir = proc.fs().lookup(&path)?.metadata().type_ == FileType::Dir;
+        match (flags & AT_REMOVEDIR != 0, is_dir) {
+            (true, false) => return Err(LxError::ENOTDIR),
+            (false, true) => return Err(LxError::EISDIR),
+            _ => {}
+        }
+        let (dir, name) = proc.fs().lookup_parent(&path)?;
+        dir.unlink(&name)?;
+        Ok(0)
+    }
+
+    /// Read the entries of the directory `fd` into `buf` of `len` bytes, in
+    /// the layout of `struct linux_dirent64`, return the length filled.
+    ///
+    /// The offset of the file is the index of the next entry, where `.` and
+    /// `..` come first. Return 0 at the end of the directory.
+    pub fn sys_getdents64(&self, fd: FileDesc, mut buf: UserOutPtr<u8>, len: usize) -> SysResult {
+        info!("getdents64: fd={:?}, buf={:?}, len={:#x}", fd, buf, len);
+        let proc = self.linux_process();
+        let file = proc.get_file(fd)?;
+        let dir = file.downcast_ref::<File>().ok_or(LxError::ENOTDIR)?;
+        let parent = proc.fs().lookup(&join_path(dir.path(), ".."))?;
+        let mut entries = vec![
+            (String::from("."), dir.inode().clone()),
+            (String::from(".."), parent),
+        ];
+        entries.extend(dir.inode().list()?);
+
+        let start = dir.seek(SeekFrom::Current(0))?;
+        let mut data = Vec::new();
+        let mut index = start;
+        for (name, inode) in entries.iter().skip(start) {
+            let begin = data.len();
+            let reclen = (DIRENT64_HEADER_LEN + name.len() + 1 + 7) & !7;
+            if begin + reclen > len {
+                break;
+            }
+            let meta = inode.metadata();
+            index += 1;
+            data.extend(&(meta.ino as u64).to_ne_bytes());
+            data.extend(&(index as i64).to_ne_bytes());
+            data.extend(&(reclen as u16).to_ne_bytes());
+            data.push(meta.type_.dirent_type());
+            data.extend(name.bytes());
+            // the name ends with NUL, padded to 8 bytes
+            data.resize(begin + reclen, 0);
+        }
+        if data.is_empty() && start < entries.len() {
+            return Err(LxError::EINVAL);
+        }
+        buf.write_array(&data)?;
+        dir.seek(SeekFrom::Start(index))?;
+        Ok(data.len())
+    }
+}
@@ -0,0 +1,365 @@
+use {
+    super::*,
+    alloc::{boxed::Box, vec::Vec},
+    bitflags::bitflags,
+    core::{future::Future, pin::Pin, time::Duration},
+    futures::future::{pending, select_all},
+    linux_object::{
+        fs::{File, FileLike, FileType, OpenFlags, Pipe, SeekFrom},
+        process::FILE_LIMIT,
+        signal::Signal,
+    },
+    zircon_object::{task::ThreadState, ZxError},
+};
+
+/// Seek from the start of the file.
+const SEEK_SET: u8 = 0;
+/// Seek from the current offset.
+const SEEK_CUR: u8 = 1;
+/// Seek from the end of the file.
+const SEEK_END: u8 = 2;
+
+/// The maximum number of bytes read or written by a syscall, and the rest is
+/// left to the next call as a short read or write.
+const MAX_RW_COUNT: usize = 0x10_0000;
+
+/// The maximum number of I/O vectors.
+const UIO_MAXIOV: usize = 1024;
+
+/// An I/O vector, in the layout of `struct iovec`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoVec {
+    base: usize,
+    len: usize,
+}
+
+impl Syscall<'_> {
+    /// Read from the file `fd` into `base`.
+    ///
+    /// Wait for the data if the file is blocking.
+    pub async fn sys_read(&self, fd: FileDesc, mut base: UserOutPtr<u8>, len: usize) -> SysResult {
+        info!("read: fd={:?}, base={:?}, len={:#x}", fd, base, len);
+        let file = self.linux_process().get_file(fd)?;
+        let mut buf = vec![0u8; len.min(MAX_RW_COUNT)];
+        let len = self.blocking(file.async_read(&mut buf)).await?;
+        base.write_array(&buf[..len])?;
+        Ok(len)
+    }
+
+    /// Write `base` to the file `fd`.
+    ///
+    /// Wait for the space if the file is blocking.
+    pub async fn sys_write(&self, fd: FileDesc, base: UserInPtr<u8>, len: usize) -> SysResult {
+        info!("write: fd={:?}, base={:?}, len={:#x}", fd, base, len);
+        let file = self.linux_process().get_file(fd)?;
+        let buf = base.read_array(len.min(MAX_RW_COUNT))?;
+        self.write_file(&file, &buf).await
+    }
+
+    /// Read from the file `fd` into the buffers of `iov`.
+    pub async fn sys_readv(&self, fd: FileDesc, iov: UserInPtr<IoVec>, count: usize) -> SysResult {
+        info!("readv: fd={:?}, iov={:?}, count={}", fd, iov, count);
+        let iovs = read_iovs(iov, count)?;
+        let file = self.linux_process().get_file(fd)?;
+        let total = iovs.iter().map(|v| v.len).sum::<usize>();
+        let mut buf = vec![0u8; total.min(MAX_RW_COUNT)];
+        let mut len = self.blocking(file.async_read(&mut buf)).await?;
+        let mut data = &buf[..len];
+        for v in iovs.iter() {
+            let n = v.len.min(data.len());
+            UserOutPtr::<u8>::from(v.base).write_array(&data[..n])?;
+            data = &data[n..];
+        }
+        len -= data.len();
+        Ok(len)
+    }
+
+    /// Write the buffers of `iov` to the file `fd`.
+    pub async fn sys_writev(&self, fd: FileDesc, iov: UserInPtr<IoVec>, count: usize) -> SysResult {
+        info!("writev: fd={:?}, iov={:?}, count={}", fd, iov, count);
+        let iovs = read_iovs(iov, count)?;
+        let file = self.linux_process().get_file(fd)?;
+        let mut buf = Vec::new();
+        for v in iovs.iter() {
+            let len = v.len.min(MAX_RW_COUNT - buf.len());
+            buf.extend(UserInPtr::<u8>::from(v.base).read_array(len)?);
+        }
+        self.write_file(&file, &buf).await
+    }
+
+    /// Write `buf` to `file`, waiting for the space if it is blocking.
+    ///
+    /// `SIGPIPE` is sent to the current thread if the file is a pipe whose
+    /// read end is closed.
+    async fn write_file(&self, file: &Arc<dyn FileLike>, buf: &[u8]) -> SysResult {
+        let ret = self.blocking(file.async_write(buf)).await;
+        if ret == Err(LxError::EPIPE) {
+            self.thread.send_signal(Signal::SIGPIPE);
+        }
+        ret
+    }
+
+    /// Read from the `offset` of the file `fd` into `base`, without changing
+    /// the current offset.
+    pub fn sys_pread64(
+        &self,
+        fd: FileDesc,
+        mut base: UserOutPtr<u8>,
+        len: usize,
+        offset: u64,
+    ) -> SysResult {
+        info!(
+            "pread64: fd={:?}, base={:?}, len={:#x}, offset={:#x}",
+            fd, base, len, offset
+        );
+        let file = self.linux_process().get_file(fd)?;
+        let mut buf = vec![0u8; len.min(MAX_RW_COUNT)];
+        let len = file.read_at(offset, &mut buf)?;
+        base.write_array(&buf[..len])?;
+        Ok(len)
+    }
+
+    /// Write `base` to the `offset` of the file `fd`, without changing the
+    /// current offset.
+    pub fn sys_pwrite64(
+        &self,
+        fd: FileDesc,
+        base: UserInPtr<u8>,
+        len: usize,
+        offset: u64,
+    ) -> SysResult {
+        info!(
+            "pwrite64: fd={:?}, base={:?}, len={:#x}, offset={:#x}",
+            fd, base, len, offset
+        );
+        let file = self.linux_process().get_file(fd)?;
+        let buf = base.read_array(len.min(MAX_RW_COUNT))?;
+        file.write_at(offset, &buf)
+    }
+
+    /// Open the file `path` as `openat` relative to the current working
+    /// directory.
+    pub fn sys_open(&self, path: UserInPtr<u8>, flags: usize, mode: u32) -> SysResult {
+        self.sys_openat(FileDesc::CWD, path, flags, mode)
+    }
+
+    /// Open the file `path` relative to the directory `dirfd`, return the new
+    /// file descriptor.
+    ///
+    /// With `CREAT`, a missing file is created with the permission bits
+    /// `mode`.
+    pub fn sys_openat(
+        &self,
+        dirfd: FileDesc,
+        path: UserInPtr<u8>,
+        flags: usize,
+        mode: u32,
+    ) -> SysResult {
+        let path = path.read_cstring()?;
+        let flags = OpenFlags::from_bits_truncate(flags);
+        info!(
+            "openat: dirfd={:?}, path={:?}, flags={:?}, mode={:#o}",
+            dirfd, path, flags, mode
+        );
+        let proc = self.linux_process();
+        let path = proc.absolute_path(dirfd, &path)?;
+        let inode = match proc.fs().lookup(&path) {
+            Ok(inode) => {
+                if flags.contains(OpenFlags::CREAT | OpenFlags::EXCL) {
+                    return Err(LxError::EEXIST);
+                }
+                inode
+            }
+            Err(LxError::ENOENT) if flags.contains(OpenFlags::CREAT) => {
+                let (dir, name) = proc.fs().lookup_parent(&path)?;
+                dir.create(&name, FileType::File, mode & 0o7777)?
+            }
+            Err(err) => return Err(err),
+        };
+        let type_ = inode.metadata().type_;
+        if flags.contains(OpenFlags::DIRECTORY) && type_ != FileType::Dir {
+            return Err(LxError::ENOTDIR);
+        }
+        if type_ == FileType::Dir && flags.writable() {
+            return Err(LxError::EISDIR);
+        }
+        if flags.contains(OpenFlags::TRUNC) && flags.writable() {
+            inode.resize(0)?;
+        }
+        let fd = proc.add_file(Arc::new(File::new(inode, path, flags)))?;
+        Ok(fd.into())
+    }
+
+    /// Move the current offset of the file `fd` by `offset` from the
+    /// position `whence`, return the new offset.
+    pub fn sys_lseek(&self, fd: FileDesc, offset: i64, whence: u8) -> SysResult {
+        info!("lseek: fd={:?}, offset={}, whence={}", fd, offset, whence);
+        let pos = match whence {
+            SEEK_SET => SeekFrom::Start(usize::try_from(offset).map_err(|_| LxError::EINVAL)?),
+            SEEK_CUR => SeekFrom::Current(offset as isize),
+            SEEK_END => SeekFrom::End(offset as isize),
+            _ => return Err(LxError::EINVAL),
+        };
+        let file = self.linux_process().get_file(fd)?;
+        let file = file.downcast_ref::<File>().ok_or(LxError::ESPIPE)?;
+        file.seek(pos)
+    }
+
+    /// Close the file descriptor `fd`.
+    pub fn sys_close(&self, fd: FileDesc) -> SysResult {
+        info!("close: fd={:?}", fd);
+        self.linux_process().close_file(fd)?;
+        Ok(0)
+    }
+
+    /// Control the device of the file `fd`.
+    pub fn sys_ioctl(&self, fd: FileDesc, request: usize, arg: usize) -> SysResult {
+        info!("ioctl: fd={:?}, request={:#x}, arg={:#x}", fd, request, arg);
+        let file = self.linux_process().get_file(fd)?;
+        file.ioctl(request, arg)
+    }
+}
+
+/// Read `count` I/O vectors at `iov`, whose total length must not overflow.
+fn read_iovs(iov: UserInPtr<IoVec>, count: usize) -> LxResult<Vec<IoVec>> {
+    if count > UIO_MAXIOV {
+        return Err(LxError::EINVAL);
+    }
+    let iovs = iov.read_array(count)?;
+    iovs.iter()
+        .try_fold(0usize, |total, v| total.checked_add(v.len))
+        .filter(|&total| total <= isize::MAX as usize)
+        .ok_or(LxError::EINVAL)?;
+    Ok(iovs)
+}
+
+bitflags! {
+    /// The events of `poll`.
+    pub struct PollEvents: u16 {
+        /// There is data to read.
+        const IN = 0x0001;
+        /// Writing will not block.
+        const OUT = 0x0004;
+        /// An error occurred.
+        const ERR = 0x0008;
+        /// The peer hung up.
+        const HUP = 0x0010;
+        /// The file descriptor is not open.
+        const INVAL = 0x0020;
+    }
+}
+
+/// A file descriptor to poll, in the layout of `struct pollfd`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    fd: FileDesc,
+    events: PollEvents,
+    revents: PollEvents,
+}
+
+impl Syscall<'_> {
+    /// Create a pipe, write the file descriptors of the read end and the
+    /// write end to `fds`.
+    pub fn sys_pipe(&self, fds: UserOutPtr<[i32; 2]>) -> SysResult {
+        self.sys_pipe2(fds, 0)
+    }
+
+    /// Create a pipe as `pipe`, with `flags` on the ends.
+    ///
+    /// `CLOEXEC` is accepted, while the file descriptors are always kept on
+    /// `execve`.
+    pub fn sys_pipe2(&self, mut fds: UserOutPtr<[i32; 2]>, flags: usize) -> SysResult {
+        info!("pipe2: fds={:?}, flags={:#x}", fds, flags);
+        let flags = OpenFlags::from_bits(flags)
+            .filter(|flags| (OpenFlags::NONBLOCK | OpenFlags::CLOEXEC).contains(*flags))
+            .ok_or(LxError::EINVAL)?;
+        let (read, write) = Pipe::create(flags);
+        let proc = self.linux_process();
+        let read_fd = proc.add_file(Arc::new(read))?;
+        let write_fd = match proc.add_file(Arc::new(write)) {
+            Ok(fd) => fd,
+            Err(err) => {
+                proc.close_file(read_fd)?;
+                return Err(err);
+            }
+        };
+        fds.write([read_fd.into(), write_fd.into()])?;
+        Ok(0)
+    }
+
+    /// Wait for any of the files in `ufds` to be ready for the events, return
+    /// the number of the files with events.
+    ///
+    /// Wait forever if `timeout_msecs` is negative, or return immediately if
+    /// it is 0. The entries with negative file descriptors are ignored, and
+    /// without any other entry, it only sleeps until the timeout.
+    pub async fn sys_poll(
+        &self,
+        mut ufds: UserInOutPtr<PollFd>,
+        nfds: usize,
+        timeout_msecs: i32,
+    ) -> SysResult {
+        info!(
+            "poll: ufds={:?}, nfds={}, timeout_msecs={}",
+            ufds, nfds, timeout_msecs
+        );
+        if nfds > FILE_LIMIT {
+            return Err(LxError::EINVAL);
+        }
+        let mut polls = ufds.read_array(nfds)?;
+        let proc = self.linux_process();
+        let deadline = match timeout_msecs {
+            t if t < 0 => FOREVER,
+            t => kernel_hal::timer_now() + Duration::from_millis(t as u64),
+        };
+        loop {
+            let mut files = Vec::new();
+            let mut count = 0;
+            for poll in polls.iter_mut() {
+                poll.revents = match proc.get_file(poll.fd) {
+                    _ if i32::from(poll.fd) < 0 => PollEvents::empty(),
+                    Ok(file) => {
+                        let status = file.poll();
+                        files.push(file);
+                        let mut revents = PollEvents::empty();
+                        revents.set(PollEvents::IN, status.read);
+                        revents.set(PollEvents::OUT, status.write);
+                        revents &= poll.events;
+                        revents.set(PollEvents::ERR, status.error);
+                        revents.set(PollEvents::HUP, status.hangup);
+                        revents
+                    }
+                    Err(_) => PollEvents::INVAL,
+                };
+                if !poll.revents.is_empty() {
+                    count += 1;
+                }
+            }
+            if count != 0 || timeout_msecs == 0 {
+                ufds.write_array(&polls)?;
+                return Ok(count);
+            }
+            self.check_signal()?;
+            let future: Pin<Box<dyn Future<Output = _> + Send + '_>> = if files.is_empty() {
+                Box::pin(pending())
+            } else {
+                let future = select_all(files.iter().map(|file| file.async_poll()));
+                Box::pin(async move { Ok(future.await) })
+            };
+            let ret = self
+                .thread
+                .blocking_run(future, ThreadState::Blocked, deadline)
+                .await;
+            match ret {
+                Ok(_) => {}
+                Err(ZxError::TIMED_OUT) => {
+                    ufds.write_array(&polls)?;
+                    return Ok(0);
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
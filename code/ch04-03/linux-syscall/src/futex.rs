@@ -0,0 +1,194 @@
+use {
+    super::*,
+    core::sync::atomic::{AtomicI32, Ordering},
+    kernel_hal::MMUFlags,
+    zircon_object::ZxError,
+};
+
+/// Wait if the futex value is the expected one, with a relative timeout.
+const FUTEX_WAIT: u32 = 0;
+/// Wake some waiters.
+const FUTEX_WAKE: u32 = 1;
+/// Wake some waiters, and move some others to another futex.
+const FUTEX_REQUEUE: u32 = 3;
+/// `FUTEX_REQUEUE` if the futex value is the expected one.
+const FUTEX_CMP_REQUEUE: u32 = 4;
+/// `FUTEX_WAIT` with an absolute timeout and a bitset.
+const FUTEX_WAIT_BITSET: u32 = 9;
+/// `FUTEX_WAKE` with a bitset.
+const FUTEX_WAKE_BITSET: u32 = 10;
+
+/// The futex is only used by the process.
+const FUTEX_PRIVATE_FLAG: u32 = 128;
+/// The absolute timeout is measured by `CLOCK_REALTIME`.
+const FUTEX_CLOCK_REALTIME: u32 = 256;
+
+/// The bitset matching all waiters, the only one supported.
+const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// A time, in the layout of `struct timespec`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TimeSpec {
+    sec: i64,
+    nsec: i64,
+}
+
+impl TimeSpec {
+    /// Convert to a duration, fail with `EINVAL` if it is negative or not
+    /// normalized.
+    fn to_duration(self) -> LxResult<Duration> {
+        if self.sec < 0 || !(0..1_000_000_000).contains(&self.nsec) {
+            return Err(LxError::EINVAL);
+        }
+        Ok(Duration::new(self.sec as u64, self.nsec as u32))
+    }
+}
+
+impl Syscall<'_> {
+    /// Operate on the futex at `uaddr`, as `op`.
+    ///
+    /// The futexes are always private to the process, since the processes
+    /// never share memory. There is no wall clock, so `CLOCK_REALTIME` is the
+    /// monotonic clock. The bitsets other than `FUTEX_BITSET_MATCH_ANY` are
+    /// not supported.
+    pub async fn sys_futex(
+        &self,
+        uaddr: usize,
+        op: u32,
+        val: u32,
+        timeout: usize,
+        uaddr2: usize,
+        val3: u32,
+    ) -> SysResult {
+        info!(
+            "futex: uaddr={:#x}, op={:#x}, val={:#x}, timeout={:#x}, uaddr2={:#x}, val3={:#x}",
+            uaddr, op, val, timeout, uaddr2, val3
+        );
+        let cmd = op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+        if op & FUTEX_CLOCK_REALTIME != 0 && cmd != FUTEX_WAIT && cmd != FUTEX_WAIT_BITSET {
+            return Err(LxError::ENOSYS);
+        }
+        if (cmd == FUTEX_WAIT_BITSET || cmd == FUTEX_WAKE_BITSET) && val3 != FUTEX_BITSET_MATCH_ANY
+        {
+            return Err(if val3 == 0 {
+                LxError::EINVAL
+            } else {
+                LxError::ENOSYS
+            });
+        }
+        let value = self.futex_value(uaddr)?;
+        let timeout = UserInPtr::<TimeSpec>::from(timeout);
+        match cmd {
+            FUTEX_WAIT => {
+                let deadline = match timeout.read_if_not_null()? {
+                    Some(timeout) => kernel_hal::timer_now() + timeout.to_duration()?,
+                    None => FOREVER,
+                };
+                self.futex_wait(value, val as i32, deadline).await
+            }
+            FUTEX_WAIT_BITSET => {
+                let deadline = match timeout.read_if_not_null()? {
+                    Some(timeout) => timeout.to_duration()?,
+                    None => FOREVER,
+                };
+                self.futex_wait(value, val as i32, deadline).await
+            }
+            FUTEX_WAKE | FUTEX_WAKE_BITSET => {
+                let futex = self.zircon_process().get_futex(value);
+                Ok(futex.wake(val as usize))
+            }
+            FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+                // the number to move is passed as the timeout
+                let requeue_count = timeout.as_ptr() as u32 as usize;
+                let requeue = self.futex_value(uaddr2)?;
+                let expected = match cmd {
+                    FUTEX_CMP_REQUEUE => Some(val3 as i32),
+                    _ => None,
+                };
+                let (woken, moved) =
+                    self.futex_requeue(value, val as usize, requeue_count, requeue, expected)?;
+                match cmd {
+                    FUTEX_CMP_REQUEUE => Ok(woken + moved),
+                    _ => Ok(woken),
+                }
+            }
+            _ => Err(LxError::ENOSYS),
+        }
+    }
+
+    /// Wait on the futex of `value` until `deadline` if it is `expected`.
+    async fn futex_wait(
+        &self,
+        value: &'static AtomicI32,
+        expected: i32,
+        deadline: Duration,
+    ) -> SysResult {
+        self.check_signal()?;
+        let futex = self.zircon_process().get_futex(value);
+        let future = futex.wait(expected);
+        futures::pin_mut!(future);
+        let ret = self
+            .thread
+            .blocking_run(future, ThreadState::BlockedFutex, deadline)
+            .await;
+        match ret {
+            Ok(()) => Ok(0),
+            Err(ZxError::BAD_STATE) => Err(LxError::EAGAIN),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Wake at most `wake_count` waiters of the futex of `value`, and move at
+    /// most `requeue_count` others to the futex of `requeue`, return the
+    /// numbers woken and moved.
+    ///
+    /// Fail with `EAGAIN` if the futex value is not `expected`, if any.
+    fn futex_requeue(
+        &self,
+        value: &'static AtomicI32,
+        wake_count: usize,
+        requeue_count: usize,
+        requeue: &'static AtomicI32,
+        expected: Option<i32>,
+    ) -> LxResult<(usize, usize)> {
+        let proc = self.zircon_process();
+        let futex = proc.get_futex(value);
+        loop {
+            let current = value.load(Ordering::SeqCst);
+            if expected.map_or(false, |expected| expected != current) {
+                return Err(LxError::EAGAIN);
+            }
+            if core::ptr::eq(value, requeue) {
+                // the waiters moved to the same futex stay as they are
+                let woken = futex.wake(wake_count);
+                return Ok((woken, futex.waiter_count().min(requeue_count)));
+            }
+            let requeue = proc.get_futex(requeue);
+            match futex.requeue(current, wake_count, requeue_count, &requeue, None) {
+                Ok(counts) => return Ok(counts),
+                // the value changed, check it again
+                Err(ZxError::BAD_STATE) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Get the futex value at `uaddr`, which must be aligned and mapped
+    /// readable in the address space of the process.
+    fn futex_value(&self, uaddr: usize) -> LxResult<&'static AtomicI32> {
+        let ptr = UserInPtr::<AtomicI32>::from(uaddr);
+        if uaddr % core::mem::align_of::<AtomicI32>() != 0 {
+            return Err(LxError::EINVAL);
+        }
+        let flags = self
+            .zircon_process()
+            .vmar()
+            .get_vaddr_flags(uaddr)
+            .map_err(|_| LxError::EFAULT)?;
+        if !flags.contains(MMUFlags::READ) {
+            return Err(LxError::EFAULT);
+        }
+        Ok(ptr.as_ref()?)
+    }
+}
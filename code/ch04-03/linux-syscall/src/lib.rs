@@ -0,0 +1,159 @@
+//! Linux syscall implementations
+
+#![no_std]
+#![deny(warnings, unsafe_code, unused_must_use, unreachable_patterns)]
+
+#[macro_use]
+extern crate alloc;
+
+#[macro_use]
+extern crate log;
+
+use {
+    alloc::sync::Arc,
+    core::{convert::TryFrom, future::Future, time::Duration},
+    kernel_hal::user::*,
+    linux_object::{error::*, fs::FileDesc, process::*, thread::ThreadExt},
+    zircon_object::{
+        object::*,
+        task::{CurrentThread, Process, ThreadFn, ThreadState},
+    },
+};
+
+mod consts;
+mod dir;
+mod file;
+mod futex;
+mod misc;
+mod signal;
+mod stat;
+mod task;
+mod vm;
+
+use consts::SyscallType as Sys;
+
+pub use consts::SyscallType;
+
+/// The result of a syscall, the value returned on success.
+pub type SysResult = LxResult<usize>;
+
+/// The deadline meaning forever.
+const FOREVER: Duration = Duration::from_nanos(i64::max_value() as u64);
+
+pub struct Syscall<'a> {
+    pub thread: &'a CurrentThread,
+    /// The syscall entry filled into the programs to load.
+    pub syscall_entry: usize,
+    pub thread_fn: ThreadFn,
+}
+
+impl Syscall<'_> {
+    pub async fn syscall(&mut self, num: u32, args: [usize; 6]) -> isize {
+        let thread_name = self.thread.name();
+        let proc_name = self.zircon_process().name();
+        let sys_type = match Sys::try_from(num) {
+            Ok(t) => t,
+            Err(_) => {
+                error!("invalid syscall number: {}", num);
+                return -(LxError::ENOSYS as isize);
+            }
+        };
+        debug!(
+            "{}|{} {:?} => args={:x?}",
+            proc_name, thread_name, sys_type, args
+        );
+        let [a0, a1, a2, a3, a4, a5] = args;
+        let ret = match sys_type {
+            Sys::READ => self.sys_read(a0.into(), a1.into(), a2).await,
+            Sys::WRITE => self.sys_write(a0.into(), a1.into(), a2).await,
+            Sys::OPEN => self.sys_open(a0.into(), a1, a2 as _),
+            Sys::CLOSE => self.sys_close(a0.into()),
+            Sys::STAT => self.sys_stat(a0.into(), a1.into()),
+            Sys::FSTAT => self.sys_fstat(a0.into(), a1.into()),
+            Sys::LSTAT => self.sys_lstat(a0.into(), a1.into()),
+            Sys::POLL => self.sys_poll(a0.into(), a1, a2 as _).await,
+            Sys::LSEEK => self.sys_lseek(a0.into(), a1 as _, a2 as _),
+            Sys::MMAP => self.sys_mmap(a0, a1, a2, a3, a4.into(), a5 as _),
+            Sys::MPROTECT => self.sys_mprotect(a0, a1, a2),
+            Sys::MUNMAP => self.sys_munmap(a0, a1),
+            Sys::BRK => self.sys_brk(a0),
+            Sys::RT_SIGACTION => self.sys_rt_sigaction(a0, a1.into(), a2.into(), a3),
+            Sys::RT_SIGPROCMASK => self.sys_rt_sigprocmask(a0 as _, a1.into(), a2.into(), a3),
+            Sys::RT_SIGRETURN => self.sys_rt_sigreturn(),
+            Sys::IOCTL => self.sys_ioctl(a0.into(), a1, a2),
+            Sys::PREAD64 => self.sys_pread64(a0.into(), a1.into(), a2, a3 as _),
+            Sys::PWRITE64 => self.sys_pwrite64(a0.into(), a1.into(), a2, a3 as _),
+            Sys::READV => self.sys_readv(a0.into(), a1.into(), a2).await,
+            Sys::WRITEV => self.sys_writev(a0.into(), a1.into(), a2).await,
+            Sys::PIPE => self.sys_pipe(a0.into()),
+            Sys::GETPID => self.sys_getpid(),
+            Sys::CLONE => self.sys_clone(a0, a1, a2.into(), a3.into(), a4),
+            Sys::FORK => self.sys_fork(),
+            Sys::VFORK => self.sys_vfork(),
+            Sys::EXIT => self.sys_exit(a0 as _),
+            Sys::WAIT4 => self.sys_wait4(a0 as _, a1.into(), a2, a3).await,
+            Sys::KILL => self.sys_kill(a0 as _, a1),
+            Sys::UNAME => self.sys_uname(a0.into()),
+            Sys::GETCWD => self.sys_getcwd(a0.into(), a1),
+            Sys::CHDIR => self.sys_chdir(a0.into()),
+            Sys::MKDIR => self.sys_mkdir(a0.into(), a1 as _),
+            Sys::RMDIR => self.sys_rmdir(a0.into()),
+            Sys::UNLINK => self.sys_unlink(a0.into()),
+            Sys::SETPGID => self.sys_setpgid(a0 as _, a1 as _),
+            Sys::GETPPID => self.sys_getppid(),
+            Sys::GETPGRP => self.sys_getpgrp(),
+            Sys::ARCH_PRCTL => self.sys_arch_prctl(a0 as _, a1),
+            Sys::GETTID => self.sys_gettid(),
+            Sys::TKILL => self.sys_tkill(a0 as _, a1),
+            Sys::FUTEX => self.sys_futex(a0, a1 as _, a2 as _, a3, a4, a5 as _).await,
+            Sys::GETDENTS64 => self.sys_getdents64(a0.into(), a1.into(), a2),
+            Sys::SET_TID_ADDRESS => self.sys_set_tid_address(a0.into()),
+            Sys::EXIT_GROUP => self.sys_exit_group(a0 as _),
+            Sys::TGKILL => self.sys_tgkill(a0 as _, a1 as _, a2),
+            Sys::OPENAT => self.sys_openat(a0.into(), a1.into(), a2, a3 as _),
+            Sys::MKDIRAT => self.sys_mkdirat(a0.into(), a1.into(), a2 as _),
+            Sys::NEWFSTATAT => self.sys_newfstatat(a0.into(), a1.into(), a2.into(), a3),
+            Sys::UNLINKAT => self.sys_unlinkat(a0.into(), a1.into(), a2),
+            Sys::PIPE2 => self.sys_pipe2(a0.into(), a1),
+            _ => {
+                error!("syscall unimplemented: {:?}", sys_type);
+                Err(LxError::ENOSYS)
+            }
+        };
+        debug!("{}|{} {:?} <= {:?}", proc_name, thread_name, sys_type, ret);
+        match ret {
+            Ok(value) => value as isize,
+            Err(err) => -(err as isize),
+        }
+    }
+
+    /// Run the blocking `future` on the current thread, which is interrupted
+    /// if the thread is killed or gets a signal.
+    async fn blocking<T>(&self, future: impl Future<Output = LxResult<T>> + Unpin) -> LxResult<T> {
+        use futures::FutureExt;
+        self.check_signal()?;
+        let future = future.map(Ok);
+        self.thread
+            .blocking_run(future, ThreadState::Blocked, FOREVER)
+            .await?
+    }
+
+    /// Fail with `EINTR` if a signal not blocked is pending on the current
+    /// thread, before blocking it.
+    fn check_signal(&self) -> LxResult {
+        if self.thread.linux().has_signal(self.zircon_process()) {
+            return Err(LxError::EINTR);
+        }
+        Ok(())
+    }
+
+    /// Get the Zircon process of the current thread.
+    fn zircon_process(&self) -> &Arc<Process> {
+        self.thread.proc()
+    }
+
+    /// Get the Linux states of the current process.
+    fn linux_process(&self) -> &LinuxProcess {
+        self.zircon_process().linux()
+    }
+}
@@ -0,0 +1,53 @@
+use super::*;
+
+/// Set the FS base register.
+const ARCH_SET_FS: i32 = 0x1002;
+
+/// The length of each field of `struct utsname`.
+const UTSNAME_FIELD_LEN: usize = 65;
+
+impl Syscall<'_> {
+    /// Set the architecture-specific thread state.
+    ///
+    /// Only `ARCH_SET_FS` is supported, which sets the TLS pointer.
+    pub fn sys_arch_prctl(&self, code: i32, addr: usize) -> SysResult {
+        info!("arch_prctl: code={:#x}, addr={:#x}", code, addr);
+        match code {
+            ARCH_SET_FS => {
+                self.thread.set_fsbase(addr)?;
+                Ok(0)
+            }
+            _ => Err(LxError::EINVAL),
+        }
+    }
+
+    /// Get the names of the system, in the layout of `struct utsname`.
+    pub fn sys_uname(&self, buf: UserOutPtr<u8>) -> SysResult {
+        info!("uname: buf={:?}", buf);
+        let fields = ["Linux", "zcore", "4.0.0", "zCore", "x86_64", "localdomain"];
+        for (i, field) in fields.iter().enumerate() {
+            buf.add(i * UTSNAME_FIELD_LEN).write_cstring(field)?;
+        }
+        Ok(0)
+    }
+
+    /// Get the current working directory into `buf` of `len` bytes, return
+    /// the length of it including the terminating NUL.
+    pub fn sys_getcwd(&self, mut buf: UserOutPtr<u8>, len: usize) -> SysResult {
+        info!("getcwd: buf={:?}, len={:#x}", buf, len);
+        let cwd = self.linux_process().cwd();
+        if cwd.len() + 1 > len {
+            return Err(LxError::ERANGE);
+        }
+        buf.write_cstring(&cwd)?;
+        Ok(cwd.len() + 1)
+    }
+
+    /// Change the current working directory to `path`.
+    pub fn sys_chdir(&self, path: UserInPtr<u8>) -> SysResult {
+        let path = path.read_cstring()?;
+        info!("chdir: path={:?}", path);
+        self.linux_process().change_directory(&path)?;
+        Ok(0)
+    }
+}
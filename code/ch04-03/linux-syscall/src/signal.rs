@@ -0,0 +1,199 @@
+use {
+    super::*,
+    core::mem::size_of,
+    linux_object::signal::{restore_frame, SigSet, Signal, SignalAction},
+    zircon_object::task::Thread,
+};
+
+/// Block the signals in the set, for `rt_sigprocmask`.
+const SIG_BLOCK: i32 = 0;
+/// Unblock the signals in the set.
+const SIG_UNBLOCK: i32 = 1;
+/// Block the signals in the set only.
+const SIG_SETMASK: i32 = 2;
+
+impl Syscall<'_> {
+    /// Get the action on the signal `signum` into `oldact`, and set it to
+    /// `act`, if they are not null.
+    ///
+    /// The flags `ONSTACK` and `RESTART` are ignored: the handler runs on the
+    /// current stack, and the interrupted syscalls fail with `EINTR`.
+    pub fn sys_rt_sigaction(
+        &self,
+        signum: usize,
+        act: UserInPtr<SignalAction>,
+        mut oldact: UserOutPtr<SignalAction>,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigaction: signum={}, act={:?}, oldact={:?}, sigsetsize={}",
+            signum, act, oldact, sigsetsize
+        );
+        if sigsetsize != size_of::<SigSet>() {
+            return Err(LxError::EINVAL);
+        }
+        let signal = Signal::new(signum)?;
+        let linux = self.linux_process();
+        oldact.write_if_not_null(linux.signal_action(signal))?;
+        if let Some(action) = act.read_if_not_null()? {
+            info!("rt_sigaction: {:?} => {:x?}", signal, action);
+            linux.set_signal_action(signal, action)?;
+        }
+        Ok(0)
+    }
+
+    /// Get the signals blocked by the current thread into `oldset`, and
+    /// change them by `set` as `how`, if they are not null.
+    pub fn sys_rt_sigprocmask(
+        &self,
+        how: i32,
+        set: UserInPtr<SigSet>,
+        mut oldset: UserOutPtr<SigSet>,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigprocmask: how={}, set={:?}, oldset={:?}, sigsetsize={}",
+            how, set, oldset, sigsetsize
+        );
+        if sigsetsize != size_of::<SigSet>() {
+            return Err(LxError::EINVAL);
+        }
+        let linux = self.thread.linux();
+        let mask = linux.signal_mask();
+        if let Some(set) = set.read_if_not_null()? {
+            let new_mask = match how {
+                SIG_BLOCK => SigSet(mask.0 | set.0),
+                SIG_UNBLOCK => SigSet(mask.0 & !set.0),
+                SIG_SETMASK => set,
+                _ => return Err(LxError::EINVAL),
+            };
+            linux.set_signal_mask(new_mask);
+        }
+        oldset.write_if_not_null(mask)?;
+        Ok(0)
+    }
+
+    /// Return from a signal handler, restoring the registers and the signals
+    /// blocked before it runs.
+    ///
+    /// It is called by the restorer, which the handler returns to. The
+    /// process is killed by `SIGSEGV` if the signal frame is invalid.
+    pub fn sys_rt_sigreturn(&self) -> SysResult {
+        info!("rt_sigreturn:");
+        let ret = self.thread.with_context(|cx| {
+            let (mask, fp_state) = restore_frame(&mut cx.general)?;
+            Ok((mask, fp_state, cx.general.rax))
+        });
+        match ret {
+            // the restored register is written back as the return value
+            Ok((mask, fp_state, rax)) => {
+                let linux = self.thread.linux();
+                linux.set_signal_mask(mask);
+                linux.set_fp_state(fp_state);
+                Ok(rax)
+            }
+            Err(err) => {
+                warn!("rt_sigreturn: invalid signal frame");
+                self.zircon_process().kill_by_signal(Signal::SIGSEGV);
+                Err(err)
+            }
+        }
+    }
+
+    /// Send the signal `sig` to the process `pid`, or only check it exists if
+    /// `sig` is 0.
+    ///
+    /// `pid` 0 is the process group of the current process, and `-pgid` is
+    /// the process group `pgid`. Sending to all the processes is not
+    /// supported.
+    pub fn sys_kill(&self, pid: isize, sig: usize) -> SysResult {
+        info!("kill: pid={}, sig={}", pid, sig);
+        let signal = to_signal(sig)?;
+        let pgid = match pid {
+            pid if pid > 0 => {
+                let proc = self.find_process(pid as KoID)?;
+                if let Some(signal) = signal {
+                    proc.send_signal(signal);
+                }
+                return Ok(0);
+            }
+            0 => self.linux_process().pgid(),
+            -1 => {
+                warn!("kill: sending to all the processes is not supported");
+                return Err(LxError::ENOSYS);
+            }
+            pid => pid.unsigned_abs() as KoID,
+        };
+        let group = process_group(&self.zircon_process().job(), pgid);
+        if group.is_empty() {
+            return Err(LxError::ESRCH);
+        }
+        if let Some(signal) = signal {
+            for proc in group {
+                proc.send_signal(signal);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Send the signal `sig` to the thread `tid`, or only check it exists if
+    /// `sig` is 0.
+    pub fn sys_tkill(&self, tid: isize, sig: usize) -> SysResult {
+        info!("tkill: tid={}, sig={}", tid, sig);
+        let signal = to_signal(sig)?;
+        if tid <= 0 {
+            return Err(LxError::EINVAL);
+        }
+        let thread = self
+            .zircon_process()
+            .job()
+            .process_ids()
+            .into_iter()
+            .find_map(|pid| self.find_thread(pid, tid as KoID).ok())
+            .ok_or(LxError::ESRCH)?;
+        if let Some(signal) = signal {
+            thread.send_signal(signal);
+        }
+        Ok(0)
+    }
+
+    /// Send the signal `sig` to the thread `tid` of the process `tgid`, or
+    /// only check it exists if `sig` is 0.
+    pub fn sys_tgkill(&self, tgid: isize, tid: isize, sig: usize) -> SysResult {
+        info!("tgkill: tgid={}, tid={}, sig={}", tgid, tid, sig);
+        let signal = to_signal(sig)?;
+        if tgid <= 0 || tid <= 0 {
+            return Err(LxError::EINVAL);
+        }
+        let thread = self.find_thread(tgid as KoID, tid as KoID)?;
+        if let Some(signal) = signal {
+            thread.send_signal(signal);
+        }
+        Ok(0)
+    }
+
+    /// Find the Linux process `pid` in the job of the current process.
+    fn find_process(&self, pid: KoID) -> LxResult<Arc<Process>> {
+        let job = self.zircon_process().job();
+        let proc = job.get_child(pid).map_err(|_| LxError::ESRCH)?;
+        proc.downcast_arc::<Process>()
+            .ok()
+            .filter(|proc| proc.ext().is::<LinuxProcess>())
+            .ok_or(LxError::ESRCH)
+    }
+
+    /// Find the thread `tid` of the Linux process `pid`.
+    fn find_thread(&self, pid: KoID, tid: KoID) -> LxResult<Arc<Thread>> {
+        let proc = self.find_process(pid)?;
+        let thread = proc.get_child(tid).map_err(|_| LxError::ESRCH)?;
+        thread.downcast_arc::<Thread>().map_err(|_| LxError::ESRCH)
+    }
+}
+
+/// Get the signal `sig` to send, or `None` if it is 0.
+fn to_signal(sig: usize) -> LxResult<Option<Signal>> {
+    match sig {
+        0 => Ok(None),
+        sig => Signal::new(sig).map(Some),
+    }
+}
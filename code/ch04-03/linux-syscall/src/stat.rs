@@ -0,0 +1,91 @@
+use {super::*, linux_object::fs::Metadata};
+
+/// Operate on `dirfd` itself if the path is empty.
+const AT_EMPTY_PATH: usize = 0x1000;
+
+/// Do not follow the symbolic link at the end of the path.
+const AT_SYMLINK_NOFOLLOW: usize = 0x100;
+
+/// The size of blocks reported by `stat`.
+const BLOCK_SIZE: usize = 512;
+
+/// The status of a file, in the layout of `struct stat` of x86_64.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    dev: u64,
+    ino: u64,
+    nlink: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    _pad0: u32,
+    rdev: u64,
+    size: u64,
+    blksize: u64,
+    blocks: u64,
+    /// The access, modification and status change time, as pairs of seconds
+    /// and nanoseconds, which are always 0.
+    times: [u64; 6],
+    _unused: [u64; 3],
+}
+
+impl From<Metadata> for Stat {
+    fn from(meta: Metadata) -> Self {
+        Stat {
+            ino: meta.ino as u64,
+            nlink: meta.nlinks as u64,
+            mode: meta.type_.mode_bits() | meta.mode,
+            rdev: meta.rdev as u64,
+            size: meta.size as u64,
+            blksize: BLOCK_SIZE as u64,
+            blocks: ((meta.size + BLOCK_SIZE - 1) / BLOCK_SIZE) as u64,
+            ..Stat::default()
+        }
+    }
+}
+
+impl Syscall<'_> {
+    /// Get the status of the file `path` into `stat`.
+    pub fn sys_stat(&self, path: UserInPtr<u8>, stat: UserOutPtr<Stat>) -> SysResult {
+        self.sys_newfstatat(FileDesc::CWD, path, stat, 0)
+    }
+
+    /// Get the status of the file `path` as `stat`, without following the
+    /// symbolic link at the end.
+    pub fn sys_lstat(&self, path: UserInPtr<u8>, stat: UserOutPtr<Stat>) -> SysResult {
+        self.sys_newfstatat(FileDesc::CWD, path, stat, AT_SYMLINK_NOFOLLOW)
+    }
+
+    /// Get the status of the file `fd` into `stat`.
+    pub fn sys_fstat(&self, fd: FileDesc, mut stat: UserOutPtr<Stat>) -> SysResult {
+        info!("fstat: fd={:?}, stat={:?}", fd, stat);
+        let file = self.linux_process().get_file(fd)?;
+        stat.write(file.metadata().into())?;
+        Ok(0)
+    }
+
+    /// Get the status of the file `path` relative to the directory `dirfd`
+    /// into `stat`.
+    ///
+    /// With `AT_EMPTY_PATH`, an empty path refers to the file `dirfd`.
+    pub fn sys_newfstatat(
+        &self,
+        dirfd: FileDesc,
+        path: UserInPtr<u8>,
+        mut stat: UserOutPtr<Stat>,
+        flags: usize,
+    ) -> SysResult {
+        let path = path.read_cstring()?;
+        info!(
+            "newfstatat: dirfd={:?}, path={:?}, stat={:?}, flags={:#x}",
+            dirfd, path, stat, flags
+        );
+        if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+            return self.sys_fstat(dirfd, stat);
+        }
+        let inode = self.linux_process().lookup_inode(dirfd, &path)?;
+        stat.write(inode.metadata().into())?;
+        Ok(0)
+    }
+}
@@ -0,0 +1,282 @@
+use {
+    super::*,
+    bitflags::bitflags,
+    core::sync::atomic::AtomicI32,
+    futures::FutureExt,
+    kernel_hal::GeneralRegs,
+    linux_object::{process::WaitTarget, thread::*},
+    zircon_object::task::Thread,
+};
+
+bitflags! {
+    /// The flags of `clone`.
+    ///
+    /// The low byte is the signal sent to the parent when the child exits.
+    pub struct CloneFlags: usize {
+        /// The signal sent to the parent when the child exits.
+        const CSIGNAL = 0xff;
+        /// Share the address space.
+        const VM = 1 << 8;
+        /// Share the filesystem information.
+        const FS = 1 << 9;
+        /// Share the file descriptor table.
+        const FILES = 1 << 10;
+        /// Share the signal handlers.
+        const SIGHAND = 1 << 11;
+        /// Suspend the parent until the child execs or exits.
+        const VFORK = 1 << 14;
+        /// Share the parent with the caller.
+        const PARENT = 1 << 15;
+        /// Create a thread in the same process.
+        const THREAD = 1 << 16;
+        /// Share the System V semaphores.
+        const SYSVSEM = 1 << 18;
+        /// Set the TLS of the child.
+        const SETTLS = 1 << 19;
+        /// Write the child thread ID to the parent memory.
+        const PARENT_SETTID = 1 << 20;
+        /// Clear the child thread ID in the child memory on exit.
+        const CHILD_CLEARTID = 1 << 21;
+        /// Write the child thread ID to the child memory.
+        const CHILD_SETTID = 1 << 24;
+    }
+}
+
+bitflags! {
+    /// The options of `wait4`.
+    pub struct WaitOptions: usize {
+        /// Return at once if no child has exited.
+        const NOHANG = 1;
+        /// Also report the stopped children.
+        const UNTRACED = 2;
+        /// Also report the continued children.
+        const CONTINUED = 8;
+    }
+}
+
+impl Syscall<'_> {
+    /// Get the ID of the current process.
+    pub fn sys_getpid(&self) -> SysResult {
+        info!("getpid:");
+        Ok(self.zircon_process().id() as usize)
+    }
+
+    /// Get the ID of the parent process.
+    ///
+    /// A process without a parent gets 0.
+    pub fn sys_getppid(&self) -> SysResult {
+        info!("getppid:");
+        let parent = self.linux_process().parent();
+        Ok(parent.map_or(0, |proc| proc.id() as usize))
+    }
+
+    /// Move the process `pid` to the process group `pgid`.
+    ///
+    /// Only the current process, which is `pid` 0, and its children can be
+    /// moved. The process group 0 is the one of the ID of the process moved.
+    pub fn sys_setpgid(&self, pid: isize, pgid: isize) -> SysResult {
+        info!("setpgid: pid={}, pgid={}", pid, pgid);
+        if pid < 0 || pgid < 0 {
+            return Err(LxError::EINVAL);
+        }
+        let current = self.zircon_process();
+        let proc = match pid as KoID {
+            0 => current.clone(),
+            pid if pid == current.id() => current.clone(),
+            pid => self
+                .linux_process()
+                .children()
+                .into_iter()
+                .find(|child| child.id() == pid)
+                .ok_or(LxError::ESRCH)?,
+        };
+        let pgid = match pgid as KoID {
+            0 => proc.id(),
+            pgid => pgid,
+        };
+        proc.linux().set_pgid(pgid);
+        Ok(0)
+    }
+
+    /// Get the ID of the process group of the current process.
+    pub fn sys_getpgrp(&self) -> SysResult {
+        info!("getpgrp:");
+        Ok(self.linux_process().pgid() as usize)
+    }
+
+    /// Get the ID of the current thread.
+    pub fn sys_gettid(&self) -> SysResult {
+        info!("gettid:");
+        Ok(self.thread.id() as usize)
+    }
+
+    /// Set the address to clear the thread ID on exit, return the ID of the
+    /// current thread.
+    ///
+    /// On exit, the futex on the address is woken up for the joining threads.
+    pub fn sys_set_tid_address(&self, tidptr: UserInPtr<AtomicI32>) -> SysResult {
+        info!("set_tid_address: {:?}", tidptr);
+        self.thread.linux().set_clear_child_tid(tidptr);
+        Ok(self.thread.id() as usize)
+    }
+
+    /// Create a child process with a copy of the current one, return the ID
+    /// of the child.
+    ///
+    /// The child continues from the return of the syscall, getting 0. The
+    /// address space is copied as snapshots of the mapped VMOs.
+    pub fn sys_fork(&self) -> SysResult {
+        info!("fork:");
+        self.fork(CloneFlags::empty(), 0, 0)
+    }
+
+    /// Create a child process as `fork`.
+    ///
+    /// The parent is not suspended, and the child gets a copy of the address
+    /// space instead of sharing it.
+    pub fn sys_vfork(&self) -> SysResult {
+        info!("vfork:");
+        self.fork(CloneFlags::empty(), 0, 0)
+    }
+
+    /// Create a child thread or process, return the ID of the child.
+    ///
+    /// With `THREAD`, which requires `VM` and `SIGHAND`, a thread is created
+    /// in the current process, on the stack `newsp` if it is not 0.
+    /// Otherwise a process is created as `fork`, sharing the address space by
+    /// `VM` is not supported.
+    pub fn sys_clone(
+        &self,
+        flags: usize,
+        newsp: usize,
+        mut parent_tid: UserOutPtr<i32>,
+        child_tid: UserInPtr<AtomicI32>,
+        newtls: usize,
+    ) -> SysResult {
+        let flags = CloneFlags::from_bits_truncate(flags);
+        info!(
+            "clone: flags={:?}, newsp={:#x}, parent_tid={:?}, child_tid={:?}, newtls={:#x}",
+            flags, newsp, parent_tid, child_tid, newtls
+        );
+        if !flags.contains(CloneFlags::THREAD) {
+            if flags.contains(CloneFlags::VM) {
+                warn!("clone: sharing the address space between processes is not supported");
+                return Err(LxError::ENOSYS);
+            }
+            return self.fork(flags, newsp, newtls);
+        }
+        if !flags.contains(CloneFlags::VM | CloneFlags::SIGHAND) {
+            return Err(LxError::EINVAL);
+        }
+        let thread = Thread::create_linux(self.zircon_process(), "")?;
+        let tid = thread.id() as i32;
+        if flags.contains(CloneFlags::PARENT_SETTID) {
+            parent_tid.write(tid)?;
+        }
+        if flags.contains(CloneFlags::CHILD_SETTID) {
+            // the fault is ignored, as the child would do
+            UserOutPtr::<i32>::from(child_tid.as_ptr() as usize)
+                .write(tid)
+                .ok();
+        }
+        if flags.contains(CloneFlags::CHILD_CLEARTID) {
+            thread.linux().set_clear_child_tid(child_tid);
+        }
+        let linux = thread.linux();
+        linux.set_signal_mask(self.thread.linux().signal_mask());
+        linux.set_fp_state(self.thread.linux().fp_state());
+        let regs = self.child_regs(flags, newsp, newtls);
+        thread.start_with_regs(regs, self.thread_fn)?;
+        Ok(tid as usize)
+    }
+
+    /// Create a child process with a copy of the current one, whose main
+    /// thread starts with the registers of the current thread.
+    fn fork(&self, flags: CloneFlags, newsp: usize, newtls: usize) -> SysResult {
+        let proc = self.zircon_process().fork_linux()?;
+        let thread = Thread::create_linux(&proc, &self.thread.name())?;
+        let linux = thread.linux();
+        linux.set_signal_mask(self.thread.linux().signal_mask());
+        linux.set_fp_state(self.thread.linux().fp_state());
+        let regs = self.child_regs(flags, newsp, newtls);
+        thread.start_with_regs(regs, self.thread_fn)?;
+        Ok(proc.id() as usize)
+    }
+
+    /// Get the registers of a child of the current thread, returning 0 from
+    /// the syscall.
+    fn child_regs(&self, flags: CloneFlags, newsp: usize, newtls: usize) -> GeneralRegs {
+        let mut regs = self.thread.with_context(|cx| cx.general);
+        regs.rax = 0;
+        if newsp != 0 {
+            regs.rsp = newsp;
+        }
+        if flags.contains(CloneFlags::SETTLS) {
+            regs.fsbase = newtls;
+        }
+        regs
+    }
+
+    /// Exit the current thread with `code`.
+    ///
+    /// The process exits with the code if it is the last thread.
+    pub fn sys_exit(&self, code: i32) -> SysResult {
+        info!("exit: code={}", code);
+        self.thread.exit_linux(code);
+        Ok(0)
+    }
+
+    /// Exit all threads of the current process with `code`.
+    ///
+    /// The other threads are killed, and the process terminates after all of
+    /// them exit.
+    pub fn sys_exit_group(&self, code: i32) -> SysResult {
+        info!("exit_group: code={}", code);
+        self.zircon_process().exit_linux(code);
+        Ok(0)
+    }
+}
+
+impl Syscall<'_> {
+    /// Wait for a child process to terminate, reap it and return its ID,
+    /// with the wait status written to `wstatus` if it is not null.
+    ///
+    /// `pid` of -1 means any child, 0 the children in the process group of
+    /// the current process, and `-pgid` the children in the process group
+    /// `pgid`. With `NOHANG`, 0 is returned if no child terminated.
+    /// The children are never stopped, and `rusage` is not filled.
+    pub async fn sys_wait4(
+        &self,
+        pid: i32,
+        mut wstatus: UserOutPtr<i32>,
+        options: usize,
+        rusage: usize,
+    ) -> SysResult {
+        let options = WaitOptions::from_bits_truncate(options);
+        info!(
+            "wait4: pid={}, wstatus={:?}, options={:?}, rusage={:#x}",
+            pid, wstatus, options, rusage
+        );
+        let linux = self.linux_process();
+        let target = match pid {
+            -1 => WaitTarget::Any,
+            0 => WaitTarget::Group(linux.pgid()),
+            pid if pid > 0 => WaitTarget::Process(pid as KoID),
+            pgid => WaitTarget::Group(pgid.unsigned_abs() as KoID),
+        };
+        let event: Arc<dyn KernelObject> = linux.child_exit_event();
+        loop {
+            // cleared before checking, not to miss a child terminating after
+            event.signal_clear(Signal::SIGNALED);
+            if let Some((id, status)) = linux.reap_child(target)? {
+                wstatus.write_if_not_null(status)?;
+                return Ok(id as usize);
+            }
+            if options.contains(WaitOptions::NOHANG) {
+                return Ok(0);
+            }
+            let future = event.wait_signal(Signal::SIGNALED).map(|_| Ok(()));
+            self.blocking(future).await?;
+        }
+    }
+}
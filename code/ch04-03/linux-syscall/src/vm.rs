@@ -0,0 +1,172 @@
+use {
+    super::*,
+    bitflags::bitflags,
+    kernel_hal::MMUFlags,
+    linux_object::fs::FileDesc,
+    zircon_object::{vm::*, ZxError},
+};
+
+bitflags! {
+    /// The protection of memory mappings.
+    pub struct MmapProt: usize {
+        /// The pages can be read.
+        const READ = 1 << 0;
+        /// The pages can be written.
+        const WRITE = 1 << 1;
+        /// The pages can be executed.
+        const EXEC = 1 << 2;
+    }
+}
+
+bitflags! {
+    /// The flags of memory mappings.
+    pub struct MmapFlags: usize {
+        /// Share the changes with other mappings of the same pages.
+        const SHARED = 1 << 0;
+        /// Keep the changes private, copy on write.
+        const PRIVATE = 1 << 1;
+        /// Place the mapping exactly at the address.
+        const FIXED = 1 << 4;
+        /// The mapping is not backed by any file.
+        const ANONYMOUS = 1 << 5;
+    }
+}
+
+impl MmapProt {
+    fn to_flags(self) -> MMUFlags {
+        let mut flags = MMUFlags::USER;
+        if self.contains(MmapProt::READ) {
+            flags |= MMUFlags::READ;
+        }
+        if self.contains(MmapProt::WRITE) {
+            flags |= MMUFlags::WRITE;
+        }
+        if self.contains(MmapProt::EXEC) {
+            flags |= MMUFlags::EXECUTE;
+        }
+        flags
+    }
+}
+
+impl Syscall<'_> {
+    /// Map `len` bytes of the file `fd` from `offset`, or anonymous memory,
+    /// return the address of the mapping.
+    ///
+    /// The address is a hint unless `MAP_FIXED`, where the pages mapped in
+    /// the range are replaced, even in the sub-regions such as the one of the
+    /// program image. The file content is copied into the
+    /// mapping, so that only private file mappings are supported.
+    pub fn sys_mmap(
+        &self,
+        addr: usize,
+        len: usize,
+        prot: usize,
+        flags: usize,
+        fd: FileDesc,
+        offset: u64,
+    ) -> SysResult {
+        let prot = MmapProt::from_bits_truncate(prot);
+        let flags = MmapFlags::from_bits_truncate(flags);
+        info!(
+            "mmap: addr={:#x}, len={:#x}, prot={:?}, flags={:?}, fd={:?}, offset={:#x}",
+            addr, len, prot, flags, fd, offset
+        );
+        if len == 0
+            || flags.contains(MmapFlags::SHARED) == flags.contains(MmapFlags::PRIVATE)
+            || !page_aligned(offset as usize)
+        {
+            return Err(LxError::EINVAL);
+        }
+        let len = len.checked_add(PAGE_SIZE - 1).ok_or(LxError::ENOMEM)? & !(PAGE_SIZE - 1);
+        let vmar = self.zircon_process().vmar();
+        // a fixed mapping is placed into the innermost region containing it
+        let (vmar, vmar_offset) = if flags.contains(MmapFlags::FIXED) {
+            let end = addr.checked_add(len).ok_or(LxError::EINVAL)?;
+            if !page_aligned(addr) {
+                return Err(LxError::EINVAL);
+            }
+            let region = vmar.find_region(addr, end).ok_or(LxError::EINVAL)?;
+            let offset = addr - region.addr();
+            (region, Some(offset))
+        } else {
+            (vmar, None)
+        };
+        let vmo = VmObject::new_paged(pages(len));
+        if !flags.contains(MmapFlags::ANONYMOUS) {
+            if flags.contains(MmapFlags::SHARED) {
+                return Err(LxError::ENODEV);
+            }
+            offset.checked_add(len as u64).ok_or(LxError::EOVERFLOW)?;
+            let file = self.linux_process().get_file(fd)?;
+            // read page by page until the end of the file
+            let mut buf = [0u8; PAGE_SIZE];
+            for vmo_offset in (0..len).step_by(PAGE_SIZE) {
+                let read = file.read_at(offset + vmo_offset as u64, &mut buf)?;
+                vmo.write(vmo_offset, &buf[..read])?;
+                if read < PAGE_SIZE {
+                    break;
+                }
+            }
+        }
+        // the pages mapped in the range are replaced only if the new mapping
+        // is valid
+        let overwrite = vmar_offset.is_some();
+        let addr = vmar
+            .map_ext(
+                vmar_offset,
+                vmo,
+                0,
+                len,
+                MMUFlags::RXW,
+                prot.to_flags(),
+                overwrite,
+                true,
+            )
+            .map_err(|err| match err {
+                ZxError::NO_MEMORY | ZxError::INVALID_ARGS => LxError::ENOMEM,
+                err => err.into(),
+            })?;
+        Ok(addr)
+    }
+
+    /// Unmap the pages in `[addr, addr + len)`, even in the sub-regions.
+    pub fn sys_munmap(&self, addr: usize, len: usize) -> SysResult {
+        info!("munmap: addr={:#x}, len={:#x}", addr, len);
+        if !page_aligned(addr) || len == 0 {
+            return Err(LxError::EINVAL);
+        }
+        self.zircon_process()
+            .vmar()
+            .unmap_nested(addr, roundup_pages(len))?;
+        Ok(0)
+    }
+
+    /// Change the protection of the pages in `[addr, addr + len)`, even in
+    /// the sub-regions.
+    pub fn sys_mprotect(&self, addr: usize, len: usize, prot: usize) -> SysResult {
+        let prot = MmapProt::from_bits_truncate(prot);
+        info!(
+            "mprotect: addr={:#x}, len={:#x}, prot={:?}",
+            addr, len, prot
+        );
+        if !page_aligned(addr) {
+            return Err(LxError::EINVAL);
+        }
+        self.zircon_process()
+            .vmar()
+            .protect_nested(addr, roundup_pages(len), prot.to_flags())
+            .map_err(|err| match err {
+                ZxError::NOT_FOUND => LxError::ENOMEM,
+                err => err.into(),
+            })?;
+        Ok(0)
+    }
+
+    /// Set the program break to `addr`, return the new break, or the current
+    /// one on failure.
+    pub fn sys_brk(&self, addr: usize) -> SysResult {
+        info!("brk: addr={:#x}", addr);
+        let vmar = self.zircon_process().vmar();
+        Ok(self.linux_process().brk(&vmar, addr))
+    }
+}
@@ -0,0 +1,122 @@
+//! Run userboot from a ZBI on the LibOS, and exit with the return code of it.
+
+#![deny(warnings, unused_must_use)]
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use structopt::StructOpt;
+use zircon_loader::*;
+use zircon_object::object::*;
+use zircon_object::task::{try_dump_task_tree, Job, Process, Status};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "zcore-runner")]
+struct Opt {
+    /// The ZBI to boot, with userboot and the vDSO in its BOOTFS.
+    #[structopt(parse(from_os_str))]
+    zbi: PathBuf,
+    /// The userboot to run instead of the one in the ZBI.
+    #[structopt(long, parse(from_os_str))]
+    userboot: Option<PathBuf>,
+    /// The vDSO to map instead of the one in the ZBI.
+    #[structopt(long, parse(from_os_str))]
+    vdso: Option<PathBuf>,
+    /// The kernel command line, with options separated by `:`.
+    #[structopt(long, default_value = "")]
+    cmdline: String,
+    /// Run the kernel shell on the standard input and output.
+    #[structopt(long)]
+    shell: bool,
+    /// The log level, overriding `RUST_LOG`.
+    #[structopt(long)]
+    log: Option<log::LevelFilter>,
+}
+
+#[async_std::main]
+async fn main() {
+    let opt = Opt::from_args();
+    kernel_hal_unix::init();
+    init_logger(opt.log);
+    init_panic_hook();
+
+    let images = open_images(&opt).unwrap_or_else(|err| {
+        log::error!("failed to read images: {}", err);
+        std::process::exit(1);
+    });
+    let options = BootOptions {
+        cmdline: opt.cmdline.clone(),
+        shell: opt.shell,
+        ..Default::default()
+    };
+    let proc = run_userboot(&images, &options).unwrap_or_else(|err| {
+        log::error!("failed to run userboot: {}", err);
+        std::process::exit(1);
+    });
+    drop(images);
+
+    let object: Arc<dyn KernelObject> = proc.clone();
+    object.wait_signal(Signal::PROCESS_TERMINATED).await;
+    std::process::exit(exit_code(&proc));
+}
+
+/// Read the ZBI, and userboot and the vDSO if they are given.
+fn open_images(opt: &Opt) -> std::io::Result<Images<Vec<u8>>> {
+    let read_optional = |path: &Option<PathBuf>| path.as_ref().map(std::fs::read).transpose();
+    Ok(Images {
+        zbi: std::fs::read(&opt.zbi)?,
+        userboot: read_optional(&opt.userboot)?,
+        vdso: read_optional(&opt.vdso)?,
+    })
+}
+
+/// Get the exit code of the terminated process, truncated as the shell does.
+fn exit_code(proc: &Process) -> i32 {
+    match proc.status() {
+        Status::Exited(retcode) => retcode as i32,
+        status => unreachable!("process not terminated: {:?}", status),
+    }
+}
+
+fn init_logger(level: Option<log::LevelFilter>) {
+    let mut builder = env_logger::builder();
+    if let Some(level) = level {
+        builder.filter_level(level);
+    }
+    builder
+        .format(|buf, record| {
+            use env_logger::fmt::Color;
+            use log::Level;
+            use std::io::Write;
+
+            let (tid, pid) = kernel_hal::Thread::get_tid();
+            let mut style = buf.style();
+            match record.level() {
+                Level::Trace => style.set_color(Color::Black).set_intense(true),
+                Level::Debug => style.set_color(Color::White),
+                Level::Info => style.set_color(Color::Green),
+                Level::Warn => style.set_color(Color::Yellow),
+                Level::Error => style.set_color(Color::Red).set_bold(true),
+            };
+            zircon_object::debuglog::kernel_log(record.level(), tid, pid, record.args());
+            let now = kernel_hal_unix::timer_now();
+            let level = style.value(record.level());
+            let args = record.args();
+            writeln!(buf, "[{:?} {:>5} {}:{}] {}", now, level, pid, tid, args)
+        })
+        .init();
+}
+
+/// Log the panic and save the kernel log as the crashlog of the next run,
+/// then dump the tasks.
+fn init_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log::error!("{}", info);
+        save_crashlog();
+        // the tasks locked, maybe by the panicking thread, are skipped
+        if let Some(dump) = Job::system_root().and_then(|job| try_dump_task_tree(&job)) {
+            log::error!("tasks:\n{}", dump);
+        }
+        default_hook(info);
+    }));
+}
@@ -0,0 +1,106 @@
+//! Conformance tests of the status codes returned by syscalls.
+//!
+//! The tests in `tests/` drive syscalls through the dispatcher of
+//! `zircon-syscall`, as a user program does, and check the status codes of
+//! the documented error cases against the Zircon documentation.
+//!
+//! User pointers are ordinary pointers of the test, as in the libos mode.
+
+#![deny(warnings)]
+
+use {
+    core::{future::Future, pin::Pin},
+    lazy_static::lazy_static,
+    std::sync::{Arc, Mutex},
+    zircon_object::{object::*, task::*},
+    zircon_syscall::{Syscall, SyscallType},
+};
+
+pub use zircon_object::ZxError;
+
+lazy_static! {
+    /// The current thread passed to `take_current`, by `Thread::start`.
+    static ref CURRENT: Mutex<Option<CurrentThread>> = Mutex::new(None);
+    /// Serialize starting threads, until `CURRENT` is taken.
+    static ref START: Mutex<()> = Mutex::new(());
+}
+
+/// The thread function which keeps the `CurrentThread` for the caller of
+/// `Thread::start`, instead of running it.
+fn take_current(thread: CurrentThread) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+    *CURRENT.lock().unwrap() = Some(thread);
+    Box::pin(async {})
+}
+
+/// A process with a thread issuing syscalls.
+pub struct Env {
+    /// The process issuing syscalls.
+    pub proc: Arc<Process>,
+    thread: CurrentThread,
+}
+
+impl Env {
+    /// Create a process in a new child job of the root job, with a started
+    /// thread.
+    pub fn new() -> Self {
+        kernel_hal_unix::init();
+        let job = Job::root().create_child().unwrap();
+        Self::with_job(&job)
+    }
+
+    /// Create a process in `job`, with a started thread.
+    pub fn with_job(job: &Arc<Job>) -> Self {
+        kernel_hal_unix::init();
+        // the memory of the test, anywhere in the lower half, is user memory
+        kernel_hal_unix::user::extend_user_space(1 << 47);
+        let proc = Process::create(job, "conformance").unwrap();
+        let thread = Thread::create(&proc, "main").unwrap();
+        let _guard = START.lock().unwrap();
+        thread.start(0, 0, 0, 0, take_current).unwrap();
+        let thread = CURRENT.lock().unwrap().take().unwrap();
+        Env { proc, thread }
+    }
+
+    /// Issue a syscall, return the status or the value returned.
+    pub async fn call(&self, sys: SyscallType, args: &[usize]) -> isize {
+        let mut full = [0; 8];
+        full[..args.len()].copy_from_slice(args);
+        let mut syscall = Syscall {
+            thread: &self.thread,
+            thread_fn: take_current,
+        };
+        syscall.syscall(sys as u32, full).await
+    }
+
+    /// Issue a syscall, and check that it returns the `expected` status.
+    pub async fn expect(&self, sys: SyscallType, args: &[usize], expected: ZxError) {
+        let name = format!("{:?}", sys);
+        let status = self.call(sys, args).await;
+        assert_eq!(
+            status, expected as isize,
+            "{}{:x?} should return {:?}",
+            name, args, expected
+        );
+    }
+
+    /// Add a handle of `object` with `rights` to the process.
+    pub fn add(&self, object: Arc<dyn KernelObject>, rights: Rights) -> HandleValue {
+        self.proc.add_handle(Handle::new(object, rights))
+    }
+}
+
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Get the address of `value`, passed as a user pointer.
+pub fn ptr<T>(value: &T) -> usize {
+    value as *const T as usize
+}
+
+/// Get the address of `value`, passed as a mutable user pointer.
+pub fn ptr_mut<T: ?Sized>(value: &mut T) -> usize {
+    value as *mut T as *mut u8 as usize
+}
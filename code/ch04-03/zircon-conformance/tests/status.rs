@@ -0,0 +1,428 @@
+//! The documented error cases of syscalls, grouped by the kind of objects.
+//!
+//! Each syscall dispatched by `zircon-syscall` falls in one of the classes:
+//!
+//! - Checked through their error cases: `HANDLE_*`, `CHANNEL_CREATE`,
+//!   `CHANNEL_READ`, `CHANNEL_WRITE`, `VMO_READ`, `VMO_WRITE`,
+//!   `PAGER_QUERY_DIRTY_RANGES`, `OBJECT_GET_PROPERTY`, `PORT_CREATE`,
+//!   `SOCKET_CREATE`, `SOCKET_WRITE`, `TIMER_CREATE`, `TIMER_SET`, `CPRNG_*`,
+//!   `NANOSLEEP`, `CLOCK_GET_MONOTONIC_VIA_KERNEL`, `DEBUG_SEND_COMMAND`,
+//!   `SYSTEM_POWERCTL` and `SYSTEM_GET_EVENT`.
+//! - Checked through the bad handle and wrong type cases only, by
+//!   `bad_handle_and_type`: the syscalls on a handle listed there, whose
+//!   other error cases need the object in a particular state.
+//! - Not checked, needing devices or resources absent on the host:
+//!   `BLOCK_*`, `BTI_CREATE`, `BTI_PIN`, `ETH_BIND_PORT`, `ETH_SET_RINGS`,
+//!   `ETH_GET_NTH_DEVICE`, `FRAMEBUFFER_*`, `GUEST_*`, `VCPU_*`,
+//!   `INTERRUPT_BIND`, `INTERRUPT_CREATE`, `INTERRUPT_TRIGGER`,
+//!   `INTERRUPT_WAIT`, `IOMMU_CREATE`, `IOPORTS_*`, `PCI_*`, `SMC_CALL`,
+//!   `VMO_CREATE_PHYSICAL` and `RESOURCE_CREATE`.
+//! - Not checked, needing user memory mapped in the VMAR of the process or
+//!   a thread running in user mode: `FUTEX_*`, `VMAR_MAP`,
+//!   `CHANNEL_CALL_NORETRY`, `CHANNEL_READ_ETC`, `CHANNEL_WRITE_ETC`,
+//!   `VMO_REPLACE_AS_EXECUTABLE`, `PROCESS_EXIT`, `DEBUG_READ` and
+//!   `DEBUG_WRITE`.
+//! - Not checked, their error cases being those of their objects, which
+//!   are covered by the unit tests of `zircon-object`:
+//!   `CLOCK_CREATE`, `CLOCK_GET`, `TICKS_GET_VIA_KERNEL`, `DEBUGLOG_CREATE`,
+//!   `DEBUGLOG_WRITE`, `FIFO_CREATE`, `OBJECT_GET_INFO`,
+//!   `OBJECT_SET_PROPERTY`, `OBJECT_WAIT_ASYNC`, `PAGER_CREATE` and
+//!   `PROFILE_CREATE`.
+
+use {
+    zircon_conformance::*,
+    zircon_object::{dev::*, ipc::*, object::*, signal::*, task::*, vm::*},
+    zircon_syscall::SyscallType as Sys,
+    ZxError::*,
+};
+
+/// Get the handle value pointed by the output argument.
+fn handle(value: &HandleValue) -> usize {
+    *value as usize
+}
+
+#[async_std::test]
+async fn handle_ops() {
+    let env = Env::new();
+    let event = env.add(Event::new(), Rights::DEFAULT_EVENT);
+    let no_dup = env.add(Event::new(), Rights::BASIC - Rights::DUPLICATE);
+    let mut out: HandleValue = 0;
+    let out_ptr = ptr_mut(&mut out);
+
+    env.expect(Sys::HANDLE_CLOSE, &[0], OK).await;
+    env.expect(
+        Sys::HANDLE_DUPLICATE,
+        &[no_dup as _, !0, out_ptr],
+        ACCESS_DENIED,
+    )
+    .await;
+    let rights = (Rights::DEFAULT_EVENT | Rights::WRITE).bits() as usize;
+    env.expect(
+        Sys::HANDLE_DUPLICATE,
+        &[event as _, rights, out_ptr],
+        INVALID_ARGS,
+    )
+    .await;
+    env.expect(
+        Sys::HANDLE_DUPLICATE,
+        &[event as _, 1 << 31, 0],
+        INVALID_ARGS,
+    )
+    .await;
+    env.expect(Sys::HANDLE_DUPLICATE, &[event as _, 1 << 31, out_ptr], OK)
+        .await;
+
+    env.expect(Sys::HANDLE_CLOSE, &[handle(&out)], OK).await;
+    env.expect(Sys::HANDLE_CLOSE, &[handle(&out)], BAD_HANDLE)
+        .await;
+    env.expect(
+        Sys::HANDLE_REPLACE,
+        &[handle(&out), !0, out_ptr],
+        BAD_HANDLE,
+    )
+    .await;
+    let closed = [event, out];
+    env.expect(Sys::HANDLE_CLOSE_MANY, &[ptr(&closed), 2], BAD_HANDLE)
+        .await;
+}
+
+#[async_std::test]
+async fn bad_handle_and_type() {
+    let env = Env::new();
+    let mut buf = [0u8; 64];
+    let buf = ptr_mut(&mut buf);
+    const BAD: usize = 0x13;
+    let calls = |h: usize| {
+        vec![
+            (Sys::BTI_RELEASE_QUARANTINE, vec![h]),
+            (Sys::CLOCK_READ, vec![h, buf]),
+            (Sys::CLOCK_GET_DETAILS, vec![h, 1 << 58, buf]),
+            (Sys::CLOCK_UPDATE, vec![h, 1 << 58, buf]),
+            (Sys::DEBUGLOG_READ, vec![h, 0, buf, 64]),
+            (Sys::ETH_GET_STATUS, vec![h, buf]),
+            (Sys::ETH_TX_KICK, vec![h, buf]),
+            (Sys::FIFO_READ, vec![h, 1, buf, 1, 0]),
+            (Sys::FIFO_WRITE, vec![h, 1, buf, 1, 0]),
+            (Sys::INTERRUPT_ACK, vec![h]),
+            (Sys::INTERRUPT_DESTROY, vec![h]),
+            (Sys::OBJECT_SET_PROFILE, vec![h, BAD, 0]),
+            (Sys::PAGER_CREATE_VMO, vec![h, 0, BAD, 0, 0x1000, buf]),
+            (Sys::PAGER_DETACH_VMO, vec![h, BAD]),
+            (Sys::PAGER_OP_RANGE, vec![h, 1, BAD, 0, 0x1000, 0]),
+            (Sys::PMT_UNPIN, vec![h]),
+            (Sys::PORT_QUEUE, vec![h, buf]),
+            (Sys::PORT_WAIT, vec![h, 0, buf]),
+            (Sys::SOCKET_READ, vec![h, 0, buf, 1, 0]),
+            (Sys::SOCKET_SHUTDOWN, vec![h, 0]),
+            (Sys::TIMER_CANCEL, vec![h]),
+            (Sys::VMAR_OP_RANGE, vec![h, 1, 0, 0x1000, 0, 0]),
+        ]
+    };
+    for (sys, args) in calls(BAD) {
+        env.expect(sys, &args, BAD_HANDLE).await;
+    }
+    // a new event for each call, as `PMT_UNPIN` consumes the handle
+    for i in 0..calls(0).len() {
+        let event = env.add(Event::new(), Rights::DEFAULT_EVENT);
+        let (sys, args) = calls(event as _).swap_remove(i);
+        env.expect(sys, &args, WRONG_TYPE).await;
+    }
+}
+
+#[async_std::test]
+async fn channel() {
+    let env = Env::new();
+    let (mut end0, mut end1): (HandleValue, HandleValue) = (0, 0);
+    let (out0, out1) = (ptr_mut(&mut end0), ptr_mut(&mut end1));
+    env.expect(Sys::CHANNEL_CREATE, &[1, out0, out1], INVALID_ARGS)
+        .await;
+    env.expect(Sys::CHANNEL_CREATE, &[0, out0, out1], OK).await;
+
+    let event = env.add(Event::new(), Rights::DEFAULT_EVENT);
+    let read_only = env.add(Channel::create().0, Rights::READ);
+    let data = [0u8; 8];
+    let write = |handle: HandleValue, num_bytes: usize, num_handles: usize| {
+        [
+            handle as usize,
+            0,
+            ptr(&data),
+            num_bytes,
+            ptr(&data),
+            num_handles,
+        ]
+    };
+    env.expect(Sys::CHANNEL_WRITE, &write(event, 8, 0), WRONG_TYPE)
+        .await;
+    env.expect(Sys::CHANNEL_WRITE, &write(read_only, 8, 0), ACCESS_DENIED)
+        .await;
+    env.expect(Sys::CHANNEL_WRITE, &write(end0, 0x1_0001, 0), OUT_OF_RANGE)
+        .await;
+    env.expect(Sys::CHANNEL_WRITE, &write(end0, 8, 65), OUT_OF_RANGE)
+        .await;
+
+    let mut buf = [0u8; 8];
+    let (mut actual_bytes, mut actual_handles) = (0u32, 0u32);
+    let (buf, actual_bytes, actual_handles) = (
+        ptr_mut(&mut buf),
+        ptr_mut(&mut actual_bytes),
+        ptr_mut(&mut actual_handles),
+    );
+    let read = |handle: HandleValue, options: usize, num_bytes: usize| {
+        [
+            handle as usize,
+            options,
+            buf,
+            0,
+            num_bytes,
+            0,
+            actual_bytes,
+            actual_handles,
+        ]
+    };
+    env.expect(Sys::CHANNEL_READ, &read(end1, 0, 8), SHOULD_WAIT)
+        .await;
+    env.expect(Sys::CHANNEL_READ, &read(end1, 2, 8), INVALID_ARGS)
+        .await;
+    env.expect(Sys::CHANNEL_WRITE, &write(end0, 8, 0), OK).await;
+    env.expect(Sys::CHANNEL_READ, &read(end1, 0, 4), BUFFER_TOO_SMALL)
+        .await;
+    env.expect(Sys::CHANNEL_READ, &read(end1, 0, 8), OK).await;
+    env.expect(Sys::HANDLE_CLOSE, &[end0 as _], OK).await;
+    env.expect(Sys::CHANNEL_READ, &read(end1, 0, 8), PEER_CLOSED)
+        .await;
+}
+
+#[async_std::test]
+async fn vmo() {
+    let env = Env::new();
+    let vmo = VmObject::new_paged(1);
+    let read_only = env.add(vmo.clone(), Rights::READ);
+    let write_only = env.add(vmo, Rights::WRITE);
+    let event = env.add(Event::new(), Rights::DEFAULT_EVENT);
+    let mut buf = [0u8; 16];
+    let buf_ptr = ptr_mut(&mut buf);
+
+    env.expect(Sys::VMO_READ, &[event as _, buf_ptr, 0, 16], WRONG_TYPE)
+        .await;
+    env.expect(
+        Sys::VMO_READ,
+        &[write_only as _, buf_ptr, 0, 16],
+        ACCESS_DENIED,
+    )
+    .await;
+    env.expect(
+        Sys::VMO_WRITE,
+        &[read_only as _, buf_ptr, 0, 16],
+        ACCESS_DENIED,
+    )
+    .await;
+    env.expect(
+        Sys::VMO_READ,
+        &[read_only as _, buf_ptr, 0x1000, 16],
+        OUT_OF_RANGE,
+    )
+    .await;
+    env.expect(Sys::VMO_READ, &[read_only as _, 0, 0, 16], INVALID_ARGS)
+        .await;
+    env.expect(Sys::VMO_READ, &[read_only as _, buf_ptr, 0xff0, 16], OK)
+        .await;
+}
+
+#[async_std::test]
+async fn pager() {
+    let env = Env::new();
+    let pager = Pager::create();
+    let vmo = pager.create_vmo(2);
+    let pager = env.add(pager, Rights::DEFAULT_PAGER);
+    let vmo = env.add(vmo, Rights::DEFAULT_VMO);
+    let event = env.add(Event::new(), Rights::DEFAULT_EVENT);
+    let mut ranges = [0u64; 6];
+    let (mut actual, mut avail) = (0usize, 0usize);
+    let ranges_ptr = ptr_mut(&mut ranges);
+    let (actual_ptr, avail_ptr) = (ptr_mut(&mut actual), ptr_mut(&mut avail));
+    let query = |pager: HandleValue, offset: usize| {
+        [
+            pager as usize,
+            vmo as usize,
+            offset,
+            0x2000,
+            ranges_ptr,
+            48,
+            actual_ptr,
+            avail_ptr,
+        ]
+    };
+
+    env.expect(Sys::PAGER_QUERY_DIRTY_RANGES, &query(event, 0), WRONG_TYPE)
+        .await;
+    env.expect(
+        Sys::PAGER_QUERY_DIRTY_RANGES,
+        &query(pager, 1),
+        INVALID_ARGS,
+    )
+    .await;
+    env.expect(Sys::PAGER_QUERY_DIRTY_RANGES, &query(pager, 0), OK)
+        .await;
+}
+
+#[async_std::test]
+async fn object_property() {
+    let env = Env::new();
+    let event = env.add(Event::new(), Rights::DEFAULT_EVENT | Rights::GET_PROPERTY);
+    let mut name = [0u8; 32];
+    let name_ptr = ptr_mut(&mut name);
+    const NAME: usize = 3;
+
+    env.expect(
+        Sys::OBJECT_GET_PROPERTY,
+        &[event as _, 0xfff, name_ptr, 32],
+        INVALID_ARGS,
+    )
+    .await;
+    env.expect(
+        Sys::OBJECT_GET_PROPERTY,
+        &[event as _, NAME, name_ptr, 31],
+        BUFFER_TOO_SMALL,
+    )
+    .await;
+    env.expect(
+        Sys::OBJECT_GET_PROPERTY,
+        &[event as _, NAME, name_ptr, 32],
+        OK,
+    )
+    .await;
+}
+
+#[async_std::test]
+async fn creation_options() {
+    let env = Env::new();
+    let (mut out0, mut out1): (HandleValue, HandleValue) = (0, 0);
+    let (out0, out1) = (ptr_mut(&mut out0), ptr_mut(&mut out1));
+
+    env.expect(Sys::PORT_CREATE, &[0x10, out0], INVALID_ARGS)
+        .await;
+    env.expect(Sys::SOCKET_CREATE, &[2, out0, out1], INVALID_ARGS)
+        .await;
+    env.expect(Sys::TIMER_CREATE, &[3, 0, out0], INVALID_ARGS)
+        .await;
+    env.expect(Sys::TIMER_CREATE, &[0, 1, out0], INVALID_ARGS)
+        .await;
+    env.expect(Sys::TIMER_CREATE, &[0, 0, 0], INVALID_ARGS)
+        .await;
+}
+
+#[async_std::test]
+async fn socket_and_timer() {
+    let env = Env::new();
+    let (end0, end1) = Socket::create();
+    let socket = env.add(end0, Rights::DEFAULT_SOCKET);
+    let data = [0u8; 4];
+    env.expect(
+        Sys::SOCKET_WRITE,
+        &[socket as _, 1, ptr(&data), 4, 0],
+        INVALID_ARGS,
+    )
+    .await;
+    drop(end1);
+    env.expect(
+        Sys::SOCKET_WRITE,
+        &[socket as _, 0, ptr(&data), 4, 0],
+        PEER_CLOSED,
+    )
+    .await;
+
+    let timer = env.add(Timer::create(Slack::Center), Rights::READ);
+    env.expect(Sys::TIMER_SET, &[timer as _, 0, 0], ACCESS_DENIED)
+        .await;
+    env.expect(Sys::TIMER_SET, &[timer as _, 0, !0], OUT_OF_RANGE)
+        .await;
+}
+
+#[async_std::test]
+async fn random_and_time() {
+    let env = Env::new();
+    let mut buf = [0u8; 257];
+    let buf_ptr = ptr_mut(&mut buf);
+    env.expect(Sys::CPRNG_DRAW_ONCE, &[buf_ptr, 257], INVALID_ARGS)
+        .await;
+    env.expect(Sys::CPRNG_DRAW_ONCE, &[buf_ptr, 256], OK).await;
+    env.expect(Sys::CPRNG_ADD_ENTROPY, &[buf_ptr, 257], INVALID_ARGS)
+        .await;
+    env.expect(Sys::CPRNG_ADD_ENTROPY, &[buf_ptr, 256], OK)
+        .await;
+
+    env.expect(Sys::NANOSLEEP, &[0], OK).await;
+    assert!(env.call(Sys::CLOCK_GET_MONOTONIC_VIA_KERNEL, &[]).await > 0);
+}
+
+#[async_std::test]
+async fn resources() {
+    let env = Env::new();
+    let root = Resource::create("root", ResourceKind::ROOT, 0, 0, ResourceFlags::empty());
+    let mmio = root
+        .create_child(
+            "mmio",
+            ResourceKind::MMIO,
+            0,
+            0x1000,
+            ResourceFlags::empty(),
+        )
+        .unwrap();
+    let root = env.add(root, Rights::DEFAULT_RESOURCE);
+    let mmio = env.add(mmio, Rights::DEFAULT_RESOURCE);
+    let event = env.add(Event::new(), Rights::DEFAULT_EVENT);
+    let cmd = "help";
+    let command = |resource: HandleValue, len: usize| [resource as usize, cmd.as_ptr() as _, len];
+
+    env.expect(Sys::DEBUG_SEND_COMMAND, &command(mmio, 4), WRONG_TYPE)
+        .await;
+    env.expect(Sys::DEBUG_SEND_COMMAND, &command(event, 4), WRONG_TYPE)
+        .await;
+    env.expect(Sys::DEBUG_SEND_COMMAND, &command(root, 1025), INVALID_ARGS)
+        .await;
+    env.expect(Sys::SYSTEM_POWERCTL, &[mmio as _, 8, 0], WRONG_TYPE)
+        .await;
+    env.expect(Sys::SYSTEM_POWERCTL, &[root as _, 100, 0], INVALID_ARGS)
+        .await;
+    env.expect(Sys::SYSTEM_POWERCTL, &[root as _, 1, 0], NOT_SUPPORTED)
+        .await;
+
+    let mut out: HandleValue = 0;
+    let job = env.add(env.proc.job(), Rights::DEFAULT_JOB);
+    env.expect(
+        Sys::SYSTEM_GET_EVENT,
+        &[job as _, 1, ptr_mut(&mut out)],
+        ACCESS_DENIED,
+    )
+    .await;
+}
+
+#[async_std::test]
+async fn job_policy() {
+    kernel_hal_unix::init();
+    let job = Job::root().create_child().unwrap();
+    let policies = [
+        BasicPolicy {
+            condition: PolicyCondition::NewAny,
+            action: PolicyAction::Deny,
+        },
+        BasicPolicy {
+            condition: PolicyCondition::BadHandle,
+            action: PolicyAction::Kill,
+        },
+    ];
+    job.set_policy_basic(SetPolicyOptions::Absolute, &policies)
+        .unwrap();
+    let env = Env::with_job(&job);
+    let (mut out0, mut out1): (HandleValue, HandleValue) = (0, 0);
+    let (out0, out1) = (ptr_mut(&mut out0), ptr_mut(&mut out1));
+
+    env.expect(Sys::CHANNEL_CREATE, &[0, out0, out1], ACCESS_DENIED)
+        .await;
+    env.expect(Sys::PORT_CREATE, &[0, out0], ACCESS_DENIED)
+        .await;
+    env.expect(Sys::HANDLE_CLOSE, &[0], OK).await;
+    assert_eq!(env.proc.status(), Status::Init);
+    env.expect(Sys::HANDLE_CLOSE, &[0x13], BAD_HANDLE).await;
+    assert_eq!(env.proc.status(), Status::Exited(TASK_RETCODE_POLICY_KILL));
+}
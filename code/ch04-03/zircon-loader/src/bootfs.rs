@@ -0,0 +1,112 @@
+//! Parsing the BOOTFS image in the ZBI, to find the files to boot.
+
+use {
+    crate::zbi::ZbiError,
+    alloc::vec::Vec,
+    core::{convert::TryInto, str},
+};
+
+/// The magic at the start of a BOOTFS image.
+const BOOTFS_MAGIC: u32 = 0xa56d_3ff9;
+/// The size of the BOOTFS header.
+const HEADER_SIZE: usize = 16;
+/// The size of a directory entry without the name.
+const DIRENT_SIZE: usize = 12;
+/// The alignment of the directory entries.
+const DIRENT_ALIGNMENT: usize = 4;
+
+/// A file in a BOOTFS image.
+#[derive(Debug, Clone, Copy)]
+pub struct BootfsFile<'a> {
+    /// The path of the file, without the leading `/`.
+    pub name: &'a str,
+    /// The content of the file.
+    pub data: &'a [u8],
+}
+
+/// Parse the files of the BOOTFS image `image`.
+pub fn parse(image: &[u8]) -> Result<Vec<BootfsFile<'_>>, ZbiError> {
+    if read_u32(image, 0)? != BOOTFS_MAGIC {
+        return Err(ZbiError::BadBootfs);
+    }
+    let dir_end = HEADER_SIZE + read_u32(image, 4)? as usize;
+    let mut files = Vec::new();
+    let mut offset = HEADER_SIZE;
+    while offset < dir_end {
+        let name_len = read_u32(image, offset)? as usize;
+        let data_len = read_u32(image, offset + 4)? as usize;
+        let data_off = read_u32(image, offset + 8)? as usize;
+        let name = image
+            .get(offset + DIRENT_SIZE..offset + DIRENT_SIZE + name_len)
+            .ok_or(ZbiError::BadBootfs)?;
+        // the name ends with a NUL
+        let name = name.split_last().ok_or(ZbiError::BadBootfs)?.1;
+        let name = str::from_utf8(name).map_err(|_| ZbiError::BadBootfs)?;
+        let data = image
+            .get(data_off..data_off + data_len)
+            .ok_or(ZbiError::BadBootfs)?;
+        files.push(BootfsFile { name, data });
+        offset += align_up(DIRENT_SIZE + name_len);
+    }
+    Ok(files)
+}
+
+/// Find the file named `name` in `files`, in any directory.
+pub fn find<'a>(files: &[BootfsFile<'a>], name: &str) -> Option<&'a [u8]> {
+    files
+        .iter()
+        .find(|file| file.name.rsplit('/').next() == Some(name))
+        .map(|file| file.data)
+}
+
+/// Read a little-endian `u32` at `offset` of `buf`.
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32, ZbiError> {
+    let bytes = buf.get(offset..offset + 4).ok_or(ZbiError::BadBootfs)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn align_up(len: usize) -> usize {
+    (len + DIRENT_ALIGNMENT - 1) & !(DIRENT_ALIGNMENT - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_files() {
+        let files: [(&str, &[u8]); 2] = [("bin/userboot", b"elf"), ("lib/libzircon.so", b"vdso")];
+        let dir_size: usize = files
+            .iter()
+            .map(|(name, _)| align_up(DIRENT_SIZE + name.len() + 1))
+            .sum();
+        let mut image = Vec::new();
+        image.extend_from_slice(&BOOTFS_MAGIC.to_le_bytes());
+        image.extend_from_slice(&(dir_size as u32).to_le_bytes());
+        image.resize(HEADER_SIZE, 0);
+        // the data are page-aligned after the directory
+        for (i, (name, data)) in files.iter().enumerate() {
+            let fields = [name.len() + 1, data.len(), 0x1000 * (i + 1)];
+            for field in fields.iter() {
+                image.extend_from_slice(&(*field as u32).to_le_bytes());
+            }
+            image.extend_from_slice(name.as_bytes());
+            image.push(0);
+            image.resize(align_up(image.len()), 0);
+        }
+        for (i, (_, data)) in files.iter().enumerate() {
+            image.resize(0x1000 * (i + 1), 0);
+            image.extend_from_slice(data);
+        }
+
+        let parsed = parse(&image).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].name, "bin/userboot");
+        assert_eq!(find(&parsed, "userboot"), Some(&b"elf"[..]));
+        assert_eq!(find(&parsed, "libzircon.so"), Some(&b"vdso"[..]));
+        assert_eq!(find(&parsed, "zircon.so"), None);
+
+        assert_eq!(parse(&image[4..]).err(), Some(ZbiError::BadBootfs));
+        assert_eq!(parse(&image[..0x1002]).err(), Some(ZbiError::BadBootfs));
+    }
+}
@@ -8,15 +8,26 @@ extern crate alloc;
 extern crate log;
 
 use {
-    alloc::{boxed::Box, sync::Arc, vec::Vec},
-    core::{future::Future, pin::Pin},
-    kernel_hal::MMUFlags,
+    alloc::{borrow::Cow, boxed::Box, string::String, sync::Arc, vec::Vec},
+    core::{fmt, future::Future, pin::Pin},
+    kernel_hal::{MMUFlags, UserContextExt, UserTrap},
     xmas_elf::ElfFile,
-    zircon_object::{dev::*, ipc::*, object::*, task::*, util::elf_loader::*, vm::*},
+    zircon_object::{
+        dev::*,
+        ipc::*,
+        object::*,
+        signal::Clock,
+        task::*,
+        util::{console, elf_loader::*, kcounter, random::random_below},
+        vm::*,
+    },
     zircon_syscall::Syscall,
 };
 
-mod kcounter;
+pub mod bootfs;
+pub mod zbi;
+
+zircon_object::kcounter!(CONTEXT_SWITCH_COUNT, "thread.context_switch");
 
 // These describe userboot itself
 const K_PROC_SELF: usize = 0;
@@ -33,78 +44,289 @@ const K_COUNTERS: usize = 10;
 const K_FISTINSTRUMENTATIONDATA: usize = 11;
 const K_HANDLECOUNT: usize = 15;
 
+// Resource handles carved out of the root resource, and the ACPI tables,
+// sent in a second message after the one above, followed by an MMIO resource
+// for each MMIO region
+const K_IRQRESOURCE: usize = 0;
+const K_IOPORTRESOURCE: usize = 1;
+const K_ACPITABLES: usize = 2;
+const K_VMEXRESOURCE: usize = 3;
+const K_FIRSTMMIORESOURCE: usize = 4;
+
+/// The size of the crashlog, which is the tail of the kernel log on panic.
+const CRASHLOG_SIZE: usize = kernel_hal::CRASHLOG_CAPACITY;
+
+/// The number of I/O ports.
+const IOPORT_COUNT: usize = 0x10000;
+
+/// The name of userboot in the BOOTFS.
+const USERBOOT_NAME: &str = "userboot";
+/// The name of the vDSO in the BOOTFS.
+const VDSO_NAME: &str = "libzircon.so";
+
 /// Program images to run.
+///
+/// userboot and the vDSO are found in the BOOTFS of the ZBI, unless they are
+/// given here, such as the LibOS variants. If both are given, the BOOTFS is
+/// not parsed, and its storage items are not decompressed.
 pub struct Images<T: AsRef<[u8]>> {
-    pub userboot: T,
-    pub vdso: T,
     pub zbi: T,
+    pub userboot: Option<T>,
+    pub vdso: Option<T>,
+}
+
+/// Options of booting userboot.
+#[derive(Debug, Clone)]
+pub struct BootOptions {
+    /// The kernel command line, with options separated by `:`.
+    ///
+    /// It follows the `CMDLINE` items of the ZBI, so its options override
+    /// the ones there.
+    pub cmdline: String,
+    /// The number of pages of the stack of userboot.
+    pub stack_pages: usize,
+    /// The number of random bits in the page number where userboot and the
+    /// vDSO are loaded. They are loaded at the start of the address space if
+    /// it is 0.
+    pub aslr_entropy_bits: u32,
+    /// The variant of the vDSO mapped into userboot.
+    ///
+    /// All variants but `Next` are passed to userboot, in the slots it reads
+    /// them from, and `Next` fails the loading with `NOT_SUPPORTED`.
+    pub vdso_variant: VdsoVariant,
+    /// Whether to create the test variants of the vDSO for core-tests.
+    ///
+    /// They are always created if one of them is mapped into userboot.
+    /// Otherwise, their slots hold the full vDSO.
+    pub test_vdsos: bool,
+    /// Whether to run the kernel shell on the serial console, on the tasks
+    /// under the root job.
+    ///
+    /// It takes the input of the serial console from userspace.
+    pub shell: bool,
+}
+
+impl Default for BootOptions {
+    fn default() -> Self {
+        BootOptions {
+            cmdline: String::new(),
+            stack_pages: 8,
+            aslr_entropy_bits: 30,
+            vdso_variant: VdsoVariant::Full,
+            test_vdsos: true,
+            shell: false,
+        }
+    }
+}
+
+/// Errors of booting userboot.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum LoaderError {
+    /// The ZBI or the BOOTFS in it is malformed.
+    BadZbi(zbi::ZbiError),
+    /// The image is not found in the BOOTFS.
+    NotFound(&'static str),
+    /// The image is not a valid ELF file.
+    BadElf(&'static str),
+    /// The symbol is not found in the vDSO.
+    MissingSymbol(&'static str),
+    /// The address space of userboot is too small for the images or the stack.
+    VmarExhausted,
+    /// Failed to set up the kernel objects.
+    Object(ZxError),
+}
+
+impl fmt::Display for LoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoaderError::BadZbi(err) => write!(f, "bad ZBI: {:?}", err),
+            LoaderError::NotFound(name) => write!(f, "{} not found in BOOTFS", name),
+            LoaderError::BadElf(name) => write!(f, "{} is not a valid ELF file", name),
+            LoaderError::MissingSymbol(name) => write!(f, "symbol {} not found in vDSO", name),
+            LoaderError::VmarExhausted => write!(f, "VMAR of userboot exhausted"),
+            LoaderError::Object(err) => write!(f, "failed to create kernel objects: {:?}", err),
+        }
+    }
+}
+
+impl From<zbi::ZbiError> for LoaderError {
+    fn from(err: zbi::ZbiError) -> Self {
+        LoaderError::BadZbi(err)
+    }
+}
+
+impl From<ZxError> for LoaderError {
+    fn from(err: ZxError) -> Self {
+        LoaderError::Object(err)
+    }
+}
+
+/// Convert the error of mapping into the VMAR, which fails with `NO_MEMORY`
+/// if there is no room.
+fn map_error(err: ZxError) -> LoaderError {
+    match err {
+        ZxError::NO_MEMORY => LoaderError::VmarExhausted,
+        err => LoaderError::Object(err),
+    }
 }
 
-pub fn run_userboot(images: &Images<impl AsRef<[u8]>>, cmdline: &str) -> Arc<Process> {
+/// The symbol in the vDSO to be filled with the syscall entry of the kernel.
+const SYSCALL_ENTRY_SYMBOL: &str = "zcore_syscall_entry";
+
+/// Create the userboot process, and start it with the handles to the kernel
+/// objects it needs.
+pub fn run_userboot(
+    images: &Images<impl AsRef<[u8]>>,
+    options: &BootOptions,
+) -> Result<Arc<Process>, LoaderError> {
+    // the BOOTFS is decompressed and parsed only if an image is found in it,
+    // otherwise the ZBI is passed to userboot as it is
+    let needs_bootfs = images.userboot.is_none() || images.vdso.is_none();
+    let zbi = if needs_bootfs {
+        zbi::decompress(images.zbi.as_ref())?
+    } else {
+        Cow::Borrowed(images.zbi.as_ref())
+    };
+    let bootfs = if needs_bootfs {
+        find_bootfs(&zbi)?
+    } else {
+        Vec::new()
+    };
+    let userboot = match &images.userboot {
+        Some(image) => image.as_ref(),
+        None => bootfs::find(&bootfs, USERBOOT_NAME).ok_or(LoaderError::NotFound(USERBOOT_NAME))?,
+    };
+    let vdso = match &images.vdso {
+        Some(image) => image.as_ref(),
+        None => bootfs::find(&bootfs, VDSO_NAME).ok_or(LoaderError::NotFound(VDSO_NAME))?,
+    };
+
+    // userboot and vdso are placed together, vdso right after userboot
+    let userboot_elf = ElfFile::new(userboot).map_err(|_| LoaderError::BadElf(USERBOOT_NAME))?;
+    let vdso_elf = ElfFile::new(vdso).map_err(|_| LoaderError::BadElf(VDSO_NAME))?;
+    let syscall_entry_offset = vdso_elf
+        .get_symbol_address(SYSCALL_ENTRY_SYMBOL)
+        .ok_or(LoaderError::MissingSymbol(SYSCALL_ENTRY_SYMBOL))?
+        as usize;
+
+    // start the UTC clock from the RTC at boot, not when it is first read
+    Clock::utc();
     let job = Job::root();
-    let proc = Process::create(&job, "userboot").unwrap();
-    let thread = Thread::create(&proc, "userboot").unwrap();
-    let resource = Resource::create(
-        "root",
-        ResourceKind::ROOT,
-        0,
-        0x1_0000_0000,
-        ResourceFlags::empty(),
-    );
+    job.set_system_root();
+    if options.shell {
+        kernel_hal::Thread::spawn(
+            Box::pin(console::shell(job.clone())),
+            0,
+            kernel_hal::CPU_AFFINITY_ALL,
+        );
+    }
+    let proc = Process::create(&job, "userboot")?;
+    let thread = Thread::create(&proc, "userboot")?;
+    let resource = Resource::create("root", ResourceKind::ROOT, 0, 0, ResourceFlags::empty());
     let vmar = proc.vmar();
 
+    let userboot_size = userboot_elf.load_segment_size();
+    let image_size = userboot_size + vdso_elf.load_segment_size();
+    let image_vmar = vmar
+        .allocate(
+            Some(aslr_offset(&vmar, image_size, options.aslr_entropy_bits)),
+            image_size,
+            VmarFlags::CAN_MAP_RXW | VmarFlags::CAN_MAP_SPECIFIC,
+            PAGE_SIZE,
+        )
+        .map_err(map_error)?;
+
     // userboot
-    let (entry, userboot_size) = {
-        let elf = ElfFile::new(images.userboot.as_ref()).unwrap();
-        let size = elf.load_segment_size();
-        let vmar = vmar
-            .allocate(None, size, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)
-            .unwrap();
-        vmar.load_from_elf(&elf).unwrap();
-        (vmar.addr() + elf.header.pt2.entry_point() as usize, size)
+    let entry = {
+        let elf = userboot_elf;
+        let vmar = image_vmar
+            .allocate_at(
+                0,
+                userboot_size,
+                VmarFlags::CAN_MAP_RXW | VmarFlags::SPECIFIC,
+                PAGE_SIZE,
+            )
+            .map_err(map_error)?;
+        vmar.load_from_elf(&elf).map_err(|err| match err {
+            ZxError::INVALID_ARGS => LoaderError::BadElf(USERBOOT_NAME),
+            err => map_error(err),
+        })?;
+        vmar.addr() + elf.header.pt2.entry_point() as usize
     };
 
-    // vdso
-    let vdso_vmo = {
-        let elf = ElfFile::new(images.vdso.as_ref()).unwrap();
-        let vdso_vmo = VmObject::new_paged(images.vdso.as_ref().len() / PAGE_SIZE + 1);
-        vdso_vmo.write(0, images.vdso.as_ref()).unwrap();
-        let size = elf.load_segment_size();
-        let vmar = vmar
+    // vdso, the test variants are copies of the full one after it is filled
+    let vdso_vmos = {
+        const VDSO_DATA_CONSTANTS: usize = 0x4a50;
+        let vdso_vmo = VmObject::new_paged(vdso.len() / PAGE_SIZE + 1);
+        vdso_vmo.write(0, vdso)?;
+        // fill syscall entry x3, if the syscalls are function calls
+        if let Some(entry) = kernel_hal::libos_syscall_entry() {
+            let syscall_entry = &entry.to_ne_bytes();
+            vdso_vmo.write_v(
+                syscall_entry_offset,
+                &[syscall_entry, syscall_entry, syscall_entry],
+            )?;
+        }
+        let constants = kernel_hal::vdso::VdsoConstants::from_hal();
+        vdso_vmo.write(VDSO_DATA_CONSTANTS, &constants.to_bytes())?;
+        vdso_vmo.set_name("vdso/full");
+        vdso_vmo.set_vdso_variant(VdsoVariant::Full);
+        // every slot of the vDSO in the message to userboot is filled, as
+        // there is no invalid handle in a message
+        let mut vmos = vec![vdso_vmo.clone()];
+        let tests = [
+            (VdsoVariant::Test1, "vdso/test1"),
+            (VdsoVariant::Test2, "vdso/test2"),
+        ];
+        let test_vdsos = options.test_vdsos || options.vdso_variant != VdsoVariant::Full;
+        for &(variant, name) in tests.iter() {
+            if !test_vdsos {
+                vmos.push(vdso_vmo.clone());
+                continue;
+            }
+            let vmo = vdso_vmo.create_child(false, 0, vdso_vmo.len())?;
+            vmo.set_name(name);
+            vmo.set_vdso_variant(variant);
+            vmos.push(vmo);
+        }
+        vmos
+    };
+    {
+        let elf = vdso_elf;
+        let vdso_vmo = vdso_vmos
+            .iter()
+            .find(|vmo| vmo.vdso_variant() == Some(options.vdso_variant))
+            .ok_or(LoaderError::Object(ZxError::NOT_SUPPORTED))?
+            .clone();
+        let vmar = image_vmar
             .allocate_at(
                 userboot_size,
-                size,
+                elf.load_segment_size(),
                 VmarFlags::CAN_MAP_RXW | VmarFlags::SPECIFIC,
                 PAGE_SIZE,
             )
-            .unwrap();
-        vmar.map_from_elf(&elf, vdso_vmo.clone()).unwrap();
-        let offset = elf
-            .get_symbol_address("zcore_syscall_entry")
-            .expect("failed to locate syscall entry") as usize;
-        let syscall_entry = &(kernel_hal_unix::syscall_entry as usize).to_ne_bytes();
-        // fill syscall entry x3
-        vdso_vmo.write(offset, syscall_entry).unwrap();
-        vdso_vmo.write(offset + 8, syscall_entry).unwrap();
-        vdso_vmo.write(offset + 16, syscall_entry).unwrap();
-        vdso_vmo
-    };
+            .map_err(map_error)?;
+        vmar.map_from_elf(&elf, vdso_vmo).map_err(|err| match err {
+            ZxError::INVALID_ARGS => LoaderError::BadElf(VDSO_NAME),
+            err => map_error(err),
+        })?;
+        proc.set_vdso_variant(options.vdso_variant)?;
+    }
 
-    // zbi
+    // zbi, with the storage items decompressed if the BOOTFS is used
     let zbi_vmo = {
-        let vmo = VmObject::new_paged(images.zbi.as_ref().len() / PAGE_SIZE + 1);
-        vmo.write(0, images.zbi.as_ref()).unwrap();
+        let vmo = VmObject::new_paged(zbi.len() / PAGE_SIZE + 1);
+        vmo.write(0, &zbi)?;
         vmo.set_name("zbi");
         vmo
     };
 
     // stack
-    const STACK_PAGES: usize = 8;
-    let stack_vmo = VmObject::new_paged(STACK_PAGES);
+    let stack_vmo = VmObject::new_paged(options.stack_pages);
     let flags = MMUFlags::READ | MMUFlags::WRITE | MMUFlags::USER;
     let stack_bottom = vmar
         .map(None, stack_vmo.clone(), 0, stack_vmo.len(), flags)
-        .unwrap();
+        .map_err(map_error)?;
     // WARN: align stack to 16B, then emulate a 'call' (push rip)
     let sp = stack_bottom + stack_vmo.len() - 8;
 
@@ -116,27 +338,24 @@ pub fn run_userboot(images: &Images<impl AsRef<[u8]>>, cmdline: &str) -> Arc<Pro
     handles[K_PROC_SELF] = Handle::new(proc.clone(), Rights::DEFAULT_PROCESS);
     handles[K_VMARROOT_SELF] = Handle::new(proc.vmar(), Rights::DEFAULT_VMAR | Rights::IO);
     handles[K_ROOTJOB] = Handle::new(job, Rights::DEFAULT_JOB);
+    let resources = create_ranged_resources(&resource)?;
+    let vmex = resource.create_child("vmex", ResourceKind::VMEX, 0, 0, ResourceFlags::empty())?;
     handles[K_ROOTRESOURCE] = Handle::new(resource, Rights::DEFAULT_RESOURCE);
     handles[K_ZBI] = Handle::new(zbi_vmo, Rights::DEFAULT_VMO);
     // set up handles[K_FIRSTVDSO..K_LASTVDSO + 1]
-    const VDSO_DATA_CONSTANTS: usize = 0x4a50;
-    const VDSO_DATA_CONSTANTS_SIZE: usize = 0x78;
-    let constants: [u8; VDSO_DATA_CONSTANTS_SIZE] =
-        unsafe { core::mem::transmute(kernel_hal::vdso_constants()) };
-    vdso_vmo.write(VDSO_DATA_CONSTANTS, &constants).unwrap();
-    vdso_vmo.set_name("vdso/full");
-    let vdso_test1 = vdso_vmo.create_child(false, 0, vdso_vmo.len()).unwrap();
-    vdso_test1.set_name("vdso/test1");
-    let vdso_test2 = vdso_vmo.create_child(false, 0, vdso_vmo.len()).unwrap();
-    vdso_test2.set_name("vdso/test2");
-    handles[K_FIRSTVDSO] = Handle::new(vdso_vmo, Rights::DEFAULT_VMO | Rights::EXECUTE);
-    handles[K_FIRSTVDSO + 1] = Handle::new(vdso_test1, Rights::DEFAULT_VMO | Rights::EXECUTE);
-    handles[K_FIRSTVDSO + 2] = Handle::new(vdso_test2, Rights::DEFAULT_VMO | Rights::EXECUTE);
-    // TODO: use correct CrashLogVmo handle
-    let crash_log_vmo = VmObject::new_paged(1);
+    debug_assert_eq!(vdso_vmos.len(), K_CRASHLOG - K_FIRSTVDSO);
+    for (i, vmo) in vdso_vmos.into_iter().enumerate() {
+        handles[K_FIRSTVDSO + i] = Handle::new(vmo, Rights::DEFAULT_VMO | Rights::EXECUTE);
+    }
+    // the crashlog of the last boot, as long as its content
+    let crash_log = kernel_hal::crashlog_load();
+    let crash_log = &crash_log[..crash_log.len().min(CRASHLOG_SIZE)];
+    let crash_log_vmo = VmObject::new_paged(pages(crash_log.len()));
+    crash_log_vmo.write(0, crash_log)?;
+    crash_log_vmo.set_content_size(crash_log.len())?;
     crash_log_vmo.set_name("crashlog");
     handles[K_CRASHLOG] = Handle::new(crash_log_vmo, Rights::DEFAULT_VMO);
-    let (counter_name_vmo, kcounters_vmo) = kcounter::create_kcounter_vmo();
+    let (counter_name_vmo, kcounters_vmo) = kcounter::create_vmos();
     handles[K_COUNTERNAMES] = Handle::new(counter_name_vmo, Rights::DEFAULT_VMO);
     handles[K_COUNTERS] = Handle::new(kcounters_vmo, Rights::DEFAULT_VMO);
     // TODO: use correct Instrumentation data handle
@@ -152,13 +371,136 @@ pub fn run_userboot(images: &Images<impl AsRef<[u8]>>, cmdline: &str) -> Arc<Pro
         Handle::new(instrumentation_data_vmo, Rights::DEFAULT_VMO);
 
     // check: handle to root proc should be only
-    let data = Vec::from(cmdline.replace(':', "\0") + "\0");
+    let data = cmdline_data(&zbi, &options.cmdline)?;
     let msg = MessagePacket { data, handles };
-    kernel_channel.write(msg).unwrap();
+    kernel_channel.write(msg)?;
+
+    // ranged resources and the VMEX resource
+    let mut handles = vec![Handle::new(proc.clone(), Rights::empty()); K_FIRSTMMIORESOURCE];
+    let (mmio, irq, ioport) = resources;
+    handles[K_IRQRESOURCE] = Handle::new(irq, Rights::DEFAULT_RESOURCE);
+    handles[K_IOPORTRESOURCE] = Handle::new(ioport, Rights::DEFAULT_RESOURCE);
+    handles[K_ACPITABLES] = Handle::new(create_acpi_vmo()?, Rights::DEFAULT_VMO);
+    handles[K_VMEXRESOURCE] = Handle::new(vmex, Rights::DEFAULT_RESOURCE);
+    for resource in mmio {
+        handles.push(Handle::new(resource, Rights::DEFAULT_RESOURCE));
+    }
+    let msg = MessagePacket {
+        data: Vec::new(),
+        handles,
+    };
+    kernel_channel.write(msg)?;
+
+    proc.start(&thread, entry, sp, Some(handle), 0, thread_fn)?;
+    Ok(proc)
+}
+
+/// Parse the files of the BOOTFS in the ZBI `zbi`.
+fn find_bootfs(zbi: &[u8]) -> Result<Vec<bootfs::BootfsFile<'_>>, zbi::ZbiError> {
+    let item = zbi::parse(zbi)?
+        .into_iter()
+        .find(|item| item.type_ == zbi::ZBI_TYPE_STORAGE_BOOTFS)
+        .ok_or(zbi::ZbiError::NotFound)?;
+    bootfs::parse(item.payload)
+}
+
+/// Join the options in the `CMDLINE` items of the ZBI and `cmdline`, in the
+/// NUL-separated format read by userboot.
+fn cmdline_data(zbi: &[u8], cmdline: &str) -> Result<Vec<u8>, zbi::ZbiError> {
+    let mut data = Vec::new();
+    for item in zbi::parse(zbi)? {
+        if item.type_ != zbi::ZBI_TYPE_CMDLINE {
+            continue;
+        }
+        let text = item.payload.split(|&c| c == 0).next().unwrap_or_default();
+        for option in text.split(|c| c.is_ascii_whitespace()) {
+            if !option.is_empty() {
+                data.extend_from_slice(option);
+                data.push(0);
+            }
+        }
+    }
+    for option in cmdline.split(':').filter(|option| !option.is_empty()) {
+        data.extend_from_slice(option.as_bytes());
+        data.push(0);
+    }
+    if data.is_empty() {
+        data.push(0);
+    }
+    Ok(data)
+}
+
+/// Choose a page-aligned offset for a region of `len` bytes in `vmar`, with
+/// `entropy_bits` random bits in the page number.
+fn aslr_offset(vmar: &VmAddressRegion, len: usize, entropy_bits: u32) -> usize {
+    let max_pages = vmar.size().saturating_sub(len) / PAGE_SIZE + 1;
+    let pages = match 1usize.checked_shl(entropy_bits) {
+        Some(n) => n.min(max_pages),
+        None => max_pages,
+    };
+    random_below(pages) * PAGE_SIZE
+}
 
-    proc.start(&thread, entry, sp, Some(handle), 0, thread_fn)
-        .expect("failed to start main thread");
-    proc
+/// Save the tail of the kernel log as the crashlog of the next boot.
+///
+/// It should be called on panic.
+pub fn save_crashlog() {
+    if let Some(log) = zircon_object::debuglog::dump_tail(CRASHLOG_SIZE) {
+        kernel_hal::crashlog_save(log.as_bytes());
+    }
+}
+
+/// Copy the ACPI tables found by the HAL into a VMO, one after another.
+///
+/// Each table starts with its header of the signature and the size, and the
+/// VMO is empty without ACPI.
+fn create_acpi_vmo() -> ZxResult<Arc<VmObject>> {
+    let tables = kernel_hal::acpi_tables();
+    let size = tables.iter().map(|table| table.size).sum();
+    let vmo = VmObject::new_paged(pages(size));
+    let mut offset = 0;
+    for table in tables {
+        let mut buf = vec![0; table.size];
+        kernel_hal::pmem_read(table.paddr, &mut buf);
+        vmo.write(offset, &buf)?;
+        offset += table.size;
+    }
+    vmo.set_content_size(size)?;
+    vmo.set_name("acpi");
+    Ok(vmo)
+}
+
+/// Carve the MMIO, IRQ and I/O port resources out of the root resource.
+///
+/// There is an MMIO resource for each MMIO region in the memory map of HAL,
+/// so that no RAM is covered, and the IRQ resource spans the IRQ vectors
+/// available to devices.
+fn create_ranged_resources(
+    root: &Arc<Resource>,
+) -> ZxResult<(Vec<Arc<Resource>>, Arc<Resource>, Arc<Resource>)> {
+    let flags = ResourceFlags::empty();
+    let create = |kind, name, range: core::ops::Range<usize>| {
+        root.create_child(name, kind, range.start, range.end - range.start, flags)
+    };
+    let mmio = kernel_hal::memory_map()
+        .into_iter()
+        .filter(|region| region.kind == kernel_hal::MemoryRegionKind::Mmio)
+        .map(|region| {
+            create(
+                ResourceKind::MMIO,
+                "mmio",
+                region.addr..region.addr + region.size,
+            )
+        })
+        .collect::<ZxResult<_>>()?;
+    let irq = kernel_hal::irq_range();
+    let irq = create(
+        ResourceKind::IRQ,
+        "irq",
+        irq.start as usize..irq.end as usize,
+    )?;
+    let ioport = create(ResourceKind::IOPORT, "ioport", 0..IOPORT_COUNT)?;
+    Ok((mmio, irq, ioport))
 }
 
 async fn new_thread(thread: CurrentThread) {
@@ -171,22 +513,47 @@ async fn new_thread(thread: CurrentThread) {
         }
         trace!("go to user: {:#x?}", cx);
         debug!("switch to {}|{}", thread.proc().name(), thread.name());
+        thread.set_last_cpu(kernel_hal::cpu_id());
         let tmp_time = kernel_hal::timer_now().as_nanos();
         // * Attention
         // The code will enter a magic zone from here.
         // `context run` will be executed into a wrapped library where context switching takes place.
         // The details are available in the trapframe crate on crates.io.
-        kernel_hal::context_run(&mut cx);
+        CONTEXT_SWITCH_COUNT.add(1);
+        thread.proc().load_ioport_bitmap();
+        if kernel_hal::context_run(&mut cx).is_err() {
+            error!(
+                "user programs can not run on this HAL, kill {}|{}",
+                thread.proc().name(),
+                thread.name()
+            );
+            thread.end_running(cx);
+            thread.proc().exit(TASK_RETCODE_EXCEPTION_KILL);
+            continue;
+        }
         // Back from the userspace
         let time = kernel_hal::timer_now().as_nanos() - tmp_time;
         thread.time_add(time);
         trace!("back from user: {:#x?}", cx);
-        let trap_num = cx.trap_num;
-        let _error_code = cx.error_code;
+        let trap = cx.trap();
         thread.end_running(cx);
-        match trap_num {
-            0x100 => handle_syscall(&thread).await,
-            n => panic!("Unsupprted exception {:x}", n),
+        match trap {
+            UserTrap::Syscall => handle_syscall(&thread).await,
+            UserTrap::Irq(vector) => {
+                kernel_hal::irq_handle(vector);
+                // the end of the time slice, let the others run
+                kernel_hal::yield_now().await;
+            }
+            UserTrap::Exception { num, code } => {
+                error!(
+                    "unhandled exception {:#x} (error code {:#x}) in {}|{}",
+                    num,
+                    code,
+                    thread.proc().name(),
+                    thread.name()
+                );
+                thread.proc().exit(TASK_RETCODE_EXCEPTION_KILL);
+            }
         }
     }
 }
@@ -196,26 +563,55 @@ fn thread_fn(thread: CurrentThread) -> Pin<Box<dyn Future<Output = ()> + Send +
 }
 
 async fn handle_syscall(thread: &CurrentThread) {
-    let (num, args) = thread.with_context(|cx| {
-        let regs = cx.general;
-        let num = regs.rax as u32;
-        // LibOS: Function call ABI
-        let args = unsafe {
-            let a6 = (regs.rsp as *const usize).read();
-            let a7 = (regs.rsp as *const usize).add(1).read();
-            [
-                regs.rdi, regs.rsi, regs.rdx, regs.rcx, regs.r8, regs.r9, a6, a7,
-            ]
-        };
-        // RealOS: Zircon syscall ABI
-        // let args = [
-        //     regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9, regs.r12, regs.r13,
-        // ];
-        (num, args)
-    });
+    let (num, args) = thread.with_context(|cx| (cx.syscall_num(), cx.syscall_args()));
     let mut syscall = Syscall { thread, thread_fn };
     let ret = syscall.syscall(num, args).await as usize;
-    thread.with_context(|cx| {
-        cx.general.rax = ret;
-    });
+    thread.with_context(|cx| cx.set_syscall_ret(ret));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_zbi() {
+        kernel_hal_unix::init();
+        let images = Images {
+            zbi: [0u8; 64],
+            userboot: None,
+            vdso: None,
+        };
+        assert!(matches!(
+            run_userboot(&images, &BootOptions::default()),
+            Err(LoaderError::BadZbi(_))
+        ));
+    }
+
+    #[test]
+    fn images_without_bootfs() {
+        kernel_hal_unix::init();
+        let images = Images {
+            zbi: &[0u8; 64][..],
+            userboot: Some(&[0u8; 64][..]),
+            vdso: Some(&[0u8; 64][..]),
+        };
+        assert!(matches!(
+            run_userboot(&images, &BootOptions::default()),
+            Err(LoaderError::BadElf(USERBOOT_NAME))
+        ));
+    }
+
+    #[test]
+    fn aslr() {
+        kernel_hal_unix::init();
+        let vmar = VmAddressRegion::new_root();
+        assert_eq!(aslr_offset(&vmar, PAGE_SIZE, 0), 0);
+        for _ in 0..16 {
+            let offset = aslr_offset(&vmar, PAGE_SIZE, 4);
+            assert!(offset < 16 * PAGE_SIZE && offset % PAGE_SIZE == 0);
+        }
+        // no more than the size of the VMAR
+        let offset = aslr_offset(&vmar, vmar.size(), 64);
+        assert_eq!(offset, 0);
+    }
 }
@@ -7,7 +7,7 @@ use std::sync::Arc;
 use structopt::StructOpt;
 use zircon_loader::*;
 use zircon_object::object::*;
-use zircon_object::task::Process;
+use zircon_object::task::{try_dump_task_tree, Job, Process};
 
 #[derive(Debug, StructOpt)]
 #[structopt()]
@@ -16,25 +16,45 @@ struct Opt {
     prebuilt_path: PathBuf,
     #[structopt(default_value = "")]
     cmdline: String,
+    /// The image files of the block devices.
+    #[structopt(long = "block", parse(from_os_str))]
+    block: Vec<PathBuf>,
 }
 
 #[async_std::main]
 async fn main() {
     kernel_hal_unix::init();
     init_logger();
+    init_panic_hook();
     let opt = Opt::from_args();
+    for path in opt.block.iter() {
+        kernel_hal_unix::block_add_file(path).expect("failed to open block image");
+    }
     let images = open_images(&opt.prebuilt_path).expect("failed to read file");
-    let proc: Arc<dyn KernelObject> = run_userboot(&images, &opt.cmdline);
+    let options = BootOptions {
+        cmdline: opt.cmdline,
+        ..Default::default()
+    };
+    let proc: Arc<dyn KernelObject> = match run_userboot(&images, &options) {
+        Ok(proc) => proc,
+        Err(err) => {
+            log::error!("failed to run userboot: {}", err);
+            std::process::exit(1);
+        }
+    };
     drop(images);
     let proc = proc.downcast_arc::<Process>().unwrap();
     proc.wait_for_end().await;
 }
 
+/// Read the ZBI, and the LibOS variants of userboot and the vDSO if they
+/// exist, which replace the ones in the ZBI.
 fn open_images(path: &Path) -> std::io::Result<Images<Vec<u8>>> {
+    let read_optional = |name: &str| std::fs::read(path.join(name)).ok();
     Ok(Images {
-        userboot: std::fs::read(path.join("userboot-libos.so"))?,
-        vdso: std::fs::read(path.join("libzircon-libos.so"))?,
         zbi: std::fs::read(path.join("bringup.zbi"))?,
+        userboot: read_optional("userboot-libos.so"),
+        vdso: read_optional("libzircon-libos.so"),
     })
 }
 
@@ -54,6 +74,7 @@ fn init_logger() {
                 Level::Warn => style.set_color(Color::Yellow),
                 Level::Error => style.set_color(Color::Red).set_bold(true),
             };
+            zircon_object::debuglog::kernel_log(record.level(), tid, pid, record.args());
             let now = kernel_hal_unix::timer_now();
             let level = style.value(record.level());
             let args = record.args();
@@ -62,6 +83,21 @@ fn init_logger() {
         .init();
 }
 
+/// Log the panic and save the kernel log as the crashlog of the next run,
+/// then dump the tasks.
+fn init_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        log::error!("{}", info);
+        save_crashlog();
+        // the tasks locked, maybe by the panicking thread, are skipped
+        if let Some(dump) = Job::system_root().and_then(|job| try_dump_task_tree(&job)) {
+            log::error!("tasks:\n{}", dump);
+        }
+        default_hook(info);
+    }));
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -76,7 +112,11 @@ fn init_logger() {
 //         };
 //         let images = open_images(&opt.prebuilt_path).expect("failed to read file");
 
-//         let proc: Arc<dyn KernelObject> = run_userboot(&images, &opt.cmdline);
+//         let options = BootOptions {
+//             cmdline: opt.cmdline,
+//             ..Default::default()
+//         };
+//         let proc: Arc<dyn KernelObject> = run_userboot(&images, &options).unwrap();
 //         drop(images);
 
 //         let proc = proc.downcast_arc::<Process>().unwrap();
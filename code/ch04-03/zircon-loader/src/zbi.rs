@@ -0,0 +1,434 @@
+//! Parsing the ZBI (Zircon Boot Image) and decompressing its storage items.
+//!
+//! The payloads of the `STORAGE` items built by Fuchsia are compressed in
+//! the zstd format, or the LZ4 frame format by the older builds, which are
+//! supported with the features `zstd` and `lz4`.
+
+use {
+    alloc::{borrow::Cow, vec::Vec},
+    core::convert::TryInto,
+};
+
+/// The type of the container header, "BOOT".
+pub const ZBI_TYPE_CONTAINER: u32 = 0x544f_4f42;
+/// The type of a ramdisk image, "RDSK".
+pub const ZBI_TYPE_STORAGE_RAMDISK: u32 = 0x4b53_4452;
+/// The type of a BOOTFS image, "BFSB".
+pub const ZBI_TYPE_STORAGE_BOOTFS: u32 = 0x4253_4642;
+/// The type of the kernel command line, "CMDL".
+pub const ZBI_TYPE_CMDLINE: u32 = 0x4c44_4d43;
+
+/// The `extra` of the container header.
+const ZBI_CONTAINER_MAGIC: u32 = 0x868c_f7e6;
+/// The `magic` of all item headers.
+const ZBI_ITEM_MAGIC: u32 = 0xb578_1729;
+/// The flag required on all item headers.
+const ZBI_FLAGS_VERSION: u32 = 0x0001_0000;
+/// The flag of an item with the CRC32 of its payload.
+const ZBI_FLAGS_CRC32: u32 = 0x0002_0000;
+/// The flag of a storage item with a compressed payload.
+const ZBI_FLAGS_STORAGE_COMPRESSED: u32 = 0x0000_0001;
+/// The `crc32` of an item without `ZBI_FLAGS_CRC32`.
+const ZBI_ITEM_NO_CRC32: u32 = 0x4a87_e8d6;
+/// The alignment of the items.
+const ZBI_ALIGNMENT: usize = 8;
+/// The size of an item header.
+const HEADER_SIZE: usize = 32;
+
+/// The magic of an LZ4 frame.
+#[cfg(feature = "lz4")]
+const LZ4F_MAGIC: u32 = 0x184d_2204;
+/// The magic of a zstd frame.
+#[cfg(feature = "zstd")]
+const ZSTD_MAGIC: u32 = 0xfd2f_b528;
+
+/// The errors of parsing a ZBI.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ZbiError {
+    /// The container header is invalid.
+    BadContainer,
+    /// An item header is invalid, or the payload is out of the container.
+    BadItem,
+    /// The compression format is not supported.
+    Unsupported,
+    /// The compressed payload is corrupted, or does not match its size.
+    Corrupted,
+    /// The BOOTFS image is invalid.
+    BadBootfs,
+    /// The BOOTFS image or a file to boot is not found.
+    NotFound,
+}
+
+/// An item in a ZBI.
+#[derive(Debug, Clone, Copy)]
+pub struct ZbiItem<'a> {
+    /// The type of the item.
+    pub type_: u32,
+    /// The type-specific field, the uncompressed size of a storage item.
+    pub extra: u32,
+    /// The flags of the item.
+    pub flags: u32,
+    /// The CRC32 of the payload, if it has `ZBI_FLAGS_CRC32`.
+    pub crc32: u32,
+    /// The payload of the item.
+    pub payload: &'a [u8],
+}
+
+impl ZbiItem<'_> {
+    /// Whether it is a storage item with a compressed payload.
+    pub fn is_compressed(&self) -> bool {
+        matches!(
+            self.type_,
+            ZBI_TYPE_STORAGE_RAMDISK | ZBI_TYPE_STORAGE_BOOTFS
+        ) && self.flags & ZBI_FLAGS_STORAGE_COMPRESSED != 0
+    }
+
+    /// Decompress the payload of a storage item, or borrow it if it is not
+    /// compressed.
+    pub fn storage(&self) -> Result<Cow<'_, [u8]>, ZbiError> {
+        if !self.is_compressed() {
+            return Ok(Cow::Borrowed(self.payload));
+        }
+        let mut output = vec![0u8; self.extra as usize];
+        let len = decompress_payload(self.payload, &mut output)?;
+        if len != output.len() {
+            return Err(ZbiError::Corrupted);
+        }
+        Ok(Cow::Owned(output))
+    }
+}
+
+/// Parse the items of the ZBI container `zbi`.
+pub fn parse(zbi: &[u8]) -> Result<Vec<ZbiItem<'_>>, ZbiError> {
+    let container = parse_header(zbi, 0).map_err(|_| ZbiError::BadContainer)?;
+    if container.type_ != ZBI_TYPE_CONTAINER || container.extra != ZBI_CONTAINER_MAGIC {
+        return Err(ZbiError::BadContainer);
+    }
+    let end = HEADER_SIZE + container.payload.len();
+    let mut items = Vec::new();
+    let mut offset = HEADER_SIZE;
+    while offset < end {
+        let item = parse_header(&zbi[..end], offset)?;
+        offset += HEADER_SIZE + align_up(item.payload.len());
+        items.push(item);
+    }
+    Ok(items)
+}
+
+/// Decompress the storage items of the ZBI `zbi`, return a ZBI with the
+/// other items unchanged.
+///
+/// The ZBI is borrowed if no item is compressed. The CRC32 of decompressed
+/// items is dropped.
+pub fn decompress(zbi: &[u8]) -> Result<Cow<'_, [u8]>, ZbiError> {
+    let items = parse(zbi)?;
+    if !items.iter().any(ZbiItem::is_compressed) {
+        return Ok(Cow::Borrowed(zbi));
+    }
+    let mut output = Vec::with_capacity(zbi.len());
+    output.extend_from_slice(&zbi[..HEADER_SIZE]);
+    for item in items {
+        let mut flags = item.flags;
+        let mut crc32 = item.crc32;
+        let payload = item.storage()?;
+        if item.is_compressed() {
+            info!(
+                "decompressed ZBI item {:#x}: {} => {} bytes",
+                item.type_,
+                item.payload.len(),
+                payload.len()
+            );
+            flags &= !(ZBI_FLAGS_STORAGE_COMPRESSED | ZBI_FLAGS_CRC32);
+            crc32 = ZBI_ITEM_NO_CRC32;
+        }
+        write_header(
+            &mut output,
+            item.type_,
+            payload.len(),
+            item.extra,
+            flags,
+            crc32,
+        );
+        output.extend_from_slice(&payload);
+        output.resize(align_up(output.len()), 0);
+    }
+    let length = (output.len() - HEADER_SIZE) as u32;
+    output[4..8].copy_from_slice(&length.to_le_bytes());
+    Ok(Cow::Owned(output))
+}
+
+/// Decompress `payload` into `output` by the format of its magic, return the
+/// size of the decompressed data.
+fn decompress_payload(payload: &[u8], output: &mut [u8]) -> Result<usize, ZbiError> {
+    match read_u32(payload, 0).map_err(|_| ZbiError::Corrupted)? {
+        #[cfg(feature = "lz4")]
+        LZ4F_MAGIC => decompress_lz4(payload, output),
+        #[cfg(feature = "zstd")]
+        ZSTD_MAGIC => decompress_zstd(payload, output),
+        magic => {
+            warn!(
+                "unsupported compression of {} bytes: magic={:#x}",
+                output.len(),
+                magic
+            );
+            Err(ZbiError::Unsupported)
+        }
+    }
+}
+
+/// Parse the item header at `offset` of `zbi`, with the payload following it.
+fn parse_header(zbi: &[u8], offset: usize) -> Result<ZbiItem<'_>, ZbiError> {
+    let field = |i: usize| read_u32(zbi, offset + i * 4);
+    let (type_, length, extra, flags) = (field(0)?, field(1)?, field(2)?, field(3)?);
+    let (magic, crc32) = (field(6)?, field(7)?);
+    if magic != ZBI_ITEM_MAGIC || flags & ZBI_FLAGS_VERSION == 0 {
+        return Err(ZbiError::BadItem);
+    }
+    let start = offset + HEADER_SIZE;
+    let payload = zbi
+        .get(start..start + length as usize)
+        .ok_or(ZbiError::BadItem)?;
+    Ok(ZbiItem {
+        type_,
+        extra,
+        flags,
+        crc32,
+        payload,
+    })
+}
+
+/// Append an item header to `output`.
+fn write_header(
+    output: &mut Vec<u8>,
+    type_: u32,
+    length: usize,
+    extra: u32,
+    flags: u32,
+    crc32: u32,
+) {
+    let fields = [
+        type_,
+        length as u32,
+        extra,
+        flags,
+        0,
+        0,
+        ZBI_ITEM_MAGIC,
+        crc32,
+    ];
+    for field in fields.iter() {
+        output.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+/// Read a little-endian `u32` at `offset` of `buf`.
+fn read_u32(buf: &[u8], offset: usize) -> Result<u32, ZbiError> {
+    let bytes = buf.get(offset..offset + 4).ok_or(ZbiError::BadItem)?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn align_up(len: usize) -> usize {
+    (len + ZBI_ALIGNMENT - 1) & !(ZBI_ALIGNMENT - 1)
+}
+
+/// The maximum distance of the matches of LZ4 blocks.
+#[cfg(feature = "lz4")]
+const LZ4_WINDOW_SIZE: usize = 0x10000;
+
+/// Decompress the LZ4 frame `input` into `output`, return the size of the
+/// decompressed data.
+///
+/// The checksums are skipped, and the dictionaries are not supported.
+#[cfg(feature = "lz4")]
+fn decompress_lz4(input: &[u8], output: &mut [u8]) -> Result<usize, ZbiError> {
+    const FLG_VERSION: u8 = 0x40;
+    const FLG_BLOCK_CHECKSUM: u8 = 0x10;
+    const FLG_CONTENT_SIZE: u8 = 0x08;
+    const FLG_CONTENT_CHECKSUM: u8 = 0x04;
+    const FLG_DICT_ID: u8 = 0x01;
+    const BLOCK_UNCOMPRESSED: u32 = 0x8000_0000;
+
+    let flg = *input.get(4).ok_or(ZbiError::Corrupted)?;
+    if flg & 0xc0 != FLG_VERSION {
+        return Err(ZbiError::Corrupted);
+    }
+    if flg & FLG_DICT_ID != 0 {
+        return Err(ZbiError::Unsupported);
+    }
+    // magic, FLG, BD, the optional content size, and HC
+    let mut offset = 7;
+    if flg & FLG_CONTENT_SIZE != 0 {
+        offset += 8;
+    }
+    let checksum_size = if flg & FLG_BLOCK_CHECKSUM != 0 { 4 } else { 0 };
+    let mut pos = 0;
+    loop {
+        let size = read_u32(input, offset).map_err(|_| ZbiError::Corrupted)?;
+        offset += 4;
+        if size == 0 {
+            break;
+        }
+        let len = (size & !BLOCK_UNCOMPRESSED) as usize;
+        let block = input.get(offset..offset + len).ok_or(ZbiError::Corrupted)?;
+        if size & BLOCK_UNCOMPRESSED != 0 {
+            output
+                .get_mut(pos..pos + len)
+                .ok_or(ZbiError::Corrupted)?
+                .copy_from_slice(block);
+            pos += len;
+        } else {
+            // the blocks may be linked, matching the data decompressed before
+            let (dict, rest) = output.split_at_mut(pos);
+            let dict = &dict[dict.len().saturating_sub(LZ4_WINDOW_SIZE)..];
+            pos += lz4_flex::block::decompress_into_with_dict(block, rest, dict)
+                .map_err(|_| ZbiError::Corrupted)?;
+        }
+        offset += len + checksum_size;
+    }
+    if flg & FLG_CONTENT_CHECKSUM != 0 && input.len() < offset + 4 {
+        return Err(ZbiError::Corrupted);
+    }
+    Ok(pos)
+}
+
+/// Decompress the zstd frame `input` into `output`, return the size of the
+/// decompressed data.
+#[cfg(feature = "zstd")]
+fn decompress_zstd(mut input: &[u8], output: &mut [u8]) -> Result<usize, ZbiError> {
+    let mut decoder = ruzstd::FrameDecoder::new();
+    decoder.reset(&mut input).map_err(|_| ZbiError::Corrupted)?;
+    decoder
+        .decode_blocks(&mut input, ruzstd::BlockDecodingStrategy::All)
+        .map_err(|_| ZbiError::Corrupted)?;
+    let data = decoder.collect().ok_or(ZbiError::Corrupted)?;
+    output
+        .get_mut(..data.len())
+        .ok_or(ZbiError::Corrupted)?
+        .copy_from_slice(&data);
+    Ok(data.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The FLG byte of LZ4 frames with independent blocks.
+    #[cfg(feature = "lz4")]
+    const FLG_VERSION_INDEPENDENT: u8 = 0x60;
+
+    /// Build a ZBI container of the items `(type, extra, flags, payload)`.
+    fn build(items: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+        let mut zbi = Vec::new();
+        write_header(
+            &mut zbi,
+            ZBI_TYPE_CONTAINER,
+            0,
+            ZBI_CONTAINER_MAGIC,
+            ZBI_FLAGS_VERSION,
+            ZBI_ITEM_NO_CRC32,
+        );
+        for &(type_, extra, flags, payload) in items {
+            let flags = flags | ZBI_FLAGS_VERSION;
+            write_header(
+                &mut zbi,
+                type_,
+                payload.len(),
+                extra,
+                flags,
+                ZBI_ITEM_NO_CRC32,
+            );
+            zbi.extend_from_slice(payload);
+            zbi.resize(align_up(zbi.len()), 0);
+        }
+        let length = (zbi.len() - HEADER_SIZE) as u32;
+        zbi[4..8].copy_from_slice(&length.to_le_bytes());
+        zbi
+    }
+
+    #[test]
+    fn parse_items() {
+        let zbi = build(&[
+            (0x4c4d_4443, 0, 0, b"cmdline"),
+            (ZBI_TYPE_STORAGE_BOOTFS, 3, 0, b"abc"),
+        ]);
+        let items = parse(&zbi).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].payload, b"cmdline");
+        assert_eq!(items[1].type_, ZBI_TYPE_STORAGE_BOOTFS);
+        assert!(!items[1].is_compressed());
+        // nothing to decompress
+        assert!(matches!(decompress(&zbi), Ok(Cow::Borrowed(_))));
+
+        assert_eq!(
+            parse(&zbi[HEADER_SIZE..]).err(),
+            Some(ZbiError::BadContainer)
+        );
+        assert_eq!(
+            parse(&zbi[..zbi.len() - 8]).err(),
+            Some(ZbiError::BadContainer)
+        );
+        let mut bad = zbi.clone();
+        bad[HEADER_SIZE + 24] ^= 1;
+        assert_eq!(parse(&bad).err(), Some(ZbiError::BadItem));
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn decompress_lz4_frame() {
+        let data: Vec<u8> = (0..0x3000).map(|i| (i % 251) as u8).collect();
+        // an LZ4 frame of a compressed block and an uncompressed block,
+        // without the content size and the checksums
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&LZ4F_MAGIC.to_le_bytes());
+        frame.extend_from_slice(&[FLG_VERSION_INDEPENDENT, 0x70, 0x00]);
+        let block = lz4_flex::block::compress(&data[..0x2000]);
+        frame.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&block);
+        frame.extend_from_slice(&(0x1000 | 0x8000_0000u32).to_le_bytes());
+        frame.extend_from_slice(&data[0x2000..]);
+        frame.extend_from_slice(&0u32.to_le_bytes());
+
+        let flags = ZBI_FLAGS_STORAGE_COMPRESSED | ZBI_FLAGS_CRC32;
+        let zbi = build(&[(ZBI_TYPE_STORAGE_RAMDISK, data.len() as u32, flags, &frame)]);
+        let output = decompress(&zbi).unwrap();
+        let items = parse(&output).unwrap();
+        assert_eq!(items[0].payload, &data[..]);
+        assert_eq!(items[0].flags, ZBI_FLAGS_VERSION);
+        assert_eq!(items[0].crc32, ZBI_ITEM_NO_CRC32);
+
+        // the size does not match
+        let zbi = build(&[(ZBI_TYPE_STORAGE_RAMDISK, 0x2000, flags, &frame)]);
+        assert_eq!(decompress(&zbi).err(), Some(ZbiError::Corrupted));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn decompress_zstd_frame() {
+        let mut data: Vec<u8> = (0..0x100).map(|i| (i % 251) as u8).collect();
+        data.resize(0x180, 0xaa);
+        // a single segment zstd frame of a raw block and an RLE block, with
+        // the content size of 2 bytes and without the checksum
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&ZSTD_MAGIC.to_le_bytes());
+        frame.push(0x60);
+        frame.extend_from_slice(&(data.len() as u16 - 0x100).to_le_bytes());
+        frame.extend_from_slice(&(0x100u32 << 3).to_le_bytes()[..3]);
+        frame.extend_from_slice(&data[..0x100]);
+        frame.extend_from_slice(&(0x80u32 << 3 | 1 << 1 | 1).to_le_bytes()[..3]);
+        frame.push(0xaa);
+
+        let flags = ZBI_FLAGS_STORAGE_COMPRESSED;
+        let zbi = build(&[(ZBI_TYPE_STORAGE_BOOTFS, data.len() as u32, flags, &frame)]);
+        let output = decompress(&zbi).unwrap();
+        let items = parse(&output).unwrap();
+        assert_eq!(items[0].payload, &data[..]);
+        assert_eq!(items[0].flags, ZBI_FLAGS_VERSION);
+
+        // the size does not match, or the frame is truncated
+        let zbi = build(&[(ZBI_TYPE_STORAGE_BOOTFS, 0x100, flags, &frame)]);
+        assert_eq!(decompress(&zbi).err(), Some(ZbiError::Corrupted));
+        let truncated = &frame[..frame.len() - 1];
+        let zbi = build(&[(ZBI_TYPE_STORAGE_BOOTFS, 0x180, flags, truncated)]);
+        assert_eq!(decompress(&zbi).err(), Some(ZbiError::Corrupted));
+    }
+}
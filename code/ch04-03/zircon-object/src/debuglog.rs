@@ -2,16 +2,23 @@
 use {
     super::*,
     crate::object::*,
-    alloc::{sync::Arc, vec::Vec},
+    alloc::{
+        boxed::Box,
+        format,
+        string::{String, ToString},
+        sync::{Arc, Weak},
+        vec,
+        vec::Vec,
+    },
+    core::{convert::TryInto, fmt},
     kernel_hal::timer_now,
     lazy_static::lazy_static,
     spin::Mutex,
 };
 
 lazy_static! {
-    static ref DLOG: Mutex<DlogBuffer> = Mutex::new(DlogBuffer {
-        buf: Vec::with_capacity(0x1000),
-    });
+    static ref DLOG: Mutex<DlogBuffer> = Mutex::new(DlogBuffer::new());
+    static ref READERS: Mutex<Vec<Weak<DebugLog>>> = Mutex::new(Vec::new());
 }
 
 /// Debuglog - Kernel debuglog
@@ -19,41 +26,104 @@ lazy_static! {
 /// ## SYNOPSIS
 ///
 /// Debuglog objects allow userspace to read and write to kernel debug logs.
+///
+/// Each write appends a record, tagged with the timestamp, the PID, TID and
+/// severity, to a fixed-size ring buffer shared by the whole kernel, where
+/// the oldest records are dropped when it is full. A reader reads one record
+/// each time from the oldest one retained when it is created, and is
+/// signaled `READABLE` if there are records not read.
 pub struct DebugLog {
     base: KObjectBase,
     flags: u32,
     read_offset: Mutex<usize>,
 }
 
+/// The ring buffer of records.
 struct DlogBuffer {
-    /// Append only buffer
-    buf: Vec<u8>,
+    buf: Box<[u8]>,
+    /// The offset of the oldest record.
+    tail: usize,
+    /// The offset to write the next record.
+    ///
+    /// Offsets increase monotonically, and wrap around in the buffer.
+    head: usize,
 }
 
 impl_kobject!(DebugLog);
 
+/// Create a reader of the debuglog.
+pub const FLAG_READABLE: u32 = 0x4000_0000;
+
 impl DebugLog {
     /// Create a new `DebugLog`.
     pub fn create(flags: u32) -> Arc<Self> {
-        Arc::new(DebugLog {
+        let dlog = Arc::new(DebugLog {
             base: KObjectBase::new(),
             flags,
             read_offset: Default::default(),
-        })
+        });
+        if flags & FLAG_READABLE != 0 {
+            let (tail, head) = {
+                let buffer = DLOG.lock();
+                (buffer.tail, buffer.head)
+            };
+            *dlog.read_offset.lock() = tail;
+            if tail != head {
+                dlog.base.signal_set(Signal::READABLE);
+            }
+            let mut readers = READERS.lock();
+            readers.retain(|reader| reader.strong_count() != 0);
+            readers.push(Arc::downgrade(&dlog));
+        }
+        dlog
     }
 
-    /// Read a log, return the actual read size.
+    /// Read a log record, return the actual read size.
+    ///
+    /// Return 0 if there is no record to read.
     pub fn read(&self, buf: &mut [u8]) -> usize {
         let mut offset = self.read_offset.lock();
-        let len = DLOG.lock().read_at(*offset, buf);
-        *offset += len;
+        let (len, drained) = {
+            let buffer = DLOG.lock();
+            let len = buffer.read_at(&mut offset, buf);
+            (len, *offset == buffer.head)
+        };
+        if drained {
+            self.base.signal_clear(Signal::READABLE);
+        }
         len
     }
 
     /// Write a log.
     pub fn write(&self, severity: Severity, flags: u32, tid: u64, pid: u64, data: &str) {
-        DLOG.lock()
-            .write(severity, flags | self.flags, tid, pid, data.as_bytes());
+        write_record(severity, flags | self.flags, tid, pid, data.as_bytes());
+    }
+}
+
+/// Write a record of the kernel `log!` output.
+pub fn kernel_log(level: log::Level, tid: u64, pid: u64, args: &fmt::Arguments) {
+    let data = args.to_string();
+    write_record(level.into(), 0, tid, pid, data.as_bytes());
+}
+
+/// Render the newest records as text lines, no longer than `max_len` bytes.
+///
+/// It is used to save the log into the crashlog on panic, so it gives up
+/// if the log is being written, instead of waiting.
+pub fn dump_tail(max_len: usize) -> Option<String> {
+    Some(DLOG.try_lock()?.dump_tail(max_len))
+}
+
+fn write_record(severity: Severity, flags: u32, tid: u64, pid: u64, data: &[u8]) {
+    let data = &data[..data.len().min(DLOG_MAX_DATA)];
+    DLOG.lock().write(severity, flags, tid, pid, data);
+    let readers: Vec<_> = READERS
+        .lock()
+        .iter()
+        .filter_map(|reader| reader.upgrade())
+        .collect();
+    for reader in readers {
+        reader.base.signal_set(Signal::READABLE);
     }
 }
 
@@ -72,7 +142,7 @@ struct DlogHeader {
 /// Log entry severity. Used for coarse filtering of log messages.
 #[allow(missing_docs)]
 #[repr(u8)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Severity {
     Trace = 0x10,
     Debug = 0x20,
@@ -82,30 +152,94 @@ pub enum Severity {
     Fatal = 0x60,
 }
 
+impl From<log::Level> for Severity {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Trace => Severity::Trace,
+            log::Level::Debug => Severity::Debug,
+            log::Level::Info => Severity::Info,
+            log::Level::Warn => Severity::Warning,
+            log::Level::Error => Severity::Error,
+        }
+    }
+}
+
 const HEADER_SIZE: usize = core::mem::size_of::<DlogHeader>();
 /// Max length of Dlog read buffer.
 pub const DLOG_MAX_LEN: usize = 256;
+/// Max length of the data of a record.
+pub const DLOG_MAX_DATA: usize = DLOG_MAX_LEN - HEADER_SIZE;
+/// The size of the ring buffer.
+const DLOG_SIZE: usize = 128 * 1024;
 
 #[allow(unsafe_code)]
 impl DlogBuffer {
-    /// Read one record at offset.
-    #[allow(clippy::cast_ptr_alignment)]
-    fn read_at(&mut self, offset: usize, buf: &mut [u8]) -> usize {
+    fn new() -> Self {
+        DlogBuffer {
+            buf: vec![0; DLOG_SIZE].into_boxed_slice(),
+            tail: 0,
+            head: 0,
+        }
+    }
+
+    /// Read one record at `offset`, and move it to the next record.
+    ///
+    /// The records dropped before `offset` are skipped.
+    fn read_at(&self, offset: &mut usize, buf: &mut [u8]) -> usize {
         assert!(buf.len() >= DLOG_MAX_LEN);
-        if offset == self.buf.len() {
+        if *offset < self.tail {
+            *offset = self.tail;
+        }
+        if *offset == self.head {
             return 0;
         }
-        let header_buf = &self.buf[offset..offset + HEADER_SIZE];
-        buf[..HEADER_SIZE].copy_from_slice(header_buf);
-        let header = unsafe { &*(header_buf.as_ptr() as *const DlogHeader) };
-        let len = (header.rollout & 0xFFF) as usize;
-        buf[HEADER_SIZE..len].copy_from_slice(&self.buf[offset + HEADER_SIZE..offset + len]);
+        let rollout = self.rollout_at(*offset);
+        let len = ((rollout >> 12) & 0xFFF) as usize;
+        self.copy_out(*offset, &mut buf[..len]);
+        *offset += (rollout & 0xFFF) as usize;
         len
     }
 
+    /// Render the newest records as text lines, no longer than `max_len` bytes.
+    fn dump_tail(&self, max_len: usize) -> String {
+        let mut lines = Vec::new();
+        let mut offset = self.tail;
+        let mut buf = [0u8; DLOG_MAX_LEN];
+        loop {
+            let len = self.read_at(&mut offset, &mut buf);
+            if len == 0 {
+                break;
+            }
+            let field = |i: usize| u64::from_ne_bytes(buf[i..i + 8].try_into().unwrap());
+            let (timestamp, pid, tid) = (field(8), field(16), field(24));
+            let data = String::from_utf8_lossy(&buf[HEADER_SIZE..len]);
+            lines.push(format!(
+                "[{:05}.{:03}] {:05}.{:05}> {}\n",
+                timestamp / 1_000_000_000,
+                timestamp / 1_000_000 % 1000,
+                pid,
+                tid,
+                data.trim_end()
+            ));
+        }
+        let mut size = 0;
+        let start = lines
+            .iter()
+            .rposition(|line| {
+                size += line.len();
+                size > max_len
+            })
+            .map_or(0, |i| i + 1);
+        lines[start..].concat()
+    }
+
     fn write(&mut self, severity: Severity, flags: u32, tid: u64, pid: u64, data: &[u8]) {
         let wire_size = HEADER_SIZE + align_up_4(data.len());
         let size = HEADER_SIZE + data.len();
+        // drop the oldest records to make room
+        while self.head + wire_size - self.tail > DLOG_SIZE {
+            self.tail += (self.rollout_at(self.tail) & 0xFFF) as usize;
+        }
         let header = DlogHeader {
             rollout: ((size as u32) << 12) | (wire_size as u32),
             datalen: data.len() as u16,
@@ -116,12 +250,105 @@ impl DlogBuffer {
             tid,
         };
         let header_buf: [u8; HEADER_SIZE] = unsafe { core::mem::transmute(header) };
-        self.buf.extend(header_buf.iter());
-        self.buf.extend(data);
-        self.buf.extend(&[0u8; 4][..wire_size - size]);
+        self.copy_in(self.head, &header_buf);
+        self.copy_in(self.head + HEADER_SIZE, data);
+        self.copy_in(self.head + size, &[0u8; 4][..wire_size - size]);
+        self.head += wire_size;
+    }
+
+    fn rollout_at(&self, offset: usize) -> u32 {
+        let mut rollout = [0u8; 4];
+        self.copy_out(offset, &mut rollout);
+        u32::from_ne_bytes(rollout)
+    }
+
+    fn copy_in(&mut self, offset: usize, data: &[u8]) {
+        let start = offset % DLOG_SIZE;
+        let first = data.len().min(DLOG_SIZE - start);
+        self.buf[start..start + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    fn copy_out(&self, offset: usize, buf: &mut [u8]) {
+        let start = offset % DLOG_SIZE;
+        let first = buf.len().min(DLOG_SIZE - start);
+        buf[..first].copy_from_slice(&self.buf[start..start + first]);
+        let rest = buf.len() - first;
+        buf[first..].copy_from_slice(&self.buf[..rest]);
     }
 }
 
 fn align_up_4(x: usize) -> usize {
     (x + 3) & !3
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer() {
+        kernel_hal_unix::init();
+        let mut buffer = DlogBuffer::new();
+        let mut offset = 0;
+        let mut buf = [0u8; DLOG_MAX_LEN];
+        assert_eq!(buffer.read_at(&mut offset, &mut buf), 0);
+
+        buffer.write(Severity::Info, 0, 2, 1, b"hello");
+        assert_eq!(buffer.read_at(&mut offset, &mut buf), HEADER_SIZE + 5);
+        assert_eq!(&buf[HEADER_SIZE..HEADER_SIZE + 5], b"hello");
+        assert_eq!(buf[6], Severity::Info as u8);
+        assert_eq!(buffer.read_at(&mut offset, &mut buf), 0);
+
+        // the oldest records are dropped
+        let data = [b'x'; DLOG_MAX_DATA];
+        for _ in 0..DLOG_SIZE / DLOG_MAX_LEN {
+            buffer.write(Severity::Info, 0, 2, 1, &data);
+        }
+        buffer.write(Severity::Error, 0, 2, 1, b"last");
+        let mut offset = 0;
+        let mut count = 0;
+        while buffer.read_at(&mut offset, &mut buf) == DLOG_MAX_LEN {
+            assert_eq!(&buf[HEADER_SIZE..], &data[..]);
+            count += 1;
+        }
+        assert_eq!(count, DLOG_SIZE / DLOG_MAX_LEN - 1);
+        assert_eq!(&buf[HEADER_SIZE..HEADER_SIZE + 4], b"last");
+    }
+
+    #[test]
+    fn dump() {
+        kernel_hal_unix::init();
+        let mut buffer = DlogBuffer::new();
+        assert_eq!(buffer.dump_tail(100), "");
+        buffer.write(Severity::Info, 0, 2, 1, b"first\n");
+        buffer.write(Severity::Error, 0, 2, 1, b"second");
+        let text = buffer.dump_tail(DLOG_SIZE);
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("00001.00002> first"));
+        assert!(lines[1].ends_with("00001.00002> second"));
+        // only the newest records that fit are kept
+        assert_eq!(
+            buffer.dump_tail(lines[1].len() + 1),
+            String::from(lines[1]) + "\n"
+        );
+        assert_eq!(buffer.dump_tail(lines[1].len()), "");
+    }
+
+    #[test]
+    fn reader() {
+        kernel_hal_unix::init();
+        let writer = DebugLog::create(0);
+        let reader = DebugLog::create(FLAG_READABLE);
+        let mut buf = [0u8; DLOG_MAX_LEN];
+        while reader.read(&mut buf) != 0 {}
+        assert!(!reader.signal().contains(Signal::READABLE));
+
+        writer.write(Severity::Info, 0, 2, 1, "hello");
+        assert!(reader.signal().contains(Signal::READABLE));
+        // other records may be written concurrently
+        while reader.read(&mut buf) != 0 {}
+        assert!(!reader.signal().contains(Signal::READABLE));
+    }
+}
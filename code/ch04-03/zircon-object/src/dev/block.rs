@@ -0,0 +1,363 @@
+use {
+    crate::ipc::Fifo,
+    crate::object::*,
+    crate::vm::VmObject,
+    alloc::{
+        boxed::Box,
+        collections::BTreeMap,
+        sync::{Arc, Weak},
+        vec,
+    },
+    core::{convert::TryFrom, mem::size_of},
+    kernel_hal::BlockDeviceInfo,
+    spin::Mutex,
+};
+
+/// Block device.
+///
+/// ## SYNOPSIS
+///
+/// A block device object represents a block device found by the HAL. The
+/// requests are sent through a FIFO in the format of the block protocol,
+/// and refer to the VMOs attached to the device by their IDs. They are
+/// served by a kernel task, one at a time.
+pub struct BlockDevice {
+    base: KObjectBase,
+    index: usize,
+    info: BlockDeviceInfo,
+    inner: Mutex<BlockDeviceInner>,
+}
+
+impl_kobject!(BlockDevice);
+
+#[derive(Default)]
+struct BlockDeviceInner {
+    vmos: BTreeMap<u16, Arc<VmObject>>,
+    next_vmoid: u16,
+    /// The client endpoint of the FIFO.
+    fifo: Weak<Fifo>,
+}
+
+/// Information of a block device.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct BlockInfo {
+    /// The number of blocks.
+    pub block_count: u64,
+    /// The size of each block in bytes.
+    pub block_size: u32,
+    /// The maximum size of a request in bytes.
+    pub max_transfer_size: u32,
+    /// Always 0.
+    pub flags: u32,
+    reserved: u32,
+}
+
+/// A request in the FIFO, with the lengths and the offsets in blocks.
+#[repr(C)]
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct BlockFifoRequest {
+    pub opcode: u32,
+    pub reqid: u32,
+    pub group: u16,
+    pub vmoid: u16,
+    pub length: u32,
+    pub vmo_offset: u64,
+    pub dev_offset: u64,
+}
+
+/// A response in the FIFO, to a request or a group of requests.
+#[repr(C)]
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct BlockFifoResponse {
+    pub status: i32,
+    pub reqid: u32,
+    pub group: u16,
+    reserved0: u16,
+    /// The number of requests in the group.
+    pub count: u32,
+    reserved1: u64,
+    reserved2: u64,
+}
+
+/// Read blocks to a VMO.
+pub const BLOCK_OP_READ: u32 = 1;
+/// Write blocks from a VMO.
+pub const BLOCK_OP_WRITE: u32 = 2;
+/// Wait for the previous writes to be persistent.
+pub const BLOCK_OP_FLUSH: u32 = 3;
+/// Detach a VMO.
+pub const BLOCK_OP_CLOSE_VMO: u32 = 5;
+/// The mask of the operation in the opcode, the rest are flags.
+const BLOCK_OP_MASK: u32 = 0xff;
+/// Flag: the request is in a group, only responded with the last one.
+pub const BLOCK_GROUP_ITEM: u32 = 0x400;
+/// Flag: the request is the last one of its group.
+pub const BLOCK_GROUP_LAST: u32 = 0x800;
+
+/// The maximum size of a request in bytes.
+const MAX_TRANSFER_SIZE: usize = 0x10_0000;
+
+/// The number of elements in each direction of the FIFO.
+const FIFO_DEPTH: usize = 128;
+
+impl BlockDevice {
+    /// Get the `index`-th block device found by the HAL.
+    pub fn get_nth(index: usize) -> ZxResult<Arc<Self>> {
+        let info = *kernel_hal::block_devices()
+            .get(index)
+            .ok_or(ZxError::OUT_OF_RANGE)?;
+        Ok(Arc::new(BlockDevice {
+            base: KObjectBase::new(),
+            index,
+            info,
+            inner: Mutex::new(BlockDeviceInner {
+                next_vmoid: 1,
+                ..Default::default()
+            }),
+        }))
+    }
+
+    /// Get information of the device.
+    pub fn info(&self) -> BlockInfo {
+        BlockInfo {
+            block_count: self.info.block_count,
+            block_size: self.info.block_size as u32,
+            max_transfer_size: MAX_TRANSFER_SIZE as u32,
+            ..Default::default()
+        }
+    }
+
+    /// Attach `vmo` to the device, return its ID used in the requests.
+    pub fn attach_vmo(&self, vmo: Arc<VmObject>) -> ZxResult<u16> {
+        let mut inner = self.inner.lock();
+        if inner.vmos.len() >= u16::MAX as usize - 1 {
+            return Err(ZxError::NO_RESOURCES);
+        }
+        // 0 is the invalid ID
+        while inner.next_vmoid == 0 || inner.vmos.contains_key(&inner.next_vmoid) {
+            inner.next_vmoid = inner.next_vmoid.wrapping_add(1);
+        }
+        let vmoid = inner.next_vmoid;
+        inner.vmos.insert(vmoid, vmo);
+        inner.next_vmoid = vmoid.wrapping_add(1);
+        Ok(vmoid)
+    }
+
+    /// Create the FIFO of the requests, return the endpoint of the client.
+    ///
+    /// The requests are served until the client endpoint is closed, and
+    /// return `ALREADY_BOUND` before that.
+    pub fn create_fifo(self: &Arc<Self>) -> ZxResult<Arc<Fifo>> {
+        let mut inner = self.inner.lock();
+        if inner.fifo.upgrade().is_some() {
+            return Err(ZxError::ALREADY_BOUND);
+        }
+        let elem_size = size_of::<BlockFifoRequest>();
+        let (client, server) = Fifo::create(FIFO_DEPTH, elem_size)?;
+        inner.fifo = Arc::downgrade(&client);
+        kernel_hal::Thread::spawn(
+            Box::pin(self.clone().serve(server)),
+            0,
+            kernel_hal::CPU_AFFINITY_ALL,
+        );
+        Ok(client)
+    }
+
+    /// Serve the requests from the FIFO `fifo`, until the peer is closed.
+    async fn serve(self: Arc<Self>, fifo: Arc<Fifo>) {
+        let elem_size = size_of::<BlockFifoRequest>();
+        let object: Arc<dyn KernelObject> = fifo.clone();
+        // the first error and the number of requests of the open groups
+        let mut groups = BTreeMap::<u16, (ZxResult, u32)>::new();
+        loop {
+            let mut request = BlockFifoRequest::default();
+            match fifo.read(elem_size, as_bytes_mut(&mut request)) {
+                Ok(_) => {}
+                Err(ZxError::SHOULD_WAIT) => {
+                    object
+                        .wait_signal(Signal::READABLE | Signal::PEER_CLOSED)
+                        .await;
+                    continue;
+                }
+                Err(_) => return,
+            }
+            let mut status = self.handle(&request);
+            let mut count = 1;
+            if request.opcode & BLOCK_GROUP_ITEM != 0 {
+                let group = groups.entry(request.group).or_insert((Ok(()), 0));
+                group.0 = group.0.and(status);
+                group.1 += 1;
+                if request.opcode & BLOCK_GROUP_LAST == 0 {
+                    continue;
+                }
+                let (group_status, group_count) = groups.remove(&request.group).unwrap();
+                status = group_status;
+                count = group_count;
+            }
+            let response = BlockFifoResponse {
+                status: status.err().map_or(0, |e| e as i32),
+                reqid: request.reqid,
+                group: request.group,
+                count,
+                ..Default::default()
+            };
+            loop {
+                match fifo.write(elem_size, as_bytes(&response)) {
+                    Ok(_) => break,
+                    Err(ZxError::SHOULD_WAIT) => {
+                        object
+                            .wait_signal(Signal::WRITABLE | Signal::PEER_CLOSED)
+                            .await;
+                    }
+                    Err(_) => return,
+                }
+            }
+        }
+    }
+
+    /// Handle a request, except the group.
+    fn handle(&self, request: &BlockFifoRequest) -> ZxResult {
+        match request.opcode & BLOCK_OP_MASK {
+            op @ BLOCK_OP_READ | op @ BLOCK_OP_WRITE => {
+                let vmo = self.inner.lock().vmos.get(&request.vmoid).cloned();
+                let vmo = vmo.ok_or(ZxError::IO)?;
+                let block_size = self.info.block_size;
+                let len = (request.length as usize)
+                    .checked_mul(block_size)
+                    .ok_or(ZxError::INVALID_ARGS)?;
+                if len == 0 || len > MAX_TRANSFER_SIZE {
+                    return Err(ZxError::INVALID_ARGS);
+                }
+                let dev_end = request.dev_offset.checked_add(request.length as u64);
+                if dev_end.map_or(true, |end| end > self.info.block_count) {
+                    return Err(ZxError::OUT_OF_RANGE);
+                }
+                // the offset in the VMO is given by the client, in blocks
+                let vmo_offset = usize::try_from(request.vmo_offset)
+                    .ok()
+                    .and_then(|offset| offset.checked_mul(block_size))
+                    .filter(|offset| offset.checked_add(len).is_some())
+                    .ok_or(ZxError::OUT_OF_RANGE)?;
+                let mut buf = vec![0u8; len];
+                if op == BLOCK_OP_READ {
+                    kernel_hal::block_read(self.index, request.dev_offset, &mut buf)
+                        .map_err(|_| ZxError::IO)?;
+                    vmo.write(vmo_offset, &buf)
+                } else {
+                    vmo.read(vmo_offset, &mut buf)?;
+                    kernel_hal::block_write(self.index, request.dev_offset, &buf)
+                        .map_err(|_| ZxError::IO)
+                }
+            }
+            // the writes are done before they are responded
+            BLOCK_OP_FLUSH => Ok(()),
+            BLOCK_OP_CLOSE_VMO => {
+                let vmo = self.inner.lock().vmos.remove(&request.vmoid);
+                vmo.map(|_| ()).ok_or(ZxError::IO)
+            }
+            _ => Err(ZxError::NOT_SUPPORTED),
+        }
+    }
+}
+
+/// View a `repr(C)` struct as bytes.
+#[allow(unsafe_code)]
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+/// View a `repr(C)` struct of plain integers as mutable bytes.
+#[allow(unsafe_code)]
+fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn read_write() {
+        kernel_hal_unix::init();
+        // unique to the test process, as the test binaries may run together
+        let name = format!("zircon-object-block-test-{}.img", std::process::id());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, [0u8; 4 * 512]).unwrap();
+        kernel_hal_unix::block_add_file(&path).unwrap();
+        let index = kernel_hal::block_devices().len() - 1;
+        let device = BlockDevice::get_nth(index).unwrap();
+        assert_eq!(device.info().block_count, 4);
+        assert_eq!(device.info().block_size, 512);
+
+        let vmo = VmObject::new_paged(1);
+        vmo.write(512, &[0x5a; 512]).unwrap();
+        let vmoid = device.attach_vmo(vmo.clone()).unwrap();
+        let fifo = device.create_fifo().unwrap();
+        assert_eq!(device.create_fifo().err(), Some(ZxError::ALREADY_BOUND));
+        let object: Arc<dyn KernelObject> = fifo.clone();
+
+        let transact = |request: BlockFifoRequest| {
+            let fifo = fifo.clone();
+            let object = object.clone();
+            async move {
+                fifo.write(size_of::<BlockFifoRequest>(), as_bytes(&request))
+                    .unwrap();
+                object.wait_signal(Signal::READABLE).await;
+                let mut response = BlockFifoResponse::default();
+                fifo.read(size_of::<BlockFifoResponse>(), as_bytes_mut(&mut response))
+                    .unwrap();
+                assert_eq!(response.reqid, request.reqid);
+                response.status
+            }
+        };
+        // write the block 1 of the VMO to the block 2
+        let write = BlockFifoRequest {
+            opcode: BLOCK_OP_WRITE,
+            reqid: 1,
+            vmoid,
+            length: 1,
+            vmo_offset: 1,
+            dev_offset: 2,
+            ..Default::default()
+        };
+        assert_eq!(transact(write).await, 0);
+        let data = std::fs::read(&path).unwrap();
+        assert!(data[1024..1536].iter().all(|&b| b == 0x5a));
+
+        // read the blocks 2..4 to the VMO
+        let read = BlockFifoRequest {
+            opcode: BLOCK_OP_READ,
+            reqid: 2,
+            vmoid,
+            length: 2,
+            dev_offset: 2,
+            ..Default::default()
+        };
+        assert_eq!(transact(read).await, 0);
+        let mut buf = [0u8; 1024];
+        vmo.read(0, &mut buf).unwrap();
+        assert!(buf[..512].iter().all(|&b| b == 0x5a));
+        assert!(buf[512..].iter().all(|&b| b == 0));
+
+        let out_of_range = BlockFifoRequest {
+            dev_offset: 3,
+            ..read
+        };
+        assert_eq!(transact(out_of_range).await, ZxError::OUT_OF_RANGE as i32);
+        let vmo_overflow = BlockFifoRequest {
+            vmo_offset: u64::MAX,
+            ..read
+        };
+        assert_eq!(transact(vmo_overflow).await, ZxError::OUT_OF_RANGE as i32);
+        let close = BlockFifoRequest {
+            opcode: BLOCK_OP_CLOSE_VMO,
+            ..read
+        };
+        assert_eq!(transact(close).await, 0);
+        assert_eq!(transact(read).await, ZxError::IO as i32);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+use {
+    super::*,
+    crate::object::*,
+    crate::vm::*,
+    alloc::{sync::Arc, vec::Vec},
+    bitflags::bitflags,
+    spin::Mutex,
+};
+
+/// Bus Transaction Initiator.
+///
+/// ## SYNOPSIS
+///
+/// A BTI represents the bus mastering/DMA capability of a device, and can be
+/// used for granting a device access to memory. It is created from the IOMMU
+/// which translates the addresses of the device.
+///
+/// Memory is granted by pinning a range of VMO, which returns a PMT and the
+/// device addresses of the range. The quarantined ranges of the BTI are
+/// released when it is destroyed.
+pub struct BusTransactionInitiator {
+    base: KObjectBase,
+    iommu: Arc<Iommu>,
+    bti_id: u64,
+    inner: Mutex<BtiInner>,
+}
+
+impl_kobject!(BusTransactionInitiator);
+
+#[derive(Default)]
+struct BtiInner {
+    /// The number of PMTs which are pinned.
+    pmo_count: usize,
+    /// Ranges pinned by the PMTs which are closed without unpinning.
+    quarantine: Vec<PinnedRange>,
+}
+
+bitflags! {
+    /// Options to pin a range of VMO.
+    pub struct IommuPerms: u32 {
+        #[allow(clippy::identity_op)]
+        const PERM_READ         = 1 << 0;
+        const PERM_WRITE        = 1 << 1;
+        const PERM_EXECUTE      = 1 << 2;
+        /// Return one address for each `minimum_contiguity` bytes.
+        const COMPRESS          = 1 << 3;
+        /// Return one address for the whole range.
+        const CONTIGUOUS        = 1 << 4;
+    }
+}
+
+/// Information of a BTI.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct BtiInfo {
+    /// The minimum contiguity of the compressed addresses.
+    pub minimum_contiguity: u64,
+    /// The size of the device address space.
+    pub aspace_size: u64,
+    /// The number of pinned PMTs.
+    pub pmo_count: u64,
+    /// The number of quarantined PMTs.
+    pub quarantine_count: u64,
+}
+
+impl BusTransactionInitiator {
+    /// Create a new BTI for the device `bti_id` behind `iommu`.
+    pub fn create(iommu: Arc<Iommu>, bti_id: u64) -> ZxResult<Arc<Self>> {
+        if !iommu.is_valid_bus_txn_id(bti_id) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        Ok(Arc::new(BusTransactionInitiator {
+            base: KObjectBase::new(),
+            iommu,
+            bti_id,
+            inner: Mutex::new(BtiInner::default()),
+        }))
+    }
+
+    /// Get the IOMMU of the BTI.
+    pub fn iommu(&self) -> &Arc<Iommu> {
+        &self.iommu
+    }
+
+    /// Get the ID of the device.
+    pub fn bti_id(&self) -> u64 {
+        self.bti_id
+    }
+
+    /// Get information of the BTI.
+    pub fn get_info(&self) -> BtiInfo {
+        let inner = self.inner.lock();
+        BtiInfo {
+            minimum_contiguity: self.minimum_contiguity() as u64,
+            aspace_size: self.iommu.aspace_size(self.bti_id) as u64,
+            pmo_count: inner.pmo_count as u64,
+            quarantine_count: inner.quarantine.len() as u64,
+        }
+    }
+
+    /// Pin the range of `vmo` for the device, return a PMT of it.
+    ///
+    /// The range must be page aligned and non-empty.
+    pub fn pin(
+        self: &Arc<Self>,
+        vmo: Arc<VmObject>,
+        offset: usize,
+        size: usize,
+        perms: IommuPerms,
+    ) -> ZxResult<Arc<PinnedMemoryToken>> {
+        if size == 0 || !page_aligned(offset) || !page_aligned(size) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let pmt = PinnedMemoryToken::create(self, vmo, perms, offset, size)?;
+        self.inner.lock().pmo_count += 1;
+        Ok(pmt)
+    }
+
+    /// Get the minimum contiguity of the compressed addresses.
+    pub fn minimum_contiguity(&self) -> usize {
+        self.iommu.minimum_contiguity(self.bti_id)
+    }
+
+    /// Release all quarantined ranges.
+    pub fn release_quarantine(&self) {
+        let quarantine = core::mem::take(&mut self.inner.lock().quarantine);
+        drop(quarantine);
+    }
+
+    /// Called when a PMT is unpinned.
+    pub(super) fn release_pmt(&self) {
+        self.inner.lock().pmo_count -= 1;
+    }
+
+    /// Called when a PMT is closed without unpinning.
+    pub(super) fn quarantine(&self, pinned: PinnedRange) {
+        let mut inner = self.inner.lock();
+        inner.pmo_count -= 1;
+        inner.quarantine.push(pinned);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_bti() -> Arc<BusTransactionInitiator> {
+        let iommu = Iommu::create(IommuType::Dummy, &[0]).unwrap();
+        BusTransactionInitiator::create(iommu, 0).unwrap()
+    }
+
+    #[test]
+    fn pin_unpin() {
+        kernel_hal_unix::init();
+        let bti = create_bti();
+        let vmo = VmObject::new_paged(4);
+        assert_eq!(
+            bti.pin(vmo.clone(), 0, 0, IommuPerms::PERM_READ).err(),
+            Some(ZxError::INVALID_ARGS)
+        );
+        let pmt = bti
+            .pin(vmo.clone(), PAGE_SIZE, 2 * PAGE_SIZE, IommuPerms::PERM_READ)
+            .unwrap();
+        assert_eq!(bti.get_info().pmo_count, 1);
+        let addrs = pmt.encode_addrs(false, false, PAGE_SIZE).unwrap();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(
+            pmt.encode_addrs(true, true, PAGE_SIZE).err(),
+            Some(ZxError::INVALID_ARGS)
+        );
+        // pinned pages can not be decommitted
+        assert_eq!(vmo.decommit(0, 4 * PAGE_SIZE), Err(ZxError::BAD_STATE));
+
+        pmt.unpin().unwrap();
+        assert_eq!(pmt.unpin(), Err(ZxError::BAD_STATE));
+        assert_eq!(bti.get_info().pmo_count, 0);
+        assert!(vmo.decommit(0, 4 * PAGE_SIZE).is_ok());
+    }
+
+    #[test]
+    fn quarantine() {
+        kernel_hal_unix::init();
+        let bti = create_bti();
+        let vmo = VmObject::new_paged(1);
+        let pmt = bti
+            .pin(vmo.clone(), 0, PAGE_SIZE, IommuPerms::PERM_WRITE)
+            .unwrap();
+        drop(pmt);
+        let info = bti.get_info();
+        assert_eq!((info.pmo_count, info.quarantine_count), (0, 1));
+        assert_eq!(vmo.decommit(0, PAGE_SIZE), Err(ZxError::BAD_STATE));
+
+        bti.release_quarantine();
+        assert_eq!(bti.get_info().quarantine_count, 0);
+        assert!(vmo.decommit(0, PAGE_SIZE).is_ok());
+
+        // released on BTI destruction
+        bti.pin(vmo.clone(), 0, PAGE_SIZE, IommuPerms::PERM_READ)
+            .unwrap();
+        drop(bti);
+        assert!(vmo.decommit(0, PAGE_SIZE).is_ok());
+    }
+}
@@ -0,0 +1,385 @@
+use {
+    crate::object::*,
+    crate::signal::*,
+    crate::vm::VmObject,
+    alloc::{
+        boxed::Box,
+        sync::{Arc, Weak},
+        vec::Vec,
+    },
+    core::{
+        cell::UnsafeCell,
+        convert::TryInto,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        task::Poll,
+    },
+    futures::{future::poll_fn, task::AtomicWaker},
+    kernel_hal::NetDeviceInfo,
+    spin::Mutex,
+};
+
+/// Ethernet device.
+///
+/// ## SYNOPSIS
+///
+/// An ethernet device object represents a network device found by the HAL.
+/// The frames are exchanged through two rings in VMOs shared with the
+/// userspace driver. The received frames are put to the RX ring, notified
+/// by an interrupt packet to the bound port. The frames put to the TX ring
+/// are sent when the device is kicked.
+///
+/// A ring VMO starts with an [`EthRingHeader`], followed by the slots of
+/// `ETH_RING_SLOT_SIZE` bytes, each holding the length of the frame in the
+/// first 4 bytes, and the frame from `ETH_FRAME_OFFSET`.
+///
+/// [`EthRingHeader`]: struct.EthRingHeader.html
+pub struct EthDevice {
+    base: KObjectBase,
+    index: usize,
+    info: NetDeviceInfo,
+    inner: Mutex<EthDeviceInner>,
+    rx_queue: Arc<RxQueue>,
+}
+
+impl_kobject!(EthDevice);
+
+#[derive(Default)]
+struct EthDeviceInner {
+    rx_ring: Option<Arc<VmObject>>,
+    tx_ring: Option<Arc<VmObject>>,
+    port: Option<(Arc<Port>, u64)>,
+}
+
+/// The frames received by the HAL, waiting to be put to the RX ring.
+///
+/// The HAL may call back in the interrupt, so the frames are put without
+/// locks or allocations, to a ring of fixed slots. The callback is called
+/// one frame at a time, the only producer, and the delivering task is the
+/// only consumer.
+struct RxQueue {
+    slots: Box<[RxSlot]>,
+    /// The next index to put, written by the callback.
+    head: AtomicUsize,
+    /// The next index to take, written by the delivering task.
+    tail: AtomicUsize,
+    waker: AtomicWaker,
+    closed: AtomicBool,
+}
+
+/// A slot of `RxQueue`, with the length and the frame.
+struct RxSlot(UnsafeCell<(usize, [u8; ETH_RING_SLOT_SIZE - ETH_FRAME_OFFSET])>);
+
+// the slots between `tail` and `head` are only accessed by the consumer,
+// and the others by the producer
+#[allow(unsafe_code)]
+unsafe impl Sync for RxQueue {}
+
+impl RxQueue {
+    fn new() -> Self {
+        let slots = (0..RX_QUEUE_LEN)
+            .map(|_| {
+                RxSlot(UnsafeCell::new((
+                    0,
+                    [0; ETH_RING_SLOT_SIZE - ETH_FRAME_OFFSET],
+                )))
+            })
+            .collect();
+        RxQueue {
+            slots,
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            waker: AtomicWaker::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Put a frame, truncated to a slot of the RX ring, and wake the
+    /// consumer. It is dropped if the queue is full or closed.
+    #[allow(unsafe_code)]
+    fn push(&self, frame: &[u8]) {
+        let head = self.head.load(Ordering::Relaxed);
+        let tail = self.tail.load(Ordering::Acquire);
+        if self.closed.load(Ordering::Acquire) || head.wrapping_sub(tail) >= RX_QUEUE_LEN {
+            return;
+        }
+        let slot = unsafe { &mut *self.slots[head % RX_QUEUE_LEN].0.get() };
+        let len = frame.len().min(slot.1.len());
+        slot.0 = len;
+        slot.1[..len].copy_from_slice(&frame[..len]);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Take the first frame.
+    #[allow(unsafe_code)]
+    fn pop(&self) -> Option<Vec<u8>> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+        let slot = unsafe { &*self.slots[tail % RX_QUEUE_LEN].0.get() };
+        let frame = slot.1[..slot.0].to_vec();
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(frame)
+    }
+
+    /// Drop the following frames, and stop the consumer.
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+/// Information of an ethernet device.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct EthInfo {
+    /// Always 0.
+    pub features: u32,
+    /// The maximum size of the payload of the frames.
+    pub mtu: u32,
+    /// The MAC address.
+    pub mac: [u8; 6],
+    padding: [u8; 2],
+}
+
+/// The header of a ring VMO.
+///
+/// The indexes increase, wrapping at `u32::MAX`, and the slot of an index
+/// is the index modulo the number of slots.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct EthRingHeader {
+    /// The next index to put, written by the producer.
+    pub head: u32,
+    /// The next index to take, written by the consumer.
+    pub tail: u32,
+}
+
+/// The size of the header and each slot of a ring VMO.
+pub const ETH_RING_SLOT_SIZE: usize = 2048;
+/// The offset of the frame in a slot.
+pub const ETH_FRAME_OFFSET: usize = 8;
+/// The status: the link is up.
+pub const ETH_STATUS_ONLINE: u32 = 1;
+
+/// The maximum number of the received frames waiting for the delivery.
+const RX_QUEUE_LEN: usize = 64;
+
+impl EthDevice {
+    /// Get the `index`-th network device found by the HAL.
+    ///
+    /// The received frames are only delivered to the last object created on
+    /// the device.
+    pub fn get_nth(index: usize) -> ZxResult<Arc<Self>> {
+        let info = *kernel_hal::net_devices()
+            .get(index)
+            .ok_or(ZxError::OUT_OF_RANGE)?;
+        let device = Arc::new(EthDevice {
+            base: KObjectBase::new(),
+            index,
+            info,
+            inner: Mutex::new(EthDeviceInner::default()),
+            rx_queue: Arc::new(RxQueue::new()),
+        });
+        let rx_queue = device.rx_queue.clone();
+        kernel_hal::net_set_rx_callback(index, Some(Box::new(move |frame| rx_queue.push(frame))))
+            .map_err(|_| ZxError::IO)?;
+        let future = deliver(Arc::downgrade(&device), device.rx_queue.clone());
+        kernel_hal::Thread::spawn(Box::pin(future), 0, kernel_hal::CPU_AFFINITY_ALL);
+        Ok(device)
+    }
+
+    /// Get information of the device.
+    pub fn info(&self) -> EthInfo {
+        EthInfo {
+            mtu: self.info.mtu as u32,
+            mac: self.info.mac,
+            ..Default::default()
+        }
+    }
+
+    /// Get the status of the device.
+    pub fn status(&self) -> u32 {
+        match kernel_hal::net_link_up(self.index) {
+            true => ETH_STATUS_ONLINE,
+            false => 0,
+        }
+    }
+
+    /// Set the VMOs of the RX and TX rings.
+    ///
+    /// Each VMO should have at least one slot after the header.
+    pub fn set_rings(&self, rx_ring: Arc<VmObject>, tx_ring: Arc<VmObject>) -> ZxResult {
+        if slot_count(&rx_ring) == 0 || slot_count(&tx_ring) == 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut inner = self.inner.lock();
+        inner.rx_ring = Some(rx_ring);
+        inner.tx_ring = Some(tx_ring);
+        Ok(())
+    }
+
+    /// Bind the device to `port` with `key`, to notify the received frames.
+    pub fn bind_port(&self, port: Arc<Port>, key: u64) -> ZxResult {
+        self.inner.lock().port = Some((port, key));
+        Ok(())
+    }
+
+    /// Send the frames in the TX ring, return the number of frames sent.
+    ///
+    /// The indexes in the header are written by the userspace, and are
+    /// rejected if more frames than the slots are between them.
+    pub fn tx_kick(&self) -> ZxResult<usize> {
+        let tx_ring = self.inner.lock().tx_ring.clone();
+        let tx_ring = tx_ring.ok_or(ZxError::BAD_STATE)?;
+        let count = slot_count(&tx_ring) as u32;
+        let mut header = read_header(&tx_ring)?;
+        if header.head.wrapping_sub(header.tail) > count {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut sent = 0;
+        let mut frame = [0u8; ETH_RING_SLOT_SIZE - ETH_FRAME_OFFSET];
+        while header.tail != header.head {
+            let offset = (header.tail % count + 1) as usize * ETH_RING_SLOT_SIZE;
+            let mut len = [0u8; 4];
+            tx_ring.read(offset, &mut len)?;
+            let len = u32::from_ne_bytes(len) as usize;
+            if len > frame.len() {
+                return Err(ZxError::OUT_OF_RANGE);
+            }
+            tx_ring.read(offset + ETH_FRAME_OFFSET, &mut frame[..len])?;
+            kernel_hal::net_send(self.index, &frame[..len]).map_err(|_| ZxError::IO)?;
+            header.tail = header.tail.wrapping_add(1);
+            tx_ring.write(4, &header.tail.to_ne_bytes())?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Put a received frame to the RX ring, and notify the bound port.
+    ///
+    /// The frame is dropped if the ring is full or not set.
+    fn receive(&self, frame: &[u8]) -> ZxResult {
+        let inner = self.inner.lock();
+        let rx_ring = inner.rx_ring.as_ref().ok_or(ZxError::BAD_STATE)?;
+        let count = slot_count(rx_ring) as u32;
+        let header = read_header(rx_ring)?;
+        if header.head.wrapping_sub(header.tail) >= count {
+            return Err(ZxError::SHOULD_WAIT);
+        }
+        let len = frame.len().min(ETH_RING_SLOT_SIZE - ETH_FRAME_OFFSET);
+        let offset = (header.head % count + 1) as usize * ETH_RING_SLOT_SIZE;
+        rx_ring.write(offset, &(len as u32).to_ne_bytes())?;
+        rx_ring.write(offset + ETH_FRAME_OFFSET, &frame[..len])?;
+        rx_ring.write(0, &header.head.wrapping_add(1).to_ne_bytes())?;
+        if let Some((port, key)) = &inner.port {
+            port.push(PortPacket {
+                key: *key,
+                status: 0,
+                data: Payload::Interrupt(PacketInterrupt {
+                    timestamp: kernel_hal::timer_now().as_nanos() as i64,
+                }),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EthDevice {
+    fn drop(&mut self) {
+        self.rx_queue.close();
+    }
+}
+
+/// Put the frames received by the HAL to the RX ring of `device`, until it
+/// is dropped.
+async fn deliver(device: Weak<EthDevice>, rx_queue: Arc<RxQueue>) {
+    loop {
+        let frame = poll_fn(|cx| {
+            // checked again after the waker is registered, not to miss a wake
+            for registered in [false, true].iter() {
+                if rx_queue.closed.load(Ordering::Acquire) {
+                    return Poll::Ready(None);
+                }
+                if let Some(frame) = rx_queue.pop() {
+                    return Poll::Ready(Some(frame));
+                }
+                if !registered {
+                    rx_queue.waker.register(cx.waker());
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        match (frame, device.upgrade()) {
+            (Some(frame), Some(device)) => device.receive(&frame).ok(),
+            _ => return,
+        };
+    }
+}
+
+/// The number of slots in a ring VMO.
+fn slot_count(ring: &VmObject) -> usize {
+    (ring.len() / ETH_RING_SLOT_SIZE).saturating_sub(1)
+}
+
+fn read_header(ring: &VmObject) -> ZxResult<EthRingHeader> {
+    let mut buf = [0u8; 8];
+    ring.read(0, &mut buf)?;
+    Ok(EthRingHeader {
+        head: u32::from_ne_bytes(buf[..4].try_into().unwrap()),
+        tail: u32::from_ne_bytes(buf[4..].try_into().unwrap()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[async_std::test]
+    async fn loopback() {
+        kernel_hal_unix::init();
+        kernel_hal_unix::net_add_loopback([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        let index = kernel_hal::net_devices().len() - 1;
+        let device = EthDevice::get_nth(index).unwrap();
+        assert_eq!(device.info().mac, [0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+        assert_eq!(device.status(), ETH_STATUS_ONLINE);
+        assert_eq!(device.tx_kick(), Err(ZxError::BAD_STATE));
+
+        let rx_ring = VmObject::new_paged(1);
+        let tx_ring = VmObject::new_paged(1);
+        assert_eq!(
+            device.set_rings(rx_ring.clone(), VmObject::new_paged(0)),
+            Err(ZxError::INVALID_ARGS)
+        );
+        device.set_rings(rx_ring.clone(), tx_ring.clone()).unwrap();
+        let port = Port::new(0).unwrap();
+        device.bind_port(port.clone(), 7).unwrap();
+
+        // put a frame to the slot 0 of the TX ring
+        let frame = [0xabu8; 60];
+        tx_ring
+            .write(ETH_RING_SLOT_SIZE, &60u32.to_ne_bytes())
+            .unwrap();
+        tx_ring
+            .write(ETH_RING_SLOT_SIZE + ETH_FRAME_OFFSET, &frame)
+            .unwrap();
+        tx_ring.write(0, &1u32.to_ne_bytes()).unwrap();
+        assert_eq!(device.tx_kick(), Ok(1));
+        assert_eq!(read_header(&tx_ring).unwrap().tail, 1);
+        // more frames than the slots
+        tx_ring.write(0, &3u32.to_ne_bytes()).unwrap();
+        assert_eq!(device.tx_kick(), Err(ZxError::INVALID_ARGS));
+        tx_ring.write(0, &1u32.to_ne_bytes()).unwrap();
+
+        // the frame is looped back to the RX ring
+        let packet = port.wait().await;
+        assert_eq!(packet.key, 7);
+        assert_eq!(read_header(&rx_ring).unwrap().head, 1);
+        let mut buf = [0u8; 64];
+        rx_ring.read(ETH_RING_SLOT_SIZE, &mut buf).unwrap();
+        assert_eq!(buf[..4], 60u32.to_ne_bytes());
+        assert_eq!(buf[ETH_FRAME_OFFSET..], frame[..64 - ETH_FRAME_OFFSET]);
+    }
+}
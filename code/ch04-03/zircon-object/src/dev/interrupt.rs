@@ -0,0 +1,447 @@
+use {
+    crate::object::*, crate::signal::*, alloc::boxed::Box, alloc::sync::Arc, bitflags::bitflags,
+    kernel_hal::IrqMode, spin::Mutex,
+};
+
+/// Hardware or virtual interrupt.
+///
+/// ## SYNOPSIS
+///
+/// A virtual interrupt is fired by `trigger`, and a physical interrupt is
+/// fired by its IRQ. The firing is either observed by a thread blocking in
+/// `wait`, or, if the interrupt is bound to a port, delivered as a
+/// `PacketType::Interrupt` packet with the timestamp.
+///
+/// A bound interrupt does not deliver another packet until it is acked
+/// by `ack`. Triggers before that are coalesced into one packet.
+///
+/// A level triggered IRQ is masked when it fires, and unmasked when it is
+/// acked, or when `wait` is called again.
+pub struct Interrupt {
+    base: KObjectBase,
+    kind: InterruptKind,
+    inner: Mutex<InterruptInner>,
+}
+
+impl_kobject!(Interrupt);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum InterruptKind {
+    Virtual,
+    Physical { vector: u32, level: bool },
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum InterruptState {
+    Idle,
+    /// Fired but not observed by `wait`.
+    Triggered,
+    /// A packet has been sent to the bound port but not acked.
+    NeedAck,
+    Destroyed,
+}
+
+struct InterruptInner {
+    state: InterruptState,
+    timestamp: i64,
+    /// Whether it has been fired again while waiting for ack.
+    pending: bool,
+    /// Whether the level triggered IRQ is masked.
+    masked: bool,
+    port: Option<Arc<Port>>,
+    key: u64,
+    /// The ID of the packet still queued in the port.
+    packet_id: Option<u64>,
+}
+
+bitflags! {
+    /// Options to create an interrupt.
+    pub struct InterruptOptions: u32 {
+        #[allow(clippy::identity_op)]
+        /// Remap the IRQ number of a PCI device.
+        const REMAP_IRQ         = 1 << 0;
+        const MODE_EDGE_LOW     = 1 << 1;
+        const MODE_EDGE_HIGH    = 2 << 1;
+        const MODE_LEVEL_LOW    = 3 << 1;
+        const MODE_LEVEL_HIGH   = 4 << 1;
+        const MODE_EDGE_BOTH    = 5 << 1;
+        const MODE_MASK         = 0xe;
+        /// A virtual interrupt which is only fired by `trigger`.
+        const VIRTUAL           = 1 << 4;
+    }
+}
+
+impl InterruptOptions {
+    /// Get the IRQ mode, edge high by default.
+    fn mode(self) -> ZxResult<IrqMode> {
+        match (self & Self::MODE_MASK).bits() >> 1 {
+            0 | 2 => Ok(IrqMode::EdgeHigh),
+            1 => Ok(IrqMode::EdgeLow),
+            3 => Ok(IrqMode::LevelLow),
+            4 => Ok(IrqMode::LevelHigh),
+            5 => Ok(IrqMode::EdgeBoth),
+            _ => Err(ZxError::INVALID_ARGS),
+        }
+    }
+}
+
+impl Interrupt {
+    /// Create a new virtual interrupt.
+    pub fn new_virtual() -> Arc<Self> {
+        Self::new(InterruptKind::Virtual)
+    }
+
+    /// Create a new interrupt of the physical IRQ `vector`.
+    ///
+    /// Return `ALREADY_BOUND` if the IRQ is claimed by another interrupt.
+    pub fn new_physical(vector: u32, options: InterruptOptions) -> ZxResult<Arc<Self>> {
+        let mode = options.mode()?;
+        let interrupt = Self::new(InterruptKind::Physical {
+            vector,
+            level: mode.is_level(),
+        });
+        let weak = Arc::downgrade(&interrupt);
+        let handler = Box::new(move || {
+            if let Some(interrupt) = weak.upgrade() {
+                interrupt.handle_irq();
+            }
+        });
+        if kernel_hal::irq_register(vector, mode, handler).is_err() {
+            // not registered, nothing to release
+            interrupt.inner.lock().state = InterruptState::Destroyed;
+            return Err(ZxError::ALREADY_BOUND);
+        }
+        kernel_hal::irq_enable(vector);
+        Ok(interrupt)
+    }
+
+    fn new(kind: InterruptKind) -> Arc<Self> {
+        Arc::new(Interrupt {
+            base: KObjectBase::default(),
+            kind,
+            inner: Mutex::new(InterruptInner {
+                state: InterruptState::Idle,
+                timestamp: 0,
+                pending: false,
+                masked: false,
+                port: None,
+                key: 0,
+                packet_id: None,
+            }),
+        })
+    }
+
+    /// Bind the interrupt to a port with `key`.
+    ///
+    /// The port must be created with `PortOptions::BIND_TO_INTERRUPT`.
+    pub fn bind(&self, port: &Arc<Port>, key: u64) -> ZxResult {
+        let mut inner = self.inner.lock();
+        if inner.state == InterruptState::Destroyed {
+            return Err(ZxError::CANCELED);
+        }
+        if !port.options().contains(PortOptions::BIND_TO_INTERRUPT) {
+            return Err(ZxError::WRONG_TYPE);
+        }
+        if inner.port.is_some() {
+            return Err(ZxError::ALREADY_BOUND);
+        }
+        inner.port = Some(port.clone());
+        inner.key = key;
+        // deliver the firing which is not observed yet
+        if inner.state == InterruptState::Triggered {
+            self.base.signal_clear(Signal::INTERRUPT_SIGNAL);
+            inner.send_packet();
+        }
+        Ok(())
+    }
+
+    /// Unbind the interrupt from `port`, and remove the queued packet.
+    pub fn unbind(&self, port: &Arc<Port>) -> ZxResult {
+        let mut inner = self.inner.lock();
+        if inner.state == InterruptState::Destroyed {
+            return Err(ZxError::CANCELED);
+        }
+        match &inner.port {
+            Some(bound) if Arc::ptr_eq(bound, port) => {}
+            _ => return Err(ZxError::NOT_FOUND),
+        }
+        inner.remove_packet();
+        inner.port = None;
+        inner.pending = false;
+        inner.state = InterruptState::Idle;
+        self.unmask(&mut inner);
+        Ok(())
+    }
+
+    /// Fire the virtual interrupt at `timestamp`.
+    ///
+    /// Return `BAD_STATE` if it is a physical interrupt.
+    pub fn trigger(&self, timestamp: i64) -> ZxResult {
+        if self.kind != InterruptKind::Virtual {
+            return Err(ZxError::BAD_STATE);
+        }
+        self.fire(timestamp)
+    }
+
+    /// Called by the IRQ handler of a physical interrupt.
+    fn handle_irq(&self) {
+        if let InterruptKind::Physical {
+            vector,
+            level: true,
+        } = self.kind
+        {
+            kernel_hal::irq_disable(vector);
+            self.inner.lock().masked = true;
+        }
+        let timestamp = kernel_hal::timer_now().as_nanos() as i64;
+        self.fire(timestamp).ok();
+    }
+
+    fn fire(&self, timestamp: i64) -> ZxResult {
+        let mut inner = self.inner.lock();
+        match inner.state {
+            InterruptState::Destroyed => return Err(ZxError::CANCELED),
+            InterruptState::NeedAck => inner.pending = true,
+            _ if inner.port.is_some() => {
+                inner.timestamp = timestamp;
+                inner.send_packet();
+                return Ok(());
+            }
+            _ => {
+                inner.state = InterruptState::Triggered;
+                self.base.signal_set(Signal::INTERRUPT_SIGNAL);
+            }
+        }
+        inner.timestamp = timestamp;
+        Ok(())
+    }
+
+    /// Acknowledge the packet of a bound interrupt, so that it can be
+    /// delivered again.
+    pub fn ack(&self) -> ZxResult {
+        let mut inner = self.inner.lock();
+        if inner.state == InterruptState::Destroyed {
+            return Err(ZxError::CANCELED);
+        }
+        if inner.port.is_none() {
+            return Err(ZxError::BAD_STATE);
+        }
+        if inner.state == InterruptState::NeedAck {
+            if inner.pending {
+                inner.pending = false;
+                inner.send_packet();
+            } else {
+                inner.packet_id = None;
+                inner.state = InterruptState::Idle;
+                self.unmask(&mut inner);
+            }
+        }
+        Ok(())
+    }
+
+    /// Asynchronous wait until the interrupt is fired, return the timestamp.
+    ///
+    /// It can not be used on an interrupt bound to a port.
+    pub async fn wait(self: &Arc<Self>) -> ZxResult<i64> {
+        let object = self.clone() as Arc<dyn KernelObject>;
+        loop {
+            {
+                let mut inner = self.inner.lock();
+                if inner.state == InterruptState::Destroyed {
+                    return Err(ZxError::CANCELED);
+                }
+                if inner.port.is_some() {
+                    return Err(ZxError::BAD_STATE);
+                }
+                if inner.state == InterruptState::Triggered {
+                    inner.state = InterruptState::Idle;
+                    self.base.signal_clear(Signal::INTERRUPT_SIGNAL);
+                    return Ok(inner.timestamp);
+                }
+                // the last firing has been observed
+                self.unmask(&mut inner);
+            }
+            object.wait_signal(Signal::INTERRUPT_SIGNAL).await;
+        }
+    }
+
+    /// Destroy the interrupt, cancel all waiters and remove the queued packet.
+    pub fn destroy(&self) -> ZxResult {
+        let mut inner = self.inner.lock();
+        if inner.state == InterruptState::Destroyed {
+            return Ok(());
+        }
+        if let InterruptKind::Physical { vector, .. } = self.kind {
+            kernel_hal::irq_disable(vector);
+            kernel_hal::irq_unregister(vector).ok();
+        }
+        inner.remove_packet();
+        inner.state = InterruptState::Destroyed;
+        self.base.signal_set(Signal::INTERRUPT_SIGNAL);
+        Ok(())
+    }
+
+    /// Unmask the level triggered IRQ if it is masked.
+    fn unmask(&self, inner: &mut InterruptInner) {
+        if let InterruptKind::Physical {
+            vector,
+            level: true,
+        } = self.kind
+        {
+            if inner.masked {
+                inner.masked = false;
+                kernel_hal::irq_enable(vector);
+            }
+        }
+    }
+}
+
+impl Drop for Interrupt {
+    fn drop(&mut self) {
+        self.destroy().ok();
+    }
+}
+
+impl InterruptInner {
+    fn send_packet(&mut self) {
+        let port = self.port.as_ref().unwrap();
+        let id = port.push_interrupt(PortPacket {
+            key: self.key,
+            status: 0,
+            data: Payload::Interrupt(PacketInterrupt {
+                timestamp: self.timestamp,
+            }),
+        });
+        self.packet_id = Some(id);
+        self.state = InterruptState::NeedAck;
+    }
+
+    fn remove_packet(&mut self) {
+        if let (Some(port), Some(id)) = (&self.port, self.packet_id.take()) {
+            port.remove_interrupt(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn interrupt_packet(key: u64, timestamp: i64) -> PortPacket {
+        PortPacket {
+            key,
+            status: 0,
+            data: Payload::Interrupt(PacketInterrupt { timestamp }),
+        }
+    }
+
+    #[async_std::test]
+    async fn trigger_wait() {
+        kernel_hal_unix::init();
+        let interrupt = Interrupt::new_virtual();
+        assert_eq!(interrupt.wait().now_or_never(), None);
+        interrupt.trigger(10).unwrap();
+        assert_eq!(interrupt.wait().await, Ok(10));
+        assert_eq!(interrupt.wait().now_or_never(), None);
+
+        interrupt.destroy().unwrap();
+        assert_eq!(interrupt.wait().await, Err(ZxError::CANCELED));
+        assert_eq!(interrupt.trigger(20), Err(ZxError::CANCELED));
+    }
+
+    #[test]
+    fn bind_port() {
+        kernel_hal_unix::init();
+        let interrupt = Interrupt::new_virtual();
+        let port = Port::new(0).unwrap();
+        assert_eq!(interrupt.bind(&port, 1), Err(ZxError::WRONG_TYPE));
+        let port = Port::new(1).unwrap();
+        assert_eq!(interrupt.ack(), Err(ZxError::BAD_STATE));
+        interrupt.bind(&port, 1).unwrap();
+        assert_eq!(interrupt.bind(&port, 1), Err(ZxError::ALREADY_BOUND));
+        assert_eq!(
+            interrupt.wait().now_or_never(),
+            Some(Err(ZxError::BAD_STATE))
+        );
+
+        interrupt.trigger(10).unwrap();
+        // coalesced until acked
+        interrupt.trigger(20).unwrap();
+        interrupt.trigger(30).unwrap();
+        assert_eq!(port.try_pop(), Some(interrupt_packet(1, 10)));
+        assert_eq!(port.try_pop(), None);
+        interrupt.ack().unwrap();
+        assert_eq!(port.try_pop(), Some(interrupt_packet(1, 30)));
+        interrupt.ack().unwrap();
+        assert_eq!(port.try_pop(), None);
+
+        // the queued packet is removed when unbound
+        interrupt.trigger(40).unwrap();
+        assert_eq!(port.len(), 1);
+        interrupt.unbind(&port).unwrap();
+        assert!(port.is_empty());
+        assert_eq!(interrupt.unbind(&port), Err(ZxError::NOT_FOUND));
+    }
+
+    #[test]
+    fn bind_after_trigger() {
+        kernel_hal_unix::init();
+        let interrupt = Interrupt::new_virtual();
+        let port = Port::new(1).unwrap();
+        interrupt.trigger(10).unwrap();
+        interrupt.bind(&port, 2).unwrap();
+        assert_eq!(interrupt.signal(), Signal::empty());
+        assert_eq!(port.try_pop(), Some(interrupt_packet(2, 10)));
+
+        interrupt.ack().unwrap();
+        interrupt.trigger(20).unwrap();
+        interrupt.destroy().unwrap();
+        assert!(port.is_empty());
+        assert_eq!(interrupt.ack(), Err(ZxError::CANCELED));
+    }
+
+    #[async_std::test]
+    async fn physical_level() {
+        kernel_hal_unix::init();
+        const VECTOR: u32 = 0x70;
+        let options = InterruptOptions::MODE_LEVEL_HIGH;
+        let interrupt = Interrupt::new_physical(VECTOR, options).unwrap();
+        assert_eq!(
+            Interrupt::new_physical(VECTOR, options).err(),
+            Some(ZxError::ALREADY_BOUND)
+        );
+        assert_eq!(interrupt.trigger(10), Err(ZxError::BAD_STATE));
+
+        assert!(kernel_hal_unix::irq_handle(VECTOR));
+        assert!(interrupt.wait().await.unwrap() > 0);
+        // masked until the next wait
+        assert!(!kernel_hal_unix::irq_handle(VECTOR));
+        assert_eq!(interrupt.wait().now_or_never(), None);
+        assert!(kernel_hal_unix::irq_handle(VECTOR));
+
+        drop(interrupt);
+        assert!(!kernel_hal_unix::irq_handle(VECTOR));
+        assert!(Interrupt::new_physical(VECTOR, options).is_ok());
+    }
+
+    #[test]
+    fn physical_edge_port() {
+        kernel_hal_unix::init();
+        const VECTOR: u32 = 0x71;
+        let interrupt = Interrupt::new_physical(VECTOR, InterruptOptions::empty()).unwrap();
+        let port = Port::new(1).unwrap();
+        interrupt.bind(&port, 3).unwrap();
+
+        // edge triggered IRQs are never masked
+        assert!(kernel_hal_unix::irq_handle(VECTOR));
+        assert!(kernel_hal_unix::irq_handle(VECTOR));
+        assert_eq!(port.try_pop().map(|p| p.key), Some(3));
+        assert!(port.is_empty());
+        interrupt.ack().unwrap();
+        assert_eq!(
+            port.try_pop().map(|p| p.type_()),
+            Some(PacketType::Interrupt)
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use {
+    super::*,
+    crate::object::*,
+    crate::vm::*,
+    alloc::{sync::Arc, vec::Vec},
+    kernel_hal::MMUFlags,
+    numeric_enum_macro::numeric_enum,
+};
+
+numeric_enum! {
+    #[repr(u32)]
+    /// The type of IOMMU hardware.
+    #[allow(missing_docs)]
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum IommuType {
+        Dummy = 0,
+        Intel = 1,
+    }
+}
+
+/// The maximum length of an IOMMU descriptor.
+pub const IOMMU_MAX_DESC_LEN: usize = 4096;
+
+/// The size of `zx_iommu_desc_dummy_t`.
+const IOMMU_DUMMY_DESC_LEN: usize = 1;
+
+/// Input/Output Memory Management Unit.
+///
+/// ## SYNOPSIS
+///
+/// An IOMMU translates the addresses used by devices, and acts as the parent
+/// of BTIs.
+///
+/// Only the dummy IOMMU is supported now, which grants devices access to all
+/// physical memory, so that the device address of a page is its physical
+/// address.
+pub struct Iommu {
+    base: KObjectBase,
+    type_: IommuType,
+}
+
+impl_kobject!(Iommu);
+
+impl Iommu {
+    /// Create a new IOMMU of `type_` with the descriptor `desc`.
+    pub fn create(type_: IommuType, desc: &[u8]) -> ZxResult<Arc<Self>> {
+        match type_ {
+            IommuType::Dummy if desc.len() == IOMMU_DUMMY_DESC_LEN => {}
+            IommuType::Dummy => return Err(ZxError::INVALID_ARGS),
+            IommuType::Intel => return Err(ZxError::NOT_SUPPORTED),
+        }
+        Ok(Arc::new(Iommu {
+            base: KObjectBase::new(),
+            type_,
+        }))
+    }
+
+    /// Get the type of the IOMMU.
+    pub fn type_(&self) -> IommuType {
+        self.type_
+    }
+
+    /// Whether `bti_id` is a valid bus transaction ID.
+    pub fn is_valid_bus_txn_id(&self, _bti_id: u64) -> bool {
+        true
+    }
+
+    /// Get the minimum contiguity of the device addresses.
+    pub fn minimum_contiguity(&self, _bti_id: u64) -> usize {
+        PAGE_SIZE
+    }
+
+    /// Get the size of the device address space.
+    pub fn aspace_size(&self, _bti_id: u64) -> usize {
+        usize::MAX
+    }
+
+    /// Map the committed pages of `vmo` in the range for the device, return
+    /// the device address of each page.
+    pub fn map(
+        &self,
+        _bti_id: u64,
+        vmo: &VmObject,
+        offset: usize,
+        size: usize,
+        perms: IommuPerms,
+    ) -> ZxResult<Vec<DevVAddr>> {
+        let flags = if perms.contains(IommuPerms::PERM_WRITE) {
+            MMUFlags::READ | MMUFlags::WRITE
+        } else {
+            MMUFlags::READ
+        };
+        (offset / PAGE_SIZE..pages(offset + size))
+            .map(|page_idx| vmo.commit_page(page_idx, flags))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create() {
+        kernel_hal_unix::init();
+        assert_eq!(
+            Iommu::create(IommuType::Dummy, &[]).err(),
+            Some(ZxError::INVALID_ARGS)
+        );
+        assert_eq!(
+            Iommu::create(IommuType::Intel, &[0]).err(),
+            Some(ZxError::NOT_SUPPORTED)
+        );
+        let iommu = Iommu::create(IommuType::Dummy, &[0]).unwrap();
+        assert_eq!(iommu.type_(), IommuType::Dummy);
+    }
+}
@@ -1,5 +1,12 @@
 //! Objects for Device Drivers.
 
+mod block;
+mod bti;
+mod eth;
+mod interrupt;
+mod iommu;
+mod pci;
+mod pmt;
 mod resource;
 
-pub use self::resource::*;
+pub use self::{block::*, bti::*, eth::*, interrupt::*, iommu::*, pci::*, pmt::*, resource::*};
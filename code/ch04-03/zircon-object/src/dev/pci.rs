@@ -0,0 +1,295 @@
+use {
+    super::*,
+    crate::object::*,
+    crate::vm::*,
+    alloc::sync::Arc,
+    kernel_hal::{PciAddr, PciBar, PciDeviceInfo},
+    numeric_enum_macro::numeric_enum,
+    spin::Mutex,
+};
+
+/// PCI device.
+///
+/// ## SYNOPSIS
+///
+/// A PCI device object represents a function found by the PCI bus
+/// enumeration of the HAL. It gives a userspace driver access to the config
+/// space, the BARs and the interrupts of the function.
+pub struct PciDevice {
+    base: KObjectBase,
+    info: PciDeviceInfo,
+    inner: Mutex<PciDeviceInner>,
+}
+
+impl_kobject!(PciDevice);
+
+struct PciDeviceInner {
+    irq_mode: PciIrqMode,
+    irq_count: u32,
+    /// The first MSI vector if MSI is enabled.
+    msi_base: Option<u32>,
+}
+
+numeric_enum! {
+    #[repr(u32)]
+    /// The interrupt mode of a PCI device.
+    #[allow(missing_docs)]
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum PciIrqMode {
+        Disabled = 0,
+        Legacy = 1,
+        LegacyNoAck = 2,
+        Msi = 3,
+        MsiX = 4,
+    }
+}
+
+/// Information of a PCI device.
+#[repr(C)]
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PcieDeviceInfo {
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub base_class: u8,
+    pub sub_class: u8,
+    pub program_interface: u8,
+    pub revision_id: u8,
+    pub bus_id: u8,
+    pub dev_id: u8,
+    pub func_id: u8,
+    padding1: u8,
+}
+
+/// The size of the config space.
+pub const PCI_CONFIG_SIZE: usize = 256;
+
+/// The size of the standard config header, which is not writable by drivers.
+const PCI_CONFIG_HDR_SIZE: usize = 64;
+
+/// The number of BARs.
+pub const PCI_MAX_BAR_COUNT: usize = 6;
+
+impl PciDevice {
+    /// Get the `index`-th PCI function found by the bus enumeration.
+    pub fn get_nth(index: usize) -> ZxResult<Arc<Self>> {
+        let info = *kernel_hal::pci_enumerate()
+            .get(index)
+            .ok_or(ZxError::OUT_OF_RANGE)?;
+        Ok(Arc::new(PciDevice {
+            base: KObjectBase::new(),
+            info,
+            inner: Mutex::new(PciDeviceInner {
+                irq_mode: PciIrqMode::Disabled,
+                irq_count: 0,
+                msi_base: None,
+            }),
+        }))
+    }
+
+    /// Get the address of the function.
+    pub fn addr(&self) -> PciAddr {
+        self.info.addr
+    }
+
+    /// Get information of the device.
+    pub fn info(&self) -> PcieDeviceInfo {
+        let info = &self.info;
+        PcieDeviceInfo {
+            vendor_id: info.vendor_id,
+            device_id: info.device_id,
+            base_class: info.base_class,
+            sub_class: info.sub_class,
+            program_interface: info.program_interface,
+            revision_id: info.revision_id,
+            bus_id: info.addr.bus,
+            dev_id: info.addr.device,
+            func_id: info.addr.function,
+            padding1: 0,
+        }
+    }
+
+    /// Read `width` bytes at `offset` of the config space.
+    pub fn config_read(&self, offset: usize, width: usize) -> ZxResult<u32> {
+        check_config_access(offset, width)?;
+        kernel_hal::pci_config_read(self.info.addr, offset, width).map_err(|_| ZxError::IO)
+    }
+
+    /// Write `width` bytes at `offset` of the config space.
+    ///
+    /// The standard config header can not be written.
+    pub fn config_write(&self, offset: usize, width: usize, value: u32) -> ZxResult {
+        check_config_access(offset, width)?;
+        if offset < PCI_CONFIG_HDR_SIZE {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        kernel_hal::pci_config_write(self.info.addr, offset, width, value).map_err(|_| ZxError::IO)
+    }
+
+    /// Get the BAR `bar_num`.
+    pub fn get_bar(&self, bar_num: usize) -> ZxResult<PciBar> {
+        if bar_num >= PCI_MAX_BAR_COUNT {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        self.info.bars[bar_num].ok_or(ZxError::NOT_FOUND)
+    }
+
+    /// Create a physical VMO of the MMIO BAR `bar_num`.
+    pub fn map_bar(&self, bar_num: usize) -> ZxResult<Arc<VmObject>> {
+        match self.get_bar(bar_num)? {
+            PciBar::Mmio { addr, size } if page_aligned(addr) => {
+                Ok(VmObject::new_physical(addr, pages(size)))
+            }
+            PciBar::Mmio { .. } => Err(ZxError::NOT_SUPPORTED),
+            PciBar::Pio { .. } => Err(ZxError::WRONG_TYPE),
+        }
+    }
+
+    /// Get the maximum number of interrupts supported in `mode`.
+    pub fn query_irq_mode(&self, mode: PciIrqMode) -> ZxResult<u32> {
+        match mode {
+            PciIrqMode::Disabled => Ok(0),
+            PciIrqMode::Legacy | PciIrqMode::LegacyNoAck if self.info.legacy_irq.is_some() => Ok(1),
+            PciIrqMode::Msi if self.info.msi_count != 0 => Ok(self.info.msi_count),
+            _ => Err(ZxError::NOT_SUPPORTED),
+        }
+    }
+
+    /// Set the interrupt mode with `requested_irq_count` interrupts.
+    pub fn set_irq_mode(&self, mode: PciIrqMode, requested_irq_count: u32) -> ZxResult {
+        let max = self.query_irq_mode(mode)?;
+        let valid = match mode {
+            PciIrqMode::Disabled => requested_irq_count == 0,
+            PciIrqMode::Msi => requested_irq_count.is_power_of_two(),
+            _ => requested_irq_count != 0,
+        };
+        if !valid || requested_irq_count > max {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut inner = self.inner.lock();
+        if inner.msi_base.take().is_some() {
+            kernel_hal::pci_msi_free(self.info.addr);
+        }
+        if mode == PciIrqMode::Msi {
+            let base = kernel_hal::pci_msi_alloc(self.info.addr, requested_irq_count)
+                .map_err(|_| ZxError::NO_RESOURCES)?;
+            inner.msi_base = Some(base);
+        }
+        inner.irq_mode = mode;
+        inner.irq_count = requested_irq_count;
+        Ok(())
+    }
+
+    /// Create an interrupt object of the `which_irq`-th interrupt in the
+    /// current mode.
+    pub fn map_interrupt(&self, which_irq: u32) -> ZxResult<Arc<Interrupt>> {
+        let inner = self.inner.lock();
+        if which_irq >= inner.irq_count {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        match inner.irq_mode {
+            PciIrqMode::Legacy | PciIrqMode::LegacyNoAck => {
+                let vector = self.info.legacy_irq.unwrap();
+                Interrupt::new_physical(vector, InterruptOptions::MODE_LEVEL_LOW)
+            }
+            PciIrqMode::Msi => {
+                let vector = inner.msi_base.unwrap() + which_irq;
+                Interrupt::new_physical(vector, InterruptOptions::MODE_EDGE_HIGH)
+            }
+            _ => Err(ZxError::BAD_STATE),
+        }
+    }
+}
+
+impl Drop for PciDevice {
+    fn drop(&mut self) {
+        if self.inner.lock().msi_base.is_some() {
+            kernel_hal::pci_msi_free(self.info.addr);
+        }
+    }
+}
+
+/// Check the access of `width` bytes at `offset` of the config space.
+fn check_config_access(offset: usize, width: usize) -> ZxResult {
+    if !matches!(width, 1 | 2 | 4) || offset % width != 0 {
+        return Err(ZxError::INVALID_ARGS);
+    }
+    if offset + width > PCI_CONFIG_SIZE {
+        return Err(ZxError::OUT_OF_RANGE);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device() {
+        kernel_hal_unix::init();
+        let addr = PciAddr {
+            bus: 0,
+            device: 3,
+            function: 0,
+        };
+        let mut bars = [None; PCI_MAX_BAR_COUNT];
+        bars[0] = Some(PciBar::Mmio {
+            addr: 0xfebc_0000,
+            size: 0x2_0000,
+        });
+        bars[1] = Some(PciBar::Pio {
+            addr: 0xc000,
+            size: 0x40,
+        });
+        kernel_hal_unix::pci_add_device(PciDeviceInfo {
+            addr,
+            vendor_id: 0x8086,
+            device_id: 0x100e,
+            base_class: 2,
+            sub_class: 0,
+            program_interface: 0,
+            revision_id: 3,
+            bars,
+            legacy_irq: Some(0x72),
+            msi_count: 4,
+        });
+        let device = (0..)
+            .map(PciDevice::get_nth)
+            .find(|d| d.as_ref().map_or(true, |d| d.addr() == addr))
+            .unwrap()
+            .unwrap();
+        assert_eq!(device.info().device_id, 0x100e);
+
+        // config space
+        assert_eq!(device.config_read(0, 2), Ok(0x8086));
+        assert_eq!(device.config_read(1, 2), Err(ZxError::INVALID_ARGS));
+        assert_eq!(device.config_read(0x100, 4), Err(ZxError::OUT_OF_RANGE));
+        assert_eq!(device.config_write(4, 2, 0), Err(ZxError::ACCESS_DENIED));
+        device.config_write(0x40, 4, 0x1234_5678).unwrap();
+        assert_eq!(device.config_read(0x42, 2), Ok(0x1234));
+
+        // BARs
+        assert_eq!(device.map_bar(0).unwrap().len(), 0x2_0000);
+        assert_eq!(device.map_bar(1).err(), Some(ZxError::WRONG_TYPE));
+        assert_eq!(device.get_bar(2), Err(ZxError::NOT_FOUND));
+
+        // interrupts
+        assert_eq!(device.map_interrupt(0).err(), Some(ZxError::INVALID_ARGS));
+        assert_eq!(device.query_irq_mode(PciIrqMode::Msi), Ok(4));
+        assert_eq!(
+            device.query_irq_mode(PciIrqMode::MsiX),
+            Err(ZxError::NOT_SUPPORTED)
+        );
+        device.set_irq_mode(PciIrqMode::Legacy, 1).unwrap();
+        let interrupt = device.map_interrupt(0).unwrap();
+        assert!(kernel_hal_unix::irq_handle(0x72));
+        drop(interrupt);
+        assert_eq!(
+            device.set_irq_mode(PciIrqMode::Msi, 3),
+            Err(ZxError::INVALID_ARGS)
+        );
+        device.set_irq_mode(PciIrqMode::Msi, 2).unwrap();
+        assert!(device.map_interrupt(1).is_ok());
+        assert_eq!(device.map_interrupt(2).err(), Some(ZxError::INVALID_ARGS));
+    }
+}
@@ -0,0 +1,126 @@
+use {
+    super::*,
+    crate::object::*,
+    crate::vm::*,
+    alloc::{
+        sync::{Arc, Weak},
+        vec::Vec,
+    },
+    spin::Mutex,
+};
+
+/// Pinned Memory Token.
+///
+/// ## SYNOPSIS
+///
+/// A PMT represents a range of a VMO pinned by a BTI, which keeps the pages
+/// committed and their device addresses valid until it is unpinned.
+///
+/// If a PMT is closed without unpinning, the pages can not be released
+/// safely since a device may still access them. They are moved into the
+/// quarantine of the BTI instead.
+pub struct PinnedMemoryToken {
+    base: KObjectBase,
+    bti: Weak<BusTransactionInitiator>,
+    vmo: Arc<VmObject>,
+    offset: usize,
+    size: usize,
+    /// The device address of each page.
+    mapped_addrs: Vec<DevVAddr>,
+    pinned: Mutex<Option<PinnedRange>>,
+}
+
+impl_kobject!(PinnedMemoryToken);
+
+/// A pinned range of VMO, unpinned when dropped.
+pub(super) struct PinnedRange {
+    vmo: Arc<VmObject>,
+    offset: usize,
+    size: usize,
+}
+
+impl Drop for PinnedRange {
+    fn drop(&mut self) {
+        self.vmo.unpin(self.offset, self.size).unwrap();
+    }
+}
+
+impl PinnedMemoryToken {
+    /// Pin the range of `vmo` and create a PMT for it.
+    pub(super) fn create(
+        bti: &Arc<BusTransactionInitiator>,
+        vmo: Arc<VmObject>,
+        perms: IommuPerms,
+        offset: usize,
+        size: usize,
+    ) -> ZxResult<Arc<Self>> {
+        vmo.pin(offset, size)?;
+        let pinned = PinnedRange {
+            vmo: vmo.clone(),
+            offset,
+            size,
+        };
+        let mapped_addrs = bti.iommu().map(bti.bti_id(), &vmo, offset, size, perms)?;
+        Ok(Arc::new(PinnedMemoryToken {
+            base: KObjectBase::new(),
+            bti: Arc::downgrade(bti),
+            vmo,
+            offset,
+            size,
+            mapped_addrs,
+            pinned: Mutex::new(Some(pinned)),
+        }))
+    }
+
+    /// Get the VMO and the range pinned by the PMT.
+    pub fn range(&self) -> (&Arc<VmObject>, usize, usize) {
+        (&self.vmo, self.offset, self.size)
+    }
+
+    /// Encode the device addresses of the pinned pages.
+    ///
+    /// If `compress`, return one address for each `min_contiguity` bytes.
+    /// If `contiguous`, return the only address of the whole range.
+    /// Otherwise, return one address for each page.
+    pub fn encode_addrs(
+        &self,
+        compress: bool,
+        contiguous: bool,
+        min_contiguity: usize,
+    ) -> ZxResult<Vec<DevVAddr>> {
+        if compress && contiguous {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        if contiguous {
+            if !self.vmo.is_contiguous() {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            return Ok(self.mapped_addrs[..1].to_vec());
+        }
+        if compress {
+            let step = min_contiguity / PAGE_SIZE;
+            return Ok(self.mapped_addrs.iter().step_by(step).cloned().collect());
+        }
+        Ok(self.mapped_addrs.clone())
+    }
+
+    /// Unpin the pages, after which their device addresses are invalid.
+    pub fn unpin(&self) -> ZxResult {
+        let pinned = self.pinned.lock().take().ok_or(ZxError::BAD_STATE)?;
+        drop(pinned);
+        if let Some(bti) = self.bti.upgrade() {
+            bti.release_pmt();
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PinnedMemoryToken {
+    fn drop(&mut self) {
+        if let Some(pinned) = self.pinned.lock().take() {
+            if let Some(bti) = self.bti.upgrade() {
+                bti.quarantine(pinned);
+            }
+        }
+    }
+}
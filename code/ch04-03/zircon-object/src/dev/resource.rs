@@ -1,4 +1,11 @@
-use {crate::object::*, alloc::sync::Arc, bitflags::bitflags, numeric_enum_macro::numeric_enum};
+use {
+    crate::object::*,
+    alloc::{sync::Arc, vec::Vec},
+    bitflags::bitflags,
+    core::ops::Range,
+    numeric_enum_macro::numeric_enum,
+    spin::Mutex,
+};
 
 numeric_enum! {
     #[repr(u32)]
@@ -27,7 +34,31 @@ bitflags! {
     }
 }
 
+impl ResourceKind {
+    /// Whether the resource of this kind grants access to a range of
+    /// addresses or numbers.
+    pub fn is_ranged(self) -> bool {
+        matches!(
+            self,
+            ResourceKind::MMIO | ResourceKind::IRQ | ResourceKind::IOPORT | ResourceKind::SMC
+        )
+    }
+}
+
+/// Ranges claimed by exclusive resources, and the koids of the resources.
+static EXCLUSIVE_RANGES: Mutex<Vec<(ResourceKind, Range<usize>, KoID)>> = Mutex::new(Vec::new());
+
 /// Address space rights and accounting.
+///
+/// ## SYNOPSIS
+///
+/// A resource of a ranged kind grants access to the range `[addr, addr+len)`
+/// of MMIO addresses, IRQ numbers, I/O ports or SMC service calls. A child
+/// resource is created from the root resource, or from a resource of the same
+/// kind whose range contains the child's.
+///
+/// An exclusive resource claims its range, so that no other resource of the
+/// same kind can be created in the range until it is destroyed.
 pub struct Resource {
     base: KObjectBase,
     kind: ResourceKind,
@@ -56,6 +87,49 @@ impl Resource {
         })
     }
 
+    /// Create a new `Resource` from the parent resource.
+    ///
+    /// The range of a ranged resource must be within the range of the parent,
+    /// and must not overlap with any range claimed by an exclusive resource.
+    pub fn create_child(
+        &self,
+        name: &str,
+        kind: ResourceKind,
+        addr: usize,
+        len: usize,
+        flags: ResourceFlags,
+    ) -> ZxResult<Arc<Self>> {
+        self.check_exclusive(flags)?;
+        if !kind.is_ranged() {
+            self.validate(kind)?;
+            return Ok(Self::create(name, kind, addr, len, flags));
+        }
+        self.validate_ranged_resource(kind, addr, len)?;
+        let range = addr..addr + len;
+        let mut claimed = EXCLUSIVE_RANGES.lock();
+        let overlapped = claimed
+            .iter()
+            .any(|(k, r, _)| *k == kind && r.start < range.end && range.start < r.end);
+        if overlapped {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        let resource = Self::create(name, kind, addr, len, flags);
+        if flags.contains(ResourceFlags::EXCLUSIVE) {
+            claimed.push((kind, range, resource.id()));
+        }
+        Ok(resource)
+    }
+
+    /// Get the kind of the resource.
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    /// Get the range of the resource.
+    pub fn range(&self) -> Range<usize> {
+        self.addr..self.addr + self.len
+    }
+
     /// Validate the resource is the given kind or it is the root resource.
     pub fn validate(&self, kind: ResourceKind) -> ZxResult {
         if self.kind == kind || self.kind == ResourceKind::ROOT {
@@ -67,6 +141,8 @@ impl Resource {
 
     /// Validate the resource is the given kind or it is the root resource,
     /// and [addr, addr+len] is within the range of the resource.
+    ///
+    /// The root resource grants access to any range.
     pub fn validate_ranged_resource(
         &self,
         kind: ResourceKind,
@@ -74,7 +150,11 @@ impl Resource {
         len: usize,
     ) -> ZxResult {
         self.validate(kind)?;
-        if addr >= self.addr && (addr + len) <= (self.addr + self.len) {
+        let end = addr.checked_add(len).ok_or(ZxError::OUT_OF_RANGE)?;
+        if self.kind == ResourceKind::ROOT {
+            return Ok(());
+        }
+        if addr >= self.addr && end <= self.addr + self.len {
             Ok(())
         } else {
             Err(ZxError::OUT_OF_RANGE)
@@ -94,3 +174,112 @@ impl Resource {
         }
     }
 }
+
+impl Drop for Resource {
+    fn drop(&mut self) {
+        if self.flags.contains(ResourceFlags::EXCLUSIVE) {
+            let id = self.id();
+            EXCLUSIVE_RANGES.lock().retain(|&(_, _, koid)| koid != id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root() -> Arc<Resource> {
+        Resource::create("root", ResourceKind::ROOT, 0, 0, ResourceFlags::empty())
+    }
+
+    #[test]
+    fn ranged() {
+        kernel_hal_unix::init();
+        let root = root();
+        let mmio = root
+            .create_child(
+                "mmio",
+                ResourceKind::MMIO,
+                0x1000,
+                0x2000,
+                ResourceFlags::empty(),
+            )
+            .unwrap();
+        assert_eq!(mmio.range(), 0x1000..0x3000);
+        assert!(mmio
+            .validate_ranged_resource(ResourceKind::MMIO, 0x2000, 0x1000)
+            .is_ok());
+        assert_eq!(
+            mmio.validate_ranged_resource(ResourceKind::MMIO, 0x2000, 0x2000),
+            Err(ZxError::OUT_OF_RANGE)
+        );
+        assert_eq!(
+            mmio.validate_ranged_resource(ResourceKind::IRQ, 0x2000, 1),
+            Err(ZxError::WRONG_TYPE)
+        );
+        // children must be within the parent
+        assert!(mmio
+            .create_child(
+                "uart",
+                ResourceKind::MMIO,
+                0x1000,
+                0x1000,
+                ResourceFlags::empty()
+            )
+            .is_ok());
+        assert_eq!(
+            mmio.create_child(
+                "uart",
+                ResourceKind::MMIO,
+                0x0,
+                0x2000,
+                ResourceFlags::empty()
+            )
+            .err(),
+            Some(ZxError::OUT_OF_RANGE)
+        );
+        assert_eq!(
+            mmio.create_child("irq", ResourceKind::IRQ, 0x1000, 1, ResourceFlags::empty())
+                .err(),
+            Some(ZxError::WRONG_TYPE)
+        );
+    }
+
+    #[test]
+    fn exclusive() {
+        kernel_hal_unix::init();
+        let root = root();
+        let ioport = root
+            .create_child(
+                "com1",
+                ResourceKind::IOPORT,
+                0x3f8,
+                8,
+                ResourceFlags::EXCLUSIVE,
+            )
+            .unwrap();
+        assert!(ioport.check_exclusive(ResourceFlags::empty()).is_err());
+        for flags in [ResourceFlags::empty(), ResourceFlags::EXCLUSIVE].iter() {
+            assert_eq!(
+                root.create_child("com1", ResourceKind::IOPORT, 0x3f0, 0x10, *flags)
+                    .err(),
+                Some(ZxError::ACCESS_DENIED)
+            );
+        }
+        // other kinds are not affected
+        assert!(root
+            .create_child("irq", ResourceKind::IRQ, 0x3f8, 8, ResourceFlags::EXCLUSIVE)
+            .is_ok());
+
+        drop(ioport);
+        assert!(root
+            .create_child(
+                "com1",
+                ResourceKind::IOPORT,
+                0x3f8,
+                8,
+                ResourceFlags::empty()
+            )
+            .is_ok());
+    }
+}
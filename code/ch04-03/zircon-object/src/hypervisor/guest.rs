@@ -0,0 +1,218 @@
+use {
+    crate::object::*,
+    crate::signal::*,
+    crate::vm::*,
+    alloc::collections::BTreeMap,
+    alloc::sync::Arc,
+    alloc::vec::Vec,
+    kernel_hal::{GuestPhysAddr, HalError, MMUFlags, PageTableTrait, PhysAddr},
+    numeric_enum_macro::numeric_enum,
+    spin::Mutex,
+};
+
+/// A hardware virtual machine.
+///
+/// ## SYNOPSIS
+///
+/// A guest is a virtual machine running on the hardware virtualization. Its
+/// physical memory is represented by a VMAR, where VMOs are mapped to back the
+/// guest physical address space.
+///
+/// Accesses of the guest to the guest physical addresses not backed by memory
+/// and to the I/O ports can be trapped, and handled in userspace either
+/// synchronously by resuming a VCPU, or asynchronously from a port.
+pub struct Guest {
+    base: KObjectBase,
+    /// The ID of the virtual machine in HAL.
+    id: usize,
+    gpas: Arc<VmAddressRegion>,
+    traps: Mutex<Vec<Trap>>,
+}
+
+impl_kobject!(Guest);
+
+numeric_enum! {
+    #[repr(u32)]
+    /// The kind of a trap.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum TrapKind {
+        /// Asynchronous trap of memory accesses, the guest is not blocked.
+        Bell = 0,
+        /// Synchronous trap of memory accesses.
+        Mem = 1,
+        /// Trap of I/O port accesses, asynchronous if bound to a port.
+        Io = 2,
+    }
+}
+
+impl TrapKind {
+    fn is_io(self) -> bool {
+        self == TrapKind::Io
+    }
+}
+
+/// A trap of a range of guest physical addresses or I/O ports.
+#[derive(Clone)]
+pub struct Trap {
+    /// The kind of the trap.
+    pub kind: TrapKind,
+    /// The start address of the range.
+    pub addr: usize,
+    /// The size of the range.
+    pub size: usize,
+    /// The port to send packets asynchronously.
+    pub port: Option<Arc<Port>>,
+    /// The key of packets.
+    pub key: u64,
+}
+
+impl Trap {
+    fn contains(&self, addr: usize) -> bool {
+        self.addr <= addr && addr - self.addr < self.size
+    }
+}
+
+/// The size of the guest physical address space.
+pub const GUEST_PHYSICAL_ASPACE_SIZE: usize = 1 << 36;
+
+/// The number of I/O ports.
+const IO_PORT_COUNT: usize = 0x10000;
+
+impl Guest {
+    /// Create a new guest.
+    ///
+    /// Fail with `NOT_SUPPORTED` if the hardware virtualization is not
+    /// available in HAL.
+    pub fn create() -> ZxResult<Arc<Self>> {
+        let id = kernel_hal::guest_create().map_err(|_| ZxError::NOT_SUPPORTED)?;
+        let page_table = Arc::new(Mutex::new(GuestPhysMap {
+            guest: id,
+            mapped: BTreeMap::new(),
+        }));
+        Ok(Arc::new(Guest {
+            base: KObjectBase::new(),
+            id,
+            gpas: VmAddressRegion::new_guest(GUEST_PHYSICAL_ASPACE_SIZE, page_table),
+            traps: Mutex::new(Vec::new()),
+        }))
+    }
+
+    /// Get the VMAR of the guest physical address space.
+    pub fn vmar(&self) -> Arc<VmAddressRegion> {
+        self.gpas.clone()
+    }
+
+    /// Get the ID of the virtual machine in HAL.
+    pub(super) fn hal_id(&self) -> usize {
+        self.id
+    }
+
+    /// Set a trap of `[addr, addr + size)`.
+    ///
+    /// A bell trap must be bound to a port, and a memory trap must not.
+    pub fn set_trap(
+        &self,
+        kind: TrapKind,
+        addr: usize,
+        size: usize,
+        port: Option<Arc<Port>>,
+        key: u64,
+    ) -> ZxResult {
+        match kind {
+            TrapKind::Bell if port.is_none() => return Err(ZxError::INVALID_ARGS),
+            TrapKind::Mem if port.is_some() => return Err(ZxError::INVALID_ARGS),
+            TrapKind::Bell | TrapKind::Mem if !page_aligned(addr) || !page_aligned(size) => {
+                return Err(ZxError::INVALID_ARGS)
+            }
+            _ => {}
+        }
+        if size == 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let limit = if kind.is_io() {
+            IO_PORT_COUNT
+        } else {
+            GUEST_PHYSICAL_ASPACE_SIZE
+        };
+        match addr.checked_add(size) {
+            Some(end) if end <= limit => {}
+            _ => return Err(ZxError::OUT_OF_RANGE),
+        }
+        let mut traps = self.traps.lock();
+        let overlapped = traps.iter().any(|trap| {
+            trap.kind.is_io() == kind.is_io()
+                && trap.addr < addr + size
+                && addr < trap.addr + trap.size
+        });
+        if overlapped {
+            return Err(ZxError::ALREADY_EXISTS);
+        }
+        traps.push(Trap {
+            kind,
+            addr,
+            size,
+            port,
+            key,
+        });
+        Ok(())
+    }
+
+    /// Find the trap of the I/O port or guest physical address `addr`.
+    pub fn find_trap(&self, io: bool, addr: usize) -> Option<Trap> {
+        let traps = self.traps.lock();
+        traps
+            .iter()
+            .find(|trap| trap.kind.is_io() == io && trap.contains(addr))
+            .cloned()
+    }
+}
+
+/// The guest physical address space maintained by HAL.
+///
+/// The virtual machine is destroyed with it, after the guest and its VMAR
+/// are both dropped.
+struct GuestPhysMap {
+    guest: usize,
+    mapped: BTreeMap<GuestPhysAddr, PhysAddr>,
+}
+
+impl PageTableTrait for GuestPhysMap {
+    fn map(
+        &mut self,
+        gpaddr: GuestPhysAddr,
+        paddr: PhysAddr,
+        flags: MMUFlags,
+    ) -> kernel_hal::Result<()> {
+        kernel_hal::guest_map(self.guest, gpaddr, paddr, flags)?;
+        self.mapped.insert(gpaddr, paddr);
+        Ok(())
+    }
+
+    fn unmap(&mut self, gpaddr: GuestPhysAddr) -> kernel_hal::Result<()> {
+        kernel_hal::guest_unmap(self.guest, gpaddr)?;
+        self.mapped.remove(&gpaddr);
+        Ok(())
+    }
+
+    fn protect(&mut self, gpaddr: GuestPhysAddr, flags: MMUFlags) -> kernel_hal::Result<()> {
+        let paddr = *self.mapped.get(&gpaddr).ok_or(HalError)?;
+        kernel_hal::guest_map(self.guest, gpaddr, paddr, flags)
+    }
+
+    fn query(&mut self, gpaddr: GuestPhysAddr) -> kernel_hal::Result<PhysAddr> {
+        self.mapped.get(&gpaddr).cloned().ok_or(HalError)
+    }
+
+    fn table_phys(&self) -> PhysAddr {
+        0
+    }
+
+    #[cfg(target_arch = "riscv64")]
+    fn activate(&self) {}
+}
+
+impl Drop for GuestPhysMap {
+    fn drop(&mut self) {
+        kernel_hal::guest_destroy(self.guest);
+    }
+}
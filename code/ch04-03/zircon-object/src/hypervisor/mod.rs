@@ -0,0 +1,6 @@
+//! Objects for hardware virtualization.
+
+mod guest;
+mod vcpu;
+
+pub use self::{guest::*, vcpu::*};
@@ -0,0 +1,285 @@
+use {
+    super::*,
+    crate::object::*,
+    crate::signal::*,
+    crate::task::{Thread, ThreadFlag},
+    alloc::sync::{Arc, Weak},
+    kernel_hal::{VcpuExit, VcpuState},
+};
+
+/// A virtual CPU of a guest.
+///
+/// ## SYNOPSIS
+///
+/// A VCPU runs the guest on behalf of the thread which creates it, and only
+/// the thread can resume it or access its state. Resuming a VCPU runs the
+/// guest until an access trapped synchronously, which is returned as a port
+/// packet to be handled by the thread.
+///
+/// A halted VCPU is blocked until an interrupt is injected.
+pub struct Vcpu {
+    base: KObjectBase,
+    /// The ID of the VCPU in HAL.
+    id: usize,
+    guest: Arc<Guest>,
+    thread: Weak<Thread>,
+    thread_id: KoID,
+}
+
+impl_kobject!(Vcpu);
+
+/// The I/O state of a VCPU, in the layout of `zx_vcpu_io_t`.
+///
+/// It is written to complete the input of the last trapped access.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct VcpuIo {
+    /// The size of the access in bytes.
+    pub access_size: u8,
+    padding1: [u8; 3],
+    /// The input data.
+    pub data: [u8; 4],
+}
+
+impl VcpuIo {
+    /// Create the I/O state with `data` of `access_size` bytes.
+    pub fn new(access_size: u8, data: [u8; 4]) -> Self {
+        VcpuIo {
+            access_size,
+            padding1: [0; 3],
+            data,
+        }
+    }
+}
+
+impl Vcpu {
+    /// Create a new VCPU of the `guest` starting at `entry`, owned by `thread`.
+    ///
+    /// A thread can only have one VCPU.
+    pub fn create(guest: Arc<Guest>, entry: usize, thread: &Arc<Thread>) -> ZxResult<Arc<Self>> {
+        if thread.flags().contains(ThreadFlag::VCPU) {
+            return Err(ZxError::BAD_STATE);
+        }
+        let id =
+            kernel_hal::vcpu_create(guest.hal_id(), entry).map_err(|_| ZxError::NO_RESOURCES)?;
+        thread.update_flags(|flags| flags.insert(ThreadFlag::VCPU));
+        Ok(Arc::new(Vcpu {
+            base: KObjectBase::new(),
+            id,
+            guest,
+            thread: Arc::downgrade(thread),
+            thread_id: thread.id(),
+        }))
+    }
+
+    /// Check that `thread` owns the VCPU.
+    fn check_thread(&self, thread: &Thread) -> ZxResult {
+        if thread.id() != self.thread_id {
+            return Err(ZxError::BAD_STATE);
+        }
+        Ok(())
+    }
+
+    /// Run the guest until an access trapped synchronously.
+    ///
+    /// The packets of asynchronous traps are sent to their ports, while the
+    /// guest keeps running.
+    pub async fn resume(self: &Arc<Self>, thread: &Thread) -> ZxResult<PortPacket> {
+        self.check_thread(thread)?;
+        loop {
+            let exit = kernel_hal::vcpu_resume(self.id).map_err(|_| ZxError::BAD_STATE)?;
+            let (trap, data) = match exit {
+                VcpuExit::Io {
+                    port,
+                    access_size,
+                    input,
+                    data,
+                } => {
+                    let trap = self
+                        .guest
+                        .find_trap(true, port as usize)
+                        .ok_or(ZxError::NOT_FOUND)?;
+                    let io = PacketGuestIo {
+                        port,
+                        access_size,
+                        input,
+                        data: data.to_le_bytes(),
+                    };
+                    (trap, Payload::GuestIo(io))
+                }
+                VcpuExit::Mmio {
+                    addr,
+                    access_size,
+                    write,
+                    data,
+                } => {
+                    let trap = self
+                        .guest
+                        .find_trap(false, addr)
+                        .ok_or(ZxError::NOT_FOUND)?;
+                    let data = match trap.kind {
+                        TrapKind::Bell => Payload::GuestBell(PacketGuestBell { addr: addr as u64 }),
+                        _ => Payload::GuestMem(PacketGuestMem {
+                            addr: addr as u64,
+                            access_size,
+                            write,
+                            data,
+                        }),
+                    };
+                    (trap, data)
+                }
+                VcpuExit::Halt => {
+                    if !self.signal().contains(Signal::VCPU_INTERRUPT) {
+                        let object = self.clone() as Arc<dyn KernelObject>;
+                        object.wait_signal(Signal::VCPU_INTERRUPT).await;
+                    }
+                    self.base.signal_clear(Signal::VCPU_INTERRUPT);
+                    continue;
+                }
+            };
+            let packet = PortPacket {
+                key: trap.key,
+                status: 0,
+                data,
+            };
+            match trap.port {
+                Some(port) => port.push(packet),
+                None => return Ok(packet),
+            }
+        }
+    }
+
+    /// Inject an interrupt of `vector` into the guest, waking up the VCPU if
+    /// it is halted.
+    pub fn interrupt(&self, vector: u32) -> ZxResult {
+        kernel_hal::vcpu_interrupt(self.id, vector).map_err(|_| ZxError::BAD_STATE)?;
+        self.base.signal_set(Signal::VCPU_INTERRUPT);
+        Ok(())
+    }
+
+    /// Read the general registers.
+    pub fn read_state(&self, thread: &Thread) -> ZxResult<VcpuState> {
+        self.check_thread(thread)?;
+        kernel_hal::vcpu_read_state(self.id).map_err(|_| ZxError::BAD_STATE)
+    }
+
+    /// Write the general registers.
+    pub fn write_state(&self, thread: &Thread, state: &VcpuState) -> ZxResult {
+        self.check_thread(thread)?;
+        kernel_hal::vcpu_write_state(self.id, state).map_err(|_| ZxError::BAD_STATE)
+    }
+
+    /// Complete the input of the last trapped access.
+    pub fn write_io(&self, thread: &Thread, io: &VcpuIo) -> ZxResult {
+        self.check_thread(thread)?;
+        if !matches!(io.access_size, 1 | 2 | 4) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let data = &io.data[..io.access_size as usize];
+        kernel_hal::vcpu_write_io(self.id, data).map_err(|_| ZxError::BAD_STATE)
+    }
+}
+
+impl Drop for Vcpu {
+    fn drop(&mut self) {
+        kernel_hal::vcpu_destroy(self.id);
+        if let Some(thread) = self.thread.upgrade() {
+            thread.update_flags(|flags| flags.remove(ThreadFlag::VCPU));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::*;
+    use crate::vm::*;
+    use kernel_hal::MMUFlags;
+
+    /// out dx, al; in al, dx; out dx, al; mov [0x2000], al; hlt
+    const GUEST_CODE: &[u8] = &[0xee, 0xec, 0xee, 0xa2, 0x00, 0x20, 0x00, 0x00, 0xf4];
+
+    #[async_std::test]
+    async fn run_guest() {
+        kernel_hal_unix::init();
+        let guest = match Guest::create() {
+            Ok(guest) => guest,
+            // the hardware virtualization is not available
+            Err(ZxError::NOT_SUPPORTED) => return,
+            Err(e) => panic!("failed to create guest: {:?}", e),
+        };
+        let vmo = VmObject::new_paged(1);
+        vmo.write(0, GUEST_CODE).unwrap();
+        guest
+            .vmar()
+            .map_at(0x1000, vmo, 0, 0x1000, MMUFlags::READ | MMUFlags::EXECUTE)
+            .unwrap();
+        guest.set_trap(TrapKind::Io, 0x3f8, 8, None, 1).unwrap();
+        let port = Port::new(0).unwrap();
+        guest
+            .set_trap(TrapKind::Bell, 0x2000, 0x1000, Some(port.clone()), 2)
+            .unwrap();
+        assert_eq!(
+            guest.set_trap(TrapKind::Io, 0x3f0, 9, None, 3),
+            Err(ZxError::ALREADY_EXISTS)
+        );
+        assert_eq!(
+            guest.set_trap(TrapKind::Mem, 0x3000, 0x1000, Some(port.clone()), 3),
+            Err(ZxError::INVALID_ARGS)
+        );
+
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").unwrap();
+        let thread = Thread::create(&proc, "thread").unwrap();
+        let other = Thread::create(&proc, "other").unwrap();
+        let vcpu = Vcpu::create(guest.clone(), 0x1000, &thread).unwrap();
+        assert_eq!(
+            Vcpu::create(guest, 0x1000, &thread).err(),
+            Some(ZxError::BAD_STATE)
+        );
+        assert_eq!(vcpu.read_state(&other).err(), Some(ZxError::BAD_STATE));
+
+        let mut state = vcpu.read_state(&thread).unwrap();
+        state.rax = b'A' as u64;
+        state.rdx = 0x3f8;
+        vcpu.write_state(&thread, &state).unwrap();
+
+        // output 'A'
+        let packet = vcpu.resume(&thread).await.unwrap();
+        assert_eq!(packet.key, 1);
+        let io = PacketGuestIo {
+            port: 0x3f8,
+            access_size: 1,
+            input: false,
+            data: [b'A', 0, 0, 0],
+        };
+        assert_eq!(packet.data, Payload::GuestIo(io));
+
+        // input 'Z'
+        let packet = vcpu.resume(&thread).await.unwrap();
+        match packet.data {
+            Payload::GuestIo(io) => assert!(io.input),
+            _ => panic!("unexpected packet: {:?}", packet),
+        }
+        vcpu.write_io(&thread, &VcpuIo::new(1, [b'Z', 0, 0, 0]))
+            .unwrap();
+
+        // output 'Z'
+        let packet = vcpu.resume(&thread).await.unwrap();
+        match packet.data {
+            Payload::GuestIo(io) => assert_eq!(io.data[0], b'Z'),
+            _ => panic!("unexpected packet: {:?}", packet),
+        }
+        assert_eq!(vcpu.read_state(&thread).unwrap().rax & 0xff, b'Z' as u64);
+
+        // ring the bell asynchronously, then the guest halts
+        let resume = async_std::task::spawn(async move { vcpu.resume(&thread).await });
+        let packet = port.wait().await;
+        assert_eq!(packet.key, 2);
+        assert_eq!(
+            packet.data,
+            Payload::GuestBell(PacketGuestBell { addr: 0x2000 })
+        );
+        drop(resume);
+    }
+}
@@ -2,11 +2,12 @@ use {
     super::*,
     crate::error::*,
     crate::object::*,
-    alloc::collections::VecDeque,
+    alloc::collections::{BTreeMap, BTreeSet, VecDeque},
     alloc::sync::{Arc, Weak},
     alloc::vec::Vec,
     core::convert::TryInto,
-    core::sync::atomic::{AtomicU32, Ordering},
+    core::sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    futures::channel::oneshot::{self, Sender},
     spin::Mutex,
 };
 
@@ -14,12 +15,22 @@ pub struct Channel {
     base: KObjectBase,
     peer: Weak<Channel>,
     recv_queue: Mutex<VecDeque<T>>,
+    call_reply: Mutex<BTreeMap<TxID, Sender<ZxResult<T>>>>,
     next_txid: AtomicU32,
+    /// Set under the lock of `recv_queue` when the peer is dropped, after
+    /// which no more messages can be queued.
+    peer_closed: AtomicBool,
 }
 
 type T = MessagePacket;
 type TxID = u32;
 
+/// The maximum number of bytes in a message.
+pub const MAX_MSG_BYTES: usize = 65536;
+
+/// The maximum number of handles in a message.
+pub const MAX_MSG_HANDLES: usize = 64;
+
 impl_kobject!(Channel
     fn peer(&self) -> ZxResult<Arc<dyn KernelObject>> {
         let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
@@ -35,16 +46,20 @@ impl Channel {
     #[allow(unsafe_code)]
     pub fn create() -> (Arc<Self>, Arc<Self>) {
         let mut channel0 = Arc::new(Channel {
-            base: KObjectBase::default(),
+            base: KObjectBase::with_signal(Signal::WRITABLE),
             peer: Weak::default(),
             recv_queue: Default::default(),
+            call_reply: Default::default(),
             next_txid: AtomicU32::new(0x8000_0000),
+            peer_closed: AtomicBool::new(false),
         });
         let channel1 = Arc::new(Channel {
-            base: KObjectBase::default(),
+            base: KObjectBase::with_signal(Signal::WRITABLE),
             peer: Arc::downgrade(&channel0),
             recv_queue: Default::default(),
+            call_reply: Default::default(),
             next_txid: AtomicU32::new(0x8000_0000),
+            peer_closed: AtomicBool::new(false),
         });
         // no other reference of `channel0`
         unsafe {
@@ -59,6 +74,9 @@ impl Channel {
         if let Some(msg) = recv_queue.front() {
             checker(msg)?;
             let msg = recv_queue.pop_front().unwrap();
+            if recv_queue.is_empty() {
+                self.base.signal_clear(Signal::READABLE);
+            }
             return Ok(msg);
         }
         if self.peer_closed() {
@@ -74,16 +92,69 @@ impl Channel {
     }
 
     /// Write a packet to the channel
+    ///
+    /// Return `OUT_OF_RANGE` if the packet exceeds the size limits,
+    /// or `NOT_SUPPORTED` if it carries either endpoint of this channel.
     pub fn write(&self, msg: T) -> ZxResult {
+        msg.check_limits()?;
         let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        self.check_transfer(&peer, &msg)?;
         peer.push_general(msg);
         Ok(())
     }
 
+    /// Send a message to a channel and await a reply.
+    ///
+    /// The first 4 bytes of the message are replaced with a new transaction ID,
+    /// and the reply is the first message from peer with the same ID.
+    ///
+    /// If this future is dropped before the reply arrives, e.g. on timeout,
+    /// the reply will be discarded.
+    pub async fn call(self: &Arc<Self>, mut msg: T) -> ZxResult<T> {
+        msg.check_limits()?;
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        self.check_transfer(&peer, &msg)?;
+        let txid = self.new_txid();
+        msg.set_txid(txid);
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut call_reply = self.call_reply.lock();
+            // clean up the calls canceled before
+            call_reply.retain(|_, sender| !sender.is_canceled());
+            call_reply.insert(txid, sender);
+        }
+        peer.push_general(msg);
+        drop(peer);
+        receiver.await.map_err(|_| ZxError::PEER_CLOSED)?
+    }
+
     /// Push a message to general queue, called from peer.
+    ///
+    /// If it is a reply of a pending `call`, deliver it to the caller instead.
     fn push_general(&self, msg: T) {
+        let txid = msg.get_txid();
+        if let Some(sender) = self.call_reply.lock().remove(&txid) {
+            // the reply of a canceled call is discarded
+            sender.send(Ok(msg)).ok();
+            return;
+        }
         let mut send_queue = self.recv_queue.lock();
         send_queue.push_back(msg);
+        if send_queue.len() == 1 {
+            self.base.signal_set(Signal::READABLE);
+        }
+    }
+
+    /// Check that `msg` does not carry either endpoint of this channel.
+    fn check_transfer(&self, peer: &Channel, msg: &T) -> ZxResult {
+        let transfer_self = msg.handles.iter().any(|handle| {
+            let id = handle.object.id();
+            id == self.id() || id == peer.id()
+        });
+        if transfer_self {
+            return Err(ZxError::NOT_SUPPORTED);
+        }
+        Ok(())
     }
 
     /// Generate a new transaction ID for `call`.
@@ -93,7 +164,97 @@ impl Channel {
 
     /// Is peer channel closed?
     fn peer_closed(&self) -> bool {
-        self.peer.strong_count() == 0
+        self.peer_closed.load(Ordering::SeqCst)
+    }
+
+    /// Free the messages of channels which are only referenced by the
+    /// handles in the messages of each other, starting from `start`.
+    ///
+    /// Such a cycle of channels is found by trial deletion: a channel is
+    /// alive if its peer is open, or it has references other than queued
+    /// handles, or it is in the queue of an alive channel. Others can never
+    /// be read again.
+    ///
+    /// A channel whose peer is closed gets no more messages and its
+    /// references only come from the handles, so the counts can be trusted.
+    fn free_cycles(start: Arc<Channel>) {
+        // hold exactly one reference for each channel found
+        let mut channels = BTreeMap::<KoID, Arc<Channel>>::new();
+        let mut edges = Vec::<(KoID, KoID)>::new();
+        let mut open = BTreeSet::<KoID>::new();
+        let mut stack = Vec::from([start.clone()]);
+        channels.insert(start.id(), start);
+        while let Some(channel) = stack.pop() {
+            let recv_queue = channel.recv_queue.lock();
+            if !channel.peer_closed.load(Ordering::SeqCst) {
+                open.insert(channel.id());
+            }
+            let handles = recv_queue.iter().flat_map(|msg| msg.handles.iter());
+            for handle in handles {
+                if let Ok(child) = handle.object.clone().downcast_arc::<Channel>() {
+                    edges.push((channel.id(), child.id()));
+                    channels.entry(child.id()).or_insert_with(|| {
+                        stack.push(child.clone());
+                        child
+                    });
+                }
+            }
+        }
+        // the references other than the one held here and the queued handles
+        let mut alive: BTreeSet<KoID> = channels
+            .iter()
+            .filter(|&(id, channel)| {
+                let queued = edges.iter().filter(|&&(_, to)| to == *id).count();
+                open.contains(id) || Arc::strong_count(channel) > 1 + queued
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        let mut stack: Vec<KoID> = alive.iter().cloned().collect();
+        while let Some(id) = stack.pop() {
+            for &(_, to) in edges.iter().filter(|&&(from, _)| from == id) {
+                if alive.insert(to) {
+                    stack.push(to);
+                }
+            }
+        }
+        let garbage: Vec<VecDeque<T>> = channels
+            .iter()
+            .filter(|&(id, _)| !alive.contains(id))
+            .map(|(_, channel)| core::mem::take(&mut *channel.recv_queue.lock()))
+            .collect();
+        // drop the messages after all references here are released
+        drop(channels);
+        drop(garbage);
+    }
+}
+
+impl Drop for Channel {
+    fn drop(&mut self) {
+        if let Some(peer) = self.peer.upgrade() {
+            {
+                let _recv_queue = peer.recv_queue.lock();
+                peer.peer_closed.store(true, Ordering::SeqCst);
+            }
+            peer.base
+                .signal_change(Signal::WRITABLE, Signal::PEER_CLOSED);
+            let call_reply = core::mem::take(&mut *peer.call_reply.lock());
+            for (_, sender) in call_reply {
+                sender.send(Err(ZxError::PEER_CLOSED)).ok();
+            }
+            // no more messages can be written to the peer
+            Channel::free_cycles(peer);
+        }
+        // the channels in own messages may be left in cycles
+        let recv_queue = core::mem::take(self.recv_queue.get_mut());
+        let children: Vec<Arc<Channel>> = recv_queue
+            .iter()
+            .flat_map(|msg| msg.handles.iter())
+            .filter_map(|handle| handle.object.clone().downcast_arc::<Channel>().ok())
+            .collect();
+        drop(recv_queue);
+        for child in children {
+            Channel::free_cycles(child);
+        }
     }
 }
 
@@ -108,6 +269,33 @@ pub struct MessagePacket {
 }
 
 impl MessagePacket {
+    /// Allocate a message with `num_bytes` zeroed bytes and room for
+    /// `num_handles` handles, reusing the buffers in the pool if possible.
+    pub fn alloc(num_bytes: usize, num_handles: usize) -> Self {
+        let mut data = pool::DATA_POOL.alloc(num_bytes);
+        data.resize(num_bytes, 0);
+        MessagePacket {
+            data,
+            handles: pool::HANDLE_POOL.alloc(num_handles),
+        }
+    }
+
+    /// Return the buffers of the message to the pool.
+    ///
+    /// The remaining handles in the message are dropped.
+    pub fn recycle(self) {
+        pool::DATA_POOL.free(self.data);
+        pool::HANDLE_POOL.free(self.handles);
+    }
+
+    /// Check the number of bytes and handles of the message.
+    fn check_limits(&self) -> ZxResult {
+        if self.data.len() > MAX_MSG_BYTES || self.handles.len() > MAX_MSG_HANDLES {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        Ok(())
+    }
+
     /// Set txid (the first 4 bytes)
     pub fn set_txid(&mut self, txid: TxID) {
         if self.data.len() >= core::mem::size_of::<TxID>() {
@@ -128,9 +316,11 @@ impl MessagePacket {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use alloc::vec;
 
     #[test]
     fn test_basics() {
+        kernel_hal_unix::init();
         let (end0, end1) = Channel::create();
         assert!(Arc::ptr_eq(
             &end0.peer().unwrap().downcast_arc().unwrap(),
@@ -145,6 +335,7 @@ mod tests {
 
     #[test]
     fn read_write() {
+        kernel_hal_unix::init();
         let (channel0, channel1) = Channel::create();
         // write a message to each other
         channel0
@@ -174,8 +365,212 @@ mod tests {
         assert_eq!(channel1.read().err(), Some(ZxError::SHOULD_WAIT));
     }
 
+    #[test]
+    fn signal() {
+        kernel_hal_unix::init();
+        let (channel0, channel1) = Channel::create();
+        assert_eq!(channel0.signal(), Signal::WRITABLE);
+        assert_eq!(channel1.signal(), Signal::WRITABLE);
+
+        for data in [b"hello 1", b"hello 2"].iter() {
+            channel0
+                .write(MessagePacket {
+                    data: Vec::from(&data[..]),
+                    handles: Vec::new(),
+                })
+                .unwrap();
+        }
+        assert_eq!(channel1.signal(), Signal::WRITABLE | Signal::READABLE);
+        assert_eq!(channel0.signal(), Signal::WRITABLE);
+
+        // READABLE is cleared once the queue is drained
+        channel1.read().unwrap();
+        assert!(channel1.signal().contains(Signal::READABLE));
+        channel1.read().unwrap();
+        assert_eq!(channel1.signal(), Signal::WRITABLE);
+
+        channel0
+            .write(MessagePacket {
+                data: Vec::from("hello 3"),
+                handles: Vec::new(),
+            })
+            .unwrap();
+        drop(channel0);
+        assert_eq!(channel1.signal(), Signal::READABLE | Signal::PEER_CLOSED);
+    }
+
+    #[test]
+    fn transfer_self() {
+        kernel_hal_unix::init();
+        let (channel0, channel1) = Channel::create();
+        for end in [&channel0, &channel1].iter() {
+            let msg = MessagePacket {
+                data: Vec::new(),
+                handles: vec![Handle::new((*end).clone(), Rights::DEFAULT_CHANNEL)],
+            };
+            assert_eq!(channel0.write(msg), Err(ZxError::NOT_SUPPORTED));
+        }
+        assert_eq!(channel1.read().err(), Some(ZxError::SHOULD_WAIT));
+    }
+
+    #[test]
+    fn handle_cycle() {
+        kernel_hal_unix::init();
+        let (x0, x1) = Channel::create();
+        let (y0, y1) = Channel::create();
+        let (z0, z1) = Channel::create();
+        let send = |to: &Arc<Channel>, end: Arc<Channel>| {
+            to.write(MessagePacket {
+                data: Vec::new(),
+                handles: vec![Handle::new(end, Rights::DEFAULT_CHANNEL)],
+            })
+            .unwrap();
+        };
+        // x1 <-> y1 and z1 -> y1
+        send(&y0, x1.clone());
+        send(&x0, y1.clone());
+        send(&z0, y1.clone());
+        // only the handles in messages are left
+        let (x1, y1) = {
+            let (x1_weak, y1_weak) = (Arc::downgrade(&x1), Arc::downgrade(&y1));
+            drop((x1, y1));
+            (x1_weak, y1_weak)
+        };
+
+        // the cycle is reachable from z1
+        drop(x0);
+        assert_eq!(x1.strong_count(), 1);
+        assert_eq!(y1.strong_count(), 2);
+
+        drop(y0);
+        assert_eq!(y1.strong_count(), 2);
+
+        // once z1 is gone, nothing can read the cycle
+        drop(z1);
+        assert_eq!(x1.strong_count(), 0);
+        assert_eq!(y1.strong_count(), 0);
+    }
+
+    #[test]
+    fn handle_cycle_open_peer() {
+        kernel_hal_unix::init();
+        let (x0, x1) = Channel::create();
+        let (y0, y1) = Channel::create();
+        let send = |to: &Arc<Channel>, end: Arc<Channel>| {
+            to.write(MessagePacket {
+                data: Vec::new(),
+                handles: vec![Handle::new(end, Rights::DEFAULT_CHANNEL)],
+            })
+            .unwrap();
+        };
+        // x1 <-> y1
+        send(&y0, x1.clone());
+        send(&x0, y1.clone());
+        let (x1, y1) = {
+            let (x1_weak, y1_weak) = (Arc::downgrade(&x1), Arc::downgrade(&y1));
+            drop((x1, y1));
+            (x1_weak, y1_weak)
+        };
+
+        // messages can still be written to x1
+        drop(y0);
+        assert_eq!(x1.strong_count(), 1);
+        assert_eq!(y1.strong_count(), 1);
+
+        drop(x0);
+        assert_eq!(x1.strong_count(), 0);
+        assert_eq!(y1.strong_count(), 0);
+    }
+
+    #[test]
+    fn limits() {
+        kernel_hal_unix::init();
+        let (channel0, channel1) = Channel::create();
+        let msg = MessagePacket {
+            data: vec![0; MAX_MSG_BYTES + 1],
+            handles: Vec::new(),
+        };
+        assert_eq!(channel0.write(msg), Err(ZxError::OUT_OF_RANGE));
+        let handle = Handle::new(channel1.clone(), Rights::DEFAULT_CHANNEL);
+        let msg = MessagePacket {
+            data: Vec::new(),
+            handles: vec![handle; MAX_MSG_HANDLES + 1],
+        };
+        assert_eq!(channel0.write(msg), Err(ZxError::OUT_OF_RANGE));
+        assert_eq!(channel1.read().err(), Some(ZxError::SHOULD_WAIT));
+
+        let msg = MessagePacket {
+            data: vec![0; MAX_MSG_BYTES],
+            handles: Vec::new(),
+        };
+        channel0.write(msg).unwrap();
+        assert_eq!(channel1.read().unwrap().data.len(), MAX_MSG_BYTES);
+    }
+
+    #[async_std::test]
+    async fn call() {
+        kernel_hal_unix::init();
+        let (channel0, channel1) = Channel::create();
+        async_std::task::spawn({
+            let channel1 = channel1.clone();
+            async move {
+                // unrelated message goes to the queue
+                channel1.write(MessagePacket::default()).unwrap();
+                async_std::task::sleep(core::time::Duration::from_millis(10)).await;
+                let request = channel1.read().unwrap();
+                assert_eq!(&request.data[4..], b"request");
+                let mut data = Vec::from("....reply");
+                data[..4].copy_from_slice(&request.data[..4]);
+                channel1
+                    .write(MessagePacket {
+                        data,
+                        handles: Vec::new(),
+                    })
+                    .unwrap();
+            }
+        });
+        let reply = channel0
+            .call(MessagePacket {
+                data: Vec::from("....request"),
+                handles: Vec::new(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(&reply.data[4..], b"reply");
+        assert!(channel0.read().unwrap().data.is_empty());
+
+        // the caller is woken up when peer is closed
+        let mut call = alloc::boxed::Box::pin(channel0.call(MessagePacket {
+            data: Vec::from("...."),
+            handles: Vec::new(),
+        }));
+        assert!(futures::poll!(call.as_mut()).is_pending());
+        drop(channel1);
+        assert_eq!(call.await.err(), Some(ZxError::PEER_CLOSED));
+    }
+
+    #[test]
+    fn call_canceled() {
+        kernel_hal_unix::init();
+        use futures::future::FutureExt;
+        let (channel0, channel1) = Channel::create();
+        // cancel the call before the reply arrives
+        let call = channel0.call(MessagePacket {
+            data: Vec::from("...."),
+            handles: Vec::new(),
+        });
+        assert!(call.now_or_never().is_none());
+
+        // the late reply is discarded
+        let request = channel1.read().unwrap();
+        channel1.write(request).unwrap();
+        assert!(channel0.call_reply.lock().is_empty());
+        assert_eq!(channel0.read().err(), Some(ZxError::SHOULD_WAIT));
+    }
+
     #[test]
     fn peer_closed() {
+        kernel_hal_unix::init();
         let (channel0, channel1) = Channel::create();
         // write a message from peer, then drop it
         channel1.write(MessagePacket::default()).unwrap();
@@ -0,0 +1,200 @@
+use {
+    super::*,
+    crate::error::*,
+    crate::object::*,
+    alloc::collections::VecDeque,
+    alloc::sync::{Arc, Weak},
+    spin::Mutex,
+};
+
+/// First-In First-Out inter-process queue.
+///
+/// ## SYNOPSIS
+///
+/// FIFOs are intended to be the control plane for shared memory transports.
+/// Their read and write operations are more efficient than sockets or
+/// channels, but there are severe restrictions on the size of elements and
+/// buffers.
+///
+/// Data is written and read in whole elements of `elem_size` bytes, and each
+/// endpoint buffers at most `elem_count` elements written by its peer.
+pub struct Fifo {
+    base: KObjectBase,
+    peer: Weak<Fifo>,
+    elem_size: usize,
+    elem_count: usize,
+    recv_queue: Mutex<VecDeque<u8>>,
+}
+
+impl_kobject!(Fifo
+    fn peer(&self) -> ZxResult<Arc<dyn KernelObject>> {
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        Ok(peer)
+    }
+    fn related_koid(&self) -> KoID {
+        self.peer.upgrade().map(|p| p.id()).unwrap_or(0)
+    }
+);
+
+/// The maximum size of the buffer of each FIFO endpoint in bytes.
+pub const FIFO_MAX_SIZE_BYTES: usize = 4096;
+
+impl Fifo {
+    /// Create a FIFO and return a pair of its endpoints.
+    ///
+    /// Return `OUT_OF_RANGE` if `elem_count` or `elem_size` is zero, or the
+    /// buffer size exceeds `FIFO_MAX_SIZE_BYTES`.
+    #[allow(unsafe_code)]
+    pub fn create(elem_count: usize, elem_size: usize) -> ZxResult<(Arc<Self>, Arc<Self>)> {
+        match elem_count.checked_mul(elem_size) {
+            Some(size) if size != 0 && size <= FIFO_MAX_SIZE_BYTES => {}
+            _ => return Err(ZxError::OUT_OF_RANGE),
+        }
+        let mut end0 = Arc::new(Fifo {
+            base: KObjectBase::with_signal(Signal::WRITABLE),
+            peer: Weak::default(),
+            elem_size,
+            elem_count,
+            recv_queue: Mutex::new(VecDeque::with_capacity(elem_count * elem_size)),
+        });
+        let end1 = Arc::new(Fifo {
+            base: KObjectBase::with_signal(Signal::WRITABLE),
+            peer: Arc::downgrade(&end0),
+            elem_size,
+            elem_count,
+            recv_queue: Mutex::new(VecDeque::with_capacity(elem_count * elem_size)),
+        });
+        // no other reference of `end0`
+        unsafe {
+            Arc::get_mut_unchecked(&mut end0).peer = Arc::downgrade(&end1);
+        }
+        Ok((end0, end1))
+    }
+
+    /// Write elements to the FIFO, return the number of elements actually written.
+    ///
+    /// If the peer buffer is full, return `SHOULD_WAIT`.
+    pub fn write(&self, elem_size: usize, data: &[u8]) -> ZxResult<usize> {
+        let count = self.check_elements(elem_size, data.len())?;
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        let mut peer_queue = peer.recv_queue.lock();
+        let rest = self.elem_count - peer_queue.len() / self.elem_size;
+        if rest == 0 {
+            return Err(ZxError::SHOULD_WAIT);
+        }
+        let count = rest.min(count);
+        if peer_queue.is_empty() {
+            peer.base.signal_set(Signal::READABLE);
+        }
+        peer_queue.extend(&data[..count * self.elem_size]);
+        if count == rest {
+            self.base.signal_clear(Signal::WRITABLE);
+        }
+        Ok(count)
+    }
+
+    /// Read elements from the FIFO, return the number of elements actually read.
+    ///
+    /// If there is no element, return `SHOULD_WAIT`, or `PEER_CLOSED` if the
+    /// peer has been closed.
+    pub fn read(&self, elem_size: usize, data: &mut [u8]) -> ZxResult<usize> {
+        let count = self.check_elements(elem_size, data.len())?;
+        let mut recv_queue = self.recv_queue.lock();
+        if recv_queue.is_empty() {
+            return match self.peer.upgrade() {
+                None => Err(ZxError::PEER_CLOSED),
+                Some(_) => Err(ZxError::SHOULD_WAIT),
+            };
+        }
+        let was_full = recv_queue.len() == self.elem_count * self.elem_size;
+        let count = count.min(recv_queue.len() / self.elem_size);
+        let len = count * self.elem_size;
+        for (dst, src) in data.iter_mut().zip(recv_queue.drain(..len)) {
+            *dst = src;
+        }
+        if recv_queue.is_empty() {
+            self.base.signal_clear(Signal::READABLE);
+        }
+        if was_full {
+            if let Some(peer) = self.peer.upgrade() {
+                peer.base.signal_set(Signal::WRITABLE);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Get the size of each element in bytes.
+    pub fn elem_size(&self) -> usize {
+        self.elem_size
+    }
+
+    /// Get the maximum number of elements in each direction.
+    pub fn elem_count(&self) -> usize {
+        self.elem_count
+    }
+
+    /// Check the element size and the buffer length, return the number of elements.
+    fn check_elements(&self, elem_size: usize, len: usize) -> ZxResult<usize> {
+        if elem_size != self.elem_size || len == 0 || len % elem_size != 0 {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        Ok(len / elem_size)
+    }
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        if let Some(peer) = self.peer.upgrade() {
+            peer.base
+                .signal_change(Signal::WRITABLE, Signal::PEER_CLOSED);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create() {
+        kernel_hal_unix::init();
+        assert!(Fifo::create(0, 4).is_err());
+        assert!(Fifo::create(4, 0).is_err());
+        assert!(Fifo::create(FIFO_MAX_SIZE_BYTES + 1, 1).is_err());
+        assert!(Fifo::create(usize::MAX, 2).is_err());
+        let (end0, end1) = Fifo::create(4, 2).unwrap();
+        assert_eq!(end0.related_koid(), end1.id());
+        assert_eq!(end0.signal(), Signal::WRITABLE);
+    }
+
+    #[test]
+    fn read_write() {
+        kernel_hal_unix::init();
+        let (end0, end1) = Fifo::create(4, 2).unwrap();
+        assert_eq!(end0.write(1, &[0; 2]), Err(ZxError::OUT_OF_RANGE));
+        assert_eq!(end0.write(2, &[0; 3]), Err(ZxError::OUT_OF_RANGE));
+
+        // partial write
+        assert_eq!(end0.write(2, &[1, 2, 3, 4, 5, 6]).unwrap(), 3);
+        assert_eq!(end0.write(2, &[7, 8, 9, 10]).unwrap(), 1);
+        assert!(!end0.signal().contains(Signal::WRITABLE));
+        assert_eq!(end0.write(2, &[0; 2]), Err(ZxError::SHOULD_WAIT));
+        assert_eq!((end1.elem_size(), end1.elem_count()), (2, 4));
+        assert!(end1.signal().contains(Signal::READABLE));
+
+        // partial read
+        let mut buf = [0u8; 6];
+        assert_eq!(end1.read(2, &mut buf).unwrap(), 3);
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6]);
+        assert!(end0.signal().contains(Signal::WRITABLE));
+        assert_eq!(end1.read(2, &mut buf).unwrap(), 1);
+        assert_eq!(&buf[..2], &[7, 8]);
+        assert_eq!(end1.signal(), Signal::WRITABLE);
+        assert_eq!(end1.read(2, &mut buf), Err(ZxError::SHOULD_WAIT));
+
+        drop(end0);
+        assert_eq!(end1.signal(), Signal::PEER_CLOSED);
+        assert_eq!(end1.read(2, &mut buf), Err(ZxError::PEER_CLOSED));
+        assert_eq!(end1.write(2, &buf), Err(ZxError::PEER_CLOSED));
+    }
+}
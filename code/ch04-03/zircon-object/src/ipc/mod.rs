@@ -1,4 +1,7 @@
 use super::*;
 
 mod channel;
-pub use self::channel::*;
+mod fifo;
+mod pool;
+mod socket;
+pub use self::{channel::*, fifo::*, socket::*};
@@ -0,0 +1,121 @@
+use {
+    super::*, crate::object::Handle, crate::util::kcounter::KCounter, alloc::vec::Vec, spin::Mutex,
+};
+
+kcounter!(DATA_POOL_HIT, "channel.msg_pool.data.hit");
+kcounter!(DATA_POOL_MISS, "channel.msg_pool.data.miss");
+kcounter!(HANDLE_POOL_HIT, "channel.msg_pool.handle.hit");
+kcounter!(HANDLE_POOL_MISS, "channel.msg_pool.handle.miss");
+
+/// The number of size classes in a pool.
+const CLASSES: usize = 4;
+
+/// The maximum number of free buffers kept in each size class.
+const MAX_FREE: usize = 16;
+
+/// Pool of buffers for message data.
+pub(super) static DATA_POOL: BufferPool<u8> = BufferPool::new(
+    [256, 4096, 16384, MAX_MSG_BYTES],
+    &DATA_POOL_HIT,
+    &DATA_POOL_MISS,
+);
+
+/// Pool of buffers for message handles.
+pub(super) static HANDLE_POOL: BufferPool<Handle> = BufferPool::new(
+    [4, 16, 32, MAX_MSG_HANDLES],
+    &HANDLE_POOL_HIT,
+    &HANDLE_POOL_MISS,
+);
+
+/// A size-classed pool of reusable `Vec`s.
+///
+/// Buffers are allocated with the capacity of the smallest class which fits,
+/// so that they can be returned to the same class when freed.
+pub(super) struct BufferPool<T> {
+    sizes: [usize; CLASSES],
+    free: [Mutex<Vec<Vec<T>>>; CLASSES],
+    /// The counters of allocations from the pool and from the heap.
+    hit: &'static KCounter,
+    miss: &'static KCounter,
+}
+
+impl<T> BufferPool<T> {
+    const fn new(sizes: [usize; CLASSES], hit: &'static KCounter, miss: &'static KCounter) -> Self {
+        BufferPool {
+            sizes,
+            hit,
+            miss,
+            free: [
+                Mutex::new(Vec::new()),
+                Mutex::new(Vec::new()),
+                Mutex::new(Vec::new()),
+                Mutex::new(Vec::new()),
+            ],
+        }
+    }
+
+    /// Get an empty buffer which can hold at least `len` elements.
+    pub fn alloc(&self, len: usize) -> Vec<T> {
+        if len == 0 {
+            return Vec::new();
+        }
+        let class = match self.sizes.iter().position(|&size| size >= len) {
+            Some(class) => class,
+            None => {
+                self.miss.add(1);
+                return Vec::with_capacity(len);
+            }
+        };
+        if let Some(buf) = self.free[class].lock().pop() {
+            self.hit.add(1);
+            return buf;
+        }
+        self.miss.add(1);
+        Vec::with_capacity(self.sizes[class])
+    }
+
+    /// Return a buffer to the pool.
+    ///
+    /// Buffers not allocated from the pool are simply dropped.
+    pub fn free(&self, mut buf: Vec<T>) {
+        buf.clear();
+        if let Some(class) = self.sizes.iter().position(|&size| size == buf.capacity()) {
+            let mut free = self.free[class].lock();
+            if free.len() < MAX_FREE {
+                free.push(buf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    kcounter!(TEST_POOL_HIT, "test.pool.hit");
+    kcounter!(TEST_POOL_MISS, "test.pool.miss");
+
+    #[test]
+    fn reuse() {
+        kernel_hal_unix::init();
+        let pool = BufferPool::<u8>::new([4, 8, 16, 32], &TEST_POOL_HIT, &TEST_POOL_MISS);
+        assert_eq!(pool.alloc(0).capacity(), 0);
+        assert_eq!(pool.alloc(64).capacity(), 64);
+
+        let mut buf = pool.alloc(5);
+        assert_eq!(buf.capacity(), 8);
+        buf.extend_from_slice(b"hello");
+        let ptr = buf.as_ptr();
+        pool.free(buf);
+
+        let hit = TEST_POOL_HIT.get();
+        let buf = pool.alloc(6);
+        assert!(buf.is_empty());
+        assert_eq!(buf.as_ptr(), ptr);
+        assert_eq!(TEST_POOL_HIT.get(), hit + 1);
+
+        // foreign buffers are not pooled
+        pool.free(Vec::with_capacity(10));
+        assert_ne!(pool.alloc(9).capacity(), 10);
+    }
+}
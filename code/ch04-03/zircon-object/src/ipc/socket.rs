@@ -0,0 +1,473 @@
+use {
+    super::*,
+    crate::error::*,
+    crate::object::*,
+    alloc::collections::VecDeque,
+    alloc::sync::{Arc, Weak},
+    core::sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    numeric_enum_macro::numeric_enum,
+    spin::Mutex,
+};
+
+/// Bidirectional streaming IPC transport.
+///
+/// ## SYNOPSIS
+///
+/// Sockets are a bidirectional stream transport. Unlike channels, sockets
+/// only move data (not handles).
+///
+/// Data is written into one end of a socket via `write` and read from the
+/// opposing end via `read`. Each end buffers at most `SOCKET_SIZE` bytes,
+/// and a write which does not fully fit is partially performed.
+///
+/// `SOCKET_READ_THRESHOLD` is asserted when the amount of data available to
+/// read reaches the read threshold, and `SOCKET_WRITE_THRESHOLD` is asserted
+/// when the free space in the peer's buffer reaches the write threshold.
+/// A threshold of 0 disables the signal.
+///
+/// Writing on either endpoint can be disabled by `set_disposition`, which
+/// asserts `SOCKET_WRITE_DISABLED` on it and `SOCKET_PEER_WRITE_DISABLED` on
+/// its peer. Once the remaining data is drained, the peer reads `BAD_STATE`.
+pub struct Socket {
+    base: KObjectBase,
+    peer: Weak<Socket>,
+    inner: Mutex<SocketInner>,
+    read_threshold: AtomicUsize,
+    write_threshold: AtomicUsize,
+    write_disabled: AtomicBool,
+}
+
+impl_kobject!(Socket
+    fn peer(&self) -> ZxResult<Arc<dyn KernelObject>> {
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        Ok(peer)
+    }
+    fn related_koid(&self) -> KoID {
+        self.peer.upgrade().map(|p| p.id()).unwrap_or(0)
+    }
+);
+
+#[derive(Default)]
+struct SocketInner {
+    /// Data written by the peer and not read yet.
+    data: VecDeque<u8>,
+}
+
+/// The capacity of the buffer of each socket endpoint.
+pub const SOCKET_SIZE: usize = 128 * 2048;
+
+/// Information of a socket endpoint, as `zx_info_socket_t`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct SocketInfo {
+    /// The options passed to `zx_socket_create`.
+    pub options: u32,
+    padding1: u32,
+    /// The maximum size of the receive buffer in bytes.
+    pub rx_buf_max: usize,
+    /// The size of the receive buffer in bytes.
+    pub rx_buf_size: usize,
+    /// The amount of data available to read in bytes.
+    pub rx_buf_available: usize,
+    /// The maximum size of the transmit buffer in bytes, or 0 if the peer is closed.
+    pub tx_buf_max: usize,
+    /// The size of the transmit buffer in bytes.
+    pub tx_buf_size: usize,
+}
+
+numeric_enum! {
+    #[repr(u32)]
+    /// The disposition of writing on a socket endpoint.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum SocketDisposition {
+        /// Keep the current disposition.
+        None = 0,
+        /// Disable writing.
+        WriteDisabled = 1,
+        /// Enable writing again.
+        WriteEnabled = 2,
+    }
+}
+
+impl Socket {
+    /// Create a socket and return a pair of its endpoints.
+    #[allow(unsafe_code)]
+    pub fn create() -> (Arc<Self>, Arc<Self>) {
+        let mut end0 = Arc::new(Socket {
+            base: KObjectBase::with_signal(Signal::WRITABLE),
+            peer: Weak::default(),
+            inner: Default::default(),
+            read_threshold: AtomicUsize::new(0),
+            write_threshold: AtomicUsize::new(0),
+            write_disabled: AtomicBool::new(false),
+        });
+        let end1 = Arc::new(Socket {
+            base: KObjectBase::with_signal(Signal::WRITABLE),
+            peer: Arc::downgrade(&end0),
+            inner: Default::default(),
+            read_threshold: AtomicUsize::new(0),
+            write_threshold: AtomicUsize::new(0),
+            write_disabled: AtomicBool::new(false),
+        });
+        // no other reference of `end0`
+        unsafe {
+            Arc::get_mut_unchecked(&mut end0).peer = Arc::downgrade(&end1);
+        }
+        (end0, end1)
+    }
+
+    /// Write data to the socket, return the number of bytes actually written.
+    ///
+    /// If the peer buffer is full, return `SHOULD_WAIT`.
+    /// If writing is disabled, return `BAD_STATE`.
+    pub fn write(&self, data: &[u8]) -> ZxResult<usize> {
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        if self.write_disabled.load(Ordering::SeqCst) {
+            return Err(ZxError::BAD_STATE);
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut peer_inner = peer.inner.lock();
+        let rest = SOCKET_SIZE - peer_inner.data.len();
+        if rest == 0 {
+            return Err(ZxError::SHOULD_WAIT);
+        }
+        let count = rest.min(data.len());
+        if peer_inner.data.is_empty() {
+            peer.base.signal_set(Signal::READABLE);
+        }
+        peer_inner.data.extend(&data[..count]);
+        if peer_inner.data.len() == SOCKET_SIZE {
+            self.base.signal_clear(Signal::WRITABLE);
+        }
+        peer.update_read_threshold(peer_inner.data.len());
+        self.update_write_threshold(peer_inner.data.len());
+        Ok(count)
+    }
+
+    /// Read data from the socket, return the number of bytes actually read.
+    ///
+    /// If `peek` is true, the data is left in the socket.
+    ///
+    /// If there is no data, return `SHOULD_WAIT`, or `PEER_CLOSED` if the
+    /// peer has been closed, or `BAD_STATE` if the peer has disabled writing.
+    pub fn read(&self, peek: bool, data: &mut [u8]) -> ZxResult<usize> {
+        let mut inner = self.inner.lock();
+        if inner.data.is_empty() {
+            return match self.peer.upgrade() {
+                None => Err(ZxError::PEER_CLOSED),
+                Some(peer) if peer.write_disabled.load(Ordering::SeqCst) => Err(ZxError::BAD_STATE),
+                Some(_) => Err(ZxError::SHOULD_WAIT),
+            };
+        }
+        let was_full = inner.data.len() == SOCKET_SIZE;
+        let count = data.len().min(inner.data.len());
+        if peek {
+            for (dst, &src) in data.iter_mut().zip(inner.data.iter()) {
+                *dst = src;
+            }
+            return Ok(count);
+        }
+        for (dst, src) in data.iter_mut().zip(inner.data.drain(..count)) {
+            *dst = src;
+        }
+        if inner.data.is_empty() {
+            self.base.signal_clear(Signal::READABLE);
+        }
+        self.update_read_threshold(inner.data.len());
+        if let Some(peer) = self.peer.upgrade() {
+            if was_full && count != 0 {
+                peer.base.signal_set(Signal::WRITABLE);
+            }
+            peer.update_write_threshold(inner.data.len());
+        }
+        Ok(count)
+    }
+
+    /// Get the information of the socket endpoint.
+    pub fn get_info(&self) -> SocketInfo {
+        let rx_buf_size = self.inner.lock().data.len();
+        let (tx_buf_max, tx_buf_size) = match self.peer.upgrade() {
+            Some(peer) => (SOCKET_SIZE, peer.inner.lock().data.len()),
+            None => (0, 0),
+        };
+        SocketInfo {
+            rx_buf_max: SOCKET_SIZE,
+            rx_buf_size,
+            rx_buf_available: rx_buf_size,
+            tx_buf_max,
+            tx_buf_size,
+            ..Default::default()
+        }
+    }
+
+    /// Get the read threshold.
+    pub fn read_threshold(&self) -> usize {
+        self.read_threshold.load(Ordering::SeqCst)
+    }
+
+    /// Set the read threshold, and update `SOCKET_READ_THRESHOLD` signal.
+    pub fn set_read_threshold(&self, threshold: usize) -> ZxResult {
+        if threshold > SOCKET_SIZE {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let inner = self.inner.lock();
+        self.read_threshold.store(threshold, Ordering::SeqCst);
+        self.update_read_threshold(inner.data.len());
+        Ok(())
+    }
+
+    /// Get the write threshold.
+    pub fn write_threshold(&self) -> usize {
+        self.write_threshold.load(Ordering::SeqCst)
+    }
+
+    /// Set the write threshold, and update `SOCKET_WRITE_THRESHOLD` signal.
+    pub fn set_write_threshold(&self, threshold: usize) -> ZxResult {
+        if threshold > SOCKET_SIZE {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        let peer_inner = peer.inner.lock();
+        self.write_threshold.store(threshold, Ordering::SeqCst);
+        self.update_write_threshold(peer_inner.data.len());
+        Ok(())
+    }
+
+    /// Set the disposition of writing on this endpoint and its peer.
+    ///
+    /// Writing can not be enabled again while the data written before
+    /// disabling is not drained, in which case `BAD_STATE` is returned and
+    /// neither disposition is changed.
+    pub fn set_disposition(
+        &self,
+        disposition: SocketDisposition,
+        disposition_peer: SocketDisposition,
+    ) -> ZxResult {
+        let peer = self.peer.upgrade().ok_or(ZxError::PEER_CLOSED)?;
+        // lock both buffers in the order of KoID to avoid deadlock
+        let (mut inner, mut peer_inner) = if self.id() < peer.id() {
+            let inner = self.inner.lock();
+            (inner, peer.inner.lock())
+        } else {
+            let peer_inner = peer.inner.lock();
+            (self.inner.lock(), peer_inner)
+        };
+        let drained = |socket: &Socket, buf: &SocketInner| {
+            !socket.write_disabled.load(Ordering::SeqCst) || buf.data.is_empty()
+        };
+        if (disposition == SocketDisposition::WriteEnabled && !drained(self, &peer_inner))
+            || (disposition_peer == SocketDisposition::WriteEnabled && !drained(&peer, &inner))
+        {
+            return Err(ZxError::BAD_STATE);
+        }
+        self.apply_disposition(&peer, &mut peer_inner, disposition);
+        peer.apply_disposition(self, &mut inner, disposition_peer);
+        Ok(())
+    }
+
+    /// Apply the disposition of writing on this endpoint,
+    /// with the locked buffer of peer.
+    fn apply_disposition(
+        &self,
+        peer: &Socket,
+        peer_inner: &mut SocketInner,
+        disposition: SocketDisposition,
+    ) {
+        match disposition {
+            SocketDisposition::None => {}
+            SocketDisposition::WriteDisabled => {
+                self.write_disabled.store(true, Ordering::SeqCst);
+                self.base.signal_change(
+                    Signal::WRITABLE | Signal::SOCKET_WRITE_THRESHOLD,
+                    Signal::SOCKET_WRITE_DISABLED,
+                );
+                peer.base.signal_set(Signal::SOCKET_PEER_WRITE_DISABLED);
+            }
+            SocketDisposition::WriteEnabled => {
+                self.write_disabled.store(false, Ordering::SeqCst);
+                let writable = if peer_inner.data.len() < SOCKET_SIZE {
+                    Signal::WRITABLE
+                } else {
+                    Signal::empty()
+                };
+                self.base
+                    .signal_change(Signal::SOCKET_WRITE_DISABLED, writable);
+                self.update_write_threshold(peer_inner.data.len());
+                peer.base.signal_clear(Signal::SOCKET_PEER_WRITE_DISABLED);
+            }
+        }
+    }
+
+    /// Update `SOCKET_READ_THRESHOLD` signal with the length of own buffer.
+    fn update_read_threshold(&self, len: usize) {
+        let threshold = self.read_threshold();
+        if threshold != 0 && len >= threshold {
+            self.base.signal_set(Signal::SOCKET_READ_THRESHOLD);
+        } else {
+            self.base.signal_clear(Signal::SOCKET_READ_THRESHOLD);
+        }
+    }
+
+    /// Update `SOCKET_WRITE_THRESHOLD` signal with the length of peer buffer.
+    fn update_write_threshold(&self, peer_len: usize) {
+        let threshold = self.write_threshold();
+        if threshold != 0 && SOCKET_SIZE - peer_len >= threshold {
+            self.base.signal_set(Signal::SOCKET_WRITE_THRESHOLD);
+        } else {
+            self.base.signal_clear(Signal::SOCKET_WRITE_THRESHOLD);
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Some(peer) = self.peer.upgrade() {
+            peer.base.signal_change(
+                Signal::WRITABLE | Signal::SOCKET_WRITE_THRESHOLD,
+                Signal::PEER_CLOSED,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn read_write() {
+        kernel_hal_unix::init();
+        let (end0, end1) = Socket::create();
+        assert_eq!(end0.related_koid(), end1.id());
+        assert_eq!(end0.signal(), Signal::WRITABLE);
+
+        assert_eq!(end0.write(b"hello").unwrap(), 5);
+        assert_eq!(end1.signal(), Signal::WRITABLE | Signal::READABLE);
+        assert_eq!(end1.get_info().rx_buf_available, 5);
+        assert_eq!(end0.get_info().tx_buf_size, 5);
+
+        // partial read
+        let mut buf = [0u8; 3];
+        assert_eq!(end1.read(false, &mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(end1.read(false, &mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(end1.signal(), Signal::WRITABLE);
+        assert_eq!(end1.read(false, &mut buf).err(), Some(ZxError::SHOULD_WAIT));
+    }
+
+    #[test]
+    fn full() {
+        kernel_hal_unix::init();
+        let (end0, end1) = Socket::create();
+        // partial write
+        let data = vec![1u8; SOCKET_SIZE + 10];
+        assert_eq!(end0.write(&data).unwrap(), SOCKET_SIZE);
+        assert!(!end0.signal().contains(Signal::WRITABLE));
+        assert_eq!(end0.write(&data).err(), Some(ZxError::SHOULD_WAIT));
+
+        let mut buf = [0u8; 10];
+        assert_eq!(end1.read(false, &mut buf).unwrap(), 10);
+        assert!(end0.signal().contains(Signal::WRITABLE));
+        assert_eq!(end0.write(&data).unwrap(), 10);
+    }
+
+    #[test]
+    fn peer_closed() {
+        kernel_hal_unix::init();
+        let (end0, end1) = Socket::create();
+        end1.write(b"data").unwrap();
+        drop(end1);
+        assert_eq!(end0.signal(), Signal::READABLE | Signal::PEER_CLOSED);
+        let info = end0.get_info();
+        assert_eq!((info.rx_buf_size, info.tx_buf_max), (4, 0));
+        assert_eq!(end0.write(b"data").err(), Some(ZxError::PEER_CLOSED));
+
+        // remaining data can still be read
+        let mut buf = [0u8; 8];
+        assert_eq!(end0.read(false, &mut buf).unwrap(), 4);
+        assert_eq!(end0.read(false, &mut buf).err(), Some(ZxError::PEER_CLOSED));
+    }
+
+    #[test]
+    fn peek() {
+        kernel_hal_unix::init();
+        let (end0, end1) = Socket::create();
+        end0.write(b"hello").unwrap();
+        let mut buf = [0u8; 5];
+        assert_eq!(end1.read(true, &mut buf[..2]).unwrap(), 2);
+        assert_eq!(&buf[..2], b"he");
+        assert!(end1.signal().contains(Signal::READABLE));
+        assert_eq!(end1.read(false, &mut buf).unwrap(), 5);
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn threshold() {
+        kernel_hal_unix::init();
+        let (end0, end1) = Socket::create();
+        assert_eq!(
+            end1.set_read_threshold(SOCKET_SIZE + 1),
+            Err(ZxError::INVALID_ARGS)
+        );
+        end1.set_read_threshold(4).unwrap();
+        assert_eq!(end1.read_threshold(), 4);
+        end0.write(b"abc").unwrap();
+        assert!(!end1.signal().contains(Signal::SOCKET_READ_THRESHOLD));
+        end0.write(b"d").unwrap();
+        assert!(end1.signal().contains(Signal::SOCKET_READ_THRESHOLD));
+        let mut buf = [0u8; 1];
+        end1.read(false, &mut buf).unwrap();
+        assert!(!end1.signal().contains(Signal::SOCKET_READ_THRESHOLD));
+
+        // 3 bytes are in the buffer of end1
+        end0.set_write_threshold(SOCKET_SIZE - 2).unwrap();
+        assert!(!end0.signal().contains(Signal::SOCKET_WRITE_THRESHOLD));
+        end1.read(false, &mut buf).unwrap();
+        assert!(end0.signal().contains(Signal::SOCKET_WRITE_THRESHOLD));
+        end0.write(b"e").unwrap();
+        assert!(!end0.signal().contains(Signal::SOCKET_WRITE_THRESHOLD));
+        // disable the signal
+        end0.set_write_threshold(0).unwrap();
+        end1.read(false, &mut buf).unwrap();
+        assert!(!end0.signal().contains(Signal::SOCKET_WRITE_THRESHOLD));
+
+        drop(end1);
+        assert_eq!(end0.set_write_threshold(1), Err(ZxError::PEER_CLOSED));
+    }
+
+    #[test]
+    fn disposition() {
+        kernel_hal_unix::init();
+        use SocketDisposition::*;
+        let (end0, end1) = Socket::create();
+        end0.write(b"data").unwrap();
+        end0.set_disposition(WriteDisabled, None).unwrap();
+        assert_eq!(end0.signal(), Signal::SOCKET_WRITE_DISABLED);
+        assert_eq!(
+            end1.signal(),
+            Signal::WRITABLE | Signal::READABLE | Signal::SOCKET_READ_DISABLED
+        );
+        assert_eq!(end0.write(b"data").err(), Some(ZxError::BAD_STATE));
+        // the other direction is not affected
+        assert_eq!(end1.write(b"data").unwrap(), 4);
+
+        // can not be enabled before drained
+        assert_eq!(
+            end1.set_disposition(None, WriteEnabled),
+            Err(ZxError::BAD_STATE)
+        );
+        let mut buf = [0u8; 8];
+        assert_eq!(end1.read(false, &mut buf).unwrap(), 4);
+        assert_eq!(end1.read(false, &mut buf).err(), Some(ZxError::BAD_STATE));
+
+        end1.set_disposition(None, WriteEnabled).unwrap();
+        assert_eq!(end0.signal(), Signal::WRITABLE | Signal::READABLE);
+        assert_eq!(end1.signal(), Signal::WRITABLE);
+        assert_eq!(end1.read(false, &mut buf).err(), Some(ZxError::SHOULD_WAIT));
+        assert_eq!(end0.write(b"data").unwrap(), 4);
+    }
+}
@@ -16,8 +16,10 @@ extern crate log;
 pub mod debuglog;
 pub mod dev;
 pub mod error;
+pub mod hypervisor;
 pub mod ipc;
 pub mod object;
+pub mod signal;
 pub mod task;
 pub mod util;
 pub mod vm;
@@ -19,3 +19,45 @@ impl Handle {
     }
 }
 // ANCHOR_END: handle
+
+/// Get the object type number of `object`, as `zx_obj_type_t`.
+///
+/// The names are the ones given by `impl_kobject!`, so that renaming an
+/// object type can not silently break the mapping.
+pub fn obj_type(object: &Arc<dyn KernelObject>) -> u32 {
+    use crate::{
+        debuglog::DebugLog,
+        dev::{BusTransactionInitiator, Interrupt, Iommu, PciDevice, PinnedMemoryToken, Resource},
+        hypervisor::{Guest, Vcpu},
+        ipc::{Channel, Fifo, Socket},
+        signal::{Clock, Event, Port, Timer},
+        task::{Job, Process, Profile, Thread},
+        vm::{Pager, VmAddressRegion, VmObject},
+    };
+    match object.type_name() {
+        Process::TYPE_NAME => 1,
+        Thread::TYPE_NAME => 2,
+        VmObject::TYPE_NAME => 3,
+        Channel::TYPE_NAME => 4,
+        Event::TYPE_NAME => 5,
+        Port::TYPE_NAME => 6,
+        Interrupt::TYPE_NAME => 9,
+        PciDevice::TYPE_NAME => 11,
+        DebugLog::TYPE_NAME => 12,
+        Socket::TYPE_NAME => 14,
+        Resource::TYPE_NAME => 15,
+        Job::TYPE_NAME => 17,
+        VmAddressRegion::TYPE_NAME => 18,
+        Fifo::TYPE_NAME => 19,
+        Guest::TYPE_NAME => 20,
+        Vcpu::TYPE_NAME => 21,
+        Timer::TYPE_NAME => 22,
+        Iommu::TYPE_NAME => 23,
+        BusTransactionInitiator::TYPE_NAME => 24,
+        Profile::TYPE_NAME => 25,
+        PinnedMemoryToken::TYPE_NAME => 26,
+        Pager::TYPE_NAME => 28,
+        Clock::TYPE_NAME => 30,
+        _ => 0,
+    }
+}
@@ -1,15 +1,23 @@
+use crate::signal::{PacketSignal, Payload, Port, PortPacket, WaitAsyncOptions};
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::fmt::Debug;
+use core::future::Future;
+use core::pin::Pin;
 use core::sync::atomic::*;
+use core::task::{Context, Poll};
 use downcast_rs::{impl_downcast, DowncastSync};
 use spin::Mutex;
 
 mod handle;
 mod rights;
+mod signal;
 
 pub use self::handle::*;
 pub use self::rights::*;
+pub use self::signal::*;
 pub use super::*;
 
 /// 内核对象公共接口
@@ -22,6 +30,19 @@ pub trait KernelObject: DowncastSync + Debug {
     fn name(&self) -> String;
     /// 设置对象名称
     fn set_name(&self, name: &str);
+    /// 获取对象当前的信号
+    fn signal(&self) -> Signal;
+    /// 置位对象的信号
+    fn signal_set(&self, signal: Signal);
+    /// 清除对象的信号
+    fn signal_clear(&self, signal: Signal);
+    /// 添加信号回调函数
+    ///
+    /// 每当信号发生变化时调用回调函数，回调返回 `true` 时将其移除。
+    /// 返回的 ID 可用于 `remove_signal_callback`。
+    fn add_signal_callback(&self, callback: SignalHandler) -> SignalCallbackId;
+    /// 移除信号回调函数，若其已被移除则什么也不做
+    fn remove_signal_callback(&self, id: SignalCallbackId);
     /// 尝试获取对象伙伴
     ///
     /// 当前该对象必须是 `Channel`
@@ -50,6 +71,103 @@ pub trait KernelObject: DowncastSync + Debug {
 
 impl_downcast!(sync KernelObject);
 
+impl dyn KernelObject {
+    /// 异步等待对象的任一信号 `signal` 被置位，返回当时对象的全部信号
+    pub fn wait_signal(self: &Arc<Self>, signal: Signal) -> impl Future<Output = Signal> {
+        struct SignalFuture {
+            object: Arc<dyn KernelObject>,
+            signal: Signal,
+            callback: Option<SignalCallbackId>,
+        }
+
+        impl Drop for SignalFuture {
+            fn drop(&mut self) {
+                // 被取消的等待不再留下回调
+                if let Some(id) = self.callback {
+                    self.object.remove_signal_callback(id);
+                }
+            }
+        }
+
+        impl Future for SignalFuture {
+            type Output = Signal;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                let current_signal = self.object.signal();
+                if !(current_signal & self.signal).is_empty() {
+                    return Poll::Ready(current_signal);
+                }
+                if self.callback.is_none() {
+                    let waker = cx.waker().clone();
+                    let signal = self.signal;
+                    let id = self.object.add_signal_callback(Box::new(move |s| {
+                        if (s & signal).is_empty() {
+                            return false;
+                        }
+                        waker.wake_by_ref();
+                        true
+                    }));
+                    self.callback = Some(id);
+                }
+                Poll::Pending
+            }
+        }
+
+        SignalFuture {
+            object: self.clone(),
+            signal,
+            callback: None,
+        }
+    }
+
+    /// 当对象的任一信号 `signal` 被置位时，向端口 `port` 发送一个信号包
+    ///
+    /// 若 `options` 包含 `REPEATING`，则每次信号被置位时都发送，
+    /// 直到端口被销毁。
+    pub fn send_signal_to_port_async(
+        self: &Arc<Self>,
+        signal: Signal,
+        port: &Arc<Port>,
+        key: u64,
+        options: WaitAsyncOptions,
+    ) {
+        let port = Arc::downgrade(port);
+        let repeating = options.contains(WaitAsyncOptions::REPEATING);
+        let observer = Port::new_observer_id();
+        // 信号是否已处于置位状态，只在其从无到有时发送
+        let asserted = AtomicBool::new(false);
+        self.add_signal_callback(Box::new(move |current| {
+            let port = match port.upgrade() {
+                Some(port) => port,
+                None => return true,
+            };
+            if (current & signal).is_empty() {
+                asserted.store(false, Ordering::SeqCst);
+                return false;
+            }
+            if asserted.swap(true, Ordering::SeqCst) {
+                return false;
+            }
+            let packet = PacketSignal {
+                trigger: signal,
+                observed: current,
+                count: 1,
+                timestamp: 0,
+            };
+            if repeating {
+                port.push_repeating(observer, key, packet);
+                return false;
+            }
+            port.push(PortPacket {
+                key,
+                status: 0,
+                data: Payload::SignalOne(packet),
+            });
+            true
+        }));
+    }
+}
+
 /// 对象 ID 类型
 pub type KoID = u64;
 
@@ -64,8 +182,16 @@ pub struct KObjectBase {
 #[derive(Default)]
 struct KObjectBaseInner {
     name: String,
+    signal: Signal,
+    signal_callbacks: Vec<(SignalCallbackId, SignalHandler)>,
 }
 
+/// 信号回调函数，返回 `true` 表示处理完毕，之后不再被调用
+pub type SignalHandler = Box<dyn Fn(Signal) -> bool + Send>;
+
+/// 信号回调函数的 ID，在所有对象中唯一
+pub type SignalCallbackId = u64;
+
 impl Default for KObjectBase {
     /// 创建一个新 `KObjectBase`
     fn default() -> Self {
@@ -83,14 +209,23 @@ impl KObjectBase {
     }
 
     /// 生成一个唯一的 ID
+    ///
+    /// The first ID is randomized so that IDs are not predictable across boots.
     fn new_koid() -> KoID {
-        static NEXT_KOID: AtomicU64 = AtomicU64::new(1024);
+        lazy_static::lazy_static! {
+            static ref NEXT_KOID: AtomicU64 =
+                AtomicU64::new(1024 + crate::util::random::random_u64() % 0x1_0000);
+        }
         NEXT_KOID.fetch_add(1, Ordering::SeqCst)
     }
     /// 获取对象名称
     pub fn name(&self) -> String {
         self.inner.lock().name.clone()
     }
+    /// Get the name without waiting for the lock, `None` if it is held.
+    pub fn try_name(&self) -> Option<String> {
+        Some(self.inner.try_lock()?.name.clone())
+    }
     /// 设置对象名称
     pub fn set_name(&self, name: &str) {
         self.inner.lock().name = String::from(name);
@@ -102,9 +237,72 @@ impl KObjectBase {
             id: Self::new_koid(),
             inner: Mutex::new(KObjectBaseInner {
                 name: String::from(name),
+                ..Default::default()
             }),
         }
     }
+
+    /// 创建一个带有初始信号 `signal` 的 `KObjectBase`
+    pub fn with_signal(signal: Signal) -> Self {
+        KObjectBase {
+            id: Self::new_koid(),
+            inner: Mutex::new(KObjectBaseInner {
+                signal,
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// 获取对象当前的信号
+    pub fn signal(&self) -> Signal {
+        self.inner.lock().signal
+    }
+
+    /// 先清除 `clear` 再置位 `set`，信号发生变化时调用回调函数
+    pub fn signal_change(&self, clear: Signal, set: Signal) {
+        let mut inner = self.inner.lock();
+        let old_signal = inner.signal;
+        inner.signal.remove(clear);
+        inner.signal.insert(set);
+        let new_signal = inner.signal;
+        if new_signal == old_signal {
+            return;
+        }
+        inner.signal_callbacks.retain(|(_, f)| !f(new_signal));
+    }
+
+    /// 置位信号
+    pub fn signal_set(&self, signal: Signal) {
+        self.signal_change(Signal::empty(), signal);
+    }
+
+    /// 清除信号
+    pub fn signal_clear(&self, signal: Signal) {
+        self.signal_change(signal, Signal::empty());
+    }
+
+    /// 添加信号回调函数，返回其 ID
+    pub fn add_signal_callback(&self, callback: SignalHandler) -> SignalCallbackId {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        let mut inner = self.inner.lock();
+        // 先用当前信号检查一次，以免错过添加回调之前发生的信号
+        if !callback(inner.signal) {
+            inner.signal_callbacks.push((id, callback));
+        }
+        id
+    }
+
+    /// 移除信号回调函数
+    pub fn remove_signal_callback(&self, id: SignalCallbackId) {
+        let mut inner = self.inner.lock();
+        inner.signal_callbacks.retain(|(i, _)| *i != id);
+    }
+
+    /// 获取信号回调函数的数量
+    pub fn signal_callback_count(&self) -> usize {
+        self.inner.lock().signal_callbacks.len()
+    }
 }
 
 /// 为内核对象 struct 自动实现 `KernelObject` trait 的宏。
@@ -119,8 +317,7 @@ macro_rules! impl_kobject {
                 self.base.id
             }
             fn type_name(&self) -> &str {
-                // 用 stringify! 宏将输入转成字符串
-                stringify!($class)
+                Self::TYPE_NAME
             }
             // 注意宏里面的类型要写完整路径，例如：alloc::string::String
             fn name(&self) -> alloc::string::String {
@@ -130,9 +327,29 @@ macro_rules! impl_kobject {
                 // 直接访问内部的 pub 方法
                 self.base.set_name(name)
             }
+            fn signal(&self) -> Signal {
+                self.base.signal()
+            }
+            fn signal_set(&self, signal: Signal) {
+                self.base.signal_set(signal);
+            }
+            fn signal_clear(&self, signal: Signal) {
+                self.base.signal_clear(signal);
+            }
+            fn add_signal_callback(&self, callback: SignalHandler) -> SignalCallbackId {
+                self.base.add_signal_callback(callback)
+            }
+            fn remove_signal_callback(&self, id: SignalCallbackId) {
+                self.base.remove_signal_callback(id);
+            }
             // 可以传入任意数量的函数，覆盖 trait 的默认实现
             $( $fn )*
         }
+        impl $class {
+            /// 对象的类型名，即 `type_name` 的返回值
+            // 用 stringify! 宏将输入转成字符串
+            pub const TYPE_NAME: &'static str = stringify!($class);
+        }
         // 为对象实现 Debug trait
         impl core::fmt::Debug for $class {
             fn fmt(
@@ -170,6 +387,7 @@ impl DummyObject {
 #[cfg(test)]
 #[test]
 fn impl_kobject() {
+    kernel_hal_unix::init();
     use alloc::format;
     let dummy = DummyObject::new();
     let object: Arc<dyn KernelObject> = dummy;
@@ -183,3 +401,59 @@ fn impl_kobject() {
     );
     let _result: Arc<DummyObject> = object.downcast_arc::<DummyObject>().unwrap();
 }
+
+#[cfg(test)]
+#[test]
+fn signal() {
+    kernel_hal_unix::init();
+    use core::sync::atomic::AtomicU32;
+    let object = DummyObject::new();
+    assert_eq!(object.signal(), Signal::empty());
+
+    let count = Arc::new(AtomicU32::new(0));
+    let count1 = count.clone();
+    object.add_signal_callback(Box::new(move |signal| {
+        count1.fetch_add(1, Ordering::SeqCst);
+        signal.contains(Signal::READABLE)
+    }));
+    // the callback is checked once when added
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    object.signal_set(Signal::WRITABLE);
+    assert_eq!(object.signal(), Signal::WRITABLE);
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    // no change, no callback
+    object.signal_set(Signal::WRITABLE);
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+
+    // the callback is removed once it returns true
+    object.signal_set(Signal::READABLE);
+    object.signal_clear(Signal::READABLE);
+    assert_eq!(object.signal(), Signal::WRITABLE);
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+}
+
+#[cfg(test)]
+#[async_std::test]
+async fn wait_signal() {
+    kernel_hal_unix::init();
+    use futures::FutureExt;
+    let object: Arc<dyn KernelObject> = DummyObject::new();
+    let future = object.wait_signal(Signal::READABLE);
+    futures::pin_mut!(future);
+    assert_eq!(future.as_mut().now_or_never(), None);
+
+    object.signal_set(Signal::WRITABLE);
+    assert_eq!(future.as_mut().now_or_never(), None);
+    object.signal_set(Signal::READABLE);
+    assert_eq!(future.await, Signal::READABLE | Signal::WRITABLE);
+
+    // a canceled wait removes its callback
+    let dummy = DummyObject::new();
+    let object: Arc<dyn KernelObject> = dummy.clone();
+    let mut future = Box::pin(object.wait_signal(Signal::READABLE));
+    assert_eq!(future.as_mut().now_or_never(), None);
+    assert_eq!(dummy.base.signal_callback_count(), 1);
+    drop(future);
+    assert_eq!(dummy.base.signal_callback_count(), 0);
+}
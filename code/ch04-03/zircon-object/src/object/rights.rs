@@ -59,8 +59,62 @@ bitflags! {
         /// TRANSFER | DUPLICATE | WRITE | INSPECT
         const DEFAULT_RESOURCE = Self::TRANSFER.bits | Self::DUPLICATE.bits | Self::WRITE.bits | Self::INSPECT.bits;
 
+        /// BASIC | IO | PROPERTY | SIGNAL | SIGNAL_PEER
+        const DEFAULT_SOCKET = Self::BASIC.bits | Self::IO.bits | Self::PROPERTY.bits | Self::SIGNAL.bits | Self::SIGNAL_PEER.bits;
+
+        /// BASIC | IO | SIGNAL | SIGNAL_PEER
+        const DEFAULT_FIFO = Self::BASIC.bits | Self::IO.bits | Self::SIGNAL.bits | Self::SIGNAL_PEER.bits;
+
+        /// BASIC | APPLY_PROFILE
+        const DEFAULT_PROFILE = Self::BASIC.bits | Self::APPLY_PROFILE.bits;
+
+        /// BASIC | IO
+        const DEFAULT_CLOCK = Self::BASIC.bits | Self::IO.bits;
+
+        /// BASIC | WRITE | SIGNAL
+        const DEFAULT_TIMER = Self::BASIC.bits | Self::WRITE.bits | Self::SIGNAL.bits;
+
+        /// (BASIC & !WAIT) | IO
+        const DEFAULT_PORT = (Self::BASIC.bits & !Self::WAIT.bits) | Self::IO.bits;
+
+        /// BASIC | IO | SIGNAL
+        const DEFAULT_INTERRUPT = Self::BASIC.bits | Self::IO.bits | Self::SIGNAL.bits;
+
+        /// BASIC
+        const DEFAULT_IOMMU = Self::BASIC.bits;
+
+        /// BASIC | IO | MAP
+        const DEFAULT_BTI = Self::BASIC.bits | Self::IO.bits | Self::MAP.bits;
+
+        /// BASIC | IO
+        const DEFAULT_PCI_DEVICE = Self::BASIC.bits | Self::IO.bits;
+
+        /// BASIC | IO
+        const DEFAULT_BLOCK_DEVICE = Self::BASIC.bits | Self::IO.bits;
+
+        /// BASIC | IO
+        const DEFAULT_ETH_DEVICE = Self::BASIC.bits | Self::IO.bits;
+
+        /// INSPECT
+        const DEFAULT_PMT = Self::INSPECT.bits;
+
+        /// TRANSFER | DUPLICATE | WRITE | INSPECT | MANAGE_PROCESS
+        const DEFAULT_GUEST = Self::TRANSFER.bits | Self::DUPLICATE.bits | Self::WRITE.bits | Self::INSPECT.bits | Self::MANAGE_PROCESS.bits;
+
+        /// BASIC | IO | EXECUTE | SIGNAL
+        const DEFAULT_VCPU = Self::BASIC.bits | Self::IO.bits | Self::EXECUTE.bits | Self::SIGNAL.bits;
+
+        /// BASIC | PROPERTY
+        const DEFAULT_PAGER = Self::BASIC.bits | Self::PROPERTY.bits;
+
         /// BASIC | WRITE | SIGNAL
         const DEFAULT_DEBUGLOG = Self::BASIC.bits | Self::WRITE.bits | Self::SIGNAL.bits;
+
+        /// BASIC | SIGNAL
+        const DEFAULT_EVENT = Self::BASIC.bits | Self::SIGNAL.bits;
+
+        /// WAIT | DUPLICATE | TRANSFER
+        const DEFAULT_SYSTEM_EVENT = Self::WAIT.bits | Self::DUPLICATE.bits | Self::TRANSFER.bits;
     }
 }
 // ANCHOR_END: rights
@@ -0,0 +1,43 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// 可等待内核对象向应用程序暴露的信号
+    #[derive(Default)]
+    pub struct Signal: u32 {
+        #[allow(clippy::identity_op)]
+        const READABLE                      = 1 << 0;
+        const WRITABLE                      = 1 << 1;
+        const PEER_CLOSED                   = 1 << 2;
+        const SIGNALED                      = 1 << 3;
+        const HANDLE_CLOSED                 = 1 << 23;
+
+        const KERNEL_ALL                    = 0xff_ffff;
+        const USER_ALL                      = 0xff << 24;
+
+        const SOCKET_PEER_WRITE_DISABLED    = 1 << 4;
+        const SOCKET_WRITE_DISABLED         = 1 << 5;
+        const SOCKET_READ_DISABLED          = Self::SOCKET_PEER_WRITE_DISABLED.bits;
+        const SOCKET_READ_THRESHOLD         = 1 << 10;
+        const SOCKET_WRITE_THRESHOLD        = 1 << 11;
+
+        const INTERRUPT_SIGNAL              = 1 << 4;
+
+        const CLOCK_STARTED                 = 1 << 4;
+
+        const VCPU_INTERRUPT                = 1 << 4;
+
+        const TIMER_SIGNALED                = Self::SIGNALED.bits;
+
+        const TASK_TERMINATED               = Self::SIGNALED.bits;
+        const PROCESS_TERMINATED            = Self::TASK_TERMINATED.bits;
+
+        const USER_SIGNAL_0                 = 1 << 24;
+        const USER_SIGNAL_1                 = 1 << 25;
+        const USER_SIGNAL_2                 = 1 << 26;
+        const USER_SIGNAL_3                 = 1 << 27;
+        const USER_SIGNAL_4                 = 1 << 28;
+        const USER_SIGNAL_5                 = 1 << 29;
+        const USER_SIGNAL_6                 = 1 << 30;
+        const USER_SIGNAL_7                 = 1 << 31;
+    }
+}
@@ -0,0 +1,374 @@
+use {
+    super::*, crate::object::*, alloc::sync::Arc, bitflags::bitflags, core::convert::TryFrom,
+    lazy_static::lazy_static, spin::Mutex,
+};
+
+/// A synthetic clock maintained by userspace.
+///
+/// ## SYNOPSIS
+///
+/// A clock is a one dimensional affine transformation of the monotonic
+/// reference timeline, which may be atomically adjusted by a clock
+/// maintainer, and observed by clients.
+///
+/// A clock is not started until its value is set by the first update, when
+/// it is signaled `CLOCK_STARTED`. Before that, reading the clock returns its
+/// backstop time.
+///
+/// The value of a monotonic clock never goes backwards, and the value of a
+/// continuous clock can only be set once when it is started. Other updates
+/// adjust its rate in parts per million.
+pub struct Clock {
+    base: KObjectBase,
+    options: ClockOptions,
+    backstop_time: i64,
+    inner: Mutex<ClockInner>,
+}
+
+impl_kobject!(Clock);
+
+struct ClockInner {
+    transform: ClockTransformation,
+    started: bool,
+    error_bound: u64,
+    last_value_update_ticks: i64,
+    last_rate_adjust_update_ticks: i64,
+    last_error_bounds_update_ticks: i64,
+    generation_counter: u32,
+}
+
+bitflags! {
+    /// Options to create a clock.
+    pub struct ClockOptions: u64 {
+        #[allow(clippy::identity_op)]
+        /// The value of the clock never goes backwards.
+        const MONOTONIC     = 1 << 0;
+        /// The value of the clock never jumps, implies `MONOTONIC`.
+        const CONTINUOUS    = 1 << 1;
+        /// Start the clock as a clone of the monotonic clock when created.
+        const AUTO_START    = 1 << 2;
+    }
+}
+
+/// The arguments to update a clock, `None` for the fields not updated.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ClockUpdateArgs {
+    /// The new value of the clock.
+    pub value: Option<i64>,
+    /// The new rate adjustment in parts per million.
+    pub rate_adjust: Option<i32>,
+    /// The new error bound of the clock in nanoseconds.
+    pub error_bound: Option<u64>,
+}
+
+/// The rate of a clock, as a ratio of synthetic ticks to reference ticks.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ClockRate {
+    /// Ticks of the synthetic clock.
+    pub synthetic_ticks: u32,
+    /// Ticks of the reference clock.
+    pub reference_ticks: u32,
+}
+
+/// An affine transformation from the reference timeline to the synthetic one.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ClockTransformation {
+    /// The offset on the reference timeline.
+    pub reference_offset: i64,
+    /// The offset on the synthetic timeline.
+    pub synthetic_offset: i64,
+    /// The rate of the synthetic timeline.
+    pub rate: ClockRate,
+}
+
+impl ClockTransformation {
+    /// Transform the reference time `reference` to the synthetic time.
+    pub fn apply(&self, reference: i64) -> i64 {
+        let delta = (reference as i128 - self.reference_offset as i128)
+            * self.rate.synthetic_ticks as i128
+            / self.rate.reference_ticks as i128;
+        let value = delta + self.synthetic_offset as i128;
+        i64::try_from(value).unwrap_or(if value < 0 { i64::MIN } else { i64::MAX })
+    }
+}
+
+/// Details of a clock.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ClockDetails {
+    /// The options of the clock.
+    pub options: u64,
+    /// The backstop time of the clock.
+    pub backstop_time: i64,
+    /// The transformation from the tick counter to the clock.
+    pub ticks_to_synthetic: ClockTransformation,
+    /// The transformation from the monotonic clock to the clock.
+    pub mono_to_synthetic: ClockTransformation,
+    /// The error bound of the clock, `u64::MAX` if unknown.
+    pub error_bound: u64,
+    /// The tick counter when the details are queried.
+    pub query_ticks: i64,
+    /// The tick counter when the value is updated last time.
+    pub last_value_update_ticks: i64,
+    /// The tick counter when the rate is adjusted last time.
+    pub last_rate_adjust_update_ticks: i64,
+    /// The tick counter when the error bound is updated last time.
+    pub last_error_bounds_update_ticks: i64,
+    /// The number of updates of the clock.
+    pub generation_counter: u32,
+    padding1: [u8; 4],
+}
+
+/// The unknown error bound.
+pub const CLOCK_UNKNOWN_ERROR: u64 = u64::MAX;
+
+/// The maximum rate adjustment in parts per million.
+const MAX_RATE_ADJUST: i32 = 1000;
+
+impl Clock {
+    /// Create a new clock with the `backstop_time`.
+    ///
+    /// An auto-started clock starts from the current monotonic time, which
+    /// must not be earlier than `backstop_time`.
+    pub fn create(options: ClockOptions, backstop_time: i64) -> ZxResult<Arc<Self>> {
+        if options.contains(ClockOptions::CONTINUOUS) && !options.contains(ClockOptions::MONOTONIC)
+        {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut inner = ClockInner {
+            transform: ClockTransformation {
+                reference_offset: 0,
+                synthetic_offset: backstop_time,
+                rate: ClockRate {
+                    synthetic_ticks: 0,
+                    reference_ticks: 1,
+                },
+            },
+            started: false,
+            error_bound: CLOCK_UNKNOWN_ERROR,
+            last_value_update_ticks: 0,
+            last_rate_adjust_update_ticks: 0,
+            last_error_bounds_update_ticks: 0,
+            generation_counter: 0,
+        };
+        let mut signal = Signal::empty();
+        if options.contains(ClockOptions::AUTO_START) {
+            let now = now();
+            if backstop_time > now {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            inner.transform = ClockTransformation {
+                reference_offset: now,
+                synthetic_offset: now,
+                rate: ClockRate {
+                    synthetic_ticks: 1,
+                    reference_ticks: 1,
+                },
+            };
+            inner.started = true;
+            inner.last_value_update_ticks = now;
+            inner.last_rate_adjust_update_ticks = now;
+            signal = Signal::CLOCK_STARTED;
+        }
+        Ok(Arc::new(Clock {
+            base: KObjectBase::with_signal(signal),
+            options,
+            backstop_time,
+            inner: Mutex::new(inner),
+        }))
+    }
+
+    /// Read the current value of the clock.
+    pub fn read(&self) -> i64 {
+        self.inner.lock().transform.apply(now())
+    }
+
+    /// Update the value, the rate or the error bound of the clock.
+    pub fn update(&self, args: ClockUpdateArgs) -> ZxResult {
+        let now = now();
+        let mut inner = self.inner.lock();
+        if let Some(rate_adjust) = args.rate_adjust {
+            if !(-MAX_RATE_ADJUST..=MAX_RATE_ADJUST).contains(&rate_adjust) {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            if !inner.started && args.value.is_none() {
+                return Err(ZxError::BAD_STATE);
+            }
+        }
+        let current = inner.transform.apply(now);
+        if let Some(value) = args.value {
+            if value < self.backstop_time {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            if inner.started {
+                if self.options.contains(ClockOptions::CONTINUOUS) {
+                    return Err(ZxError::INVALID_ARGS);
+                }
+                if self.options.contains(ClockOptions::MONOTONIC) && value < current {
+                    return Err(ZxError::INVALID_ARGS);
+                }
+            }
+        }
+        if args.value.is_some() || args.rate_adjust.is_some() {
+            inner.transform.reference_offset = now;
+            inner.transform.synthetic_offset = args.value.unwrap_or(current);
+        }
+        if let Some(rate_adjust) = args.rate_adjust {
+            inner.transform.rate = ClockRate {
+                synthetic_ticks: (1_000_000 + rate_adjust) as u32,
+                reference_ticks: 1_000_000,
+            };
+            inner.last_rate_adjust_update_ticks = now;
+        }
+        if args.value.is_some() {
+            if !inner.started {
+                if args.rate_adjust.is_none() {
+                    inner.transform.rate = ClockRate {
+                        synthetic_ticks: 1,
+                        reference_ticks: 1,
+                    };
+                }
+                inner.started = true;
+                self.base.signal_set(Signal::CLOCK_STARTED);
+            }
+            inner.last_value_update_ticks = now;
+        }
+        if let Some(error_bound) = args.error_bound {
+            inner.error_bound = error_bound;
+            inner.last_error_bounds_update_ticks = now;
+        }
+        inner.generation_counter += 1;
+        Ok(())
+    }
+
+    /// Get the details of the clock.
+    pub fn get_details(&self) -> ClockDetails {
+        let inner = self.inner.lock();
+        ClockDetails {
+            options: self.options.bits(),
+            backstop_time: self.backstop_time,
+            // the tick counter is the monotonic clock
+            ticks_to_synthetic: inner.transform,
+            mono_to_synthetic: inner.transform,
+            error_bound: inner.error_bound,
+            query_ticks: now(),
+            last_value_update_ticks: inner.last_value_update_ticks,
+            last_rate_adjust_update_ticks: inner.last_rate_adjust_update_ticks,
+            last_error_bounds_update_ticks: inner.last_error_bounds_update_ticks,
+            generation_counter: inner.generation_counter,
+            padding1: [0; 4],
+        }
+    }
+}
+
+lazy_static! {
+    static ref UTC_CLOCK: Arc<Clock> = {
+        let rtc = kernel_hal::rtc_now().map(|time| time.as_nanos() as i64);
+        info!("UTC time from RTC: {:?}ns", rtc);
+        let clock = Clock::create(ClockOptions::MONOTONIC, rtc.unwrap_or(0)).unwrap();
+        if rtc.is_some() {
+            let args = ClockUpdateArgs {
+                value: rtc,
+                ..Default::default()
+            };
+            clock.update(args).unwrap();
+        }
+        clock
+    };
+}
+
+impl Clock {
+    /// Get the UTC clock of the system.
+    ///
+    /// It is started from the time of the RTC when it is first used, which is
+    /// also its backstop time. Without an RTC, its backstop time is 0, and it
+    /// is not started until the first update from userspace.
+    pub fn utc() -> Arc<Self> {
+        UTC_CLOCK.clone()
+    }
+}
+
+/// Get the current monotonic time in nanoseconds.
+fn now() -> i64 {
+    kernel_hal::timer_now().as_nanos() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update() {
+        kernel_hal_unix::init();
+        let clock = Clock::create(ClockOptions::MONOTONIC, 1000).unwrap();
+        assert_eq!(clock.read(), 1000);
+        assert_eq!(clock.signal(), Signal::empty());
+        let rate = ClockUpdateArgs {
+            rate_adjust: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(clock.update(rate), Err(ZxError::BAD_STATE));
+        let value = |value| ClockUpdateArgs {
+            value: Some(value),
+            ..Default::default()
+        };
+        assert_eq!(clock.update(value(999)), Err(ZxError::INVALID_ARGS));
+
+        clock.update(value(1_000_000_000_000)).unwrap();
+        assert_eq!(clock.signal(), Signal::CLOCK_STARTED);
+        let read = clock.read();
+        assert!(read >= 1_000_000_000_000);
+        // monotonic clocks never go backwards
+        assert_eq!(clock.update(value(2000)), Err(ZxError::INVALID_ARGS));
+        clock.update(rate).unwrap();
+        assert!(clock.read() >= read);
+
+        let details = clock.get_details();
+        assert_eq!(details.backstop_time, 1000);
+        assert_eq!(details.generation_counter, 2);
+        assert_eq!(details.ticks_to_synthetic.rate.synthetic_ticks, 1_000_010);
+        assert_eq!(details.error_bound, CLOCK_UNKNOWN_ERROR);
+    }
+
+    #[test]
+    fn utc() {
+        kernel_hal_unix::init();
+        let utc = Clock::utc();
+        assert!(Arc::ptr_eq(&utc, &Clock::utc()));
+        let rtc = kernel_hal::rtc_now().unwrap().as_nanos() as i64;
+        let backstop_time = utc.get_details().backstop_time;
+        assert!(backstop_time > 0 && backstop_time <= rtc);
+        assert!(utc.signal().contains(Signal::CLOCK_STARTED));
+        assert!(utc.read() >= backstop_time);
+    }
+
+    #[test]
+    fn continuous() {
+        kernel_hal_unix::init();
+        assert_eq!(
+            Clock::create(ClockOptions::CONTINUOUS, 0).err(),
+            Some(ZxError::INVALID_ARGS)
+        );
+        let options = ClockOptions::MONOTONIC | ClockOptions::CONTINUOUS;
+        let clock = Clock::create(options | ClockOptions::AUTO_START, 0).unwrap();
+        assert_eq!(clock.signal(), Signal::CLOCK_STARTED);
+        let update = ClockUpdateArgs {
+            value: Some(i64::MAX / 2),
+            ..Default::default()
+        };
+        assert_eq!(clock.update(update), Err(ZxError::INVALID_ARGS));
+
+        let transform = ClockTransformation {
+            reference_offset: 100,
+            synthetic_offset: 1000,
+            rate: ClockRate {
+                synthetic_ticks: 2,
+                reference_ticks: 1,
+            },
+        };
+        assert_eq!(transform.apply(200), 1200);
+        assert_eq!(transform.apply(i64::MAX), i64::MAX);
+    }
+}
@@ -0,0 +1,22 @@
+use {super::*, crate::object::*, alloc::sync::Arc};
+
+/// Signalable event for concurrent programming.
+///
+/// ## SYNOPSIS
+///
+/// An event has no state other than its signals. It is signaled by whoever
+/// holds it with `SIGNAL` right, and waited by others.
+pub struct Event {
+    base: KObjectBase,
+}
+
+impl_kobject!(Event);
+
+impl Event {
+    /// Create a new event.
+    pub fn new() -> Arc<Self> {
+        Arc::new(Event {
+            base: KObjectBase::new(),
+        })
+    }
+}
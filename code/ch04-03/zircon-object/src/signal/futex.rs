@@ -0,0 +1,408 @@
+use {
+    super::*,
+    crate::object::*,
+    crate::task::Thread,
+    alloc::collections::VecDeque,
+    alloc::sync::{Arc, Weak},
+    alloc::vec::Vec,
+    core::future::Future,
+    core::pin::Pin,
+    core::sync::atomic::{AtomicI32, Ordering},
+    core::task::{Context, Poll, Waker},
+    spin::Mutex,
+};
+
+/// A primitive for creating userspace synchronization tools.
+///
+/// ## SYNOPSIS
+///
+/// A **futex** is a Fast Userspace muTEX. It is a low level synchronization
+/// primitive which is a building block for higher level APIs such as
+/// `pthread_mutex_t` and `pthread_cond_t`.
+///
+/// Futexes are designed to not enter the kernel or allocate kernel resources
+/// in the uncontested case. A futex is identified by the address of a 32-bit
+/// integer in user memory, and is created by the process on the first wait.
+///
+/// A futex may have an owner thread. While it is owned, the owner inherits
+/// the highest priority of the threads waiting on it. Priority is not
+/// propagated transitively through futexes the owner itself waits on.
+pub struct Futex {
+    base: KObjectBase,
+    value: &'static AtomicI32,
+    inner: Mutex<FutexInner>,
+}
+
+impl_kobject!(Futex);
+
+#[derive(Default)]
+struct FutexInner {
+    /// Waiters in FIFO order.
+    ///
+    /// A waiter is owned by its future, so a canceled wait leaves a dead
+    /// entry here which is skipped on wake.
+    waiter_queue: VecDeque<Weak<Waiter>>,
+    /// The thread owning this futex.
+    owner: Option<Arc<Thread>>,
+}
+
+impl Futex {
+    /// Create a new futex on the value.
+    pub fn new(value: &'static AtomicI32) -> Arc<Self> {
+        Arc::new(Futex {
+            base: KObjectBase::default(),
+            value,
+            inner: Mutex::new(FutexInner::default()),
+        })
+    }
+
+    /// Wait on this futex.
+    ///
+    /// The returned future checks that the value of the futex is still
+    /// `current_value` under the futex lock, otherwise it returns `BAD_STATE`.
+    /// It completes when woken by `wake` or `requeue`.
+    pub fn wait(self: &Arc<Self>, current_value: i32) -> impl Future<Output = ZxResult> {
+        self.wait_with_owner(current_value, None, None)
+    }
+
+    /// Wait on this futex as `thread`, and set the owner to `new_owner`
+    /// if the value check passes.
+    ///
+    /// The owner inherits the priority of `thread` while it is waiting.
+    pub fn wait_with_owner(
+        self: &Arc<Self>,
+        current_value: i32,
+        thread: Option<Arc<Thread>>,
+        new_owner: Option<Arc<Thread>>,
+    ) -> impl Future<Output = ZxResult> {
+        #[must_use = "wait does nothing unless polled/`await`-ed"]
+        struct FutexFuture {
+            futex: Arc<Futex>,
+            waiter: Arc<Waiter>,
+            current_value: i32,
+            new_owner: Option<Arc<Thread>>,
+            queued: bool,
+        }
+        impl Drop for FutexFuture {
+            fn drop(&mut self) {
+                // a canceled waiter no longer boosts the owner
+                let mut waiter = self.waiter.inner.lock();
+                if self.queued && !waiter.woken {
+                    waiter.woken = true;
+                    let futex = waiter.futex.take();
+                    drop(waiter);
+                    if let Some(futex) = futex {
+                        futex.inner.lock().update_owner_priority(futex.id());
+                    }
+                }
+            }
+        }
+        impl Future for FutexFuture {
+            type Output = ZxResult;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                if self.queued {
+                    let mut waiter = self.waiter.inner.lock();
+                    if waiter.woken {
+                        return Poll::Ready(Ok(()));
+                    }
+                    waiter.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                // first poll: check the value and enqueue atomically
+                let futex = self.futex.clone();
+                let mut inner = futex.inner.lock();
+                if futex.value.load(Ordering::SeqCst) != self.current_value {
+                    return Poll::Ready(Err(ZxError::BAD_STATE));
+                }
+                {
+                    let mut waiter = self.waiter.inner.lock();
+                    waiter.waker = Some(cx.waker().clone());
+                    waiter.futex = Some(futex.clone());
+                }
+                inner.waiter_queue.retain(|w| w.strong_count() != 0);
+                inner.waiter_queue.push_back(Arc::downgrade(&self.waiter));
+                let new_owner = self.new_owner.take();
+                inner.set_owner(futex.id(), new_owner);
+                self.queued = true;
+                Poll::Pending
+            }
+        }
+        FutexFuture {
+            futex: self.clone(),
+            waiter: Arc::new(Waiter {
+                thread,
+                inner: Mutex::new(WaiterInner::default()),
+            }),
+            current_value,
+            new_owner,
+            queued: false,
+        }
+    }
+
+    /// Wake some number of threads waiting on this futex.
+    ///
+    /// The owner of the futex is cleared.
+    /// Return the number of threads actually woken.
+    pub fn wake(&self, wake_count: usize) -> usize {
+        let mut inner = self.inner.lock();
+        let count = inner.wake(wake_count).len();
+        inner.set_owner(self.id(), None);
+        count
+    }
+
+    /// Wake one thread waiting on this futex, and make it the owner.
+    ///
+    /// If there is no waiter, the owner is cleared.
+    /// Return the number of threads actually woken.
+    pub fn wake_single_owner(&self) -> usize {
+        let mut inner = self.inner.lock();
+        let woken = inner.wake(1);
+        let owner = woken.first().and_then(|w| w.thread.clone());
+        inner.set_owner(self.id(), owner);
+        woken.len()
+    }
+
+    /// Get the owner of this futex.
+    pub fn owner(&self) -> Option<Arc<Thread>> {
+        self.inner.lock().owner.clone()
+    }
+
+    /// Wake some number of threads waiting on this futex,
+    /// and move more waiters to another wait queue.
+    ///
+    /// The owner of this futex is cleared, and the owner of `requeue` futex
+    /// is set to `new_requeue_owner`.
+    /// Return the numbers of threads actually woken and moved, or
+    /// `BAD_STATE` if the value of this futex is not `current_value`.
+    pub fn requeue(
+        &self,
+        current_value: i32,
+        wake_count: usize,
+        requeue_count: usize,
+        requeue: &Arc<Futex>,
+        new_requeue_owner: Option<Arc<Thread>>,
+    ) -> ZxResult<(usize, usize)> {
+        if core::ptr::eq(self, requeue.as_ref()) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut requeued = VecDeque::new();
+        let woken;
+        {
+            let mut inner = self.inner.lock();
+            if self.value.load(Ordering::SeqCst) != current_value {
+                return Err(ZxError::BAD_STATE);
+            }
+            woken = inner.wake(wake_count).len();
+            while requeued.len() < requeue_count {
+                let waiter = match inner.waiter_queue.pop_front() {
+                    Some(waiter) => waiter,
+                    None => break,
+                };
+                if let Some(waiter) = waiter.upgrade() {
+                    waiter.inner.lock().futex = Some(requeue.clone());
+                    requeued.push_back(Arc::downgrade(&waiter));
+                }
+            }
+            inner.set_owner(self.id(), None);
+        }
+        let moved = requeued.len();
+        let mut requeue_inner = requeue.inner.lock();
+        requeue_inner.waiter_queue.append(&mut requeued);
+        requeue_inner.set_owner(requeue.id(), new_requeue_owner);
+        Ok((woken, moved))
+    }
+
+    /// Get the number of live waiters.
+    pub fn waiter_count(&self) -> usize {
+        let inner = self.inner.lock();
+        inner
+            .waiter_queue
+            .iter()
+            .filter(|w| w.strong_count() != 0)
+            .count()
+    }
+}
+
+impl FutexInner {
+    /// Wake at most `wake_count` waiters, return the woken ones.
+    fn wake(&mut self, wake_count: usize) -> Vec<Arc<Waiter>> {
+        let mut woken = Vec::new();
+        while woken.len() < wake_count {
+            let waiter = match self.waiter_queue.pop_front() {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            // skip the canceled waiter
+            if let Some(waiter) = waiter.upgrade() {
+                waiter.wake();
+                woken.push(waiter);
+            }
+        }
+        woken
+    }
+
+    /// Change the owner of futex `id`, and move the inherited priority.
+    fn set_owner(&mut self, id: KoID, owner: Option<Arc<Thread>>) {
+        if let Some(old_owner) = self.owner.take() {
+            old_owner.set_inherited_priority(id, None);
+        }
+        self.owner = owner;
+        self.update_owner_priority(id);
+    }
+
+    /// Let the owner inherit the highest priority of waiters of futex `id`.
+    fn update_owner_priority(&mut self, id: KoID) {
+        self.waiter_queue.retain(|w| w.strong_count() != 0);
+        let owner = match &self.owner {
+            Some(owner) => owner,
+            None => return,
+        };
+        let priority = self
+            .waiter_queue
+            .iter()
+            .filter_map(|w| w.upgrade())
+            .filter(|w| !w.inner.lock().woken)
+            .filter_map(|w| w.thread.as_ref().map(|t| t.priority()))
+            .max();
+        owner.set_inherited_priority(id, priority);
+    }
+}
+
+struct Waiter {
+    /// The waiting thread.
+    thread: Option<Arc<Thread>>,
+    inner: Mutex<WaiterInner>,
+}
+
+#[derive(Default)]
+struct WaiterInner {
+    waker: Option<Waker>,
+    woken: bool,
+    /// The futex it is waiting on, changed on requeue.
+    futex: Option<Arc<Futex>>,
+}
+
+impl Waiter {
+    fn wake(&self) {
+        let mut inner = self.inner.lock();
+        inner.woken = true;
+        inner.futex = None;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{Job, Process, DEFAULT_PRIORITY};
+    use alloc::boxed::Box;
+    use futures::{future::FutureExt, poll};
+
+    fn new_value(value: i32) -> &'static AtomicI32 {
+        Box::leak(Box::new(AtomicI32::new(value)))
+    }
+
+    #[async_std::test]
+    async fn wait_wake() {
+        kernel_hal_unix::init();
+        let value = new_value(1);
+        let futex = Futex::new(value);
+        // the value has changed
+        assert_eq!(futex.wait(0).await, Err(ZxError::BAD_STATE));
+
+        let mut wait0 = Box::pin(futex.wait(1));
+        let mut wait1 = Box::pin(futex.wait(1));
+        assert!(poll!(wait0.as_mut()).is_pending());
+        assert!(poll!(wait1.as_mut()).is_pending());
+        assert_eq!(futex.waiter_count(), 2);
+
+        // FIFO order
+        assert_eq!(futex.wake(1), 1);
+        assert_eq!(poll!(wait0.as_mut()), Poll::Ready(Ok(())));
+        assert!(poll!(wait1.as_mut()).is_pending());
+        assert_eq!(futex.wake(usize::MAX), 1);
+        assert_eq!(wait1.await, Ok(()));
+        assert_eq!(futex.wake(1), 0);
+    }
+
+    #[test]
+    fn cancel() {
+        kernel_hal_unix::init();
+        let futex = Futex::new(new_value(0));
+        let mut wait0 = Box::pin(futex.wait(0));
+        assert!(wait0.as_mut().now_or_never().is_none());
+        drop(wait0);
+        assert_eq!(futex.waiter_count(), 0);
+        // the canceled waiter is not counted
+        assert_eq!(futex.wake(1), 0);
+    }
+
+    #[async_std::test]
+    async fn requeue() {
+        kernel_hal_unix::init();
+        let futex0 = Futex::new(new_value(0));
+        let futex1 = Futex::new(new_value(0));
+        let mut waits: Vec<_> = (0..4).map(|_| Box::pin(futex0.wait(0))).collect();
+        for wait in waits.iter_mut() {
+            assert!(poll!(wait.as_mut()).is_pending());
+        }
+        assert_eq!(
+            futex0.requeue(0, 1, 1, &futex0, None),
+            Err(ZxError::INVALID_ARGS)
+        );
+        assert_eq!(
+            futex0.requeue(1, 1, 1, &futex1, None),
+            Err(ZxError::BAD_STATE)
+        );
+
+        assert_eq!(futex0.requeue(0, 1, 2, &futex1, None), Ok((1, 2)));
+        assert_eq!(poll!(waits[0].as_mut()), Poll::Ready(Ok(())));
+        assert_eq!(futex0.waiter_count(), 1);
+        assert_eq!(futex1.waiter_count(), 2);
+
+        assert_eq!(futex1.wake(usize::MAX), 2);
+        assert_eq!(poll!(waits[1].as_mut()), Poll::Ready(Ok(())));
+        assert_eq!(poll!(waits[2].as_mut()), Poll::Ready(Ok(())));
+        assert!(poll!(waits[3].as_mut()).is_pending());
+    }
+
+    #[async_std::test]
+    async fn owner_priority() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").unwrap();
+        let owner = Thread::create(&proc, "owner").unwrap();
+        let waiter0 = Thread::create(&proc, "waiter0").unwrap();
+        let waiter1 = Thread::create(&proc, "waiter1").unwrap();
+        waiter0.set_base_priority(20);
+        waiter1.set_base_priority(24);
+
+        let futex = Futex::new(new_value(0));
+        let mut wait0 =
+            Box::pin(futex.wait_with_owner(0, Some(waiter0.clone()), Some(owner.clone())));
+        assert!(poll!(wait0.as_mut()).is_pending());
+        assert!(Arc::ptr_eq(&futex.owner().unwrap(), &owner));
+        assert_eq!(owner.priority(), 20);
+
+        let mut wait1 =
+            Box::pin(futex.wait_with_owner(0, Some(waiter1.clone()), Some(owner.clone())));
+        assert!(poll!(wait1.as_mut()).is_pending());
+        assert_eq!(owner.priority(), 24);
+
+        // a canceled waiter no longer boosts the owner
+        drop(wait1);
+        assert_eq!(owner.priority(), 20);
+
+        // the woken thread becomes the new owner
+        assert_eq!(futex.wake_single_owner(), 1);
+        assert_eq!(poll!(wait0.as_mut()), Poll::Ready(Ok(())));
+        assert!(Arc::ptr_eq(&futex.owner().unwrap(), &waiter0));
+        assert_eq!(owner.priority(), DEFAULT_PRIORITY);
+
+        assert_eq!(futex.wake(1), 0);
+        assert!(futex.owner().is_none());
+    }
+}
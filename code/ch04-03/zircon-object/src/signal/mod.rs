@@ -0,0 +1,10 @@
+use super::*;
+
+mod clock;
+mod event;
+mod futex;
+mod port;
+mod port_packet;
+mod timer;
+
+pub use self::{clock::*, event::*, futex::*, port::*, port_packet::*, timer::*};
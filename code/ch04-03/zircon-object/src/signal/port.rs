@@ -0,0 +1,296 @@
+use {
+    super::*,
+    crate::object::*,
+    alloc::collections::VecDeque,
+    alloc::sync::Arc,
+    bitflags::bitflags,
+    core::sync::atomic::{AtomicU64, Ordering},
+    spin::Mutex,
+};
+
+/// Signaling and mailbox primitive.
+///
+/// ## SYNOPSIS
+///
+/// Ports allow threads to wait for packets to be delivered from various
+/// events. These events include explicit queueing on the port, asynchronous
+/// waits on other objects, and interrupt firings of bound interrupt objects.
+///
+/// Packets from bound interrupts are delivered before other packets.
+///
+/// A repeating asynchronous wait queues at most one packet at a time. If the
+/// signals are asserted again before the packet is taken out, the `count` of
+/// the queued packet is increased instead.
+pub struct Port {
+    base: KObjectBase,
+    options: PortOptions,
+    inner: Mutex<PortInner>,
+}
+
+impl_kobject!(Port);
+
+#[derive(Default)]
+struct PortInner {
+    /// Packets and the IDs of the repeating observers which queue them.
+    queue: VecDeque<(Option<u64>, PortPacket)>,
+    /// Interrupt packets and their IDs.
+    interrupt_queue: VecDeque<(u64, PortPacket)>,
+    next_interrupt_id: u64,
+}
+
+bitflags! {
+    /// Options to create a port.
+    pub struct PortOptions: u32 {
+        #[allow(clippy::identity_op)]
+        /// Allow interrupts to be bound to the port.
+        const BIND_TO_INTERRUPT = 1 << 0;
+    }
+}
+
+bitflags! {
+    /// Options of an asynchronous signal wait.
+    pub struct WaitAsyncOptions: u32 {
+        #[allow(clippy::identity_op)]
+        /// Keep observing the signals after the first packet.
+        const REPEATING = 1 << 0;
+    }
+}
+
+impl Port {
+    /// Create a new `Port`.
+    pub fn new(options: u32) -> ZxResult<Arc<Self>> {
+        let options = PortOptions::from_bits(options).ok_or(ZxError::INVALID_ARGS)?;
+        Ok(Arc::new(Port {
+            base: KObjectBase::default(),
+            options,
+            inner: Default::default(),
+        }))
+    }
+
+    /// Get the options of the port.
+    pub fn options(&self) -> PortOptions {
+        self.options
+    }
+
+    /// Push a packet into the port.
+    pub fn push(&self, packet: PortPacket) {
+        let mut inner = self.inner.lock();
+        inner.queue.push_back((None, packet));
+        self.base.signal_set(Signal::READABLE);
+    }
+
+    /// Push a `SignalRep` packet for a repeating observer, or accumulate it
+    /// into the packet of the observer which is still in the queue.
+    pub(crate) fn push_repeating(&self, observer: u64, key: u64, signal: PacketSignal) {
+        let mut inner = self.inner.lock();
+        let queued = inner.queue.iter_mut().find(|(id, _)| *id == Some(observer));
+        if let Some((
+            _,
+            PortPacket {
+                data: Payload::SignalRep(queued),
+                ..
+            },
+        )) = queued
+        {
+            queued.count += signal.count;
+            queued.observed = signal.observed;
+            return;
+        }
+        let packet = PortPacket {
+            key,
+            status: 0,
+            data: Payload::SignalRep(signal),
+        };
+        inner.queue.push_back((Some(observer), packet));
+        self.base.signal_set(Signal::READABLE);
+    }
+
+    /// Generate a new ID for a repeating observer.
+    pub(crate) fn new_observer_id() -> u64 {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        NEXT_ID.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Push an interrupt packet into the port, return its ID.
+    pub(crate) fn push_interrupt(&self, packet: PortPacket) -> u64 {
+        let mut inner = self.inner.lock();
+        let id = inner.next_interrupt_id;
+        inner.next_interrupt_id += 1;
+        inner.interrupt_queue.push_back((id, packet));
+        self.base.signal_set(Signal::READABLE);
+        id
+    }
+
+    /// Remove a queued interrupt packet by its ID.
+    ///
+    /// Return `false` if the packet has been taken out.
+    pub(crate) fn remove_interrupt(&self, id: u64) -> bool {
+        let mut inner = self.inner.lock();
+        let len = inner.interrupt_queue.len();
+        inner.interrupt_queue.retain(|&(pid, _)| pid != id);
+        let removed = inner.interrupt_queue.len() != len;
+        if inner.queue.is_empty() && inner.interrupt_queue.is_empty() {
+            self.base.signal_clear(Signal::READABLE);
+        }
+        removed
+    }
+
+    /// Take a packet from the port if there is any.
+    pub fn try_pop(&self) -> Option<PortPacket> {
+        let mut inner = self.inner.lock();
+        let packet = match inner.interrupt_queue.pop_front() {
+            Some((_, packet)) => Some(packet),
+            None => inner.queue.pop_front().map(|(_, packet)| packet),
+        };
+        if inner.queue.is_empty() && inner.interrupt_queue.is_empty() {
+            self.base.signal_clear(Signal::READABLE);
+        }
+        packet
+    }
+
+    /// Asynchronous wait until at least one packet is available, then take it.
+    pub async fn wait(self: &Arc<Self>) -> PortPacket {
+        let object = self.clone() as Arc<dyn KernelObject>;
+        loop {
+            object.wait_signal(Signal::READABLE).await;
+            if let Some(packet) = self.try_pop() {
+                return packet;
+            }
+        }
+    }
+
+    /// Get the number of packets in the port.
+    pub fn len(&self) -> usize {
+        let inner = self.inner.lock();
+        inner.queue.len() + inner.interrupt_queue.len()
+    }
+
+    /// Whether there is no packet in the port.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+
+    fn user_packet(key: u64) -> PortPacket {
+        PortPacket {
+            key,
+            status: 0,
+            data: Payload::User([key as u8; 32]),
+        }
+    }
+
+    #[test]
+    fn options() {
+        kernel_hal_unix::init();
+        assert!(Port::new(0).is_ok());
+        assert!(Port::new(1)
+            .unwrap()
+            .options()
+            .contains(PortOptions::BIND_TO_INTERRUPT));
+        assert_eq!(Port::new(2).err(), Some(ZxError::INVALID_ARGS));
+    }
+
+    #[async_std::test]
+    async fn push_wait() {
+        kernel_hal_unix::init();
+        let port = Port::new(0).unwrap();
+        assert_eq!(port.wait().now_or_never(), None);
+
+        port.push(user_packet(1));
+        port.push(user_packet(2));
+        assert_eq!(port.signal(), Signal::READABLE);
+        assert_eq!(port.len(), 2);
+        assert_eq!(port.wait().await, user_packet(1));
+        assert_eq!(port.wait().await, user_packet(2));
+        assert!(port.is_empty());
+        assert_eq!(port.signal(), Signal::empty());
+    }
+
+    #[test]
+    fn interrupt_first() {
+        kernel_hal_unix::init();
+        let port = Port::new(1).unwrap();
+        port.push(user_packet(1));
+        let id = port.push_interrupt(user_packet(2));
+        let id3 = port.push_interrupt(user_packet(3));
+        assert!(port.remove_interrupt(id));
+        assert!(!port.remove_interrupt(id));
+        assert_eq!(port.try_pop(), Some(user_packet(3)));
+        assert!(!port.remove_interrupt(id3));
+        assert_eq!(port.try_pop(), Some(user_packet(1)));
+        assert_eq!(port.try_pop(), None);
+    }
+
+    #[test]
+    fn wait_async() {
+        kernel_hal_unix::init();
+        let object: Arc<dyn KernelObject> = DummyObject::new();
+        let port = Port::new(0).unwrap();
+        let signal_packet = |key, observed, count| PortPacket {
+            key,
+            status: 0,
+            data: Payload::SignalOne(PacketSignal {
+                trigger: Signal::READABLE,
+                observed,
+                count,
+                timestamp: 0,
+            }),
+        };
+        object.send_signal_to_port_async(Signal::READABLE, &port, 1, WaitAsyncOptions::empty());
+        assert!(port.is_empty());
+        object.signal_set(Signal::READABLE);
+        object.signal_clear(Signal::READABLE);
+        object.signal_set(Signal::READABLE);
+        assert_eq!(port.try_pop(), Some(signal_packet(1, Signal::READABLE, 1)));
+        assert_eq!(port.try_pop(), None);
+    }
+
+    #[test]
+    fn wait_async_repeating() {
+        kernel_hal_unix::init();
+        let object: Arc<dyn KernelObject> = DummyObject::new();
+        let port = Port::new(0).unwrap();
+        object.signal_set(Signal::READABLE);
+        object.send_signal_to_port_async(Signal::READABLE, &port, 2, WaitAsyncOptions::REPEATING);
+        // asserted when added
+        assert_eq!(port.len(), 1);
+        // no edge, no packet
+        object.signal_set(Signal::WRITABLE);
+        assert_eq!(port.len(), 1);
+
+        // coalesced into the queued packet
+        object.signal_clear(Signal::READABLE);
+        object.signal_set(Signal::READABLE);
+        object.signal_clear(Signal::READABLE);
+        object.signal_set(Signal::READABLE);
+        assert_eq!(port.len(), 1);
+        let packet = port.try_pop().unwrap();
+        assert_eq!(packet.key, 2);
+        assert_eq!(
+            packet.data,
+            Payload::SignalRep(PacketSignal {
+                trigger: Signal::READABLE,
+                observed: Signal::READABLE | Signal::WRITABLE,
+                count: 3,
+                timestamp: 0,
+            })
+        );
+
+        // a new packet after the last one is taken out
+        object.signal_clear(Signal::READABLE);
+        object.signal_set(Signal::READABLE);
+        assert_eq!(
+            port.try_pop().map(|p| p.type_()),
+            Some(PacketType::SignalRep)
+        );
+
+        // the observer is removed once the port is dropped
+        drop(port);
+        object.signal_clear(Signal::READABLE);
+    }
+}
@@ -0,0 +1,175 @@
+use {super::*, crate::object::Signal, core::convert::TryFrom, numeric_enum_macro::numeric_enum};
+
+numeric_enum! {
+    #[repr(u32)]
+    /// The type of a port packet.
+    #[allow(missing_docs)]
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum PacketType {
+        User = 0,
+        SignalOne = 1,
+        SignalRep = 2,
+        GuestBell = 3,
+        GuestMem = 4,
+        GuestIo = 5,
+        GuestVcpu = 6,
+        Interrupt = 7,
+        PageRequest = 9,
+    }
+}
+
+/// The payload of a packet generated by a signal wait.
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PacketSignal {
+    pub trigger: Signal,
+    pub observed: Signal,
+    pub count: u64,
+    pub timestamp: u64,
+}
+
+/// The payload of a packet generated by an interrupt.
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PacketInterrupt {
+    pub timestamp: i64,
+}
+
+/// The payload of a packet generated by a guest bell trap.
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PacketGuestBell {
+    pub addr: u64,
+}
+
+/// The payload of a packet generated by a guest memory trap.
+///
+/// The access is decoded by the hypervisor, `data` is valid for write.
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PacketGuestMem {
+    pub addr: u64,
+    pub access_size: u8,
+    pub write: bool,
+    pub data: u64,
+}
+
+/// The payload of a packet generated by a guest I/O trap.
+///
+/// `data` is valid for output.
+#[allow(missing_docs)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PacketGuestIo {
+    pub port: u16,
+    pub access_size: u8,
+    pub input: bool,
+    pub data: [u8; 4],
+}
+
+/// The payload of a port packet.
+#[allow(missing_docs)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Payload {
+    User([u8; 32]),
+    SignalOne(PacketSignal),
+    SignalRep(PacketSignal),
+    GuestBell(PacketGuestBell),
+    GuestMem(PacketGuestMem),
+    GuestIo(PacketGuestIo),
+    Interrupt(PacketInterrupt),
+}
+
+/// A packet queued in a port.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct PortPacket {
+    /// The key given by the user when the packet source is bound.
+    pub key: u64,
+    /// The status of the packet.
+    pub status: i32,
+    /// The type specific data.
+    pub data: Payload,
+}
+
+impl PortPacket {
+    /// Get the type of the packet.
+    pub fn type_(&self) -> PacketType {
+        match self.data {
+            Payload::User(_) => PacketType::User,
+            Payload::SignalOne(_) => PacketType::SignalOne,
+            Payload::SignalRep(_) => PacketType::SignalRep,
+            Payload::GuestBell(_) => PacketType::GuestBell,
+            Payload::GuestMem(_) => PacketType::GuestMem,
+            Payload::GuestIo(_) => PacketType::GuestIo,
+            Payload::Interrupt(_) => PacketType::Interrupt,
+        }
+    }
+}
+
+/// The layout of `zx_port_packet_t` in user space.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct PortPacketRepr {
+    /// The key given by the user.
+    pub key: u64,
+    /// The type of the packet.
+    pub type_: u32,
+    /// The status of the packet.
+    pub status: i32,
+    /// The type specific data.
+    pub data: [u8; 32],
+}
+
+impl From<PortPacket> for PortPacketRepr {
+    fn from(packet: PortPacket) -> Self {
+        let mut data = [0u8; 32];
+        match packet.data {
+            Payload::User(user) => data = user,
+            Payload::SignalOne(signal) | Payload::SignalRep(signal) => {
+                data[0..4].copy_from_slice(&signal.trigger.bits().to_ne_bytes());
+                data[4..8].copy_from_slice(&signal.observed.bits().to_ne_bytes());
+                data[8..16].copy_from_slice(&signal.count.to_ne_bytes());
+                data[16..24].copy_from_slice(&signal.timestamp.to_ne_bytes());
+            }
+            Payload::GuestBell(bell) => {
+                data[0..8].copy_from_slice(&bell.addr.to_ne_bytes());
+            }
+            Payload::GuestMem(mem) => {
+                data[0..8].copy_from_slice(&mem.addr.to_ne_bytes());
+                data[8] = mem.access_size;
+                data[9] = mem.write as u8;
+                data[16..24].copy_from_slice(&mem.data.to_ne_bytes());
+            }
+            Payload::GuestIo(io) => {
+                data[0..2].copy_from_slice(&io.port.to_ne_bytes());
+                data[2] = io.access_size;
+                data[3] = io.input as u8;
+                data[4..8].copy_from_slice(&io.data);
+            }
+            Payload::Interrupt(interrupt) => {
+                data[0..8].copy_from_slice(&interrupt.timestamp.to_ne_bytes());
+            }
+        }
+        PortPacketRepr {
+            key: packet.key,
+            type_: packet.type_() as u32,
+            status: packet.status,
+            data,
+        }
+    }
+}
+
+impl TryFrom<PortPacketRepr> for PortPacket {
+    type Error = ZxError;
+
+    /// Only user packets can be converted from user space.
+    fn try_from(repr: PortPacketRepr) -> ZxResult<Self> {
+        if repr.type_ != PacketType::User as u32 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        Ok(PortPacket {
+            key: repr.key,
+            status: repr.status,
+            data: Payload::User(repr.data),
+        })
+    }
+}
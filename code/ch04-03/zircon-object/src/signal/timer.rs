@@ -0,0 +1,218 @@
+use {
+    super::*, crate::object::*, alloc::boxed::Box, alloc::collections::BTreeMap, alloc::sync::Arc,
+    core::time::Duration, lazy_static::lazy_static, spin::Mutex,
+};
+
+/// An object that may be signaled at some point in the future.
+///
+/// ## SYNOPSIS
+///
+/// A timer is used to wait until a specified point in time has occurred
+/// or the timer has been canceled.
+///
+/// The timer may fire anywhere in the window allowed by its slack, so that
+/// it can be coalesced with other timers firing in that window.
+pub struct Timer {
+    base: KObjectBase,
+    slack: Slack,
+    inner: Mutex<TimerInner>,
+}
+
+impl_kobject!(Timer);
+
+#[derive(Default)]
+struct TimerInner {
+    /// The coalesced deadline of the pending timer.
+    deadline: Option<Duration>,
+    /// Bumped on each set and cancel, so that the stale HAL timers are ignored.
+    generation: u64,
+    /// The ID of the HAL timer of the last set.
+    hal_timer: Option<u64>,
+}
+
+/// Slack mode of a timer, which decides where the slack window is.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Slack {
+    /// The timer may fire in `[deadline - slack, deadline + slack]`.
+    Center = 0,
+    /// The timer may fire in `[deadline - slack, deadline]`.
+    Early = 1,
+    /// The timer may fire in `[deadline, deadline + slack]`.
+    Late = 2,
+}
+
+lazy_static! {
+    /// Deadlines of all pending timers, with the number of timers on each.
+    static ref PENDING_DEADLINES: Mutex<BTreeMap<Duration, usize>> = Mutex::new(BTreeMap::new());
+}
+
+impl Timer {
+    /// Create a new timer with `slack` mode.
+    pub fn create(slack: Slack) -> Arc<Self> {
+        Arc::new(Timer {
+            base: KObjectBase::default(),
+            slack,
+            inner: Mutex::new(TimerInner::default()),
+        })
+    }
+
+    /// Start a one-shot timer that will fire when `deadline` passes.
+    ///
+    /// If there is a pending timer in the slack window, the deadline is
+    /// coalesced with it. Setting a pending timer resets it.
+    pub fn set(self: &Arc<Self>, deadline: Duration, slack: Duration) {
+        let mut inner = self.inner.lock();
+        inner.cancel();
+        self.base.signal_clear(Signal::TIMER_SIGNALED);
+        let (earliest, latest) = match self.slack {
+            Slack::Center => (deadline.checked_sub(slack), deadline.checked_add(slack)),
+            Slack::Early => (deadline.checked_sub(slack), Some(deadline)),
+            Slack::Late => (Some(deadline), deadline.checked_add(slack)),
+        };
+        let earliest = earliest.unwrap_or_default();
+        let latest = latest.unwrap_or(Duration::MAX);
+        let deadline = {
+            let mut pending = PENDING_DEADLINES.lock();
+            let deadline = pending
+                .range(earliest..=latest)
+                .next()
+                .map_or(deadline.max(earliest).min(latest), |(&d, _)| d);
+            *pending.entry(deadline).or_default() += 1;
+            deadline
+        };
+        inner.deadline = Some(deadline);
+        let generation = inner.generation;
+        let me = Arc::downgrade(self);
+        let hal_timer = kernel_hal::timer_set(
+            deadline,
+            Box::new(move |now| {
+                if let Some(timer) = me.upgrade() {
+                    timer.touch(generation, now);
+                }
+            }),
+        );
+        inner.hal_timer = Some(hal_timer);
+    }
+
+    /// Cancel the pending timer, and clear `TIMER_SIGNALED` signal.
+    pub fn cancel(&self) {
+        self.inner.lock().cancel();
+        self.base.signal_clear(Signal::TIMER_SIGNALED);
+    }
+
+    /// Get the deadline of the pending timer after coalescing.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.inner.lock().deadline
+    }
+
+    /// Called by HAL timer. Signal the timer if its deadline has passed.
+    ///
+    /// The HAL timer of an earlier `set` is ignored.
+    fn touch(&self, generation: u64, now: Duration) {
+        let mut inner = self.inner.lock();
+        if inner.generation != generation {
+            return;
+        }
+        if let Some(deadline) = inner.deadline {
+            if now >= deadline {
+                inner.deadline = None;
+                remove_pending(deadline);
+                self.base.signal_set(Signal::TIMER_SIGNALED);
+            }
+        }
+    }
+}
+
+impl TimerInner {
+    /// Cancel the pending timer and its HAL timer.
+    fn cancel(&mut self) {
+        if let Some(old) = self.deadline.take() {
+            remove_pending(old);
+        }
+        if let Some(hal_timer) = self.hal_timer.take() {
+            kernel_hal::timer_cancel(hal_timer);
+        }
+        self.generation += 1;
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.inner.lock().cancel();
+    }
+}
+
+/// Remove one timer on `deadline` from pending deadlines.
+fn remove_pending(deadline: Duration) {
+    let mut pending = PENDING_DEADLINES.lock();
+    if let Some(count) = pending.get_mut(&deadline) {
+        *count -= 1;
+        if *count == 0 {
+            pending.remove(&deadline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel_hal::timer_now;
+
+    #[test]
+    fn set() {
+        let _mock = kernel_hal_unix::mock::init();
+        let timer = Timer::create(Slack::Late);
+        timer.set(timer_now() + Duration::from_millis(10), Duration::default());
+        assert!(!timer.signal().contains(Signal::TIMER_SIGNALED));
+        kernel_hal_unix::mock::advance(Duration::from_millis(20));
+        assert!(timer.signal().contains(Signal::TIMER_SIGNALED));
+        assert_eq!(timer.deadline(), None);
+
+        // a reset timer does not fire on the old deadline
+        timer.set(timer_now() + Duration::from_millis(10), Duration::default());
+        assert!(!timer.signal().contains(Signal::TIMER_SIGNALED));
+        timer.set(timer_now() + Duration::from_secs(100), Duration::default());
+        kernel_hal_unix::mock::advance(Duration::from_millis(20));
+        assert!(!timer.signal().contains(Signal::TIMER_SIGNALED));
+
+        timer.cancel();
+        assert_eq!(timer.deadline(), None);
+
+        // a timer set again ignores the HAL timer of the earlier set
+        let deadline = timer_now() + Duration::from_secs(100);
+        timer.set(deadline, Duration::default());
+        let stale = timer.inner.lock().generation;
+        timer.set(deadline, Duration::default());
+        timer.touch(stale, deadline);
+        assert!(!timer.signal().contains(Signal::TIMER_SIGNALED));
+        timer.cancel();
+    }
+
+    #[test]
+    fn coalesce() {
+        kernel_hal_unix::init();
+        let base = timer_now() + Duration::from_secs(1000);
+        let ms = Duration::from_millis;
+        let timer0 = Timer::create(Slack::Center);
+        timer0.set(base, Duration::default());
+        assert_eq!(timer0.deadline(), Some(base));
+
+        // in the slack window
+        let timer1 = Timer::create(Slack::Late);
+        timer1.set(base - ms(5), ms(10));
+        assert_eq!(timer1.deadline(), Some(base));
+        let timer2 = Timer::create(Slack::Center);
+        timer2.set(base + ms(5), ms(10));
+        assert_eq!(timer2.deadline(), Some(base));
+
+        // out of the slack window
+        let timer3 = Timer::create(Slack::Early);
+        timer3.set(base - ms(5), ms(10));
+        assert_eq!(timer3.deadline(), Some(base - ms(5)));
+
+        timer0.cancel();
+        timer1.cancel();
+        timer2.cancel();
+        timer3.cancel();
+    }
+}
@@ -0,0 +1,207 @@
+use {
+    super::*,
+    crate::object::*,
+    alloc::{string::String, sync::Arc, vec::Vec},
+    core::fmt,
+};
+
+/// A snapshot of a job and the tasks under it, made by `dump_task_tree`.
+///
+/// It is displayed as a tree, indented by the depth of the tasks.
+#[derive(Debug, Clone)]
+pub struct JobDump {
+    pub koid: KoID,
+    pub name: String,
+    pub processes: Vec<ProcessDump>,
+    pub children: Vec<JobDump>,
+    /// The number of processes and child jobs skipped by
+    /// `try_dump_task_tree`, as they were locked.
+    pub skipped: usize,
+}
+
+/// A snapshot of a process and its threads.
+#[derive(Debug, Clone)]
+pub struct ProcessDump {
+    pub koid: KoID,
+    pub name: String,
+    pub status: Status,
+    pub handle_count: usize,
+    pub threads: Vec<ThreadDump>,
+    /// The number of threads skipped by `try_dump_task_tree`, as they were
+    /// locked.
+    pub skipped: usize,
+}
+
+/// A snapshot of a thread.
+#[derive(Debug, Clone)]
+pub struct ThreadDump {
+    pub koid: KoID,
+    pub name: String,
+    pub state: ThreadState,
+}
+
+/// Walk the tasks under `root_job`, and take a snapshot of them.
+///
+/// The locks of the tasks are taken one at a time, so the tasks changed
+/// during the walk may be found in either state.
+pub fn dump_task_tree(root_job: &Arc<Job>) -> JobDump {
+    let processes = job_children::<Process>(root_job, root_job.process_ids())
+        .map(|proc| ProcessDump {
+            koid: proc.id(),
+            name: proc.name(),
+            status: proc.status(),
+            handle_count: proc.handle_count(),
+            threads: proc
+                .thread_ids()
+                .into_iter()
+                .filter_map(|id| proc.get_child(id).ok())
+                .map(|thread| {
+                    let thread = thread.downcast_arc::<Thread>().unwrap();
+                    ThreadDump {
+                        koid: thread.id(),
+                        name: thread.name(),
+                        state: thread.state(),
+                    }
+                })
+                .collect(),
+            skipped: 0,
+        })
+        .collect();
+    let children = job_children::<Job>(root_job, root_job.children_ids())
+        .map(|child| dump_task_tree(&child))
+        .collect();
+    JobDump {
+        koid: root_job.id(),
+        name: root_job.name(),
+        processes,
+        children,
+        skipped: 0,
+    }
+}
+
+/// Take a snapshot like `dump_task_tree`, but never wait for a lock, as on
+/// panic, where the locks held by the panicking thread are never released.
+///
+/// The tasks locked are skipped and counted, and `None` is returned if
+/// `root_job` is locked.
+pub fn try_dump_task_tree(root_job: &Arc<Job>) -> Option<JobDump> {
+    root_job.try_dump()
+}
+
+/// Get the children of `job` with `ids`, skipping the ones gone since.
+fn job_children<T: KernelObject>(job: &Job, ids: Vec<KoID>) -> impl Iterator<Item = Arc<T>> + '_ {
+    ids.into_iter()
+        .filter_map(move |id| job.get_child(id).ok())
+        .map(|child| child.downcast_arc::<T>().unwrap())
+}
+
+impl JobDump {
+    fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        writeln!(
+            f,
+            "{:indent$}job {} {:?}",
+            "",
+            self.koid,
+            self.name,
+            indent = indent
+        )?;
+        for proc in self.processes.iter() {
+            writeln!(
+                f,
+                "{:indent$}process {} {:?}: {:?}, {} handles",
+                "",
+                proc.koid,
+                proc.name,
+                proc.status,
+                proc.handle_count,
+                indent = indent + 2
+            )?;
+            for thread in proc.threads.iter() {
+                writeln!(
+                    f,
+                    "{:indent$}thread {} {:?}: {:?}",
+                    "",
+                    thread.koid,
+                    thread.name,
+                    thread.state,
+                    indent = indent + 4
+                )?;
+            }
+            fmt_skipped(f, proc.skipped, "threads", indent + 4)?;
+        }
+        for child in self.children.iter() {
+            child.fmt_indented(f, indent + 2)?;
+        }
+        fmt_skipped(f, self.skipped, "tasks", indent + 2)
+    }
+}
+
+/// Write the number of `what` skipped, if any.
+fn fmt_skipped(
+    f: &mut fmt::Formatter<'_>,
+    skipped: usize,
+    what: &str,
+    indent: usize,
+) -> fmt::Result {
+    if skipped != 0 {
+        writeln!(
+            f,
+            "{:indent$}{} {} locked",
+            "",
+            skipped,
+            what,
+            indent = indent
+        )?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for JobDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn dump() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let job = root_job.create_child().unwrap();
+        let proc = Process::create(&job, "proc").unwrap();
+        let thread = Thread::create(&proc, "thread").unwrap();
+        proc.add_handle(Handle::new(thread.clone(), Rights::DEFAULT_THREAD));
+
+        let dump = dump_task_tree(&root_job);
+        assert_eq!(dump.koid, root_job.id());
+        assert!(dump.processes.is_empty());
+        assert_eq!(dump.children.len(), 1);
+        let child = &dump.children[0];
+        assert_eq!(child.koid, job.id());
+        assert_eq!(child.processes.len(), 1);
+        let proc_dump = &child.processes[0];
+        assert_eq!(proc_dump.name, "proc");
+        assert_eq!(proc_dump.status, Status::Init);
+        assert_eq!(proc_dump.handle_count, 1);
+        assert_eq!(proc_dump.threads.len(), 1);
+        assert_eq!(proc_dump.threads[0].koid, thread.id());
+        assert_eq!(proc_dump.threads[0].state, ThreadState::New);
+
+        let text = format!("{}", dump);
+        assert_eq!(
+            text,
+            format!(
+                "job {} \"\"\n  job {} \"\"\n    process {} \"proc\": Init, 1 handles\n      \
+                 thread {} \"thread\": New\n",
+                root_job.id(),
+                job.id(),
+                proc.id(),
+                thread.id()
+            )
+        );
+    }
+}
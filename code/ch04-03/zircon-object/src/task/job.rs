@@ -7,6 +7,7 @@ use {
     crate::task::Task,
     alloc::sync::{Arc, Weak},
     alloc::vec::Vec,
+    lazy_static::lazy_static,
     spin::Mutex,
 };
 
@@ -45,6 +46,10 @@ struct JobInner {
     self_ref: Weak<Job>,
 }
 
+lazy_static! {
+    static ref SYSTEM_ROOT: Mutex<Weak<Job>> = Mutex::new(Weak::new());
+}
+
 impl Job {
     /// Create the root job.
     pub fn root() -> Arc<Self> {
@@ -95,6 +100,17 @@ impl Job {
         self.parent.clone()
     }
 
+    /// Set this job as the root job of the system, such as the one userboot
+    /// runs in, which is dumped on panic.
+    pub fn set_system_root(self: &Arc<Self>) {
+        *SYSTEM_ROOT.lock() = Arc::downgrade(self);
+    }
+
+    /// Get the root job of the system, if it is set and still alive.
+    pub fn system_root() -> Option<Arc<Self>> {
+        SYSTEM_ROOT.lock().upgrade()
+    }
+
     /// Sets one or more security and/or resource policies to an empty job.
     ///
     /// The job's effective policies is the combination of the parent's
@@ -169,6 +185,27 @@ impl Job {
             .collect()
     }
 
+    /// Take a snapshot of the job and the tasks under it for
+    /// `try_dump_task_tree`, `None` if a lock of it is held. The tasks locked
+    /// are skipped.
+    pub(super) fn try_dump(&self) -> Option<JobDump> {
+        let name = self.base.try_name()?;
+        let (processes, children) = {
+            let inner = self.inner.try_lock()?;
+            let children: Vec<_> = inner.children.iter().filter_map(|j| j.upgrade()).collect();
+            (inner.processes.clone(), children)
+        };
+        let process_dumps: Vec<_> = processes.iter().filter_map(|p| p.try_dump()).collect();
+        let child_dumps: Vec<_> = children.iter().filter_map(|j| j.try_dump()).collect();
+        Some(JobDump {
+            koid: self.base.id,
+            name,
+            skipped: processes.len() + children.len() - process_dumps.len() - child_dumps.len(),
+            processes: process_dumps,
+            children: child_dumps,
+        })
+    }
+
     /// Return true if this job has no processes and no child jobs.
     pub fn is_empty(&self) -> bool {
         self.inner.lock().is_empty()
@@ -236,6 +273,7 @@ mod tests {
 
     #[test]
     fn create() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let job = Job::create_child(&root_job).expect("failed to create job");
 
@@ -254,6 +292,7 @@ mod tests {
 
     #[test]
     fn set_policy() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
 
         // default policy
@@ -336,6 +375,7 @@ mod tests {
 
     #[test]
     fn parent_child() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let job = Job::create_child(&root_job).expect("failed to create job");
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
@@ -359,6 +399,7 @@ mod tests {
 
     #[test]
     fn check() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         assert!(root_job.is_empty());
         let job = root_job.create_child().expect("failed to create job");
@@ -374,6 +415,7 @@ mod tests {
 
     #[test]
     fn kill() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let job = Job::create_child(&root_job).expect("failed to create job");
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
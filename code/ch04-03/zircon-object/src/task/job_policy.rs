@@ -90,6 +90,14 @@ pub enum PolicyCondition {
     AmbientMarkVMOExec = 14,
 }
 
+impl PolicyCondition {
+    /// Whether it is a condition of creating a new object, covered by `NewAny`.
+    pub fn is_new_object(self) -> bool {
+        (PolicyCondition::NewVMO as u32..=PolicyCondition::NewProfile as u32)
+            .contains(&(self as u32))
+    }
+}
+
 /// The action taken when the condition happens specified by a policy.
 #[repr(u32)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
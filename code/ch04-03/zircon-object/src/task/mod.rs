@@ -1,11 +1,16 @@
 use super::*;
 
+mod dump;
 mod job;
 mod job_policy;
 mod process;
+mod profile;
 mod thread;
 
-pub use {self::job::*, self::job_policy::*, self::process::*, self::thread::*};
+pub use {
+    self::dump::*, self::job::*, self::job_policy::*, self::process::*, self::profile::*,
+    self::thread::*,
+};
 
 /// Task (Thread, Process, or Job)
 pub trait Task: Sync + Send {
@@ -22,3 +27,6 @@ pub trait Task: Sync + Send {
 
 /// The return code set when a task is killed via zx_task_kill().
 pub const TASK_RETCODE_SYSCALL_KILL: i64 = -1028;
+
+/// The return code set when a process is killed by an unhandled exception.
+pub const TASK_RETCODE_EXCEPTION_KILL: i64 = -1029;
@@ -1,10 +1,12 @@
 use {
     super::{job::Job, job_policy::*, thread::*, *},
-    crate::{error::*, object::*, vm::*},
-    alloc::{sync::Arc, vec::Vec},
+    crate::{error::*, object::*, signal::Futex, util::random::random_u64, vm::*},
+    alloc::{boxed::Box, sync::Arc, vec::Vec},
     core::{
+        any::Any,
         future::Future,
         pin::Pin,
+        sync::atomic::AtomicI32,
         task::{Context, Poll},
     },
     hashbrown::HashMap,
@@ -16,6 +18,7 @@ pub struct Process {
     job: Arc<Job>,
     policy: JobPolicy,
     vmar: Arc<VmAddressRegion>,
+    ext: Box<dyn Any + Send + Sync>,
     inner: Mutex<ProcessInner>,
 }
 
@@ -33,11 +36,31 @@ impl_kobject!(Process
 #[derive(Default)]
 struct ProcessInner {
     max_handle_id: u32,
+    /// The random secret mixed into handle values, so that they are not
+    /// predictable by other processes.
+    handle_secret: u32,
     status: Status,
     handles: HashMap<HandleValue, Handle>,
+    futexes: HashMap<usize, Arc<Futex>>,
     threads: Vec<Arc<Thread>>,
+    /// I/O ports accessible by the process, one bit for each port.
+    ioport_bitmap: Vec<u64>,
+    /// Whether syscalls of the process are traced.
+    syscall_trace: bool,
+    /// The vDSO variant mapped as executable by the process.
+    vdso_variant: Option<VdsoVariant>,
 }
 
+/// The return code of a process killed by the job policy.
+pub const TASK_RETCODE_POLICY_KILL: i64 = -1026;
+
+/// The bits of the handle secret, leaving the lowest 2 bits of handle values
+/// always set.
+const HANDLE_SECRET_MASK: u32 = 0x3fff_ffff;
+
+/// The number of I/O ports.
+const IOPORT_COUNT: usize = 0x10000;
+
 /// Status of a process.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Status {
@@ -58,17 +81,138 @@ impl Default for Status {
 impl Process {
     /// Create a new process in the `job`.
     pub fn create(job: &Arc<Job>, name: &str) -> ZxResult<Arc<Self>> {
+        Self::create_with_ext(job, name, ())
+    }
+
+    /// Create a new process in the `job`, with an extension `ext`.
+    ///
+    /// The extension keeps the states of an upper layer, such as a Linux
+    /// process built on the process.
+    pub fn create_with_ext(
+        job: &Arc<Job>,
+        name: &str,
+        ext: impl Any + Send + Sync,
+    ) -> ZxResult<Arc<Self>> {
+        Self::create_with_vmar(job, name, VmAddressRegion::new_root(), ext)
+    }
+
+    /// Create a new process in the same job, with a copy of the address space
+    /// and an extension `ext`.
+    ///
+    /// The mapped VMOs are copied as snapshots, see [`VmAddressRegion::fork`].
+    /// Handles and threads are not copied.
+    ///
+    /// [`VmAddressRegion::fork`]: crate::vm::VmAddressRegion::fork
+    pub fn fork_with_ext(self: &Arc<Self>, ext: impl Any + Send + Sync) -> ZxResult<Arc<Self>> {
+        let vmar = self.vmar.fork()?;
+        Self::create_with_vmar(&self.job, &self.name(), vmar, ext)
+    }
+
+    fn create_with_vmar(
+        job: &Arc<Job>,
+        name: &str,
+        vmar: Arc<VmAddressRegion>,
+        ext: impl Any + Send + Sync,
+    ) -> ZxResult<Arc<Self>> {
         let proc = Arc::new(Process {
             base: KObjectBase::with_name(name),
             job: job.clone(),
             policy: job.policy(),
-            vmar: VmAddressRegion::new_root(),
-            inner: Mutex::new(ProcessInner::default()),
+            vmar,
+            ext: Box::new(ext),
+            inner: Mutex::new(ProcessInner {
+                handle_secret: random_u64() as u32 & HANDLE_SECRET_MASK,
+                ..Default::default()
+            }),
         });
         job.add_process(proc.clone())?;
         Ok(proc)
     }
 
+    /// Check whether syscalls of the process are traced.
+    pub fn syscall_trace(&self) -> bool {
+        self.inner.lock().syscall_trace
+    }
+
+    /// Enable or disable tracing syscalls of the process.
+    pub fn set_syscall_trace(&self, enable: bool) {
+        self.inner.lock().syscall_trace = enable;
+    }
+
+    /// Get the vDSO variant mapped by the process, which decides the
+    /// syscalls permitted.
+    pub fn vdso_variant(&self) -> Option<VdsoVariant> {
+        self.inner.lock().vdso_variant
+    }
+
+    /// Record the vDSO variant mapped as executable by the process.
+    ///
+    /// A process can only map one variant of the vDSO.
+    pub fn set_vdso_variant(&self, variant: VdsoVariant) -> ZxResult {
+        let mut inner = self.inner.lock();
+        match inner.vdso_variant {
+            Some(mapped) if mapped != variant => Err(ZxError::ACCESS_DENIED),
+            _ => {
+                inner.vdso_variant = Some(variant);
+                Ok(())
+            }
+        }
+    }
+
+    /// Get a futex from the process, or create it if not exist.
+    ///
+    /// Futexes are keyed by the user virtual address of their values.
+    /// The futexes whose wait queues have emptied are removed here, unless
+    /// they are still owned or referenced.
+    pub fn get_futex(&self, value: &'static AtomicI32) -> Arc<Futex> {
+        let mut inner = self.inner.lock();
+        inner
+            .futexes
+            .retain(|_, futex| Arc::strong_count(futex) > 1 || futex.owner().is_some());
+        inner
+            .futexes
+            .entry(value as *const AtomicI32 as usize)
+            .or_insert_with(|| Futex::new(value))
+            .clone()
+    }
+
+    /// Grant or revoke the access to I/O ports `[port, port + len)`.
+    pub fn set_ioport_access(&self, port: u16, len: u32, enable: bool) -> ZxResult {
+        let end = port as usize + len as usize;
+        if len == 0 || end > IOPORT_COUNT {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut inner = self.inner.lock();
+        kernel_hal::ioport_set_access(port, len, enable).map_err(|_| ZxError::NOT_SUPPORTED)?;
+        if inner.ioport_bitmap.is_empty() {
+            inner.ioport_bitmap.resize(IOPORT_COUNT / 64, 0);
+        }
+        for port in port as usize..end {
+            if enable {
+                inner.ioport_bitmap[port / 64] |= 1 << (port % 64);
+            } else {
+                inner.ioport_bitmap[port / 64] &= !(1 << (port % 64));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the I/O permission bitmap of the process on the current CPU, for
+    /// its thread to run next.
+    pub fn load_ioport_bitmap(&self) {
+        kernel_hal::ioport_load_bitmap(&self.inner.lock().ioport_bitmap);
+    }
+
+    /// Whether the process can access I/O port `port`.
+    pub fn ioport_accessible(&self, port: u16) -> bool {
+        let inner = self.inner.lock();
+        let port = port as usize;
+        inner
+            .ioport_bitmap
+            .get(port / 64)
+            .map_or(false, |bits| bits & (1 << (port % 64)) != 0)
+    }
+
     /// Get a handle from the process
     fn get_handle(&self, handle_value: HandleValue) -> ZxResult<Handle> {
         self.inner.lock().get_handle(handle_value)
@@ -85,7 +229,7 @@ impl Process {
     }
 
     /// Add all handles to the process
-    pub fn add_handles(&self, handles: Vec<Handle>) -> Vec<HandleValue> {
+    pub fn add_handles(&self, handles: impl IntoIterator<Item = Handle>) -> Vec<HandleValue> {
         let mut inner = self.inner.lock();
         handles.into_iter().map(|h| inner.add_handle(h)).collect()
     }
@@ -99,6 +243,11 @@ impl Process {
             .collect()
     }
 
+    /// Get the number of handles in the process.
+    pub fn handle_count(&self) -> usize {
+        self.inner.lock().handles.len()
+    }
+
     /// Get the kernel object corresponding to this `handle_value`
     pub fn get_object<T: KernelObject>(&self, handle_value: HandleValue) -> ZxResult<Arc<T>> {
         let handle = self.get_handle(handle_value)?;
@@ -127,6 +276,28 @@ impl Process {
         Ok(object)
     }
 
+    /// 根据句柄值查找任意类型的内核对象，并检查权限
+    pub fn get_dyn_object_with_rights(
+        &self,
+        handle_value: HandleValue,
+        desired_rights: Rights,
+    ) -> ZxResult<Arc<dyn KernelObject>> {
+        let handle = self.get_handle(handle_value)?;
+        if !handle.rights.contains(desired_rights) {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        Ok(handle.object)
+    }
+
+    /// 根据句柄值查找任意类型的内核对象，并返回句柄的权限
+    pub fn get_dyn_object_and_rights(
+        &self,
+        handle_value: HandleValue,
+    ) -> ZxResult<(Arc<dyn KernelObject>, Rights)> {
+        let handle = self.get_handle(handle_value)?;
+        Ok((handle.object, handle.rights))
+    }
+
     /// Get the kernel object corresponding to this `handle_value` and this handle's rights.
     pub fn get_object_and_rights<T: KernelObject>(
         &self,
@@ -205,7 +376,7 @@ impl Process {
         inner.handles.clear();
     }
 
-    /// The process finally terminates.
+    /// The process finally terminates, with `PROCESS_TERMINATED` signaled.
     fn terminate(&self) {
         let mut inner = self.inner.lock();
         let _retcode = match inner.status {
@@ -215,19 +386,44 @@ impl Process {
                 0
             }
         };
+        drop(inner);
         self.job.remove_process(self.base.id);
+        self.base.signal_set(Signal::PROCESS_TERMINATED);
     }
 
     /// Check whether `condition` is allowed in the parent job's policy.
+    ///
+    /// The conditions of creating objects not specified fall back to `NewAny`.
+    /// There is no exception handler, so the policy exceptions are only logged.
+    /// The process is killed by the `Kill` action.
     pub fn check_policy(&self, condition: PolicyCondition) -> ZxResult {
-        match self
+        let action = self
             .policy
             .get_action(condition)
-            .unwrap_or(PolicyAction::Allow)
-        {
+            .or_else(|| {
+                if condition.is_new_object() {
+                    self.policy.get_action(PolicyCondition::NewAny)
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(PolicyAction::Allow);
+        match action {
             PolicyAction::Allow => Ok(()),
             PolicyAction::Deny => Err(ZxError::ACCESS_DENIED),
-            _ => unimplemented!(),
+            PolicyAction::AllowException => {
+                warn!("{}: policy exception of {:?}", self.name(), condition);
+                Ok(())
+            }
+            PolicyAction::DenyException => {
+                warn!("{}: policy exception of {:?}", self.name(), condition);
+                Err(ZxError::ACCESS_DENIED)
+            }
+            PolicyAction::Kill => {
+                warn!("{}: killed by policy of {:?}", self.name(), condition);
+                self.exit(TASK_RETCODE_POLICY_KILL);
+                Err(ZxError::ACCESS_DENIED)
+            }
         }
     }
 
@@ -241,6 +437,11 @@ impl Process {
         self.vmar.clone()
     }
 
+    /// Get the extension of the process.
+    pub fn ext(&self) -> &(dyn Any + Send + Sync) {
+        &*self.ext
+    }
+
     /// Get the job of the process.
     pub fn job(&self) -> Arc<Job> {
         self.job.clone()
@@ -273,6 +474,22 @@ impl Process {
         self.inner.lock().threads.iter().map(|t| t.id()).collect()
     }
 
+    /// Take a snapshot of the process for `try_dump_task_tree`, `None` if a
+    /// lock of it is held. The threads locked are skipped.
+    pub(super) fn try_dump(&self) -> Option<ProcessDump> {
+        let name = self.base.try_name()?;
+        let inner = self.inner.try_lock()?;
+        let threads: Vec<_> = inner.threads.iter().filter_map(|t| t.try_dump()).collect();
+        Some(ProcessDump {
+            koid: self.base.id,
+            name,
+            status: inner.status,
+            handle_count: inner.handles.len(),
+            skipped: inner.threads.len() - threads.len(),
+            threads,
+        })
+    }
+
     /// Get information of this process.
     pub fn get_info(&self) -> ProcessInfo {
         let mut info = ProcessInfo {
@@ -354,7 +571,7 @@ impl Task for Process {
 impl ProcessInner {
     /// Add a handle to the process
     fn add_handle(&mut self, handle: Handle) -> HandleValue {
-        let key = (self.max_handle_id << 2) | 0x3u32;
+        let key = ((self.max_handle_id ^ self.handle_secret) << 2) | 0x3u32;
         self.max_handle_id += 1;
         self.handles.insert(key, handle);
         key
@@ -385,6 +602,7 @@ mod tests {
 
     #[test]
     fn create() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
 
@@ -392,8 +610,29 @@ mod tests {
         assert!(Arc::ptr_eq(&root_job, &proc.job()));
     }
 
+    #[test]
+    fn ioport_access() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        assert!(!proc.ioport_accessible(0x3f8));
+        assert_eq!(
+            proc.set_ioport_access(0xfff8, 16, true),
+            Err(ZxError::INVALID_ARGS)
+        );
+
+        proc.set_ioport_access(0x3f8, 8, true).unwrap();
+        assert!(proc.ioport_accessible(0x3f8));
+        assert!(proc.ioport_accessible(0x3ff));
+        assert!(!proc.ioport_accessible(0x400));
+        proc.set_ioport_access(0x3fc, 4, false).unwrap();
+        assert!(proc.ioport_accessible(0x3fb));
+        assert!(!proc.ioport_accessible(0x3fc));
+    }
+
     #[test]
     fn handle() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let handle = Handle::new(proc.clone(), Rights::DEFAULT_PROCESS);
@@ -454,6 +693,7 @@ mod tests {
 
     #[test]
     fn get_child() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -467,6 +707,7 @@ mod tests {
 
     #[test]
     fn contains_thread() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -480,6 +721,7 @@ mod tests {
 
     #[test]
     fn check_policy() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let policy1 = BasicPolicy {
             condition: PolicyCondition::BadHandle,
@@ -502,17 +744,45 @@ mod tests {
             Some(ZxError::ACCESS_DENIED)
         );
 
-        let _job = root_job.create_child().unwrap();
+        let job = root_job.create_child().unwrap();
         assert_eq!(
             root_job
                 .set_policy_basic(SetPolicyOptions::Absolute, &[policy1, policy2])
                 .err(),
             Some(ZxError::BAD_STATE)
         );
+
+        // a child job inherits the policy of its parent
+        let policy3 = BasicPolicy {
+            condition: PolicyCondition::NewAny,
+            action: PolicyAction::Deny,
+        };
+        let policy4 = BasicPolicy {
+            condition: PolicyCondition::WrongObject,
+            action: PolicyAction::Kill,
+        };
+        job.set_policy_basic(SetPolicyOptions::Relative, &[policy3, policy4])
+            .unwrap();
+        let proc = Process::create(&job, "proc").expect("failed to create process");
+        assert_eq!(
+            proc.check_policy(PolicyCondition::NewTimer).err(),
+            Some(ZxError::ACCESS_DENIED)
+        );
+        assert_eq!(
+            proc.check_policy(PolicyCondition::NewChannel).err(),
+            Some(ZxError::ACCESS_DENIED)
+        );
+        assert!(proc.check_policy(PolicyCondition::BadHandle).is_ok());
+        assert_eq!(
+            proc.check_policy(PolicyCondition::WrongObject).err(),
+            Some(ZxError::ACCESS_DENIED)
+        );
+        assert_eq!(proc.status(), Status::Exited(TASK_RETCODE_POLICY_KILL));
     }
 
     #[test]
     fn exit() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -530,5 +800,65 @@ mod tests {
             Thread::create(&proc, "thread1").err(),
             Some(ZxError::BAD_STATE)
         );
+
+        // terminated after all threads exit
+        assert!(!proc.signal().contains(Signal::PROCESS_TERMINATED));
+        drop(CurrentThread(thread));
+        assert!(proc.signal().contains(Signal::PROCESS_TERMINATED));
+    }
+
+    #[test]
+    fn vdso_variant() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        assert_eq!(proc.vdso_variant(), None);
+
+        // mapping the same variant again is fine
+        assert!(proc.set_vdso_variant(VdsoVariant::Test1).is_ok());
+        assert!(proc.set_vdso_variant(VdsoVariant::Test1).is_ok());
+        assert_eq!(
+            proc.set_vdso_variant(VdsoVariant::Full),
+            Err(ZxError::ACCESS_DENIED)
+        );
+        assert_eq!(proc.vdso_variant(), Some(VdsoVariant::Test1));
+    }
+
+    #[test]
+    fn futex_table() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        let value0: &'static AtomicI32 = Box::leak(Box::new(AtomicI32::new(0)));
+        let value1: &'static AtomicI32 = Box::leak(Box::new(AtomicI32::new(0)));
+
+        let futex0 = proc.get_futex(value0);
+        assert!(Arc::ptr_eq(&futex0, &proc.get_futex(value0)));
+        proc.get_futex(value1);
+        assert_eq!(proc.inner.lock().futexes.len(), 2);
+
+        // the idle futex is removed, and the referenced one is kept
+        proc.get_futex(value0);
+        assert_eq!(proc.inner.lock().futexes.len(), 1);
+        drop(futex0);
+        proc.get_futex(value1);
+        assert_eq!(proc.inner.lock().futexes.len(), 1);
+    }
+
+    #[test]
+    fn try_dump_locked() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        Thread::create(&proc, "thread").expect("failed to create thread");
+
+        let dump = try_dump_task_tree(&root_job).unwrap();
+        assert_eq!((dump.processes.len(), dump.skipped), (1, 0));
+        assert_eq!(dump.processes[0].threads.len(), 1);
+        let guard = proc.inner.lock();
+        let dump = try_dump_task_tree(&root_job).unwrap();
+        assert_eq!((dump.processes.len(), dump.skipped), (0, 1));
+        assert!(alloc::format!("{}", dump).ends_with("  1 tasks locked\n"));
+        drop(guard);
     }
 }
@@ -0,0 +1,120 @@
+use {super::*, crate::object::*, alloc::sync::Arc};
+
+/// Scheduling profile.
+///
+/// ## SYNOPSIS
+///
+/// A profile holds the scheduler parameters, which are either a priority or
+/// deadline parameters, and the CPU affinity mask, to be applied to threads.
+///
+/// Profiles can only be created from the root job.
+pub struct Profile {
+    base: KObjectBase,
+    info: ProfileInfo,
+}
+
+impl_kobject!(Profile);
+
+/// The parameters of a profile, `None` for those not specified.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ProfileInfo {
+    /// The scheduler parameters.
+    pub params: Option<SchedulerParams>,
+    /// The CPU affinity mask.
+    pub cpu_affinity_mask: Option<CpuMask>,
+}
+
+/// Scheduler parameters of a profile.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SchedulerParams {
+    /// Fair scheduling with the priority.
+    Priority(i32),
+    /// Deadline scheduling.
+    Deadline(SchedDeadlineParams),
+}
+
+/// Parameters of deadline scheduling, all in nanoseconds.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct SchedDeadlineParams {
+    /// The worst case execution time in each period.
+    pub capacity: i64,
+    /// The deadline relative to the start of each period.
+    pub relative_deadline: i64,
+    /// The period of the task.
+    pub period: i64,
+}
+
+/// The maximum number of CPUs in a CPU mask.
+pub const CPU_SET_MAX_CPUS: usize = 512;
+
+/// A mask of CPUs, one bit for each CPU.
+pub type CpuMask = [u64; CPU_SET_MAX_CPUS / 64];
+
+/// The lowest priority.
+pub const PRIORITY_LOWEST: i32 = 0;
+
+/// The highest priority.
+pub const PRIORITY_HIGHEST: i32 = 31;
+
+impl Profile {
+    /// Create a new profile with `info`.
+    pub fn create(info: ProfileInfo) -> ZxResult<Arc<Self>> {
+        match info.params {
+            Some(SchedulerParams::Priority(priority)) => {
+                if !(PRIORITY_LOWEST..=PRIORITY_HIGHEST).contains(&priority) {
+                    return Err(ZxError::INVALID_ARGS);
+                }
+            }
+            Some(SchedulerParams::Deadline(params)) => {
+                if params.capacity <= 0
+                    || params.capacity > params.relative_deadline
+                    || params.relative_deadline > params.period
+                {
+                    return Err(ZxError::INVALID_ARGS);
+                }
+            }
+            None if info.cpu_affinity_mask.is_none() => return Err(ZxError::INVALID_ARGS),
+            None => {}
+        }
+        Ok(Arc::new(Profile {
+            base: KObjectBase::new(),
+            info,
+        }))
+    }
+
+    /// Get the parameters of the profile.
+    pub fn info(&self) -> ProfileInfo {
+        self.info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create() {
+        kernel_hal_unix::init();
+        assert!(Profile::create(ProfileInfo::default()).is_err());
+        let priority = |p| ProfileInfo {
+            params: Some(SchedulerParams::Priority(p)),
+            cpu_affinity_mask: None,
+        };
+        assert!(Profile::create(priority(PRIORITY_HIGHEST + 1)).is_err());
+        let profile = Profile::create(priority(PRIORITY_HIGHEST)).unwrap();
+        assert_eq!(profile.info(), priority(PRIORITY_HIGHEST));
+
+        let deadline = |capacity, relative_deadline, period| ProfileInfo {
+            params: Some(SchedulerParams::Deadline(SchedDeadlineParams {
+                capacity,
+                relative_deadline,
+                period,
+            })),
+            cpu_affinity_mask: Some([1; CPU_SET_MAX_CPUS / 64]),
+        };
+        assert!(Profile::create(deadline(0, 10, 10)).is_err());
+        assert!(Profile::create(deadline(5, 20, 10)).is_err());
+        assert!(Profile::create(deadline(5, 10, 20)).is_ok());
+    }
+}
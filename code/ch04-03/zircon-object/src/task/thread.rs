@@ -2,16 +2,19 @@ use {
     super::process::Process,
     super::*,
     crate::object::*,
-    alloc::{boxed::Box, sync::Arc},
+    alloc::{boxed::Box, collections::BTreeMap, sync::Arc},
     bitflags::bitflags,
     core::{
+        any::Any,
         future::Future,
         ops::Deref,
         pin::Pin,
         task::{Context, Poll, Waker},
+        time::Duration,
     },
+    futures::{channel::oneshot, future::FutureExt, select_biased},
+    kernel_hal::{CpuAffinity, GeneralRegs, UserContext, UserContextExt, CPU_AFFINITY_ALL},
     spin::Mutex,
-    trapframe::UserContext,
 };
 
 pub use self::thread_state::*;
@@ -21,6 +24,7 @@ mod thread_state;
 pub struct Thread {
     base: KObjectBase,
     proc: Arc<Process>,
+    ext: Box<dyn Any + Send + Sync>,
     inner: Mutex<ThreadInner>,
 }
 
@@ -40,6 +44,8 @@ struct ThreadInner {
     suspend_count: usize,
     /// The waker of task when suspending.
     waker: Option<Waker>,
+    /// A token used to interrupt blocking thread, with the status returned
+    killer: Option<oneshot::Sender<ZxError>>,
     /// Thread state
     ///
     /// NOTE: This variable will never be `Suspended`. On suspended, the
@@ -51,7 +57,19 @@ struct ThreadInner {
     killed: bool,
     /// The time this thread has run on cpu
     time: u128,
+    /// The CPU this thread ran on last time
+    last_cpu: u32,
     flags: ThreadFlag,
+    /// The priority set by profile
+    base_priority: i32,
+    /// Priorities inherited from the waiters of futexes owned by this thread,
+    /// keyed by the KoID of futex
+    inherited_priority: BTreeMap<KoID, i32>,
+    /// The CPUs the thread runs on, set by profile
+    affinity: Option<CpuAffinity>,
+    /// Whether the affinity is changed after the thread starts, to be applied
+    /// by the thread itself
+    affinity_changed: bool,
 }
 
 impl ThreadInner {
@@ -84,17 +102,34 @@ bitflags! {
     }
 }
 
+/// The default priority of threads.
+pub const DEFAULT_PRIORITY: i32 = 16;
+
 /// The type of a new thread function.
 pub type ThreadFn = fn(thread: CurrentThread) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
 
 impl Thread {
     /// Create a new thread.
     pub fn create(proc: &Arc<Process>, name: &str) -> ZxResult<Arc<Self>> {
+        Self::create_with_ext(proc, name, ())
+    }
+
+    /// Create a new thread in the process `proc`, with an extension `ext`.
+    ///
+    /// The extension keeps the states of an upper layer, such as a Linux
+    /// thread built on the thread.
+    pub fn create_with_ext(
+        proc: &Arc<Process>,
+        name: &str,
+        ext: impl Any + Send + Sync,
+    ) -> ZxResult<Arc<Self>> {
         let thread = Arc::new(Thread {
             base: KObjectBase::with_name(name),
             proc: proc.clone(),
+            ext: Box::new(ext),
             inner: Mutex::new(ThreadInner {
                 context: Some(Box::new(UserContext::default())),
+                base_priority: DEFAULT_PRIORITY,
                 ..Default::default()
             }),
         });
@@ -107,6 +142,11 @@ impl Thread {
         &self.proc
     }
 
+    /// Get the extension of the thread.
+    pub fn ext(&self) -> &(dyn Any + Send + Sync) {
+        &*self.ext
+    }
+
     /// Start execution on the thread.
     pub fn start(
         self: &Arc<Self>,
@@ -119,17 +159,40 @@ impl Thread {
         {
             let mut inner = self.inner.lock();
             let context = inner.context.as_mut().ok_or(ZxError::BAD_STATE)?;
-            context.general.rip = entry;
-            context.general.rsp = stack;
-            context.general.rdi = arg1;
-            context.general.rsi = arg2;
-            context.general.rflags |= 0x3202;
+            context.setup(entry, stack, arg1, arg2);
             inner.change_state(ThreadState::Running);
         }
-        kernel_hal::Thread::spawn(thread_fn(CurrentThread(self.clone())), 0);
+        self.spawn(thread_fn);
         Ok(())
     }
 
+    /// Start execution on the thread with the general registers `regs`.
+    ///
+    /// It is used to start a copy of a thread, which continues from where the
+    /// copied one is.
+    pub fn start_with_regs(self: &Arc<Self>, regs: GeneralRegs, thread_fn: ThreadFn) -> ZxResult {
+        {
+            let mut inner = self.inner.lock();
+            let context = inner.context.as_mut().ok_or(ZxError::BAD_STATE)?;
+            context.general = regs;
+            context.enable_interrupts();
+            inner.change_state(ThreadState::Running);
+        }
+        self.spawn(thread_fn);
+        Ok(())
+    }
+
+    /// Spawn the HAL thread running `thread_fn`, on the CPUs of its affinity.
+    fn spawn(self: &Arc<Self>, thread_fn: ThreadFn) {
+        let affinity = {
+            let mut inner = self.inner.lock();
+            inner.affinity_changed = false;
+            inner.affinity.unwrap_or(CPU_AFFINITY_ALL)
+        };
+        let vmtoken = self.proc().vmar().table_phys();
+        kernel_hal::Thread::spawn(thread_fn(CurrentThread(self.clone())), vmtoken, affinity);
+    }
+
     /// Stop the thread. Internal implementation of `exit` and `kill`.
     ///
     /// The thread do not terminate immediately when stopped. It is just made dying.
@@ -149,6 +212,10 @@ impl Thread {
         if let Some(waker) = inner.waker.take() {
             waker.wake();
         }
+        // Cancel blocking
+        if let Some(killer) = inner.killer.take() {
+            killer.send(ZxError::STOP).ok();
+        }
     }
 
     /// Read one aspect of thread state.
@@ -185,6 +252,17 @@ impl Thread {
         self.inner.lock().state()
     }
 
+    /// Take a snapshot of the thread for `try_dump_task_tree`, `None` if a
+    /// lock of it is held.
+    pub(super) fn try_dump(&self) -> Option<ThreadDump> {
+        let state = self.inner.try_lock()?.state();
+        Some(ThreadDump {
+            koid: self.base.id,
+            name: self.base.try_name()?,
+            state,
+        })
+    }
+
     /// Add the parameter to the time this thread has run on cpu.
     pub fn time_add(&self, time: u128) {
         self.inner.lock().time += time;
@@ -195,6 +273,20 @@ impl Thread {
         self.inner.lock().time as u64
     }
 
+    /// Record the CPU this thread is going to run on.
+    pub fn set_last_cpu(&self, cpu: u32) {
+        self.inner.lock().last_cpu = cpu;
+    }
+
+    /// Get the thread's runtime statistics.
+    pub fn get_stats(&self) -> ThreadStats {
+        let inner = self.inner.lock();
+        ThreadStats {
+            total_runtime: inner.time as u64,
+            last_scheduled_cpu: inner.last_cpu,
+        }
+    }
+
     /// Set this thread as the first thread of a process.
     pub(super) fn set_first_thread(&self) {
         self.inner.lock().first_thread = true;
@@ -205,6 +297,50 @@ impl Thread {
         self.inner.lock().first_thread
     }
 
+    /// Get the effective priority of the thread.
+    ///
+    /// It is the higher one of the base priority and inherited priorities.
+    pub fn priority(&self) -> i32 {
+        let inner = self.inner.lock();
+        let inherited = inner.inherited_priority.values().copied().max();
+        inherited.map_or(inner.base_priority, |p| p.max(inner.base_priority))
+    }
+
+    /// Set the base priority of the thread.
+    pub fn set_base_priority(&self, priority: i32) {
+        self.inner.lock().base_priority = priority;
+    }
+
+    /// Apply the priority and the CPU affinity mask of `profile`.
+    ///
+    /// The deadline parameters are not supported, the priority is kept. Only
+    /// the first 64 CPUs of the mask are known by the HAL.
+    pub fn set_profile(&self, profile: &Profile) {
+        let info = profile.info();
+        let mut inner = self.inner.lock();
+        if let Some(SchedulerParams::Priority(priority)) = info.params {
+            inner.base_priority = priority;
+        }
+        if let Some(mask) = info.cpu_affinity_mask {
+            inner.affinity = Some(mask[0]);
+            inner.affinity_changed = true;
+        }
+    }
+
+    /// Get the CPUs the thread runs on, `None` for all of them.
+    pub fn affinity(&self) -> Option<CpuAffinity> {
+        self.inner.lock().affinity
+    }
+
+    /// Set or clear the priority inherited from `source`.
+    pub(crate) fn set_inherited_priority(&self, source: KoID, priority: Option<i32>) {
+        let mut inner = self.inner.lock();
+        match priority {
+            Some(priority) => inner.inherited_priority.insert(source, priority),
+            None => inner.inherited_priority.remove(&source),
+        };
+    }
+
     /// Get the thread's flags.
     pub fn flags(&self) -> ThreadFlag {
         self.inner.lock().flags
@@ -216,6 +352,7 @@ impl Thread {
     }
 
     /// Set the thread local fsbase register on x86_64.
+    #[cfg(target_arch = "x86_64")]
     pub fn set_fsbase(&self, fsbase: usize) -> ZxResult {
         let mut inner = self.inner.lock();
         let context = inner.context.as_mut().ok_or(ZxError::BAD_STATE)?;
@@ -224,12 +361,26 @@ impl Thread {
     }
 
     /// Set the thread local gsbase register on x86_64.
+    #[cfg(target_arch = "x86_64")]
     pub fn set_gsbase(&self, gsbase: usize) -> ZxResult {
         let mut inner = self.inner.lock();
         let context = inner.context.as_mut().ok_or(ZxError::BAD_STATE)?;
         context.general.gsbase = gsbase;
         Ok(())
     }
+
+    /// Interrupt the blocking of the thread, which returns
+    /// `INTERNAL_INTR_RETRY`. Return whether the thread was blocking.
+    ///
+    /// It is used to interrupt blocking syscalls on asynchronous events, such
+    /// as Linux signals.
+    pub fn interrupt(&self) -> bool {
+        let mut inner = self.inner.lock();
+        match inner.killer.take() {
+            Some(killer) => killer.send(ZxError::INTERNAL_INTR_RETRY).is_ok(),
+            None => false,
+        }
+    }
 }
 
 impl Task for Thread {
@@ -240,8 +391,12 @@ impl Task for Thread {
     fn suspend(&self) {
         let mut inner = self.inner.lock();
         inner.suspend_count += 1;
-        // let state = inner.state;
-        // inner.change_state(state);
+        // interrupt sleeping, which will be retried after resumed
+        if inner.state == ThreadState::BlockedSleeping {
+            if let Some(killer) = inner.killer.take() {
+                killer.send(ZxError::INTERNAL_INTR_RETRY).ok();
+            }
+        }
     }
 
     fn resume(&self) {
@@ -307,6 +462,11 @@ impl CurrentThread {
 
             fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
                 let mut inner = self.thread.inner.lock();
+                if inner.affinity_changed {
+                    // polled by the HAL thread of this thread
+                    inner.affinity_changed = false;
+                    kernel_hal::Thread::set_affinity(inner.affinity.unwrap_or(CPU_AFFINITY_ALL));
+                }
                 if inner.state() != ThreadState::Suspended {
                     // resume:  return the context token from thread object
                     // There is no need to call change_state here
@@ -324,6 +484,35 @@ impl CurrentThread {
         }
     }
 
+    /// Wait until the thread is resumed, after a blocking syscall is
+    /// interrupted by suspension.
+    ///
+    /// Return `STOP` if the thread is killed.
+    pub fn wait_for_resume(&self) -> impl Future<Output = ZxResult> {
+        #[must_use = "wait_for_resume does nothing unless polled/`await`-ed"]
+        struct ResumeChecker {
+            thread: Arc<Thread>,
+        }
+        impl Future for ResumeChecker {
+            type Output = ZxResult;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let mut inner = self.thread.inner.lock();
+                if inner.state == ThreadState::Dying {
+                    Poll::Ready(Err(ZxError::STOP))
+                } else if inner.suspend_count == 0 {
+                    Poll::Ready(Ok(()))
+                } else {
+                    inner.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+        ResumeChecker {
+            thread: self.0.clone(),
+        }
+    }
+
     /// The thread ends running and takes back the context.
     pub fn end_running(&self, context: Box<UserContext>) {
         let mut inner = self.inner.lock();
@@ -332,6 +521,45 @@ impl CurrentThread {
         // inner.change_state(state);
     }
 
+    /// Run async `future` and change state to `state` while blocking.
+    ///
+    /// Return `TIMED_OUT` if the `deadline` passes, or `STOP` if the thread
+    /// is killed before the future completes. Blocking in `BlockedSleeping`
+    /// state is also interrupted by suspension, with `INTERNAL_INTR_RETRY`.
+    pub async fn blocking_run<F, T>(
+        &self,
+        future: F,
+        state: ThreadState,
+        deadline: Duration,
+    ) -> ZxResult<T>
+    where
+        F: Future<Output = ZxResult<T>> + Unpin,
+    {
+        let (old_state, killed) = {
+            let mut inner = self.inner.lock();
+            if inner.state == ThreadState::Dying {
+                return Err(ZxError::STOP);
+            }
+            let (sender, receiver) = oneshot::channel();
+            inner.killer = Some(sender);
+            let old_state = inner.state;
+            inner.change_state(state);
+            (old_state, receiver)
+        };
+        let ret = select_biased! {
+            ret = future.fuse() => ret,
+            err = killed.fuse() => Err(err.unwrap_or(ZxError::STOP)),
+            _ = kernel_hal::sleep_until(deadline).fuse() => Err(ZxError::TIMED_OUT),
+        };
+        let mut inner = self.inner.lock();
+        inner.killer = None;
+        if inner.state == ThreadState::Dying {
+            return ret;
+        }
+        inner.change_state(old_state);
+        ret
+    }
+
     /// Access saved context of current thread.
     ///
     /// Will panic if the context is not availiable.
@@ -392,6 +620,13 @@ pub struct ThreadInfo {
     state: u32,
 }
 
+/// The runtime statistics of a thread.
+#[repr(C)]
+pub struct ThreadStats {
+    total_runtime: u64,
+    last_scheduled_cpu: u32,
+}
+
 #[cfg(test)]
 mod tests {
     use super::job::Job;
@@ -399,9 +634,21 @@ mod tests {
     use core::time::Duration;
     use kernel_hal::timer_now;
     use kernel_hal::GeneralRegs;
+    use kernel_hal_unix::mock;
+
+    /// Spawn `future` as a thread of the mock HAL.
+    fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+        kernel_hal::Thread::spawn(Box::pin(future), 0, CPU_AFFINITY_ALL);
+    }
+
+    /// Sleep for `duration` in the virtual time of the mock HAL.
+    fn sleep(duration: Duration) -> impl Future<Output = ()> {
+        kernel_hal::sleep_until(timer_now() + duration)
+    }
 
     #[test]
     fn create() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -412,22 +659,153 @@ mod tests {
         assert!(Arc::ptr_eq(&child, &thread));
     }
 
-    #[async_std::test]
-    async fn start() {
+    #[test]
+    fn stats() {
         kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
+        thread.time_add(1000);
+        thread.time_add(500);
+        thread.set_last_cpu(2);
+        let stats = thread.get_stats();
+        assert_eq!(stats.total_runtime, 1500);
+        assert_eq!(stats.last_scheduled_cpu, 2);
+    }
+
+    #[test]
+    fn blocking_run() {
+        let _mock = mock::init();
+        use futures::future::{pending, ready};
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        let thread = Thread::create(&proc, "thread").expect("failed to create thread");
+        let current = CurrentThread(thread.clone());
+        let forever = Duration::from_nanos(i64::max_value() as u64);
+
+        mock::block_on(async move {
+            let ret = current
+                .blocking_run(ready(Ok(1)), ThreadState::BlockedChannel, forever)
+                .await;
+            assert_eq!(ret, Ok(1));
+
+            let deadline = timer_now() + Duration::from_millis(10);
+            let ret = current
+                .blocking_run(pending::<ZxResult>(), ThreadState::BlockedChannel, deadline)
+                .await;
+            assert_eq!(ret, Err(ZxError::TIMED_OUT));
+            assert_eq!(timer_now(), deadline);
+            assert_eq!(thread.state(), ThreadState::New);
+
+            spawn({
+                let thread = thread.clone();
+                async move {
+                    sleep(Duration::from_millis(10)).await;
+                    assert_eq!(thread.state(), ThreadState::BlockedChannel);
+                    thread.kill();
+                }
+            });
+            let ret = current
+                .blocking_run(pending::<ZxResult>(), ThreadState::BlockedChannel, forever)
+                .await;
+            assert_eq!(ret, Err(ZxError::STOP));
+            assert_eq!(thread.state(), ThreadState::Dying);
+        });
+    }
+
+    #[test]
+    fn suspend_sleeping() {
+        let _mock = mock::init();
+        use futures::future::pending;
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        let thread = Thread::create(&proc, "thread").expect("failed to create thread");
+        let current = CurrentThread(thread.clone());
+        let forever = Duration::from_nanos(i64::max_value() as u64);
+
+        mock::block_on(async move {
+            // blocking other than sleeping is not interrupted
+            spawn({
+                let thread = thread.clone();
+                async move {
+                    sleep(Duration::from_millis(10)).await;
+                    thread.suspend();
+                }
+            });
+            let deadline = timer_now() + Duration::from_millis(30);
+            let ret = current
+                .blocking_run(pending::<ZxResult>(), ThreadState::BlockedChannel, deadline)
+                .await;
+            assert_eq!(ret, Err(ZxError::TIMED_OUT));
+            thread.resume();
+
+            spawn({
+                let thread = thread.clone();
+                async move {
+                    sleep(Duration::from_millis(10)).await;
+                    assert_eq!(thread.state(), ThreadState::BlockedSleeping);
+                    thread.suspend();
+                    sleep(Duration::from_millis(10)).await;
+                    thread.resume();
+                }
+            });
+            let ret = current
+                .blocking_run(pending::<ZxResult>(), ThreadState::BlockedSleeping, forever)
+                .await;
+            assert_eq!(ret, Err(ZxError::INTERNAL_INTR_RETRY));
+            let time = timer_now();
+            current.wait_for_resume().await.unwrap();
+            assert_eq!(timer_now() - time, Duration::from_millis(10));
+
+            thread.suspend();
+            thread.kill();
+            assert_eq!(current.wait_for_resume().await, Err(ZxError::STOP));
+        });
+    }
+
+    #[test]
+    fn interrupt() {
+        let _mock = mock::init();
+        use futures::future::pending;
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        let thread = Thread::create(&proc, "thread").expect("failed to create thread");
+        let current = CurrentThread(thread.clone());
+        let forever = Duration::from_nanos(i64::max_value() as u64);
+
+        assert!(!thread.interrupt());
+        mock::block_on(async move {
+            spawn({
+                let thread = thread.clone();
+                async move {
+                    sleep(Duration::from_millis(10)).await;
+                    assert!(thread.interrupt());
+                }
+            });
+            let ret = current
+                .blocking_run(pending::<ZxResult>(), ThreadState::BlockedFutex, forever)
+                .await;
+            assert_eq!(ret, Err(ZxError::INTERNAL_INTR_RETRY));
+            assert_eq!(thread.state(), ThreadState::New);
+        });
+    }
+
+    #[test]
+    fn start() {
+        let _mock = mock::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        let thread = Thread::create(&proc, "thread").expect("failed to create thread");
         let thread1 = Thread::create(&proc, "thread1").expect("failed to create thread");
 
         // function for new thread
         async fn new_thread(thread: CurrentThread) {
             let cx = thread.wait_for_run().await;
-            assert_eq!(cx.general.rip, 1);
-            assert_eq!(cx.general.rsp, 4);
+            assert_eq!(cx.ip(), 1);
+            assert_eq!(cx.sp(), 4);
             assert_eq!(cx.general.rdi, 3);
             assert_eq!(cx.general.rsi, 2);
-            async_std::task::sleep(Duration::from_millis(10)).await;
+            sleep(Duration::from_millis(10)).await;
             thread.end_running(cx);
         }
 
@@ -460,8 +838,8 @@ mod tests {
             Err(ZxError::BAD_STATE)
         );
 
-        // wait 100ms for the new thread to exit
-        async_std::task::sleep(core::time::Duration::from_millis(100)).await;
+        // run the new thread until it exits
+        mock::run();
 
         // no other references to `Thread`
         assert_eq!(Arc::strong_count(&thread), 1);
@@ -470,6 +848,7 @@ mod tests {
 
     #[test]
     fn info() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -478,8 +857,29 @@ mod tests {
         assert!(info.state == thread.state() as u32);
     }
 
+    #[test]
+    fn set_profile() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let proc = Process::create(&root_job, "proc").expect("failed to create process");
+        let thread = Thread::create(&proc, "thread").expect("failed to create thread");
+        assert_eq!(thread.affinity(), None);
+
+        let mut mask = [0; CPU_SET_MAX_CPUS / 64];
+        mask[0] = 0b10;
+        let profile = Profile::create(ProfileInfo {
+            params: Some(SchedulerParams::Priority(24)),
+            cpu_affinity_mask: Some(mask),
+        })
+        .unwrap();
+        thread.set_profile(&profile);
+        assert_eq!(thread.priority(), 24);
+        assert_eq!(thread.affinity(), Some(0b10));
+    }
+
     #[test]
     fn read_write_state() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -514,8 +914,9 @@ mod tests {
         // TODO
     }
 
-    #[async_std::test]
-    async fn wait_for_run() {
+    #[test]
+    fn wait_for_run() {
+        let _mock = mock::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -536,19 +937,20 @@ mod tests {
             thread.suspend();
             thread.suspend();
             assert_eq!(thread.state(), ThreadState::Suspended);
-            async_std::task::spawn({
+            spawn({
                 let thread = (*thread).clone();
                 async move {
-                    async_std::task::sleep(Duration::from_millis(10)).await;
+                    sleep(Duration::from_millis(10)).await;
                     thread.resume();
-                    async_std::task::sleep(Duration::from_millis(10)).await;
+                    sleep(Duration::from_millis(10)).await;
                     thread.resume();
                 }
             });
             let time = timer_now();
             let _context = thread.wait_for_run().await;
-            assert!(timer_now() - time >= Duration::from_millis(20));
+            assert_eq!(timer_now() - time, Duration::from_millis(20));
         }
+        mock::run();
         // FIX ME
         // let thread: Arc<dyn KernelObject> = thread;
         // thread.wait_signal(Signal::THREAD_TERMINATED).await;
@@ -556,6 +958,7 @@ mod tests {
 
     #[test]
     fn time() {
+        kernel_hal_unix::init();
         let root_job = Job::root();
         let proc = Process::create(&root_job, "proc").expect("failed to create process");
         let thread = Thread::create(&proc, "thread").expect("failed to create thread");
@@ -5,6 +5,8 @@ use numeric_enum_macro::numeric_enum;
 numeric_enum! {
     #[repr(u32)]
     /// Possible values for "kind" in zx_thread_read_state and zx_thread_write_state.
+    ///
+    /// `FS` and `GS` are only on x86_64.
     #[allow(missing_docs)]
     #[derive(Debug, Copy, Clone)]
     pub enum ThreadStateKind {
@@ -23,16 +25,24 @@ impl ContextExt for UserContext {
     fn read_state(&self, kind: ThreadStateKind, buf: &mut [u8]) -> ZxResult<usize> {
         match kind {
             ThreadStateKind::General => buf.write_struct(&self.general),
+            #[cfg(target_arch = "x86_64")]
             ThreadStateKind::FS => buf.write_struct(&self.general.fsbase),
+            #[cfg(target_arch = "x86_64")]
             ThreadStateKind::GS => buf.write_struct(&self.general.gsbase),
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => Err(ZxError::INVALID_ARGS),
         }
     }
 
     fn write_state(&mut self, kind: ThreadStateKind, buf: &[u8]) -> ZxResult {
         match kind {
             ThreadStateKind::General => self.general = buf.read_struct()?,
+            #[cfg(target_arch = "x86_64")]
             ThreadStateKind::FS => self.general.fsbase = buf.read_struct()?,
+            #[cfg(target_arch = "x86_64")]
             ThreadStateKind::GS => self.general.gsbase = buf.read_struct()?,
+            #[cfg(not(target_arch = "x86_64"))]
+            _ => return Err(ZxError::INVALID_ARGS),
         }
         Ok(())
     }
@@ -0,0 +1,253 @@
+//! The kernel console, running commands sent by `zx_debug_send_command`, or
+//! typed in the kernel shell.
+//!
+//! The output of commands is written to the serial console.
+
+use {
+    super::kcounter,
+    crate::{debuglog, object::*, task::*, vm::*, ZxError, ZxResult},
+    alloc::{format, string::String, sync::Arc},
+    kernel_hal::serial_write,
+};
+
+/// The maximum length of commands sent at once.
+pub const MAX_COMMAND_LEN: usize = 1024;
+
+/// The default number of bytes dumped by `dlog`.
+const DLOG_DUMP_LEN: usize = 4096;
+
+/// The prompt of the kernel shell.
+const PROMPT: &str = "zcore> ";
+
+/// Commands and their usage.
+const COMMANDS: &[(&str, &str)] = &[
+    ("help", "list the commands"),
+    ("dlog [len]", "dump the tail of the debuglog"),
+    ("mem", "show the physical memory usage"),
+    ("ps", "list the jobs and processes"),
+    ("threads <koid>", "list the threads of a process"),
+    ("vmos <koid>", "list the VMOs mapped in a process"),
+    ("kcounter", "show the kernel counters"),
+    ("kill <koid>", "kill a job, process or thread but the root"),
+];
+
+/// Run the commands in `script`, one command in a line, on the tasks under
+/// `root_job`.
+///
+/// Fail with `NOT_FOUND` on an unknown command or task, `WRONG_TYPE` on a
+/// task of the wrong type, `ACCESS_DENIED` on killing `root_job`, or
+/// `INVALID_ARGS` on bad arguments, skipping the remaining commands.
+pub fn run_script(script: &str, root_job: &Arc<Job>) -> ZxResult {
+    for line in script.lines() {
+        let mut args = line.split_whitespace();
+        let cmd = match args.next() {
+            Some(cmd) => cmd,
+            None => continue,
+        };
+        match cmd {
+            "help" => {
+                for (usage, description) in COMMANDS {
+                    serial_write(&format!("{:<16}: {}\n", usage, description));
+                }
+            }
+            "dlog" => {
+                let len = match args.next() {
+                    Some(len) => len.parse().map_err(|_| ZxError::INVALID_ARGS)?,
+                    None => DLOG_DUMP_LEN,
+                };
+                match debuglog::dump_tail(len) {
+                    Some(log) => serial_write(&log),
+                    None => serial_write("dlog: the debuglog is busy\n"),
+                }
+            }
+            "mem" => {
+                let stats = kernel_hal::frame_stats();
+                let watermarks = memory_watermarks();
+                serial_write(&format!(
+                    "total: {} KiB, free: {} KiB\n\
+                     watermarks: out of memory {} KiB, critical {} KiB, warning {} KiB\n",
+                    stats.total * PAGE_SIZE / 1024,
+                    stats.free * PAGE_SIZE / 1024,
+                    watermarks.out_of_memory * PAGE_SIZE / 1024,
+                    watermarks.critical * PAGE_SIZE / 1024,
+                    watermarks.warning * PAGE_SIZE / 1024,
+                ));
+            }
+            "ps" => serial_write(&format!("{}", dump_task_tree(root_job))),
+            "threads" => {
+                let proc = find_process(root_job, parse_koid(args.next())?)?;
+                for id in proc.thread_ids() {
+                    if let Ok(thread) = proc.get_child(id) {
+                        let thread = thread.downcast_arc::<Thread>().unwrap();
+                        serial_write(&format!(
+                            "{:>8} {:<32} {:?}\n",
+                            id,
+                            thread.name(),
+                            thread.state()
+                        ));
+                    }
+                }
+            }
+            "vmos" => {
+                let proc = find_process(root_job, parse_koid(args.next())?)?;
+                for (addr, vmo) in proc.vmar().mapped_vmos() {
+                    let committed = vmo.committed_pages_in_range(0, pages(vmo.len()));
+                    serial_write(&format!(
+                        "{:#018x} {:>8} {:<32} size: {} KiB, committed: {} KiB\n",
+                        addr,
+                        vmo.id(),
+                        vmo.name(),
+                        vmo.len() / 1024,
+                        committed * PAGE_SIZE / 1024,
+                    ));
+                }
+            }
+            "kcounter" => {
+                for counter in kcounter::all() {
+                    serial_write(&format!("{:<40} {}\n", counter.name(), counter.get()));
+                }
+            }
+            "kill" => {
+                let koid = parse_koid(args.next())?;
+                // it would take down the whole system
+                if koid == root_job.id() {
+                    return Err(ZxError::ACCESS_DENIED);
+                }
+                let task = find_task(root_job, koid).ok_or(ZxError::NOT_FOUND)?;
+                if let Ok(job) = task.clone().downcast_arc::<Job>() {
+                    job.kill();
+                } else if let Ok(proc) = task.clone().downcast_arc::<Process>() {
+                    proc.kill();
+                } else if let Ok(thread) = task.downcast_arc::<Thread>() {
+                    thread.kill();
+                }
+            }
+            _ => {
+                serial_write(&format!("unknown command: {}\n", cmd));
+                return Err(ZxError::NOT_FOUND);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run the kernel shell on the serial console, running each line typed as
+/// commands on the tasks under `root_job`.
+///
+/// It never returns, and takes the input of the serial console from the
+/// `zx_debug_read` of userspace.
+pub async fn shell(root_job: Arc<Job>) {
+    let mut line = String::new();
+    let mut buf = [0u8; 64];
+    serial_write(PROMPT);
+    loop {
+        let len = kernel_hal::serial_read(&mut buf);
+        if len == 0 {
+            kernel_hal::serial_wait().await;
+            continue;
+        }
+        for &c in buf[..len].iter() {
+            match c {
+                b'\r' | b'\n' => {
+                    serial_write("\n");
+                    if let Err(err) = run_script(&line, &root_job) {
+                        serial_write(&format!("error: {:?}\n", err));
+                    }
+                    line.clear();
+                    serial_write(PROMPT);
+                }
+                // backspace or delete
+                0x08 | 0x7f => {
+                    if line.pop().is_some() {
+                        serial_write("\x08 \x08");
+                    }
+                }
+                c if (c == b' ' || c.is_ascii_graphic()) && line.len() < MAX_COMMAND_LEN => {
+                    line.push(c as char);
+                    serial_write(core::str::from_utf8(&[c]).unwrap());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn parse_koid(arg: Option<&str>) -> ZxResult<KoID> {
+    arg.and_then(|koid| koid.parse().ok())
+        .ok_or(ZxError::INVALID_ARGS)
+}
+
+/// Find the job, process or thread with `koid` under `job`.
+fn find_task(job: &Arc<Job>, koid: KoID) -> Option<Arc<dyn KernelObject>> {
+    if job.id() == koid {
+        return Some(job.clone());
+    }
+    if let Ok(child) = job.get_child(koid) {
+        return Some(child);
+    }
+    for id in job.process_ids() {
+        if let Ok(thread) = job.get_child(id).and_then(|proc| proc.get_child(koid)) {
+            return Some(thread);
+        }
+    }
+    for id in job.children_ids() {
+        if let Ok(child) = job.get_child(id) {
+            let child = child.downcast_arc::<Job>().unwrap();
+            if let Some(task) = find_task(&child, koid) {
+                return Some(task);
+            }
+        }
+    }
+    None
+}
+
+fn find_process(job: &Arc<Job>, koid: KoID) -> ZxResult<Arc<Process>> {
+    find_task(job, koid)
+        .ok_or(ZxError::NOT_FOUND)?
+        .downcast_arc::<Process>()
+        .map_err(|_| ZxError::WRONG_TYPE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        assert_eq!(run_script("help\n\nmem\ndlog 64", &root_job), Ok(()));
+        assert_eq!(run_script("dlog -1", &root_job), Err(ZxError::INVALID_ARGS));
+        assert_eq!(
+            run_script("mem\nfoo\nhelp", &root_job),
+            Err(ZxError::NOT_FOUND)
+        );
+    }
+
+    #[test]
+    fn tasks() {
+        kernel_hal_unix::init();
+        let root_job = Job::root();
+        let job = root_job.create_child().unwrap();
+        let proc = Process::create(&job, "proc").unwrap();
+        let thread = Thread::create(&proc, "thread").unwrap();
+        let vmo = VmObject::new_paged(2);
+        let flags = MMUFlags::READ | MMUFlags::WRITE | MMUFlags::USER;
+        proc.vmar().map(None, vmo, 0, 2 * PAGE_SIZE, flags).unwrap();
+
+        assert_eq!(run_script("ps\nkcounter", &root_job), Ok(()));
+        let script = format!("threads {}\nvmos {}", proc.id(), proc.id());
+        assert_eq!(run_script(&script, &root_job), Ok(()));
+        let script = format!("threads {}", thread.id());
+        assert_eq!(run_script(&script, &root_job), Err(ZxError::WRONG_TYPE));
+        assert_eq!(run_script("vmos 0", &root_job), Err(ZxError::NOT_FOUND));
+        assert_eq!(run_script("kill", &root_job), Err(ZxError::INVALID_ARGS));
+        let script = format!("kill {}", root_job.id());
+        assert_eq!(run_script(&script, &root_job), Err(ZxError::ACCESS_DENIED));
+        assert_eq!(proc.status(), Status::Init);
+
+        let script = format!("kill {}", proc.id());
+        assert_eq!(run_script(&script, &root_job), Ok(()));
+        assert_eq!(proc.status(), Status::Exited(TASK_RETCODE_SYSCALL_KILL));
+    }
+}
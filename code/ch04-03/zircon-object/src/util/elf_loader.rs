@@ -14,19 +14,27 @@ pub trait VmarExt {
     /// Create `VMObject` from all LOAD segments of `elf` and map them to this VMAR.
     /// Return the first `VMObject`.
     fn load_from_elf(&self, elf: &ElfFile) -> ZxResult<Arc<VmObject>>;
+    /// Same as `load_from_elf`, but this VMAR starts at the linked address
+    /// `vaddr` of `elf` instead of 0, for the programs not position-independent.
+    fn load_from_elf_at(&self, elf: &ElfFile, vaddr: usize) -> ZxResult<Arc<VmObject>>;
     /// Same as `load_from_elf`, but the `vmo` is an existing one instead of a lot of new ones.
     fn map_from_elf(&self, elf: &ElfFile, vmo: Arc<VmObject>) -> ZxResult;
 }
 
 impl VmarExt for VmAddressRegion {
     fn load_from_elf(&self, elf: &ElfFile) -> ZxResult<Arc<VmObject>> {
+        self.load_from_elf_at(elf, 0)
+    }
+    fn load_from_elf_at(&self, elf: &ElfFile, vaddr: usize) -> ZxResult<Arc<VmObject>> {
         let mut first_vmo = None;
         for ph in elf.program_iter() {
             if ph.get_type().unwrap() != Type::Load {
                 continue;
             }
             let vmo = make_vmo(elf, ph)?;
-            let offset = ph.virtual_addr() as usize / PAGE_SIZE * PAGE_SIZE;
+            let offset = (ph.virtual_addr() as usize / PAGE_SIZE * PAGE_SIZE)
+                .checked_sub(vaddr)
+                .ok_or(ZxError::INVALID_ARGS)?;
             let flags = ph.flags().to_mmu_flags();
             trace!("ph:{:#x?}, offset:{:#x?}, flags:{:#x?}", ph, offset, flags);
             //映射vmo物理内存块到 VMAR
@@ -94,6 +102,8 @@ fn make_vmo(elf: &ElfFile, ph: ProgramHeader) -> ZxResult<Arc<VmObject>> {
 pub trait ElfExt {
     /// Get total size of all LOAD segments.
     fn load_segment_size(&self) -> usize;
+    /// Get the page-aligned lowest address of all LOAD segments.
+    fn load_segment_start(&self) -> usize;
     /// Get address of the given `symbol`.
     fn get_symbol_address(&self, symbol: &str) -> Option<u64>;
     /// Get the program interpreter path name.
@@ -116,6 +126,14 @@ impl ElfExt for ElfFile<'_> {
             * PAGE_SIZE
     }
 
+    fn load_segment_start(&self) -> usize {
+        self.program_iter()
+            .filter(|ph| ph.get_type().unwrap() == Type::Load)
+            .map(|ph| ph.virtual_addr() as usize / PAGE_SIZE * PAGE_SIZE)
+            .min()
+            .unwrap_or(0)
+    }
+
     fn get_symbol_address(&self, symbol: &str) -> Option<u64> {
         for section in self.section_iter() {
             if let SectionData::SymbolTable64(entries) = section.get_data(self).unwrap() {
@@ -0,0 +1,301 @@
+use {
+    crate::vm::{pages, VmObject},
+    alloc::{sync::Arc, vec, vec::Vec},
+    core::{
+        mem::size_of,
+        sync::atomic::{AtomicUsize, Ordering},
+    },
+    kernel_hal::{PhysAddr, PhysFrame},
+    spin::Once,
+};
+
+/// Kernel counter.
+///
+/// A named counter for kernel statistics, defined by [`kcounter!`].
+///
+/// [`kcounter!`]: ../../macro.kcounter.html
+#[derive(Debug)]
+pub struct KCounter {
+    name: &'static str,
+    /// The value added before the arena is created.
+    value: AtomicUsize,
+    /// The index in the arena, valid after the arena is created.
+    index: AtomicUsize,
+}
+
+impl KCounter {
+    /// Create a new counter with `name`.
+    pub const fn new(name: &'static str) -> Self {
+        KCounter {
+            name,
+            value: AtomicUsize::new(0),
+            index: AtomicUsize::new(0),
+        }
+    }
+
+    /// Get the name of the counter.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Add `x` to the counter.
+    ///
+    /// It is added to the arena atomically once the arena is created.
+    pub fn add(&self, x: usize) {
+        match ARENA.get() {
+            Some(arena) => arena.add(self.index.load(Ordering::Relaxed), x),
+            None => {
+                self.value.fetch_add(x, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Get the value of the counter.
+    pub fn get(&self) -> usize {
+        let value = self.value.load(Ordering::Relaxed);
+        match ARENA.get() {
+            Some(arena) => value + arena.sum(self.index.load(Ordering::Relaxed)),
+            None => value,
+        }
+    }
+}
+
+/// Define a new static [`KCounter`].
+///
+/// The counter is registered in the link section `kcounter_desc`, to be
+/// listed by [`all`].
+///
+/// [`KCounter`]: util/kcounter/struct.KCounter.html
+/// [`all`]: util/kcounter/fn.all.html
+#[macro_export]
+macro_rules! kcounter {
+    ($var:ident, $name:expr) => {
+        static $var: $crate::util::kcounter::KCounter =
+            $crate::util::kcounter::KCounter::new($name);
+        const _: () = {
+            #[used]
+            #[cfg_attr(
+                any(target_os = "none", target_os = "linux"),
+                link_section = "kcounter_desc"
+            )]
+            static DESCRIPTOR: &$crate::util::kcounter::KCounter = &$var;
+        };
+    };
+}
+
+/// Get all counters defined by `kcounter!`, sorted by their names.
+///
+/// The link section is only collected on ELF targets, and nothing is found
+/// on the others.
+pub fn all() -> Vec<&'static KCounter> {
+    #[cfg(any(target_os = "none", target_os = "linux"))]
+    let descriptors: &[&KCounter] = unsafe {
+        // defined by the linker around the section
+        extern "C" {
+            static __start_kcounter_desc: &'static KCounter;
+            static __stop_kcounter_desc: &'static KCounter;
+        }
+        let start = &__start_kcounter_desc as *const &KCounter;
+        let end = &__stop_kcounter_desc as *const &KCounter;
+        core::slice::from_raw_parts(start, end.offset_from(start) as usize)
+    };
+    #[cfg(not(any(target_os = "none", target_os = "linux")))]
+    let descriptors: &[&KCounter] = &[];
+    let mut counters = descriptors.to_vec();
+    counters.sort_by_key(|counter| counter.name());
+    counters
+}
+
+/// The magic of the descriptor table VMO.
+const KCOUNTER_MAGIC: u64 = 1_547_273_975;
+
+/// The maximum length of the names in the descriptor table, with the NUL.
+const NAME_SIZE: usize = 56;
+
+/// The type of the counters summed over the CPUs.
+const KCOUNTER_TYPE_SUM: u64 = 1;
+
+/// The header of the descriptor table VMO.
+#[repr(C)]
+struct DescriptorVmoHeader {
+    magic: u64,
+    max_cpus: u64,
+    /// The size of the table following the header in bytes.
+    descriptor_table_size: u64,
+}
+
+/// An entry of the descriptor table.
+#[repr(C)]
+struct Descriptor {
+    name: [u8; NAME_SIZE],
+    type_: u64,
+}
+
+/// The arena of the counter values, in physical memory.
+///
+/// It has a block of all counters for each CPU, ordered as the descriptor
+/// table. The counters are added in the block of the current CPU, and their
+/// values are summed over the blocks.
+struct Arena {
+    frames: Vec<PhysFrame>,
+    /// The number of counters in a block.
+    counters: usize,
+    /// The number of blocks.
+    cpus: usize,
+}
+
+impl Arena {
+    fn paddr(&self) -> PhysAddr {
+        self.frames[0].addr()
+    }
+
+    /// Get the address of the counter `index` in the block of `cpu`.
+    fn counter_paddr(&self, cpu: usize, index: usize) -> PhysAddr {
+        self.paddr() + (cpu * self.counters + index) * size_of::<u64>()
+    }
+
+    /// Add `x` to the value of the counter `index` of the current CPU
+    /// atomically, so that the concurrent updates are not lost and readers
+    /// never see a torn value.
+    ///
+    /// A thread may move to another CPU meanwhile, which only changes the
+    /// block the value is added to.
+    fn add(&self, index: usize, x: usize) {
+        let cpu = kernel_hal::cpu_id() as usize % self.cpus;
+        kernel_hal::pmem_fetch_add_u64(self.counter_paddr(cpu, index), x as u64);
+    }
+
+    /// Get the value of the counter `index` summed over the CPUs.
+    fn sum(&self, index: usize) -> usize {
+        (0..self.cpus)
+            .map(|cpu| kernel_hal::pmem_fetch_add_u64(self.counter_paddr(cpu, index), 0) as usize)
+            .sum()
+    }
+}
+
+static ARENA: Once<Arena> = Once::new();
+
+/// Create the VMOs of the descriptor table and the arena of the counters,
+/// in the format read by the `kcounter` tool of userboot.
+///
+/// The arena VMO is backed by the physical memory the counters are written
+/// to, so it always shows the current values. It should be called once.
+pub fn create_vmos() -> (Arc<VmObject>, Arc<VmObject>) {
+    let counters = all();
+    let max_cpus = kernel_hal::cpu_count() as usize;
+
+    let header = DescriptorVmoHeader {
+        magic: KCOUNTER_MAGIC,
+        max_cpus: max_cpus as u64,
+        descriptor_table_size: (counters.len() * size_of::<Descriptor>()) as u64,
+    };
+    let desc_size = size_of::<DescriptorVmoHeader>() + header.descriptor_table_size as usize;
+    let desc_vmo = VmObject::new_paged(pages(desc_size));
+    let descs: Vec<Descriptor> = counters
+        .iter()
+        .enumerate()
+        .map(|(i, counter)| {
+            let mut desc = Descriptor {
+                name: [0; NAME_SIZE],
+                type_: KCOUNTER_TYPE_SUM,
+            };
+            let len = counter.name().len().min(NAME_SIZE - 1);
+            desc.name[..len].copy_from_slice(&counter.name().as_bytes()[..len]);
+            counter.index.store(i, Ordering::Relaxed);
+            desc
+        })
+        .collect();
+    // the header and the table are written in one traversal of the VMO
+    let mut bufs = vec![as_bytes(&header)];
+    bufs.extend(descs.iter().map(as_bytes));
+    desc_vmo.write_v(0, &bufs).unwrap();
+    desc_vmo.set_name("counters/desc");
+
+    let arena_pages = pages(max_cpus * counters.len() * size_of::<u64>()).max(1);
+    let arena = ARENA.call_once(|| Arena {
+        frames: PhysFrame::alloc_contiguous_zeroed(arena_pages, 0),
+        counters: counters.len(),
+        cpus: max_cpus,
+    });
+    assert_eq!(
+        arena.frames.len(),
+        arena_pages,
+        "failed to allocate kcounter arena"
+    );
+    // move the values added before the arena is created
+    for counter in counters.iter() {
+        let value = counter.value.swap(0, Ordering::Relaxed);
+        arena.add(counter.index.load(Ordering::Relaxed), value);
+    }
+    let arena_vmo = VmObject::new_physical(arena.paddr(), arena_pages);
+    arena_vmo.set_name("counters/arena");
+    (desc_vmo, arena_vmo)
+}
+
+/// View a `repr(C)` struct as bytes.
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::convert::TryInto;
+
+    kcounter!(TEST_COUNTER, "test.counter");
+
+    #[test]
+    fn arena() {
+        kernel_hal_unix::init();
+        TEST_COUNTER.add(2);
+        let counters = all();
+        assert!(counters.windows(2).all(|w| w[0].name() <= w[1].name()));
+        let index = counters
+            .iter()
+            .position(|counter| counter.name() == "test.counter")
+            .unwrap();
+
+        let (desc_vmo, arena_vmo) = create_vmos();
+        let mut header = [0u8; 24];
+        desc_vmo.read(0, &mut header).unwrap();
+        assert_eq!(header[..8], KCOUNTER_MAGIC.to_ne_bytes());
+        let mut name = [0u8; 12];
+        let offset = size_of::<DescriptorVmoHeader>() + index * size_of::<Descriptor>();
+        desc_vmo.read(offset, &mut name).unwrap();
+        assert_eq!(&name, b"test.counter");
+
+        // the arena follows the counter, in the blocks of the CPUs
+        let max_cpus = u64::from_ne_bytes(header[8..16].try_into().unwrap()) as usize;
+        let read_value = || {
+            (0..max_cpus)
+                .map(|cpu| {
+                    let mut value = [0u8; 8];
+                    arena_vmo
+                        .read((cpu * counters.len() + index) * 8, &mut value)
+                        .unwrap();
+                    u64::from_ne_bytes(value)
+                })
+                .sum::<u64>()
+        };
+        assert_eq!(read_value(), 2);
+        TEST_COUNTER.add(3);
+        assert_eq!(read_value(), 5);
+        assert_eq!(TEST_COUNTER.get(), 5);
+
+        // no update is lost by the concurrent writers
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..1000 {
+                        TEST_COUNTER.add(1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(read_value(), 4005);
+        assert_eq!(TEST_COUNTER.get(), 4005);
+    }
+}
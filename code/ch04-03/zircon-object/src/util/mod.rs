@@ -1,4 +1,7 @@
 //! Utilities.
 
 pub(crate) mod block_range;
+pub mod console;
 pub mod elf_loader;
+pub mod kcounter;
+pub mod random;
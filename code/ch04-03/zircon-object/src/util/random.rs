@@ -0,0 +1,65 @@
+//! The kernel CSPRNG, a ChaCha20 pool seeded from the hardware RNG of HAL.
+
+use {
+    lazy_static::lazy_static,
+    rand_chacha::{
+        rand_core::{RngCore, SeedableRng},
+        ChaCha20Rng,
+    },
+    spin::Mutex,
+};
+
+/// The maximum number of bytes added to the entropy pool at once.
+pub const CPRNG_ADD_ENTROPY_MAX_LEN: usize = 256;
+
+lazy_static! {
+    static ref CPRNG: Mutex<ChaCha20Rng> = {
+        let mut seed = [0; 32];
+        kernel_hal::rand_bytes(&mut seed);
+        Mutex::new(ChaCha20Rng::from_seed(seed))
+    };
+}
+
+/// Fill `buf` with random bytes.
+pub fn draw(buf: &mut [u8]) {
+    CPRNG.lock().fill_bytes(buf);
+}
+
+/// Mix `entropy` into the pool, by reseeding it with its own output folded
+/// with `entropy`.
+pub fn add_entropy(entropy: &[u8]) {
+    let mut cprng = CPRNG.lock();
+    let mut seed = [0; 32];
+    cprng.fill_bytes(&mut seed);
+    for (i, &byte) in entropy.iter().enumerate() {
+        seed[i % seed.len()] ^= byte;
+    }
+    *cprng = ChaCha20Rng::from_seed(seed);
+}
+
+/// Get a random `u64`.
+pub fn random_u64() -> u64 {
+    CPRNG.lock().next_u64()
+}
+
+/// Get a random number in `[0, bound)`.
+pub fn random_below(bound: usize) -> usize {
+    assert_ne!(bound, 0);
+    (random_u64() % bound as u64) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_and_add_entropy() {
+        kernel_hal_unix::init();
+        let (mut a, mut b) = ([0u8; 64], [0u8; 64]);
+        draw(&mut a);
+        add_entropy(&[0; CPRNG_ADD_ENTROPY_MAX_LEN]);
+        draw(&mut b);
+        assert_ne!(a, b);
+        assert_ne!(a, [0; 64]);
+    }
+}
@@ -0,0 +1,76 @@
+use {
+    super::*,
+    core::sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Number of pages committed by all paged VMOs.
+pub(super) static VMO_COMMITTED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of pages pinned in all paged VMOs.
+pub(super) static VMO_PINNED_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Kernel memory statistics, returned by `ZX_INFO_KMEM_STATS`.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct KmemInfo {
+    /// The total amount of physical memory available to the system.
+    pub total_bytes: u64,
+    /// The amount of unallocated memory.
+    pub free_bytes: u64,
+    /// The amount of memory reserved by and mapped into the kernel for reasons
+    /// not covered by other fields in this struct. Here they are pinned pages.
+    pub wired_bytes: u64,
+    /// The size of the kernel heap.
+    pub total_heap_bytes: u64,
+    /// The amount of the kernel heap that is not being used.
+    pub free_heap_bytes: u64,
+    /// The amount of memory committed to VMOs, both kernel and user.
+    pub vmo_bytes: u64,
+    /// The amount of memory used for architecture-specific MMU metadata
+    /// like page tables.
+    pub mmu_overhead_bytes: u64,
+    /// The amount of memory in use by IPC.
+    pub ipc_bytes: u64,
+    /// Non-free memory that isn't accounted for in any other field.
+    pub other_bytes: u64,
+}
+
+impl KmemInfo {
+    /// Collect kernel memory statistics from the frame allocator, the heap
+    /// and VMOs.
+    pub fn collect() -> Self {
+        let frames = kernel_hal::frame_stats();
+        let heap = kernel_hal::heap_stats();
+        let used = frames.total - frames.free;
+        let vmo = VMO_COMMITTED_PAGES.load(Ordering::Relaxed);
+        let wired = VMO_PINNED_PAGES.load(Ordering::Relaxed);
+        KmemInfo {
+            total_bytes: (frames.total * PAGE_SIZE) as u64,
+            free_bytes: (frames.free * PAGE_SIZE) as u64,
+            wired_bytes: (wired * PAGE_SIZE) as u64,
+            total_heap_bytes: heap.total as u64,
+            free_heap_bytes: heap.free as u64,
+            vmo_bytes: (vmo * PAGE_SIZE) as u64,
+            other_bytes: (used.saturating_sub(vmo) * PAGE_SIZE) as u64,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collect() {
+        kernel_hal_unix::init();
+        let vmo = VmObject::new_paged(4);
+        vmo.commit(0, 4 * PAGE_SIZE).unwrap();
+        let info = KmemInfo::collect();
+        assert!(info.total_bytes >= info.free_bytes + 4 * PAGE_SIZE as u64);
+        assert!(info.vmo_bytes >= 4 * PAGE_SIZE as u64);
+        // the heap of the host is counted on unix
+        assert!(info.total_heap_bytes > info.free_heap_bytes);
+        drop(vmo);
+    }
+}
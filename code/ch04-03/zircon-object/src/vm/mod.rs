@@ -1,9 +1,12 @@
 //! Objects for Virtual Memory Management.
 
+mod kmem;
+mod pager;
+mod pressure;
 mod vmar;
 mod vmo;
 
-pub use self::{vmar::*, vmo::*};
+pub use self::{kmem::*, pager::*, pressure::*, vmar::*, vmo::*};
 
 /// Physical Address
 pub type PhysAddr = usize;
@@ -0,0 +1,192 @@
+use {
+    super::*,
+    crate::object::*,
+    alloc::{sync::Arc, vec::Vec},
+    numeric_enum_macro::numeric_enum,
+    spin::Mutex,
+};
+
+/// Create and manage VMOs whose content is managed by userspace.
+///
+/// ## SYNOPSIS
+///
+/// A pager creates pager-backed VMOs and tracks which pages of them have been
+/// modified, so that a userspace filesystem can write them back to storage.
+///
+/// Page requests are not delivered yet: uncommitted pages of a pager-backed
+/// VMO are simply zero-filled on first access.
+pub struct Pager {
+    base: KObjectBase,
+    inner: Mutex<PagerInner>,
+}
+
+impl_kobject!(Pager);
+
+#[derive(Default)]
+struct PagerInner {
+    /// KoIDs of VMOs created by this pager, which are not detached.
+    vmos: Vec<KoID>,
+}
+
+numeric_enum! {
+    #[repr(u32)]
+    /// Operations used by `pager_op_range`.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum PagerOp {
+        Fail = 1,
+        Dirty = 2,
+        WritebackBegin = 3,
+        WritebackEnd = 4,
+    }
+}
+
+/// A range of dirty pages, returned by `pager_query_dirty_ranges`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct VmoDirtyRange {
+    /// Offset in bytes of the range.
+    pub offset: u64,
+    /// Length in bytes of the range.
+    pub length: u64,
+    /// Options of the range. Always 0 now.
+    pub options: u64,
+}
+
+impl Pager {
+    /// Create a new pager.
+    pub fn create() -> Arc<Self> {
+        Arc::new(Pager {
+            base: KObjectBase::new(),
+            inner: Mutex::new(PagerInner::default()),
+        })
+    }
+
+    /// Create a pager-backed VMO with `pages` pages.
+    pub fn create_vmo(&self, pages: usize) -> Arc<VmObject> {
+        let vmo = VmObject::new_pager_backed(pages);
+        self.inner.lock().vmos.push(vmo.id());
+        vmo
+    }
+
+    /// Detach `vmo` from this pager.
+    pub fn detach_vmo(&self, vmo: &VmObject) -> ZxResult {
+        let mut inner = self.inner.lock();
+        let len = inner.vmos.len();
+        inner.vmos.retain(|&id| id != vmo.id());
+        if inner.vmos.len() == len {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        Ok(())
+    }
+
+    /// Perform `op` on the range `[offset, offset + len)` of `vmo`.
+    pub fn op_range(&self, op: PagerOp, vmo: &VmObject, offset: usize, len: usize) -> ZxResult {
+        self.check_vmo(vmo)?;
+        if !page_aligned(offset) || !page_aligned(len) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        match op {
+            PagerOp::Dirty => vmo.mark_dirty(offset, len),
+            PagerOp::WritebackBegin => vmo.writeback_begin(offset, len),
+            PagerOp::WritebackEnd => vmo.writeback_end(offset, len),
+            // there is no outstanding page request to fail
+            PagerOp::Fail => Err(ZxError::NOT_SUPPORTED),
+        }
+    }
+
+    /// Query the dirty ranges of `vmo` in `[offset, offset + len)`.
+    pub fn query_dirty_ranges(
+        &self,
+        vmo: &VmObject,
+        offset: usize,
+        len: usize,
+    ) -> ZxResult<Vec<VmoDirtyRange>> {
+        self.check_vmo(vmo)?;
+        if !page_aligned(offset) || !page_aligned(len) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        vmo.dirty_ranges(offset, len)
+    }
+
+    /// Check whether `vmo` is created by this pager.
+    fn check_vmo(&self, vmo: &VmObject) -> ZxResult {
+        if self.inner.lock().vmos.contains(&vmo.id()) {
+            Ok(())
+        } else {
+            Err(ZxError::INVALID_ARGS)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kernel_hal::MMUFlags;
+
+    #[test]
+    fn dirty_ranges() {
+        kernel_hal_unix::init();
+        let pager = Pager::create();
+        let vmo = pager.create_vmo(4);
+        assert!(vmo.get_info().flags.contains(VmoInfoFlags::PAGER_BACKED));
+        let query = || pager.query_dirty_ranges(&vmo, 0, 4 * PAGE_SIZE).unwrap();
+        assert!(query().is_empty());
+
+        // reading does not dirty pages
+        vmo.test_read(0);
+        assert!(query().is_empty());
+
+        vmo.test_write(1, 1);
+        vmo.test_write(2, 1);
+        let range = VmoDirtyRange {
+            offset: PAGE_SIZE as u64,
+            length: 2 * PAGE_SIZE as u64,
+            options: 0,
+        };
+        assert_eq!(query(), [range]);
+
+        // pages written during writeback stay dirty
+        pager
+            .op_range(PagerOp::WritebackBegin, &vmo, 0, 4 * PAGE_SIZE)
+            .unwrap();
+        vmo.test_write(2, 2);
+        pager
+            .op_range(PagerOp::WritebackEnd, &vmo, 0, 4 * PAGE_SIZE)
+            .unwrap();
+        let range = VmoDirtyRange {
+            offset: 2 * PAGE_SIZE as u64,
+            length: PAGE_SIZE as u64,
+            options: 0,
+        };
+        assert_eq!(query(), [range]);
+
+        // pages mapped writable are dirty again after writeback
+        let vmar = VmAddressRegion::new_root();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        vmar.map_at(0, vmo.clone(), 0, PAGE_SIZE, flags).unwrap();
+        pager
+            .op_range(PagerOp::WritebackBegin, &vmo, 0, 4 * PAGE_SIZE)
+            .unwrap();
+        pager
+            .op_range(PagerOp::WritebackEnd, &vmo, 0, 4 * PAGE_SIZE)
+            .unwrap();
+        let range = VmoDirtyRange {
+            offset: 0,
+            length: PAGE_SIZE as u64,
+            options: 0,
+        };
+        assert_eq!(query(), [range]);
+
+        // only VMOs created by the pager are accepted
+        let other = VmObject::new_paged(1);
+        assert_eq!(
+            pager.query_dirty_ranges(&other, 0, PAGE_SIZE).err(),
+            Some(ZxError::INVALID_ARGS)
+        );
+        pager.detach_vmo(&vmo).unwrap();
+        assert_eq!(
+            pager.query_dirty_ranges(&vmo, 0, PAGE_SIZE).err(),
+            Some(ZxError::INVALID_ARGS)
+        );
+    }
+}
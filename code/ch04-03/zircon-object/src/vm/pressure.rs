@@ -0,0 +1,151 @@
+use {
+    crate::object::*, crate::signal::Event, alloc::sync::Arc, lazy_static::lazy_static,
+    numeric_enum_macro::numeric_enum, spin::Mutex,
+};
+
+numeric_enum! {
+    #[repr(u32)]
+    /// The kind of a system event, each of which is signaled when the memory
+    /// pressure is at its level.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, PartialOrd, Ord)]
+    pub enum SystemEventKind {
+        /// Free frames are below the out-of-memory watermark.
+        OutOfMemory = 1,
+        /// Free frames are below the critical watermark.
+        MemoryPressureCritical = 2,
+        /// Free frames are below the warning watermark.
+        MemoryPressureWarning = 3,
+        /// Free frames are enough.
+        MemoryPressureNormal = 4,
+    }
+}
+
+/// The watermarks of free frames in pages, from the lowest to the highest.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct MemoryWatermarks {
+    /// The out-of-memory watermark.
+    pub out_of_memory: usize,
+    /// The critical watermark.
+    pub critical: usize,
+    /// The warning watermark.
+    pub warning: usize,
+}
+
+impl MemoryWatermarks {
+    /// The default watermarks, at 1/64, 1/32 and 1/16 of all frames.
+    fn default_for(total: usize) -> Self {
+        MemoryWatermarks {
+            out_of_memory: total / 64,
+            critical: total / 32,
+            warning: total / 16,
+        }
+    }
+
+    /// Get the memory pressure level of `free` frames.
+    fn level(&self, free: usize) -> SystemEventKind {
+        if free < self.out_of_memory {
+            SystemEventKind::OutOfMemory
+        } else if free < self.critical {
+            SystemEventKind::MemoryPressureCritical
+        } else if free < self.warning {
+            SystemEventKind::MemoryPressureWarning
+        } else {
+            SystemEventKind::MemoryPressureNormal
+        }
+    }
+}
+
+struct MemoryPressure {
+    watermarks: MemoryWatermarks,
+    level: Option<SystemEventKind>,
+}
+
+lazy_static! {
+    static ref SYSTEM_EVENTS: [Arc<Event>; 4] =
+        [Event::new(), Event::new(), Event::new(), Event::new()];
+    static ref MEMORY_PRESSURE: Mutex<MemoryPressure> = Mutex::new(MemoryPressure {
+        watermarks: MemoryWatermarks::default_for(kernel_hal::frame_stats().total),
+        level: None,
+    });
+}
+
+/// Get the kernel-owned event of `kind`.
+///
+/// Exactly one of the events is signaled, the one of the current memory
+/// pressure level.
+pub fn system_event(kind: SystemEventKind) -> Arc<Event> {
+    update_memory_pressure();
+    SYSTEM_EVENTS[kind as usize - 1].clone()
+}
+
+/// Get the watermarks of free frames.
+pub fn memory_watermarks() -> MemoryWatermarks {
+    MEMORY_PRESSURE.lock().watermarks
+}
+
+/// Set the watermarks of free frames.
+pub fn set_memory_watermarks(watermarks: MemoryWatermarks) -> ZxResult {
+    if watermarks.out_of_memory > watermarks.critical || watermarks.critical > watermarks.warning {
+        return Err(ZxError::INVALID_ARGS);
+    }
+    MEMORY_PRESSURE.lock().watermarks = watermarks;
+    update_memory_pressure();
+    Ok(())
+}
+
+/// Check the free frames against the watermarks, and signal the system event
+/// of the new level if it changes.
+///
+/// It is called whenever frames are committed to or released from VMOs.
+pub(super) fn update_memory_pressure() {
+    let free = kernel_hal::frame_stats().free;
+    let mut pressure = MEMORY_PRESSURE.lock();
+    let level = pressure.watermarks.level(free);
+    if pressure.level == Some(level) {
+        return;
+    }
+    if let Some(old) = pressure.level.replace(level) {
+        SYSTEM_EVENTS[old as usize - 1].signal_clear(Signal::SIGNALED);
+        if level < old {
+            warn!("memory pressure rises to {:?}: {} pages free", level, free);
+        }
+    }
+    SYSTEM_EVENTS[level as usize - 1].signal_set(Signal::SIGNALED);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watermarks() {
+        kernel_hal_unix::init();
+        let default = memory_watermarks();
+        let normal = system_event(SystemEventKind::MemoryPressureNormal);
+        let warning = system_event(SystemEventKind::MemoryPressureWarning);
+        assert!(normal.signal().contains(Signal::SIGNALED));
+        assert!(!warning.signal().contains(Signal::SIGNALED));
+
+        // other tests may allocate frames concurrently, leave a margin
+        let free = kernel_hal::frame_stats().free;
+        let watermarks = MemoryWatermarks {
+            out_of_memory: 0,
+            critical: free / 2,
+            warning: free + 0x1000,
+        };
+        set_memory_watermarks(watermarks).unwrap();
+        assert!(!normal.signal().contains(Signal::SIGNALED));
+        assert!(warning.signal().contains(Signal::SIGNALED));
+        assert_eq!(
+            set_memory_watermarks(MemoryWatermarks {
+                critical: free + 0x2000,
+                ..watermarks
+            }),
+            Err(ZxError::INVALID_ARGS)
+        );
+
+        set_memory_watermarks(default).unwrap();
+        assert!(normal.signal().contains(Signal::SIGNALED));
+        assert!(!warning.signal().contains(Signal::SIGNALED));
+    }
+}
@@ -1,11 +1,14 @@
 use {
     super::*,
     crate::object::*,
+    crate::util::random::random_below,
     alloc::sync::Arc,
     alloc::vec,
     alloc::vec::Vec,
     bitflags::bitflags,
+    core::ops::Range,
     kernel_hal::{MMUFlags, PageTableTrait},
+    numeric_enum_macro::numeric_enum,
     spin::Mutex,
 };
 
@@ -42,6 +45,21 @@ bitflags! {
     }
 }
 
+numeric_enum! {
+    #[repr(u32)]
+    /// Operations used by `vmar_op_range`.
+    ///
+    /// They share the numbers with `VmoOpType`, except `MapRange`.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum VmarOpType {
+        Commit = 1,
+        Decommit = 2,
+        MapRange = 3,
+        AlwaysNeed = 11,
+        DontNeed = 12,
+    }
+}
+
 /// Virtual Memory Address Regions
 pub struct VmAddressRegion {
     flags: VmarFlags,
@@ -63,6 +81,20 @@ struct VmarInner {
     mappings: Vec<Arc<VmMapping>>,
 }
 
+impl VmarInner {
+    /// Unmap the mappings in `[begin, end)`, splitting those partially in it.
+    fn unmap_mappings(&mut self, begin: VirtAddr, end: VirtAddr) {
+        self.mappings.drain_filter(|map| map.within(begin, end));
+        let mut new_maps = Vec::new();
+        for map in self.mappings.iter() {
+            if map.overlap(begin, end) {
+                new_maps.extend(map.cut(begin, end));
+            }
+        }
+        self.mappings.extend(new_maps);
+    }
+}
+
 impl VmAddressRegion {
     /// Create a new root VMAR.
     pub fn new_root() -> Arc<Self> {
@@ -72,6 +104,7 @@ impl VmAddressRegion {
             let i = VMAR_ID.fetch_add(1, Ordering::SeqCst);
             (0x2_0000_0000 + 0x100_0000_0000 * i, 0x100_0000_0000)
         };
+        kernel_hal::user::extend_user_space(addr + size);
         Arc::new(VmAddressRegion {
             flags: VmarFlags::ROOT_FLAGS,
             base: KObjectBase::new(),
@@ -83,6 +116,20 @@ impl VmAddressRegion {
         })
     }
 
+    /// Create a root VMAR of the guest physical address space `[0, size)`,
+    /// whose pages are mapped by `page_table`.
+    pub fn new_guest(size: usize, page_table: Arc<Mutex<dyn PageTableTrait>>) -> Arc<Self> {
+        Arc::new(VmAddressRegion {
+            flags: VmarFlags::ROOT_FLAGS,
+            base: KObjectBase::new(),
+            addr: 0,
+            size,
+            parent: None,
+            page_table,
+            inner: Mutex::new(Some(VmarInner::default())),
+        })
+    }
+
     /// Create a kernel root VMAR.
     pub fn new_kernel() -> Arc<Self> {
         let kernel_vmar_base = KERNEL_ASPACE_BASE as usize;
@@ -182,22 +229,44 @@ impl VmAddressRegion {
         if !page_aligned(vmo_offset) || !page_aligned(len) || vmo_offset.overflowing_add(len).1 {
             return Err(ZxError::INVALID_ARGS);
         }
-        if !permissions.contains(flags & MMUFlags::RXW) {
+        if !permissions.contains(flags & MMUFlags::RXW) || !self.is_valid_mapping_flags(flags) {
             return Err(ZxError::ACCESS_DENIED);
         }
+        if flags.contains(MMUFlags::EXECUTE | MMUFlags::NO_EXECUTE) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        // the mapping may never be protected to be executable
+        let permissions = if flags.contains(MMUFlags::NO_EXECUTE) {
+            permissions - MMUFlags::EXECUTE
+        } else {
+            permissions
+        };
         if vmo_offset > vmo.len() || len > vmo.len() - vmo_offset {
             return Err(ZxError::INVALID_ARGS);
         }
-        // Simplify: overwrite == false && map_range == true
-        if overwrite || !map_range {
-            warn!("Simplify: overwrite == false && map_range == true");
+        // Simplify: map_range == true
+        if !map_range {
+            warn!("Simplify: map_range == true");
             return Err(ZxError::INVALID_ARGS);
         }
         let mut guard = self.inner.lock();
         let inner = guard.as_mut().ok_or(ZxError::BAD_STATE)?;
+        if overwrite {
+            // the mappings in the range are replaced, but not the sub-regions
+            let offset = vmar_offset.ok_or(ZxError::INVALID_ARGS)?;
+            let begin = self.addr.checked_add(offset).ok_or(ZxError::INVALID_ARGS)?;
+            let end = begin.checked_add(len).ok_or(ZxError::INVALID_ARGS)?;
+            if !page_aligned(offset) || end > self.end_addr() {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            if inner.children.iter().any(|vmar| vmar.overlap(begin, end)) {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            inner.unmap_mappings(begin, end);
+        }
         let offset = self.determine_offset(inner, vmar_offset, len, PAGE_SIZE)?;
         let addr = self.addr + offset;
-        let flags = flags | MMUFlags::from_bits_truncate(vmo.cache_policy() as u32 as usize);
+        let flags = flags.with_cache_policy(vmo.cache_policy());
         // align = 1K? 2K? 4K? 8K? ...
         if !self.test_map(inner, offset, len, PAGE_SIZE) {
             return Err(ZxError::NO_MEMORY);
@@ -220,7 +289,6 @@ impl VmAddressRegion {
     /// including `addr` and ending before exclusively at `addr + len`.
     /// Any sub-region that is in the range must be fully in the range
     /// (i.e. partial overlaps are an error).
-    /// NOT SUPPORT:
     /// If a mapping is only partially in the range, the mapping is split and the requested
     /// portion is unmapped.
     pub fn unmap(&self, addr: VirtAddr, len: usize) -> ZxResult {
@@ -240,19 +308,34 @@ impl VmAddressRegion {
             return Err(ZxError::INVALID_ARGS);
         }
 
-        if inner
-            .mappings
-            .iter()
-            .any(|map| map.partial_overlap(begin, end))
-        {
-            warn!("Simplify: Not support partial unmap.");
-            return Err(ZxError::INVALID_ARGS);
+        inner.unmap_mappings(begin, end);
+        for vmar in inner.children.drain_filter(|vmar| vmar.within(begin, end)) {
+            vmar.destroy_internal()?;
         }
-        inner.mappings.drain_filter(|map| map.within(begin, end));
+        Ok(())
+    }
 
+    /// Same as `unmap`, but the sub-regions partially in the range are not
+    /// an error, and the requested portion of them is unmapped recursively.
+    pub fn unmap_nested(&self, addr: VirtAddr, len: usize) -> ZxResult {
+        if !page_aligned(addr) || !page_aligned(len) || len == 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut guard = self.inner.lock();
+        let inner = guard.as_mut().ok_or(ZxError::BAD_STATE)?;
+        let begin = addr;
+        let end = addr.checked_add(len).ok_or(ZxError::INVALID_ARGS)?;
+        inner.unmap_mappings(begin, end);
         for vmar in inner.children.drain_filter(|vmar| vmar.within(begin, end)) {
             vmar.destroy_internal()?;
         }
+        for vmar in inner.children.iter() {
+            if vmar.overlap(begin, end) {
+                let child_begin = begin.max(vmar.addr);
+                let child_end = end.min(vmar.end_addr());
+                vmar.unmap_nested(child_begin, child_end - child_begin)?;
+            }
+        }
         Ok(())
     }
 
@@ -295,11 +378,129 @@ impl VmAddressRegion {
             .mappings
             .iter()
             .filter(|map| map.overlap(addr, end_addr))
-            .for_each(|map| {
+            .try_for_each(|map| {
                 let start_index = pages(addr.max(map.addr()) - map.addr());
                 let end_index = pages(end_addr.min(map.end_addr()) - map.addr());
-                map.protect(flags, start_index, end_index);
-            });
+                map.protect(flags, start_index, end_index)
+            })
+    }
+
+    /// Same as `protect`, but the range may overlap with sub-regions, whose
+    /// mappings in the range are protected too.
+    ///
+    /// Nothing is changed unless the range is fully populated by mappings
+    /// allowing `flags`.
+    pub fn protect_nested(&self, addr: usize, len: usize, flags: MMUFlags) -> ZxResult {
+        if !page_aligned(addr) || !page_aligned(len) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let end_addr = addr.checked_add(len).ok_or(ZxError::INVALID_ARGS)?;
+        let mut mappings = Vec::new();
+        self.collect_mappings(addr, end_addr, &mut mappings)?;
+        let length = mappings.iter().fold(0, |acc, map| {
+            acc + end_addr
+                .min(map.end_addr())
+                .saturating_sub(addr.max(map.addr()))
+        });
+        if length != len {
+            return Err(ZxError::NOT_FOUND);
+        }
+        if mappings
+            .iter()
+            .any(|map| !map.is_valid_mapping_flags(flags))
+        {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        mappings.iter().try_for_each(|map| {
+            let start_index = pages(addr.max(map.addr()) - map.addr());
+            let end_index = pages(end_addr.min(map.end_addr()) - map.addr());
+            map.protect(flags, start_index, end_index)
+        })
+    }
+
+    /// Perform an operation on the VMOs mapped into the range `[addr, addr + len)`.
+    ///
+    /// The range must be fully populated by mappings, which may belong to sub-regions.
+    pub fn op_range(&self, op: VmarOpType, addr: usize, len: usize) -> ZxResult {
+        if !page_aligned(addr) || len == 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let len = roundup_pages(len);
+        let end_addr = addr.checked_add(len).ok_or(ZxError::OUT_OF_RANGE)?;
+        if addr < self.addr || end_addr > self.end_addr() {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let mut mappings = Vec::new();
+        self.collect_mappings(addr, end_addr, &mut mappings)?;
+        let length = mappings.iter().fold(0, |acc, map| {
+            acc + end_addr
+                .min(map.end_addr())
+                .saturating_sub(addr.max(map.addr()))
+        });
+        if length != len {
+            return Err(ZxError::NOT_FOUND);
+        }
+        match op {
+            VmarOpType::Commit | VmarOpType::Decommit => {
+                if mappings
+                    .iter()
+                    .any(|map| !map.is_valid_mapping_flags(MMUFlags::WRITE))
+                {
+                    return Err(ZxError::ACCESS_DENIED);
+                }
+                for map in mappings.iter() {
+                    let begin = addr.max(map.addr());
+                    let end = end_addr.min(map.end_addr());
+                    let vmo_offset = {
+                        let inner = map.inner.lock();
+                        inner.vmo_offset + (begin - inner.addr)
+                    };
+                    if op == VmarOpType::Commit {
+                        map.vmo.commit(vmo_offset, end - begin)?;
+                    } else {
+                        map.vmo.decommit(vmo_offset, end - begin)?;
+                    }
+                }
+            }
+            // the pages of a mapping are all mapped in the page table when it
+            // is created, so the range is already populated
+            VmarOpType::MapRange => {
+                if mappings
+                    .iter()
+                    .any(|map| !map.is_valid_mapping_flags(MMUFlags::READ))
+                {
+                    return Err(ZxError::ACCESS_DENIED);
+                }
+            }
+            // only hints, nothing to do
+            VmarOpType::AlwaysNeed | VmarOpType::DontNeed => {}
+        }
+        Ok(())
+    }
+
+    /// Collect mappings overlapping `[begin, end)` in this region and all sub-regions.
+    fn collect_mappings(
+        &self,
+        begin: VirtAddr,
+        end: VirtAddr,
+        mappings: &mut Vec<Arc<VmMapping>>,
+    ) -> ZxResult {
+        let guard = self.inner.lock();
+        let inner = guard.as_ref().ok_or(ZxError::BAD_STATE)?;
+        mappings.extend(
+            inner
+                .mappings
+                .iter()
+                .filter(|map| map.overlap(begin, end))
+                .cloned(),
+        );
+        for vmar in inner
+            .children
+            .iter()
+            .filter(|vmar| vmar.overlap(begin, end))
+        {
+            vmar.collect_mappings(begin, end, mappings)?;
+        }
         Ok(())
     }
 
@@ -338,6 +539,77 @@ impl VmAddressRegion {
         Ok(())
     }
 
+    /// Create a new root VMAR of the same range, with the same sub-regions,
+    /// and snapshots of the mapped VMOs mapped at the same addresses.
+    ///
+    /// The snapshots are taken per mapping, so the new address space is
+    /// isolated from this one, as the one of a child process after `fork`.
+    pub fn fork(&self) -> ZxResult<Arc<Self>> {
+        let vmar = Arc::new(VmAddressRegion {
+            flags: self.flags,
+            base: KObjectBase::new(),
+            addr: self.addr,
+            size: self.size,
+            parent: None,
+            page_table: Arc::new(Mutex::new(kernel_hal::PageTable::new())),
+            inner: Mutex::new(Some(VmarInner::default())),
+        });
+        vmar.fork_from(self)?;
+        Ok(vmar)
+    }
+
+    /// Copy the sub-regions and mappings of `src`, which has the same range,
+    /// into this empty region.
+    fn fork_from(self: &Arc<Self>, src: &Self) -> ZxResult {
+        let guard = src.inner.lock();
+        let src_inner = guard.as_ref().ok_or(ZxError::BAD_STATE)?;
+        for child in src_inner.children.iter() {
+            let vmar =
+                self.allocate_at(child.addr - self.addr, child.size, child.flags, PAGE_SIZE)?;
+            vmar.fork_from(child)?;
+        }
+        let mut mappings = Vec::new();
+        for mapping in src_inner.mappings.iter() {
+            mappings.push(mapping.fork(self.page_table.clone())?);
+        }
+        let mut guard = self.inner.lock();
+        let inner = guard.as_mut().ok_or(ZxError::BAD_STATE)?;
+        inner.mappings.extend(mappings);
+        Ok(())
+    }
+
+    /// Get the innermost region containing the whole range `[begin, end)`,
+    /// which is this region if no sub-region contains it.
+    pub fn find_region(self: &Arc<Self>, begin: VirtAddr, end: VirtAddr) -> Option<Arc<Self>> {
+        if begin < self.addr || end > self.end_addr() {
+            return None;
+        }
+        let child = {
+            let guard = self.inner.lock();
+            let inner = guard.as_ref()?;
+            inner
+                .children
+                .iter()
+                .find(|vmar| vmar.within_region(begin, end))
+                .cloned()
+        };
+        match child {
+            Some(child) => child.find_region(begin, end),
+            None => Some(self.clone()),
+        }
+    }
+
+    /// Get the direct sub-region starting at `addr`.
+    pub fn find_child(&self, addr: VirtAddr) -> Option<Arc<Self>> {
+        let guard = self.inner.lock();
+        let inner = guard.as_ref()?;
+        inner
+            .children
+            .iter()
+            .find(|vmar| vmar.addr == addr)
+            .cloned()
+    }
+
     /// Get physical address of the underlying page table.
     pub fn table_phys(&self) -> PhysAddr {
         self.page_table.lock().table_phys()
@@ -348,6 +620,11 @@ impl VmAddressRegion {
         self.addr
     }
 
+    /// Get the size of this VMAR.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     /// Whether this VMAR is dead.
     pub fn is_dead(&self) -> bool {
         self.inner.lock().is_none()
@@ -404,6 +681,9 @@ impl VmAddressRegion {
     }
 
     /// Find a free area with `len`.
+    ///
+    /// The area is chosen randomly from all free areas, unless this region is
+    /// `COMPACT`, where the lowest one after `offset_hint` is chosen.
     fn find_free_area(
         &self,
         inner: &VmarInner,
@@ -411,15 +691,51 @@ impl VmAddressRegion {
         len: usize,
         align: usize,
     ) -> Option<usize> {
-        // TODO: randomize
         debug_assert!(check_aligned(offset_hint, align));
         debug_assert!(check_aligned(len, align));
-        // brute force:
-        // try each area's end address as the start
-        core::iter::once(offset_hint)
-            .chain(inner.children.iter().map(|map| map.end_addr() - self.addr))
-            .chain(inner.mappings.iter().map(|map| map.end_addr() - self.addr))
-            .find(|&offset| self.test_map(inner, offset, len, align))
+        if self.flags.contains(VmarFlags::COMPACT) {
+            // brute force:
+            // try each area's end address as the start
+            return core::iter::once(offset_hint)
+                .chain(inner.children.iter().map(|map| map.end_addr() - self.addr))
+                .chain(inner.mappings.iter().map(|map| map.end_addr() - self.addr))
+                .find(|&offset| self.test_map(inner, offset, len, align));
+        }
+        let mut used: Vec<(usize, usize)> = inner
+            .children
+            .iter()
+            .map(|vmar| (vmar.addr, vmar.end_addr()))
+            .chain(
+                inner
+                    .mappings
+                    .iter()
+                    .map(|map| (map.addr(), map.end_addr())),
+            )
+            .map(|(begin, end)| (begin - self.addr, end - self.addr))
+            .collect();
+        used.sort_unstable();
+        // the first candidate offset and the number of candidates in each gap
+        let mut gaps = Vec::new();
+        let mut free_begin = 0;
+        for (begin, end) in used.into_iter().chain(Some((self.size, self.size))) {
+            let first = (free_begin + align - 1) / align * align;
+            if first + len <= begin {
+                gaps.push((first, (begin - len - first) / align + 1));
+            }
+            free_begin = free_begin.max(end);
+        }
+        let count: usize = gaps.iter().map(|&(_, n)| n).sum();
+        if count == 0 {
+            return None;
+        }
+        let mut index = random_below(count);
+        for (first, n) in gaps {
+            if index < n {
+                return Some(first + index * align);
+            }
+            index -= n;
+        }
+        unreachable!()
     }
 
     fn end_addr(&self) -> VirtAddr {
@@ -438,10 +754,23 @@ impl VmAddressRegion {
         self.overlap(begin, end) && !self.within(begin, end)
     }
 
+    /// Whether `[begin, end)` is in this region.
+    fn within_region(&self, begin: VirtAddr, end: VirtAddr) -> bool {
+        self.addr <= begin && end <= self.end_addr()
+    }
+
     fn contains(&self, vaddr: VirtAddr) -> bool {
         self.addr <= vaddr && vaddr < self.end_addr()
     }
 
+    /// Check whether mappings with `flags` are allowed in this region.
+    fn is_valid_mapping_flags(&self, flags: MMUFlags) -> bool {
+        (!flags.contains(MMUFlags::READ) || self.flags.contains(VmarFlags::CAN_MAP_READ))
+            && (!flags.contains(MMUFlags::WRITE) || self.flags.contains(VmarFlags::CAN_MAP_WRITE))
+            && (!flags.contains(MMUFlags::EXECUTE)
+                || self.flags.contains(VmarFlags::CAN_MAP_EXECUTE))
+    }
+
     /// Get information of this VmAddressRegion
     pub fn get_info(&self) -> VmarInfo {
         // pub fn get_info(&self, va: usize) -> VmarInfo {
@@ -457,6 +786,46 @@ impl VmAddressRegion {
         self.flags
     }
 
+    /// Get the VMOs mapped in this VMAR and its children, with the address
+    /// of each mapping, in the order of the addresses.
+    pub fn mapped_vmos(&self) -> Vec<(VirtAddr, Arc<VmObject>)> {
+        let mut vmos = Vec::new();
+        if let Some(inner) = self.inner.lock().as_ref() {
+            for map in inner.mappings.iter() {
+                vmos.push((map.inner.lock().addr, map.vmo.clone()));
+            }
+            for child in inner.children.iter() {
+                vmos.extend(child.mapped_vmos());
+            }
+        }
+        vmos.sort_by_key(|&(addr, _)| addr);
+        vmos
+    }
+
+    /// Get the flags of the page mapped at `vaddr` in this VMAR or its
+    /// children, or `NOT_FOUND` if the page is not mapped.
+    pub fn get_vaddr_flags(&self, vaddr: VirtAddr) -> ZxResult<MMUFlags> {
+        let guard = self.inner.lock();
+        let inner = guard.as_ref().ok_or(ZxError::BAD_STATE)?;
+        if let Some(child) = inner.children.iter().find(|vmar| vmar.contains(vaddr)) {
+            let child = child.clone();
+            drop(guard);
+            return child.get_vaddr_flags(vaddr);
+        }
+        let mapping = inner
+            .mappings
+            .iter()
+            .find(|map| map.contains(vaddr))
+            .ok_or(ZxError::NOT_FOUND)?;
+        let flags = mapping.get_flags(vaddr)?;
+        // the pages decommitted are not mapped until they are committed
+        self.page_table
+            .lock()
+            .query(vaddr)
+            .map_err(|_| ZxError::NOT_FOUND)?;
+        Ok(flags)
+    }
+
     #[cfg(test)]
     fn count(&self) -> usize {
         let mut guard = self.inner.lock();
@@ -562,14 +931,149 @@ impl VmMapping {
         })
     }
 
+    /// Map the page `vmo_page` of the VMO to `paddr` again, if it is in this
+    /// mapping, after its frame is replaced by a copy or committed again.
+    pub(super) fn remap_page(&self, vmo_page: usize, paddr: PhysAddr) {
+        let inner = self.inner.lock();
+        let start = inner.vmo_offset / PAGE_SIZE;
+        if vmo_page < start || vmo_page >= start + inner.size / PAGE_SIZE {
+            return;
+        }
+        let i = vmo_page - start;
+        let vaddr = inner.addr + i * PAGE_SIZE;
+        let mut page_table = self.page_table.lock();
+        match page_table.query(vaddr) {
+            Ok(mapped) if mapped == paddr => return,
+            Ok(_) => page_table.unmap(vaddr).expect("failed to unmap"),
+            // unmapped by a decommit
+            Err(_) => {}
+        }
+        page_table
+            .map(vaddr, paddr, inner.flags[i])
+            .expect("failed to map");
+        kernel_hal::tlb_flush(vaddr..vaddr + PAGE_SIZE, page_table.table_phys());
+    }
+
+    /// Unmap the pages of the VMO in `vmo_pages` mapped here.
+    pub(super) fn unmap_vmo_pages(&self, vmo_pages: Range<usize>) {
+        let inner = self.inner.lock();
+        let start = inner.vmo_offset / PAGE_SIZE;
+        let begin = vmo_pages.start.max(start) - start;
+        let end = vmo_pages.end.min(start + inner.size / PAGE_SIZE) - start;
+        if begin >= end {
+            return;
+        }
+        let mut page_table = self.page_table.lock();
+        for i in begin..end {
+            let vaddr = inner.addr + i * PAGE_SIZE;
+            if page_table.query(vaddr).is_ok() {
+                page_table.unmap(vaddr).expect("failed to unmap");
+            }
+        }
+        kernel_hal::tlb_flush(
+            inner.addr + begin * PAGE_SIZE..inner.addr + end * PAGE_SIZE,
+            page_table.table_phys(),
+        );
+    }
+
+    /// Get the pages of the VMO mapped writable here.
+    pub(super) fn writable_vmo_pages(&self) -> Vec<usize> {
+        let inner = self.inner.lock();
+        let start = inner.vmo_offset / PAGE_SIZE;
+        (0..inner.flags.len())
+            .filter(|&i| inner.flags[i].contains(MMUFlags::WRITE))
+            .map(|i| start + i)
+            .collect()
+    }
+
+    /// Create a mapping of the same range and flags in `page_table`, with a
+    /// snapshot of the mapped part of the VMO.
+    ///
+    /// The pages are shared with the snapshot copy-on-write, except the ones
+    /// mapped writable, which are copied as they can not be write-protected.
+    fn fork(&self, page_table: Arc<Mutex<dyn PageTableTrait>>) -> ZxResult<Arc<Self>> {
+        let inner = self.inner.lock().clone();
+        let vmo = self.vmo.create_child(false, inner.vmo_offset, inner.size)?;
+        let mapping = Arc::new(VmMapping {
+            permissions: self.permissions,
+            vmo: vmo.clone(),
+            page_table,
+            inner: Mutex::new(VmMappingInner {
+                vmo_offset: 0,
+                ..inner
+            }),
+        });
+        vmo.append_mapping(Arc::downgrade(&mapping));
+        mapping.map()?;
+        Ok(mapping)
+    }
+
     fn unmap(&self) {
         let inner = self.inner.lock();
         let pages = inner.size / PAGE_SIZE;
         // TODO inner.vmo_offset unused?
-        self.page_table
-            .lock()
+        let mut page_table = self.page_table.lock();
+        page_table
             .unmap_cont(inner.addr, pages)
-            .expect("failed to unmap")
+            .expect("failed to unmap");
+        kernel_hal::tlb_flush(inner.addr..inner.end_addr(), page_table.table_phys());
+    }
+
+    /// Cut and unmap the part in `[begin, end)` of the mapping, which must be
+    /// partially in the range.
+    ///
+    /// Return the tail as a new mapping if the mapping is split into two.
+    fn cut(&self, begin: VirtAddr, end: VirtAddr) -> Option<Arc<Self>> {
+        let mut inner = self.inner.lock();
+        let mut page_table = self.page_table.lock();
+        let vmtoken = page_table.table_phys();
+        if begin <= inner.addr {
+            // cut the head
+            let len = end - inner.addr;
+            page_table
+                .unmap_cont(inner.addr, pages(len))
+                .expect("failed to unmap");
+            kernel_hal::tlb_flush(inner.addr..end, vmtoken);
+            inner.flags.drain(..pages(len));
+            inner.addr = end;
+            inner.size -= len;
+            inner.vmo_offset += len;
+            None
+        } else if end >= inner.end_addr() {
+            // cut the tail
+            let len = inner.end_addr() - begin;
+            page_table
+                .unmap_cont(begin, pages(len))
+                .expect("failed to unmap");
+            kernel_hal::tlb_flush(begin..inner.end_addr(), vmtoken);
+            let addr = inner.addr;
+            inner.flags.truncate(pages(begin - addr));
+            inner.size -= len;
+            None
+        } else {
+            // cut the middle, the tail becomes a new mapping
+            page_table
+                .unmap_cont(begin, pages(end - begin))
+                .expect("failed to unmap");
+            kernel_hal::tlb_flush(begin..end, vmtoken);
+            let addr = inner.addr;
+            let tail = VmMappingInner {
+                flags: inner.flags.split_off(pages(end - addr)),
+                addr: end,
+                size: inner.end_addr() - end,
+                vmo_offset: inner.vmo_offset + (end - addr),
+            };
+            inner.flags.truncate(pages(begin - addr));
+            inner.size = begin - addr;
+            let mapping = Arc::new(VmMapping {
+                permissions: self.permissions,
+                vmo: self.vmo.clone(),
+                page_table: self.page_table.clone(),
+                inner: Mutex::new(tail),
+            });
+            self.vmo.append_mapping(Arc::downgrade(&mapping));
+            Some(mapping)
+        }
     }
 
     fn overlap(&self, begin: VirtAddr, end: VirtAddr) -> bool {
@@ -595,15 +1099,28 @@ impl VmMapping {
         self.permissions.contains(flags & MMUFlags::RXW)
     }
 
-    fn protect(&self, flags: MMUFlags, start_index: usize, end_index: usize) {
+    fn protect(&self, flags: MMUFlags, start_index: usize, end_index: usize) -> ZxResult {
+        if flags.contains(MMUFlags::WRITE) {
+            // the pages shared copy-on-write are copied before being writable
+            let vmo_offset = self.inner.lock().vmo_offset / PAGE_SIZE;
+            for i in start_index..end_index {
+                self.vmo.commit_page(vmo_offset + i, MMUFlags::WRITE)?;
+            }
+        }
         let mut inner = self.inner.lock();
         let mut pg_table = self.page_table.lock();
         for i in start_index..end_index {
             inner.flags[i] = (inner.flags[i] & !MMUFlags::RXW) | (flags & MMUFlags::RXW);
-            pg_table
-                .protect(inner.addr + i * PAGE_SIZE, inner.flags[i])
-                .unwrap();
+            let vaddr = inner.addr + i * PAGE_SIZE;
+            // the pages decommitted are mapped with the new flags later
+            if pg_table.query(vaddr).is_ok() {
+                pg_table.protect(vaddr, inner.flags[i]).unwrap();
+            }
         }
+        let begin = inner.addr + start_index * PAGE_SIZE;
+        let end = inner.addr + end_index * PAGE_SIZE;
+        kernel_hal::tlb_flush(begin..end, pg_table.table_phys());
+        Ok(())
     }
 
     fn size(&self) -> usize {
@@ -658,6 +1175,7 @@ mod tests {
 
     #[test]
     fn create_child() {
+        kernel_hal_unix::init();
         let root_vmar = VmAddressRegion::new_root();
         let child = root_vmar
             .allocate_at(0, 0x2000, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)
@@ -696,6 +1214,7 @@ mod tests {
     #[test]
     #[allow(unsafe_code)]
     fn map() {
+        kernel_hal_unix::init();
         let vmar = VmAddressRegion::new_root();
         let vmo = VmObject::new_paged(4);
         let flags = MMUFlags::READ | MMUFlags::WRITE;
@@ -718,12 +1237,182 @@ mod tests {
             Err(ZxError::INVALID_ARGS)
         );
         vmar.map_at(0, vmo.clone(), 0, 0x4000, flags).unwrap();
-        vmar.map_at(0x12000, vmo.clone(), 0x2000, 0x1000, flags)
-            .unwrap();
-        unsafe {
+        vmar.map_at(0x12000, vmo, 0x2000, 0x1000, flags).unwrap();
+        // the pages are only accessible with the address space active
+        kernel_hal_unix::with_address_space(vmar.table_phys(), || unsafe {
             ((vmar.addr() + 0x2000) as *mut usize).write(MAGIC);
             assert_eq!(((vmar.addr() + 0x12000) as *const usize).read(), MAGIC);
+        });
+    }
+
+    #[test]
+    fn op_range() {
+        kernel_hal_unix::init();
+        let vmar = VmAddressRegion::new_root();
+        let child = vmar
+            .allocate_at(0x2000, 0x2000, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)
+            .unwrap();
+        let vmo = VmObject::new_paged(2);
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let addr = vmar.map_at(0, vmo.clone(), 0, 0x2000, flags).unwrap();
+        child.map_at(0, vmo.clone(), 0, 0x1000, flags).unwrap();
+
+        vmo.test_write(1, 1);
+        vmar.op_range(VmarOpType::Commit, addr, 0x3000).unwrap();
+        vmar.op_range(VmarOpType::Decommit, addr + 0x1000, 0x1000)
+            .unwrap();
+        assert_eq!(vmo.test_read(1), 0);
+
+        // range not fully mapped
+        assert_eq!(
+            vmar.op_range(VmarOpType::Commit, addr, 0x4000),
+            Err(ZxError::NOT_FOUND)
+        );
+        assert_eq!(
+            vmar.op_range(VmarOpType::Commit, addr + 1, 0x1000),
+            Err(ZxError::INVALID_ARGS)
+        );
+        vmar.op_range(VmarOpType::MapRange, addr, 0x3000).unwrap();
+        assert_eq!(
+            vmar.op_range(VmarOpType::MapRange, addr, 0x4000),
+            Err(ZxError::NOT_FOUND)
+        );
+
+        // commit requires write permission
+        let ro_vmar = child
+            .allocate_at(0x1000, 0x1000, VmarFlags::CAN_MAP_READ, PAGE_SIZE)
+            .unwrap();
+        let ro_addr = ro_vmar
+            .map_ext(
+                None,
+                vmo,
+                0,
+                0x1000,
+                MMUFlags::READ,
+                MMUFlags::READ,
+                false,
+                true,
+            )
+            .unwrap();
+        assert_eq!(
+            vmar.op_range(VmarOpType::Commit, ro_addr, 0x1000),
+            Err(ZxError::ACCESS_DENIED)
+        );
+    }
+
+    #[test]
+    fn decommit_mapped() {
+        kernel_hal_unix::init();
+        let vmar = VmAddressRegion::new_root();
+        let vmo = VmObject::new_paged(2);
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let addr = vmar.map_at(0, vmo.clone(), 0, 0x2000, flags).unwrap();
+        vmo.test_write(1, 1);
+
+        // the pages are unmapped and their frames are freed
+        vmo.decommit(0x1000, 0x1000).unwrap();
+        assert_eq!(vmo.committed_pages_in_range(0, 2), 1);
+        assert!(vmar.page_table.lock().query(addr + 0x1000).is_err());
+        assert_eq!(vmar.get_vaddr_flags(addr + 0x1000), Err(ZxError::NOT_FOUND));
+        assert_eq!(vmar.get_vaddr_flags(addr), Ok(flags));
+
+        // and mapped again once they are committed
+        vmo.commit(0x1000, 0x1000).unwrap();
+        let paddr = vmo.commit_page(1, MMUFlags::READ).unwrap();
+        assert_eq!(vmar.page_table.lock().query(addr + 0x1000).unwrap(), paddr);
+        assert_eq!(vmo.test_read(1), 0);
+    }
+
+    #[test]
+    fn allocate_random() {
+        kernel_hal_unix::init();
+        let root = VmAddressRegion::new_root();
+        let compact = root
+            .allocate(None, 0x10000, VmarFlags::COMPACT, PAGE_SIZE)
+            .unwrap();
+        for i in 0..4 {
+            let child = compact
+                .allocate(None, 0x1000, VmarFlags::empty(), PAGE_SIZE)
+                .unwrap();
+            assert_eq!(child.addr(), compact.addr() + i * 0x1000);
         }
+        let vmar = root
+            .allocate(None, 0x10000, VmarFlags::empty(), PAGE_SIZE)
+            .unwrap();
+        let mut addrs: Vec<_> = (0..16)
+            .map(|_| {
+                vmar.allocate(None, 0x1000, VmarFlags::empty(), PAGE_SIZE)
+                    .unwrap()
+                    .addr()
+            })
+            .collect();
+        addrs.sort_unstable();
+        let expected: Vec<_> = (0..16).map(|i| vmar.addr() + i * 0x1000).collect();
+        assert_eq!(addrs, expected);
+        assert_eq!(
+            vmar.allocate(None, 0x1000, VmarFlags::empty(), PAGE_SIZE)
+                .err(),
+            Some(ZxError::NO_MEMORY)
+        );
+    }
+
+    #[test]
+    fn map_execute() {
+        kernel_hal_unix::init();
+        let root = VmAddressRegion::new_root();
+        let vmar = root
+            .allocate(None, 0x2000, VmarFlags::CAN_MAP_READ, PAGE_SIZE)
+            .unwrap();
+        let vmo = VmObject::new_paged(1);
+        assert_eq!(
+            vmar.map(
+                None,
+                vmo.clone(),
+                0,
+                0x1000,
+                MMUFlags::READ | MMUFlags::EXECUTE
+            ),
+            Err(ZxError::ACCESS_DENIED)
+        );
+        vmar.map(None, vmo.clone(), 0, 0x1000, MMUFlags::READ)
+            .unwrap();
+        // permissions of the mapping limit its flags
+        assert_eq!(
+            root.map_ext(
+                None,
+                vmo,
+                0,
+                0x1000,
+                MMUFlags::READ,
+                MMUFlags::READ | MMUFlags::EXECUTE,
+                false,
+                true
+            ),
+            Err(ZxError::ACCESS_DENIED)
+        );
+    }
+
+    #[test]
+    fn no_execute() {
+        kernel_hal_unix::init();
+        let vmar = VmAddressRegion::new_root();
+        let vmo = VmObject::new_paged(2);
+        let nx = MMUFlags::READ | MMUFlags::NO_EXECUTE;
+        assert_eq!(
+            vmar.map(None, vmo.clone(), 0, 0x2000, nx | MMUFlags::EXECUTE),
+            Err(ZxError::INVALID_ARGS)
+        );
+
+        let addr = vmar.map(None, vmo.clone(), 0, 0x1000, nx).unwrap();
+        let mapping = vmar.inner.lock().as_ref().unwrap().mappings[0].clone();
+        assert!(!mapping.get_flags(addr).unwrap().is_executable());
+        assert_eq!(
+            vmar.protect(addr, 0x1000, MMUFlags::READ | MMUFlags::EXECUTE),
+            Err(ZxError::ACCESS_DENIED)
+        );
+        let addr = vmar.map(None, vmo, 0x1000, 0x1000, MMUFlags::READ).unwrap();
+        vmar.protect(addr, 0x1000, MMUFlags::READ | MMUFlags::EXECUTE)
+            .unwrap();
     }
 
     /// ```text
@@ -770,6 +1459,7 @@ mod tests {
 
     #[test]
     fn unmap_vmar() {
+        kernel_hal_unix::init();
         let s = Sample::new();
         let base = s.root.addr();
         s.child1.unmap(base, 0x1000).unwrap();
@@ -792,6 +1482,7 @@ mod tests {
 
     #[test]
     fn destroy() {
+        kernel_hal_unix::init();
         let s = Sample::new();
         s.child1.destroy().unwrap();
         assert!(s.child1.is_dead());
@@ -807,6 +1498,7 @@ mod tests {
 
     #[test]
     fn unmap_mapping() {
+        kernel_hal_unix::init();
         //   +--------+--------+--------+--------+--------+
         // 1 [--------------------------|xxxxxxxx|--------]
         // 2 [xxxxxxxx|-----------------]
@@ -825,24 +1517,197 @@ mod tests {
         assert_eq!(vmar.count(), 1);
         assert_eq!(vmar.used_size(), 0x5000);
 
-        // // 1. unmap middle.
-        // vmar.unmap(base + 0x3000, 0x1000).unwrap();
-        // assert_eq!(vmar.count(), 2);
-        // assert_eq!(vmar.used_size(), 0x4000);
-
-        // // 2. unmap prefix.
-        // vmar.unmap(base, 0x1000).unwrap();
-        // assert_eq!(vmar.count(), 2);
-        // assert_eq!(vmar.used_size(), 0x3000);
-
-        // // 3. unmap postfix.
-        // vmar.unmap(base + 0x2000, 0x1000).unwrap();
-        // assert_eq!(vmar.count(), 2);
-        // assert_eq!(vmar.used_size(), 0x2000);
+        // 1. unmap middle.
+        vmar.unmap(base + 0x3000, 0x1000).unwrap();
+        assert_eq!(vmar.count(), 2);
+        assert_eq!(vmar.used_size(), 0x4000);
+
+        // 2. unmap prefix.
+        vmar.unmap(base, 0x1000).unwrap();
+        assert_eq!(vmar.count(), 2);
+        assert_eq!(vmar.used_size(), 0x3000);
+
+        // 3. unmap postfix.
+        vmar.unmap(base + 0x2000, 0x1000).unwrap();
+        assert_eq!(vmar.count(), 2);
+        assert_eq!(vmar.used_size(), 0x2000);
+
+        // the remaining pages keep their offsets in the VMO and their flags
+        let mut maps: Vec<_> = {
+            let guard = vmar.inner.lock();
+            let inner = guard.as_ref().unwrap();
+            inner
+                .mappings
+                .iter()
+                .map(|map| {
+                    let inner = map.inner.lock();
+                    (
+                        inner.addr - base,
+                        inner.size,
+                        inner.vmo_offset,
+                        inner.flags.clone(),
+                    )
+                })
+                .collect()
+        };
+        maps.sort_by_key(|map| map.0);
+        assert_eq!(
+            maps,
+            [
+                (0x1000, 0x1000, 0x1000, vec![flags]),
+                (0x4000, 0x1000, 0x4000, vec![flags]),
+            ]
+        );
 
         // 4. unmap all.
         vmar.unmap(base, 0x5000).unwrap();
         assert_eq!(vmar.count(), 0);
         assert_eq!(vmar.used_size(), 0x0);
     }
+
+    #[test]
+    fn nested_and_overwrite() {
+        kernel_hal_unix::init();
+        let vmar = VmAddressRegion::new_root();
+        let base = vmar.addr();
+        let child = vmar
+            .allocate_at(0x2000, 0x4000, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)
+            .unwrap();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        vmar.map_at(0, VmObject::new_paged(2), 0, 0x2000, flags)
+            .unwrap();
+        child
+            .map_at(0, VmObject::new_paged(4), 0, 0x4000, flags)
+            .unwrap();
+
+        // the range overlaps with the sub-region
+        assert_eq!(
+            vmar.protect(base + 0x1000, 0x2000, MMUFlags::READ),
+            Err(ZxError::INVALID_ARGS)
+        );
+        vmar.protect_nested(base + 0x1000, 0x2000, MMUFlags::READ)
+            .unwrap();
+        assert_eq!(vmar.get_vaddr_flags(base + 0x1000), Ok(MMUFlags::READ));
+        assert_eq!(vmar.get_vaddr_flags(base + 0x2000), Ok(MMUFlags::READ));
+        assert_eq!(vmar.get_vaddr_flags(base + 0x3000), Ok(flags));
+        // nothing is changed if the range is not fully mapped
+        assert_eq!(
+            vmar.protect_nested(base + 0x5000, 0x2000, MMUFlags::READ),
+            Err(ZxError::NOT_FOUND)
+        );
+        assert_eq!(vmar.get_vaddr_flags(base + 0x5000), Ok(flags));
+
+        assert_eq!(
+            vmar.unmap(base + 0x1000, 0x2000),
+            Err(ZxError::INVALID_ARGS)
+        );
+        vmar.unmap_nested(base + 0x1000, 0x2000).unwrap();
+        assert_eq!(vmar.count(), 2);
+        assert_eq!(child.count(), 1);
+        assert_eq!(child.used_size(), 0x3000);
+
+        // the mappings are replaced only if the new one is valid
+        let vmo = VmObject::new_paged(1);
+        assert_eq!(
+            child.map_ext(
+                Some(0x1000),
+                vmo.clone(),
+                0,
+                0x1000,
+                MMUFlags::READ,
+                flags,
+                true,
+                true
+            ),
+            Err(ZxError::ACCESS_DENIED)
+        );
+        assert_eq!(child.used_size(), 0x3000);
+        child
+            .map_ext(
+                Some(0x1000),
+                vmo,
+                0,
+                0x1000,
+                MMUFlags::RXW,
+                flags,
+                true,
+                true,
+            )
+            .unwrap();
+        assert_eq!(child.count(), 2);
+        assert_eq!(child.used_size(), 0x3000);
+        // the sub-regions are never replaced
+        assert_eq!(
+            vmar.map_ext(
+                Some(0x2000),
+                VmObject::new_paged(1),
+                0,
+                0x1000,
+                MMUFlags::RXW,
+                flags,
+                true,
+                true
+            ),
+            Err(ZxError::INVALID_ARGS)
+        );
+        assert!(Arc::ptr_eq(
+            &vmar.find_region(base + 0x3000, base + 0x5000).unwrap(),
+            &child
+        ));
+        assert!(Arc::ptr_eq(
+            &vmar.find_region(base, base + 0x3000).unwrap(),
+            &vmar
+        ));
+    }
+
+    #[test]
+    fn fork() {
+        kernel_hal_unix::init();
+        let vmar = VmAddressRegion::new_root();
+        let child = vmar
+            .allocate_at(0x10000, 0x4000, VmarFlags::CAN_MAP_RXW, PAGE_SIZE)
+            .unwrap();
+        let vmo = VmObject::new_paged(4);
+        vmo.write(0x1000, &[1, 2, 3, 4]).unwrap();
+        let flags = MMUFlags::READ | MMUFlags::WRITE;
+        let addr = child
+            .map_at(0x1000, vmo.clone(), 0x1000, 0x2000, flags)
+            .unwrap();
+        child
+            .protect(addr + 0x1000, 0x1000, MMUFlags::READ)
+            .unwrap();
+
+        let new_vmar = vmar.fork().unwrap();
+        assert_eq!(new_vmar.addr(), vmar.addr());
+        let new_child = new_vmar.find_child(child.addr()).unwrap();
+        assert_eq!(new_child.get_flags(), child.get_flags());
+        assert_eq!(new_child.count(), 1);
+        let mapping = new_child.inner.lock().as_ref().unwrap().mappings[0].clone();
+        assert_eq!((mapping.addr(), mapping.size()), (addr, 0x2000));
+        assert_eq!(mapping.get_flags(addr), Ok(flags));
+        assert_eq!(mapping.get_flags(addr + 0x1000), Ok(MMUFlags::READ));
+
+        // the writable page is copied, and the copy is isolated
+        let mut buf = [0u8; 4];
+        mapping.vmo.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        vmo.write(0x1000, &[5]).unwrap();
+        mapping.vmo.read(0, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        mapping.vmo.write(1, &[6]).unwrap();
+        vmo.read(0x1000, &mut buf).unwrap();
+        assert_eq!(buf, [5, 2, 3, 4]);
+
+        // the page mapped read-only is shared until it is made writable
+        let shared = vmo.commit_page(2, MMUFlags::READ).unwrap();
+        assert_eq!(mapping.vmo.commit_page(1, MMUFlags::READ), Ok(shared));
+        new_child.protect(addr + 0x1000, 0x1000, flags).unwrap();
+        let copy = mapping.vmo.commit_page(1, MMUFlags::READ).unwrap();
+        assert_ne!(copy, shared);
+        assert_eq!(vmo.commit_page(2, MMUFlags::READ), Ok(shared));
+        assert_eq!(
+            new_vmar.page_table.lock().query(addr + 0x1000).unwrap(),
+            copy
+        );
+    }
 }
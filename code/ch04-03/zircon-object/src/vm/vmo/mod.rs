@@ -9,6 +9,7 @@ use {
     bitflags::bitflags,
     core::ops::Deref,
     kernel_hal::{CachePolicy, MMUFlags},
+    numeric_enum_macro::numeric_enum,
     spin::Mutex,
 };
 
@@ -25,6 +26,30 @@ pub trait VMObjectTrait: Sync + Send {
     /// Write memory from `buf` to VMO at `offset`.
     fn write(&self, offset: usize, buf: &[u8]) -> ZxResult;
 
+    /// Read memory from VMO at `offset` into several buffers (scatter).
+    ///
+    /// The buffers are filled in order as if they were one contiguous buffer.
+    fn read_v(&self, offset: usize, bufs: &mut [&mut [u8]]) -> ZxResult {
+        let mut offset = offset;
+        for buf in bufs.iter_mut() {
+            self.read(offset, buf)?;
+            offset += buf.len();
+        }
+        Ok(())
+    }
+
+    /// Write memory from several buffers to VMO at `offset` (gather).
+    ///
+    /// The buffers are written in order as if they were one contiguous buffer.
+    fn write_v(&self, offset: usize, bufs: &[&[u8]]) -> ZxResult {
+        let mut offset = offset;
+        for buf in bufs.iter() {
+            self.write(offset, buf)?;
+            offset += buf.len();
+        }
+        Ok(())
+    }
+
     /// Resets the range of bytes in the VMO from `offset` to `offset+len` to 0.
     fn zero(&self, offset: usize, len: usize) -> ZxResult;
 
@@ -91,6 +116,27 @@ pub trait VMObjectTrait: Sync + Send {
     fn is_paged(&self) -> bool {
         false
     }
+
+    /// Mark committed pages in the range as dirty. Only for pager-backed VMOs.
+    fn mark_dirty(&self, _offset: usize, _len: usize) -> ZxResult {
+        Err(ZxError::NOT_SUPPORTED)
+    }
+
+    /// Start writing back dirty pages in the range. Only for pager-backed VMOs.
+    fn writeback_begin(&self, _offset: usize, _len: usize) -> ZxResult {
+        Err(ZxError::NOT_SUPPORTED)
+    }
+
+    /// Finish writing back pages in the range. Pages which are not
+    /// dirtied again since `writeback_begin` become clean.
+    fn writeback_end(&self, _offset: usize, _len: usize) -> ZxResult {
+        Err(ZxError::NOT_SUPPORTED)
+    }
+
+    /// Get the dirty ranges in the range. Only for pager-backed VMOs.
+    fn dirty_ranges(&self, _offset: usize, _len: usize) -> ZxResult<Vec<VmoDirtyRange>> {
+        Err(ZxError::NOT_SUPPORTED)
+    }
 }
 
 /// Virtual memory containers
@@ -114,6 +160,23 @@ struct VmObjectInner {
     children: Vec<Weak<VmObject>>,
     mapping_count: usize,
     content_size: usize,
+    vdso_variant: Option<VdsoVariant>,
+}
+
+/// Variants of the vDSO, which permit different sets of syscalls.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VdsoVariant {
+    /// The vDSO for normal processes, without the test-only syscalls.
+    Full,
+    /// A vDSO for core-tests, also permitting the numbered test-only
+    /// syscalls.
+    Test1,
+    /// Another vDSO for core-tests, also permitting the other test-only
+    /// syscalls.
+    Test2,
+    /// The vDSO also exporting the syscalls in development. The userboot has
+    /// no slot for it, so it is never created by the loader.
+    Next,
 }
 
 impl VmObject {
@@ -133,6 +196,16 @@ impl VmObject {
         })
     }
 
+    /// Create a new VMO whose pages are managed by a pager.
+    pub(super) fn new_pager_backed(pages: usize) -> Arc<Self> {
+        Arc::new(VmObject {
+            base: KObjectBase::new(),
+            resizable: false,
+            trait_: VMObjectPaged::new_pager_backed(pages),
+            inner: Mutex::new(VmObjectInner::default()),
+        })
+    }
+
     /// Create a new VMO representing a piece of contiguous physical memory.
     pub fn new_physical(paddr: PhysAddr, pages: usize) -> Arc<Self> {
         Arc::new(VmObject {
@@ -332,6 +405,16 @@ impl VmObject {
         inner.mapping_count
     }
 
+    /// Mark the VMO as the image of a vDSO variant.
+    pub fn set_vdso_variant(&self, variant: VdsoVariant) {
+        self.inner.lock().vdso_variant = Some(variant);
+    }
+
+    /// Get the vDSO variant of the VMO, if it is a vDSO image.
+    pub fn vdso_variant(&self) -> Option<VdsoVariant> {
+        self.inner.lock().vdso_variant
+    }
+
     /// Returns true if the object size can be changed.
     pub fn is_resizable(&self) -> bool {
         self.resizable
@@ -448,6 +531,25 @@ bitflags! {
     }
 }
 
+numeric_enum! {
+    #[repr(u32)]
+    /// Operations used by `vmo_op_range` and `vmar_op_range`.
+    #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+    pub enum VmoOpType {
+        Commit = 1,
+        Decommit = 2,
+        Lock = 3,
+        Unlock = 4,
+        CacheSync = 6,
+        CacheInvalidate = 7,
+        CacheClean = 8,
+        CacheCleanInvalidate = 9,
+        Zero = 10,
+        AlwaysNeed = 11,
+        DontNeed = 12,
+    }
+}
+
 /// Different operations that `range_change` can perform against any VmMappings that are found.
 #[allow(dead_code)]
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -466,4 +568,13 @@ mod tests {
         vmo.read(0, &mut buf).unwrap();
         assert_eq!(&buf, &[0, 1, 2, 3]);
     }
+
+    pub fn read_write_v(vmo: &VmObject) {
+        let (mut buf0, mut buf1) = ([0u8; 3], [0u8; 5]);
+        vmo.write_v(0xffe, &[&[0, 1, 2], &[3], &[4, 5, 6, 7]])
+            .unwrap();
+        vmo.read_v(0xffe, &mut [&mut buf0, &mut buf1]).unwrap();
+        assert_eq!(&buf0, &[0, 1, 2]);
+        assert_eq!(&buf1, &[3, 4, 5, 6, 7]);
+    }
 }
@@ -1,13 +1,17 @@
 use {
     super::*,
     crate::util::block_range::BlockIter,
+    alloc::collections::BTreeMap,
     alloc::sync::Arc,
     alloc::vec::Vec,
     core::ops::Range,
+    core::sync::atomic::Ordering,
     kernel_hal::{MMUFlags, PhysFrame, PAGE_SIZE},
-    spin::Mutex,
+    spin::{Mutex, MutexGuard},
 };
 
+crate::kcounter!(VM_FRAME_ALLOC, "vm.frame.alloc");
+
 /// The main VM object type, holding a list of pages.
 pub struct VMObjectPaged {
     inner: Mutex<VMObjectPagedInner>,
@@ -16,8 +20,16 @@ pub struct VMObjectPaged {
 /// The mutable part of `VMObjectPaged`.
 #[derive(Default)]
 struct VMObjectPagedInner {
-    /// Physical frames of this VMO.
-    frames: Vec<PhysFrame>,
+    /// Committed physical frames of this VMO, keyed by page index.
+    ///
+    /// Pages are committed on demand, so a huge but sparse VMO only
+    /// costs metadata for the pages actually touched.
+    ///
+    /// A frame shared with a snapshot child or its parent is copy-on-write,
+    /// it is replaced by a copy before it is written.
+    frames: BTreeMap<usize, Arc<PhysFrame>>,
+    /// The size of this VMO in pages.
+    size: usize,
     /// Cache Policy
     cache_policy: CachePolicy,
     /// Is contiguous
@@ -26,16 +38,41 @@ struct VMObjectPagedInner {
     pin_count: usize,
     /// All mappings to this VMO.
     mappings: Vec<Weak<VmMapping>>,
+    /// Is managed by a pager
+    pager_backed: bool,
+    /// Dirty state of pages, only for pager-backed VMO. Clean pages are absent.
+    dirty: BTreeMap<usize, DirtyState>,
+    /// The pages replaced by a copy or committed while mapped, and the new
+    /// frames, to be remapped in the mappings once the lock is released.
+    remapped: Vec<(usize, PhysAddr)>,
+}
+
+/// Dirty state of a page in pager-backed VMO.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum DirtyState {
+    /// The page is modified.
+    Dirty,
+    /// The page is being written back, and not modified since then.
+    AwaitingClean,
 }
 
 impl VMObjectPaged {
     /// Create a new VMO backing on physical memory allocated in pages.
     pub fn new(pages: usize) -> Arc<Self> {
-        let mut frames = Vec::new();
-        frames.resize_with(pages, || PhysFrame::alloc_zeroed().unwrap());
         Arc::new(VMObjectPaged {
             inner: Mutex::new(VMObjectPagedInner {
-                frames,
+                size: pages,
+                ..Default::default()
+            }),
+        })
+    }
+
+    /// Create a new VMO whose pages are managed by a pager.
+    pub fn new_pager_backed(pages: usize) -> Arc<Self> {
+        Arc::new(VMObjectPaged {
+            inner: Mutex::new(VMObjectPagedInner {
+                size: pages,
+                pager_backed: true,
                 ..Default::default()
             }),
         })
@@ -44,17 +81,42 @@ impl VMObjectPaged {
     /// Create a list of contiguous pages
     pub fn new_contiguous(pages: usize, align_log2: usize) -> ZxResult<Arc<Self>> {
         let frames = PhysFrame::alloc_contiguous_zeroed(pages, align_log2 - PAGE_SIZE_LOG2);
+        update_memory_pressure();
         if frames.is_empty() {
             return Err(ZxError::NO_MEMORY);
         }
+        VMO_COMMITTED_PAGES.fetch_add(frames.len(), Ordering::Relaxed);
+        VM_FRAME_ALLOC.add(frames.len());
         Ok(Arc::new(VMObjectPaged {
             inner: Mutex::new(VMObjectPagedInner {
-                frames,
+                frames: frames.into_iter().map(Arc::new).enumerate().collect(),
+                size: pages,
                 contiguous: true,
                 ..Default::default()
             }),
         }))
     }
+
+    /// Release the lock, and map the copies of the shared pages written
+    /// under it, and the pages committed again after a decommit, in the
+    /// mappings of this VMO.
+    fn remap_pages(&self, mut inner: MutexGuard<'_, VMObjectPagedInner>) {
+        if inner.remapped.is_empty() {
+            return;
+        }
+        let remapped = core::mem::take(&mut inner.remapped);
+        let mappings: Vec<_> = inner
+            .mappings
+            .iter()
+            .filter_map(|map| map.upgrade())
+            .collect();
+        drop(inner);
+        for mapping in mappings {
+            for &(page_idx, paddr) in remapped.iter() {
+                mapping.remap_page(page_idx, paddr);
+            }
+        }
+    }
 }
 
 impl VMObjectTrait for VMObjectPaged {
@@ -63,10 +125,11 @@ impl VMObjectTrait for VMObjectPaged {
         if inner.cache_policy != CachePolicy::Cached {
             return Err(ZxError::BAD_STATE);
         }
-        inner.for_each_page(offset, buf.len(), |paddr, buf_range| {
-            kernel_hal::pmem_read(paddr, &mut buf[buf_range]);
-        });
-        Ok(())
+        inner.check_range(offset, buf.len())?;
+        inner.for_each_page(offset, buf.len(), false, |paddr, buf_range| match paddr {
+            Some(paddr) => kernel_hal::pmem_read(paddr, &mut buf[buf_range]),
+            None => buf[buf_range].fill(0),
+        })
     }
 
     fn write(&self, offset: usize, buf: &[u8]) -> ZxResult {
@@ -74,55 +137,154 @@ impl VMObjectTrait for VMObjectPaged {
         if inner.cache_policy != CachePolicy::Cached {
             return Err(ZxError::BAD_STATE);
         }
-        inner.for_each_page(offset, buf.len(), |paddr, buf_range| {
-            kernel_hal::pmem_write(paddr, &buf[buf_range]);
+        inner.check_range(offset, buf.len())?;
+        inner.commit_range(offset / PAGE_SIZE..pages(offset + buf.len()))?;
+        let ret = inner.for_each_page(offset, buf.len(), true, |paddr, buf_range| {
+            kernel_hal::pmem_write(paddr.unwrap(), &buf[buf_range]);
         });
+        self.remap_pages(inner);
+        ret
+    }
+
+    fn read_v(&self, offset: usize, bufs: &mut [&mut [u8]]) -> ZxResult {
+        let mut inner = self.inner.lock();
+        if inner.cache_policy != CachePolicy::Cached {
+            return Err(ZxError::BAD_STATE);
+        }
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        inner.check_range(offset, total)?;
+        let mut offset = offset;
+        for buf in bufs.iter_mut() {
+            inner.for_each_page(offset, buf.len(), false, |paddr, buf_range| match paddr {
+                Some(paddr) => kernel_hal::pmem_read(paddr, &mut buf[buf_range]),
+                None => buf[buf_range].fill(0),
+            })?;
+            offset += buf.len();
+        }
         Ok(())
     }
 
+    fn write_v(&self, offset: usize, bufs: &[&[u8]]) -> ZxResult {
+        let mut inner = self.inner.lock();
+        if inner.cache_policy != CachePolicy::Cached {
+            return Err(ZxError::BAD_STATE);
+        }
+        let total = bufs.iter().map(|buf| buf.len()).sum();
+        inner.check_range(offset, total)?;
+        inner.commit_range(offset / PAGE_SIZE..pages(offset + total))?;
+        let mut offset = offset;
+        let mut ret = Ok(());
+        for buf in bufs.iter() {
+            ret = inner.for_each_page(offset, buf.len(), true, |paddr, buf_range| {
+                kernel_hal::pmem_write(paddr.unwrap(), &buf[buf_range]);
+            });
+            if ret.is_err() {
+                break;
+            }
+            offset += buf.len();
+        }
+        self.remap_pages(inner);
+        ret
+    }
+
     fn zero(&self, offset: usize, len: usize) -> ZxResult {
         let mut inner = self.inner.lock();
         if inner.cache_policy != CachePolicy::Cached {
             return Err(ZxError::BAD_STATE);
         }
-        inner.for_each_page(offset, len, |paddr, buf_range| {
-            kernel_hal::pmem_zero(paddr, buf_range.len());
-        });
-        Ok(())
+        inner.check_range(offset, len)?;
+        // uncommitted pages are already zero
+        let mut ranges = Vec::new();
+        let mut zeroed = Vec::new();
+        let ret = inner
+            .copy_shared_pages(offset / PAGE_SIZE..pages(offset + len))
+            .and_then(|_| {
+                inner.for_each_page(offset, len, false, |paddr, buf_range| {
+                    if let Some(paddr) = paddr {
+                        ranges.push((paddr, buf_range.len()));
+                        zeroed.push((offset + buf_range.start) / PAGE_SIZE);
+                    }
+                })
+            });
+        kernel_hal::pmem_zero_n(&ranges);
+        for page_idx in zeroed {
+            inner.mark_page_dirty(page_idx);
+        }
+        self.remap_pages(inner);
+        ret
     }
 
     fn len(&self) -> usize {
         let inner = self.inner.lock();
-        inner.frames.len() * PAGE_SIZE
+        inner.size * PAGE_SIZE
     }
 
     fn set_len(&self, len: usize) -> ZxResult {
         assert!(page_aligned(len));
         let mut inner = self.inner.lock();
-        inner.frames.resize_with(len / PAGE_SIZE, || {
-            PhysFrame::alloc().ok_or(ZxError::NO_MEMORY).unwrap()
-        });
+        let new_size = len / PAGE_SIZE;
+        // release committed pages beyond the new size
+        let removed = inner.frames.split_off(&new_size);
+        VMO_COMMITTED_PAGES.fetch_sub(removed.len(), Ordering::Relaxed);
+        inner.dirty.split_off(&new_size);
+        inner.size = new_size;
+        drop(removed);
+        update_memory_pressure();
         Ok(())
     }
 
-    fn commit_page(&self, page_idx: usize, _flags: MMUFlags) -> ZxResult<PhysAddr> {
-        let inner = self.inner.lock();
-        Ok(inner.frames[page_idx].addr())
+    fn commit_page(&self, page_idx: usize, flags: MMUFlags) -> ZxResult<PhysAddr> {
+        let mut inner = self.inner.lock();
+        let ret = inner.commit_page_with_flags(page_idx, flags);
+        self.remap_pages(inner);
+        ret
     }
 
     fn commit_pages_with(
         &self,
         f: &mut dyn FnMut(&mut dyn FnMut(usize, MMUFlags) -> ZxResult<PhysAddr>) -> ZxResult,
     ) -> ZxResult {
-        let inner = self.inner.lock();
-        f(&mut |page_idx, _| Ok(inner.frames[page_idx].addr()))
+        let mut inner = self.inner.lock();
+        let ret = f(&mut |page_idx, flags| inner.commit_page_with_flags(page_idx, flags));
+        self.remap_pages(inner);
+        ret
     }
 
-    fn commit(&self, _offset: usize, _len: usize) -> ZxResult {
-        Ok(())
+    fn commit(&self, offset: usize, len: usize) -> ZxResult {
+        let mut inner = self.inner.lock();
+        inner.check_range(offset, len)?;
+        let ret = inner.commit_range(offset / PAGE_SIZE..pages(offset + len));
+        self.remap_pages(inner);
+        ret
     }
 
-    fn decommit(&self, _offset: usize, _len: usize) -> ZxResult {
+    fn decommit(&self, offset: usize, len: usize) -> ZxResult {
+        let mut inner = self.inner.lock();
+        inner.check_range(offset, len)?;
+        if inner.pager_backed {
+            return Err(ZxError::NOT_SUPPORTED);
+        }
+        if inner.pin_count != 0 {
+            return Err(ZxError::BAD_STATE);
+        }
+        let range = offset / PAGE_SIZE..pages(offset + len);
+        let mut tail = inner.frames.split_off(&range.start);
+        let mut rest = tail.split_off(&range.end);
+        VMO_COMMITTED_PAGES.fetch_sub(tail.len(), Ordering::Relaxed);
+        inner.frames.append(&mut rest);
+        let mappings: Vec<_> = inner
+            .mappings
+            .iter()
+            .filter_map(|map| map.upgrade())
+            .collect();
+        drop(inner);
+        // the frames are freed after they are unmapped, and the pages are
+        // mapped again once they are committed
+        for mapping in mappings {
+            mapping.unmap_vmo_pages(range.clone());
+        }
+        drop(tail);
+        update_memory_pressure();
         Ok(())
     }
 
@@ -130,7 +292,14 @@ impl VMObjectTrait for VMObjectPaged {
         assert!(page_aligned(offset));
         assert!(page_aligned(len));
         let mut inner = self.inner.lock();
-        let child = inner.create_child(offset, len)?;
+        // the pages mapped writable can not be write-protected, copy them now
+        let writable: Vec<_> = inner
+            .mappings
+            .iter()
+            .filter_map(|map| map.upgrade())
+            .flat_map(|map| map.writable_vmo_pages())
+            .collect();
+        let child = inner.create_child(offset, len, &writable)?;
         Ok(child)
     }
 
@@ -172,7 +341,7 @@ impl VMObjectTrait for VMObjectPaged {
             return Err(ZxError::BAD_STATE);
         }
         if inner.cache_policy == CachePolicy::Cached && policy != CachePolicy::Cached {
-            for frame in inner.frames.iter() {
+            for frame in inner.frames.values() {
                 kernel_hal::frame_flush(frame.addr());
             }
         }
@@ -181,30 +350,37 @@ impl VMObjectTrait for VMObjectPaged {
     }
 
     fn committed_pages_in_range(&self, start_idx: usize, end_idx: usize) -> usize {
-        end_idx - start_idx
+        let inner = self.inner.lock();
+        inner.frames.range(start_idx..end_idx).count()
     }
 
     fn pin(&self, offset: usize, len: usize) -> ZxResult {
         let mut inner = self.inner.lock();
-        if offset + len > inner.frames.len() * PAGE_SIZE {
-            return Err(ZxError::OUT_OF_RANGE);
-        }
+        inner.check_range(offset, len)?;
         if len == 0 {
             return Ok(());
         }
+        // pinned pages must be backed by physical memory not shared
+        let ret = (offset / PAGE_SIZE..pages(offset + len))
+            .try_for_each(|page_idx| inner.commit_page_for_write(page_idx).map(|_| ()));
+        if let Err(err) = ret {
+            self.remap_pages(inner);
+            return Err(err);
+        }
         inner.pin_count += pages(len);
+        VMO_PINNED_PAGES.fetch_add(pages(len), Ordering::Relaxed);
+        self.remap_pages(inner);
         Ok(())
     }
 
     fn unpin(&self, offset: usize, len: usize) -> ZxResult {
         let mut inner = self.inner.lock();
-        if offset + len > inner.frames.len() * PAGE_SIZE {
-            return Err(ZxError::OUT_OF_RANGE);
-        }
+        inner.check_range(offset, len)?;
         if len == 0 {
             return Ok(());
         }
         inner.pin_count -= pages(len);
+        VMO_PINNED_PAGES.fetch_sub(pages(len), Ordering::Relaxed);
         Ok(())
     }
 
@@ -216,9 +392,93 @@ impl VMObjectTrait for VMObjectPaged {
     fn is_paged(&self) -> bool {
         true
     }
+
+    fn mark_dirty(&self, offset: usize, len: usize) -> ZxResult {
+        let mut inner = self.inner.lock();
+        inner.check_pager_range(offset, len)?;
+        let range = offset / PAGE_SIZE..pages(offset + len);
+        if range.clone().any(|idx| !inner.frames.contains_key(&idx)) {
+            return Err(ZxError::NOT_FOUND);
+        }
+        for page_idx in range {
+            inner.mark_page_dirty(page_idx);
+        }
+        Ok(())
+    }
+
+    fn writeback_begin(&self, offset: usize, len: usize) -> ZxResult {
+        let mut inner = self.inner.lock();
+        inner.check_pager_range(offset, len)?;
+        let range = offset / PAGE_SIZE..pages(offset + len);
+        for (_, state) in inner.dirty.range_mut(range) {
+            *state = DirtyState::AwaitingClean;
+        }
+        Ok(())
+    }
+
+    fn writeback_end(&self, offset: usize, len: usize) -> ZxResult {
+        let mut inner = self.inner.lock();
+        inner.check_pager_range(offset, len)?;
+        let range = offset / PAGE_SIZE..pages(offset + len);
+        // writes through the pages mapped writable can not be observed,
+        // they are dirty again as soon as they are written back
+        let writable: Vec<_> = inner
+            .mappings
+            .iter()
+            .filter_map(|map| map.upgrade())
+            .flat_map(|map| map.writable_vmo_pages())
+            .collect();
+        inner.dirty.retain(|idx, state| {
+            if !range.contains(idx) || *state != DirtyState::AwaitingClean {
+                return true;
+            }
+            *state = DirtyState::Dirty;
+            writable.contains(idx)
+        });
+        Ok(())
+    }
+
+    fn dirty_ranges(&self, offset: usize, len: usize) -> ZxResult<Vec<VmoDirtyRange>> {
+        let inner = self.inner.lock();
+        inner.check_pager_range(offset, len)?;
+        let mut ranges: Vec<VmoDirtyRange> = Vec::new();
+        for (&idx, _) in inner.dirty.range(offset / PAGE_SIZE..pages(offset + len)) {
+            let page_offset = (idx * PAGE_SIZE) as u64;
+            match ranges.last_mut() {
+                Some(last) if last.offset + last.length == page_offset => {
+                    last.length += PAGE_SIZE as u64;
+                }
+                _ => ranges.push(VmoDirtyRange {
+                    offset: page_offset,
+                    length: PAGE_SIZE as u64,
+                    options: 0,
+                }),
+            }
+        }
+        Ok(ranges)
+    }
+}
+
+impl Drop for VMObjectPaged {
+    fn drop(&mut self) {
+        let inner = self.inner.get_mut();
+        VMO_COMMITTED_PAGES.fetch_sub(inner.frames.len(), Ordering::Relaxed);
+        VMO_PINNED_PAGES.fetch_sub(inner.pin_count, Ordering::Relaxed);
+        inner.frames.clear();
+        update_memory_pressure();
+    }
 }
 
 impl VMObjectPagedInner {
+    /// Check whether `offset..offset+len` lies inside the VMO.
+    fn check_range(&self, offset: usize, len: usize) -> ZxResult {
+        let end = offset.checked_add(len).ok_or(ZxError::OUT_OF_RANGE)?;
+        if end > self.size * PAGE_SIZE {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        Ok(())
+    }
+
     /// Helper function to split range into sub-ranges within pages.
     ///
     /// ```text
@@ -235,30 +495,165 @@ impl VMObjectPagedInner {
     ///                     [==]
     /// ```
     ///
+    /// If `commit` is true, uncommitted pages are committed on the way, and
+    /// the shared ones are copied to be written.
+    ///
     /// `f` is a function to process in-page ranges.
     /// It takes 2 arguments:
-    /// * `paddr`: the start physical address of the in-page range.
+    /// * `paddr`: the start physical address of the in-page range,
+    ///   or `None` if the page is not committed.
     /// * `buf_range`: the range in view of the input buffer.
     fn for_each_page(
         &mut self,
         offset: usize,
         buf_len: usize,
-        mut f: impl FnMut(PhysAddr, Range<usize>),
-    ) {
+        commit: bool,
+        mut f: impl FnMut(Option<PhysAddr>, Range<usize>),
+    ) -> ZxResult {
         let iter = BlockIter {
             begin: offset,
             end: offset + buf_len,
             block_size_log2: 12,
         };
         for block in iter {
-            let paddr = self.frames[block.block].addr();
+            let paddr = if commit {
+                self.mark_page_dirty(block.block);
+                Some(self.commit_page_for_write(block.block)?)
+            } else {
+                self.frames.get(&block.block).map(|frame| frame.addr())
+            };
             let buf_range = block.origin_begin() - offset..block.origin_end() - offset;
-            f(paddr + block.begin, buf_range);
+            f(paddr.map(|paddr| paddr + block.begin), buf_range);
+        }
+        Ok(())
+    }
+
+    /// Commit page `page_idx`, and mark it dirty if it will be mapped writable.
+    fn commit_page_with_flags(&mut self, page_idx: usize, flags: MMUFlags) -> ZxResult<PhysAddr> {
+        if !flags.contains(MMUFlags::WRITE) {
+            return self.commit_page(page_idx);
+        }
+        let paddr = self.commit_page_for_write(page_idx)?;
+        // writes through mappings can not be observed, treat them as dirty
+        self.mark_page_dirty(page_idx);
+        Ok(paddr)
+    }
+
+    /// Mark page `page_idx` dirty if this VMO is pager-backed.
+    fn mark_page_dirty(&mut self, page_idx: usize) {
+        if self.pager_backed {
+            self.dirty.insert(page_idx, DirtyState::Dirty);
+        }
+    }
+
+    /// Check whether the page-aligned range is valid for pager operations.
+    fn check_pager_range(&self, offset: usize, len: usize) -> ZxResult {
+        if !self.pager_backed {
+            return Err(ZxError::NOT_SUPPORTED);
+        }
+        self.check_range(offset, len)
+    }
+
+    /// Get the physical address of page `page_idx`, allocate a zeroed frame if not committed.
+    fn commit_page(&mut self, page_idx: usize) -> ZxResult<PhysAddr> {
+        if page_idx >= self.size {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        if let Some(frame) = self.frames.get(&page_idx) {
+            return Ok(frame.addr());
+        }
+        let frame = PhysFrame::alloc_zeroed();
+        update_memory_pressure();
+        let frame = frame.ok_or(ZxError::NO_MEMORY)?;
+        VM_FRAME_ALLOC.add(1);
+        let paddr = frame.addr();
+        self.frames.insert(page_idx, Arc::new(frame));
+        VMO_COMMITTED_PAGES.fetch_add(1, Ordering::Relaxed);
+        self.remap_committed(page_idx, paddr);
+        Ok(paddr)
+    }
+
+    /// Record the page `page_idx` committed to be remapped if the VMO is
+    /// mapped, as its pages may be unmapped by a decommit.
+    fn remap_committed(&mut self, page_idx: usize, paddr: PhysAddr) {
+        if !self.mappings.is_empty() {
+            self.remapped.push((page_idx, paddr));
         }
     }
 
+    /// Commit page `page_idx` to be written, copy the frame if it is shared.
+    fn commit_page_for_write(&mut self, page_idx: usize) -> ZxResult<PhysAddr> {
+        self.commit_page(page_idx)?;
+        self.copy_shared_page(page_idx)
+    }
+
+    /// Replace the frame of committed page `page_idx` by a copy if it is
+    /// shared, and record it to be remapped.
+    fn copy_shared_page(&mut self, page_idx: usize) -> ZxResult<PhysAddr> {
+        let frame = self.frames.get_mut(&page_idx).unwrap();
+        if Arc::strong_count(frame) == 1 {
+            return Ok(frame.addr());
+        }
+        let copy = PhysFrame::alloc();
+        update_memory_pressure();
+        let copy = copy.ok_or(ZxError::NO_MEMORY)?;
+        VM_FRAME_ALLOC.add(1);
+        let paddr = copy.addr();
+        kernel_hal::frame_copy(frame.addr(), paddr);
+        *frame = Arc::new(copy);
+        self.remapped.push((page_idx, paddr));
+        Ok(paddr)
+    }
+
+    /// Copy the shared frames of the committed pages in `range`.
+    fn copy_shared_pages(&mut self, range: Range<usize>) -> ZxResult {
+        let committed: Vec<_> = self.frames.range(range).map(|(&idx, _)| idx).collect();
+        for page_idx in committed {
+            self.copy_shared_page(page_idx)?;
+        }
+        Ok(())
+    }
+
+    /// Commit the pages of `range`, zeroing the new frames together.
+    fn commit_range(&mut self, range: Range<usize>) -> ZxResult {
+        if range.end > self.size {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let mut ranges = Vec::new();
+        let mut result = Ok(());
+        for page_idx in range {
+            if self.frames.contains_key(&page_idx) {
+                continue;
+            }
+            match PhysFrame::alloc() {
+                Some(frame) => {
+                    ranges.push((frame.addr(), PAGE_SIZE));
+                    self.remap_committed(page_idx, frame.addr());
+                    self.frames.insert(page_idx, Arc::new(frame));
+                }
+                None => {
+                    result = Err(ZxError::NO_MEMORY);
+                    break;
+                }
+            }
+        }
+        kernel_hal::pmem_zero_n(&ranges);
+        VM_FRAME_ALLOC.add(ranges.len());
+        VMO_COMMITTED_PAGES.fetch_add(ranges.len(), Ordering::Relaxed);
+        update_memory_pressure();
+        result
+    }
+
     /// Create a snapshot child VMO.
-    fn create_child(&mut self, offset: usize, len: usize) -> ZxResult<Arc<VMObjectPaged>> {
+    ///
+    /// The committed pages are shared copy-on-write, except the ones in
+    /// `writable`, which are copied.
+    fn create_child(
+        &mut self,
+        offset: usize,
+        len: usize,
+        writable: &[usize],
+    ) -> ZxResult<Arc<VMObjectPaged>> {
         // clone contiguous vmo is no longer permitted
         // https://fuchsia.googlesource.com/fuchsia/+/e6b4c6751bbdc9ed2795e81b8211ea294f139a45
         if self.contiguous {
@@ -267,21 +662,28 @@ impl VMObjectPagedInner {
         if self.cache_policy != CachePolicy::Cached || self.pin_count != 0 {
             return Err(ZxError::BAD_STATE);
         }
-        let mut frames = Vec::with_capacity(pages(len));
-        for _ in 0..pages(len) {
-            frames.push(PhysFrame::alloc().ok_or(ZxError::NO_MEMORY)?);
-        }
-        for (i, frame) in frames.iter().enumerate() {
-            if let Some(src_frame) = self.frames.get(pages(offset) + i) {
-                kernel_hal::frame_copy(src_frame.addr(), frame.addr())
-            } else {
-                kernel_hal::pmem_zero(frame.addr(), PAGE_SIZE);
+        // only committed pages need to be shared, others read as zero
+        let start = pages(offset);
+        let mut frames = BTreeMap::new();
+        let mut copies = Vec::new();
+        for (&idx, src_frame) in self.frames.range(start..start + pages(len)) {
+            if !writable.contains(&idx) {
+                frames.insert(idx - start, src_frame.clone());
+                continue;
             }
+            let frame = PhysFrame::alloc().ok_or(ZxError::NO_MEMORY)?;
+            copies.push((src_frame.addr(), frame.addr()));
+            frames.insert(idx - start, Arc::new(frame));
         }
+        kernel_hal::frame_copy_n(&copies);
+        VMO_COMMITTED_PAGES.fetch_add(frames.len(), Ordering::Relaxed);
+        VM_FRAME_ALLOC.add(copies.len());
+        update_memory_pressure();
         // create child VMO
         let child = Arc::new(VMObjectPaged {
             inner: Mutex::new(VMObjectPagedInner {
                 frames,
+                size: pages(len),
                 ..Default::default()
             }),
         });
@@ -292,6 +694,9 @@ impl VMObjectPagedInner {
         if self.contiguous {
             info.flags |= VmoInfoFlags::CONTIGUOUS;
         }
+        if self.pager_backed {
+            info.flags |= VmoInfoFlags::PAGER_BACKED;
+        }
         // info.num_children = if self.type_.is_hidden() { 2 } else { 0 };
         info.committed_bytes = (self.frames.len() * PAGE_SIZE) as u64;
     }
@@ -303,12 +708,31 @@ mod tests {
 
     #[test]
     fn read_write() {
+        kernel_hal_unix::init();
         let vmo = VmObject::new_paged(2);
         super::super::tests::read_write(&*vmo);
     }
 
+    #[test]
+    fn read_write_v() {
+        kernel_hal_unix::init();
+        let vmo = VmObject::new_paged(2);
+        super::super::tests::read_write_v(&*vmo);
+
+        let mut buf = [0u8; 2];
+        assert_eq!(
+            vmo.read_v(2 * PAGE_SIZE - 1, &mut [&mut buf]),
+            Err(ZxError::OUT_OF_RANGE)
+        );
+        assert_eq!(
+            vmo.write_v(2 * PAGE_SIZE - 1, &[&[1], &[2]]),
+            Err(ZxError::OUT_OF_RANGE)
+        );
+    }
+
     #[test]
     fn create_child() {
+        kernel_hal_unix::init();
         let vmo = VmObject::new_paged(1);
         let child_vmo = vmo.create_child(false, 0, PAGE_SIZE).unwrap();
 
@@ -323,6 +747,47 @@ mod tests {
         assert_eq!(child_vmo.test_read(0), 2);
     }
 
+    #[test]
+    fn create_child_copy_on_write() {
+        kernel_hal_unix::init();
+        let vmo = VmObject::new_paged(2);
+        vmo.test_write(0, 1);
+        vmo.test_write(1, 2);
+        let child_vmo = vmo.create_child(false, 0, 2 * PAGE_SIZE).unwrap();
+
+        // the frames are shared until they are written
+        let paddr = |vmo: &VmObject, page| vmo.commit_page(page, MMUFlags::READ).unwrap();
+        assert_eq!(paddr(&vmo, 0), paddr(&child_vmo, 0));
+        assert_eq!(paddr(&vmo, 1), paddr(&child_vmo, 1));
+
+        child_vmo.test_write(0, 3);
+        assert_ne!(paddr(&vmo, 0), paddr(&child_vmo, 0));
+        assert_eq!((vmo.test_read(0), child_vmo.test_read(0)), (1, 3));
+
+        vmo.zero(PAGE_SIZE, PAGE_SIZE).unwrap();
+        assert_ne!(paddr(&vmo, 1), paddr(&child_vmo, 1));
+        assert_eq!((vmo.test_read(1), child_vmo.test_read(1)), (0, 2));
+    }
+
+    #[test]
+    fn sparse() {
+        kernel_hal_unix::init();
+        // 1TiB VMO, only the touched pages are committed
+        let vmo = VmObject::new_paged(1 << 28);
+        assert_eq!(vmo.len(), 1 << 40);
+        assert_eq!(vmo.get_info().committed_bytes, 0);
+        vmo.test_write(1 << 27, 1);
+        assert_eq!(vmo.test_read(1 << 27), 1);
+        assert_eq!(vmo.test_read(1), 0);
+        assert_eq!(vmo.committed_pages_in_range(0, 1 << 28), 1);
+
+        vmo.commit(0, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(vmo.committed_pages_in_range(0, 1 << 28), 3);
+        vmo.decommit(0, PAGE_SIZE).unwrap();
+        assert_eq!(vmo.committed_pages_in_range(0, 1 << 28), 2);
+        assert_eq!(vmo.commit(1 << 40, PAGE_SIZE), Err(ZxError::OUT_OF_RANGE));
+    }
+
     impl VmObject {
         pub fn test_write(&self, page: usize, value: u8) {
             self.write(page * PAGE_SIZE, &[value]).unwrap();
@@ -109,6 +109,18 @@ impl VMObjectTrait for VMObjectPhysical {
         0
     }
 
+    fn pin(&self, offset: usize, len: usize) -> ZxResult {
+        if offset + len > self.len() {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        // physical memory is always present
+        Ok(())
+    }
+
+    fn unpin(&self, _offset: usize, _len: usize) -> ZxResult {
+        Ok(())
+    }
+
     fn is_contiguous(&self) -> bool {
         true
     }
@@ -122,6 +134,7 @@ mod tests {
 
     #[test]
     fn read_write() {
+        kernel_hal_unix::init();
         let vmo = VmObject::new_physical(0x1000, 2);
         assert_eq!(vmo.cache_policy(), CachePolicy::Uncached);
         super::super::tests::read_write(&vmo);
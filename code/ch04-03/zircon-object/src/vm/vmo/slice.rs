@@ -37,6 +37,16 @@ impl VMObjectTrait for VMObjectSlice {
         self.parent.write(offset + self.offset, buf)
     }
 
+    fn read_v(&self, offset: usize, bufs: &mut [&mut [u8]]) -> ZxResult {
+        self.check_range(offset, bufs.iter().map(|buf| buf.len()).sum())?;
+        self.parent.read_v(offset + self.offset, bufs)
+    }
+
+    fn write_v(&self, offset: usize, bufs: &[&[u8]]) -> ZxResult {
+        self.check_range(offset, bufs.iter().map(|buf| buf.len()).sum())?;
+        self.parent.write_v(offset + self.offset, bufs)
+    }
+
     fn zero(&self, offset: usize, len: usize) -> ZxResult {
         self.check_range(offset, len)?;
         self.parent.zero(offset + self.offset, len)
@@ -109,4 +119,28 @@ impl VMObjectTrait for VMObjectSlice {
     fn is_paged(&self) -> bool {
         self.parent.is_paged()
     }
+
+    fn mark_dirty(&self, offset: usize, len: usize) -> ZxResult {
+        self.check_range(offset, len)?;
+        self.parent.mark_dirty(offset + self.offset, len)
+    }
+
+    fn writeback_begin(&self, offset: usize, len: usize) -> ZxResult {
+        self.check_range(offset, len)?;
+        self.parent.writeback_begin(offset + self.offset, len)
+    }
+
+    fn writeback_end(&self, offset: usize, len: usize) -> ZxResult {
+        self.check_range(offset, len)?;
+        self.parent.writeback_end(offset + self.offset, len)
+    }
+
+    fn dirty_ranges(&self, offset: usize, len: usize) -> ZxResult<Vec<VmoDirtyRange>> {
+        self.check_range(offset, len)?;
+        let mut ranges = self.parent.dirty_ranges(offset + self.offset, len)?;
+        for range in ranges.iter_mut() {
+            range.offset -= self.offset as u64;
+        }
+        Ok(ranges)
+    }
 }
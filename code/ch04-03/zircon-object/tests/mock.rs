@@ -0,0 +1,151 @@
+//! The timers, waits and suspension on the mock HAL, in the virtual time.
+
+use {
+    core::time::Duration,
+    futures::{future::pending, FutureExt},
+    kernel_hal::{timer_now, PAGE_SIZE},
+    kernel_hal_unix::mock,
+    std::{future::Future, pin::Pin, sync::Arc},
+    zircon_object::{object::*, signal::*, task::*, vm::*, ZxError, ZxResult},
+};
+
+#[test]
+fn timer() {
+    let _mock = mock::init();
+    let timer = Timer::create(Slack::Late);
+    let deadline = timer_now() + Duration::from_secs(10);
+    timer.set(deadline, Duration::default());
+
+    mock::advance(Duration::from_secs(10) - Duration::from_nanos(1));
+    assert!(!timer.signal().contains(Signal::TIMER_SIGNALED));
+    mock::advance(Duration::from_nanos(1));
+    assert!(timer.signal().contains(Signal::TIMER_SIGNALED));
+    assert_eq!(timer_now(), deadline);
+}
+
+#[test]
+fn wait_signal() {
+    let _mock = mock::init();
+    let start = timer_now();
+    let event: Arc<dyn KernelObject> = Event::new();
+    kernel_hal::Thread::spawn(
+        Box::pin({
+            let event = event.clone();
+            async move {
+                kernel_hal::sleep_until(start + Duration::from_secs(1)).await;
+                event.signal_set(Signal::SIGNALED);
+            }
+        }),
+        0,
+        kernel_hal::CPU_AFFINITY_ALL,
+    );
+    let signal = mock::block_on(async move { event.wait_signal(Signal::SIGNALED).await });
+    assert_eq!(signal, Signal::SIGNALED);
+    assert_eq!(timer_now(), start + Duration::from_secs(1));
+
+    // waits for a year in no time
+    let deadline = timer_now() + Duration::from_secs(365 * 24 * 3600);
+    mock::block_on(kernel_hal::sleep_until(deadline));
+    assert_eq!(timer_now(), deadline);
+}
+
+#[test]
+fn init_clears_timers() {
+    let guard = mock::init();
+    let timer = Timer::create(Slack::Late);
+    timer.set(timer_now() + Duration::from_secs(1), Duration::default());
+    drop(guard);
+
+    let _mock = mock::init();
+    assert_eq!(timer_now(), Duration::default());
+    mock::advance(Duration::from_secs(2));
+    assert!(!timer.signal().contains(Signal::TIMER_SIGNALED));
+}
+
+#[test]
+fn canceled_timers_removed() {
+    let _mock = mock::init();
+    let timer = Timer::create(Slack::Late);
+    timer.set(timer_now() + Duration::from_secs(1), Duration::default());
+    timer.set(timer_now() + Duration::from_secs(2), Duration::default());
+    timer.cancel();
+    // a sleep dropped before its deadline
+    let deadline = timer_now() + Duration::from_secs(3);
+    let mut sleep = Box::pin(kernel_hal::sleep_until(deadline));
+    assert_eq!((&mut sleep).now_or_never(), None);
+    drop(sleep);
+
+    // no timer is left to move the time forward
+    mock::run();
+    assert_eq!(timer_now(), Duration::default());
+}
+
+/// The results of the sleeper, with the time they are returned.
+static RESULTS: spin::Mutex<Vec<(ZxResult, Duration)>> = spin::Mutex::new(Vec::new());
+
+fn sleeper(thread: CurrentThread) -> Pin<Box<dyn Future<Output = ()> + Send + 'static>> {
+    Box::pin(async move {
+        let deadline = timer_now() + Duration::from_secs(10);
+        let ret = thread
+            .blocking_run(
+                pending::<ZxResult>(),
+                ThreadState::BlockedSleeping,
+                deadline,
+            )
+            .await;
+        RESULTS.lock().push((ret, timer_now()));
+        let ret = thread.wait_for_resume().await;
+        RESULTS.lock().push((ret, timer_now()));
+    })
+}
+
+#[test]
+fn suspend_sleeping() {
+    let _mock = mock::init();
+    RESULTS.lock().clear();
+    let root_job = Job::root();
+    let proc = Process::create(&root_job, "proc").expect("failed to create process");
+    let thread = Thread::create(&proc, "thread").expect("failed to create thread");
+    let start = timer_now();
+    thread.start(0, 0, 0, 0, sleeper).unwrap();
+    mock::run_until_idle();
+    assert_eq!(thread.state(), ThreadState::BlockedSleeping);
+
+    mock::advance(Duration::from_secs(1));
+    thread.suspend();
+    mock::advance(Duration::from_secs(2));
+    thread.resume();
+    mock::run_until_idle();
+    assert_eq!(
+        *RESULTS.lock(),
+        [
+            (
+                Err(ZxError::INTERNAL_INTR_RETRY),
+                start + Duration::from_secs(1)
+            ),
+            (Ok(()), start + Duration::from_secs(3)),
+        ]
+    );
+}
+
+#[test]
+fn frame_counts() {
+    let _mock = mock::init();
+    let vmo = VmObject::new_paged(4);
+    vmo.commit(0, 4 * PAGE_SIZE).unwrap();
+    assert_eq!(mock::frame_counts().allocated, 4);
+    drop(vmo);
+    assert_eq!(mock::frame_counts().freed, 4);
+}
+
+#[test]
+fn ioport_not_supported() {
+    let _mock = mock::init();
+    let root_job = Job::root();
+    let proc = Process::create(&root_job, "proc").expect("failed to create process");
+    assert_eq!(
+        proc.set_ioport_access(0x3f8, 8, true),
+        Err(ZxError::NOT_SUPPORTED)
+    );
+    assert!(!proc.ioport_accessible(0x3f8));
+}
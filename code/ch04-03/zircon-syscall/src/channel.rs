@@ -1,6 +1,10 @@
 use {
     super::*,
-    zircon_object::ipc::{Channel, MessagePacket},
+    alloc::vec::Vec,
+    zircon_object::{
+        ipc::{Channel, MessagePacket, MAX_MSG_BYTES, MAX_MSG_HANDLES},
+        task::{Process, ThreadState},
+    },
 };
 
 impl Syscall<'_> {
@@ -10,39 +14,96 @@ impl Syscall<'_> {
         &self,
         handle_value: HandleValue,
         options: u32,
-        mut bytes: UserOutPtr<u8>,
+        bytes: UserOutPtr<u8>,
         handles: usize,
         num_bytes: u32,
         num_handles: u32,
-        mut actual_bytes: UserOutPtr<u32>,
-        mut actual_handles: UserOutPtr<u32>,
+        actual_bytes: UserOutPtr<u32>,
+        actual_handles: UserOutPtr<u32>,
     ) -> ZxResult {
         info!(
             "channel.read: handle={:#x?}, options={:?}, bytes=({:#x?}; {:#x?}), handles=({:#x?}; {:#x?})",
             handle_value, options, bytes, num_bytes, handles, num_handles,
         );
+        self.channel_read(
+            handle_value,
+            options,
+            bytes,
+            handles,
+            num_bytes,
+            num_handles,
+            actual_bytes,
+            actual_handles,
+            false,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    /// Read a message from a channel, with the type and rights of each handle.
+    ///
+    /// The `handles` buffer is an array of `zx_handle_info_t`.
+    pub fn sys_channel_read_etc(
+        &self,
+        handle_value: HandleValue,
+        options: u32,
+        bytes: UserOutPtr<u8>,
+        handles: usize,
+        num_bytes: u32,
+        num_handles: u32,
+        actual_bytes: UserOutPtr<u32>,
+        actual_handles: UserOutPtr<u32>,
+    ) -> ZxResult {
+        info!(
+            "channel.read_etc: handle={:#x?}, options={:?}, bytes=({:#x?}; {:#x?}), handles=({:#x?}; {:#x?})",
+            handle_value, options, bytes, num_bytes, handles, num_handles,
+        );
+        self.channel_read(
+            handle_value,
+            options,
+            bytes,
+            handles,
+            num_bytes,
+            num_handles,
+            actual_bytes,
+            actual_handles,
+            true,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn channel_read(
+        &self,
+        handle_value: HandleValue,
+        options: u32,
+        mut bytes: UserOutPtr<u8>,
+        handles: usize,
+        num_bytes: u32,
+        num_handles: u32,
+        mut actual_bytes: UserOutPtr<u32>,
+        mut actual_handles: UserOutPtr<u32>,
+        is_etc: bool,
+    ) -> ZxResult {
+        // Discard the message if it does not fit in the buffers.
+        // Otherwise it is kept in the channel for the next read.
+        const MAY_DISCARD: u32 = 1;
+        if options & !MAY_DISCARD != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let may_discard = options & MAY_DISCARD != 0;
         let proc = self.thread.proc();
         let channel = proc.get_object_with_rights::<Channel>(handle_value, Rights::READ)?;
-        // FIX ME:
-        const MAY_DISCARD: u32 = 1;
-        let never_discard = options & MAY_DISCARD == 0;
-
-        let msg = if never_discard {
-            channel.check_and_read(|front_msg| {
-                if num_bytes < front_msg.data.len() as u32
-                    || num_handles < front_msg.handles.len() as u32
-                {
-                    let bytes = front_msg.data.len();
-                    actual_bytes.write_if_not_null(bytes as u32)?;
-                    actual_handles.write_if_not_null(front_msg.handles.len() as u32)?;
-                    Err(ZxError::BUFFER_TOO_SMALL)
-                } else {
-                    Ok(())
+        let mut msg = channel.check_and_read(|front_msg| {
+            if num_bytes < front_msg.data.len() as u32
+                || num_handles < front_msg.handles.len() as u32
+            {
+                actual_bytes.write_if_not_null(front_msg.data.len() as u32)?;
+                actual_handles.write_if_not_null(front_msg.handles.len() as u32)?;
+                if !may_discard {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
                 }
-            })?
-        } else {
-            channel.read()?
-        };
+            }
+            Ok(())
+        })?;
 
         // 如果要过 core-tests 把这个打开
         // hack_core_tests(handle_value, &self.thread.proc().name(), &mut msg.data);
@@ -50,15 +111,35 @@ impl Syscall<'_> {
         actual_bytes.write_if_not_null(msg.data.len() as u32)?;
         actual_handles.write_if_not_null(msg.handles.len() as u32)?;
         if num_bytes < msg.data.len() as u32 || num_handles < msg.handles.len() as u32 {
+            // the message and its handles are discarded
+            msg.recycle();
             return Err(ZxError::BUFFER_TOO_SMALL);
         }
         bytes.write_array(msg.data.as_slice())?;
-        let values = proc.add_handles(msg.handles);
-        UserOutPtr::<HandleValue>::from(handles).write_array(&values)?;
+        if is_etc {
+            let infos: Vec<HandleInfo> = msg
+                .handles
+                .drain(..)
+                .map(|handle| HandleInfo {
+                    type_: obj_type(&handle.object),
+                    rights: handle.rights.bits(),
+                    handle: proc.add_handle(handle),
+                    unused: 0,
+                })
+                .collect();
+            UserOutPtr::<HandleInfo>::from(handles).write_array(&infos)?;
+        } else {
+            let values = proc.add_handles(msg.handles.drain(..));
+            UserOutPtr::<HandleValue>::from(handles).write_array(&values)?;
+        }
+        msg.recycle();
         Ok(())
     }
 
-    /// Write a message to a channel.  
+    /// Write a message to a channel.
+    ///
+    /// With the `USE_IOVEC` option, `user_bytes` is an array of `num_bytes`
+    /// `zx_channel_iovec_t`, and the data is gathered from them.
     pub fn sys_channel_write(
         &self,
         handle_value: HandleValue,
@@ -72,30 +153,120 @@ impl Syscall<'_> {
             "channel.write: handle_value={:#x}, num_bytes={:#x}, num_handles={:#x}",
             handle_value, num_bytes, num_handles,
         );
-        if options != 0 {
-            return Err(ZxError::INVALID_ARGS);
-        }
-        if num_bytes > 65536 {
-            return Err(ZxError::OUT_OF_RANGE);
-        }
         let proc = self.thread.proc();
-        let data = user_bytes.read_array(num_bytes as usize)?;
+        let mut msg = read_msg_data(user_bytes, num_bytes, num_handles, options)?;
         let handles = user_handles.read_array(num_handles as usize)?;
         let transfer_self = handles.iter().any(|&handle| handle == handle_value);
-        let handles = proc.remove_handles(&handles)?;
+        msg.handles.extend(proc.remove_handles(&handles)?);
         if transfer_self {
             return Err(ZxError::NOT_SUPPORTED);
         }
-        if handles.len() > 64 {
-            return Err(ZxError::OUT_OF_RANGE);
-        }
-        for handle in handles.iter() {
+        for handle in msg.handles.iter() {
             if !handle.rights.contains(Rights::TRANSFER) {
                 return Err(ZxError::ACCESS_DENIED);
             }
         }
         let channel = proc.get_object_with_rights::<Channel>(handle_value, Rights::WRITE)?;
-        channel.write(MessagePacket { data, handles })?;
+        channel.write(msg)?;
+        Ok(())
+    }
+
+    /// Write a message to a channel, with an operation on each handle.
+    ///
+    /// Handles are moved or duplicated according to their dispositions, and the
+    /// result of each operation is written back to the disposition.
+    pub fn sys_channel_write_etc(
+        &self,
+        handle_value: HandleValue,
+        options: u32,
+        user_bytes: UserInPtr<u8>,
+        num_bytes: u32,
+        mut user_handles: UserInOutPtr<HandleDisposition>,
+        num_handles: u32,
+    ) -> ZxResult {
+        info!(
+            "channel.write_etc: handle_value={:#x}, num_bytes={:#x}, num_handles={:#x}",
+            handle_value, num_bytes, num_handles,
+        );
+        let proc = self.thread.proc();
+        let mut msg = read_msg_data(user_bytes, num_bytes, num_handles, options)?;
+        let mut dispositions = user_handles.read_array(num_handles as usize)?;
+        let mut first_error = None;
+        for disposition in dispositions.iter_mut() {
+            match disposition.take_handle(proc, handle_value) {
+                Ok(handle) => {
+                    disposition.result = ZxError::OK as i32;
+                    msg.handles.push(handle);
+                }
+                Err(err) => {
+                    disposition.result = err as i32;
+                    first_error.get_or_insert(err);
+                }
+            }
+        }
+        user_handles.write_array(&dispositions)?;
+        if let Some(err) = first_error {
+            return Err(err);
+        }
+        let channel = proc.get_object_with_rights::<Channel>(handle_value, Rights::WRITE)?;
+        channel.write(msg)?;
+        Ok(())
+    }
+
+    /// Send a message to a channel and await a reply.
+    ///
+    /// The thread is never interrupted and retried, so the `channel_call_finish`
+    /// half of the vDSO `zx_channel_call` is never needed.
+    pub async fn sys_channel_call_noretry(
+        &self,
+        handle_value: HandleValue,
+        options: u32,
+        deadline: Deadline,
+        user_args: UserInPtr<ChannelCallArgs>,
+        mut actual_bytes: UserOutPtr<u32>,
+        mut actual_handles: UserOutPtr<u32>,
+    ) -> ZxResult {
+        let mut args = user_args.read()?;
+        info!(
+            "channel.call_noretry: handle={:#x}, deadline={:?}, args={:#x?}",
+            handle_value, deadline, args
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        if args.rd_num_bytes < 4 || args.wr_num_bytes < 4 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        if args.wr_num_bytes as usize > MAX_MSG_BYTES
+            || args.wr_num_handles as usize > MAX_MSG_HANDLES
+        {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let proc = self.thread.proc();
+        let channel =
+            proc.get_object_with_rights::<Channel>(handle_value, Rights::READ | Rights::WRITE)?;
+        let wr_msg = MessagePacket {
+            data: args.wr_bytes.read_array(args.wr_num_bytes as usize)?,
+            handles: {
+                let handles = args.wr_handles.read_array(args.wr_num_handles as usize)?;
+                proc.remove_handles(&handles)?
+            },
+        };
+        let future = alloc::boxed::Box::pin(channel.call(wr_msg));
+        let rd_msg = self
+            .thread
+            .blocking_run(future, ThreadState::BlockedChannel, deadline.into())
+            .await?;
+        actual_bytes.write(rd_msg.data.len() as u32)?;
+        actual_handles.write(rd_msg.handles.len() as u32)?;
+        if args.rd_num_bytes < rd_msg.data.len() as u32
+            || args.rd_num_handles < rd_msg.handles.len() as u32
+        {
+            return Err(ZxError::BUFFER_TOO_SMALL);
+        }
+        args.rd_bytes.write_array(rd_msg.data.as_slice())?;
+        args.rd_handles
+            .write_array(&proc.add_handles(rd_msg.handles))?;
         Ok(())
     }
 
@@ -120,6 +291,139 @@ impl Syscall<'_> {
     }
 }
 
+/// The option of channel write that the bytes are given by iovecs.
+const USE_IOVEC: u32 = 2;
+
+/// The maximum number of iovecs in a message.
+const MAX_MSG_IOVECS: usize = 8192;
+
+/// Allocate a message with room for `num_handles` handles, and read its data
+/// from user, gathering from iovecs if `options` contains `USE_IOVEC`.
+fn read_msg_data(
+    user_bytes: UserInPtr<u8>,
+    num_bytes: u32,
+    num_handles: u32,
+    options: u32,
+) -> ZxResult<MessagePacket> {
+    if options & !USE_IOVEC != 0 {
+        return Err(ZxError::INVALID_ARGS);
+    }
+    if num_handles as usize > MAX_MSG_HANDLES {
+        return Err(ZxError::OUT_OF_RANGE);
+    }
+    if options & USE_IOVEC == 0 {
+        if num_bytes as usize > MAX_MSG_BYTES {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let mut msg = MessagePacket::alloc(num_bytes as usize, num_handles as usize);
+        user_bytes.read_array_into(&mut msg.data)?;
+        return Ok(msg);
+    }
+    if num_bytes as usize > MAX_MSG_IOVECS {
+        return Err(ZxError::OUT_OF_RANGE);
+    }
+    let iovecs = UserInPtr::<ChannelIoVec>::from(user_bytes.as_ptr() as usize)
+        .read_array(num_bytes as usize)?;
+    if iovecs.iter().any(|iovec| iovec.reserved != 0) {
+        return Err(ZxError::INVALID_ARGS);
+    }
+    let total: usize = iovecs.iter().map(|iovec| iovec.capacity as usize).sum();
+    if total > MAX_MSG_BYTES {
+        return Err(ZxError::OUT_OF_RANGE);
+    }
+    let mut msg = MessagePacket::alloc(total, num_handles as usize);
+    let mut offset = 0;
+    for iovec in iovecs.iter() {
+        let end = offset + iovec.capacity as usize;
+        iovec.buffer.read_array_into(&mut msg.data[offset..end])?;
+        offset = end;
+    }
+    Ok(msg)
+}
+
+/// A buffer of message data, as `zx_channel_iovec_t`.
+#[repr(C)]
+#[derive(Debug)]
+struct ChannelIoVec {
+    buffer: UserInPtr<u8>,
+    capacity: u32,
+    reserved: u32,
+}
+
+/// A handle received from a channel, as `zx_handle_info_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HandleInfo {
+    handle: HandleValue,
+    type_: u32,
+    rights: u32,
+    unused: u32,
+}
+
+/// An operation on a handle being written to a channel, as `zx_handle_disposition_t`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct HandleDisposition {
+    op: u32,
+    handle: HandleValue,
+    type_: u32,
+    rights: u32,
+    result: i32,
+}
+
+const HANDLE_OP_MOVE: u32 = 0;
+const HANDLE_OP_DUPLICATE: u32 = 1;
+
+impl HandleDisposition {
+    /// Move or duplicate the handle out of `proc` for writing to `channel`.
+    ///
+    /// A moved handle is removed from `proc` even if the operation fails.
+    fn take_handle(&self, proc: &Process, channel: HandleValue) -> ZxResult<Handle> {
+        if self.handle == channel {
+            return Err(ZxError::NOT_SUPPORTED);
+        }
+        let handle = match self.op {
+            HANDLE_OP_MOVE => proc.remove_handle(self.handle)?,
+            HANDLE_OP_DUPLICATE => {
+                let (object, rights) = proc.get_dyn_object_and_rights(self.handle)?;
+                if !rights.contains(Rights::DUPLICATE) {
+                    return Err(ZxError::ACCESS_DENIED);
+                }
+                Handle::new(object, rights)
+            }
+            _ => return Err(ZxError::INVALID_ARGS),
+        };
+        if !handle.rights.contains(Rights::TRANSFER) {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        if self.type_ != 0 && self.type_ != obj_type(&handle.object) {
+            return Err(ZxError::WRONG_TYPE);
+        }
+        let rights = Rights::from_bits(self.rights).ok_or(ZxError::INVALID_ARGS)?;
+        if rights.contains(Rights::SAME_RIGHTS) {
+            return Ok(handle);
+        }
+        if !handle.rights.contains(rights) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        Ok(Handle::new(handle.object, rights))
+    }
+}
+
+/// Arguments of `zx_channel_call`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ChannelCallArgs {
+    wr_bytes: UserInPtr<u8>,
+    wr_handles: UserInPtr<HandleValue>,
+    rd_bytes: UserOutPtr<u8>,
+    rd_handles: UserOutPtr<HandleValue>,
+    wr_num_bytes: u32,
+    wr_num_handles: u32,
+    rd_num_bytes: u32,
+    rd_num_handles: u32,
+}
+
 // HACK: pass arguments to standalone-test
 // #[allow(clippy::naive_bytecount)]
 // fn hack_core_tests(handle: HandleValue, thread_name: &str, data: &mut Vec<u8>) {
@@ -177,5 +177,16 @@ pub enum SyscallType {
     COUNT = 167,
     FUTEX_WAKE_HANDLE_CLOSE_THREAD_EXIT = 200,
     VMAR_UNMAP_HANDLE_CLOSE_THREAD_EXIT = 201,
+    BLOCK_GET_NTH_DEVICE = 202,
+    BLOCK_ATTACH_VMO = 203,
+    BLOCK_GET_FIFO = 204,
+    ETH_GET_NTH_DEVICE = 205,
+    ETH_SET_RINGS = 206,
+    ETH_BIND_PORT = 207,
+    ETH_TX_KICK = 208,
+    ETH_GET_STATUS = 209,
+    FRAMEBUFFER_GET_VMO = 210,
+    FRAMEBUFFER_FLUSH = 211,
+    PAGER_QUERY_DIRTY_RANGES = 212,
 }
 }
@@ -0,0 +1,33 @@
+use {
+    super::*,
+    zircon_object::util::random::{self, CPRNG_ADD_ENTROPY_MAX_LEN},
+};
+
+/// The maximum number of random bytes drawn at once.
+const CPRNG_DRAW_MAX_LEN: usize = 256;
+
+impl Syscall<'_> {
+    /// Draw random bytes from the kernel CSPRNG.
+    ///
+    /// `zx_cprng_draw` in vDSO draws larger buffers in chunks with it.
+    pub fn sys_cprng_draw_once(&self, mut buf: UserOutPtr<u8>, len: usize) -> ZxResult {
+        info!("cprng.draw_once: buf=({:?}; {:#x})", buf, len);
+        if len > CPRNG_DRAW_MAX_LEN {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let mut data = [0; CPRNG_DRAW_MAX_LEN];
+        random::draw(&mut data[..len]);
+        buf.write_array(&data[..len])?;
+        Ok(())
+    }
+
+    /// Mix `len` bytes of entropy into the kernel CSPRNG.
+    pub fn sys_cprng_add_entropy(&self, buf: UserInPtr<u8>, len: usize) -> ZxResult {
+        info!("cprng.add_entropy: buf=({:?}; {:#x})", buf, len);
+        if len > CPRNG_ADD_ENTROPY_MAX_LEN {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        random::add_entropy(&buf.read_array(len)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,540 @@
+use {
+    super::*,
+    core::convert::TryFrom,
+    kernel_hal::{CachePolicy, PciBar, SmcParams, SmcResult},
+    zircon_object::{dev::*, signal::Port, vm::*},
+};
+
+/// The pixel format of the blue, green, red and an unused byte in memory.
+const ZX_PIXEL_FORMAT_RGB_X888: u32 = 0x0004_0005;
+
+impl Syscall<'_> {
+    /// Create a new IOMMU object in the kernel.
+    pub fn sys_iommu_create(
+        &self,
+        resource: HandleValue,
+        type_: u32,
+        desc: UserInPtr<u8>,
+        desc_size: usize,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "iommu.create: resource={:#x}, type={:#x}, desc={:#x?}, desc_size={:#x}",
+            resource, type_, desc, desc_size
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        if desc_size > IOMMU_MAX_DESC_LEN {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let type_ = IommuType::try_from(type_).map_err(|_| ZxError::INVALID_ARGS)?;
+        let desc = desc.read_array(desc_size)?;
+        let iommu = Iommu::create(type_, &desc)?;
+        let handle = proc.add_handle(Handle::new(iommu, Rights::DEFAULT_IOMMU));
+        out.write(handle)?;
+        Ok(())
+    }
+
+    /// Create a new bus transaction initiator for the device `bti_id`.
+    pub fn sys_bti_create(
+        &self,
+        iommu: HandleValue,
+        options: u32,
+        bti_id: u64,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "bti.create: iommu={:#x}, options={:#x}, bti_id={:#x}",
+            iommu, options, bti_id
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let iommu = proc.get_object::<Iommu>(iommu)?;
+        let bti = BusTransactionInitiator::create(iommu, bti_id)?;
+        let handle = proc.add_handle(Handle::new(bti, Rights::DEFAULT_BTI));
+        out.write(handle)?;
+        Ok(())
+    }
+
+    /// Pin pages of a VMO and grant a device access to them.
+    ///
+    /// The device addresses are written to `addrs`, whose length must match
+    /// the encoding specified by `options`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sys_bti_pin(
+        &self,
+        bti: HandleValue,
+        options: u32,
+        vmo: HandleValue,
+        offset: usize,
+        size: usize,
+        mut addrs: UserOutPtr<DevVAddr>,
+        addrs_count: usize,
+        mut pmt_out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "bti.pin: bti={:#x}, options={:#x}, vmo={:#x}, offset={:#x}, size={:#x}, addrs_count={:#x}",
+            bti, options, vmo, offset, size, addrs_count
+        );
+        let options = IommuPerms::from_bits(options).ok_or(ZxError::INVALID_ARGS)?;
+        let proc = self.thread.proc();
+        let bti = proc.get_object_with_rights::<BusTransactionInitiator>(bti, Rights::MAP)?;
+        let mut rights = Rights::MAP;
+        if options.contains(IommuPerms::PERM_READ) {
+            rights |= Rights::READ;
+        }
+        if options.contains(IommuPerms::PERM_WRITE) {
+            rights |= Rights::WRITE;
+        }
+        if options.contains(IommuPerms::PERM_EXECUTE) {
+            rights |= Rights::EXECUTE;
+        }
+        let vmo = proc.get_object_with_rights::<VmObject>(vmo, rights)?;
+        let pmt = bti.pin(vmo, offset, size, options)?;
+        // the pages are unpinned if the PMT can not be handed out
+        let ret = pmt
+            .encode_addrs(
+                options.contains(IommuPerms::COMPRESS),
+                options.contains(IommuPerms::CONTIGUOUS),
+                bti.minimum_contiguity(),
+            )
+            .and_then(|encoded| {
+                if encoded.len() != addrs_count {
+                    return Err(ZxError::INVALID_ARGS);
+                }
+                Ok(addrs.write_array(&encoded)?)
+            });
+        if let Err(err) = ret {
+            pmt.unpin()?;
+            return Err(err);
+        }
+        let handle = proc.add_handle(Handle::new(pmt.clone(), Rights::DEFAULT_PMT));
+        if let Err(err) = pmt_out.write(handle) {
+            proc.remove_handle(handle)?;
+            pmt.unpin()?;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Release all quarantined PMTs of a BTI.
+    pub fn sys_bti_release_quarantine(&self, bti: HandleValue) -> ZxResult {
+        info!("bti.release_quarantine: bti={:#x}", bti);
+        let proc = self.thread.proc();
+        let bti = proc.get_object_with_rights::<BusTransactionInitiator>(bti, Rights::WRITE)?;
+        bti.release_quarantine();
+        Ok(())
+    }
+
+    /// Unpin pages and revoke device access to them, the handle is consumed.
+    pub fn sys_pmt_unpin(&self, pmt: HandleValue) -> ZxResult {
+        info!("pmt.unpin: pmt={:#x}", pmt);
+        let proc = self.thread.proc();
+        let pmt = proc.remove_object::<PinnedMemoryToken>(pmt)?;
+        pmt.unpin()
+    }
+
+    /// Grant the calling process access to I/O ports `[io_addr, io_addr + len)`.
+    pub fn sys_ioports_request(&self, resource: HandleValue, io_addr: u16, len: u32) -> ZxResult {
+        info!(
+            "ioports.request: resource={:#x}, io_addr={:#x}, len={:#x}",
+            resource, io_addr, len
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate_ranged_resource(ResourceKind::IOPORT, io_addr as usize, len as usize)?;
+        proc.set_ioport_access(io_addr, len, true)
+    }
+
+    /// Revoke the access to I/O ports `[io_addr, io_addr + len)` of the calling process.
+    pub fn sys_ioports_release(&self, resource: HandleValue, io_addr: u16, len: u32) -> ZxResult {
+        info!(
+            "ioports.release: resource={:#x}, io_addr={:#x}, len={:#x}",
+            resource, io_addr, len
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate_ranged_resource(ResourceKind::IOPORT, io_addr as usize, len as usize)?;
+        proc.set_ioport_access(io_addr, len, false)
+    }
+
+    /// Get the `index`-th PCI device found by the bus enumeration.
+    pub fn sys_pci_get_nth_device(
+        &self,
+        resource: HandleValue,
+        index: u32,
+        mut out_info: UserOutPtr<PcieDeviceInfo>,
+        mut out_handle: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "pci.get_nth_device: resource={:#x}, index={:#x}",
+            resource, index
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        let device = PciDevice::get_nth(index as usize)?;
+        out_info.write(device.info())?;
+        let handle = proc.add_handle(Handle::new(device, Rights::DEFAULT_PCI_DEVICE));
+        out_handle.write(handle)?;
+        Ok(())
+    }
+
+    /// Read `width` bytes at `offset` of the config space of a PCI device.
+    pub fn sys_pci_config_read(
+        &self,
+        handle: HandleValue,
+        offset: u16,
+        width: usize,
+        mut out_val: UserOutPtr<u32>,
+    ) -> ZxResult {
+        info!(
+            "pci.config_read: handle={:#x}, offset={:#x}, width={:#x}",
+            handle, offset, width
+        );
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<PciDevice>(handle, Rights::READ)?;
+        out_val.write(device.config_read(offset as usize, width)?)?;
+        Ok(())
+    }
+
+    /// Write `width` bytes at `offset` of the config space of a PCI device.
+    pub fn sys_pci_config_write(
+        &self,
+        handle: HandleValue,
+        offset: u16,
+        width: usize,
+        val: u32,
+    ) -> ZxResult {
+        info!(
+            "pci.config_write: handle={:#x}, offset={:#x}, width={:#x}, val={:#x}",
+            handle, offset, width, val
+        );
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<PciDevice>(handle, Rights::WRITE)?;
+        device.config_write(offset as usize, width, val)
+    }
+
+    /// Get a BAR of a PCI device.
+    ///
+    /// A physical VMO is created for a MMIO BAR, and the calling process is
+    /// granted the access to the I/O ports of a PIO BAR.
+    pub fn sys_pci_get_bar(
+        &self,
+        handle: HandleValue,
+        bar_num: u32,
+        mut out_bar: UserOutPtr<PciBarRepr>,
+        mut out_handle: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!("pci.get_bar: handle={:#x}, bar_num={:#x}", handle, bar_num);
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<PciDevice>(handle, Rights::READ)?;
+        let bar = match device.get_bar(bar_num as usize)? {
+            PciBar::Mmio { size, .. } => {
+                let vmo = device.map_bar(bar_num as usize)?;
+                let handle = proc.add_handle(Handle::new(vmo, Rights::DEFAULT_VMO));
+                out_handle.write(handle)?;
+                PciBarRepr {
+                    id: bar_num,
+                    type_: PCI_BAR_TYPE_MMIO,
+                    size,
+                    addr_or_handle: handle as usize,
+                }
+            }
+            PciBar::Pio { addr, size } => {
+                proc.set_ioport_access(addr, size as u32, true)?;
+                PciBarRepr {
+                    id: bar_num,
+                    type_: PCI_BAR_TYPE_PIO,
+                    size,
+                    addr_or_handle: addr as usize,
+                }
+            }
+        };
+        out_bar.write(bar)?;
+        Ok(())
+    }
+
+    /// Create an interrupt object of the `which_irq`-th interrupt of a PCI device.
+    pub fn sys_pci_map_interrupt(
+        &self,
+        handle: HandleValue,
+        which_irq: i32,
+        mut out_handle: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "pci.map_interrupt: handle={:#x}, which_irq={:#x}",
+            handle, which_irq
+        );
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<PciDevice>(handle, Rights::READ)?;
+        let which_irq = u32::try_from(which_irq).map_err(|_| ZxError::INVALID_ARGS)?;
+        let interrupt = device.map_interrupt(which_irq)?;
+        let handle = proc.add_handle(Handle::new(interrupt, Rights::DEFAULT_INTERRUPT));
+        out_handle.write(handle)?;
+        Ok(())
+    }
+
+    /// Get the maximum number of interrupts supported by a PCI device in `mode`.
+    pub fn sys_pci_query_irq_mode(
+        &self,
+        handle: HandleValue,
+        mode: u32,
+        mut out_max_irqs: UserOutPtr<u32>,
+    ) -> ZxResult {
+        info!("pci.query_irq_mode: handle={:#x}, mode={:#x}", handle, mode);
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<PciDevice>(handle, Rights::READ)?;
+        let mode = PciIrqMode::try_from(mode).map_err(|_| ZxError::INVALID_ARGS)?;
+        out_max_irqs.write(device.query_irq_mode(mode)?)?;
+        Ok(())
+    }
+
+    /// Set the interrupt mode of a PCI device.
+    pub fn sys_pci_set_irq_mode(
+        &self,
+        handle: HandleValue,
+        mode: u32,
+        requested_irq_count: u32,
+    ) -> ZxResult {
+        info!(
+            "pci.set_irq_mode: handle={:#x}, mode={:#x}, requested_irq_count={:#x}",
+            handle, mode, requested_irq_count
+        );
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<PciDevice>(handle, Rights::WRITE)?;
+        let mode = PciIrqMode::try_from(mode).map_err(|_| ZxError::INVALID_ARGS)?;
+        device.set_irq_mode(mode, requested_irq_count)
+    }
+
+    /// Get the `index`-th block device found by the HAL.
+    pub fn sys_block_get_nth_device(
+        &self,
+        resource: HandleValue,
+        index: u32,
+        mut out_info: UserOutPtr<BlockInfo>,
+        mut out_handle: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "block.get_nth_device: resource={:#x}, index={:#x}",
+            resource, index
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        let device = BlockDevice::get_nth(index as usize)?;
+        out_info.write(device.info())?;
+        let handle = proc.add_handle(Handle::new(device, Rights::DEFAULT_BLOCK_DEVICE));
+        out_handle.write(handle)?;
+        Ok(())
+    }
+
+    /// Attach a VMO to a block device, to be referred in the requests.
+    pub fn sys_block_attach_vmo(
+        &self,
+        handle: HandleValue,
+        vmo: HandleValue,
+        mut out_vmoid: UserOutPtr<u16>,
+    ) -> ZxResult {
+        info!("block.attach_vmo: handle={:#x}, vmo={:#x}", handle, vmo);
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<BlockDevice>(handle, Rights::WRITE)?;
+        let vmo = proc.get_object_with_rights::<VmObject>(vmo, Rights::IO)?;
+        out_vmoid.write(device.attach_vmo(vmo)?)?;
+        Ok(())
+    }
+
+    /// Get the FIFO of the requests to a block device.
+    pub fn sys_block_get_fifo(
+        &self,
+        handle: HandleValue,
+        mut out_fifo: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!("block.get_fifo: handle={:#x}", handle);
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<BlockDevice>(handle, Rights::WRITE)?;
+        let fifo = device.create_fifo()?;
+        out_fifo.write(proc.add_handle(Handle::new(fifo, Rights::DEFAULT_FIFO)))?;
+        Ok(())
+    }
+
+    /// Get the `index`-th network device found by the HAL.
+    pub fn sys_eth_get_nth_device(
+        &self,
+        resource: HandleValue,
+        index: u32,
+        mut out_info: UserOutPtr<EthInfo>,
+        mut out_handle: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "eth.get_nth_device: resource={:#x}, index={:#x}",
+            resource, index
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        let device = EthDevice::get_nth(index as usize)?;
+        out_info.write(device.info())?;
+        let handle = proc.add_handle(Handle::new(device, Rights::DEFAULT_ETH_DEVICE));
+        out_handle.write(handle)?;
+        Ok(())
+    }
+
+    /// Set the VMOs of the RX and TX rings of a network device.
+    pub fn sys_eth_set_rings(
+        &self,
+        handle: HandleValue,
+        rx_vmo: HandleValue,
+        tx_vmo: HandleValue,
+    ) -> ZxResult {
+        info!(
+            "eth.set_rings: handle={:#x}, rx_vmo={:#x}, tx_vmo={:#x}",
+            handle, rx_vmo, tx_vmo
+        );
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<EthDevice>(handle, Rights::WRITE)?;
+        let rx_vmo = proc.get_object_with_rights::<VmObject>(rx_vmo, Rights::IO)?;
+        let tx_vmo = proc.get_object_with_rights::<VmObject>(tx_vmo, Rights::IO)?;
+        device.set_rings(rx_vmo, tx_vmo)
+    }
+
+    /// Bind a network device to a port, to notify the received frames.
+    pub fn sys_eth_bind_port(&self, handle: HandleValue, port: HandleValue, key: u64) -> ZxResult {
+        info!(
+            "eth.bind_port: handle={:#x}, port={:#x}, key={:#x}",
+            handle, port, key
+        );
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<EthDevice>(handle, Rights::READ)?;
+        let port = proc.get_object_with_rights::<Port>(port, Rights::WRITE)?;
+        device.bind_port(port, key)
+    }
+
+    /// Send the frames in the TX ring of a network device.
+    pub fn sys_eth_tx_kick(
+        &self,
+        handle: HandleValue,
+        mut out_count: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!("eth.tx_kick: handle={:#x}", handle);
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<EthDevice>(handle, Rights::WRITE)?;
+        out_count.write(device.tx_kick()?)?;
+        Ok(())
+    }
+
+    /// Get the status of a network device.
+    pub fn sys_eth_get_status(
+        &self,
+        handle: HandleValue,
+        mut out_status: UserOutPtr<u32>,
+    ) -> ZxResult {
+        info!("eth.get_status: handle={:#x}", handle);
+        let proc = self.thread.proc();
+        let device = proc.get_object_with_rights::<EthDevice>(handle, Rights::READ)?;
+        out_status.write(device.status())?;
+        Ok(())
+    }
+
+    /// Get the format and the size of the framebuffer of the display.
+    pub fn sys_framebuffer_get_info(
+        &self,
+        resource: HandleValue,
+        mut out_format: UserOutPtr<u32>,
+        mut out_width: UserOutPtr<u32>,
+        mut out_height: UserOutPtr<u32>,
+        mut out_stride: UserOutPtr<u32>,
+    ) -> ZxResult {
+        info!("framebuffer.get_info: resource={:#x}", resource);
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        let info = kernel_hal::framebuffer_info().ok_or(ZxError::UNAVAILABLE)?;
+        out_format.write(ZX_PIXEL_FORMAT_RGB_X888)?;
+        out_width.write(info.width)?;
+        out_height.write(info.height)?;
+        out_stride.write(info.stride)?;
+        Ok(())
+    }
+
+    /// Get a physical VMO of the framebuffer of the display.
+    pub fn sys_framebuffer_get_vmo(
+        &self,
+        resource: HandleValue,
+        mut out_vmo: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!("framebuffer.get_vmo: resource={:#x}", resource);
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        let info = kernel_hal::framebuffer_info().ok_or(ZxError::UNAVAILABLE)?;
+        let vmo = VmObject::new_physical(info.paddr, pages(info.size));
+        // the framebuffer is in the memory, not a device
+        vmo.set_cache_policy(CachePolicy::Cached)?;
+        out_vmo.write(proc.add_handle(Handle::new(vmo, Rights::DEFAULT_VMO)))?;
+        Ok(())
+    }
+
+    /// Show the update of the rectangle in the framebuffer on the display.
+    pub fn sys_framebuffer_flush(
+        &self,
+        resource: HandleValue,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> ZxResult {
+        info!(
+            "framebuffer.flush: resource={:#x}, x={}, y={}, width={}, height={}",
+            resource, x, y, width, height
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        kernel_hal::framebuffer_flush(x, y, width, height);
+        Ok(())
+    }
+
+    /// Make a secure monitor call.
+    ///
+    /// The service call number of `func_id` must be within the range of the
+    /// SMC `resource`.
+    pub fn sys_smc_call(
+        &self,
+        resource: HandleValue,
+        parameters: UserInPtr<SmcParams>,
+        mut out_smc_result: UserOutPtr<SmcResult>,
+    ) -> ZxResult {
+        info!(
+            "smc.call: resource={:#x}, parameters={:#x?}",
+            resource, parameters
+        );
+        let params = parameters.read()?;
+        let service_call = (params.func_id >> 24) & SMC_SERVICE_CALL_NUM_MASK;
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate_ranged_resource(ResourceKind::SMC, service_call as usize, 1)?;
+        out_smc_result.write(kernel_hal::smc_call(&params))?;
+        Ok(())
+    }
+}
+
+/// The mask of the service call number in a SMC function ID.
+const SMC_SERVICE_CALL_NUM_MASK: u32 = 0x3f;
+
+const PCI_BAR_TYPE_MMIO: u32 = 1;
+const PCI_BAR_TYPE_PIO: u32 = 2;
+
+/// A BAR of a PCI device.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct PciBarRepr {
+    id: u32,
+    type_: u32,
+    size: usize,
+    /// The handle of the VMO for a MMIO BAR, or the base address for a PIO BAR.
+    addr_or_handle: usize,
+}
@@ -0,0 +1,92 @@
+use {
+    super::*,
+    alloc::{boxed::Box, string::String, vec},
+    zircon_object::{dev::*, task::ThreadState, util::console},
+};
+
+/// The maximum number of bytes written to the serial console at once.
+const DEBUG_WRITE_MAX_LEN: usize = 256;
+
+/// The maximum number of bytes read from the serial console at once.
+const DEBUG_READ_MAX_LEN: usize = 256;
+
+impl Syscall<'_> {
+    /// Write to the serial console, truncated to 256 bytes.
+    ///
+    /// Early userspace uses it to print before a debuglog reader exists.
+    pub fn sys_debug_write(&self, buf: UserInPtr<u8>, len: usize) -> ZxResult {
+        info!("debug.write: buf=({:?}; {:#x})", buf, len);
+        let data = buf.read_array(len.min(DEBUG_WRITE_MAX_LEN))?;
+        // the truncation may split the last character
+        let data = match core::str::from_utf8(&data) {
+            Err(e) if e.error_len().is_none() => &data[..e.valid_up_to()],
+            _ => &data,
+        };
+        kernel_hal::serial_write(&String::from_utf8_lossy(data));
+        Ok(())
+    }
+
+    /// Read from the serial console, blocking until some input is available.
+    pub async fn sys_debug_read(
+        &self,
+        handle: HandleValue,
+        mut buf: UserOutPtr<u8>,
+        buf_size: usize,
+        mut actual: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!(
+            "debug.read: handle={:#x}, buf=({:?}; {:#x})",
+            handle, buf, buf_size
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(handle)?
+            .validate(ResourceKind::ROOT)?;
+        if buf_size == 0 {
+            actual.write(0)?;
+            return Ok(());
+        }
+        let future = Box::pin(async move {
+            let mut data = vec![0; buf_size.min(DEBUG_READ_MAX_LEN)];
+            loop {
+                let len = kernel_hal::serial_read(&mut data);
+                if len != 0 {
+                    data.truncate(len);
+                    return Ok(data);
+                }
+                kernel_hal::serial_wait().await;
+            }
+        });
+        let data = self
+            .thread
+            .blocking_run(future, ThreadState::Blocked, Deadline::forever().into())
+            .await?;
+        buf.write_array(&data)?;
+        actual.write(data.len())?;
+        Ok(())
+    }
+
+    /// Run commands in the kernel console, with output to the serial console.
+    pub fn sys_debug_send_command(
+        &self,
+        resource: HandleValue,
+        buf: UserInPtr<u8>,
+        len: usize,
+    ) -> ZxResult {
+        info!(
+            "debug.send_command: resource={:#x}, buf=({:?}; {:#x})",
+            resource, buf, len
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        if len > console::MAX_COMMAND_LEN {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let script = buf.read_string(len)?;
+        let mut root_job = proc.job();
+        while let Some(parent) = root_job.parent() {
+            root_job = parent;
+        }
+        console::run_script(&script, &root_job)
+    }
+}
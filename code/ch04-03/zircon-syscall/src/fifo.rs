@@ -0,0 +1,81 @@
+use {super::*, alloc::vec, zircon_object::ipc::Fifo};
+
+impl Syscall<'_> {
+    /// Create a FIFO.
+    pub fn sys_fifo_create(
+        &self,
+        elem_count: usize,
+        elem_size: usize,
+        options: u32,
+        mut out0: UserOutPtr<HandleValue>,
+        mut out1: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "fifo.create: count={:#x}, item_size={:#x}, options={:#x}",
+            elem_count, elem_size, options,
+        );
+        if options != 0 {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let proc = self.thread.proc();
+        let (end0, end1) = Fifo::create(elem_count, elem_size)?;
+        let handle0 = proc.add_handle(Handle::new(end0, Rights::DEFAULT_FIFO));
+        let handle1 = proc.add_handle(Handle::new(end1, Rights::DEFAULT_FIFO));
+        out0.write(handle0)?;
+        out1.write(handle1)?;
+        Ok(())
+    }
+
+    /// Write elements to a FIFO.
+    pub fn sys_fifo_write(
+        &self,
+        handle_value: HandleValue,
+        elem_size: usize,
+        user_bytes: UserInPtr<u8>,
+        count: usize,
+        mut actual_count: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!(
+            "fifo.write: handle={:#x}, item_size={:#x}, count={:#x}",
+            handle_value, elem_size, count
+        );
+        let proc = self.thread.proc();
+        let fifo = proc.get_object_with_rights::<Fifo>(handle_value, Rights::WRITE)?;
+        if elem_size != fifo.elem_size() {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        // no more than the capacity can be written at once
+        let len = elem_size * count.min(fifo.elem_count());
+        let data = user_bytes.read_array(len)?;
+        let actual = fifo.write(elem_size, &data)?;
+        actual_count.write_if_not_null(actual)?;
+        Ok(())
+    }
+
+    /// Read elements from a FIFO.
+    pub fn sys_fifo_read(
+        &self,
+        handle_value: HandleValue,
+        elem_size: usize,
+        mut user_bytes: UserOutPtr<u8>,
+        count: usize,
+        mut actual_count: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!(
+            "fifo.read: handle={:#x}, item_size={:#x}, count={:#x}",
+            handle_value, elem_size, count
+        );
+        let proc = self.thread.proc();
+        let fifo = proc.get_object_with_rights::<Fifo>(handle_value, Rights::READ)?;
+        if elem_size != fifo.elem_size() {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        // no more than the capacity can be read at once
+        let len = elem_size * count.min(fifo.elem_count());
+        let mut data = vec![0; len];
+        let actual = fifo.read(elem_size, &mut data)?;
+        user_bytes.write_array(&data[..actual * elem_size])?;
+        actual_count.write_if_not_null(actual)?;
+        Ok(())
+    }
+}
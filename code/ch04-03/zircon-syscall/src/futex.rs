@@ -0,0 +1,134 @@
+use {
+    super::*,
+    alloc::sync::Arc,
+    core::sync::atomic::AtomicI32,
+    kernel_hal::MMUFlags,
+    zircon_object::task::{Thread, ThreadState},
+};
+
+impl Syscall<'_> {
+    /// Wait on a futex.
+    ///
+    /// The wait only happens if the futex value is still `current_value`,
+    /// and then the owner of the futex is set to `new_futex_owner`.
+    pub async fn sys_futex_wait(
+        &self,
+        value_ptr: UserInPtr<AtomicI32>,
+        current_value: i32,
+        new_futex_owner: HandleValue,
+        deadline: Deadline,
+    ) -> ZxResult {
+        info!(
+            "futex.wait: value_ptr={:#x?}, current_value={:#x}, new_futex_owner={:#x}, deadline={:?}",
+            value_ptr, current_value, new_futex_owner, deadline
+        );
+        let value = self.check_futex_ptr(value_ptr)?;
+        let proc = self.thread.proc();
+        let new_owner = self.get_futex_owner(new_futex_owner)?;
+        if let Some(owner) = &new_owner {
+            if Arc::ptr_eq(owner, &*self.thread) {
+                return Err(ZxError::INVALID_ARGS);
+            }
+        }
+        let futex = proc.get_futex(value);
+        let future = futex.wait_with_owner(current_value, Some((*self.thread).clone()), new_owner);
+        futures::pin_mut!(future);
+        self.thread
+            .blocking_run(future, ThreadState::BlockedFutex, deadline.into())
+            .await
+    }
+
+    /// Wake some number of threads waiting on a futex, and clear its owner.
+    pub fn sys_futex_wake(&self, value_ptr: UserInPtr<AtomicI32>, count: u32) -> ZxResult {
+        info!(
+            "futex.wake: value_ptr={:#x?}, count={:#x}",
+            value_ptr, count
+        );
+        let value = self.check_futex_ptr(value_ptr)?;
+        let proc = self.thread.proc();
+        proc.get_futex(value).wake(count as usize);
+        Ok(())
+    }
+
+    /// Wake one thread waiting on a futex, and make it the owner.
+    pub fn sys_futex_wake_single_owner(&self, value_ptr: UserInPtr<AtomicI32>) -> ZxResult {
+        info!("futex.wake_single_owner: value_ptr={:#x?}", value_ptr);
+        let value = self.check_futex_ptr(value_ptr)?;
+        let proc = self.thread.proc();
+        proc.get_futex(value).wake_single_owner();
+        Ok(())
+    }
+
+    /// Get the KoID of the owner of a futex, or 0 if it has no owner.
+    pub fn sys_futex_get_owner(
+        &self,
+        value_ptr: UserInPtr<AtomicI32>,
+        mut koid: UserOutPtr<KoID>,
+    ) -> ZxResult {
+        info!("futex.get_owner: value_ptr={:#x?}", value_ptr);
+        let value = self.check_futex_ptr(value_ptr)?;
+        let proc = self.thread.proc();
+        let owner = proc.get_futex(value).owner();
+        koid.write_if_not_null(owner.map_or(0, |t| t.id()))?;
+        Ok(())
+    }
+
+    /// Wake some number of threads waiting on a futex,
+    /// and move more waiters to another wait queue.
+    pub fn sys_futex_requeue(
+        &self,
+        value_ptr: UserInPtr<AtomicI32>,
+        wake_count: u32,
+        current_value: i32,
+        requeue_ptr: UserInPtr<AtomicI32>,
+        requeue_count: u32,
+        new_requeue_owner: HandleValue,
+    ) -> ZxResult {
+        info!(
+            "futex.requeue: value_ptr={:#x?}, wake_count={:#x}, current_value={:#x}, requeue_ptr={:#x?}, requeue_count={:#x}, new_requeue_owner={:#x}",
+            value_ptr, wake_count, current_value, requeue_ptr, requeue_count, new_requeue_owner
+        );
+        let value = self.check_futex_ptr(value_ptr)?;
+        let requeue = self.check_futex_ptr(requeue_ptr)?;
+        if core::ptr::eq(value, requeue) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let new_requeue_owner = self.get_futex_owner(new_requeue_owner)?;
+        let futex = proc.get_futex(value);
+        let requeue_futex = proc.get_futex(requeue);
+        futex.requeue(
+            current_value,
+            wake_count as usize,
+            requeue_count as usize,
+            &requeue_futex,
+            new_requeue_owner,
+        )?;
+        Ok(())
+    }
+
+    /// Check the futex pointer is not null, aligned, and readable in the
+    /// address space of the process.
+    fn check_futex_ptr(&self, ptr: UserInPtr<AtomicI32>) -> ZxResult<&'static AtomicI32> {
+        ptr.check()?;
+        let flags = self
+            .thread
+            .proc()
+            .vmar()
+            .get_vaddr_flags(ptr.as_ptr() as usize)
+            .map_err(|_| ZxError::INVALID_ARGS)?;
+        if !flags.contains(MMUFlags::READ) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        Ok(ptr.as_ref()?)
+    }
+
+    /// Get the thread of an owner handle, which may be `INVALID_HANDLE`.
+    fn get_futex_owner(&self, handle: HandleValue) -> ZxResult<Option<Arc<Thread>>> {
+        if handle == INVALID_HANDLE {
+            return Ok(None);
+        }
+        let thread = self.thread.proc().get_object::<Thread>(handle)?;
+        Ok(Some(thread))
+    }
+}
@@ -0,0 +1,82 @@
+use super::*;
+
+impl Syscall<'_> {
+    /// Close a handle. Closing `INVALID_HANDLE` is not an error.
+    pub fn sys_handle_close(&self, handle: HandleValue) -> ZxResult {
+        info!("handle.close: handle={:#x}", handle);
+        if handle == INVALID_HANDLE {
+            return Ok(());
+        }
+        let proc = self.thread.proc();
+        proc.remove_handle(handle)?;
+        Ok(())
+    }
+
+    /// Close a number of handles, skipping `INVALID_HANDLE`.
+    pub fn sys_handle_close_many(&self, handles: UserInPtr<HandleValue>, num: usize) -> ZxResult {
+        info!("handle.close_many: handles=({:#x?}; {:#x})", handles, num);
+        // the handles are read in chunks, not to allocate by the user count
+        const CHUNK_SIZE: usize = 64;
+        let proc = self.thread.proc();
+        let mut offset = 0;
+        while offset < num {
+            let count = (num - offset).min(CHUNK_SIZE);
+            for handle in handles.add(offset).read_array(count)? {
+                if handle != INVALID_HANDLE {
+                    proc.remove_handle(handle)?;
+                }
+            }
+            offset += count;
+        }
+        Ok(())
+    }
+
+    /// Create a new handle of the same object, with the same or reduced `rights`.
+    pub fn sys_handle_duplicate(
+        &self,
+        handle: HandleValue,
+        rights: u32,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "handle.duplicate: handle={:#x}, rights={:#x}",
+            handle, rights
+        );
+        let proc = self.thread.proc();
+        let (object, handle_rights) = proc.get_dyn_object_and_rights(handle)?;
+        if !handle_rights.contains(Rights::DUPLICATE) {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        let rights = reduce_rights(handle_rights, rights)?;
+        out.write(proc.add_handle(Handle::new(object, rights)))?;
+        Ok(())
+    }
+
+    /// Replace a handle with a new one of the same or reduced `rights`.
+    pub fn sys_handle_replace(
+        &self,
+        handle: HandleValue,
+        rights: u32,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!("handle.replace: handle={:#x}, rights={:#x}", handle, rights);
+        let proc = self.thread.proc();
+        let (_, handle_rights) = proc.get_dyn_object_and_rights(handle)?;
+        let rights = reduce_rights(handle_rights, rights)?;
+        let object = proc.remove_handle(handle)?.object;
+        out.write(proc.add_handle(Handle::new(object, rights)))?;
+        Ok(())
+    }
+}
+
+/// Get the rights of a new handle, which must not exceed the old ones.
+fn reduce_rights(old: Rights, rights: u32) -> ZxResult<Rights> {
+    let rights = Rights::from_bits(rights).ok_or(ZxError::INVALID_ARGS)?;
+    if rights == Rights::SAME_RIGHTS {
+        return Ok(old);
+    }
+    if !old.contains(rights) {
+        return Err(ZxError::INVALID_ARGS);
+    }
+    Ok(rights)
+}
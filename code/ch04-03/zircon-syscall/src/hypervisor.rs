@@ -0,0 +1,167 @@
+use {
+    super::*,
+    alloc::boxed::Box,
+    kernel_hal::VcpuState,
+    zircon_object::{
+        dev::*,
+        hypervisor::*,
+        signal::{Port, PortPacketRepr},
+        task::ThreadState,
+    },
+};
+
+impl Syscall<'_> {
+    /// Create a guest, return the handles of it and its physical address space.
+    pub fn sys_guest_create(
+        &self,
+        resource: HandleValue,
+        options: u32,
+        mut guest_handle: UserOutPtr<HandleValue>,
+        mut vmar_handle: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "guest.create: resource={:#x}, options={:#x}",
+            resource, options
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::HYPERVISOR)?;
+        let guest = Guest::create()?;
+        let vmar = guest.vmar();
+        let vmar_rights = Rights::DEFAULT_VMAR | Rights::READ | Rights::WRITE | Rights::EXECUTE;
+        guest_handle.write(proc.add_handle(Handle::new(guest, Rights::DEFAULT_GUEST)))?;
+        vmar_handle.write(proc.add_handle(Handle::new(vmar, vmar_rights)))?;
+        Ok(())
+    }
+
+    /// Set a trap of guest physical addresses or I/O ports.
+    pub fn sys_guest_set_trap(
+        &self,
+        handle: HandleValue,
+        kind: u32,
+        addr: usize,
+        size: usize,
+        port_handle: HandleValue,
+        key: u64,
+    ) -> ZxResult {
+        info!(
+            "guest.set_trap: handle={:#x}, kind={:#x}, addr={:#x}, size={:#x}, port={:#x}, key={:#x}",
+            handle, kind, addr, size, port_handle, key
+        );
+        let proc = self.thread.proc();
+        let guest = proc.get_object_with_rights::<Guest>(handle, Rights::WRITE)?;
+        let kind = TrapKind::try_from(kind).map_err(|_| ZxError::INVALID_ARGS)?;
+        let port = if port_handle == INVALID_HANDLE {
+            None
+        } else {
+            Some(proc.get_object_with_rights::<Port>(port_handle, Rights::WRITE)?)
+        };
+        guest.set_trap(kind, addr, size, port, key)
+    }
+
+    /// Create a VCPU of the guest starting at `entry`, owned by the current thread.
+    pub fn sys_vcpu_create(
+        &self,
+        guest: HandleValue,
+        options: u32,
+        entry: usize,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "vcpu.create: guest={:#x}, options={:#x}, entry={:#x}",
+            guest, options, entry
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let guest = proc.get_object_with_rights::<Guest>(guest, Rights::MANAGE_PROCESS)?;
+        let vcpu = Vcpu::create(guest, entry, self.thread)?;
+        let handle = proc.add_handle(Handle::new(vcpu, Rights::DEFAULT_VCPU));
+        out.write(handle)?;
+        Ok(())
+    }
+
+    /// Resume the VCPU until an access trapped synchronously.
+    pub async fn sys_vcpu_resume(
+        &self,
+        handle: HandleValue,
+        mut packet_out: UserOutPtr<PortPacketRepr>,
+    ) -> ZxResult {
+        info!("vcpu.resume: handle={:#x}", handle);
+        let proc = self.thread.proc();
+        let vcpu = proc.get_object_with_rights::<Vcpu>(handle, Rights::EXECUTE)?;
+        let thread = (*self.thread).clone();
+        let future = Box::pin(async move { vcpu.resume(&thread).await });
+        let packet = self
+            .thread
+            .blocking_run(future, ThreadState::Blocked, Deadline::forever().into())
+            .await?;
+        packet_out.write(packet.into())?;
+        Ok(())
+    }
+
+    /// Inject an interrupt of `vector` into the VCPU.
+    pub fn sys_vcpu_interrupt(&self, handle: HandleValue, vector: u32) -> ZxResult {
+        info!("vcpu.interrupt: handle={:#x}, vector={:#x}", handle, vector);
+        let proc = self.thread.proc();
+        let vcpu = proc.get_object_with_rights::<Vcpu>(handle, Rights::SIGNAL)?;
+        vcpu.interrupt(vector)
+    }
+
+    /// Read the state of the VCPU.
+    pub fn sys_vcpu_read_state(
+        &self,
+        handle: HandleValue,
+        kind: u32,
+        mut buffer: UserOutPtr<VcpuState>,
+        buffer_size: usize,
+    ) -> ZxResult {
+        info!(
+            "vcpu.read_state: handle={:#x}, kind={:#x}, buffer=({:#x?}; {:#x})",
+            handle, kind, buffer, buffer_size
+        );
+        let proc = self.thread.proc();
+        let vcpu = proc.get_object_with_rights::<Vcpu>(handle, Rights::READ)?;
+        if kind != VCPU_STATE || buffer_size != core::mem::size_of::<VcpuState>() {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        buffer.write(vcpu.read_state(self.thread)?)?;
+        Ok(())
+    }
+
+    /// Write the state of the VCPU.
+    pub fn sys_vcpu_write_state(
+        &self,
+        handle: HandleValue,
+        kind: u32,
+        buffer: usize,
+        buffer_size: usize,
+    ) -> ZxResult {
+        info!(
+            "vcpu.write_state: handle={:#x}, kind={:#x}, buffer=({:#x}; {:#x})",
+            handle, kind, buffer, buffer_size
+        );
+        let proc = self.thread.proc();
+        let vcpu = proc.get_object_with_rights::<Vcpu>(handle, Rights::WRITE)?;
+        match kind {
+            VCPU_STATE if buffer_size == core::mem::size_of::<VcpuState>() => {
+                let state = UserInPtr::<VcpuState>::from(buffer).read()?;
+                vcpu.write_state(self.thread, &state)
+            }
+            VCPU_IO if buffer_size == core::mem::size_of::<VcpuIo>() => {
+                let io = UserInPtr::<VcpuIo>::from(buffer).read()?;
+                vcpu.write_io(self.thread, &io)
+            }
+            _ => Err(ZxError::INVALID_ARGS),
+        }
+    }
+}
+
+/// The general registers of a VCPU.
+const VCPU_STATE: u32 = 0;
+/// The I/O state of a VCPU.
+const VCPU_IO: u32 = 1;
@@ -0,0 +1,121 @@
+use {
+    super::*,
+    alloc::boxed::Box,
+    zircon_object::{dev::*, signal::Port, task::ThreadState},
+};
+
+impl Syscall<'_> {
+    /// Create an interrupt object.
+    ///
+    /// A physical interrupt claims the IRQ `src_num`, which must be within
+    /// the range of the IRQ `resource`.
+    pub fn sys_interrupt_create(
+        &self,
+        resource: HandleValue,
+        src_num: usize,
+        options: u32,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "interrupt.create: resource={:#x}, src_num={:#x}, options={:#x}",
+            resource, src_num, options
+        );
+        let options = InterruptOptions::from_bits(options).ok_or(ZxError::INVALID_ARGS)?;
+        let proc = self.thread.proc();
+        let resource = proc.get_object::<Resource>(resource)?;
+        let interrupt = if options.contains(InterruptOptions::VIRTUAL) {
+            resource.validate(ResourceKind::IRQ)?;
+            Interrupt::new_virtual()
+        } else {
+            if options.contains(InterruptOptions::REMAP_IRQ) {
+                return Err(ZxError::NOT_SUPPORTED);
+            }
+            resource.validate_ranged_resource(ResourceKind::IRQ, src_num, 1)?;
+            Interrupt::new_physical(src_num as u32, options)?
+        };
+        let handle = proc.add_handle(Handle::new(interrupt, Rights::DEFAULT_INTERRUPT));
+        out.write(handle)?;
+        Ok(())
+    }
+
+    /// Bind or unbind an interrupt object to a port.
+    pub fn sys_interrupt_bind(
+        &self,
+        interrupt: HandleValue,
+        port: HandleValue,
+        key: u64,
+        options: u32,
+    ) -> ZxResult {
+        info!(
+            "interrupt.bind: interrupt={:#x}, port={:#x}, key={:#x}, options={:#x}",
+            interrupt, port, key, options
+        );
+        const BIND: u32 = 0;
+        const UNBIND: u32 = 1;
+        let proc = self.thread.proc();
+        let interrupt = proc.get_object_with_rights::<Interrupt>(interrupt, Rights::READ)?;
+        let port = proc.get_object_with_rights::<Port>(port, Rights::WRITE)?;
+        match options {
+            BIND => interrupt.bind(&port, key),
+            UNBIND => interrupt.unbind(&port),
+            _ => Err(ZxError::INVALID_ARGS),
+        }
+    }
+
+    /// Trigger a virtual interrupt object.
+    pub fn sys_interrupt_trigger(
+        &self,
+        interrupt: HandleValue,
+        options: u32,
+        timestamp: i64,
+    ) -> ZxResult {
+        info!(
+            "interrupt.trigger: interrupt={:#x}, options={:#x}, timestamp={:#x}",
+            interrupt, options, timestamp
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let interrupt = proc.get_object_with_rights::<Interrupt>(interrupt, Rights::SIGNAL)?;
+        interrupt.trigger(timestamp)
+    }
+
+    /// Acknowledge an interrupt and re-arm it.
+    pub fn sys_interrupt_ack(&self, interrupt: HandleValue) -> ZxResult {
+        info!("interrupt.ack: interrupt={:#x}", interrupt);
+        let proc = self.thread.proc();
+        let interrupt = proc.get_object_with_rights::<Interrupt>(interrupt, Rights::WRITE)?;
+        interrupt.ack()
+    }
+
+    /// Wait for an interrupt.
+    pub async fn sys_interrupt_wait(
+        &self,
+        interrupt: HandleValue,
+        mut out: UserOutPtr<i64>,
+    ) -> ZxResult {
+        info!("interrupt.wait: interrupt={:#x}", interrupt);
+        let proc = self.thread.proc();
+        let interrupt = proc.get_object_with_rights::<Interrupt>(interrupt, Rights::WAIT)?;
+        let future = Box::pin(async move { interrupt.wait().await });
+        let timestamp = self
+            .thread
+            .blocking_run(
+                future,
+                ThreadState::BlockedInterrupt,
+                Deadline::forever().into(),
+            )
+            .await?;
+        out.write_if_not_null(timestamp)?;
+        Ok(())
+    }
+
+    /// Destroy an interrupt object.
+    pub fn sys_interrupt_destroy(&self, interrupt: HandleValue) -> ZxResult {
+        info!("interrupt.destroy: interrupt={:#x}", interrupt);
+        let proc = self.thread.proc();
+        let interrupt = proc.get_object::<Interrupt>(interrupt)?;
+        interrupt.destroy()
+    }
+}
@@ -12,15 +12,42 @@ use {
     core::convert::TryFrom,
     kernel_hal::user::*,
     zircon_object::object::*,
-    zircon_object::task::{CurrentThread, ThreadFn},
+    zircon_object::task::{CurrentThread, PolicyCondition, ThreadFn},
+    zircon_object::vm::VdsoVariant,
 };
 
 mod channel;
 mod consts;
+mod cprng;
+mod ddk;
+mod debug;
 mod debuglog;
+mod fifo;
+mod futex;
+mod handle;
+mod hypervisor;
+mod interrupt;
+mod object;
+mod pager;
+mod port;
+mod profile;
+mod resource;
+mod socket;
+mod strace;
+mod system;
+mod task;
+mod time;
+mod timer;
+mod vmar;
+mod vmo;
 
+use self::time::Deadline;
 use consts::SyscallType as Sys;
 
+pub use consts::SyscallType;
+
+zircon_object::kcounter!(SYSCALL_COUNT, "syscall.count");
+
 pub struct Syscall<'a> {
     pub thread: &'a CurrentThread,
     pub thread_fn: ThreadFn,
@@ -29,7 +56,8 @@ pub struct Syscall<'a> {
 impl Syscall<'_> {
     pub async fn syscall(&mut self, num: u32, args: [usize; 8]) -> isize {
         let thread_name = self.thread.name();
-        let proc_name = self.thread.proc().name();
+        let proc = self.thread.proc();
+        let proc_name = proc.name();
         let sys_type = match Sys::try_from(num) {
             Ok(t) => t,
             Err(_) => {
@@ -37,12 +65,51 @@ impl Syscall<'_> {
                 return ZxError::INVALID_ARGS as _;
             }
         };
-        info!(
-            "{}|{} {:?} => args={:x?}",
-            proc_name, thread_name, sys_type, args
+        SYSCALL_COUNT.add(1);
+        // traced syscalls are logged to the `strace` target at info level,
+        // prefixed with the koids of the process and the thread
+        let (target, level) = if cfg!(feature = "strace") || proc.syscall_trace() {
+            ("strace", log::Level::Info)
+        } else {
+            (module_path!(), log::Level::Debug)
+        };
+        let (pid, tid) = (proc.id(), self.thread.id());
+        log!(
+            target: target,
+            level,
+            "[{}:{}] {}|{} {:?}({})",
+            pid,
+            tid,
+            proc_name,
+            thread_name,
+            sys_type,
+            strace::SyscallArgs::new(&sys_type, &args)
         );
         let [a0, a1, a2, a3, a4, a5, a6, a7] = args;
+        // the value returned on success, other than 0 only for the syscalls
+        // returning a time or ticks instead of a status
+        let mut value = 0;
+        let policy = new_object_policy(&sys_type).map_or(Ok(()), |c| proc.check_policy(c));
         let ret = match sys_type {
+            _ if !vdso_permits(proc.vdso_variant(), &sys_type) => Err(ZxError::BAD_SYSCALL),
+            _ if policy.is_err() => policy,
+            Sys::BLOCK_GET_NTH_DEVICE => {
+                self.sys_block_get_nth_device(a0 as _, a1 as _, a2.into(), a3.into())
+            }
+            Sys::BLOCK_ATTACH_VMO => self.sys_block_attach_vmo(a0 as _, a1 as _, a2.into()),
+            Sys::BLOCK_GET_FIFO => self.sys_block_get_fifo(a0 as _, a1.into()),
+            Sys::BTI_CREATE => self.sys_bti_create(a0 as _, a1 as _, a2 as _, a3.into()),
+            Sys::BTI_PIN => self.sys_bti_pin(
+                a0 as _,
+                a1 as _,
+                a2 as _,
+                a3 as _,
+                a4 as _,
+                a5.into(),
+                a6 as _,
+                a7.into(),
+            ),
+            Sys::BTI_RELEASE_QUARANTINE => self.sys_bti_release_quarantine(a0 as _),
             Sys::CHANNEL_CREATE => self.sys_channel_create(a0 as _, a1.into(), a2.into()),
             Sys::CHANNEL_READ => self.sys_channel_read(
                 a0 as _,
@@ -54,21 +121,276 @@ impl Syscall<'_> {
                 a6.into(),
                 a7.into(),
             ),
+            Sys::CHANNEL_READ_ETC => self.sys_channel_read_etc(
+                a0 as _,
+                a1 as _,
+                a2.into(),
+                a3 as _,
+                a4 as _,
+                a5 as _,
+                a6.into(),
+                a7.into(),
+            ),
             Sys::CHANNEL_WRITE => {
                 self.sys_channel_write(a0 as _, a1 as _, a2.into(), a3 as _, a4.into(), a5 as _)
             }
+            Sys::CHANNEL_WRITE_ETC => {
+                self.sys_channel_write_etc(a0 as _, a1 as _, a2.into(), a3 as _, a4.into(), a5 as _)
+            }
+            Sys::CHANNEL_CALL_NORETRY => {
+                self.sys_channel_call_noretry(
+                    a0 as _,
+                    a1 as _,
+                    a2.into(),
+                    a3.into(),
+                    a4.into(),
+                    a5.into(),
+                )
+                .await
+            }
+            Sys::CLOCK_GET => self.sys_clock_get(a0 as _, a1.into()),
+            Sys::CLOCK_GET_MONOTONIC_VIA_KERNEL => {
+                value = self.sys_clock_get_monotonic() as isize;
+                Ok(())
+            }
+            Sys::CLOCK_CREATE => self.sys_clock_create(a0 as _, a1.into(), a2.into()),
+            Sys::CLOCK_READ => self.sys_clock_read(a0 as _, a1.into()),
+            Sys::CLOCK_GET_DETAILS => self.sys_clock_get_details(a0 as _, a1 as _, a2.into()),
+            Sys::CLOCK_UPDATE => self.sys_clock_update(a0 as _, a1 as _, a2.into()),
+            Sys::CPRNG_DRAW_ONCE => self.sys_cprng_draw_once(a0.into(), a1 as _),
+            Sys::CPRNG_ADD_ENTROPY => self.sys_cprng_add_entropy(a0.into(), a1 as _),
+            Sys::DEBUG_READ => {
+                self.sys_debug_read(a0 as _, a1.into(), a2 as _, a3.into())
+                    .await
+            }
+            Sys::DEBUG_WRITE => self.sys_debug_write(a0.into(), a1 as _),
+            Sys::DEBUG_SEND_COMMAND => self.sys_debug_send_command(a0 as _, a1.into(), a2 as _),
             Sys::DEBUGLOG_CREATE => self.sys_debuglog_create(a0 as _, a1 as _, a2.into()),
             Sys::DEBUGLOG_WRITE => self.sys_debuglog_write(a0 as _, a1 as _, a2.into(), a3 as _),
             Sys::DEBUGLOG_READ => self.sys_debuglog_read(a0 as _, a1 as _, a2.into(), a3 as _),
+            Sys::ETH_GET_NTH_DEVICE => {
+                self.sys_eth_get_nth_device(a0 as _, a1 as _, a2.into(), a3.into())
+            }
+            Sys::ETH_SET_RINGS => self.sys_eth_set_rings(a0 as _, a1 as _, a2 as _),
+            Sys::ETH_BIND_PORT => self.sys_eth_bind_port(a0 as _, a1 as _, a2 as _),
+            Sys::ETH_TX_KICK => self.sys_eth_tx_kick(a0 as _, a1.into()),
+            Sys::ETH_GET_STATUS => self.sys_eth_get_status(a0 as _, a1.into()),
+            Sys::FIFO_CREATE => {
+                self.sys_fifo_create(a0 as _, a1 as _, a2 as _, a3.into(), a4.into())
+            }
+            Sys::FIFO_WRITE => self.sys_fifo_write(a0 as _, a1 as _, a2.into(), a3 as _, a4.into()),
+            Sys::FIFO_READ => self.sys_fifo_read(a0 as _, a1 as _, a2.into(), a3 as _, a4.into()),
+            Sys::FRAMEBUFFER_GET_INFO => {
+                self.sys_framebuffer_get_info(a0 as _, a1.into(), a2.into(), a3.into(), a4.into())
+            }
+            Sys::FRAMEBUFFER_GET_VMO => self.sys_framebuffer_get_vmo(a0 as _, a1.into()),
+            Sys::FRAMEBUFFER_FLUSH => {
+                self.sys_framebuffer_flush(a0 as _, a1 as _, a2 as _, a3 as _, a4 as _)
+            }
+            Sys::FUTEX_WAIT => {
+                self.sys_futex_wait(a0.into(), a1 as _, a2 as _, a3.into())
+                    .await
+            }
+            Sys::FUTEX_WAKE => self.sys_futex_wake(a0.into(), a1 as _),
+            Sys::FUTEX_WAKE_SINGLE_OWNER => self.sys_futex_wake_single_owner(a0.into()),
+            Sys::FUTEX_GET_OWNER => self.sys_futex_get_owner(a0.into(), a1.into()),
+            Sys::FUTEX_REQUEUE => {
+                self.sys_futex_requeue(a0.into(), a1 as _, a2 as _, a3.into(), a4 as _, a5 as _)
+            }
+            Sys::GUEST_CREATE => self.sys_guest_create(a0 as _, a1 as _, a2.into(), a3.into()),
+            Sys::GUEST_SET_TRAP => {
+                self.sys_guest_set_trap(a0 as _, a1 as _, a2 as _, a3 as _, a4 as _, a5 as _)
+            }
+            Sys::HANDLE_CLOSE => self.sys_handle_close(a0 as _),
+            Sys::HANDLE_CLOSE_MANY => self.sys_handle_close_many(a0.into(), a1 as _),
+            Sys::HANDLE_DUPLICATE => self.sys_handle_duplicate(a0 as _, a1 as _, a2.into()),
+            Sys::HANDLE_REPLACE => self.sys_handle_replace(a0 as _, a1 as _, a2.into()),
+            Sys::INTERRUPT_CREATE => {
+                self.sys_interrupt_create(a0 as _, a1 as _, a2 as _, a3.into())
+            }
+            Sys::INTERRUPT_BIND => self.sys_interrupt_bind(a0 as _, a1 as _, a2 as _, a3 as _),
+            Sys::INTERRUPT_TRIGGER => self.sys_interrupt_trigger(a0 as _, a1 as _, a2 as _),
+            Sys::INTERRUPT_ACK => self.sys_interrupt_ack(a0 as _),
+            Sys::INTERRUPT_WAIT => self.sys_interrupt_wait(a0 as _, a1.into()).await,
+            Sys::INTERRUPT_DESTROY => self.sys_interrupt_destroy(a0 as _),
+            Sys::IOMMU_CREATE => {
+                self.sys_iommu_create(a0 as _, a1 as _, a2.into(), a3 as _, a4.into())
+            }
+            Sys::IOPORTS_REQUEST => self.sys_ioports_request(a0 as _, a1 as _, a2 as _),
+            Sys::IOPORTS_RELEASE => self.sys_ioports_release(a0 as _, a1 as _, a2 as _),
+            Sys::NANOSLEEP => self.sys_nanosleep(a0.into()).await,
+            Sys::OBJECT_GET_INFO => {
+                self.sys_object_get_info(a0 as _, a1 as _, a2 as _, a3 as _, a4.into(), a5.into())
+            }
+            Sys::OBJECT_GET_PROPERTY => {
+                self.sys_object_get_property(a0 as _, a1 as _, a2 as _, a3 as _)
+            }
+            Sys::OBJECT_SET_PROPERTY => {
+                self.sys_object_set_property(a0 as _, a1 as _, a2 as _, a3 as _)
+            }
+            Sys::OBJECT_SET_PROFILE => self.sys_object_set_profile(a0 as _, a1 as _, a2 as _),
+            Sys::OBJECT_WAIT_ASYNC => {
+                self.sys_object_wait_async(a0 as _, a1 as _, a2 as _, a3 as _, a4 as _)
+            }
+            Sys::PAGER_CREATE => self.sys_pager_create(a0 as _, a1.into()),
+            Sys::PAGER_CREATE_VMO => {
+                self.sys_pager_create_vmo(a0 as _, a1 as _, a2 as _, a3 as _, a4 as _, a5.into())
+            }
+            Sys::PAGER_DETACH_VMO => self.sys_pager_detach_vmo(a0 as _, a1 as _),
+            Sys::PAGER_OP_RANGE => {
+                self.sys_pager_op_range(a0 as _, a1 as _, a2 as _, a3 as _, a4 as _, a5 as _)
+            }
+            Sys::PAGER_QUERY_DIRTY_RANGES => self.sys_pager_query_dirty_ranges(
+                a0 as _,
+                a1 as _,
+                a2 as _,
+                a3 as _,
+                a4 as _,
+                a5 as _,
+                a6.into(),
+                a7.into(),
+            ),
+            Sys::PCI_CONFIG_READ => self.sys_pci_config_read(a0 as _, a1 as _, a2 as _, a3.into()),
+            Sys::PCI_CONFIG_WRITE => self.sys_pci_config_write(a0 as _, a1 as _, a2 as _, a3 as _),
+            Sys::PCI_GET_BAR => self.sys_pci_get_bar(a0 as _, a1 as _, a2.into(), a3.into()),
+            Sys::PCI_GET_NTH_DEVICE => {
+                self.sys_pci_get_nth_device(a0 as _, a1 as _, a2.into(), a3.into())
+            }
+            Sys::PCI_MAP_INTERRUPT => self.sys_pci_map_interrupt(a0 as _, a1 as _, a2.into()),
+            Sys::PCI_QUERY_IRQ_MODE => self.sys_pci_query_irq_mode(a0 as _, a1 as _, a2.into()),
+            Sys::PCI_SET_IRQ_MODE => self.sys_pci_set_irq_mode(a0 as _, a1 as _, a2 as _),
+            Sys::PMT_UNPIN => self.sys_pmt_unpin(a0 as _),
+            Sys::PORT_CREATE => self.sys_port_create(a0 as _, a1.into()),
+            Sys::PORT_QUEUE => self.sys_port_queue(a0 as _, a1.into()),
+            Sys::PORT_WAIT => self.sys_port_wait(a0 as _, a1.into(), a2.into()).await,
+            Sys::PROFILE_CREATE => self.sys_profile_create(a0 as _, a1 as _, a2.into(), a3.into()),
+            Sys::PROCESS_EXIT => self.sys_process_exit(a0 as _),
+            Sys::RESOURCE_CREATE => self.sys_resource_create(
+                a0 as _,
+                a1 as _,
+                a2 as _,
+                a3 as _,
+                a4.into(),
+                a5 as _,
+                a6.into(),
+            ),
+            Sys::SMC_CALL => self.sys_smc_call(a0 as _, a1.into(), a2.into()),
+            Sys::SOCKET_CREATE => self.sys_socket_create(a0 as _, a1.into(), a2.into()),
+            Sys::SOCKET_WRITE => {
+                self.sys_socket_write(a0 as _, a1 as _, a2.into(), a3 as _, a4.into())
+            }
+            Sys::SOCKET_READ => {
+                self.sys_socket_read(a0 as _, a1 as _, a2.into(), a3 as _, a4.into())
+            }
+            Sys::SOCKET_SHUTDOWN => self.sys_socket_shutdown(a0 as _, a1 as _),
+            Sys::SYSTEM_GET_EVENT => self.sys_system_get_event(a0 as _, a1 as _, a2.into()),
+            Sys::SYSTEM_POWERCTL => self.sys_system_powerctl(a0 as _, a1 as _, a2 as _),
+            Sys::TICKS_GET_VIA_KERNEL => {
+                value = self.sys_ticks_get() as isize;
+                Ok(())
+            }
+            Sys::TIMER_CREATE => self.sys_timer_create(a0 as _, a1 as _, a2.into()),
+            Sys::TIMER_SET => self.sys_timer_set(a0 as _, a1.into(), a2 as _),
+            Sys::TIMER_CANCEL => self.sys_timer_cancel(a0 as _),
+            Sys::VCPU_CREATE => self.sys_vcpu_create(a0 as _, a1 as _, a2 as _, a3.into()),
+            Sys::VCPU_RESUME => self.sys_vcpu_resume(a0 as _, a1.into()).await,
+            Sys::VCPU_INTERRUPT => self.sys_vcpu_interrupt(a0 as _, a1 as _),
+            Sys::VCPU_READ_STATE => self.sys_vcpu_read_state(a0 as _, a1 as _, a2.into(), a3 as _),
+            Sys::VCPU_WRITE_STATE => self.sys_vcpu_write_state(a0 as _, a1 as _, a2 as _, a3 as _),
+            Sys::VMAR_MAP => self.sys_vmar_map(
+                a0 as _,
+                a1 as _,
+                a2 as _,
+                a3 as _,
+                a4 as _,
+                a5 as _,
+                a6.into(),
+            ),
+            Sys::VMAR_OP_RANGE => {
+                self.sys_vmar_op_range(a0 as _, a1 as _, a2 as _, a3 as _, a4 as _, a5 as _)
+            }
+            Sys::VMO_READ => self.sys_vmo_read(a0 as _, a1.into(), a2 as _, a3 as _),
+            Sys::VMO_WRITE => self.sys_vmo_write(a0 as _, a1.into(), a2 as _, a3 as _),
+            Sys::VMO_CREATE_PHYSICAL => {
+                self.sys_vmo_create_physical(a0 as _, a1 as _, a2 as _, a3.into())
+            }
+            Sys::VMO_REPLACE_AS_EXECUTABLE => {
+                self.sys_vmo_replace_as_executable(a0 as _, a1 as _, a2.into())
+            }
             _ => {
                 error!("syscall unimplemented: {:?}", sys_type);
                 Err(ZxError::NOT_SUPPORTED)
             }
         };
-        info!("{}|{} {:?} <= {:?}", proc_name, thread_name, sys_type, ret);
+        if ret == Err(ZxError::BAD_HANDLE) {
+            // the result is always BAD_HANDLE whether allowed or denied,
+            // but the process may be killed
+            proc.check_policy(PolicyCondition::BadHandle).ok();
+        }
+        let ret = ret.map(|()| value);
+        log!(
+            target: target,
+            level,
+            "[{}:{}] {}|{} {:?} <= {:?}",
+            pid,
+            tid,
+            proc_name,
+            thread_name,
+            sys_type,
+            ret
+        );
         match ret {
-            Ok(_) => 0,
+            Ok(value) => value,
             Err(err) => err as isize,
         }
     }
 }
+
+/// Whether the syscall is permitted by the vDSO variant mapped by the process.
+///
+/// The test-only syscalls are split between the test variants: `Test1`
+/// exports the numbered ones, and `Test2` the wrapper and the handle
+/// creation. The `Next` variant exports the syscalls in development, none of
+/// which is dispatched, so it permits the same ones as `Full`. A process
+/// without a vDSO is restricted as with `Full`.
+fn vdso_permits(variant: Option<VdsoVariant>, sys_type: &Sys) -> bool {
+    let test1 = matches!(
+        sys_type,
+        Sys::SYSCALL_TEST_0
+            | Sys::SYSCALL_TEST_1
+            | Sys::SYSCALL_TEST_2
+            | Sys::SYSCALL_TEST_3
+            | Sys::SYSCALL_TEST_4
+            | Sys::SYSCALL_TEST_5
+            | Sys::SYSCALL_TEST_6
+            | Sys::SYSCALL_TEST_7
+            | Sys::SYSCALL_TEST_8
+    );
+    let test2 = matches!(
+        sys_type,
+        Sys::SYSCALL_TEST_WRAPPER | Sys::SYSCALL_TEST_HANDLE_CREATE
+    );
+    match variant.unwrap_or(VdsoVariant::Full) {
+        VdsoVariant::Full | VdsoVariant::Next => !test1 && !test2,
+        VdsoVariant::Test1 => !test2,
+        VdsoVariant::Test2 => !test1,
+    }
+}
+
+/// Get the policy condition checked before the syscall creating an object.
+fn new_object_policy(sys_type: &Sys) -> Option<PolicyCondition> {
+    let condition = match sys_type {
+        Sys::VMO_CREATE => PolicyCondition::NewVMO,
+        Sys::CHANNEL_CREATE => PolicyCondition::NewChannel,
+        Sys::EVENT_CREATE => PolicyCondition::NewEvent,
+        Sys::EVENTPAIR_CREATE => PolicyCondition::NewEventPair,
+        Sys::PORT_CREATE => PolicyCondition::NewPort,
+        Sys::SOCKET_CREATE => PolicyCondition::NewSocket,
+        Sys::FIFO_CREATE => PolicyCondition::NewFIFO,
+        Sys::TIMER_CREATE => PolicyCondition::NewTimer,
+        Sys::PROCESS_CREATE => PolicyCondition::NewProcess,
+        Sys::PROFILE_CREATE => PolicyCondition::NewProfile,
+        _ => return None,
+    };
+    Some(condition)
+}
@@ -0,0 +1,264 @@
+use {
+    super::*,
+    numeric_enum_macro::numeric_enum,
+    zircon_object::{
+        dev::*,
+        ipc::{Socket, SocketInfo},
+        signal::{Port, WaitAsyncOptions},
+        task::{Process, Thread, ThreadStats},
+        vm::*,
+    },
+};
+
+impl Syscall<'_> {
+    /// Get the value of an object property.
+    pub fn sys_object_get_property(
+        &self,
+        handle_value: HandleValue,
+        property: u32,
+        buffer: usize,
+        buffer_size: usize,
+    ) -> ZxResult {
+        let property = Property::try_from(property).map_err(|_| ZxError::INVALID_ARGS)?;
+        info!(
+            "object.get_property: handle={:#x?}, property={:?}, buffer=({:#x?}; {:#x?})",
+            handle_value, property, buffer, buffer_size
+        );
+        let proc = self.thread.proc();
+        let object = proc.get_dyn_object_with_rights(handle_value, Rights::GET_PROPERTY)?;
+        match property {
+            Property::Name => {
+                if buffer_size < MAX_NAME_LEN {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                let s = object.name();
+                let mut name = [0u8; MAX_NAME_LEN];
+                let len = s.len().min(MAX_NAME_LEN - 1);
+                name[..len].copy_from_slice(&s.as_bytes()[..len]);
+                UserOutPtr::<u8>::from(buffer).write_array(&name)?;
+            }
+            Property::SocketRxThreshold | Property::SocketTxThreshold => {
+                if buffer_size < core::mem::size_of::<usize>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                let socket = object
+                    .downcast_arc::<Socket>()
+                    .map_err(|_| ZxError::WRONG_TYPE)?;
+                let threshold = if property == Property::SocketRxThreshold {
+                    socket.read_threshold()
+                } else {
+                    socket.write_threshold()
+                };
+                UserOutPtr::<usize>::from(buffer).write(threshold)?;
+            }
+            Property::ProcessSyscallTrace => {
+                if buffer_size < core::mem::size_of::<u32>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                let proc = object
+                    .downcast_arc::<Process>()
+                    .map_err(|_| ZxError::WRONG_TYPE)?;
+                UserOutPtr::<u32>::from(buffer).write(proc.syscall_trace() as u32)?;
+            }
+            _ => {
+                warn!("not supported property: {:?}", property);
+                return Err(ZxError::NOT_SUPPORTED);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the value of an object property.
+    pub fn sys_object_set_property(
+        &self,
+        handle_value: HandleValue,
+        property: u32,
+        buffer: usize,
+        buffer_size: usize,
+    ) -> ZxResult {
+        let property = Property::try_from(property).map_err(|_| ZxError::INVALID_ARGS)?;
+        info!(
+            "object.set_property: handle={:#x?}, property={:?}, buffer=({:#x?}; {:#x?})",
+            handle_value, property, buffer, buffer_size
+        );
+        let proc = self.thread.proc();
+        let object = proc.get_dyn_object_with_rights(handle_value, Rights::SET_PROPERTY)?;
+        match property {
+            Property::Name => {
+                let length = buffer_size.min(MAX_NAME_LEN - 1);
+                let s = UserInPtr::<u8>::from(buffer).read_string(length)?;
+                object.set_name(s.trim_end_matches('\0'));
+            }
+            Property::SocketRxThreshold | Property::SocketTxThreshold => {
+                if buffer_size < core::mem::size_of::<usize>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                let socket = object
+                    .downcast_arc::<Socket>()
+                    .map_err(|_| ZxError::WRONG_TYPE)?;
+                let threshold = UserInPtr::<usize>::from(buffer).read()?;
+                if property == Property::SocketRxThreshold {
+                    socket.set_read_threshold(threshold)?;
+                } else {
+                    socket.set_write_threshold(threshold)?;
+                }
+            }
+            Property::ProcessSyscallTrace => {
+                if buffer_size < core::mem::size_of::<u32>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                let proc = object
+                    .downcast_arc::<Process>()
+                    .map_err(|_| ZxError::WRONG_TYPE)?;
+                match UserInPtr::<u32>::from(buffer).read()? {
+                    0 => proc.set_syscall_trace(false),
+                    1 => proc.set_syscall_trace(true),
+                    _ => return Err(ZxError::INVALID_ARGS),
+                }
+            }
+            _ => {
+                warn!("not supported property: {:?}", property);
+                return Err(ZxError::NOT_SUPPORTED);
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe for signals on an object, and send packets to `port` when
+    /// the signals are asserted.
+    pub fn sys_object_wait_async(
+        &self,
+        handle_value: HandleValue,
+        port_handle_value: HandleValue,
+        key: u64,
+        signals: u32,
+        options: u32,
+    ) -> ZxResult {
+        let signals = Signal::from_bits(signals).ok_or(ZxError::INVALID_ARGS)?;
+        info!(
+            "object.wait_async: handle={:#x}, port={:#x}, key={:#x}, signal={:?}, options={:#X}",
+            handle_value, port_handle_value, key, signals, options
+        );
+        let options = WaitAsyncOptions::from_bits(options).ok_or(ZxError::INVALID_ARGS)?;
+        let proc = self.thread.proc();
+        let object = proc.get_dyn_object_with_rights(handle_value, Rights::WAIT)?;
+        let port = proc.get_object_with_rights::<Port>(port_handle_value, Rights::WRITE)?;
+        object.send_signal_to_port_async(signals, &port, key, options);
+        Ok(())
+    }
+
+    /// Query information about an object.
+    pub fn sys_object_get_info(
+        &self,
+        handle: HandleValue,
+        topic: u32,
+        buffer: usize,
+        buffer_size: usize,
+        mut actual: UserOutPtr<usize>,
+        mut avail: UserOutPtr<usize>,
+    ) -> ZxResult {
+        let topic = Topic::try_from(topic).map_err(|_| ZxError::INVALID_ARGS)?;
+        info!(
+            "object.get_info: handle={:#x?}, topic={:?}, buffer=({:#x?}; {:#x?})",
+            handle, topic, buffer, buffer_size,
+        );
+        let proc = self.thread.proc();
+        match topic {
+            Topic::KmemStats => {
+                proc.get_object::<Resource>(handle)?
+                    .validate(ResourceKind::ROOT)?;
+                if buffer_size < core::mem::size_of::<KmemInfo>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                UserOutPtr::<KmemInfo>::from(buffer).write(KmemInfo::collect())?;
+                actual.write_if_not_null(1)?;
+                avail.write_if_not_null(1)?;
+            }
+            Topic::Bti => {
+                let bti = proc
+                    .get_object_with_rights::<BusTransactionInitiator>(handle, Rights::INSPECT)?;
+                if buffer_size < core::mem::size_of::<BtiInfo>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                UserOutPtr::<BtiInfo>::from(buffer).write(bti.get_info())?;
+                actual.write_if_not_null(1)?;
+                avail.write_if_not_null(1)?;
+            }
+            Topic::Socket => {
+                let socket = proc.get_object_with_rights::<Socket>(handle, Rights::INSPECT)?;
+                if buffer_size < core::mem::size_of::<SocketInfo>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                UserOutPtr::<SocketInfo>::from(buffer).write(socket.get_info())?;
+                actual.write_if_not_null(1)?;
+                avail.write_if_not_null(1)?;
+            }
+            Topic::ThreadStats => {
+                let thread = proc.get_object_with_rights::<Thread>(handle, Rights::INSPECT)?;
+                if buffer_size < core::mem::size_of::<ThreadStats>() {
+                    return Err(ZxError::BUFFER_TOO_SMALL);
+                }
+                UserOutPtr::<ThreadStats>::from(buffer).write(thread.get_stats())?;
+                actual.write_if_not_null(1)?;
+                avail.write_if_not_null(1)?;
+            }
+            _ => {
+                warn!("not supported info topic: {:?}", topic);
+                return Err(ZxError::NOT_SUPPORTED);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maximum length of an object name, including the trailing `\0`.
+pub(crate) const MAX_NAME_LEN: usize = 32;
+
+numeric_enum! {
+    #[repr(u32)]
+    #[derive(Debug, Eq, PartialEq)]
+    enum Property {
+        Name = 3,
+        RegisterFs = 4,
+        ProcessDebugAddr = 5,
+        ProcessVdsoBaseAddress = 6,
+        ProcessBreakOnLoad = 7,
+        SocketRxThreshold = 12,
+        SocketTxThreshold = 13,
+        ExceptionState = 16,
+        ExceptionStrategy = 18,
+        /// Whether syscalls of a process are traced, a `u32` of 0 or 1.
+        /// It is an extension of zCore.
+        ProcessSyscallTrace = 0x1000,
+    }
+}
+
+numeric_enum! {
+    #[repr(u32)]
+    #[derive(Debug)]
+    enum Topic {
+        None = 0,
+        HandleValid = 1,
+        HandleBasic = 2,
+        Process = 3,
+        ProcessThreads = 4,
+        Vmar = 7,
+        JobChildren = 8,
+        JobProcess = 9,
+        Thread = 10,
+        ThreadExceptionReport = 11,
+        TaskStats = 12,
+        ProcessMaps = 13,
+        ProcessVmos = 14,
+        ThreadStats = 15,
+        CpuStats = 16,
+        KmemStats = 17,
+        Resource = 18,
+        HandleCount = 19,
+        Bti = 20,
+        ProcessHandleStats = 21,
+        Socket = 22,
+        Vmo = 23,
+        Job = 24,
+    }
+}
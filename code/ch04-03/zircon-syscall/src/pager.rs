@@ -0,0 +1,109 @@
+use {super::*, zircon_object::vm::*};
+
+impl Syscall<'_> {
+    /// Create a new pager object.
+    pub fn sys_pager_create(&self, options: u32, mut out: UserOutPtr<HandleValue>) -> ZxResult {
+        info!("pager.create: options={:#x}", options);
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let pager = Pager::create();
+        out.write(proc.add_handle(Handle::new(pager, Rights::DEFAULT_PAGER)))?;
+        Ok(())
+    }
+
+    /// Create a pager-backed VMO.
+    ///
+    /// Page requests are not supported yet, so `port` and `key` are ignored.
+    pub fn sys_pager_create_vmo(
+        &self,
+        pager: HandleValue,
+        options: u32,
+        port: HandleValue,
+        key: u64,
+        size: usize,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "pager.create_vmo: pager={:#x}, options={:#x}, port={:#x}, key={:#x}, size={:#x}",
+            pager, options, port, key, size
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let pager = proc.get_object::<Pager>(pager)?;
+        let pages = pages(size);
+        if pages * PAGE_SIZE < size {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let vmo = pager.create_vmo(pages);
+        out.write(proc.add_handle(Handle::new(vmo, Rights::DEFAULT_VMO)))?;
+        Ok(())
+    }
+
+    /// Detach a VMO from a pager.
+    pub fn sys_pager_detach_vmo(&self, pager: HandleValue, vmo: HandleValue) -> ZxResult {
+        info!("pager.detach_vmo: pager={:#x}, vmo={:#x}", pager, vmo);
+        let proc = self.thread.proc();
+        let pager = proc.get_object::<Pager>(pager)?;
+        let vmo = proc.get_object::<VmObject>(vmo)?;
+        pager.detach_vmo(&vmo)
+    }
+
+    /// Perform an operation on a range of a pager-backed VMO.
+    pub fn sys_pager_op_range(
+        &self,
+        pager: HandleValue,
+        op: u32,
+        vmo: HandleValue,
+        offset: usize,
+        len: usize,
+        data: u64,
+    ) -> ZxResult {
+        info!(
+            "pager.op_range: pager={:#x}, op={:#x}, vmo={:#x}, offset={:#x}, len={:#x}, data={:#x}",
+            pager, op, vmo, offset, len, data
+        );
+        let op = PagerOp::try_from(op).map_err(|_| ZxError::NOT_SUPPORTED)?;
+        if op != PagerOp::Fail && data != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let pager = proc.get_object::<Pager>(pager)?;
+        let vmo = proc.get_object::<VmObject>(vmo)?;
+        pager.op_range(op, &vmo, offset, len)
+    }
+
+    /// Query the dirty ranges of a pager-backed VMO.
+    ///
+    /// The prebuilt vDSO predates this syscall, so it is numbered among the
+    /// extensions of zCore.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sys_pager_query_dirty_ranges(
+        &self,
+        pager: HandleValue,
+        vmo: HandleValue,
+        offset: usize,
+        len: usize,
+        buffer: usize,
+        buffer_size: usize,
+        mut actual: UserOutPtr<usize>,
+        mut avail: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!(
+            "pager.query_dirty_ranges: pager={:#x}, vmo={:#x}, offset={:#x}, len={:#x}",
+            pager, vmo, offset, len
+        );
+        let proc = self.thread.proc();
+        let pager = proc.get_object::<Pager>(pager)?;
+        let vmo = proc.get_object::<VmObject>(vmo)?;
+        let ranges = pager.query_dirty_ranges(&vmo, offset, len)?;
+        let count = (buffer_size / core::mem::size_of::<VmoDirtyRange>()).min(ranges.len());
+        UserOutPtr::<VmoDirtyRange>::from(buffer).write_array(&ranges[..count])?;
+        actual.write_if_not_null(count)?;
+        avail.write_if_not_null(ranges.len())?;
+        Ok(())
+    }
+}
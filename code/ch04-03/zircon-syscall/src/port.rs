@@ -0,0 +1,54 @@
+use {
+    super::*,
+    alloc::boxed::Box,
+    core::convert::TryFrom,
+    zircon_object::{signal::*, task::ThreadState},
+};
+
+impl Syscall<'_> {
+    /// Create an IO port.
+    pub fn sys_port_create(&self, options: u32, mut out: UserOutPtr<HandleValue>) -> ZxResult {
+        info!("port.create: options={:#x}", options);
+        let port = Port::new(options)?;
+        let proc = self.thread.proc();
+        let handle = proc.add_handle(Handle::new(port, Rights::DEFAULT_PORT));
+        out.write(handle)?;
+        Ok(())
+    }
+
+    /// Queue a user packet to a port.
+    pub fn sys_port_queue(
+        &self,
+        handle_value: HandleValue,
+        packet_in: UserInPtr<PortPacketRepr>,
+    ) -> ZxResult {
+        info!("port.queue: handle={:#x}", handle_value);
+        let proc = self.thread.proc();
+        let port = proc.get_object_with_rights::<Port>(handle_value, Rights::WRITE)?;
+        let packet = PortPacket::try_from(packet_in.read()?)?;
+        port.push(packet);
+        Ok(())
+    }
+
+    /// Wait for a packet arrival in a port.
+    pub async fn sys_port_wait(
+        &self,
+        handle_value: HandleValue,
+        deadline: Deadline,
+        mut packet_out: UserOutPtr<PortPacketRepr>,
+    ) -> ZxResult {
+        info!(
+            "port.wait: handle={:#x}, deadline={:?}",
+            handle_value, deadline
+        );
+        let proc = self.thread.proc();
+        let port = proc.get_object_with_rights::<Port>(handle_value, Rights::READ)?;
+        let future = Box::pin(async move { Ok(port.wait().await) });
+        let packet = self
+            .thread
+            .blocking_run(future, ThreadState::BlockedPort, deadline.into())
+            .await?;
+        packet_out.write(packet.into())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+use {super::*, zircon_object::task::*};
+
+/// The layout of `zx_profile_info_t` in user space.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProfileInfoRepr {
+    flags: u32,
+    padding1: [u8; 4],
+    /// Union of the priority and the deadline parameters.
+    params: [u8; 24],
+    cpu_affinity_mask: CpuMask,
+}
+
+impl ProfileInfoRepr {
+    const FLAG_PRIORITY: u32 = 1 << 0;
+    const FLAG_CPU_MASK: u32 = 1 << 1;
+    const FLAG_DEADLINE: u32 = 1 << 2;
+
+    /// Decode the parameters specified by the flags.
+    fn decode(&self) -> ZxResult<ProfileInfo> {
+        let i64_at = |i: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&self.params[i * 8..i * 8 + 8]);
+            i64::from_ne_bytes(bytes)
+        };
+        let all = Self::FLAG_PRIORITY | Self::FLAG_CPU_MASK | Self::FLAG_DEADLINE;
+        if self.flags & !all != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let priority = self.flags & Self::FLAG_PRIORITY != 0;
+        let deadline = self.flags & Self::FLAG_DEADLINE != 0;
+        let params = match (priority, deadline) {
+            (true, true) => return Err(ZxError::INVALID_ARGS),
+            (true, false) => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(&self.params[..4]);
+                Some(SchedulerParams::Priority(i32::from_ne_bytes(bytes)))
+            }
+            (false, true) => Some(SchedulerParams::Deadline(SchedDeadlineParams {
+                capacity: i64_at(0),
+                relative_deadline: i64_at(1),
+                period: i64_at(2),
+            })),
+            (false, false) => None,
+        };
+        let cpu_affinity_mask =
+            Some(self.cpu_affinity_mask).filter(|_| self.flags & Self::FLAG_CPU_MASK != 0);
+        Ok(ProfileInfo {
+            params,
+            cpu_affinity_mask,
+        })
+    }
+}
+
+impl Syscall<'_> {
+    /// Create a scheduler profile from the root job.
+    pub fn sys_profile_create(
+        &self,
+        root_job: HandleValue,
+        options: u32,
+        profile: UserInPtr<ProfileInfoRepr>,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "profile.create: root_job={:#x}, options={:#x}, profile={:#x?}",
+            root_job, options, profile
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let job = proc.get_object_with_rights::<Job>(root_job, Rights::MANAGE_PROCESS)?;
+        if job.parent().is_some() {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        let info = profile.read()?.decode()?;
+        let profile = Profile::create(info)?;
+        out.write(proc.add_handle(Handle::new(profile, Rights::DEFAULT_PROFILE)))?;
+        Ok(())
+    }
+
+    /// Apply the scheduler profile to a thread.
+    pub fn sys_object_set_profile(
+        &self,
+        handle: HandleValue,
+        profile: HandleValue,
+        options: u32,
+    ) -> ZxResult {
+        info!(
+            "object.set_profile: handle={:#x}, profile={:#x}, options={:#x}",
+            handle, profile, options
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let thread = proc.get_object_with_rights::<Thread>(handle, Rights::MANAGE_THREAD)?;
+        let profile = proc.get_object_with_rights::<Profile>(profile, Rights::APPLY_PROFILE)?;
+        thread.set_profile(&profile);
+        Ok(())
+    }
+}
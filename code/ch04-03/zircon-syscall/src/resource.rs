@@ -0,0 +1,35 @@
+use {super::object::MAX_NAME_LEN, super::*, core::convert::TryFrom, zircon_object::dev::*};
+
+impl Syscall<'_> {
+    /// Create a resource object from the parent resource.
+    ///
+    /// The low 16 bits of `options` is the kind, and the high 16 bits are the flags.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sys_resource_create(
+        &self,
+        parent_rsrc: HandleValue,
+        options: u32,
+        base: u64,
+        size: usize,
+        name: UserInPtr<u8>,
+        name_size: usize,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "resource.create: parent={:#x}, options={:#x}, base={:#x}, size={:#x}",
+            parent_rsrc, options, base, size
+        );
+        let kind = ResourceKind::try_from(options & 0xffff).map_err(|_| ZxError::INVALID_ARGS)?;
+        if kind == ResourceKind::COUNT {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let flags = ResourceFlags::from_bits(options & 0xffff_0000).ok_or(ZxError::INVALID_ARGS)?;
+        let name = name.read_string(name_size.min(MAX_NAME_LEN - 1))?;
+        let proc = self.thread.proc();
+        let parent = proc.get_object_with_rights::<Resource>(parent_rsrc, Rights::WRITE)?;
+        let resource = parent.create_child(&name, kind, base as usize, size, flags)?;
+        let handle = proc.add_handle(Handle::new(resource, Rights::DEFAULT_RESOURCE));
+        out.write(handle)?;
+        Ok(())
+    }
+}
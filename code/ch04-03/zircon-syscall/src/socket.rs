@@ -0,0 +1,116 @@
+use {
+    super::*,
+    alloc::vec,
+    zircon_object::ipc::{Socket, SocketDisposition, SOCKET_SIZE},
+};
+
+impl Syscall<'_> {
+    /// Create a socket.
+    ///
+    /// Only stream sockets are supported now.
+    pub fn sys_socket_create(
+        &self,
+        options: u32,
+        mut out0: UserOutPtr<HandleValue>,
+        mut out1: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!("socket.create: options={:#x}", options);
+        const DATAGRAM: u32 = 1;
+        match options {
+            0 => {}
+            DATAGRAM => return Err(ZxError::NOT_SUPPORTED),
+            _ => return Err(ZxError::INVALID_ARGS),
+        }
+        let proc = self.thread.proc();
+        let (end0, end1) = Socket::create();
+        let handle0 = proc.add_handle(Handle::new(end0, Rights::DEFAULT_SOCKET));
+        let handle1 = proc.add_handle(Handle::new(end1, Rights::DEFAULT_SOCKET));
+        out0.write(handle0)?;
+        out1.write(handle1)?;
+        Ok(())
+    }
+
+    /// Write data to a socket.
+    pub fn sys_socket_write(
+        &self,
+        handle_value: HandleValue,
+        options: u32,
+        user_bytes: UserInPtr<u8>,
+        count: usize,
+        mut actual_count: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!(
+            "socket.write: socket={:#x?}, options={:#x?}, buffer=({:#x?}; {:#x?})",
+            handle_value, options, user_bytes, count,
+        );
+        if options != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        if user_bytes.is_null() && count > 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let socket = proc.get_object_with_rights::<Socket>(handle_value, Rights::WRITE)?;
+        // never copy more than the peer buffer can hold
+        let data = user_bytes.read_array(count.min(SOCKET_SIZE))?;
+        let actual = socket.write(&data)?;
+        actual_count.write_if_not_null(actual)?;
+        Ok(())
+    }
+
+    /// Read data from a socket.
+    pub fn sys_socket_read(
+        &self,
+        handle_value: HandleValue,
+        options: u32,
+        mut user_bytes: UserOutPtr<u8>,
+        count: usize,
+        mut actual_count: UserOutPtr<usize>,
+    ) -> ZxResult {
+        info!(
+            "socket.read: socket={:#x?}, options={:#x?}, buffer=({:#x?}; {:#x?})",
+            handle_value, options, user_bytes, count,
+        );
+        const PEEK: u32 = 1 << 3;
+        if options & !PEEK != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        if user_bytes.is_null() && count > 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let socket = proc.get_object_with_rights::<Socket>(handle_value, Rights::READ)?;
+        // never allocate more than the bytes available to read
+        let count = count.min(socket.get_info().rx_buf_available);
+        let mut data = vec![0; count];
+        let actual = socket.read(options & PEEK != 0, &mut data)?;
+        user_bytes.write_array(&data[..actual])?;
+        actual_count.write_if_not_null(actual)?;
+        Ok(())
+    }
+
+    /// Prevent reading or writing on a socket.
+    ///
+    /// Shutting down reading disables writing on the peer.
+    pub fn sys_socket_shutdown(&self, handle_value: HandleValue, options: u32) -> ZxResult {
+        info!(
+            "socket.shutdown: socket={:#x?}, options={:#x?}",
+            handle_value, options
+        );
+        const SHUTDOWN_WRITE: u32 = 1;
+        const SHUTDOWN_READ: u32 = 1 << 1;
+        if options & !(SHUTDOWN_WRITE | SHUTDOWN_READ) != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let disposition = |flag: u32| {
+            if options & flag != 0 {
+                SocketDisposition::WriteDisabled
+            } else {
+                SocketDisposition::None
+            }
+        };
+        let proc = self.thread.proc();
+        let socket = proc.get_object_with_rights::<Socket>(handle_value, Rights::WRITE)?;
+        socket.set_disposition(disposition(SHUTDOWN_WRITE), disposition(SHUTDOWN_READ))
+    }
+}
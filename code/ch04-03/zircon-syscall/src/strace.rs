@@ -0,0 +1,441 @@
+//! Decode the arguments of traced syscalls by their signatures.
+
+use {
+    super::consts::SyscallType as Sys, alloc::string::String, core::fmt,
+    kernel_hal::user::UserInPtr,
+};
+
+/// The kind of a syscall argument, deciding how it is shown.
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    /// A handle value.
+    Handle,
+    /// A pointer to user memory.
+    Ptr,
+    /// A string in user memory, whose length is the next argument.
+    Str,
+    /// An unsigned number, such as a size, a count or an offset.
+    Uint,
+    /// A signed number.
+    Int,
+    /// Options, rights, signals or other bit flags.
+    Flags,
+    /// A deadline in nanoseconds.
+    Time,
+}
+
+use Arg::*;
+
+/// The longest part of a string argument shown.
+const MAX_STR_LEN: usize = 64;
+
+/// Get the names and kinds of the arguments of the syscall.
+///
+/// Returns `None` for the syscalls not implemented, whose arguments are
+/// shown as they are.
+fn signature(sys_type: &Sys) -> Option<&'static [(&'static str, Arg)]> {
+    let sig: &'static [(&'static str, Arg)] = match sys_type {
+        Sys::BLOCK_GET_NTH_DEVICE => &[
+            ("resource", Handle),
+            ("index", Uint),
+            ("out_info", Ptr),
+            ("out_handle", Ptr),
+        ],
+        Sys::BLOCK_ATTACH_VMO => &[("handle", Handle), ("vmo", Handle), ("out_vmoid", Ptr)],
+        Sys::BLOCK_GET_FIFO => &[("handle", Handle), ("out_fifo", Ptr)],
+        Sys::BTI_CREATE => &[
+            ("iommu", Handle),
+            ("options", Flags),
+            ("bti_id", Uint),
+            ("out", Ptr),
+        ],
+        Sys::BTI_PIN => &[
+            ("bti", Handle),
+            ("options", Flags),
+            ("vmo", Handle),
+            ("offset", Uint),
+            ("size", Uint),
+            ("addrs", Ptr),
+            ("addrs_count", Uint),
+            ("pmt_out", Ptr),
+        ],
+        Sys::BTI_RELEASE_QUARANTINE => &[("bti", Handle)],
+        Sys::CHANNEL_CREATE => &[("options", Flags), ("out0", Ptr), ("out1", Ptr)],
+        Sys::CHANNEL_READ | Sys::CHANNEL_READ_ETC => &[
+            ("handle", Handle),
+            ("options", Flags),
+            ("bytes", Ptr),
+            ("handles", Ptr),
+            ("num_bytes", Uint),
+            ("num_handles", Uint),
+            ("actual_bytes", Ptr),
+            ("actual_handles", Ptr),
+        ],
+        Sys::CHANNEL_WRITE | Sys::CHANNEL_WRITE_ETC => &[
+            ("handle", Handle),
+            ("options", Flags),
+            ("bytes", Ptr),
+            ("num_bytes", Uint),
+            ("handles", Ptr),
+            ("num_handles", Uint),
+        ],
+        Sys::CHANNEL_CALL_NORETRY => &[
+            ("handle", Handle),
+            ("options", Flags),
+            ("deadline", Time),
+            ("args", Ptr),
+            ("actual_bytes", Ptr),
+            ("actual_handles", Ptr),
+        ],
+        Sys::CLOCK_GET => &[("clock_id", Uint), ("out", Ptr)],
+        Sys::CLOCK_GET_MONOTONIC_VIA_KERNEL | Sys::TICKS_GET_VIA_KERNEL => &[],
+        Sys::CLOCK_CREATE => &[("options", Flags), ("args", Ptr), ("out", Ptr)],
+        Sys::CLOCK_READ => &[("handle", Handle), ("now", Ptr)],
+        Sys::CLOCK_GET_DETAILS => &[("handle", Handle), ("options", Flags), ("details", Ptr)],
+        Sys::CLOCK_UPDATE => &[("handle", Handle), ("options", Flags), ("args", Ptr)],
+        Sys::CPRNG_DRAW_ONCE | Sys::CPRNG_ADD_ENTROPY => &[("buf", Ptr), ("len", Uint)],
+        Sys::DEBUG_READ => &[
+            ("handle", Handle),
+            ("buf", Ptr),
+            ("buf_size", Uint),
+            ("actual", Ptr),
+        ],
+        Sys::DEBUG_WRITE => &[("buf", Str), ("len", Uint)],
+        Sys::DEBUG_SEND_COMMAND => &[("resource", Handle), ("buf", Str), ("len", Uint)],
+        Sys::DEBUGLOG_CREATE => &[("rsrc", Handle), ("options", Flags), ("out", Ptr)],
+        Sys::DEBUGLOG_WRITE => &[
+            ("handle", Handle),
+            ("options", Flags),
+            ("buf", Str),
+            ("len", Uint),
+        ],
+        Sys::DEBUGLOG_READ => &[
+            ("handle", Handle),
+            ("options", Flags),
+            ("buf", Ptr),
+            ("len", Uint),
+        ],
+        Sys::ETH_GET_NTH_DEVICE => &[
+            ("resource", Handle),
+            ("index", Uint),
+            ("out_info", Ptr),
+            ("out_handle", Ptr),
+        ],
+        Sys::ETH_SET_RINGS => &[("handle", Handle), ("rx_vmo", Handle), ("tx_vmo", Handle)],
+        Sys::ETH_BIND_PORT => &[("handle", Handle), ("port", Handle), ("key", Uint)],
+        Sys::ETH_TX_KICK => &[("handle", Handle), ("out_count", Ptr)],
+        Sys::ETH_GET_STATUS => &[("handle", Handle), ("out_status", Ptr)],
+        Sys::FIFO_CREATE => &[
+            ("elem_count", Uint),
+            ("elem_size", Uint),
+            ("options", Flags),
+            ("out0", Ptr),
+            ("out1", Ptr),
+        ],
+        Sys::FIFO_WRITE | Sys::FIFO_READ => &[
+            ("handle", Handle),
+            ("elem_size", Uint),
+            ("data", Ptr),
+            ("count", Uint),
+            ("actual_count", Ptr),
+        ],
+        Sys::FRAMEBUFFER_GET_INFO => &[
+            ("resource", Handle),
+            ("format", Ptr),
+            ("width", Ptr),
+            ("height", Ptr),
+            ("stride", Ptr),
+        ],
+        Sys::FRAMEBUFFER_GET_VMO => &[("resource", Handle), ("out_vmo", Ptr)],
+        Sys::FRAMEBUFFER_FLUSH => &[
+            ("resource", Handle),
+            ("x", Uint),
+            ("y", Uint),
+            ("width", Uint),
+            ("height", Uint),
+        ],
+        Sys::FUTEX_WAIT => &[
+            ("value_ptr", Ptr),
+            ("current_value", Int),
+            ("new_futex_owner", Handle),
+            ("deadline", Time),
+        ],
+        Sys::FUTEX_WAKE => &[("value_ptr", Ptr), ("count", Uint)],
+        Sys::FUTEX_WAKE_SINGLE_OWNER => &[("value_ptr", Ptr)],
+        Sys::FUTEX_GET_OWNER => &[("value_ptr", Ptr), ("koid", Ptr)],
+        Sys::FUTEX_REQUEUE => &[
+            ("value_ptr", Ptr),
+            ("wake_count", Uint),
+            ("current_value", Int),
+            ("requeue_ptr", Ptr),
+            ("requeue_count", Uint),
+            ("new_requeue_owner", Handle),
+        ],
+        Sys::GUEST_CREATE => &[
+            ("resource", Handle),
+            ("options", Flags),
+            ("guest_handle", Ptr),
+            ("vmar_handle", Ptr),
+        ],
+        Sys::GUEST_SET_TRAP => &[
+            ("handle", Handle),
+            ("kind", Uint),
+            ("addr", Ptr),
+            ("size", Uint),
+            ("port_handle", Handle),
+            ("key", Uint),
+        ],
+        Sys::HANDLE_CLOSE => &[("handle", Handle)],
+        Sys::HANDLE_CLOSE_MANY => &[("handles", Ptr), ("num", Uint)],
+        Sys::HANDLE_DUPLICATE | Sys::HANDLE_REPLACE => {
+            &[("handle", Handle), ("rights", Flags), ("out", Ptr)]
+        }
+        Sys::INTERRUPT_CREATE => &[
+            ("resource", Handle),
+            ("src_num", Uint),
+            ("options", Flags),
+            ("out", Ptr),
+        ],
+        Sys::INTERRUPT_BIND => &[
+            ("interrupt", Handle),
+            ("port", Handle),
+            ("key", Uint),
+            ("options", Flags),
+        ],
+        Sys::INTERRUPT_TRIGGER => &[
+            ("interrupt", Handle),
+            ("options", Flags),
+            ("timestamp", Int),
+        ],
+        Sys::INTERRUPT_ACK | Sys::INTERRUPT_DESTROY => &[("interrupt", Handle)],
+        Sys::INTERRUPT_WAIT => &[("interrupt", Handle), ("out", Ptr)],
+        Sys::IOMMU_CREATE => &[
+            ("resource", Handle),
+            ("type", Uint),
+            ("desc", Ptr),
+            ("desc_size", Uint),
+            ("out", Ptr),
+        ],
+        Sys::IOPORTS_REQUEST | Sys::IOPORTS_RELEASE => {
+            &[("resource", Handle), ("io_addr", Flags), ("len", Uint)]
+        }
+        Sys::NANOSLEEP => &[("deadline", Time)],
+        Sys::OBJECT_GET_INFO => &[
+            ("handle", Handle),
+            ("topic", Uint),
+            ("buffer", Ptr),
+            ("buffer_size", Uint),
+            ("actual", Ptr),
+            ("avail", Ptr),
+        ],
+        Sys::OBJECT_GET_PROPERTY | Sys::OBJECT_SET_PROPERTY => &[
+            ("handle", Handle),
+            ("property", Uint),
+            ("buffer", Ptr),
+            ("buffer_size", Uint),
+        ],
+        Sys::OBJECT_SET_PROFILE => &[("handle", Handle), ("profile", Handle), ("options", Flags)],
+        Sys::OBJECT_WAIT_ASYNC => &[
+            ("handle", Handle),
+            ("port", Handle),
+            ("key", Uint),
+            ("signals", Flags),
+            ("options", Flags),
+        ],
+        Sys::PAGER_CREATE => &[("options", Flags), ("out", Ptr)],
+        Sys::PAGER_CREATE_VMO => &[
+            ("pager", Handle),
+            ("options", Flags),
+            ("port", Handle),
+            ("key", Uint),
+            ("size", Uint),
+            ("out", Ptr),
+        ],
+        Sys::PAGER_DETACH_VMO => &[("pager", Handle), ("vmo", Handle)],
+        Sys::PAGER_OP_RANGE => &[
+            ("pager", Handle),
+            ("op", Uint),
+            ("vmo", Handle),
+            ("offset", Uint),
+            ("len", Uint),
+            ("data", Uint),
+        ],
+        Sys::PAGER_QUERY_DIRTY_RANGES => &[
+            ("pager", Handle),
+            ("vmo", Handle),
+            ("offset", Uint),
+            ("len", Uint),
+            ("buffer", Ptr),
+            ("buffer_size", Uint),
+            ("actual", Ptr),
+            ("avail", Ptr),
+        ],
+        Sys::PCI_CONFIG_READ => &[
+            ("handle", Handle),
+            ("offset", Uint),
+            ("width", Uint),
+            ("out_val", Ptr),
+        ],
+        Sys::PCI_CONFIG_WRITE => &[
+            ("handle", Handle),
+            ("offset", Uint),
+            ("width", Uint),
+            ("val", Flags),
+        ],
+        Sys::PCI_GET_BAR => &[
+            ("handle", Handle),
+            ("bar_num", Uint),
+            ("out_bar", Ptr),
+            ("out_handle", Ptr),
+        ],
+        Sys::PCI_GET_NTH_DEVICE => &[
+            ("resource", Handle),
+            ("index", Uint),
+            ("out_info", Ptr),
+            ("out_handle", Ptr),
+        ],
+        Sys::PCI_MAP_INTERRUPT => &[("handle", Handle), ("which_irq", Int), ("out_handle", Ptr)],
+        Sys::PCI_QUERY_IRQ_MODE => &[("handle", Handle), ("mode", Uint), ("out_max_irqs", Ptr)],
+        Sys::PCI_SET_IRQ_MODE => &[
+            ("handle", Handle),
+            ("mode", Uint),
+            ("requested_irq_count", Uint),
+        ],
+        Sys::PMT_UNPIN => &[("pmt", Handle)],
+        Sys::PORT_CREATE => &[("options", Flags), ("out", Ptr)],
+        Sys::PORT_QUEUE => &[("handle", Handle), ("packet_in", Ptr)],
+        Sys::PORT_WAIT => &[("handle", Handle), ("deadline", Time), ("packet_out", Ptr)],
+        Sys::PROFILE_CREATE => &[
+            ("root_job", Handle),
+            ("options", Flags),
+            ("profile", Ptr),
+            ("out", Ptr),
+        ],
+        Sys::PROCESS_EXIT => &[("code", Int)],
+        Sys::RESOURCE_CREATE => &[
+            ("parent_rsrc", Handle),
+            ("options", Flags),
+            ("base", Flags),
+            ("size", Uint),
+            ("name", Str),
+            ("name_size", Uint),
+            ("out", Ptr),
+        ],
+        Sys::SMC_CALL => &[("resource", Handle), ("parameters", Ptr), ("out", Ptr)],
+        Sys::SOCKET_CREATE => &[("options", Flags), ("out0", Ptr), ("out1", Ptr)],
+        Sys::SOCKET_WRITE | Sys::SOCKET_READ => &[
+            ("handle", Handle),
+            ("options", Flags),
+            ("buffer", Ptr),
+            ("count", Uint),
+            ("actual_count", Ptr),
+        ],
+        Sys::SOCKET_SHUTDOWN => &[("handle", Handle), ("options", Flags)],
+        Sys::SYSTEM_GET_EVENT => &[("root_job", Handle), ("kind", Uint), ("out", Ptr)],
+        Sys::SYSTEM_POWERCTL => &[("resource", Handle), ("cmd", Uint), ("arg", Ptr)],
+        Sys::TIMER_CREATE => &[("options", Flags), ("clock_id", Uint), ("out", Ptr)],
+        Sys::TIMER_SET => &[("handle", Handle), ("deadline", Time), ("slack", Int)],
+        Sys::TIMER_CANCEL => &[("handle", Handle)],
+        Sys::VCPU_CREATE => &[
+            ("guest", Handle),
+            ("options", Flags),
+            ("entry", Ptr),
+            ("out", Ptr),
+        ],
+        Sys::VCPU_RESUME => &[("handle", Handle), ("packet_out", Ptr)],
+        Sys::VCPU_INTERRUPT => &[("handle", Handle), ("vector", Uint)],
+        Sys::VCPU_READ_STATE | Sys::VCPU_WRITE_STATE => &[
+            ("handle", Handle),
+            ("kind", Uint),
+            ("buffer", Ptr),
+            ("buffer_size", Uint),
+        ],
+        Sys::VMAR_MAP => &[
+            ("vmar", Handle),
+            ("options", Flags),
+            ("vmar_offset", Uint),
+            ("vmo", Handle),
+            ("vmo_offset", Uint),
+            ("len", Uint),
+            ("mapped_addr", Ptr),
+        ],
+        Sys::VMAR_OP_RANGE => &[
+            ("handle", Handle),
+            ("op", Uint),
+            ("addr", Ptr),
+            ("len", Uint),
+            ("buffer", Ptr),
+            ("buffer_size", Uint),
+        ],
+        Sys::VMO_READ | Sys::VMO_WRITE => &[
+            ("handle", Handle),
+            ("buf", Ptr),
+            ("offset", Uint),
+            ("len", Uint),
+        ],
+        Sys::VMO_CREATE_PHYSICAL => &[
+            ("resource", Handle),
+            ("paddr", Ptr),
+            ("size", Uint),
+            ("out", Ptr),
+        ],
+        Sys::VMO_REPLACE_AS_EXECUTABLE => &[("handle", Handle), ("vmex", Handle), ("out", Ptr)],
+        _ => return None,
+    };
+    Some(sig)
+}
+
+/// The arguments of a syscall, shown by its signature.
+pub struct SyscallArgs<'a> {
+    sys_type: &'a Sys,
+    args: &'a [usize; 8],
+}
+
+impl<'a> SyscallArgs<'a> {
+    pub fn new(sys_type: &'a Sys, args: &'a [usize; 8]) -> Self {
+        SyscallArgs { sys_type, args }
+    }
+}
+
+impl fmt::Display for SyscallArgs<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sig = match signature(self.sys_type) {
+            Some(sig) => sig,
+            None => return write!(f, "args={:x?}", self.args),
+        };
+        for (i, &(name, kind)) in sig.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            let arg = self.args[i];
+            write!(f, "{}=", name)?;
+            match kind {
+                Handle | Flags => write!(f, "{:#x}", arg)?,
+                Ptr if arg == 0 => f.write_str("NULL")?,
+                Ptr => write!(f, "{:#x}", arg)?,
+                Str => match read_str(arg, self.args[i + 1]) {
+                    Some(s) => write!(f, "{:?}", s)?,
+                    None => write!(f, "{:#x}", arg)?,
+                },
+                Uint => write!(f, "{}", arg)?,
+                Int => write!(f, "{}", arg as i64)?,
+                Time if arg as i64 == i64::MAX => f.write_str("INFINITE")?,
+                Time => write!(f, "{}ns", arg as i64)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Read the leading part of a string argument, or `None` if it is not
+/// readable.
+fn read_str(ptr: usize, len: usize) -> Option<String> {
+    let bytes = UserInPtr::<u8>::from(ptr)
+        .read_array(len.min(MAX_STR_LEN))
+        .ok()?;
+    let mut s = String::from_utf8_lossy(&bytes).into_owned();
+    if len > MAX_STR_LEN {
+        s.push_str("...");
+    }
+    Some(s)
+}
@@ -0,0 +1,61 @@
+use {
+    super::*,
+    zircon_object::{dev::*, task::Job, vm::*},
+};
+
+impl Syscall<'_> {
+    /// Get the kernel-owned event of `kind`, signaled by the memory pressure.
+    pub fn sys_system_get_event(
+        &self,
+        root_job: HandleValue,
+        kind: u32,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "system.get_event: root_job={:#x}, kind={:#x}",
+            root_job, kind
+        );
+        let proc = self.thread.proc();
+        let job = proc.get_object_with_rights::<Job>(root_job, Rights::MANAGE_PROCESS)?;
+        if job.parent().is_some() {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        let kind = SystemEventKind::try_from(kind).map_err(|_| ZxError::INVALID_ARGS)?;
+        let event = system_event(kind);
+        out.write(proc.add_handle(Handle::new(event, Rights::DEFAULT_SYSTEM_EVENT)))?;
+        Ok(())
+    }
+
+    /// Control the power of the system.
+    ///
+    /// Only reboot and shutdown are supported, which do not return on success.
+    pub fn sys_system_powerctl(&self, resource: HandleValue, cmd: u32, arg: usize) -> ZxResult {
+        info!(
+            "system.powerctl: resource={:#x}, cmd={:#x}, arg={:#x}",
+            resource, cmd, arg
+        );
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate(ResourceKind::ROOT)?;
+        match cmd {
+            POWERCTL_REBOOT | POWERCTL_REBOOT_BOOTLOADER | POWERCTL_REBOOT_RECOVERY => {
+                kernel_hal::reboot()
+            }
+            POWERCTL_SHUTDOWN => kernel_hal::shutdown(),
+            POWERCTL_ENABLE_ALL_CPUS
+            | POWERCTL_DISABLE_ALL_CPUS_BUT_PRIMARY
+            | POWERCTL_ACPI_TRANSITION_S_STATE
+            | POWERCTL_X86_SET_PKG_PL1 => Err(ZxError::NOT_SUPPORTED),
+            _ => Err(ZxError::INVALID_ARGS),
+        }
+    }
+}
+
+const POWERCTL_ENABLE_ALL_CPUS: u32 = 1;
+const POWERCTL_DISABLE_ALL_CPUS_BUT_PRIMARY: u32 = 2;
+const POWERCTL_ACPI_TRANSITION_S_STATE: u32 = 3;
+const POWERCTL_X86_SET_PKG_PL1: u32 = 4;
+const POWERCTL_REBOOT: u32 = 5;
+const POWERCTL_REBOOT_BOOTLOADER: u32 = 6;
+const POWERCTL_REBOOT_RECOVERY: u32 = 7;
+const POWERCTL_SHUTDOWN: u32 = 8;
@@ -0,0 +1,11 @@
+use super::*;
+
+impl Syscall<'_> {
+    /// Exit the current process with `code`, terminating all its threads.
+    pub fn sys_process_exit(&self, code: i64) -> ZxResult {
+        info!("proc.exit: code={:?}", code);
+        let proc = self.thread.proc();
+        proc.exit(code);
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use {
+    super::*,
+    core::time::Duration,
+    futures::future::pending,
+    zircon_object::{
+        signal::{Clock, ClockDetails, ClockOptions, ClockUpdateArgs},
+        task::ThreadState,
+    },
+};
+
+/// Deadline in nanoseconds, as passed to blocking syscalls.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Deadline(i64);
+
+impl From<usize> for Deadline {
+    fn from(x: usize) -> Self {
+        Deadline(x as i64)
+    }
+}
+
+impl Deadline {
+    /// Is the deadline after time 0?
+    pub fn is_positive(&self) -> bool {
+        self.0.is_positive()
+    }
+
+    /// A deadline which never expires.
+    pub fn forever() -> Self {
+        Deadline(i64::max_value())
+    }
+}
+
+impl From<Deadline> for Duration {
+    fn from(deadline: Deadline) -> Self {
+        Duration::from_nanos(deadline.0.max(0) as _)
+    }
+}
+
+/// The arguments to create a clock, version 1.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClockCreateArgsV1 {
+    backstop_time: i64,
+}
+
+/// The arguments to update a clock, version 1.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ClockUpdateArgsV1 {
+    rate_adjust: i32,
+    padding1: [u8; 4],
+    value: i64,
+    error_bound: u64,
+}
+
+/// Get the version of the arguments from the options.
+fn args_version(options: u64) -> u64 {
+    options >> ARGS_VERSION_SHIFT
+}
+
+const ZX_CLOCK_MONOTONIC: u32 = 0;
+const ZX_CLOCK_UTC: u32 = 1;
+const ZX_CLOCK_THREAD: u32 = 2;
+
+const ARGS_VERSION_SHIFT: u64 = 58;
+const ARGS_VERSION_MASK: u64 = 0x3f << ARGS_VERSION_SHIFT;
+
+impl Syscall<'_> {
+    /// Get the current monotonic time in nanoseconds.
+    ///
+    /// The vDSO computes it from the ticks without entering the kernel, this
+    /// is the fallback when ticks can not be read from userspace.
+    pub fn sys_clock_get_monotonic(&self) -> i64 {
+        info!("clock.get_monotonic");
+        kernel_hal::timer_now().as_nanos() as i64
+    }
+
+    /// Get the current time of the clock `clock_id` in nanoseconds.
+    ///
+    /// The UTC time is read from the UTC clock object.
+    pub fn sys_clock_get(&self, clock_id: u32, mut out: UserOutPtr<i64>) -> ZxResult {
+        info!("clock.get: clock_id={:#x}", clock_id);
+        let time = match clock_id {
+            ZX_CLOCK_MONOTONIC => kernel_hal::timer_now().as_nanos() as i64,
+            ZX_CLOCK_UTC => Clock::utc().read(),
+            ZX_CLOCK_THREAD => return Err(ZxError::NOT_SUPPORTED),
+            _ => return Err(ZxError::INVALID_ARGS),
+        };
+        out.write(time)?;
+        Ok(())
+    }
+
+    /// Sleep until the `deadline`.
+    ///
+    /// A deadline in the past only yields the CPU. If the thread is suspended
+    /// while sleeping, it keeps sleeping after resumed.
+    pub async fn sys_nanosleep(&self, deadline: Deadline) -> ZxResult {
+        info!("nanosleep: deadline={:?}", deadline);
+        if deadline.0 <= kernel_hal::timer_now().as_nanos() as i64 {
+            kernel_hal::yield_now().await;
+            return Ok(());
+        }
+        loop {
+            let ret = self
+                .thread
+                .blocking_run(
+                    pending::<ZxResult>(),
+                    ThreadState::BlockedSleeping,
+                    deadline.into(),
+                )
+                .await;
+            match ret {
+                Err(ZxError::TIMED_OUT) => return Ok(()),
+                Err(ZxError::INTERNAL_INTR_RETRY) => self.thread.wait_for_resume().await?,
+                ret => return ret,
+            }
+        }
+    }
+
+    /// Get the current value of the timebase.
+    pub fn sys_ticks_get(&self) -> u64 {
+        info!("ticks.get");
+        kernel_hal::timer_ticks()
+    }
+
+    /// Create a new clock object.
+    pub fn sys_clock_create(
+        &self,
+        options: u64,
+        args: UserInPtr<ClockCreateArgsV1>,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!("clock.create: options={:#x}, args={:#x?}", options, args);
+        let backstop_time = if args.is_null() {
+            0
+        } else {
+            if args_version(options) != 1 {
+                return Err(ZxError::INVALID_ARGS);
+            }
+            args.read()?.backstop_time
+        };
+        let options =
+            ClockOptions::from_bits(options & !ARGS_VERSION_MASK).ok_or(ZxError::INVALID_ARGS)?;
+        let clock = Clock::create(options, backstop_time)?;
+        let proc = self.thread.proc();
+        out.write(proc.add_handle(Handle::new(clock, Rights::DEFAULT_CLOCK)))?;
+        Ok(())
+    }
+
+    /// Read the current value of a clock.
+    pub fn sys_clock_read(&self, handle: HandleValue, mut now: UserOutPtr<i64>) -> ZxResult {
+        info!("clock.read: handle={:#x}", handle);
+        let proc = self.thread.proc();
+        let clock = proc.get_object_with_rights::<Clock>(handle, Rights::READ)?;
+        now.write(clock.read())?;
+        Ok(())
+    }
+
+    /// Get the details of a clock.
+    pub fn sys_clock_get_details(
+        &self,
+        handle: HandleValue,
+        options: u64,
+        mut details: UserOutPtr<ClockDetails>,
+    ) -> ZxResult {
+        info!(
+            "clock.get_details: handle={:#x}, options={:#x}",
+            handle, options
+        );
+        if args_version(options) != 1 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let clock = proc.get_object_with_rights::<Clock>(handle, Rights::READ)?;
+        details.write(clock.get_details())?;
+        Ok(())
+    }
+
+    /// Update the value, the rate or the error bound of a clock.
+    pub fn sys_clock_update(
+        &self,
+        handle: HandleValue,
+        options: u64,
+        args: UserInPtr<ClockUpdateArgsV1>,
+    ) -> ZxResult {
+        info!("clock.update: handle={:#x}, options={:#x}", handle, options);
+        const VALUE_VALID: u64 = 1 << 0;
+        const RATE_ADJUST_VALID: u64 = 1 << 1;
+        const ERROR_BOUND_VALID: u64 = 1 << 2;
+        if args_version(options) != 1 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let flags = options & !ARGS_VERSION_MASK;
+        if flags & !(VALUE_VALID | RATE_ADJUST_VALID | ERROR_BOUND_VALID) != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        let clock = proc.get_object_with_rights::<Clock>(handle, Rights::WRITE)?;
+        let args = args.read()?;
+        let valid = |bit| flags & bit != 0;
+        clock.update(ClockUpdateArgs {
+            value: Some(args.value).filter(|_| valid(VALUE_VALID)),
+            rate_adjust: Some(args.rate_adjust).filter(|_| valid(RATE_ADJUST_VALID)),
+            error_bound: Some(args.error_bound).filter(|_| valid(ERROR_BOUND_VALID)),
+        })
+    }
+}
@@ -0,0 +1,59 @@
+use {
+    super::*,
+    core::time::Duration,
+    zircon_object::signal::{Slack, Timer},
+};
+
+impl Syscall<'_> {
+    /// Create a timer.
+    ///
+    /// `options` is the slack mode, and only the monotonic clock is supported.
+    pub fn sys_timer_create(
+        &self,
+        options: u32,
+        clock_id: u32,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "timer.create: options={:#x}, clock_id={:#x}",
+            options, clock_id
+        );
+        if clock_id != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let slack = match options {
+            0 => Slack::Center,
+            1 => Slack::Early,
+            2 => Slack::Late,
+            _ => return Err(ZxError::INVALID_ARGS),
+        };
+        let proc = self.thread.proc();
+        let handle = Handle::new(Timer::create(slack), Rights::DEFAULT_TIMER);
+        out.write(proc.add_handle(handle))?;
+        Ok(())
+    }
+
+    /// Start a timer.
+    pub fn sys_timer_set(&self, handle: HandleValue, deadline: Deadline, slack: i64) -> ZxResult {
+        info!(
+            "timer.set: handle={:#x}, deadline={:?}, slack={:#x}",
+            handle, deadline, slack
+        );
+        if slack < 0 {
+            return Err(ZxError::OUT_OF_RANGE);
+        }
+        let proc = self.thread.proc();
+        let timer = proc.get_object_with_rights::<Timer>(handle, Rights::WRITE)?;
+        timer.set(deadline.into(), Duration::from_nanos(slack as u64));
+        Ok(())
+    }
+
+    /// Cancel a timer.
+    pub fn sys_timer_cancel(&self, handle: HandleValue) -> ZxResult {
+        info!("timer.cancel: handle={:#x}", handle);
+        let proc = self.thread.proc();
+        let timer = proc.get_object_with_rights::<Timer>(handle, Rights::WRITE)?;
+        timer.cancel();
+        Ok(())
+    }
+}
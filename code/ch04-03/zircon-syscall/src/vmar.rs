@@ -0,0 +1,135 @@
+use {super::*, bitflags::bitflags, kernel_hal::MMUFlags, zircon_object::vm::*};
+
+impl Syscall<'_> {
+    /// Add a memory mapping.
+    ///
+    /// Mapping with `PERM_EXECUTE` requires the VMO handle to have the `EXECUTE` right.
+    #[allow(clippy::too_many_arguments)]
+    pub fn sys_vmar_map(
+        &self,
+        vmar_handle: HandleValue,
+        options: u32,
+        vmar_offset: usize,
+        vmo_handle: HandleValue,
+        vmo_offset: usize,
+        len: usize,
+        mut mapped_addr: UserOutPtr<VirtAddr>,
+    ) -> ZxResult {
+        let options = VmOptions::from_bits(options).ok_or(ZxError::INVALID_ARGS)?;
+        info!(
+            "vmar.map: vmar_handle={:#x?}, options={:?}, vmar_offset={:#x?}, vmo_handle={:#x?}, vmo_offset={:#x?}, len={:#x?}",
+            vmar_handle, options, vmar_offset, vmo_handle, vmo_offset, len
+        );
+        let proc = self.thread.proc();
+        let (vmar, vmar_rights) = proc.get_object_and_rights::<VmAddressRegion>(vmar_handle)?;
+        let (vmo, vmo_rights) = proc.get_object_and_rights::<VmObject>(vmo_handle)?;
+        if !vmo_rights.contains(Rights::MAP) {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        if options.intersects(VmOptions::CAN_MAP_RXW | VmOptions::CAN_MAP_SPECIFIC) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        // the mapping is limited by the rights of both handles
+        let mut permissions = MMUFlags::empty();
+        permissions.set(
+            MMUFlags::READ,
+            vmo_rights.contains(Rights::READ) && vmar_rights.contains(Rights::READ),
+        );
+        permissions.set(
+            MMUFlags::WRITE,
+            vmo_rights.contains(Rights::WRITE) && vmar_rights.contains(Rights::WRITE),
+        );
+        permissions.set(
+            MMUFlags::EXECUTE,
+            vmo_rights.contains(Rights::EXECUTE) && vmar_rights.contains(Rights::EXECUTE),
+        );
+        let mut flags = MMUFlags::USER;
+        flags.set(MMUFlags::READ, options.contains(VmOptions::PERM_READ));
+        flags.set(MMUFlags::WRITE, options.contains(VmOptions::PERM_WRITE));
+        flags.set(MMUFlags::EXECUTE, options.contains(VmOptions::PERM_EXECUTE));
+        if !permissions.contains(flags & MMUFlags::RXW) {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        let vmar_offset = if options.contains(VmOptions::SPECIFIC) {
+            Some(vmar_offset)
+        } else if vmar_offset != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        } else {
+            None
+        };
+        let overwrite = options.contains(VmOptions::SPECIFIC_OVERWRITE);
+        // all mappings are committed eagerly
+        let vaddr = vmar.map_ext(
+            vmar_offset,
+            vmo,
+            vmo_offset,
+            len,
+            permissions,
+            flags,
+            overwrite,
+            true,
+        )?;
+        // the syscalls permitted are decided by the vDSO mapped, even if it
+        // is not executable yet, not to be bypassed by `vmar_protect`
+        if let Some(variant) = vmo.vdso_variant() {
+            if let Err(err) = proc.set_vdso_variant(variant) {
+                vmar.unmap(vaddr, roundup_pages(len))?;
+                return Err(err);
+            }
+        }
+        mapped_addr.write(vaddr)?;
+        Ok(())
+    }
+
+    /// Perform an operation on VMOs mapped into this VMAR.
+    pub fn sys_vmar_op_range(
+        &self,
+        handle: HandleValue,
+        op: u32,
+        addr: usize,
+        len: usize,
+        buffer: usize,
+        buffer_size: usize,
+    ) -> ZxResult {
+        info!(
+            "vmar.op_range: handle={:#x}, op={:#x}, addr={:#x}, len={:#x}, buffer=({:#x}; {:#x})",
+            handle, op, addr, len, buffer, buffer_size,
+        );
+        if buffer != 0 || buffer_size != 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let op = VmarOpType::try_from(op).map_err(|_| ZxError::INVALID_ARGS)?;
+        let proc = self.thread.proc();
+        let (vmar, rights) = proc.get_object_and_rights::<VmAddressRegion>(handle)?;
+        if matches!(op, VmarOpType::Commit | VmarOpType::Decommit)
+            && !rights.contains(Rights::WRITE)
+        {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        if op == VmarOpType::MapRange && !rights.contains(Rights::READ) {
+            return Err(ZxError::ACCESS_DENIED);
+        }
+        vmar.op_range(op, addr, len)
+    }
+}
+
+bitflags! {
+    /// Options of `vmar_map` and `vmar_allocate`.
+    struct VmOptions: u32 {
+        #[allow(clippy::identity_op)]
+        const PERM_READ             = 1 << 0;
+        const PERM_WRITE            = 1 << 1;
+        const PERM_EXECUTE          = 1 << 2;
+        const COMPACT               = 1 << 4;
+        const SPECIFIC              = 1 << 5;
+        const SPECIFIC_OVERWRITE    = 1 << 6;
+        const CAN_MAP_SPECIFIC      = 1 << 7;
+        const CAN_MAP_READ          = 1 << 8;
+        const CAN_MAP_WRITE         = 1 << 9;
+        const CAN_MAP_EXECUTE       = 1 << 10;
+        const MAP_RANGE             = 1 << 11;
+        const REQUIRE_NON_RESIZABLE = 1 << 12;
+        const ALLOW_FAULTS          = 1 << 13;
+        const CAN_MAP_RXW           = Self::CAN_MAP_READ.bits | Self::CAN_MAP_EXECUTE.bits | Self::CAN_MAP_WRITE.bits;
+    }
+}
@@ -0,0 +1,125 @@
+use {
+    super::*,
+    alloc::vec,
+    zircon_object::{dev::*, task::PolicyCondition, vm::*},
+};
+
+/// The bytes copied between a VMO and the user memory at a time.
+const VMO_COPY_CHUNK: usize = 16 * PAGE_SIZE;
+
+/// Check that `[offset, offset + len)` is in the VMO, before copying it in
+/// chunks.
+fn check_vmo_range(vmo: &VmObject, offset: usize, len: usize) -> ZxResult {
+    match offset.checked_add(len) {
+        Some(end) if end <= vmo.len() => Ok(()),
+        _ => Err(ZxError::OUT_OF_RANGE),
+    }
+}
+
+impl Syscall<'_> {
+    /// Read `len` bytes from a VMO at `offset`.
+    pub fn sys_vmo_read(
+        &self,
+        handle: HandleValue,
+        buf: UserOutPtr<u8>,
+        offset: usize,
+        len: usize,
+    ) -> ZxResult {
+        info!(
+            "vmo.read: handle={:#x}, offset={:#x}, buf=({:?}; {:#x})",
+            handle, offset, buf, len
+        );
+        let proc = self.thread.proc();
+        let vmo = proc.get_object_with_rights::<VmObject>(handle, Rights::READ)?;
+        check_vmo_range(&vmo, offset, len)?;
+        let mut data = vec![0u8; len.min(VMO_COPY_CHUNK)];
+        for start in (0..len).step_by(VMO_COPY_CHUNK) {
+            let data = &mut data[..(len - start).min(VMO_COPY_CHUNK)];
+            vmo.read(offset + start, data)?;
+            buf.add(start).write_array(data)?;
+        }
+        Ok(())
+    }
+
+    /// Write `len` bytes to a VMO at `offset`.
+    pub fn sys_vmo_write(
+        &self,
+        handle: HandleValue,
+        buf: UserInPtr<u8>,
+        offset: usize,
+        len: usize,
+    ) -> ZxResult {
+        info!(
+            "vmo.write: handle={:#x}, offset={:#x}, buf=({:?}; {:#x})",
+            handle, offset, buf, len
+        );
+        let proc = self.thread.proc();
+        let vmo = proc.get_object_with_rights::<VmObject>(handle, Rights::WRITE)?;
+        check_vmo_range(&vmo, offset, len)?;
+        let mut data = vec![0u8; len.min(VMO_COPY_CHUNK)];
+        for start in (0..len).step_by(VMO_COPY_CHUNK) {
+            let data = &mut data[..(len - start).min(VMO_COPY_CHUNK)];
+            buf.add(start).read_array_into(data)?;
+            vmo.write(offset + start, data)?;
+        }
+        Ok(())
+    }
+
+    /// Create a VMO representing a range of physical memory.
+    ///
+    /// The range must be within the MMIO `resource`.
+    pub fn sys_vmo_create_physical(
+        &self,
+        resource: HandleValue,
+        paddr: PhysAddr,
+        size: usize,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "vmo.create_physical: resource={:#x}, paddr={:#x}, size={:#x}",
+            resource, paddr, size
+        );
+        if !page_aligned(paddr) {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let size = roundup_pages(size);
+        if size == 0 {
+            return Err(ZxError::INVALID_ARGS);
+        }
+        let proc = self.thread.proc();
+        proc.get_object::<Resource>(resource)?
+            .validate_ranged_resource(ResourceKind::MMIO, paddr, size)?;
+        let vmo = VmObject::new_physical(paddr, pages(size));
+        let handle = proc.add_handle(Handle::new(vmo, Rights::DEFAULT_VMO | Rights::EXECUTE));
+        out.write(handle)?;
+        Ok(())
+    }
+
+    /// Add execute rights to a VMO.
+    ///
+    /// `vmex` must be a `VMEX` resource, or be invalid if the job policy allows
+    /// `AMBIENT_MARK_VMO_EXEC`.
+    pub fn sys_vmo_replace_as_executable(
+        &self,
+        handle: HandleValue,
+        vmex: HandleValue,
+        mut out: UserOutPtr<HandleValue>,
+    ) -> ZxResult {
+        info!(
+            "vmo.replace_as_executable: handle={:#x}, vmex={:#x}",
+            handle, vmex
+        );
+        let proc = self.thread.proc();
+        if vmex != INVALID_HANDLE {
+            proc.get_object::<Resource>(vmex)?
+                .validate(ResourceKind::VMEX)?;
+        } else {
+            proc.check_policy(PolicyCondition::AmbientMarkVMOExec)?;
+        }
+        let _ = proc.get_object::<VmObject>(handle)?;
+        let mut handle = proc.remove_handle(handle)?;
+        handle.rights |= Rights::EXECUTE;
+        out.write(proc.add_handle(handle))?;
+        Ok(())
+    }
+}
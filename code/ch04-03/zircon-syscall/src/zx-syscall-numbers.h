@@ -175,3 +175,16 @@
 
 #define ZX_SYS_futex_wake_handle_close_thread_exit 200
 #define ZX_SYS_vmar_unmap_handle_close_thread_exit 201
+
+// the extensions of zCore
+#define ZX_SYS_block_get_nth_device 202
+#define ZX_SYS_block_attach_vmo 203
+#define ZX_SYS_block_get_fifo 204
+#define ZX_SYS_eth_get_nth_device 205
+#define ZX_SYS_eth_set_rings 206
+#define ZX_SYS_eth_bind_port 207
+#define ZX_SYS_eth_tx_kick 208
+#define ZX_SYS_eth_get_status 209
+#define ZX_SYS_framebuffer_get_vmo 210
+#define ZX_SYS_framebuffer_flush 211
+#define ZX_SYS_pager_query_dirty_ranges 212